@@ -0,0 +1,41 @@
+//! Benchmarks parsing the bundled Goodreads book pages, already fetched and
+//! parsed into HTML, as the candidates and dry runs do for every book.
+//!
+//! Compare two versions of the parsers with
+//! `cargo bench --bench goodreads -- --save-baseline before`, then
+//! `cargo bench --bench goodreads -- --baseline before`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use libreads::parse_check::parse_goodreads_page;
+use scraper::Html;
+
+const BOOK_URL: &str = "https://www.goodreads.com/book/show/0";
+
+const PAGES: &[(&str, &str)] = &[
+    (
+        "1984",
+        include_str!("../tests/testdata/goodreads_1984_book_page.html"),
+    ),
+    (
+        "series",
+        include_str!("../tests/testdata/goodreads_series_book_page.html"),
+    ),
+    (
+        "legacy",
+        include_str!("../tests/testdata/goodreads_series_legacy_book_page.html"),
+    ),
+];
+
+fn parse_book_pages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("goodreads_book_page");
+    for (name, html) in PAGES {
+        let document = Html::parse_document(html);
+        group.bench_function(*name, |b| {
+            b.iter(|| parse_goodreads_page(&document, BOOK_URL))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse_book_pages);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Mounts the LibReads API under `/books` in another actix app, next to its
+//! own routes, instead of running a second server:
+//!
+//! ```sh
+//! cargo run --example mount_under_prefix
+//! curl 'http://127.0.0.1:8080/books/info/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424'
+//! ```
+
+use actix_web::{
+    web::{self, Data},
+    App, HttpResponse, HttpServer,
+};
+use libreads::{
+    config::Config,
+    libreads::{BookService, LibReads},
+    web::LibReadsState,
+};
+use std::sync::Arc;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let config = Config::default();
+    let libreads = LibReads::new(&config);
+    let breakers = libreads.circuit_breakers().clone();
+    let libreads: Data<dyn BookService> = Data::from(Arc::new(libreads) as Arc<_>);
+    let state = LibReadsState::new(config, libreads).with_breakers(breakers);
+
+    // Binding and TLS are up to the app.
+    HttpServer::new(move || {
+        App::new()
+            .service(
+                web::scope("/books").configure(|cfg| libreads::web::configure(cfg, state.clone())),
+            )
+            .route(
+                "/",
+                web::get().to(|| async { HttpResponse::Ok().body("My app") }),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
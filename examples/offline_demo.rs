@@ -0,0 +1,90 @@
+//! Runs the whole pipeline against local mock servers, so it works without
+//! network access: Goodreads, the LibGen API and library.lol are replaced
+//! with `httpmock` servers serving the fixtures in `tests/testdata`.
+//!
+//! ```sh
+//! cargo run --example offline_demo
+//! ```
+
+use httpmock::{Method::GET, MockServer};
+use libreads::{
+    config::{Config, DownloadSource},
+    convert::ConversionOptions,
+    extension::Extension,
+    libreads::{Error, LibReads},
+};
+
+const MD5: &str = "AB13556B96D473C8DFAD7165C4704526";
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let goodreads = MockServer::start();
+    goodreads.mock(|when, then| {
+        when.method(GET)
+            .path("/book/show/1048424.Governing_the_Commons");
+        then.status(200).body(include_str!(
+            "../tests/testdata/goodreads_isbn13_only_page.html"
+        ));
+    });
+
+    let libgen = MockServer::start();
+    libgen.mock(|when, then| {
+        when.method(GET)
+            .path("/json.php")
+            .query_param("isbn", "9780521405997");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(include_str!(
+                "../tests/testdata/libgen_governing_the_commons.json"
+            ));
+    });
+
+    // The book page links to a file on the same server.
+    let library_dot_lol = MockServer::start();
+    library_dot_lol.mock(|when, then| {
+        when.method(GET).path(format!("/main/{}", MD5));
+        then.status(200).body(format!(
+            r#"<div id="download"><h2><a href="{}">GET</a></h2></div>"#,
+            library_dot_lol.url("/book.epub")
+        ));
+    });
+    library_dot_lol.mock(|when, then| {
+        when.method(GET).path("/book.epub");
+        then.status(200)
+            .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+    });
+
+    // The configuration replaces the default upstreams.
+    let libreads = LibReads::new(&Config {
+        download_sources: vec![DownloadSource::LibraryDotLol],
+        goodreads_url: Some(goodreads.base_url()),
+        libgen_url: Some(libgen.url("/json.php")),
+        library_lol_url: Some(library_dot_lol.url("/main")),
+        ..Config::default()
+    });
+
+    let book_info = libreads
+        .get_book_info_from_goodreads_url(
+            "https://www.goodreads.com/book/show/1048424.Governing_the_Commons",
+        )
+        .await?;
+    println!(
+        "Formats found: {:?} -> {:?} selected",
+        book_info.available_formats, book_info.metadata.extension
+    );
+    println!("Download link: {}", book_info.download_links.best_link());
+
+    // The selected edition is already an EPUB, so ebook-convert isn't needed.
+    let result = libreads
+        .download_as(book_info, Extension::Epub, &ConversionOptions::default())
+        .await?;
+    println!(
+        "Ebook downloaded as {} ({} bytes, converted: {})",
+        result.path.display(),
+        result.bytes,
+        result.converted
+    );
+    std::fs::remove_file(result.path).expect("Delete the downloaded ebook");
+
+    Ok(())
+}
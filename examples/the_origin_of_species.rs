@@ -12,14 +12,19 @@ async fn main() -> Result<(), Error> {
         )
         .await?;
     println!(
-        "IPFS.io download link: {}",
-        book_info.download_links.ipfs_dot_io
+        "Formats found: {:?} -> {:?} selected",
+        book_info.available_formats, book_info.metadata.extension
     );
+    println!("Download link: {}", book_info.download_links.best_link());
 
-    let filename = download_as(book_info.into(), Extension::Mobi)
+    let result = download_as(book_info.into(), Extension::Mobi)
         .await
         .expect("Download and convert the ebook");
-    println!("Ebook downloaded as {}", filename);
+    println!(
+        "Ebook downloaded as {} ({} bytes)",
+        result.path.display(),
+        result.bytes
+    );
 
     Ok(())
 }
@@ -0,0 +1,218 @@
+//! Module archive serves several formats of a book in one go, as a ZIP, e.g.
+//! `?format=epub,pdf,original`.
+//!
+//! The book is downloaded once, then converted to each format from the
+//! downloaded file. A format that can't be converted is left out of the
+//! archive, and the reason is written to `errors.txt` instead.
+
+use crate::{
+    convert::{self, ConversionOptions},
+    extension::Extension,
+    library_dot_lol::DownloadLinks,
+    libreads::{self, BookInfo, BookService},
+    temp_file::TempFileGuard,
+};
+use std::{io::Write, path::PathBuf};
+
+pub const ERRORS_FILENAME: &str = "errors.txt";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ArchiveFormat {
+    // The book as it was downloaded.
+    Original,
+    Converted(Extension),
+}
+
+// Parses comma-separated formats, e.g. "epub,pdf,original". Duplicates are
+// ignored.
+pub fn parse_formats(formats: &str) -> Result<Vec<ArchiveFormat>, String> {
+    let mut parsed = vec![];
+    for format in formats.split(',').map(str::trim) {
+        let format = match format.to_lowercase().as_str() {
+            "" => return Err(format!("empty format in {:?}", formats)),
+            "original" => ArchiveFormat::Original,
+            extension => {
+                let Ok(extension) = extension.parse::<Extension>();
+                if !extension.is_conversion_target() {
+                    return Err(format!("unsupported format {:?}", format));
+                }
+                ArchiveFormat::Converted(extension)
+            }
+        };
+        if !parsed.contains(&format) {
+            parsed.push(format);
+        }
+    }
+    Ok(parsed)
+}
+
+// The files going in the archive, in the requested order, and the formats
+// that failed.
+#[derive(Debug)]
+pub struct Artifacts {
+    pub files: Vec<(Extension, TempFileGuard)>,
+    pub errors: Vec<(Extension, libreads::Error)>,
+}
+
+// Downloads the book, then converts it to each format. A format that turns
+// out to be the original one isn't converted.
+pub async fn convert_all(
+    libreads: &dyn BookService,
+    book_info: BookInfo,
+    formats: &[ArchiveFormat],
+    options: &ConversionOptions,
+) -> Result<Artifacts, libreads::Error> {
+    let original_extension = book_info.metadata.extension.clone();
+    let downloaded = libreads
+        .book_file(book_info.clone(), original_extension.clone(), options)
+        .await?;
+
+    // Conversions copy the book under its usual name first: the original is
+    // moved out of their way.
+    let original_path = downloaded
+        .path
+        .with_extension(format!("original.{}", original_extension));
+    let original = match tokio::fs::rename(&downloaded.path, &original_path).await {
+        Ok(()) => TempFileGuard::new(&original_path),
+        Err(err) => {
+            let _ = TempFileGuard::new(&downloaded.path).close().await;
+            return Err(libreads::Error::Conversion(err.into()));
+        }
+    };
+    let local_link = std::path::absolute(&original_path)
+        .ok()
+        .and_then(|path| reqwest::Url::from_file_path(path).ok())
+        .ok_or_else(|| {
+            libreads::Error::Conversion(convert::Error::Io(format!(
+                "invalid path {:?}",
+                original_path
+            )))
+        })?;
+    // Only this file is converted, never another edition.
+    let local_book = BookInfo {
+        download_links: DownloadLinks {
+            http: local_link.to_string(),
+            filename: book_info.download_links.filename.clone(),
+            ..DownloadLinks::default()
+        },
+        alternatives: vec![],
+        ..book_info
+    };
+
+    let mut extensions: Vec<Extension> = vec![];
+    for format in formats {
+        let extension = match format {
+            ArchiveFormat::Original => original_extension.clone(),
+            ArchiveFormat::Converted(extension) => extension.clone(),
+        };
+        if !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
+    }
+
+    let mut artifacts = Artifacts {
+        files: vec![],
+        errors: vec![],
+    };
+    let mut original = Some(original);
+    for extension in extensions {
+        if extension == original_extension {
+            if let Some(original) = original.take() {
+                artifacts.files.push((extension, original));
+            }
+            continue;
+        }
+        match libreads
+            .book_file(local_book.clone(), extension.clone(), options)
+            .await
+        {
+            Ok(result) => artifacts
+                .files
+                .push((extension, TempFileGuard::new(result.path))),
+            Err(err) => artifacts.errors.push((extension, err)),
+        }
+    }
+    if let Some(original) = original {
+        let _ = original.close().await;
+    }
+
+    Ok(artifacts)
+}
+
+// Writes the files to a ZIP at `path`, under their names, and `errors` as
+// `ERRORS_FILENAME`. Books are mostly compressed already, so they are stored
+// as they are.
+pub async fn write_zip(
+    path: PathBuf,
+    entries: Vec<(String, PathBuf)>,
+    errors: Option<String>,
+) -> std::io::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, file) in entries {
+            zip.start_file(name, options)?;
+            std::io::copy(&mut std::fs::File::open(file)?, &mut zip)?;
+        }
+        if let Some(errors) = errors {
+            zip.start_file(ERRORS_FILENAME, options)?;
+            zip.write_all(errors.as_bytes())?;
+        }
+        zip.finish()?;
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        assert_eq!(
+            Ok(vec![
+                ArchiveFormat::Converted(Extension::Epub),
+                ArchiveFormat::Converted(Extension::Pdf),
+                ArchiveFormat::Original,
+            ]),
+            parse_formats("epub, PDF,original,epub")
+        );
+        assert_eq!(
+            Err(r#"unsupported format "doc""#.to_string()),
+            parse_formats("epub,doc")
+        );
+        assert_eq!(
+            Err(r#"empty format in "epub,""#.to_string()),
+            parse_formats("epub,")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_zip() {
+        let dir = std::env::temp_dir();
+        let book = dir.join("libreads_archive_book.epub");
+        std::fs::write(&book, "book").unwrap();
+        let path = dir.join("libreads_archive.zip");
+
+        write_zip(
+            path.clone(),
+            vec![("Emma.epub".to_string(), book.clone())],
+            Some("mobi: the conversion failed\n".to_string()),
+        )
+        .await
+        .expect("Should write the archive");
+
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let names: Vec<&str> = zip.file_names().collect();
+        assert_eq!(vec!["Emma.epub", ERRORS_FILENAME], names);
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name("Emma.epub").unwrap(), &mut content)
+            .unwrap();
+        assert_eq!("book", content);
+
+        std::fs::remove_file(book).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+//! Module book_query parses the compact way books are asked for on the
+//! command line and with `/api/search?q=`: a book page URL, or fields
+//! separated by `|`, e.g. `isbn:9780553418026`, `title:Dune|author:Frank
+//! Herbert` or `gr:5470` for a Goodreads book ID.
+//!
+//! Backslashes escape `|`, `:` and themselves in values, e.g.
+//! `title:Dune\: Messiah|author:Frank Herbert`.
+
+use crate::{
+    book_ref::{BookRef, Provider},
+    goodreads,
+};
+use std::{fmt, str::FromStr};
+
+const ISBN_KEY: &str = "isbn";
+const TITLE_KEY: &str = "title";
+const AUTHOR_KEY: &str = "author";
+const GOODREADS_KEY: &str = "gr";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookQuery {
+    // A book page, e.g. on Goodreads or Open Library.
+    Url(String),
+    // An ISBN-10 or ISBN-13, without its dashes.
+    Isbn(String),
+    TitleAndAuthor { title: String, author: String },
+    // A numeric Goodreads book ID.
+    Goodreads(String),
+}
+
+impl BookQuery {
+    // The page the book is identified from, when it isn't searched for
+    // straight on LibGen.
+    pub fn page_url(&self) -> Option<String> {
+        match self {
+            Self::Url(url) => Some(url.clone()),
+            Self::Goodreads(id) => Some(goodreads::book_url(id)),
+            Self::Isbn(_) | Self::TitleAndAuthor { .. } => None,
+        }
+    }
+
+    pub fn book_ref(&self) -> Option<BookRef> {
+        match self {
+            Self::Url(url) => BookRef::from_url(url),
+            Self::Goodreads(id) => Some(BookRef {
+                provider: Provider::Goodreads,
+                id: id.clone(),
+            }),
+            Self::Isbn(_) | Self::TitleAndAuthor { .. } => None,
+        }
+    }
+}
+
+// Escapes what `FromStr` splits fields on.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '|' | ':') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl fmt::Display for BookQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Url(url) => write!(f, "{}", url),
+            Self::Isbn(isbn) => write!(f, "{}:{}", ISBN_KEY, escape(isbn)),
+            Self::TitleAndAuthor { title, author } => write!(
+                f,
+                "{}:{}|{}:{}",
+                TITLE_KEY,
+                escape(title),
+                AUTHOR_KEY,
+                escape(author)
+            ),
+            Self::Goodreads(id) => write!(f, "{}:{}", GOODREADS_KEY, escape(id)),
+        }
+    }
+}
+
+// Splits a query into its `key:value` fields, on the `|` and first `:` that
+// aren't escaped. Keys are lower-cased, and keys and values trimmed.
+fn fields(query: &str) -> Result<Vec<(String, String)>, String> {
+    let mut fields = vec![];
+    let mut key: Option<String> = None;
+    let mut value = String::new();
+    let mut chars = query.chars();
+    let mut field = |key: Option<String>, value: String| match key {
+        Some(key) => {
+            fields.push((key.trim().to_lowercase(), value.trim().to_string()));
+            Ok(())
+        }
+        None => Err(format!("{:?} is not a key:value field", value.trim())),
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next().ok_or("it ends with a lone \\")?),
+            ':' if key.is_none() => key = Some(std::mem::take(&mut value)),
+            '|' => field(key.take(), std::mem::take(&mut value))?,
+            c => value.push(c),
+        }
+    }
+    field(key, value)?;
+    Ok(fields)
+}
+
+// ISBN-10s are 9 digits and a check digit, which can be "X", ISBN-13s 13
+// digits.
+fn is_isbn(isbn: &str) -> bool {
+    let digits = |value: &str| value.chars().all(|c| c.is_ascii_digit());
+    if !isbn.is_ascii() {
+        return false;
+    }
+    match isbn.len() {
+        10 => digits(&isbn[..9]) && (digits(&isbn[9..]) || &isbn[9..] == "X"),
+        13 => digits(isbn),
+        _ => false,
+    }
+}
+
+// Parses what `Display` prints. Anything starting with http:// or https://
+// is a book page URL, and isn't unescaped.
+impl FromStr for BookQuery {
+    type Err = String;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let query = query.trim();
+        let invalid = |reason: String| {
+            format!(
+                "invalid book query {:?}: {}, expected a book URL, \"isbn:9780553418026\", \"title:Dune|author:Frank Herbert\" or \"gr:5470\"",
+                query, reason
+            )
+        };
+        let lowercase = query.to_lowercase();
+        if lowercase.starts_with("http://") || lowercase.starts_with("https://") {
+            return Ok(Self::Url(query.to_string()));
+        }
+
+        let mut isbn = None;
+        let mut title = None;
+        let mut author = None;
+        let mut goodreads_id = None;
+        for (key, value) in fields(query).map_err(invalid)? {
+            if value.is_empty() {
+                return Err(invalid(format!("{} is empty", key)));
+            }
+            let field = match key.as_str() {
+                ISBN_KEY => &mut isbn,
+                TITLE_KEY => &mut title,
+                AUTHOR_KEY => &mut author,
+                GOODREADS_KEY => &mut goodreads_id,
+                _ => return Err(invalid(format!("unknown key {:?}", key))),
+            };
+            if field.replace(value).is_some() {
+                return Err(invalid(format!("{} is given twice", key)));
+            }
+        }
+
+        match (isbn, title, author, goodreads_id) {
+            (Some(isbn), None, None, None) => {
+                match goodreads::canonical_isbn(&isbn).filter(|isbn| is_isbn(isbn)) {
+                    Some(isbn) => Ok(Self::Isbn(isbn)),
+                    None => Err(invalid(format!("{:?} is not an ISBN", isbn))),
+                }
+            }
+            (None, Some(title), Some(author), None) => Ok(Self::TitleAndAuthor { title, author }),
+            (None, None, None, Some(id)) => match id.chars().all(|c| c.is_ascii_digit()) {
+                true => Ok(Self::Goodreads(id)),
+                false => Err(invalid(format!("{:?} is not a Goodreads book ID", id))),
+            },
+            (None, Some(_), None, None) => Err(invalid("the title needs an author".to_string())),
+            (None, None, Some(_), None) => Err(invalid("the author needs a title".to_string())),
+            _ => Err(invalid(
+                "it mixes ISBN, title and Goodreads ID fields".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title_and_author(title: &str, author: &str) -> BookQuery {
+        BookQuery::TitleAndAuthor {
+            title: title.to_string(),
+            author: author.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        for (query, want) in [
+            (
+                "https://www.goodreads.com/book/show/5470.Nineteen_Eighty_Four",
+                BookQuery::Url(
+                    "https://www.goodreads.com/book/show/5470.Nineteen_Eighty_Four".to_string(),
+                ),
+            ),
+            // URLs aren't unescaped.
+            (
+                " HTTPS://openlibrary.org/books/OL12345M/A|B\\C ",
+                BookQuery::Url("HTTPS://openlibrary.org/books/OL12345M/A|B\\C".to_string()),
+            ),
+            (
+                "isbn:9780553418026",
+                BookQuery::Isbn("9780553418026".to_string()),
+            ),
+            (
+                "ISBN: 978-0-553-41802-6 ",
+                BookQuery::Isbn("9780553418026".to_string()),
+            ),
+            ("isbn:055341802x", BookQuery::Isbn("055341802X".to_string())),
+            ("gr:5470", BookQuery::Goodreads("5470".to_string())),
+            (
+                "title:Dune|author:Frank Herbert",
+                title_and_author("Dune", "Frank Herbert"),
+            ),
+            (
+                " author : Frank Herbert | Title : Dune ",
+                title_and_author("Dune", "Frank Herbert"),
+            ),
+            // Only the first colon separates the key from the value.
+            (
+                "title:Dune: Messiah|author:Frank Herbert",
+                title_and_author("Dune: Messiah", "Frank Herbert"),
+            ),
+            (
+                r"title:Dune\: Messiah|author:Frank Herbert",
+                title_and_author("Dune: Messiah", "Frank Herbert"),
+            ),
+            (
+                r"title:Either\|Or|author:Søren Kierkegaard",
+                title_and_author("Either|Or", "Søren Kierkegaard"),
+            ),
+            (
+                r"title:C:\\Windows \\ \x|author:A\:B",
+                title_and_author(r"C:\Windows \ x", "A:B"),
+            ),
+            // Keys can be escaped too.
+            (
+                r"tit\le:Dune|author:Frank Herbert",
+                title_and_author("Dune", "Frank Herbert"),
+            ),
+        ] {
+            assert_eq!(Ok(want), query.parse::<BookQuery>(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for (query, reason) in [
+            ("", "\"\" is not a key:value field"),
+            (
+                "9780553418026",
+                "\"9780553418026\" is not a key:value field",
+            ),
+            ("www.goodreads.com/book/show/5470", "not a key:value field"),
+            ("isbn:", "isbn is empty"),
+            ("isbn:123", "\"123\" is not an ISBN"),
+            ("isbn:97805534180XX", "is not an ISBN"),
+            ("isbn:97805534180X6", "is not an ISBN"),
+            ("isbn:05534X802X", "is not an ISBN"),
+            ("gr:5470-1984", "\"5470-1984\" is not a Goodreads book ID"),
+            ("title:Dune", "the title needs an author"),
+            ("author:Frank Herbert", "the author needs a title"),
+            (
+                "title:Dune|author:Frank Herbert|",
+                "\"\" is not a key:value field",
+            ),
+            (
+                "title:Dune||author:Frank Herbert",
+                "\"\" is not a key:value field",
+            ),
+            (
+                "title:Dune|author:Frank Herbert|title:Dune",
+                "title is given twice",
+            ),
+            (
+                "isbn:9780553418026|title:Dune|author:Frank Herbert",
+                "it mixes",
+            ),
+            ("isbn:9780553418026|gr:5470", "it mixes"),
+            ("year:1965", "unknown key \"year\""),
+            (
+                "title:Dune|author:Frank Herbert\\",
+                "it ends with a lone \\",
+            ),
+            (
+                r"title\:Dune|author:Frank Herbert",
+                "is not a key:value field",
+            ),
+        ] {
+            let err = query.parse::<BookQuery>().expect_err(query);
+            assert!(err.contains(reason), "{}: {}", query, err);
+            assert!(err.starts_with("invalid book query"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        for (query, want) in [
+            (
+                BookQuery::Url("https://www.goodreads.com/book/show/5470".to_string()),
+                "https://www.goodreads.com/book/show/5470",
+            ),
+            (
+                BookQuery::Isbn("9780553418026".to_string()),
+                "isbn:9780553418026",
+            ),
+            (BookQuery::Goodreads("5470".to_string()), "gr:5470"),
+            (
+                title_and_author("Dune", "Frank Herbert"),
+                "title:Dune|author:Frank Herbert",
+            ),
+            (
+                title_and_author(r"Either|Or: A Fragment of Life \ 1", "Kierkegaard"),
+                r"title:Either\|Or\: A Fragment of Life \\ 1|author:Kierkegaard",
+            ),
+        ] {
+            assert_eq!(want, query.to_string());
+            assert_eq!(Ok(query), want.parse::<BookQuery>(), "{}", want);
+        }
+    }
+
+    #[test]
+    fn test_page_url_and_book_ref() {
+        let url = "https://www.goodreads.com/book/show/5470.Nineteen_Eighty_Four";
+        for (query, page_url, book_ref) in [
+            (BookQuery::Url(url.to_string()), Some(url), Some("5470")),
+            (
+                BookQuery::Goodreads("5470".to_string()),
+                Some("https://www.goodreads.com/book/show/5470"),
+                Some("5470"),
+            ),
+            (BookQuery::Isbn("9780553418026".to_string()), None, None),
+            (title_and_author("Dune", "Frank Herbert"), None, None),
+        ] {
+            assert_eq!(page_url.map(str::to_string), query.page_url(), "{}", query);
+            assert_eq!(
+                book_ref.map(|id| id.parse::<BookRef>().unwrap()),
+                query.book_ref(),
+                "{}",
+                query
+            );
+        }
+    }
+}
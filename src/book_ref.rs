@@ -0,0 +1,212 @@
+//! Module book_ref identifies a book independently of the URL it was asked
+//! for with, so the history of "the same book, spelled differently" isn't
+//! scattered.
+//!
+//! Goodreads books are referred to by their numeric ID, e.g. `1048424` for
+//! `https://www.goodreads.com/book/show/1048424.Governing_the_Commons`.
+//! Other providers' books by an ID prefixed with the provider, e.g.
+//! `ol:OL12345M` for an Open Library edition.
+
+use crate::goodreads;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+const OPEN_LIBRARY_PREFIX: &str = "ol:";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Provider {
+    Goodreads,
+    OpenLibrary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BookRef {
+    pub provider: Provider,
+    pub id: String,
+}
+
+impl BookRef {
+    // The book of a page URL: a Goodreads book page, or an Open Library
+    // edition or work, e.g. "https://openlibrary.org/books/OL12345M/Title".
+    // Goodreads work and review pages don't say which book they are about.
+    pub fn from_url(page_url: &str) -> Option<Self> {
+        if let Some(id) = goodreads::book_id(page_url) {
+            return Some(Self {
+                provider: Provider::Goodreads,
+                id,
+            });
+        }
+
+        let url = reqwest::Url::parse(page_url.trim()).ok()?;
+        if !matches!(url.host_str()?, "openlibrary.org" | "www.openlibrary.org") {
+            return None;
+        }
+        let mut segments = url.path_segments()?;
+        let id = match (segments.next()?, segments.next()?) {
+            ("books", id) if id.ends_with('M') => id,
+            ("works", id) if id.ends_with('W') => id,
+            _ => return None,
+        };
+        is_open_library_id(id).then(|| Self {
+            provider: Provider::OpenLibrary,
+            id: id.to_string(),
+        })
+    }
+}
+
+// Open Library IDs are "OL", digits, and a letter for the kind of record,
+// e.g. "OL12345M" for an edition or "OL27448W" for a work.
+fn is_open_library_id(id: &str) -> bool {
+    let Some(digits) = id
+        .strip_prefix("OL")
+        .and_then(|id| id.strip_suffix(['M', 'W']))
+    else {
+        return false;
+    };
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+impl fmt::Display for BookRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.provider {
+            Provider::Goodreads => write!(f, "{}", self.id),
+            Provider::OpenLibrary => write!(f, "{}{}", OPEN_LIBRARY_PREFIX, self.id),
+        }
+    }
+}
+
+// Parses what `Display` prints: "1048424", or "ol:OL12345M".
+impl FromStr for BookRef {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let invalid = || {
+            format!(
+                "invalid book {:?}: expected a numeric Goodreads book ID, or an Open Library ID such as \"ol:OL12345M\"",
+                value
+            )
+        };
+        if let Some(id) = value.strip_prefix(OPEN_LIBRARY_PREFIX) {
+            return match is_open_library_id(id) {
+                true => Ok(Self {
+                    provider: Provider::OpenLibrary,
+                    id: id.to_string(),
+                }),
+                false => Err(invalid()),
+            };
+        }
+        match !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+            true => Ok(Self {
+                provider: Provider::Goodreads,
+                id: value.to_string(),
+            }),
+            false => Err(invalid()),
+        }
+    }
+}
+
+impl Serialize for BookRef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BookRef {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+// Described as the string it is serialised as.
+impl utoipa::PartialSchema for BookRef {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::schema::Type::String)
+            .examples([serde_json::json!("1048424")])
+            .into()
+    }
+}
+
+impl utoipa::ToSchema for BookRef {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn goodreads(id: &str) -> BookRef {
+        BookRef {
+            provider: Provider::Goodreads,
+            id: id.to_string(),
+        }
+    }
+
+    fn open_library(id: &str) -> BookRef {
+        BookRef {
+            provider: Provider::OpenLibrary,
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_url() {
+        for (url, want) in [
+            (
+                "https://www.goodreads.com/book/show/1048424.Governing_the_Commons",
+                Some(goodreads("1048424")),
+            ),
+            (
+                "https://www.goodreads.com/en/book/show/40961427-1984?ref=share#reviews",
+                Some(goodreads("40961427")),
+            ),
+            (
+                "https://openlibrary.org/books/OL12345M/Governing_the_Commons",
+                Some(open_library("OL12345M")),
+            ),
+            (
+                "https://openlibrary.org/works/OL27448W",
+                Some(open_library("OL27448W")),
+            ),
+            ("https://www.goodreads.com/work/editions/153313", None),
+            ("https://openlibrary.org/authors/OL23919A", None),
+            ("https://openlibrary.org/books/OL12345W", None),
+            ("https://example.com/books/OL12345M", None),
+            ("not a url", None),
+        ] {
+            assert_eq!(want, BookRef::from_url(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        for (value, want) in [
+            ("1048424", goodreads("1048424")),
+            (" 1048424 ", goodreads("1048424")),
+            ("ol:OL12345M", open_library("OL12345M")),
+            ("ol:OL27448W", open_library("OL27448W")),
+        ] {
+            assert_eq!(Ok(want.clone()), value.parse::<BookRef>(), "{}", value);
+            assert_eq!(value.trim(), want.to_string());
+        }
+        for value in ["", "12ab", "ol:", "ol:12345", "ol:OL12345A", "gr:1048424"] {
+            assert!(value.parse::<BookRef>().is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let book = open_library("OL12345M");
+        let json = serde_json::to_string(&book).unwrap();
+        assert_eq!(r#""ol:OL12345M""#, json);
+        assert_eq!(book, serde_json::from_str(&json).unwrap());
+        assert!(serde_json::from_str::<BookRef>(r#""OL12345M""#).is_err());
+    }
+}
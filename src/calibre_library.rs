@@ -0,0 +1,254 @@
+//! Module calibre_library finds books in a local Calibre library, so books
+//! that are already on disk don't have to be fetched from LibGen.
+//!
+//! It reads the library's `metadata.db`. The `md5` of the metadata it returns
+//! is the path of the book file, which `get_download_links` turns into a
+//! `file://` link.
+
+use crate::{
+    extension::Extension,
+    goodreads::BookIdentification,
+    libgen::{self, LibgenMetadata, MetadataStore},
+    library_dot_lol::{self, DownloadLinks, DownloadLinksStore},
+};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+
+const METADATA_DB: &str = "metadata.db";
+
+const SELECT_BOOKS: &str = "
+    SELECT books.title, authors.name, substr(books.pubdate, 1, 4), books.path, data.format, data.name
+    FROM books
+    JOIN books_authors_link ON books_authors_link.book = books.id
+    JOIN authors ON authors.id = books_authors_link.author
+    JOIN data ON data.book = books.id";
+
+#[derive(Clone, Debug)]
+pub struct CalibreLibrary {
+    // The directory containing `metadata.db`.
+    pub path: PathBuf,
+}
+
+impl CalibreLibrary {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn find_books(
+        &self,
+        book_identification: &BookIdentification,
+    ) -> Result<Vec<LibgenMetadata>, rusqlite::Error> {
+        let connection = Connection::open_with_flags(
+            self.path.join(METADATA_DB),
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+
+        let canonical = book_identification.canonical();
+        for isbn in [canonical.isbn10, canonical.isbn13].into_iter().flatten() {
+            let query = format!(
+                "{} JOIN identifiers ON identifiers.book = books.id
+                WHERE identifiers.type = 'isbn' AND upper(replace(identifiers.val, '-', '')) = ?1",
+                SELECT_BOOKS
+            );
+            let books = self.query_books(&connection, &query, params![isbn])?;
+            if !books.is_empty() {
+                return Ok(books);
+            }
+        }
+
+        if let (Some(title), Some(author)) =
+            (&book_identification.title, &book_identification.author)
+        {
+            let query = format!(
+                "{} WHERE books.title = ?1 COLLATE NOCASE AND authors.name = ?2",
+                SELECT_BOOKS
+            );
+            return self.query_books(&connection, &query, params![title, author]);
+        }
+
+        Ok(vec![])
+    }
+
+    fn query_books(
+        &self,
+        connection: &Connection,
+        query: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<LibgenMetadata>, rusqlite::Error> {
+        let mut statement = connection.prepare(query)?;
+        let books = statement.query_map(params, |row| {
+            let year: Option<String> = row.get(2)?;
+            let book_path: String = row.get(3)?;
+            let format: String = row.get(4)?;
+            let name: String = row.get(5)?;
+
+            let Ok(extension) = format.parse::<Extension>();
+            let file = self
+                .path
+                .join(book_path)
+                .join(format!("{}.{}", name, extension));
+
+            Ok(LibgenMetadata {
+                title: row.get(0)?,
+                author: row.get(1)?,
+                // Calibre stores unknown publication dates as year 101.
+                year: year.filter(|year| year != "0101").unwrap_or_default(),
+                extension,
+                md5: file.to_string_lossy().to_string(),
+                filesize: None,
+                language: None,
+                pages: None,
+                publisher: None,
+            })
+        })?;
+
+        books.collect()
+    }
+
+    // Returns the book file if `id` is a file inside this library.
+    fn find_file(&self, id: &str) -> Option<PathBuf> {
+        let library = self.path.canonicalize().ok()?;
+        let file = Path::new(id).canonicalize().ok()?;
+        (file.starts_with(library) && file.is_file()).then_some(file)
+    }
+}
+
+#[async_trait]
+impl MetadataStore for CalibreLibrary {
+    async fn get_metadata(
+        &self,
+        book_identification: &BookIdentification,
+    ) -> Result<Vec<LibgenMetadata>, libgen::Error> {
+        let library = self.clone();
+        let book_identification = book_identification.clone();
+
+        tokio::task::spawn_blocking(move || library.find_books(&book_identification))
+            .await
+            .map_err(|err| libgen::Error::LocalLibrary(err.to_string()))?
+            .map_err(|err| libgen::Error::LocalLibrary(err.to_string()))
+    }
+}
+
+#[async_trait]
+impl DownloadLinksStore for CalibreLibrary {
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, library_dot_lol::Error> {
+        let file = self
+            .find_file(id)
+            .ok_or_else(|| library_dot_lol::Error::MissingDownloadLinks(id.to_string()))?;
+        let url = reqwest::Url::from_file_path(&file)
+            .map_err(|_| library_dot_lol::Error::MissingDownloadLinks(id.to_string()))?;
+
+        Ok(DownloadLinks {
+            http: url.to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = "tests/testdata/calibre";
+
+    fn book_file() -> String {
+        Path::new(LIBRARY)
+            .join("George Orwell/1984 (1)/1984 - George Orwell.epub")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn want_1984() -> Vec<LibgenMetadata> {
+        vec![LibgenMetadata {
+            title: "1984".to_string(),
+            author: "George Orwell".to_string(),
+            year: "2017".to_string(),
+            extension: Extension::Epub,
+            md5: book_file(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_get_metadata_by_isbn() {
+        let got = CalibreLibrary::new(LIBRARY)
+            .get_metadata(&BookIdentification {
+                isbn10: Some("0451524934".to_string()),
+                isbn13: Some("978-0451524935".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+        assert_eq!(Ok(want_1984()), got);
+    }
+
+    #[tokio::test]
+    async fn test_get_metadata_by_title_and_author() {
+        let got = CalibreLibrary::new(LIBRARY)
+            .get_metadata(&BookIdentification {
+                title: Some("1984".to_string()),
+                author: Some("george orwell".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+        assert_eq!(Ok(want_1984()), got);
+    }
+
+    #[tokio::test]
+    async fn test_get_metadata_without_file() {
+        // Animal Farm is in the library, but has no file.
+        let got = CalibreLibrary::new(LIBRARY)
+            .get_metadata(&BookIdentification {
+                isbn13: Some("9780451526342".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+        assert_eq!(Ok(vec![]), got);
+    }
+
+    #[tokio::test]
+    async fn test_get_metadata_missing_library() {
+        let got = CalibreLibrary::new("tests/testdata/no_such_library")
+            .get_metadata(&BookIdentification {
+                isbn13: Some("9780451524935".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+        assert!(matches!(got, Err(libgen::Error::LocalLibrary(_))));
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links() {
+        let got = CalibreLibrary::new(LIBRARY)
+            .get_download_links(&book_file())
+            .await
+            .expect("The book is in the library");
+
+        let want = reqwest::Url::from_file_path(Path::new(&book_file()).canonicalize().unwrap())
+            .unwrap()
+            .to_string();
+        assert_eq!(want, got.http);
+        assert_eq!(want, got.best_link());
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_outside_library() {
+        for id in [
+            "6C6C4E4E1B9D9B1E8C3E5D1A2F0B7C8D",
+            "tests/testdata/calibre/../goodreads_1984_book_page.html",
+            "tests/testdata/calibre/metadata.db/..",
+        ] {
+            assert_eq!(
+                Err(library_dot_lol::Error::MissingDownloadLinks(id.to_string())),
+                CalibreLibrary::new(LIBRARY).get_download_links(id).await
+            );
+        }
+    }
+}
@@ -0,0 +1,561 @@
+//! Module capabilities finds out, once at startup, what this server's
+//! toolchain can convert: the Calibre version behind ebook-convert, the
+//! formats it reads and writes, and whether DjVuLibre is there for DJVU.
+//!
+//! Conversions the toolchain can't do fail fast, before anything is
+//! downloaded, instead of after ebook-convert gave up. So do all of them
+//! when conversion is disabled, see `Config::conversion_enabled`. Books none
+//! of whose editions can be converted are rejected with the formats they can
+//! be served in instead, see `ConverterCapabilities::check_editions`.
+
+use crate::{
+    convert,
+    extension::{Extension, CONVERSION_TARGETS},
+};
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+
+// `ebook-convert --version` only prints a couple of lines, but Calibre can be
+// slow to start the first time.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// What ebook-convert reads and writes, out of the formats LibReads handles.
+// DJVU goes through ddjvu, and Calibre can't read DOC (only DOCX).
+const CALIBRE_INPUTS: &[Extension] = &[
+    Extension::Mobi,
+    Extension::Epub,
+    Extension::Azw3,
+    Extension::Pdf,
+    Extension::Docx,
+    Extension::Txt,
+    Extension::Htmlz,
+    Extension::Cbz,
+    Extension::Cbr,
+];
+const CALIBRE_OUTPUTS: &[Extension] = &[
+    Extension::Mobi,
+    Extension::Epub,
+    Extension::Azw3,
+    Extension::Pdf,
+    Extension::Txt,
+    Extension::Htmlz,
+];
+// Formats older Calibre versions don't know about, and the version that
+// added them.
+const ADDED_IN: &[(Extension, CalibreVersion)] = &[(
+    Extension::Azw3,
+    CalibreVersion {
+        major: 0,
+        minor: 8,
+        patch: 50,
+    },
+)];
+// The version assumed when ebook-convert wasn't probed: recent enough for
+// every format of `ADDED_IN`.
+const ASSUMED_CALIBRE: CalibreVersion = CalibreVersion {
+    major: 7,
+    minor: 0,
+    patch: 0,
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct CalibreVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl std::fmt::Display for CalibreVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for CalibreVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+// Parses the output of `ebook-convert --version`, e.g.
+// "ebook-convert (calibre 7.4.0)".
+pub fn parse_version(output: &str) -> Option<CalibreVersion> {
+    let (_, rest) = output.split_once("calibre ")?;
+    let version = rest
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?;
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    Some(CalibreVersion {
+        major: parts.next()?.ok()?,
+        minor: parts.next()?.ok()?,
+        patch: parts.next().unwrap_or(Ok(0)).ok()?,
+    })
+}
+
+// A book none of whose editions can be converted to the format asked for.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct UnsupportedConversion {
+    // Why the best edition can't be, see `ConverterCapabilities::check`.
+    pub reason: String,
+    // The format of the best edition.
+    pub source: Extension,
+    pub requested: Extension,
+    // What the editions can be served as, in their order: each one's own
+    // format, then what it converts to.
+    pub available_formats: Vec<Extension>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ConverterCapabilities {
+    // False when conversion is disabled: books are only served as they are.
+    pub enabled: bool,
+    // `None` when ebook-convert couldn't be run.
+    pub calibre_version: Option<CalibreVersion>,
+    // Whether ddjvu is installed, see `convert::DDJVU_EXECUTABLE`.
+    pub djvu: bool,
+    // Formats that can be converted from and to. DJVU is only listed when
+    // both ddjvu and ebook-convert are there.
+    pub inputs: Vec<Extension>,
+    pub outputs: Vec<Extension>,
+}
+
+impl ConverterCapabilities {
+    // Looks the formats up for a Calibre version, or none without Calibre.
+    pub fn new(calibre_version: Option<CalibreVersion>, djvu: bool) -> Self {
+        let supported = |formats: &[Extension]| -> Vec<Extension> {
+            let Some(version) = calibre_version else {
+                return vec![];
+            };
+            formats
+                .iter()
+                .filter(|ext| {
+                    ADDED_IN
+                        .iter()
+                        .all(|(added, since)| added != *ext || version >= *since)
+                })
+                .cloned()
+                .collect()
+        };
+
+        let mut inputs = supported(CALIBRE_INPUTS);
+        if djvu && calibre_version.is_some() {
+            inputs.push(Extension::Djvu);
+        }
+        Self {
+            enabled: true,
+            calibre_version,
+            djvu,
+            inputs,
+            outputs: supported(CALIBRE_OUTPUTS),
+        }
+    }
+
+    // Nothing is converted, whatever is installed.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new(None, false)
+        }
+    }
+
+    // What a full toolchain converts, for when it wasn't probed: only the
+    // conversions Calibre and DjVuLibre can't do are rejected, or all of them
+    // when this build can't convert.
+    pub fn assumed() -> Self {
+        match cfg!(feature = "convert") {
+            true => Self::new(Some(ASSUMED_CALIBRE), true),
+            false => Self::disabled(),
+        }
+    }
+
+    // Runs `ebook-convert --version`, and looks for ddjvu.
+    pub async fn probe(executable: &str, djvu_executable: &str) -> Self {
+        let version = tokio::process::Command::new(executable)
+            .arg("--version")
+            .kill_on_drop(true)
+            .output();
+        let calibre_version = match tokio::time::timeout(PROBE_TIMEOUT, version).await {
+            Ok(Ok(output)) if output.status.success() => {
+                parse_version(&String::from_utf8_lossy(&output.stdout))
+            }
+            _ => None,
+        };
+
+        Self::new(calibre_version, convert::is_on_path(djvu_executable))
+    }
+
+    // Fails, saying why, when `from` can't be converted to `to`.
+    pub fn check(&self, from: &Extension, to: &Extension) -> Result<(), String> {
+        if from == to {
+            return Ok(());
+        }
+        let upper = |ext: &Extension| ext.to_string().to_uppercase();
+        if !self.enabled {
+            return Err(format!(
+                "conversion is disabled on this server: cannot convert {} to {}, ask for format=original",
+                upper(from),
+                upper(to)
+            ));
+        }
+        // ddjvu alone turns DJVU into PDF.
+        if *from == Extension::Djvu && (*to == Extension::Pdf || self.calibre_version.is_some()) {
+            return match self.djvu {
+                true if *to == Extension::Pdf => Ok(()),
+                true => self.check(&Extension::Pdf, to),
+                false => Err("this server cannot read DJVU without DjVuLibre".to_string()),
+            };
+        }
+        if self.calibre_version.is_none() {
+            return Err(format!(
+                "this server has no Calibre to convert {} to {}",
+                upper(from),
+                upper(to)
+            ));
+        }
+        if !self.inputs.contains(from) {
+            return Err(format!("this server's Calibre cannot read {}", upper(from)));
+        }
+        if !self.outputs.contains(to) {
+            return Err(format!("this server's Calibre cannot write {}", upper(to)));
+        }
+        Ok(())
+    }
+
+    // What a book in `from` can be served as: its own format, then the
+    // conversion targets `check` accepts.
+    pub fn available_outputs(&self, from: &Extension) -> Vec<Extension> {
+        std::iter::once(from.clone())
+            .chain(
+                CONVERSION_TARGETS
+                    .iter()
+                    .filter(|to| *to != from && self.check(from, to).is_ok())
+                    .cloned(),
+            )
+            .collect()
+    }
+
+    // Fails when none of `sources`, the formats of the editions of a book,
+    // best first, can be converted to `to`. Any of them will do, as the
+    // others are tried when the best one fails.
+    pub fn check_editions(
+        &self,
+        sources: &[Extension],
+        to: &Extension,
+    ) -> Result<(), UnsupportedConversion> {
+        let Some(best) = sources.first() else {
+            return Ok(());
+        };
+        let Err(reason) = self.check(best, to) else {
+            return Ok(());
+        };
+        if sources.iter().any(|from| self.check(from, to).is_ok()) {
+            return Ok(());
+        }
+
+        let mut available_formats: Vec<Extension> = vec![];
+        for output in sources.iter().flat_map(|from| self.available_outputs(from)) {
+            if !available_formats.contains(&output) {
+                available_formats.push(output);
+            }
+        }
+        Err(UnsupportedConversion {
+            reason,
+            source: best.clone(),
+            requested: to.clone(),
+            available_formats,
+        })
+    }
+}
+
+impl std::fmt::Display for ConverterCapabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |extensions: &[Extension]| {
+            extensions
+                .iter()
+                .map(Extension::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self.calibre_version {
+            _ if !self.enabled => write!(f, "disabled, books are only served as they are"),
+            Some(version) => write!(
+                f,
+                "Calibre {}, reads {}, writes {}",
+                version,
+                list(&self.inputs),
+                list(&self.outputs)
+            ),
+            None if self.djvu => write!(f, "no Calibre, only DJVU to PDF"),
+            None => write!(f, "no Calibre, books are only served as they are"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> CalibreVersion {
+        CalibreVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        for (output, want) in [
+            ("ebook-convert (calibre 7.4.0)", Some(version(7, 4, 0))),
+            (
+                "ebook-convert (calibre 5.37.0)\nCreated by: Kovid Goyal <kovid@kovidgoyal.net>\n",
+                Some(version(5, 37, 0)),
+            ),
+            ("ebook-convert.exe (calibre 6.11)", Some(version(6, 11, 0))),
+            ("ebook-convert (calibre 0.8.49)", Some(version(0, 8, 49))),
+            ("ebook-convert (calibre x.y)", None),
+            ("command not found", None),
+            ("", None),
+        ] {
+            assert_eq!(want, parse_version(output), "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn test_capabilities_by_version() {
+        let recent = ConverterCapabilities::new(Some(version(7, 4, 0)), true);
+        assert!(recent.outputs.contains(&Extension::Azw3));
+        assert!(recent.inputs.contains(&Extension::Djvu));
+        assert!(recent.inputs.contains(&Extension::Docx));
+        assert!(!recent.inputs.contains(&Extension::Doc));
+
+        let old = ConverterCapabilities::new(Some(version(0, 8, 49)), false);
+        assert!(!old.outputs.contains(&Extension::Azw3));
+        assert!(!old.inputs.contains(&Extension::Djvu));
+
+        let missing = ConverterCapabilities::new(None, true);
+        assert_eq!(Vec::<Extension>::new(), missing.inputs);
+        assert_eq!(Vec::<Extension>::new(), missing.outputs);
+    }
+
+    #[test]
+    fn test_assumed() {
+        let assumed = ConverterCapabilities::assumed();
+        if !cfg!(feature = "convert") {
+            assert_eq!(ConverterCapabilities::disabled(), assumed);
+            return;
+        }
+        assert!(assumed.check(&Extension::Djvu, &Extension::Azw3).is_ok());
+        assert!(assumed.check(&Extension::Cbr, &Extension::Epub).is_ok());
+        assert!(assumed.check(&Extension::Doc, &Extension::Epub).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let recent = ConverterCapabilities::new(Some(version(7, 4, 0)), true);
+        let without_djvulibre = ConverterCapabilities::new(Some(version(7, 4, 0)), false);
+        let old = ConverterCapabilities::new(Some(version(0, 8, 49)), true);
+        let missing = ConverterCapabilities::new(None, true);
+        let disabled = ConverterCapabilities::disabled();
+
+        for (capabilities, from, to, want) in [
+            (&recent, Extension::Epub, Extension::Azw3, Ok(())),
+            (&recent, Extension::Djvu, Extension::Epub, Ok(())),
+            (&recent, Extension::Cbz, Extension::Epub, Ok(())),
+            (
+                &recent,
+                Extension::Doc,
+                Extension::Epub,
+                Err("this server's Calibre cannot read DOC"),
+            ),
+            (
+                &without_djvulibre,
+                Extension::Djvu,
+                Extension::Epub,
+                Err("this server cannot read DJVU without DjVuLibre"),
+            ),
+            (
+                &old,
+                Extension::Epub,
+                Extension::Azw3,
+                Err("this server's Calibre cannot write AZW3"),
+            ),
+            (&missing, Extension::Djvu, Extension::Pdf, Ok(())),
+            (&disabled, Extension::Epub, Extension::Epub, Ok(())),
+            (
+                &disabled,
+                Extension::Djvu,
+                Extension::Pdf,
+                Err("conversion is disabled on this server: cannot convert DJVU to PDF, ask for format=original"),
+            ),
+            (&missing, Extension::Epub, Extension::Epub, Ok(())),
+            (
+                &missing,
+                Extension::Epub,
+                Extension::Mobi,
+                Err("this server has no Calibre to convert EPUB to MOBI"),
+            ),
+        ] {
+            assert_eq!(
+                want.map_err(str::to_string),
+                capabilities.check(&from, &to),
+                "{} to {}",
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn test_available_outputs() {
+        use Extension::*;
+
+        let recent = ConverterCapabilities::new(Some(version(7, 4, 0)), true);
+        let without_djvulibre = ConverterCapabilities::new(Some(version(7, 4, 0)), false);
+        let old = ConverterCapabilities::new(Some(version(0, 8, 49)), false);
+        let missing = ConverterCapabilities::new(None, true);
+        let disabled = ConverterCapabilities::disabled();
+
+        for (capabilities, from, want) in [
+            (&recent, Epub, vec![Epub, Mobi, Azw3, Pdf, Txt, Htmlz]),
+            (&recent, Djvu, vec![Djvu, Mobi, Epub, Azw3, Pdf, Txt, Htmlz]),
+            (&recent, Cbz, vec![Cbz, Mobi, Epub, Azw3, Pdf, Txt, Htmlz]),
+            (&recent, Doc, vec![Doc]),
+            (
+                &recent,
+                Other("zip".to_string()),
+                vec![Other("zip".to_string())],
+            ),
+            (&without_djvulibre, Djvu, vec![Djvu]),
+            (&old, Epub, vec![Epub, Mobi, Pdf, Txt, Htmlz]),
+            (&missing, Djvu, vec![Djvu, Pdf]),
+            (&missing, Epub, vec![Epub]),
+            (&disabled, Djvu, vec![Djvu]),
+            (&disabled, Epub, vec![Epub]),
+        ] {
+            assert_eq!(want, capabilities.available_outputs(&from), "{}", from);
+        }
+    }
+
+    #[test]
+    fn test_check_editions() {
+        use Extension::*;
+
+        let recent = ConverterCapabilities::new(Some(version(7, 4, 0)), true);
+        let without_djvulibre = ConverterCapabilities::new(Some(version(7, 4, 0)), false);
+        let disabled = ConverterCapabilities::disabled();
+
+        for (capabilities, sources, to) in [
+            (&recent, vec![Epub], Mobi),
+            (&recent, vec![Djvu], Epub),
+            // An alternative edition can be converted.
+            (&without_djvulibre, vec![Djvu, Pdf], Epub),
+            (&recent, vec![Doc, Docx], Epub),
+            // Or is in the format already.
+            (&disabled, vec![Epub, Mobi], Mobi),
+            (&without_djvulibre, vec![Djvu], Djvu),
+            (&recent, vec![], Epub),
+        ] {
+            assert_eq!(
+                Ok(()),
+                capabilities.check_editions(&sources, &to),
+                "{:?} to {}",
+                sources,
+                to
+            );
+        }
+
+        for (capabilities, sources, to, want) in [
+            (
+                &without_djvulibre,
+                vec![Djvu],
+                Epub,
+                UnsupportedConversion {
+                    reason: "this server cannot read DJVU without DjVuLibre".to_string(),
+                    source: Djvu,
+                    requested: Epub,
+                    available_formats: vec![Djvu],
+                },
+            ),
+            (
+                &recent,
+                vec![Epub, Pdf],
+                Djvu,
+                UnsupportedConversion {
+                    reason: "this server's Calibre cannot write DJVU".to_string(),
+                    source: Epub,
+                    requested: Djvu,
+                    available_formats: vec![Epub, Mobi, Azw3, Pdf, Txt, Htmlz],
+                },
+            ),
+            (
+                &recent,
+                vec![Other("zip".to_string()), Doc],
+                Epub,
+                UnsupportedConversion {
+                    reason: "this server's Calibre cannot read ZIP".to_string(),
+                    source: Other("zip".to_string()),
+                    requested: Epub,
+                    available_formats: vec![Other("zip".to_string()), Doc],
+                },
+            ),
+            (
+                &disabled,
+                vec![Djvu, Epub],
+                Mobi,
+                UnsupportedConversion {
+                    reason: "conversion is disabled on this server: cannot convert DJVU to MOBI, ask for format=original".to_string(),
+                    source: Djvu,
+                    requested: Mobi,
+                    available_formats: vec![Djvu, Epub],
+                },
+            ),
+        ] {
+            assert_eq!(
+                Err(want),
+                capabilities.check_editions(&sources, &to),
+                "{:?} to {}",
+                sources,
+                to
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let got = ConverterCapabilities::probe(
+            "tests/testdata/fake_ebook_convert_version.sh",
+            "this-executable-does-not-exist",
+        )
+        .await;
+        assert_eq!(Some(version(6, 11, 0)), got.calibre_version);
+        assert!(!got.djvu);
+        assert!(got.outputs.contains(&Extension::Epub));
+
+        let got = ConverterCapabilities::probe(
+            "this-executable-does-not-exist",
+            "this-executable-does-not-exist",
+        )
+        .await;
+        assert_eq!(ConverterCapabilities::new(None, false), got);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            "Calibre 0.8.49, reads mobi, epub, pdf, docx, txt, htmlz, cbz, cbr, writes mobi, epub, pdf, txt, htmlz",
+            ConverterCapabilities::new(Some(version(0, 8, 49)), false).to_string()
+        );
+        assert_eq!(
+            "no Calibre, only DJVU to PDF",
+            ConverterCapabilities::new(None, true).to_string()
+        );
+        assert_eq!(
+            "disabled, books are only served as they are",
+            ConverterCapabilities::disabled().to_string()
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Module circuit_breaker stops calling the upstreams that keep failing, so
+//! a mirror that is down fails right away instead of after its timeout, and
+//! the next store or mirror is tried at once.
+//!
+//! Each upstream host has its own circuit. It is closed as long as the host
+//! answers. After `DEFAULT_FAILURE_THRESHOLD` consecutive failures, i.e.
+//! connection errors, timeouts or 5xx answers, it opens: requests to the host
+//! fail without being sent. After `DEFAULT_COOLDOWN`, it is half-open: one
+//! request goes through, and closes the circuit when it succeeds, or opens it
+//! again when it fails.
+//!
+//! The circuits are reported by `/healthz`. The failures are also counted
+//! by host and kind of error, see `http_client::HttpErrorKind`, and reported
+//! as Prometheus counters by `/metrics`.
+
+use crate::{clock::SharedClock, http_client::HttpErrorKind};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+// The circuit of an upstream, as reported by `/healthz`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CircuitStatus {
+    pub state: State,
+    pub consecutive_failures: u32,
+}
+
+// A request refused because the circuit of its upstream is open.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CircuitOpen {
+    pub host: String,
+    pub consecutive_failures: u32,
+}
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is unavailable after {} consecutive failures",
+            self.host, self.consecutive_failures
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    // When the circuit last opened. It stays set until a request succeeds.
+    opened_at: Option<SystemTime>,
+    // When the half-open circuit let its trial request through. A trial
+    // that never reports back is given up on after the cooldown.
+    trial_at: Option<SystemTime>,
+}
+
+// The circuits of every upstream. Clones share their circuits, like
+// `http_client::ConnectionLimits`, which holds them.
+#[derive(Clone)]
+pub struct CircuitBreakers {
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    // How many requests failed, by host and label of their kind.
+    errors: Arc<Mutex<BTreeMap<(String, &'static str), u64>>>,
+    failure_threshold: u32,
+    cooldown: Duration,
+    clock: SharedClock,
+}
+
+impl CircuitBreakers {
+    pub fn new(failure_threshold: u32, cooldown: Duration, clock: SharedClock) -> Self {
+        Self {
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+            errors: Arc::default(),
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            clock,
+        }
+    }
+
+    // Returns an error, without calling the upstream of `url`, while its
+    // circuit is open. A half-open circuit lets a single request through.
+    pub fn check(&self, url: &reqwest::Url) -> Result<(), CircuitOpen> {
+        let host = host(url);
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(&host) else {
+            return Ok(());
+        };
+
+        let open = CircuitOpen {
+            host,
+            consecutive_failures: circuit.consecutive_failures,
+        };
+        match self.state(circuit, now) {
+            State::Closed => Ok(()),
+            State::Open => Err(open),
+            State::HalfOpen if self.elapsed(circuit.trial_at, now) => {
+                circuit.trial_at = Some(now);
+                Ok(())
+            }
+            State::HalfOpen => Err(open),
+        }
+    }
+
+    // The upstream of `url` answered: its circuit closes.
+    pub fn record_success(&self, url: &reqwest::Url) {
+        let mut circuits = self.circuits.lock().unwrap();
+        circuits.insert(host(url), Circuit::default());
+    }
+
+    // The upstream of `url` failed: its circuit opens once it failed
+    // `failure_threshold` times in a row, or when its trial request failed.
+    pub fn record_failure(&self, url: &reqwest::Url) {
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(host(url)).or_default();
+        circuit.consecutive_failures += 1;
+        circuit.trial_at = None;
+        if circuit.consecutive_failures >= self.failure_threshold {
+            circuit.opened_at = Some(now);
+        }
+    }
+
+    // Counts why a request to the upstream of `url` failed.
+    pub fn record_error(&self, url: &reqwest::Url, kind: &HttpErrorKind) {
+        let mut errors = self.errors.lock().unwrap();
+        *errors.entry((host(url), kind.label())).or_default() += 1;
+    }
+
+    // The failed requests as a Prometheus counter, in its text format.
+    pub fn metrics(&self) -> String {
+        let errors = self.errors.lock().unwrap();
+        let mut metrics = String::from(
+            "# HELP libreads_upstream_errors_total Requests to the upstream that failed, by kind of error.\n\
+             # TYPE libreads_upstream_errors_total counter\n",
+        );
+        for ((host, kind), count) in errors.iter() {
+            let _ = writeln!(
+                metrics,
+                "libreads_upstream_errors_total{{host=\"{}\",kind=\"{}\"}} {}",
+                host, kind, count
+            );
+        }
+        metrics
+    }
+
+    // The circuits of the upstreams called so far, by host.
+    pub fn statuses(&self) -> BTreeMap<String, CircuitStatus> {
+        let now = self.clock.now();
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .iter()
+            .map(|(host, circuit)| {
+                let status = CircuitStatus {
+                    state: self.state(circuit, now),
+                    consecutive_failures: circuit.consecutive_failures,
+                };
+                (host.clone(), status)
+            })
+            .collect()
+    }
+
+    fn state(&self, circuit: &Circuit, now: SystemTime) -> State {
+        match circuit.opened_at {
+            None => State::Closed,
+            Some(_) if self.elapsed(circuit.opened_at, now) => State::HalfOpen,
+            Some(_) => State::Open,
+        }
+    }
+
+    // Whether the cooldown has passed since `since`, or it is unset.
+    fn elapsed(&self, since: Option<SystemTime>, now: SystemTime) -> bool {
+        since.is_none_or(|since| now.duration_since(since).unwrap_or_default() >= self.cooldown)
+    }
+}
+
+// Upstreams are told apart by host, and port when it isn't the default
+// one, e.g. "library.lol" or "127.0.0.1:8080".
+pub(crate) fn host(url: &reqwest::Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_FAILURE_THRESHOLD,
+            DEFAULT_COOLDOWN,
+            SharedClock::default(),
+        )
+    }
+}
+
+impl std::fmt::Debug for CircuitBreakers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.statuses()).finish()
+    }
+}
+
+impl PartialEq for CircuitBreakers {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.circuits, &other.circuits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::time::UNIX_EPOCH;
+
+    fn breakers() -> (CircuitBreakers, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+        let breakers = CircuitBreakers::new(3, Duration::from_secs(30), SharedClock(clock.clone()));
+        (breakers, clock)
+    }
+
+    fn url(url: &str) -> reqwest::Url {
+        reqwest::Url::parse(url).unwrap()
+    }
+
+    fn state(breakers: &CircuitBreakers, host: &str) -> Option<State> {
+        breakers.statuses().get(host).map(|status| status.state)
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let (breakers, _) = breakers();
+        let page = url("http://library.lol/main/ABCD");
+
+        breakers.record_failure(&page);
+        breakers.record_failure(&page);
+        assert_eq!(Ok(()), breakers.check(&page));
+        // A success starts the count again.
+        breakers.record_success(&page);
+        breakers.record_failure(&page);
+        breakers.record_failure(&page);
+        assert_eq!(Some(State::Closed), state(&breakers, "library.lol"));
+
+        breakers.record_failure(&page);
+        assert_eq!(Some(State::Open), state(&breakers, "library.lol"));
+        assert_eq!(
+            Err(CircuitOpen {
+                host: "library.lol".to_string(),
+                consecutive_failures: 3,
+            }),
+            breakers.check(&url("http://library.lol/main/EFGH"))
+        );
+        // Other upstreams aren't affected.
+        assert_eq!(Ok(()), breakers.check(&url("http://library.lol:8080/main")));
+        assert_eq!(Ok(()), breakers.check(&url("https://libgen.rocks/ads.php")));
+    }
+
+    #[test]
+    fn test_half_open_after_cooldown() {
+        let (breakers, clock) = breakers();
+        let page = url("http://library.lol/main/ABCD");
+        for _ in 0..3 {
+            breakers.record_failure(&page);
+        }
+
+        clock.advance(Duration::from_secs(29));
+        assert!(breakers.check(&page).is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Some(State::HalfOpen), state(&breakers, "library.lol"));
+        // A single trial request goes through.
+        assert_eq!(Ok(()), breakers.check(&page));
+        assert!(breakers.check(&page).is_err());
+
+        // It failed: the circuit opens for another cooldown.
+        breakers.record_failure(&page);
+        assert_eq!(Some(State::Open), state(&breakers, "library.lol"));
+        clock.advance(Duration::from_secs(29));
+        assert!(breakers.check(&page).is_err());
+
+        // The next one succeeded: the circuit closes.
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Ok(()), breakers.check(&page));
+        breakers.record_success(&page);
+        assert_eq!(
+            BTreeMap::from([(
+                "library.lol".to_string(),
+                CircuitStatus {
+                    state: State::Closed,
+                    consecutive_failures: 0,
+                }
+            )]),
+            breakers.statuses()
+        );
+        assert_eq!(Ok(()), breakers.check(&page));
+        assert_eq!(Ok(()), breakers.check(&page));
+    }
+
+    #[test]
+    fn test_abandoned_trial() {
+        let (breakers, clock) = breakers();
+        let page = url("http://library.lol/main/ABCD");
+        for _ in 0..3 {
+            breakers.record_failure(&page);
+        }
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Ok(()), breakers.check(&page));
+        // The trial never reported back, e.g. its client went away.
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Ok(()), breakers.check(&page));
+    }
+
+    #[test]
+    fn test_shared_between_clones() {
+        let (breakers, _) = breakers();
+        let clone = breakers.clone();
+        for _ in 0..3 {
+            clone.record_failure(&url("https://dweb.link/ipfs/bafk"));
+        }
+
+        assert!(breakers
+            .check(&url("https://dweb.link/ipfs/other"))
+            .is_err());
+        assert_eq!(breakers, clone);
+        assert_ne!(breakers, CircuitBreakers::default());
+        assert_eq!(
+            "dweb.link is unavailable after 3 consecutive failures",
+            breakers
+                .check(&url("https://dweb.link/ipfs/bafk"))
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_error_metrics() {
+        let (breakers, _) = breakers();
+        let page = url("https://libgen.rs/json.php");
+        breakers.record_error(&page, &HttpErrorKind::DnsFailure);
+        breakers.record_error(&page, &HttpErrorKind::DnsFailure);
+        breakers.record_error(
+            &url("http://library.lol/main/ABCD"),
+            &HttpErrorKind::Status(502),
+        );
+
+        let metrics = breakers.clone().metrics();
+        for line in [
+            "libreads_upstream_errors_total{host=\"libgen.rs\",kind=\"dns_failure\"} 2",
+            "libreads_upstream_errors_total{host=\"library.lol\",kind=\"status\"} 1",
+        ] {
+            assert!(metrics.lines().any(|got| got == line), "{}", metrics);
+        }
+    }
+}
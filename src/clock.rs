@@ -0,0 +1,125 @@
+//! Module clock tells the time to the parts of LibReads that depend on it:
+//! the download links cache expiry, the library timestamps, and the waits
+//! before retrying a rate-limited upstream.
+//!
+//! `SystemClock` is used by default. Tests use a `MockClock`, which only
+//! moves when told to, so they neither sleep nor depend on the time.
+
+use async_trait::async_trait;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+    async fn sleep(&self, duration: Duration);
+}
+
+// Seconds since the Unix epoch, as timestamps are stored on disk.
+pub fn unix_seconds(clock: &dyn Clock) -> u64 {
+    clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+// A clock shared by the parts of LibReads, the system clock by default.
+#[derive(Clone)]
+pub struct SharedClock(pub Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(SystemClock))
+    }
+}
+
+impl std::ops::Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl std::fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedClock")
+    }
+}
+
+// A clock that only moves when advanced. Sleeping moves it forward right
+// away, and is recorded, to check how long LibReads waited.
+#[cfg(any(test, feature = "test-util"))]
+pub struct MockClock {
+    now: std::sync::Mutex<SystemTime>,
+    sleeps: std::sync::Mutex<Vec<Duration>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: std::sync::Mutex::new(now),
+            sleeps: std::sync::Mutex::new(vec![]),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    // How long each `sleep` was for, in order.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleeps.lock().unwrap().push(duration);
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_clock() {
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000));
+        assert_eq!(1_000, unix_seconds(&clock));
+
+        clock.advance(Duration::from_secs(10));
+        clock.sleep(Duration::from_secs(5)).await;
+        clock.sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(1_016, unix_seconds(&clock));
+        assert_eq!(
+            vec![Duration::from_secs(5), Duration::from_secs(1)],
+            clock.sleeps()
+        );
+    }
+}
@@ -1,84 +1,529 @@
-use crate::{extension::Extension, libreads::BookInfo};
-use tokio::{fs::File, io};
+use crate::{
+    capabilities::ConverterCapabilities,
+    extension::Extension,
+    http_client::{self, ConnectionLimits, HttpFailure},
+    language_check::{self, LanguageMismatch},
+    libreads::BookInfo,
+    name_template::{NameFields, NameTemplate},
+    output_path,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+#[cfg(feature = "convert")]
+use {
+    std::process::Stdio,
+    tokio::io::{self, AsyncSeekExt, SeekFrom},
+};
 
 const EBOOK_CONVERT_EXECUTABLE: &str = "ebook-convert";
+// ebook-convert can't read DJVU, DjVuLibre's ddjvu turns it into a PDF first.
+pub const DDJVU_EXECUTABLE: &str = "ddjvu";
+// Comics are converted for large screens, so their pages stay legible.
+#[cfg(feature = "convert")]
+const COMIC_OUTPUT_PROFILE: &str = "tablet";
+pub const DEFAULT_CONVERSION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 500 * 1024 * 1024;
+// The source of books copied from disk, e.g. from a Calibre library.
+pub const LOCAL_SOURCE: &str = "local";
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct InputBookInfo {
     title: String,
     extension: Extension,
     download_link: String,
+    cover_url: Option<String>,
+    // Where the book comes from, reported in `ConversionResult`: its LibGen
+    // MD5, and the mirror serving `download_link`.
+    #[serde(default)]
+    md5: String,
+    #[serde(default)]
+    source: String,
+    // Other mirrors serving the same file, as `(source, link)`, tried in
+    // order when `download_link` fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mirrors: Vec<(String, String)>,
+    // The size LibGen announced, in bytes: a download of another size isn't
+    // the book.
+    #[serde(default)]
+    filesize: Option<u64>,
+    // The name the book was uploaded to LibGen with, when known. It names
+    // the output file rather than the bare title.
+    #[serde(default)]
+    original_filename: Option<String>,
+    // Only used to name the output file, see `name_template`.
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    year: String,
+    #[serde(default)]
+    series: String,
+    #[serde(default)]
+    series_index: String,
 }
 
 impl From<BookInfo> for InputBookInfo {
     fn from(book: BookInfo) -> Self {
+        let mut links = book.download_links.named_links().into_iter();
+        let (source, download_link) = links.next().unwrap_or_default();
+        let source = match local_path(&download_link) {
+            Some(_) => LOCAL_SOURCE.to_string(),
+            None => source,
+        };
+
         Self {
             title: book.metadata.title,
             extension: book.metadata.extension,
-            download_link: book.download_links.cloudflare,
+            download_link,
+            cover_url: book.cover_url,
+            md5: book.metadata.md5,
+            source,
+            mirrors: links.collect(),
+            filesize: book.metadata.filesize,
+            original_filename: book.download_links.filename,
+            author: book.metadata.author,
+            year: book.metadata.year,
+            series: book.identification.series.unwrap_or_default(),
+            series_index: book.identification.series_index.unwrap_or_default(),
         }
     }
 }
 
+impl InputBookInfo {
+    // Whether the book is copied from disk rather than downloaded.
+    pub fn is_local(&self) -> bool {
+        self.local_path().is_some()
+    }
+
+    // The file the book is copied from, when it's on disk.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        local_path(&self.download_link)
+    }
+
+    // The mirror serving `download_link`, or `LOCAL_SOURCE`.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
 #[test]
 fn test_input_from_book_info() {
     let book_info = BookInfo {
         metadata: crate::libgen::LibgenMetadata {
             title: "Alice in Wonderland".to_string(),
-            author: "this field should be ignored".to_string(),
-            year: "this field should be ignored".to_string(),
+            author: "Lewis Carroll".to_string(),
+            year: "1865".to_string(),
             extension: Extension::Mobi,
-            md5: "this field should be ignored".to_string(),
+            md5: "ab13556b96d473c8dfad7165c4704526".to_string(),
+            filesize: Some(2_341_234),
+            language: None,
+            pages: None,
+            publisher: None,
         },
         download_links: crate::library_dot_lol::DownloadLinks {
-            cloudflare: "https://hello.com".to_string(),
-            ipfs_dot_io: "this field should be ignored".to_string(),
-            infura: "this field should be ignored".to_string(),
-            pinata: "this field should be ignored".to_string(),
-            http: "this field should be ignored".to_string(),
+            ipfs_cid: Some("bafk".to_string()),
+            gateways: vec![
+                "https://dweb.link".to_string(),
+                "https://w3s.link".to_string(),
+            ],
+            http: "http://12.34.45.67/main/alice.mobi".to_string(),
+            filename: Some("Alice in Wonderland (1865).mobi".to_string()),
         },
+        cover_url: Some("https://hello.com/cover.jpg".to_string()),
+        isbn10: None,
+        isbn13: None,
+        available_formats: vec![Extension::Mobi],
+        alternatives: vec![],
+        found_by_title: false,
+        identification: crate::goodreads::BookIdentification {
+            series: Some("Alice's Adventures".to_string()),
+            series_index: Some("1".to_string()),
+            ..Default::default()
+        },
+        book: None,
     };
     let got = InputBookInfo::from(book_info);
 
     let want = InputBookInfo {
         title: "Alice in Wonderland".to_string(),
         extension: Extension::Mobi,
-        download_link: "https://hello.com".to_string(),
+        download_link:
+            "https://dweb.link/ipfs/bafk?filename=Alice%20in%20Wonderland%20%281865%29.mobi"
+                .to_string(),
+        cover_url: Some("https://hello.com/cover.jpg".to_string()),
+        md5: "ab13556b96d473c8dfad7165c4704526".to_string(),
+        source: "dweb.link".to_string(),
+        original_filename: Some("Alice in Wonderland (1865).mobi".to_string()),
+        author: "Lewis Carroll".to_string(),
+        year: "1865".to_string(),
+        filesize: Some(2_341_234),
+        mirrors: vec![
+            (
+                "w3s.link".to_string(),
+                "https://w3s.link/ipfs/bafk?filename=Alice%20in%20Wonderland%20%281865%29.mobi"
+                    .to_string(),
+            ),
+            (
+                "HTTP".to_string(),
+                "http://12.34.45.67/main/alice.mobi".to_string(),
+            ),
+        ],
+        series: "Alice's Adventures".to_string(),
+        series_index: "1".to_string(),
     };
     assert_eq!(want, got);
 }
 
+#[test]
+fn test_input_book_info_round_trip() {
+    let input = InputBookInfo {
+        title: "Alice in Wonderland".to_string(),
+        extension: Extension::Mobi,
+        download_link: "https://hello.com".to_string(),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+
+    let json = serde_json::to_string(&input).unwrap();
+    assert_eq!(input, serde_json::from_str(&json).unwrap());
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConversionResult {
+    pub path: PathBuf,
+    pub extension: Extension,
+    pub bytes: u64,
+    // Whether ebook-convert was run, or the downloaded file was already in
+    // the wanted format.
+    pub converted: bool,
+    // The LibGen MD5 of the edition that was downloaded, the mirror that
+    // served it, e.g. "dweb.link" or `LOCAL_SOURCE`, and its format.
+    pub md5: String,
+    pub source: String,
+    pub original_extension: Extension,
+    pub download_duration: Duration,
+    pub conversion_duration: Option<Duration>,
+    // The downloaded book seems to be in another language than
+    // `ConversionOptions::language`, see `language_check`.
+    pub language_mismatch: Option<LanguageMismatch>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConversionOptions {
+    // ebook-convert output profile, e.g. "kindle_pw3". See `device`.
+    pub output_profile: Option<String>,
+    // ebook-convert is killed if the conversion takes longer than this.
+    pub timeout: Duration,
+    pub executable: String,
+    pub djvu_executable: String,
+    // Downloads larger than this many bytes are aborted. `None` disables the
+    // limit, for trusted sources only.
+    pub max_download_size: Option<u64>,
+    // Proxy for the book and cover downloads, see `http_client`.
+    pub proxy: Option<String>,
+    // Names the output file. Defaults to the original filename, or the title.
+    pub name_template: Option<NameTemplate>,
+    // Keeps comic pages in colour and in proportion, see `comic_options`.
+    pub keep_images: bool,
+    // What the server's toolchain can convert, probed at startup. Unknown
+    // when unset, and conversions are attempted anyway.
+    pub capabilities: Option<ConverterCapabilities>,
+    // Keeps the `.part` file of a download that failed midway, and resumes
+    // from it with a Range request, from the next mirror or the next run.
+    // The MD5 of resumed downloads is checked, as they are spliced together.
+    // Off by default: the server would leave the files behind.
+    pub resume: bool,
+    // Told how far downloads are, e.g. to draw a progress bar.
+    pub progress: Option<ProgressCallback>,
+    // How many books and covers are downloaded at once. `LibReads` replaces
+    // it with its own, shared with its other requests.
+    pub connection_limits: ConnectionLimits,
+    // The language the book is checked to be in once downloaded, lowercased
+    // as LibGen spells it, e.g. "english". Not checked when unset.
+    pub language: Option<String>,
+    // Where the book is downloaded and converted, created if missing. The
+    // server uses `Config::work_dir`, and the command line the current
+    // directory, the default.
+    pub work_dir: PathBuf,
+}
+
+// How far a download is. `total` is unknown when the mirror doesn't say, and
+// LibGen didn't either.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DownloadProgress {
+    pub source: String,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct ProgressCallback(pub Arc<dyn Fn(&DownloadProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(&DownloadProgress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressCallback")
+    }
+}
+
+impl PartialEq for ProgressCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            output_profile: None,
+            timeout: DEFAULT_CONVERSION_TIMEOUT,
+            executable: EBOOK_CONVERT_EXECUTABLE.to_string(),
+            djvu_executable: DDJVU_EXECUTABLE.to_string(),
+            max_download_size: Some(DEFAULT_MAX_DOWNLOAD_SIZE),
+            proxy: None,
+            name_template: None,
+            keep_images: false,
+            capabilities: None,
+            resume: false,
+            progress: None,
+            connection_limits: ConnectionLimits::default(),
+            language: None,
+            work_dir: PathBuf::new(),
+        }
+    }
+}
+
 // This takes some book metadata, download the book, convert it if needed and
-// return the converted book filename.
+// return the converted book path along with some stats.
 pub async fn download_as(
     book: InputBookInfo,
     wanted_extension: Extension,
-) -> Result<String, Error> {
-    let title = sanitise_title(book.title.as_str());
+) -> Result<ConversionResult, Error> {
+    download_as_with_options(book, wanted_extension, &ConversionOptions::default()).await
+}
+
+// Same as `download_as`, with control over how the conversion is done.
+pub async fn download_as_with_options(
+    book: InputBookInfo,
+    wanted_extension: Extension,
+    options: &ConversionOptions,
+) -> Result<ConversionResult, Error> {
+    if book.extension != wanted_extension && !wanted_extension.is_conversion_target() {
+        return Err(Error::Conversion(format!(
+            "cannot convert to {:?}",
+            wanted_extension.to_string()
+        )));
+    }
+    if let Some(capabilities) = &options.capabilities {
+        capabilities
+            .check(&book.extension, &wanted_extension)
+            .map_err(Error::Unsupported)?;
+    }
+    if book.extension != wanted_extension && !cfg!(feature = "convert") {
+        return Err(Error::Unsupported(format!(
+            "this build of LibReads cannot convert {} to {}, ask for format=original",
+            book.extension.to_string().to_uppercase(),
+            wanted_extension.to_string().to_uppercase()
+        )));
+    }
 
-    let in_filename = format!("{}.{}", title, book.extension);
-    download(book.download_link.as_str(), &in_filename).await?;
+    let client = http_client::client(options.proxy.as_deref())
+        .map_err(|err| Error::Http(HttpFailure::other(err.to_string())))?;
+
+    if !options.work_dir.as_os_str().is_empty() {
+        tokio::fs::create_dir_all(&options.work_dir).await?;
+    }
+    let name_template = options.name_template.as_ref();
+    let in_filename = work_file(
+        &options.work_dir,
+        output_filename(&book, &book.extension, name_template),
+    )?;
+    let download_start = Instant::now();
+    let source = download_from_mirrors(&client, &book, &in_filename, options).await?;
+    let download_duration = download_start.elapsed();
+    let language_mismatch = match &options.language {
+        Some(language) => check_language(&in_filename, &book.extension, language).await,
+        None => None,
+    };
 
     if book.extension == wanted_extension {
-        return Ok(in_filename);
+        let bytes = tokio::fs::metadata(&in_filename).await?.len();
+        return Ok(ConversionResult {
+            path: PathBuf::from(in_filename),
+            extension: wanted_extension,
+            bytes,
+            converted: false,
+            md5: book.md5.clone(),
+            source: source.clone(),
+            original_extension: book.extension.clone(),
+            download_duration,
+            conversion_duration: None,
+            language_mismatch,
+        });
+    }
+
+    #[cfg(not(feature = "convert"))]
+    unreachable!("conversions are rejected before downloading");
+    #[cfg(feature = "convert")]
+    convert_download(
+        &client,
+        &book,
+        in_filename,
+        wanted_extension,
+        source,
+        download_duration,
+        options,
+    )
+    .await
+    .map(|result| ConversionResult {
+        language_mismatch,
+        ..result
+    })
+}
+
+// Samples the downloaded book off the async runtime, see `language_check`.
+async fn check_language(
+    filename: &str,
+    extension: &Extension,
+    language: &str,
+) -> Option<LanguageMismatch> {
+    let (path, extension, language) = (
+        PathBuf::from(filename),
+        extension.clone(),
+        language.to_string(),
+    );
+    tokio::task::spawn_blocking(move || language_check::check(&path, &extension, &language))
+        .await
+        .ok()
+        .flatten()
+}
+
+// Converts the downloaded `in_filename`, which it deletes, to
+// `wanted_extension`.
+#[cfg(feature = "convert")]
+async fn convert_download(
+    client: &reqwest::Client,
+    book: &InputBookInfo,
+    in_filename: String,
+    wanted_extension: Extension,
+    source: String,
+    download_duration: Duration,
+    options: &ConversionOptions,
+) -> Result<ConversionResult, Error> {
+    let title = sanitise_title(book.title.as_str());
+    let name_template = options.name_template.as_ref();
+    if book.extension == Extension::Pdf && is_encrypted_pdf(Path::new(&in_filename)).await? {
+        tokio::fs::remove_file(&in_filename).await?;
+        return Err(Error::DrmProtected);
     }
 
-    let out_filename = format!("{}.{}", title, wanted_extension);
+    let out_filename = work_file(
+        &options.work_dir,
+        output_filename(book, &wanted_extension, name_template),
+    )?;
+    let conversion_start = Instant::now();
+
+    // ebook-convert can't read DJVU, so it goes through a PDF first.
+    let in_filename = if book.extension == Extension::Djvu {
+        if wanted_extension == Extension::Pdf {
+            djvu_to_pdf(&in_filename, &out_filename, options).await?;
+            let bytes = tokio::fs::metadata(&out_filename).await?.len();
+            return Ok(ConversionResult {
+                path: PathBuf::from(out_filename),
+                extension: wanted_extension,
+                bytes,
+                converted: true,
+                md5: book.md5.clone(),
+                source: source.clone(),
+                original_extension: book.extension.clone(),
+                download_duration,
+                conversion_duration: Some(conversion_start.elapsed()),
+                language_mismatch: None,
+            });
+        }
+
+        let pdf_filename = work_file(&options.work_dir, format!("{} (djvu).pdf", title))?;
+        djvu_to_pdf(&in_filename, &pdf_filename, options).await?;
+        pdf_filename
+    } else {
+        in_filename
+    };
+
+    let cover_filename = match &book.cover_url {
+        Some(cover_url) => {
+            let _permit = options.connection_limits.metadata().await;
+            download_cover(client, options, cover_url, &title).await
+        }
+        None => None,
+    };
 
-    println!("Converting book to {:?}...", wanted_extension);
-    // Note: using std::process instead of tokio::process because it hangs
-    // forever on the CI.
-    // TODO: figure out why and fix it.
-    let output = std::process::Command::new(EBOOK_CONVERT_EXECUTABLE)
-        .arg(&in_filename)
-        .arg(&out_filename)
-        .output()?;
+    tracing::info!("Converting book to {:?}...", wanted_extension);
+    let output = run_converter(
+        &options.executable,
+        ebook_convert_args(
+            &in_filename,
+            &out_filename,
+            cover_filename.as_deref(),
+            &comic_options(&book.extension, options),
+        ),
+        options.timeout,
+    )
+    .await;
+    let conversion_duration = conversion_start.elapsed();
 
     tokio::fs::remove_file(&in_filename)
         .await
         .expect("Delete input file");
+    if let Some(cover_filename) = cover_filename {
+        let _ = tokio::fs::remove_file(cover_filename).await;
+    }
 
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            // Don't leave a partially converted file behind.
+            let _ = tokio::fs::remove_file(&out_filename).await;
+            return Err(err);
+        }
+    };
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
     let output = String::from_utf8_lossy(&output.stdout);
+    if is_drm_error(&output) || is_drm_error(&stderr) {
+        let _ = tokio::fs::remove_file(&out_filename).await;
+        return Err(Error::DrmProtected);
+    }
+    if book.extension == Extension::Cbr && (is_unrar_error(&output) || is_unrar_error(&stderr)) {
+        let _ = tokio::fs::remove_file(&out_filename).await;
+        return Err(Error::UnrarMissing);
+    }
+    if is_unsupported_input_error(&output) || is_unsupported_input_error(&stderr) {
+        let _ = tokio::fs::remove_file(&out_filename).await;
+        return Err(Error::Unsupported(format!(
+            "this server's Calibre cannot read {}",
+            book.extension.to_string().to_uppercase()
+        )));
+    }
     if !output.contains("Output saved to") {
         // Something probably went wrong.
         // We return the full command output as an error.
@@ -87,34 +532,964 @@ pub async fn download_as(
         ));
     }
 
-    Ok(out_filename)
+    let bytes = tokio::fs::metadata(&out_filename).await?.len();
+    Ok(ConversionResult {
+        path: PathBuf::from(out_filename),
+        extension: wanted_extension,
+        bytes,
+        converted: true,
+        md5: book.md5.clone(),
+        source,
+        original_extension: book.extension.clone(),
+        download_duration,
+        conversion_duration: Some(conversion_duration),
+        language_mismatch: None,
+    })
 }
 
-#[cfg(test)]
-mod conversion_tests {
-    use super::*;
-    use httpmock::{Method::GET, MockServer};
+// Runs a converter, killing it if it takes longer than `timeout`.
+#[cfg(feature = "convert")]
+async fn run_converter(
+    executable: &str,
+    args: Vec<String>,
+    timeout: Duration,
+) -> Result<std::process::Output, Error> {
+    // Note: std::process used to be used instead of tokio::process because
+    // the latter hung forever on the CI, which runs the tests under
+    // tarpaulin. Killing ebook-convert on timeout takes tokio::process, and
+    // the timeout now bounds such a hang instead of the whole test run.
+    // TODO: figure out why it hung and whether it still does.
+    // The child is killed if the timeout drops the `wait_with_output` future.
+    let child = tokio::process::Command::new(executable)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => Ok(output?),
+        Err(_) => Err(Error::ConversionTimeout(timeout)),
+    }
+}
+
+// Converts a DJVU file to PDF, and deletes the DJVU file.
+#[cfg(feature = "convert")]
+async fn djvu_to_pdf(
+    djvu_filename: &str,
+    pdf_filename: &str,
+    options: &ConversionOptions,
+) -> Result<(), Error> {
+    tracing::info!("Converting DJVU to PDF...");
+    let output = run_converter(
+        &options.djvu_executable,
+        ddjvu_args(djvu_filename, pdf_filename),
+        options.timeout,
+    )
+    .await;
+    let _ = tokio::fs::remove_file(djvu_filename).await;
+
+    let err = match output {
+        Ok(output) if output.status.success() => return Ok(()),
+        Ok(output) => Error::Conversion(String::from_utf8_lossy(&output.stderr).to_string()),
+        Err(err) => err,
+    };
+    let _ = tokio::fs::remove_file(pdf_filename).await;
+    Err(err)
+}
+
+#[cfg(feature = "convert")]
+fn ddjvu_args(djvu_filename: &str, pdf_filename: &str) -> Vec<String> {
+    vec![
+        "-format=pdf".to_string(),
+        djvu_filename.to_string(),
+        pdf_filename.to_string(),
+    ]
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_ddjvu_args() {
+    assert_eq!(
+        vec!["-format=pdf", "Book.djvu", "Book (djvu).pdf"],
+        ddjvu_args("Book.djvu", "Book (djvu).pdf")
+    );
+}
+
+// Whether `executable` can be found in one of the PATH directories.
+pub fn is_on_path(executable: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(executable).is_file()))
+        .unwrap_or(false)
+}
+
+#[test]
+fn test_is_on_path() {
+    assert!(is_on_path("sh"));
+    assert!(!is_on_path("this-executable-does-not-exist"));
+}
+
+// Calibre refuses to convert DRM-protected books, and says so in its output.
+#[cfg(feature = "convert")]
+fn is_drm_error(output: &str) -> bool {
+    output.contains("DRMError") || output.contains("This book is DRMed")
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_is_drm_error() {
+    for output in [
+        "calibre.ebooks.DRMError: This file is locked with DRM.",
+        "ValueError: This book is DRMed and cannot be converted",
+    ] {
+        assert!(is_drm_error(output), "{}", output);
+    }
+    for output in ["", "Output saved to   /tmp/book.mobi"] {
+        assert!(!is_drm_error(output), "{}", output);
+    }
+}
+
+// Calibre reads CBR through unrar, which some builds ship without.
+#[cfg(feature = "convert")]
+fn is_unrar_error(output: &str) -> bool {
+    let output = output.to_lowercase();
+    output.contains("unrar") && (output.contains("not found") || output.contains("no module"))
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_is_unrar_error() {
+    for output in [
+        "ModuleNotFoundError: No module named 'calibre_extensions.unrar'",
+        "RuntimeError: unrar not found, install it to read RAR archives",
+    ] {
+        assert!(is_unrar_error(output), "{}", output);
+    }
+    for output in ["", "Output saved to   /tmp/unrarred.epub"] {
+        assert!(!is_unrar_error(output), "{}", output);
+    }
+}
+
+// Calibre has no input plugin for some formats, e.g. legacy DOC, and says
+// "ValueError: No plugin to handle input format: doc".
+#[cfg(feature = "convert")]
+fn is_unsupported_input_error(output: &str) -> bool {
+    output
+        .to_lowercase()
+        .contains("no plugin to handle input format")
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_is_unsupported_input_error() {
+    assert!(is_unsupported_input_error(
+        "ValueError: No plugin to handle input format: doc"
+    ));
+    for output in ["", "Output saved to   /tmp/plugin.epub"] {
+        assert!(!is_unsupported_input_error(output), "{}", output);
+    }
+}
+
+// How much of each end of a PDF is searched for the encryption dictionary.
+#[cfg(feature = "convert")]
+const PDF_ENCRYPT_SEARCH_SIZE: u64 = 64 * 1024;
+
+// Encrypted PDFs reference an `/Encrypt` dictionary from their trailer, which
+// sits at the end of the file, or at the start of linearized files.
+#[cfg(feature = "convert")]
+async fn is_encrypted_pdf(path: &Path) -> Result<bool, Error> {
+    let mut file = File::open(path).await?;
+    let len = file.metadata().await?.len();
+
+    let mut head = Vec::new();
+    (&mut file)
+        .take(PDF_ENCRYPT_SEARCH_SIZE)
+        .read_to_end(&mut head)
+        .await?;
+    if contains(&head, b"/Encrypt") {
+        return Ok(true);
+    }
+
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(len.saturating_sub(PDF_ENCRYPT_SEARCH_SIZE)))
+        .await?;
+    file.read_to_end(&mut tail).await?;
+    Ok(contains(&tail, b"/Encrypt"))
+}
+
+#[cfg(feature = "convert")]
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(feature = "convert")]
+#[tokio::test]
+async fn test_is_encrypted_pdf() {
+    assert_eq!(
+        Ok(true),
+        is_encrypted_pdf(Path::new("tests/testdata/dummy_encrypted_ebook.pdf")).await
+    );
+    assert_eq!(
+        Ok(false),
+        is_encrypted_pdf(Path::new("tests/testdata/dummy_invalid_ebook.pdf")).await
+    );
+    assert!(is_encrypted_pdf(Path::new("this file doesn't exist"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_download_as_disallowed_target() {
+    let book = InputBookInfo {
+        title: "Alice in Wonderland".to_string(),
+        extension: Extension::Epub,
+        download_link: "http://this.link.is.never.fetched/book.epub".to_string(),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+
+    let got = download_as(book, Extension::Other("exe".to_string())).await;
+
+    assert_eq!(
+        Err(Error::Conversion("cannot convert to \"exe\"".to_string())),
+        got
+    );
+}
+
+// Run with `cargo test --no-default-features`.
+#[cfg(not(feature = "convert"))]
+#[tokio::test]
+async fn test_download_as_without_convert_feature() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/book.epub");
+        then.status(200)
+            .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+    });
+    let book = InputBookInfo {
+        title: "Without Calibre".to_string(),
+        extension: Extension::Epub,
+        download_link: mock_server.url("/book.epub"),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+
+    // Conversions are rejected before downloading anything.
+    let got = download_as(book.clone(), Extension::Mobi).await;
+    assert_eq!(
+        Err(Error::Unsupported(
+            "this build of LibReads cannot convert EPUB to MOBI, ask for format=original"
+                .to_string()
+        )),
+        got
+    );
+    endpoint_mock.assert_hits(0);
+
+    let got = download_as(book, Extension::Epub).await.unwrap();
+    assert!(!got.converted);
+    endpoint_mock.assert();
+    std::fs::remove_file(got.path).unwrap();
+}
+
+// Returns the name of the file `download_as` will produce, without
+// downloading or converting anything.
+pub fn output_filename(
+    book: &InputBookInfo,
+    wanted_extension: &Extension,
+    name_template: Option<&NameTemplate>,
+) -> String {
+    if let Some(name_template) = name_template {
+        return output_path::escape_reserved(name_template.render(&NameFields {
+            title: &book.title,
+            author: &book.author,
+            year: &book.year,
+            extension: wanted_extension,
+            md5: &book.md5,
+            series: &book.series,
+            series_index: &book.series_index,
+        }));
+    }
+    let stem = book
+        .original_filename
+        .as_deref()
+        .map(|filename| sanitise_filename(original_stem(filename, &book.extension)))
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| sanitise_title(&book.title));
+    output_path::escape_reserved(format!("{}.{}", stem, wanted_extension))
+}
+
+// Where a file named `name` is written in `work_dir`, once checked to be a
+// safe file name, see `output_path`.
+fn work_file(work_dir: &Path, name: String) -> Result<String, Error> {
+    let path = output_path::join(work_dir, &name)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+// Strips the extension of the original file, if it has the expected one.
+fn original_stem<'a>(filename: &'a str, extension: &Extension) -> &'a str {
+    let suffix = format!(".{}", extension);
+    match filename.len().checked_sub(suffix.len()) {
+        Some(at)
+            if filename.is_char_boundary(at) && filename[at..].eq_ignore_ascii_case(&suffix) =>
+        {
+            &filename[..at]
+        }
+        _ => filename,
+    }
+}
+
+// Unlike `sanitise_title`, keeps the punctuation of original filenames, which
+// often carry the edition or publisher, e.g. "Title (Publisher, 2000)". Only
+// what could break a header or a path goes.
+fn sanitise_filename(filename: &str) -> String {
+    filename
+        .replace(|c: char| "/\\\":*?<>|".contains(c), " ")
+        .replace(char::is_control, "")
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end()
+        .to_string()
+}
+
+#[test]
+fn test_sanitise_filename() {
+    for (filename, want) in [
+        (
+            "Pride and Prejudice (CIDEB, 2000)",
+            "Pride and Prejudice (CIDEB, 2000)",
+        ),
+        ("Orgueil et préjugés", "Orgueil et préjugés"),
+        ("../../etc/passwd", "etc passwd"),
+        ("say \"hi\"\r\n", "say  hi"),
+        ("...", ""),
+    ] {
+        assert_eq!(want, sanitise_filename(filename), "{:?}", filename);
+    }
+}
+
+#[test]
+fn test_output_filename() {
+    let book = InputBookInfo {
+        title: "Alice: in Wonderland".to_string(),
+        extension: Extension::Epub,
+        download_link: "https://hello.com".to_string(),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+
+    assert_eq!(
+        "Alice  in Wonderland.mobi",
+        output_filename(&book, &Extension::Mobi, None)
+    );
+    assert_eq!(
+        "Alice  in Wonderland.epub",
+        output_filename(&book, &Extension::Epub, None)
+    );
+
+    // The original filename is kept, with the extension swapped when
+    // converting.
+    for (original_filename, want_epub, want_mobi) in [
+        (
+            "Lewis Carroll - Alice: in Wonderland (Macmillan, 1865).EPUB",
+            "Lewis Carroll - Alice  in Wonderland (Macmillan, 1865).epub",
+            "Lewis Carroll - Alice  in Wonderland (Macmillan, 1865).mobi",
+        ),
+        (
+            "Alice au pays des merveilles",
+            "Alice au pays des merveilles.epub",
+            "Alice au pays des merveilles.mobi",
+        ),
+        (
+            "/.epub",
+            "Alice  in Wonderland.epub",
+            "Alice  in Wonderland.mobi",
+        ),
+    ] {
+        let book = InputBookInfo {
+            original_filename: Some(original_filename.to_string()),
+            ..book.clone()
+        };
+        assert_eq!(want_epub, output_filename(&book, &Extension::Epub, None));
+        assert_eq!(want_mobi, output_filename(&book, &Extension::Mobi, None));
+    }
+
+    // A template takes precedence over the original filename.
+    let book = InputBookInfo {
+        author: "Lewis Carroll".to_string(),
+        year: "1865".to_string(),
+        original_filename: Some("alice.epub".to_string()),
+        ..book
+    };
+    let name_template: NameTemplate = "{author} - {title} ({year})".parse().unwrap();
+    assert_eq!(
+        "Lewis Carroll - Alice  in Wonderland (1865).mobi",
+        output_filename(&book, &Extension::Mobi, Some(&name_template))
+    );
+}
+
+#[test]
+fn test_output_filename_is_safe() {
+    let book = InputBookInfo {
+        title: String::new(),
+        extension: Extension::Epub,
+        download_link: "https://hello.com".to_string(),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+    let name_template: NameTemplate = "{author} {title}".parse().unwrap();
+
+    // Titles and original filenames come from LibGen: none of them can make
+    // LibReads write outside of the work directory.
+    for name in [
+        "../../etc/cron.d/evil",
+        "/etc/passwd",
+        "..\\..\\windows\\evil",
+        "C:\\evil",
+        "evil\0.epub",
+        "..",
+        "Con",
+        "LPT1",
+        "nul.",
+        "Title. . ",
+    ] {
+        let book = InputBookInfo {
+            title: name.to_string(),
+            original_filename: Some(name.to_string()),
+            ..book.clone()
+        };
+        let from_title = InputBookInfo {
+            original_filename: None,
+            ..book.clone()
+        };
+        for filename in [
+            output_filename(&book, &Extension::Epub, None),
+            output_filename(&from_title, &Extension::Epub, None),
+            output_filename(&book, &Extension::Epub, Some(&name_template)),
+        ] {
+            assert!(
+                output_path::join(Path::new(""), &filename).is_ok(),
+                "{:?} gave {:?}",
+                name,
+                filename
+            );
+        }
+    }
+    assert_eq!(
+        "_Con.epub",
+        output_filename(
+            &InputBookInfo {
+                title: "Con".to_string(),
+                ..book
+            },
+            &Extension::Epub,
+            None
+        )
+    );
+}
+
+#[cfg(feature = "convert")]
+fn ebook_convert_args(
+    in_filename: &str,
+    out_filename: &str,
+    cover: Option<&str>,
+    options: &ConversionOptions,
+) -> Vec<String> {
+    let mut args = vec![in_filename.to_string(), out_filename.to_string()];
+    if let Some(cover) = cover {
+        args.push("--cover".to_string());
+        args.push(cover.to_string());
+    }
+    if let Some(output_profile) = &options.output_profile {
+        args.push("--output-profile".to_string());
+        args.push(output_profile.to_string());
+    }
+    if options.keep_images {
+        args.push("--dont-grayscale".to_string());
+        args.push("--keep-aspect-ratio".to_string());
+    }
+
+    args
+}
+
+// Comics use the tablet profile unless a device asked for one, and keep their
+// images in colour and in proportion.
+#[cfg(feature = "convert")]
+fn comic_options(input: &Extension, options: &ConversionOptions) -> ConversionOptions {
+    if !input.is_comic() {
+        return options.clone();
+    }
+    ConversionOptions {
+        output_profile: Some(
+            options
+                .output_profile
+                .clone()
+                .unwrap_or_else(|| COMIC_OUTPUT_PROFILE.to_string()),
+        ),
+        keep_images: true,
+        ..options.clone()
+    }
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_comic_options() {
+    let default_options = ConversionOptions::default();
+    assert_eq!(
+        default_options,
+        comic_options(&Extension::Epub, &default_options)
+    );
+
+    for input in [Extension::Cbz, Extension::Cbr] {
+        let got = comic_options(&input, &default_options);
+        assert_eq!(Some("tablet".to_string()), got.output_profile);
+        assert!(got.keep_images);
+        assert_eq!(
+            vec![
+                "comic.cbz",
+                "comic.epub",
+                "--output-profile",
+                "tablet",
+                "--dont-grayscale",
+                "--keep-aspect-ratio"
+            ],
+            ebook_convert_args("comic.cbz", "comic.epub", None, &got)
+        );
+    }
+
+    let kindle_options = ConversionOptions {
+        output_profile: Some("kindle_pw3".to_string()),
+        ..ConversionOptions::default()
+    };
+    assert_eq!(
+        Some("kindle_pw3".to_string()),
+        comic_options(&Extension::Cbz, &kindle_options).output_profile
+    );
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_ebook_convert_args() {
+    let default_options = ConversionOptions::default();
+    assert_eq!(
+        vec!["book.epub", "book.mobi"],
+        ebook_convert_args("book.epub", "book.mobi", None, &default_options)
+    );
+    assert_eq!(
+        vec!["book.epub", "book.mobi", "--cover", "book.cover.jpg"],
+        ebook_convert_args(
+            "book.epub",
+            "book.mobi",
+            Some("book.cover.jpg"),
+            &default_options
+        )
+    );
+
+    let kindle_options = ConversionOptions {
+        output_profile: Some("kindle_pw3".to_string()),
+        ..ConversionOptions::default()
+    };
+    assert_eq!(
+        vec![
+            "book.epub",
+            "book.azw3",
+            "--cover",
+            "book.cover.jpg",
+            "--output-profile",
+            "kindle_pw3"
+        ],
+        ebook_convert_args(
+            "book.epub",
+            "book.azw3",
+            Some("book.cover.jpg"),
+            &kindle_options
+        )
+    );
+}
+
+// Downloads the cover next to the book file. A missing cover should never
+// prevent the conversion, so failures are only logged.
+#[cfg(feature = "convert")]
+async fn download_cover(
+    client: &reqwest::Client,
+    options: &ConversionOptions,
+    cover_url: &str,
+    title: &str,
+) -> Option<String> {
+    let limits = &options.connection_limits;
+    let cover_filename = work_file(
+        &options.work_dir,
+        format!("{}.cover.{}", title, cover_extension(cover_url)),
+    )
+    .map_err(|err| tracing::warn!("Not downloading the cover: {:?}", err))
+    .ok()?;
+
+    let result: Result<(), Error> = async {
+        let resp = http_client::send_once(client.get(cover_url), limits)
+            .await?
+            .error_for_status()?;
+        let mut out = File::create(&cover_filename).await?;
+        io::copy(&mut resp.bytes().await?.as_ref(), &mut out).await?;
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => Some(cover_filename),
+        Err(err) => {
+            tracing::warn!(
+                "Could not download the cover, converting without it: {:?}",
+                err
+            );
+            let _ = tokio::fs::remove_file(&cover_filename).await;
+            None
+        }
+    }
+}
+
+#[cfg(feature = "convert")]
+fn cover_extension(cover_url: &str) -> &'static str {
+    let path = cover_url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit('.').next().map(|ext| ext.to_lowercase()) {
+        Some(ext) if ext == "png" => "png",
+        Some(ext) if ext == "gif" => "gif",
+        _ => "jpg",
+    }
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn test_cover_extension() {
+    for (url, want) in [
+        ("https://example.com/books/5470.jpg", "jpg"),
+        ("https://example.com/books/5470.JPEG", "jpg"),
+        ("https://example.com/no-cover.png", "png"),
+        ("https://example.com/cover.gif?size=large", "gif"),
+        ("https://example.com/cover", "jpg"),
+    ] {
+        assert_eq!(want, cover_extension(url));
+    }
+}
+
+#[cfg(feature = "convert")]
+#[tokio::test]
+async fn test_download_cover_failure_is_not_fatal() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/cover.jpg");
+        then.status(404);
+    });
+
+    let got = download_cover(
+        &reqwest::Client::new(),
+        &ConversionOptions::default(),
+        &mock_server.url("/cover.jpg"),
+        "Missing cover",
+    )
+    .await;
+    assert_eq!(None, got);
+    assert!(!std::path::Path::new("Missing cover.cover.jpg").exists());
+    endpoint_mock.assert();
+}
+
+#[cfg(feature = "convert")]
+#[tokio::test]
+async fn test_download_cover() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/cover.png");
+        then.status(200).body("not really a png");
+    });
+
+    let got = download_cover(
+        &reqwest::Client::new(),
+        &ConversionOptions::default(),
+        &mock_server.url("/cover.png"),
+        "Some cover",
+    )
+    .await;
+    assert_eq!(Some("Some cover.cover.png".to_string()), got);
+    std::fs::remove_file("Some cover.cover.png").expect("Delete cover file");
+    endpoint_mock.assert();
+}
+
+#[cfg(all(test, feature = "convert"))]
+mod conversion_tests {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    #[tokio::test]
+    async fn convert() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+        });
+
+        let book = InputBookInfo {
+            title: "Governing the Commons".to_string(),
+            extension: Extension::Epub,
+            download_link: mock_server.url("/book.epub"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+
+        let got = download_as(book, Extension::Mobi).await.unwrap();
+        assert_eq!(PathBuf::from("Governing the Commons.mobi"), got.path);
+        assert_eq!(Extension::Mobi, got.extension);
+        assert!(got.converted);
+        assert!(got.conversion_duration.is_some());
+        assert_eq!(
+            std::fs::metadata(&got.path)
+                .expect("Output file exists")
+                .len(),
+            got.bytes
+        );
+        tokio::fs::remove_file(got.path)
+            .await
+            .expect("Delete output file");
+        endpoint_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn convert_djvu_through_pdf() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.djvu");
+            then.status(200).body("not really a DJVU file");
+        });
+
+        let book = InputBookInfo {
+            title: "Djvu Book".to_string(),
+            extension: Extension::Djvu,
+            download_link: mock_server.url("/book.djvu"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+        let options = ConversionOptions {
+            executable: "tests/testdata/slow_ebook_convert.sh".to_string(),
+            djvu_executable: "tests/testdata/fake_ddjvu.sh".to_string(),
+            ..ConversionOptions::default()
+        };
+
+        let got = download_as_with_options(book, Extension::Epub, &options)
+            .await
+            .unwrap();
+        assert_eq!(PathBuf::from("Djvu Book.epub"), got.path);
+        assert!(got.converted);
+        assert_eq!(
+            "not really a DJVU file",
+            std::fs::read_to_string(&got.path).unwrap()
+        );
+        // Intermediate files have been deleted.
+        assert!(!Path::new("Djvu Book.djvu").exists());
+        assert!(!Path::new("Djvu Book (djvu).pdf").exists());
+        tokio::fs::remove_file(got.path)
+            .await
+            .expect("Delete output file");
+        endpoint_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn convert_djvu_to_pdf() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.djvu");
+            then.status(200).body("not really a DJVU file");
+        });
+
+        let book = InputBookInfo {
+            title: "Djvu To Pdf".to_string(),
+            extension: Extension::Djvu,
+            download_link: mock_server.url("/book.djvu"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+        let options = ConversionOptions {
+            executable: "this-executable-does-not-exist".to_string(),
+            djvu_executable: "tests/testdata/fake_ddjvu.sh".to_string(),
+            ..ConversionOptions::default()
+        };
+
+        // ebook-convert isn't needed: ddjvu already produces a PDF.
+        let got = download_as_with_options(book, Extension::Pdf, &options)
+            .await
+            .unwrap();
+        assert_eq!(PathBuf::from("Djvu To Pdf.pdf"), got.path);
+        assert!(!Path::new("Djvu To Pdf.djvu").exists());
+        tokio::fs::remove_file(got.path)
+            .await
+            .expect("Delete output file");
+        endpoint_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn djvu_conversion_fails() {
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/book.djvu");
+            then.status(200).body("not really a DJVU file");
+        });
+
+        let book = InputBookInfo {
+            title: "Broken Djvu".to_string(),
+            extension: Extension::Djvu,
+            download_link: mock_server.url("/book.djvu"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+        let options = ConversionOptions {
+            djvu_executable: "false".to_string(),
+            ..ConversionOptions::default()
+        };
+
+        let got = download_as_with_options(book, Extension::Epub, &options).await;
+        assert!(matches!(got, Err(Error::Conversion(_))));
+        assert!(!Path::new("Broken Djvu.djvu").exists());
+        assert!(!Path::new("Broken Djvu (djvu).pdf").exists());
+    }
+
+    #[tokio::test]
+    async fn unsupported_conversion_fails_fast() {
+        let book = InputBookInfo {
+            title: "Unsupported".to_string(),
+            extension: Extension::Doc,
+            download_link: "http://this.link.is.never.fetched/book.doc".to_string(),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+        let options = ConversionOptions {
+            capabilities: Some(
+                ConverterCapabilities::probe(
+                    "tests/testdata/fake_ebook_convert_version.sh",
+                    "this-executable-does-not-exist",
+                )
+                .await,
+            ),
+            ..ConversionOptions::default()
+        };
+
+        let got = download_as_with_options(book, Extension::Epub, &options).await;
+        assert_eq!(
+            Err(Error::Unsupported(
+                "this server's Calibre cannot read DOC".to_string()
+            )),
+            got
+        );
+        assert!(!Path::new("Unsupported.doc").exists());
+    }
 
     #[tokio::test]
-    async fn convert() {
+    async fn cbr_without_unrar() {
         let mock_server = MockServer::start();
-        let endpoint_mock = mock_server.mock(|when, then| {
-            when.method(GET).path("/book.epub");
-            then.status(200)
-                .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/comic.cbr");
+            then.status(200).body("not really a CBR file");
         });
 
         let book = InputBookInfo {
-            title: "Governing the Commons".to_string(),
-            extension: Extension::Epub,
-            download_link: mock_server.url("/book.epub"),
+            title: "Rar Comic".to_string(),
+            extension: Extension::Cbr,
+            download_link: mock_server.url("/comic.cbr"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+        let options = ConversionOptions {
+            executable: "tests/testdata/no_unrar_ebook_convert.sh".to_string(),
+            ..ConversionOptions::default()
         };
 
-        let output_filename = download_as(book, Extension::Mobi).await.unwrap();
-        tokio::fs::remove_file(output_filename)
-            .await
-            .expect("Delete output file");
-        endpoint_mock.assert();
+        let got = download_as_with_options(book, Extension::Epub, &options).await;
+        assert_eq!(Err(Error::UnrarMissing), got);
+        assert!(!Path::new("Rar Comic.cbr").exists());
+        assert!(!Path::new("Rar Comic.epub").exists());
     }
 
     #[tokio::test]
@@ -130,6 +1505,16 @@ mod conversion_tests {
             title: "Dummy invalid ebook 1".to_string(),
             extension: Extension::Pdf,
             download_link: mock_server.url("/book.pdf"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
         };
 
         let got = download_as(book, Extension::Mobi).await;
@@ -137,6 +1522,54 @@ mod conversion_tests {
         endpoint_mock.assert();
     }
 
+    #[tokio::test]
+    async fn conversion_times_out() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+        });
+
+        // Fake converter that starts writing the output file, then hangs.
+        let converter = std::env::temp_dir().join("libreads_slow_ebook_convert.sh");
+        std::fs::write(&converter, "#!/bin/sh\ntouch \"$2\"\nsleep 5\n").unwrap();
+        std::fs::set_permissions(&converter, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let book = InputBookInfo {
+            title: "Slow conversion".to_string(),
+            extension: Extension::Epub,
+            download_link: mock_server.url("/book.epub"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
+        };
+        let options = ConversionOptions {
+            timeout: Duration::from_millis(100),
+            executable: converter.to_string_lossy().to_string(),
+            ..ConversionOptions::default()
+        };
+
+        let got = download_as_with_options(book, Extension::Mobi, &options).await;
+        assert_eq!(
+            Err(Error::ConversionTimeout(Duration::from_millis(100))),
+            got
+        );
+        assert!(!std::path::Path::new("Slow conversion.epub").exists());
+        assert!(!std::path::Path::new("Slow conversion.mobi").exists());
+        endpoint_mock.assert();
+        std::fs::remove_file(converter).unwrap();
+    }
+
     #[tokio::test]
     async fn returns_early_if_no_conversion_is_needed() {
         let mock_server = MockServer::start();
@@ -150,15 +1583,33 @@ mod conversion_tests {
             title: "Dummy invalid ebook 2".to_string(),
             extension: Extension::Pdf,
             download_link: mock_server.url("/book.pdf"),
+            cover_url: None,
+            md5: String::new(),
+            source: String::new(),
+            original_filename: None,
+            author: String::new(),
+            year: String::new(),
+            filesize: None,
+            mirrors: vec![],
+            series: String::new(),
+            series_index: String::new(),
         };
 
         // Note: when the input format and output format are the same (here PDF),
         // if should not try to perform any conversion.
         // Therefore, it should not matter whether the ebook is valid or invalid.
-        let output_filename = download_as(book, Extension::Pdf)
+        let got = download_as(book, Extension::Pdf)
             .await
             .expect("Should exit early and not perform validations");
-        std::fs::remove_file(output_filename).expect("Delete output file");
+        assert_eq!(PathBuf::from("Dummy invalid ebook 2.pdf"), got.path);
+        assert_eq!(Extension::Pdf, got.extension);
+        assert_eq!(
+            include_bytes!("../tests/testdata/dummy_invalid_ebook.pdf").len() as u64,
+            got.bytes
+        );
+        assert!(!got.converted);
+        assert_eq!(None, got.conversion_duration);
+        std::fs::remove_file(got.path).expect("Delete output file");
         endpoint_mock.assert();
     }
 }
@@ -169,22 +1620,557 @@ async fn propagates_reqwest_errors() {
         title: "Dummy invalid ebook".to_string(),
         extension: Extension::Djvu,
         download_link: "malformed_url".to_string(),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
     };
 
     let got = download_as(book, Extension::Djvu).await;
-    assert_eq!(Err(Error::Http("builder error".to_string(),)), got);
+    assert_eq!(Err(Error::Http(HttpFailure::other("builder error"))), got);
+}
+
+// Downloads the book from `download_link`, or from the next mirror when one
+// fails or sends something else than the book. Returns the mirror it was
+// downloaded from.
+async fn download_from_mirrors(
+    client: &reqwest::Client,
+    book: &InputBookInfo,
+    filename: &str,
+    options: &ConversionOptions,
+) -> Result<String, Error> {
+    let links = std::iter::once((&book.source, &book.download_link))
+        .chain(book.mirrors.iter().map(|(source, link)| (source, link)));
+
+    let mut last_error = None;
+    for (source, link) in links {
+        let settings = DownloadSettings {
+            source,
+            max_size: options.max_download_size,
+            expected_size: book.filesize,
+            expected_md5: Some(book.md5.as_str()),
+            resume: options.resume,
+            progress: options.progress.as_ref(),
+            limits: options.connection_limits.clone(),
+        };
+        match download(client, link, filename, &settings).await {
+            Ok(()) => return Ok(source.clone()),
+            Err(
+                err @ (Error::Http(_) | Error::InvalidDownload(_) | Error::UpstreamUnavailable(_)),
+            ) => {
+                tracing::warn!(
+                    "Could not download from {}, trying the next mirror: {:?}",
+                    source,
+                    err
+                );
+                last_error = Some(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| Error::Http(HttpFailure::other("no download link"))))
+}
+
+// What a download is checked against, and who is told how far it is.
+#[derive(Default)]
+struct DownloadSettings<'a> {
+    source: &'a str,
+    max_size: Option<u64>,
+    expected_size: Option<u64>,
+    // Only checked when resuming, see `ConversionOptions::resume`.
+    expected_md5: Option<&'a str>,
+    resume: bool,
+    progress: Option<&'a ProgressCallback>,
+    limits: ConnectionLimits,
+}
+
+impl DownloadSettings<'_> {
+    fn report(&self, downloaded: u64, total: Option<u64>) {
+        if let Some(progress) = self.progress {
+            (progress.0)(&DownloadProgress {
+                source: self.source.to_string(),
+                downloaded,
+                total,
+            });
+        }
+    }
+}
+
+// The file a book is downloaded to, `{filename}.part`, until it is complete.
+// It is deleted when dropped, e.g. when the download is cancelled, unless it
+// is kept to be resumed.
+struct PartFile {
+    path: Option<String>,
+}
+
+impl PartFile {
+    fn keep(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for PartFile {
+    // Deleted right away: a cancelled download may be followed by the end of
+    // the runtime.
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Downloads `url` to `{filename}.part`, then renames it to `filename` once
+// its size, and MD5 when resuming, are checked.
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    filename: &str,
+    settings: &DownloadSettings<'_>,
+) -> Result<(), Error> {
+    tracing::info!("Downloading {}...", &filename);
+
+    // Books from a local library are copied instead of downloaded.
+    if let Some(path) = local_path(url) {
+        tokio::fs::copy(path, filename).await?;
+        return Ok(());
+    }
+
+    let path = Path::new(filename);
+    let part_filename = work_file(
+        path.parent().unwrap_or(Path::new("")),
+        format!(
+            "{}.part",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ),
+    )?;
+    let part = PartFile {
+        path: Some(part_filename.clone()),
+    };
+    match download_part(client, url, &part_filename, settings).await {
+        Ok(()) => {}
+        // The mirror failed midway: what was downloaded is still good.
+        Err(err @ Error::Http(_)) if settings.resume => {
+            part.keep();
+            return Err(err);
+        }
+        Err(err) => return Err(err),
+    }
+
+    if let Some(expected) = settings
+        .expected_md5
+        .filter(|md5| settings.resume && is_md5(md5))
+    {
+        let md5 = file_md5(&part_filename).await?;
+        if !md5.eq_ignore_ascii_case(expected) {
+            return Err(Error::InvalidDownload(format!(
+                "expected MD5 {}, got {}",
+                expected.to_lowercase(),
+                md5
+            )));
+        }
+    }
+    tokio::fs::rename(&part_filename, filename).await?;
+    part.keep();
+    Ok(())
 }
 
-async fn download(url: &str, filename: &str) -> Result<(), Error> {
-    println!("Downloading {}...", &filename);
+async fn download_part(
+    client: &reqwest::Client,
+    url: &str,
+    part_filename: &str,
+    settings: &DownloadSettings<'_>,
+) -> Result<(), Error> {
+    let mut offset = match settings.resume {
+        true => tokio::fs::metadata(part_filename)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0),
+        false => 0,
+    };
+    // A larger file isn't this book, and a complete one is only checked.
+    if let Some(expected) = settings.expected_size {
+        if offset > expected {
+            offset = 0;
+        } else if offset == expected && offset > 0 {
+            settings.report(offset, Some(expected));
+            return Ok(());
+        }
+    }
+
+    // Held until the whole book is downloaded.
+    let _permit = settings.limits.download().await;
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    }
+    let limits = &settings.limits;
+    let mut resp = http_client::send_once(request, limits).await?;
+    if offset > 0 && resp.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        offset = 0;
+        resp = http_client::send_once(client.get(url), limits).await?;
+    }
+    let mut resp = resp.error_for_status()?;
+    // Mirrors that don't support ranges send the whole book again.
+    if resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        offset = 0;
+    } else if !resp
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|range| range.starts_with(&format!("bytes {}-", offset)))
+    {
+        return Err(Error::InvalidDownload(
+            "got another range than requested".to_string(),
+        ));
+    }
+
+    // IPFS gateways answer errors and captchas with a page.
+    let is_html = |content_type: &str| content_type.trim_start().starts_with("text/html");
+    if resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_html)
+    {
+        return Err(Error::InvalidDownload(
+            "got an HTML page instead of the book".to_string(),
+        ));
+    }
+    let total = resp.content_length().map(|length| offset + length);
+    if let (Some(limit), Some(total)) = (settings.max_size, total) {
+        if total > limit {
+            return Err(Error::TooLarge { limit, seen: total });
+        }
+    }
+
+    let mut out = match offset {
+        0 => File::create(part_filename).await?,
+        _ => OpenOptions::new().append(true).open(part_filename).await?,
+    };
+    let total = total.or(settings.expected_size);
+    settings.report(offset, total);
+
+    // The server may not send a Content-Length, or lie about it, so the size
+    // is checked again while downloading.
+    let mut seen = offset;
+    while let Some(chunk) = resp.chunk().await? {
+        if seen == 0 && looks_like_html(&chunk) {
+            return Err(Error::InvalidDownload(
+                "got an HTML page instead of the book".to_string(),
+            ));
+        }
+        seen += chunk.len() as u64;
+        if let Some(limit) = settings.max_size.filter(|limit| seen > *limit) {
+            return Err(Error::TooLarge { limit, seen });
+        }
+        out.write_all(&chunk).await?;
+        settings.report(seen, total);
+    }
+    out.flush().await?;
 
-    let resp = reqwest::get(url).await?;
-    let mut out = File::create(filename).await?;
-    io::copy(&mut resp.bytes().await?.as_ref(), &mut out).await?;
+    if let Some(expected) = settings.expected_size.filter(|expected| *expected != seen) {
+        return Err(Error::InvalidDownload(format!(
+            "expected {} bytes, got {}",
+            expected, seen
+        )));
+    }
 
     Ok(())
 }
 
+// LibGen MD5s are 32 hexadecimal characters.
+fn is_md5(md5: &str) -> bool {
+    md5.len() == 32 && md5.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+async fn file_md5(filename: &str) -> Result<String, Error> {
+    use md5::{Digest, Md5};
+
+    let mut file = File::open(filename).await?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Whether a download starts like an HTML page. No format LibReads serves
+// does.
+fn looks_like_html(start: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&start[..start.len().min(64)]).to_lowercase();
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+#[test]
+fn test_looks_like_html() {
+    assert!(looks_like_html(
+        b"<!DOCTYPE html><html><body>504 Gateway Timeout"
+    ));
+    assert!(looks_like_html(b"\n  <html lang=\"en\">"));
+    assert!(!looks_like_html(b"%PDF-1.4"));
+    assert!(!looks_like_html(b"PK\x03\x04"));
+    assert!(!looks_like_html(b""));
+}
+
+fn local_path(url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+#[test]
+fn test_local_path() {
+    assert_eq!(
+        Some(PathBuf::from("/books/George Orwell/1984.epub")),
+        local_path("file:///books/George%20Orwell/1984.epub")
+    );
+    assert_eq!(None, local_path("https://example.com/1984.epub"));
+    assert_eq!(None, local_path("/books/1984.epub"));
+}
+
+#[tokio::test]
+async fn test_download_local_file() {
+    let source = std::path::Path::new(
+        "tests/testdata/calibre/George Orwell/1984 (1)/1984 - George Orwell.epub",
+    )
+    .canonicalize()
+    .unwrap();
+    let url = reqwest::Url::from_file_path(&source).unwrap();
+    let filename = "test_download_local_file.epub";
+
+    download(
+        &reqwest::Client::new(),
+        url.as_str(),
+        filename,
+        &DownloadSettings::default(),
+    )
+    .await
+    .unwrap();
+
+    let got = tokio::fs::read(filename).await.unwrap();
+    tokio::fs::remove_file(filename).await.unwrap();
+    assert_eq!(tokio::fs::read(source).await.unwrap(), got);
+}
+
+#[tokio::test]
+async fn test_download_as_uses_proxy() {
+    use httpmock::{Method::GET, MockServer};
+
+    let proxy = MockServer::start();
+    let proxy_mock = proxy.mock(|when, then| {
+        when.method(GET)
+            .path("/book.mobi")
+            .header("host", "mirror.invalid");
+        then.status(200)
+            .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
+    });
+    let book = InputBookInfo {
+        title: "proxied book".to_string(),
+        extension: Extension::Mobi,
+        download_link: "http://mirror.invalid/book.mobi".to_string(),
+        cover_url: None,
+        md5: String::new(),
+        source: String::new(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+    let options = ConversionOptions {
+        proxy: Some(proxy.base_url()),
+        ..ConversionOptions::default()
+    };
+
+    let got = download_as_with_options(book.clone(), Extension::Mobi, &options)
+        .await
+        .expect("The book should be downloaded through the proxy");
+    proxy_mock.assert();
+    std::fs::remove_file(got.path).unwrap();
+
+    let options = ConversionOptions {
+        proxy: Some("127.0.0.1:9050".to_string()),
+        ..ConversionOptions::default()
+    };
+    let got = download_as_with_options(book, Extension::Mobi, &options).await;
+    assert!(matches!(got, Err(Error::Http(failure)) if failure.message.contains("invalid proxy")));
+}
+
+#[tokio::test]
+async fn test_download_to_work_dir() {
+    use httpmock::{Method::GET, MockServer};
+
+    let book_bytes = include_bytes!("../tests/testdata/dummy_ebook.mobi");
+    let mock_server = MockServer::start();
+    mock_server.mock(|when, then| {
+        when.method(GET).path("/book.mobi");
+        then.status(200).body(book_bytes);
+    });
+    let work_dir = std::env::temp_dir().join("libreads_convert_work_dir");
+    let _ = std::fs::remove_dir_all(&work_dir);
+    let book = InputBookInfo {
+        title: "work dir book".to_string(),
+        extension: Extension::Mobi,
+        download_link: mock_server.url("/book.mobi"),
+        cover_url: None,
+        md5: String::new(),
+        source: "HTTP".to_string(),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        filesize: None,
+        mirrors: vec![],
+        series: String::new(),
+        series_index: String::new(),
+    };
+    let options = ConversionOptions {
+        work_dir: work_dir.clone(),
+        ..ConversionOptions::default()
+    };
+
+    // The directory is created on the first download.
+    let got = download_as_with_options(book, Extension::Mobi, &options)
+        .await
+        .unwrap();
+    assert_eq!(work_dir.join("work dir book.mobi"), got.path);
+    assert_eq!(
+        book_bytes.len() as u64,
+        std::fs::metadata(&got.path).unwrap().len()
+    );
+    assert!(!Path::new("work dir book.mobi").exists());
+
+    std::fs::remove_dir_all(work_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_download_fails_over_between_mirrors() {
+    use httpmock::{Method::GET, MockServer};
+
+    let book_bytes = include_bytes!("../tests/testdata/dummy_ebook.mobi");
+    let mock_server = MockServer::start();
+    let error_page_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/error-page/ipfs/bafk");
+        then.status(200)
+            .header("content-type", "text/html; charset=utf-8")
+            .body("<html><body>504 Gateway Time-out</body></html>");
+    });
+    let untyped_page_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/untyped-page/ipfs/bafk");
+        then.status(200)
+            .body("<!DOCTYPE html><html><body>Rate limited</body></html>");
+    });
+    let truncated_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/truncated/ipfs/bafk");
+        then.status(200).body(&book_bytes[..100]);
+    });
+    let book_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/book.mobi");
+        then.status(200).body(book_bytes);
+    });
+    let mirror = |name: &str| {
+        (
+            name.to_string(),
+            mock_server.url(format!("/{}/ipfs/bafk", name)),
+        )
+    };
+    let book = InputBookInfo {
+        title: "failover book".to_string(),
+        extension: Extension::Mobi,
+        download_link: mock_server.url("/error-page/ipfs/bafk"),
+        cover_url: None,
+        md5: String::new(),
+        source: "error-page".to_string(),
+        mirrors: vec![
+            mirror("untyped-page"),
+            mirror("truncated"),
+            ("HTTP".to_string(), mock_server.url("/book.mobi")),
+        ],
+        filesize: Some(book_bytes.len() as u64),
+        original_filename: None,
+        author: String::new(),
+        year: String::new(),
+        series: String::new(),
+        series_index: String::new(),
+    };
+
+    let got = download_as(book.clone(), Extension::Mobi)
+        .await
+        .expect("The book should be downloaded from the last mirror");
+    assert_eq!("HTTP", got.source);
+    assert_eq!(book_bytes.len() as u64, got.bytes);
+    std::fs::remove_file(got.path).unwrap();
+    for mock in [
+        &error_page_mock,
+        &untyped_page_mock,
+        &truncated_mock,
+        &book_mock,
+    ] {
+        mock.assert();
+    }
+
+    // The last error is returned when every mirror fails.
+    let book = InputBookInfo {
+        mirrors: vec![mirror("truncated")],
+        ..book
+    };
+    let got = download_as(book, Extension::Mobi).await;
+    assert_eq!(
+        Err(Error::InvalidDownload(format!(
+            "expected {} bytes, got 100",
+            book_bytes.len()
+        ))),
+        got
+    );
+    assert!(!Path::new("failover book.mobi").exists());
+}
+
+#[tokio::test]
+async fn test_downloads_share_connection_limits() {
+    use crate::http_client::slow_server::SlowServer;
+    use futures_util::future::join_all;
+
+    let server = SlowServer::start(b"book", Duration::from_millis(50)).await;
+    let settings = DownloadSettings {
+        limits: ConnectionLimits::new(2, 16),
+        ..DownloadSettings::default()
+    };
+    let client = reqwest::Client::new();
+    let filenames: Vec<String> = (0..5)
+        .map(|i| format!("test_downloads_share_connection_limits_{}.epub", i))
+        .collect();
+
+    let results = join_all(
+        filenames
+            .iter()
+            .map(|filename| download(&client, &server.url, filename, &settings)),
+    )
+    .await;
+
+    for (filename, result) in filenames.iter().zip(results) {
+        assert_eq!(Ok(()), result);
+        assert_eq!("book", std::fs::read_to_string(filename).unwrap());
+        std::fs::remove_file(filename).unwrap();
+    }
+    assert_eq!(2, server.max_in_flight());
+}
+
 #[tokio::test]
 async fn test_download_incorrect_filename() {
     use httpmock::{Method::GET, MockServer};
@@ -195,18 +2181,103 @@ async fn test_download_incorrect_filename() {
         then.status(200);
     });
 
-    let got = download(mock_server.url("/").as_str(), "   /\\ Invalid file name").await;
+    let got = download(
+        &reqwest::Client::new(),
+        mock_server.url("/").as_str(),
+        "   /\\ Invalid file name",
+        &DownloadSettings::default(),
+    )
+    .await;
+    // Rejected before downloading anything, see `output_path`.
     assert_eq!(
         Err(Error::Io(
-            "No such file or directory (os error 2)".to_string()
+            "invalid file name \"\\\\ Invalid file name.part\": \"\\\\ Invalid file name.part\" contains a path separator"
+                .to_string()
         )),
         got,
     );
 
+    endpoint_mock.assert_hits(0);
+}
+
+#[tokio::test]
+async fn test_download_too_large_content_length() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/book.epub");
+        then.status(200).body(vec![0; 2048]);
+    });
+    let filename = "test_download_too_large_content_length.epub";
+
+    let got = download(
+        &reqwest::Client::new(),
+        mock_server.url("/book.epub").as_str(),
+        filename,
+        &DownloadSettings {
+            max_size: Some(1024),
+            ..DownloadSettings::default()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        Err(Error::TooLarge {
+            limit: 1024,
+            seen: 2048
+        }),
+        got
+    );
+    assert!(!Path::new(filename).exists());
     endpoint_mock.assert();
 }
 
-fn sanitise_title(title: &str) -> String {
+#[tokio::test]
+async fn test_download_too_large_streaming() {
+    use tokio::net::TcpListener;
+
+    // A chunked response has no Content-Length, so the download has to be
+    // cut off while streaming.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/book.epub", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = socket.read(&mut request).await;
+        let _ = socket
+            .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .await;
+        for _ in 0..4 {
+            let _ = socket.write_all(b"400\r\n").await;
+            let _ = socket.write_all(&[0; 1024]).await;
+            let _ = socket.write_all(b"\r\n").await;
+        }
+        let _ = socket.write_all(b"0\r\n\r\n").await;
+    });
+    let filename = "test_download_too_large_streaming.epub";
+
+    let got = download(
+        &reqwest::Client::new(),
+        &url,
+        filename,
+        &DownloadSettings {
+            max_size: Some(1500),
+            ..DownloadSettings::default()
+        },
+    )
+    .await;
+
+    assert!(
+        matches!(got, Err(Error::TooLarge { limit: 1500, seen }) if seen > 1500 && seen <= 4096),
+        "{:?}",
+        got
+    );
+    assert!(!Path::new(filename).exists());
+    server.abort();
+}
+
+pub(crate) fn sanitise_title(title: &str) -> String {
     title
         .replace(|c: char| c.is_ascii_punctuation(), " ")
         .replace(|c: char| !c.is_whitespace() && !c.is_alphanumeric(), "")
@@ -232,16 +2303,44 @@ fn test_sanitise_title() {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Error {
     Io(String),
-    Http(String),
+    Http(HttpFailure),
     Conversion(String),
+    ConversionTimeout(Duration),
+    // The book is DRM-protected or encrypted, so it can't be converted.
+    DrmProtected,
+    // The download was bigger than the limit, in bytes. `seen` is either
+    // the announced Content-Length or how much was read before giving up.
+    TooLarge { limit: u64, seen: u64 },
+    // The mirror sent something else than the book, e.g. an HTML error page,
+    // or a file of another size than LibGen's.
+    InvalidDownload(String),
+    // The book is a CBR comic, but Calibre was built without unrar.
+    UnrarMissing,
+    // The server's toolchain can't do this conversion, see `capabilities`.
+    Unsupported(String),
+    // The mirror kept failing, so it isn't called for a while.
+    UpstreamUnavailable(String),
 }
 
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self {
-        Error::Http(err.to_string())
+        Error::Http(HttpFailure::from(err))
+    }
+}
+
+impl From<http_client::SendError> for Error {
+    fn from(err: http_client::SendError) -> Self {
+        match err {
+            http_client::SendError::Http(err) => Error::from(err),
+            http_client::SendError::RateLimited(limit) => Error::Http(HttpFailure::from(limit)),
+            http_client::SendError::CircuitOpen(open) => {
+                Error::UpstreamUnavailable(open.to_string())
+            }
+            http_client::SendError::Replay(message) => Error::UpstreamUnavailable(message),
+        }
     }
 }
 
@@ -250,3 +2349,211 @@ impl From<std::io::Error> for Error {
         Error::Io(err.to_string())
     }
 }
+
+impl From<output_path::Error> for Error {
+    fn from(err: output_path::Error) -> Self {
+        Error::Io(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod resume_tests {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+    use std::sync::Mutex;
+
+    const BOOK: &[u8] = include_bytes!("../tests/testdata/dummy_ebook.mobi");
+
+    async fn book_md5() -> String {
+        file_md5("tests/testdata/dummy_ebook.mobi").await.unwrap()
+    }
+
+    fn resuming<'a>(md5: &'a str, progress: Option<&'a ProgressCallback>) -> DownloadSettings<'a> {
+        DownloadSettings {
+            source: "HTTP",
+            expected_size: Some(BOOK.len() as u64),
+            expected_md5: Some(md5),
+            resume: true,
+            progress,
+            ..DownloadSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_with_range() {
+        let filename = "test_download_resumes_with_range.mobi";
+        let part_filename = format!("{}.part", filename);
+        std::fs::write(&part_filename, &BOOK[..100]).unwrap();
+        let mock_server = MockServer::start();
+        let range_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/book.mobi")
+                .header("range", "bytes=100-");
+            then.status(206)
+                .header(
+                    "content-range",
+                    format!("bytes 100-{}/{}", BOOK.len() - 1, BOOK.len()),
+                )
+                .body(&BOOK[100..]);
+        });
+        let events = Arc::new(Mutex::new(vec![]));
+        let progress = {
+            let events = events.clone();
+            ProgressCallback::new(move |progress| events.lock().unwrap().push(progress.clone()))
+        };
+        let md5 = book_md5().await;
+
+        download(
+            &reqwest::Client::new(),
+            &mock_server.url("/book.mobi"),
+            filename,
+            &resuming(&md5, Some(&progress)),
+        )
+        .await
+        .expect("The download should be resumed");
+
+        range_mock.assert();
+        assert_eq!(BOOK, std::fs::read(filename).unwrap());
+        assert!(!Path::new(&part_filename).exists());
+        std::fs::remove_file(filename).unwrap();
+
+        let events = events.lock().unwrap();
+        let total = Some(BOOK.len() as u64);
+        assert_eq!(
+            Some(&DownloadProgress {
+                source: "HTTP".to_string(),
+                downloaded: 100,
+                total,
+            }),
+            events.first()
+        );
+        assert_eq!(
+            Some(&DownloadProgress {
+                source: "HTTP".to_string(),
+                downloaded: BOOK.len() as u64,
+                total,
+            }),
+            events.last()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_restarts_without_range_support() {
+        let filename = "test_download_restarts_without_range_support.mobi";
+        let part_filename = format!("{}.part", filename);
+        std::fs::write(&part_filename, [0; 100]).unwrap();
+        let mock_server = MockServer::start();
+        let full_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/book.mobi")
+                .header("range", "bytes=100-");
+            then.status(200).body(BOOK);
+        });
+        let md5 = book_md5().await;
+
+        download(
+            &reqwest::Client::new(),
+            &mock_server.url("/book.mobi"),
+            filename,
+            &resuming(&md5, None),
+        )
+        .await
+        .expect("The book should be downloaded again");
+
+        full_mock.assert();
+        assert_eq!(BOOK, std::fs::read(filename).unwrap());
+        assert!(!Path::new(&part_filename).exists());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_part_file_on_failure() {
+        let filename = "test_download_part_file_on_failure.mobi";
+        let part_filename = format!("{}.part", filename);
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/unavailable.mobi");
+            then.status(503);
+        });
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/corrupted.mobi");
+            then.status(206)
+                .header(
+                    "content-range",
+                    format!("bytes 100-{}/{}", BOOK.len() - 1, BOOK.len()),
+                )
+                .body(&BOOK[100..]);
+        });
+        let md5 = book_md5().await;
+        let client = reqwest::Client::new();
+
+        // Kept for the next mirror or run when resuming.
+        std::fs::write(&part_filename, &BOOK[..100]).unwrap();
+        let got = download(
+            &client,
+            &mock_server.url("/unavailable.mobi"),
+            filename,
+            &resuming(&md5, None),
+        )
+        .await;
+        assert!(matches!(got, Err(Error::Http(_))), "{:?}", got);
+        assert_eq!(&BOOK[..100], std::fs::read(&part_filename).unwrap());
+
+        // Deleted otherwise.
+        let settings = DownloadSettings {
+            resume: false,
+            ..resuming(&md5, None)
+        };
+        let got = download(
+            &client,
+            &mock_server.url("/unavailable.mobi"),
+            filename,
+            &settings,
+        )
+        .await;
+        assert!(matches!(got, Err(Error::Http(_))), "{:?}", got);
+        assert!(!Path::new(&part_filename).exists());
+
+        // A resumed download that doesn't add up to the book is deleted.
+        std::fs::write(&part_filename, [0; 100]).unwrap();
+        let got = download(
+            &client,
+            &mock_server.url("/corrupted.mobi"),
+            filename,
+            &resuming(&md5, None),
+        )
+        .await;
+        assert!(
+            matches!(&got, Err(Error::InvalidDownload(message)) if message.starts_with(&format!("expected MD5 {}", md5))),
+            "{:?}",
+            got
+        );
+        assert!(!Path::new(&part_filename).exists());
+        assert!(!Path::new(filename).exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_download_deletes_part_file() {
+        let filename = "test_cancelled_download_deletes_part_file.mobi";
+        let part_filename = format!("{}.part", filename);
+        std::fs::write(&part_filename, &BOOK[..100]).unwrap();
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/book.mobi");
+            then.status(200).body(BOOK).delay(Duration::from_secs(5));
+        });
+        let md5 = book_md5().await;
+        let url = mock_server.url("/book.mobi");
+        let settings = resuming(&md5, None);
+
+        let got = tokio::time::timeout(
+            Duration::from_millis(100),
+            download(&reqwest::Client::new(), &url, filename, &settings),
+        )
+        .await;
+
+        assert!(got.is_err(), "the download should have been cancelled");
+        assert!(!Path::new(&part_filename).exists());
+        assert!(!Path::new(filename).exists());
+    }
+}
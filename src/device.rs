@@ -0,0 +1,101 @@
+//! Module device maps e-readers to the output format and ebook-convert
+//! output profile that render best on them.
+
+use crate::extension::Extension;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Device {
+    Kindle,
+    Kobo,
+    Generic,
+}
+
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "kindle" => Ok(Self::Kindle),
+            "kobo" => Ok(Self::Kobo),
+            "generic" => Ok(Self::Generic),
+            _ => Err(format!(
+                "unknown device {:?}, expected one of: kindle, kobo, generic",
+                s
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_device_from_str() {
+    for (input, want) in [
+        ("kindle", Device::Kindle),
+        ("Kindle", Device::Kindle),
+        ("kobo", Device::Kobo),
+        (" KOBO ", Device::Kobo),
+        ("generic", Device::Generic),
+    ] {
+        assert_eq!(Ok(want), Device::from_str(input));
+    }
+
+    for input in ["", "nook", "kindle2"] {
+        assert!(Device::from_str(input).is_err());
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputSettings {
+    pub extension: Extension,
+    // Passed to ebook-convert as `--output-profile`.
+    pub output_profile: Option<&'static str>,
+}
+
+// Modern Kindles render AZW3 (KF8) much better than MOBI. Kobo devices
+// read EPUB natively; KEPUB would need a Calibre plugin, so we stick to EPUB
+// with the Kobo profile.
+pub fn output_settings(device: Device) -> OutputSettings {
+    match device {
+        Device::Kindle => OutputSettings {
+            extension: Extension::Azw3,
+            output_profile: Some("kindle_pw3"),
+        },
+        Device::Kobo => OutputSettings {
+            extension: Extension::Epub,
+            output_profile: Some("kobo"),
+        },
+        Device::Generic => OutputSettings {
+            extension: Extension::Epub,
+            output_profile: None,
+        },
+    }
+}
+
+#[test]
+fn test_output_settings() {
+    for (device, want) in [
+        (
+            Device::Kindle,
+            OutputSettings {
+                extension: Extension::Azw3,
+                output_profile: Some("kindle_pw3"),
+            },
+        ),
+        (
+            Device::Kobo,
+            OutputSettings {
+                extension: Extension::Epub,
+                output_profile: Some("kobo"),
+            },
+        ),
+        (
+            Device::Generic,
+            OutputSettings {
+                extension: Extension::Epub,
+                output_profile: None,
+            },
+        ),
+    ] {
+        assert_eq!(want, output_settings(device));
+    }
+}
@@ -1,20 +1,73 @@
 //! Module extension provides representation, deserialisation and sorting for
 //! ebook extensions.
 
-use serde::{de, Deserialize, Deserializer};
-use serde_json::Value;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Extension {
     Mobi,
     Epub,
     Azw3,
     Djvu,
     Pdf,
+    // Word documents: Calibre reads DOCX, but not the legacy DOC format.
+    Docx,
     Doc,
+    Txt,
+    Htmlz,
+    // Comic book archives: images in a ZIP or RAR file.
+    Cbz,
+    Cbr,
     Other(String),
 }
 
+// The formats books can be converted to. Anything else is rejected, so an
+// arbitrary `Other` extension can never be requested.
+pub const CONVERSION_TARGETS: &[Extension] = &[
+    Extension::Mobi,
+    Extension::Epub,
+    Extension::Azw3,
+    Extension::Djvu,
+    Extension::Pdf,
+    Extension::Txt,
+    Extension::Htmlz,
+];
+
+impl Extension {
+    pub fn is_conversion_target(&self) -> bool {
+        CONVERSION_TARGETS.contains(self)
+    }
+
+    pub fn is_comic(&self) -> bool {
+        matches!(self, Extension::Cbz | Extension::Cbr)
+    }
+}
+
+#[test]
+fn test_is_conversion_target() {
+    for ext in [Extension::Mobi, Extension::Txt, Extension::Htmlz] {
+        assert!(ext.is_conversion_target(), "{}", ext);
+    }
+    for ext in [
+        Extension::Doc,
+        Extension::Docx,
+        Extension::Cbz,
+        Extension::Cbr,
+        Extension::Other("exe".to_string()),
+        Extension::Other(String::new()),
+    ] {
+        assert!(!ext.is_conversion_target(), "{}", ext);
+    }
+}
+
+#[test]
+fn test_is_comic() {
+    assert!(Extension::Cbz.is_comic());
+    assert!(Extension::Cbr.is_comic());
+    assert!(!Extension::Pdf.is_comic());
+    assert!(!Extension::Other("cb7".to_string()).is_comic());
+}
+
 impl std::fmt::Display for Extension {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -26,7 +79,12 @@ impl std::fmt::Display for Extension {
                 Extension::Azw3 => "azw3",
                 Extension::Djvu => "djvu",
                 Extension::Pdf => "pdf",
+                Extension::Docx => "docx",
                 Extension::Doc => "doc",
+                Extension::Txt => "txt",
+                Extension::Htmlz => "htmlz",
+                Extension::Cbz => "cbz",
+                Extension::Cbr => "cbr",
                 Extension::Other(ext) => ext.as_str(),
             }
         )
@@ -41,7 +99,12 @@ fn test_display_extension() {
         (Extension::Azw3, "azw3"),
         (Extension::Djvu, "djvu"),
         (Extension::Pdf, "pdf"),
+        (Extension::Docx, "docx"),
         (Extension::Doc, "doc"),
+        (Extension::Txt, "txt"),
+        (Extension::Htmlz, "htmlz"),
+        (Extension::Cbz, "cbz"),
+        (Extension::Cbr, "cbr"),
         (Extension::Other("hello".to_string()), "hello"),
         (Extension::Other("asdsfdsfds".to_string()), "asdsfdsfds"),
         (Extension::Other("".to_string()), ""),
@@ -55,31 +118,135 @@ fn test_display_extension() {
     }
 }
 
+// Extensions are (de)serialised as plain strings, e.g. "epub", parsed like
+// `FromStr`. Objects with an "extension" key, e.g. `{"extension": "epub"}`,
+// are also read, as in LibGen's results: see `libgen::LibgenMetadata`.
 impl<'de> Deserialize<'de> for Extension {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let v = Value::deserialize(deserializer)?;
-        let ext: Option<&str> = Option::deserialize(&v["extension"]).map_err(de::Error::custom)?;
-        Ok(match ext {
-            Some(ext) => match ext.to_lowercase().as_str() {
-                "mobi" => Self::Mobi,
-                "epub" => Self::Epub,
-                "azw3" => Self::Azw3,
-                "djvu" => Self::Djvu,
-                "pdf" => Self::Pdf,
-                "doc" => Self::Doc,
-                ext => Self::Other(ext.to_string()),
-            },
-            None => Self::Other(String::new()),
+        deserializer.deserialize_any(ExtensionVisitor)
+    }
+}
+
+struct ExtensionVisitor;
+
+impl<'de> de::Visitor<'de> for ExtensionVisitor {
+    type Value = Extension;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            r#"an extension, e.g. "epub", or {{"extension": "epub"}}"#
+        )
+    }
+
+    fn visit_str<E>(self, ext: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        let Ok(ext) = ext.parse();
+        Ok(ext)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut extension = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "extension" if extension.is_some() => {
+                    return Err(de::Error::duplicate_field("extension"))
+                }
+                "extension" => extension = Some(map.next_value::<String>()?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        let ext = extension.ok_or_else(|| de::Error::missing_field("extension"))?;
+        self.visit_str(&ext)
+    }
+}
+
+impl Serialize for Extension {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+// Described as the string it is serialised as.
+impl utoipa::PartialSchema for Extension {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::schema::Type::String)
+            .examples([serde_json::json!("epub")])
+            .into()
+    }
+}
+
+impl utoipa::ToSchema for Extension {}
+
+#[test]
+fn test_serialise_extension() {
+    for (ext, want) in [
+        (Extension::Mobi, r#""mobi""#),
+        (Extension::Pdf, r#""pdf""#),
+        (Extension::Cbz, r#""cbz""#),
+        (Extension::Other("cb7".to_string()), r#""cb7""#),
+    ] {
+        assert_eq!(want, serde_json::to_string(&ext).unwrap());
+    }
+}
+
+impl std::str::FromStr for Extension {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "mobi" => Self::Mobi,
+            "epub" => Self::Epub,
+            "azw3" => Self::Azw3,
+            "djvu" => Self::Djvu,
+            "pdf" => Self::Pdf,
+            "docx" => Self::Docx,
+            "doc" => Self::Doc,
+            "txt" => Self::Txt,
+            "htmlz" => Self::Htmlz,
+            "cbz" => Self::Cbz,
+            "cbr" => Self::Cbr,
+            ext => Self::Other(ext.to_string()),
         })
     }
 }
 
+#[test]
+fn test_extension_from_str() {
+    for (data, want) in [
+        ("mobi", Extension::Mobi),
+        ("EPUB", Extension::Epub),
+        ("Azw3", Extension::Azw3),
+        ("TXT", Extension::Txt),
+        ("doc", Extension::Doc),
+        ("docx", Extension::Docx),
+        ("DOCX", Extension::Docx),
+        ("htmlz", Extension::Htmlz),
+        ("cbz", Extension::Cbz),
+        ("CBR", Extension::Cbr),
+        ("cb7", Extension::Other("cb7".to_string())),
+    ] {
+        assert_eq!(Ok(want), data.parse());
+    }
+}
+
 #[test]
 fn test_deserialise_extension() {
-    for (data, want) in vec![
+    for (data, want) in [
         ("pdf", Extension::Pdf),
         ("PDF", Extension::Pdf),
         ("Pdf", Extension::Pdf),
@@ -89,6 +256,13 @@ fn test_deserialise_extension() {
         ("epub", Extension::Epub),
         ("djvu", Extension::Djvu),
         ("azw3", Extension::Azw3),
+        ("txt", Extension::Txt),
+        ("htmlz", Extension::Htmlz),
+        ("DOC", Extension::Doc),
+        ("Docx", Extension::Docx),
+        ("DOCX", Extension::Docx),
+        ("cbz", Extension::Cbz),
+        ("Cbr", Extension::Cbr),
         (
             "randomextension",
             Extension::Other("randomextension".to_string()),
@@ -100,33 +274,134 @@ fn test_deserialise_extension() {
         ("", Extension::Other(String::new())),
     ] {
         let got: Extension =
-            serde_json::from_str(format!(r#"{{ "extension": "{data}" }}"#, data = data).as_str())
-                .expect("Should deserialise valid data");
+            serde_json::from_str(&format!(r#""{}""#, data)).expect("Should deserialise valid data");
         assert_eq!(want, got);
     }
 }
 
 #[test]
-fn test_deserialise_missing_extension() {
-    let got: Extension = serde_json::from_str("{}").expect("Should deserialise valid data");
-    assert_eq!(Extension::Other(String::new()), got)
+fn test_deserialise_extension_map() {
+    for (data, want) in [
+        (r#"{ "extension": "pdf" }"#, Extension::Pdf),
+        (r#"{ "extension": "EPUB" }"#, Extension::Epub),
+        (r#"{ "extension": "DOCX" }"#, Extension::Docx),
+        (
+            r#"{ "title": "Emma", "extension": "cb7", "year": 2000 }"#,
+            Extension::Other("cb7".to_string()),
+        ),
+    ] {
+        assert_eq!(
+            want,
+            serde_json::from_str::<Extension>(data).expect("Should deserialise valid data"),
+            "{}",
+            data
+        );
+    }
+
+    for data in [
+        r#"{ "title": "Emma" }"#,
+        r#"{ "extension": "pdf", "extension": "epub" }"#,
+        r#"{ "extension": 3 }"#,
+        "3",
+        "null",
+    ] {
+        assert!(serde_json::from_str::<Extension>(data).is_err(), "{}", data);
+    }
+
+    // Flattened in a struct, it takes the "extension" key of the object.
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Edition {
+        title: String,
+        #[serde(flatten)]
+        extension: Extension,
+    }
+    assert_eq!(
+        Edition {
+            title: "Emma".to_string(),
+            extension: Extension::Mobi,
+        },
+        serde_json::from_str(r#"{ "title": "Emma", "extension": "mobi" }"#).unwrap()
+    );
 }
 
-impl Ord for Extension {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        fn val(ext: &Extension) -> u8 {
-            match ext {
-                Extension::Mobi => 1,
-                Extension::Epub => 2,
-                Extension::Azw3 => 3,
-                Extension::Djvu => 4,
-                Extension::Pdf => 90,
-                Extension::Doc => 91,
-                Extension::Other(_) => 92,
-            }
+#[test]
+fn test_deserialise_extension_urlencoded() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Query {
+        format: Extension,
+        exclude: Option<Extension>,
+    }
+
+    assert_eq!(
+        Query {
+            format: Extension::Epub,
+            exclude: None,
+        },
+        serde_urlencoded::from_str("format=EPUB").unwrap()
+    );
+    assert_eq!(
+        Query {
+            format: Extension::Other("cb7".to_string()),
+            exclude: Some(Extension::Pdf),
+        },
+        serde_urlencoded::from_str("format=cb7&exclude=pdf").unwrap()
+    );
+    assert_eq!(
+        "format=azw3",
+        serde_urlencoded::to_string([("format", Extension::Azw3)]).unwrap()
+    );
+}
+
+#[test]
+fn test_extension_round_trip() {
+    use std::collections::HashMap;
+
+    for ext in [
+        Extension::Mobi,
+        Extension::Epub,
+        Extension::Azw3,
+        Extension::Djvu,
+        Extension::Pdf,
+        Extension::Docx,
+        Extension::Doc,
+        Extension::Txt,
+        Extension::Htmlz,
+        Extension::Cbz,
+        Extension::Cbr,
+        Extension::Other("cb7".to_string()),
+    ] {
+        let json = serde_json::to_string(&ext).unwrap();
+        assert_eq!(ext, serde_json::from_str::<Extension>(&json).unwrap());
+
+        let query = serde_urlencoded::to_string([("format", &ext)]).unwrap();
+        let got: HashMap<String, Extension> = serde_urlencoded::from_str(&query).unwrap();
+        assert_eq!(ext, got["format"]);
+    }
+}
+
+impl Extension {
+    // Lower is better. See `ranking::FormatRanker`.
+    pub fn preference(&self) -> u8 {
+        match self {
+            Extension::Mobi => 1,
+            Extension::Epub => 2,
+            Extension::Azw3 => 3,
+            Extension::Djvu => 4,
+            Extension::Pdf => 90,
+            Extension::Docx => 91,
+            Extension::Doc => 92,
+            Extension::Htmlz => 93,
+            Extension::Txt => 94,
+            Extension::Cbz => 95,
+            Extension::Cbr => 96,
+            Extension::Other(_) => 97,
         }
+    }
+}
 
-        val(self).cmp(&val(other))
+impl Ord for Extension {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.preference().cmp(&other.preference())
     }
 }
 
@@ -140,13 +415,18 @@ impl PartialOrd for Extension {
 fn test_sort_extensions() {
     let mut extensions = vec![
         Extension::Pdf,
+        Extension::Cbr,
         Extension::Other("whatever".to_string()),
+        Extension::Cbz,
+        Extension::Txt,
+        Extension::Htmlz,
         Extension::Mobi,
         Extension::Pdf,
         Extension::Djvu,
         Extension::Epub,
         Extension::Azw3,
         Extension::Doc,
+        Extension::Docx,
         Extension::Pdf,
         Extension::Mobi,
         Extension::Epub,
@@ -170,8 +450,13 @@ fn test_sort_extensions() {
             Extension::Pdf,
             Extension::Pdf,
             Extension::Pdf,
+            Extension::Docx,
             Extension::Doc,
             Extension::Doc,
+            Extension::Htmlz,
+            Extension::Txt,
+            Extension::Cbz,
+            Extension::Cbr,
             Extension::Other("whatever".to_string()),
         ],
         extensions
@@ -186,7 +471,15 @@ impl Extension {
             Extension::Azw3 => "application/vnd.amazon.ebook",
             Extension::Djvu => "image/vnd",
             Extension::Pdf => "application/pdf",
+            Extension::Docx => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
             Extension::Doc => "application/msword",
+            Extension::Txt => "text/plain; charset=utf-8",
+            // HTMLZ is a ZIP archive of the HTML, its CSS and images.
+            Extension::Htmlz => "application/zip",
+            Extension::Cbz => "application/vnd.comicbook+zip",
+            Extension::Cbr => "application/vnd.comicbook+rar",
             Extension::Other(_) => "plain/text",
         }
         .to_string()
@@ -201,7 +494,15 @@ fn test_extension_content_type() {
         (Extension::Azw3, "application/vnd.amazon.ebook"),
         (Extension::Djvu, "image/vnd"),
         (Extension::Pdf, "application/pdf"),
+        (
+            Extension::Docx,
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        ),
         (Extension::Doc, "application/msword"),
+        (Extension::Txt, "text/plain; charset=utf-8"),
+        (Extension::Htmlz, "application/zip"),
+        (Extension::Cbz, "application/vnd.comicbook+zip"),
+        (Extension::Cbr, "application/vnd.comicbook+rar"),
         (Extension::Other("abc".to_string()), "plain/text"),
         (Extension::Other("def".to_string()), "plain/text"),
         (Extension::Other(String::new()), "plain/text"),
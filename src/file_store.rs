@@ -0,0 +1,670 @@
+//! Module file_store abstracts where LibReads keeps the files that must
+//! survive restarts: the books of the library and the cached download links.
+//!
+//! Files are addressed by `/`-separated keys, e.g. `{md5}/metadata.json`.
+//! They are kept in a local directory by default. With the `s3` feature, a
+//! location such as `s3://bucket/prefix` keeps them in an S3 bucket, or in an
+//! S3-compatible service such as MinIO. The AWS region, credentials and
+//! endpoint are read from the usual `AWS_*` variables, e.g.
+//! `AWS_ENDPOINT_URL=http://localhost:9000` for MinIO.
+
+use crate::{keyed_lock::KeyedLocks, output_path};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// How long the presigned URLs of stored files are valid.
+pub const PRESIGNED_URL_TTL: Duration = Duration::from_secs(60 * 60);
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+#[async_trait]
+pub trait FileStore: Send + Sync + Debug {
+    // Writes the file at `key`, replacing any previous one. Readers never see
+    // a partial file.
+    async fn put(&self, key: &str, body: ByteStream) -> Result<(), Error>;
+
+    async fn get(&self, key: &str) -> Result<ByteStream, Error>;
+
+    async fn exists(&self, key: &str) -> Result<bool, Error>;
+
+    // Deletes the file at `key`. Deleting a missing file succeeds.
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+
+    // Returns the keys starting with `prefix`, in no particular order.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+
+    // A URL the file at `key` can be downloaded from without credentials,
+    // when the store supports it.
+    async fn presigned_url(
+        &self,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    // Where the file at `key` is on disk, for local stores.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+// Where files are kept, as given in the configuration.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    Dir(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+// Parses a directory, or an `s3://bucket/prefix` URL when the `s3` feature
+// is enabled.
+pub fn parse_location(location: &str) -> Result<Location, Error> {
+    let Some(rest) = location.strip_prefix("s3://") else {
+        return Ok(Location::Dir(PathBuf::from(location)));
+    };
+    if !cfg!(feature = "s3") {
+        return Err(Error::Unsupported(
+            "S3 storage requires the `s3` feature".to_string(),
+        ));
+    }
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(Error::InvalidKey(location.to_string()));
+    }
+    Ok(Location::S3 {
+        bucket: bucket.to_string(),
+        prefix: prefix.trim_matches('/').to_string(),
+    })
+}
+
+// Opens the store at `location`, see `parse_location`.
+pub fn open(location: &Path) -> Result<Arc<dyn FileStore>, Error> {
+    let location = location
+        .to_str()
+        .ok_or_else(|| Error::InvalidKey(format!("{:?}", location)))?;
+    match parse_location(location)? {
+        Location::Dir(dir) => Ok(Arc::new(LocalFileStore::new(dir))),
+        #[cfg(feature = "s3")]
+        Location::S3 { bucket, prefix } => Ok(Arc::new(s3::S3FileStore::new(bucket, prefix))),
+        #[cfg(not(feature = "s3"))]
+        Location::S3 { .. } => unreachable!("rejected by parse_location"),
+    }
+}
+
+// A stream of `data`.
+pub fn bytes_stream(data: impl Into<Bytes>) -> ByteStream {
+    Box::pin(stream::once(futures_util::future::ready(Ok(data.into()))))
+}
+
+// A stream of the file at `path`, read in chunks.
+pub async fn file_stream(path: &Path) -> io::Result<ByteStream> {
+    let file = tokio::fs::File::open(path).await?;
+    Ok(Box::pin(stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = file.read(&mut chunk).await?;
+        chunk.truncate(read);
+        Ok((read > 0).then(|| (Bytes::from(chunk), file)))
+    })))
+}
+
+// Reads a whole stream in memory.
+pub async fn read_to_vec(mut body: ByteStream) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data)
+}
+
+// Keeps files in a directory, which is created on the first write.
+#[derive(Debug, Clone)]
+pub struct LocalFileStore {
+    dir: PathBuf,
+    // Writers of the same key take turns, see `put`.
+    writes: KeyedLocks,
+}
+
+impl LocalFileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            writes: KeyedLocks::default(),
+        }
+    }
+
+    // Keys can't leave the directory.
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        output_path::join(&self.dir, key).map_err(|_| Error::InvalidKey(key.to_string()))
+    }
+}
+
+#[async_trait]
+impl FileStore for LocalFileStore {
+    async fn put(&self, key: &str, body: ByteStream) -> Result<(), Error> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Written aside, synced, then renamed over the previous file. Two
+        // writers of the same key would write the same temporary file.
+        let tmp_path = path.with_file_name(format!(
+            "{}.tmp",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let _write = self.writes.lock(key).await;
+        let written = match write_file(&tmp_path, body).await {
+            Ok(()) => tokio::fs::rename(&tmp_path, path).await,
+            Err(err) => Err(err),
+        };
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+        Ok(written?)
+    }
+
+    async fn get(&self, key: &str) -> Result<ByteStream, Error> {
+        match file_stream(&self.path(key)?).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(Error::NotFound(key.to_string()))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, Error> {
+        Ok(tokio::fs::metadata(self.path(key)?)
+            .await
+            .is_ok_and(|metadata| metadata.is_file()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        let path = self.path(key)?;
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        // Directories left empty are removed too, up to the store's.
+        let mut dir = path.parent();
+        while let Some(parent) = dir.filter(|parent| *parent != self.dir) {
+            if tokio::fs::remove_dir(parent).await.is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = vec![];
+        let mut dirs = vec![(self.dir.clone(), String::new())];
+        while let Some((dir, dir_key)) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                entries => entries?,
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let key = format!("{}{}", dir_key, entry.file_name().to_string_lossy());
+                if entry.file_type().await?.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", key)));
+                } else if key.starts_with(prefix) && !key.ends_with(".tmp") {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.path(key).ok()
+    }
+}
+
+async fn write_file(path: &Path, mut body: ByteStream) -> io::Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    while let Some(chunk) = body.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+    file.sync_all().await
+}
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use super::*;
+    use aws_sdk_s3::{presigning::PresigningConfig, primitives, Client};
+    use tokio::sync::OnceCell;
+
+    // Keeps files in an S3 bucket, under `prefix`.
+    #[derive(Debug)]
+    pub struct S3FileStore {
+        bucket: String,
+        prefix: String,
+        // Created on the first call, as loading the AWS configuration is
+        // asynchronous.
+        client: OnceCell<Client>,
+    }
+
+    impl S3FileStore {
+        pub fn new(bucket: String, prefix: String) -> Self {
+            Self {
+                bucket,
+                prefix,
+                client: OnceCell::new(),
+            }
+        }
+
+        async fn client(&self) -> &Client {
+            self.client
+                .get_or_init(|| async {
+                    let config =
+                        aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+                    // S3-compatible services such as MinIO usually don't
+                    // support virtual-hosted buckets.
+                    let s3_config = aws_sdk_s3::config::Builder::from(&config)
+                        .force_path_style(config.endpoint_url().is_some())
+                        .build();
+                    Client::from_conf(s3_config)
+                })
+                .await
+        }
+
+        fn object_key(&self, key: &str) -> String {
+            match self.prefix.is_empty() {
+                true => key.to_string(),
+                false => format!("{}/{}", self.prefix, key),
+            }
+        }
+    }
+
+    fn s3_error(err: impl std::error::Error) -> Error {
+        Error::Io(aws_sdk_s3::error::DisplayErrorContext(err).to_string())
+    }
+
+    #[async_trait]
+    impl FileStore for S3FileStore {
+        // Objects are uploaded in one request, so the body is read in memory
+        // first: books are small enough.
+        async fn put(&self, key: &str, body: ByteStream) -> Result<(), Error> {
+            let data = read_to_vec(body).await?;
+            self.client()
+                .await
+                .put_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key))
+                .body(primitives::ByteStream::from(data))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            Ok(())
+        }
+
+        async fn get(&self, key: &str) -> Result<ByteStream, Error> {
+            let output = self
+                .client()
+                .await
+                .get_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key))
+                .send()
+                .await
+                .map_err(|err| match err.into_service_error() {
+                    err if err.is_no_such_key() => Error::NotFound(key.to_string()),
+                    err => s3_error(err),
+                })?;
+            Ok(Box::pin(stream::unfold(
+                output.body,
+                |mut body| async move {
+                    let chunk = body.next().await?;
+                    Some((chunk.map_err(io::Error::other), body))
+                },
+            )))
+        }
+
+        async fn exists(&self, key: &str) -> Result<bool, Error> {
+            let result = self
+                .client()
+                .await
+                .head_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key))
+                .send()
+                .await;
+            match result.map_err(|err| err.into_service_error()) {
+                Ok(_) => Ok(true),
+                Err(err) if err.is_not_found() => Ok(false),
+                Err(err) => Err(s3_error(err)),
+            }
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), Error> {
+            self.client()
+                .await
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            Ok(())
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            let mut pages = self
+                .client()
+                .await
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(self.object_key(prefix))
+                .into_paginator()
+                .send();
+            let object_prefix = self.object_key("");
+            let mut keys = vec![];
+            while let Some(page) = pages.next().await {
+                for object in page.map_err(s3_error)?.contents() {
+                    if let Some(key) = object
+                        .key()
+                        .and_then(|key| key.strip_prefix(&object_prefix))
+                    {
+                        keys.push(key.to_string());
+                    }
+                }
+            }
+            Ok(keys)
+        }
+
+        async fn presigned_url(
+            &self,
+            key: &str,
+            expires_in: Duration,
+        ) -> Result<Option<String>, Error> {
+            let presigning = PresigningConfig::expires_in(expires_in).map_err(s3_error)?;
+            let request = self
+                .client()
+                .await
+                .get_object()
+                .bucket(&self.bucket)
+                .key(self.object_key(key))
+                .presigned(presigning)
+                .await
+                .map_err(s3_error)?;
+            Ok(Some(request.uri().to_string()))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    NotFound(String),
+    InvalidKey(String),
+    Unsupported(String),
+    Io(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(key) => write!(f, "{} not found", key),
+            Self::InvalidKey(key) => write!(f, "invalid key {:?}", key),
+            Self::Unsupported(err) | Self::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libreads_file_store_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn get(store: &dyn FileStore, key: &str) -> Result<Vec<u8>, Error> {
+        Ok(read_to_vec(store.get(key).await?).await?)
+    }
+
+    // Runs against any store, so a backend behaves like the others.
+    async fn check_store(store: &dyn FileStore) {
+        assert_eq!(Ok(false), store.exists("ABCD/metadata.json").await);
+        assert_eq!(
+            Err(Error::NotFound("ABCD/metadata.json".to_string())),
+            get(store, "ABCD/metadata.json").await
+        );
+
+        store
+            .put("ABCD/metadata.json", bytes_stream("{}"))
+            .await
+            .unwrap();
+        let book = Path::new("tests/testdata/dummy_ebook.epub");
+        store
+            .put("ABCD/1984.epub", file_stream(book).await.unwrap())
+            .await
+            .unwrap();
+        store
+            .put("EFGH/metadata.json", bytes_stream("[]"))
+            .await
+            .unwrap();
+
+        assert_eq!(Ok(true), store.exists("ABCD/metadata.json").await);
+        assert_eq!(Ok(b"{}".to_vec()), get(store, "ABCD/metadata.json").await);
+        assert_eq!(
+            Ok(std::fs::read(book).unwrap()),
+            get(store, "ABCD/1984.epub").await
+        );
+
+        // Files are replaced.
+        store
+            .put("ABCD/metadata.json", bytes_stream("{\"a\":1}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            Ok(b"{\"a\":1}".to_vec()),
+            get(store, "ABCD/metadata.json").await
+        );
+
+        let mut keys = store.list("").await.unwrap();
+        keys.sort();
+        assert_eq!(
+            vec!["ABCD/1984.epub", "ABCD/metadata.json", "EFGH/metadata.json"],
+            keys
+        );
+        let mut keys = store.list("ABCD/").await.unwrap();
+        keys.sort();
+        assert_eq!(vec!["ABCD/1984.epub", "ABCD/metadata.json"], keys);
+
+        for key in ["ABCD/1984.epub", "ABCD/metadata.json", "EFGH/metadata.json"] {
+            assert_eq!(Ok(()), store.delete(key).await);
+        }
+        assert_eq!(Ok(()), store.delete("ABCD/metadata.json").await);
+        assert_eq!(Ok(false), store.exists("ABCD/metadata.json").await);
+        assert_eq!(Ok(vec![]), store.list("").await);
+    }
+
+    #[tokio::test]
+    async fn test_local_store() {
+        let dir = store_dir("local");
+        let store = LocalFileStore::new(&dir);
+
+        check_store(&store).await;
+
+        assert_eq!(
+            Some(dir.join("ABCD").join("1984.epub")),
+            store.local_path("ABCD/1984.epub")
+        );
+        assert_eq!(
+            Ok(None),
+            store
+                .presigned_url("ABCD/1984.epub", PRESIGNED_URL_TTL)
+                .await
+        );
+        // Empty directories are removed with their last file.
+        assert!(!dir.join("ABCD").exists());
+        assert!(dir.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_store_invalid_keys() {
+        let dir = store_dir("invalid_keys");
+        let store = LocalFileStore::new(&dir);
+
+        for key in ["", "../secret", "/etc/passwd", "ABCD/../../secret"] {
+            assert_eq!(
+                Err(Error::InvalidKey(key.to_string())),
+                store.put(key, bytes_stream("")).await
+            );
+            assert_eq!(
+                Err(Error::InvalidKey(key.to_string())),
+                store.delete(key).await
+            );
+            assert_eq!(None, store.local_path(key));
+        }
+        assert!(!dir.exists());
+    }
+
+    // `chunks` chunks of `byte`, one at a time, so concurrent writers would
+    // interleave them.
+    fn slow_stream(byte: u8, chunks: usize) -> ByteStream {
+        Box::pin(stream::iter(0..chunks).then(move |_| async move {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            Ok(Bytes::from(vec![byte; 1024]))
+        }))
+    }
+
+    fn tmp_files(dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "tmp"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_local_store_concurrent_writes() {
+        let dir = store_dir("concurrent_writes");
+        let store = LocalFileStore::new(&dir);
+
+        let (a, b) = tokio::join!(
+            store.put("ABCD/1984.epub", slow_stream(b'a', 20)),
+            store.put("ABCD/1984.epub", slow_stream(b'b', 20)),
+        );
+        assert_eq!((Ok(()), Ok(())), (a, b));
+
+        let got = std::fs::read(dir.join("ABCD").join("1984.epub")).unwrap();
+        assert!(
+            got == vec![b'a'; 20 * 1024] || got == vec![b'b'; 20 * 1024],
+            "got a mixture of both writes"
+        );
+        assert_eq!(Vec::<PathBuf>::new(), tmp_files(&dir.join("ABCD")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_store_failed_write() {
+        let dir = store_dir("failed_write");
+        let store = LocalFileStore::new(&dir);
+        store
+            .put("ABCD/1984.epub", bytes_stream("previous"))
+            .await
+            .unwrap();
+
+        let failing: ByteStream = Box::pin(slow_stream(b'a', 2).chain(stream::once(async {
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+        })));
+        assert!(store.put("ABCD/1984.epub", failing).await.is_err());
+
+        // The previous file is kept, and the partial one deleted.
+        assert_eq!(
+            Ok(b"previous".to_vec()),
+            get(&store, "ABCD/1984.epub").await
+        );
+        assert_eq!(Vec::<PathBuf>::new(), tmp_files(&dir.join("ABCD")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            Ok(Location::Dir(PathBuf::from("/srv/libreads"))),
+            parse_location("/srv/libreads")
+        );
+        if cfg!(feature = "s3") {
+            assert_eq!(
+                Ok(Location::S3 {
+                    bucket: "books".to_string(),
+                    prefix: "libreads/library".to_string(),
+                }),
+                parse_location("s3://books/libreads/library/")
+            );
+            assert_eq!(
+                Ok(Location::S3 {
+                    bucket: "books".to_string(),
+                    prefix: String::new(),
+                }),
+                parse_location("s3://books")
+            );
+            assert_eq!(
+                Err(Error::InvalidKey("s3:///library".to_string())),
+                parse_location("s3:///library")
+            );
+        } else {
+            assert_eq!(
+                Err(Error::Unsupported(
+                    "S3 storage requires the `s3` feature".to_string()
+                )),
+                parse_location("s3://books/library")
+            );
+        }
+    }
+
+    // Runs against a real bucket, e.g. MinIO, when `LIBREADS_TEST_S3_BUCKET`
+    // is set: `cargo test --features s3`.
+    #[cfg(feature = "s3")]
+    #[tokio::test]
+    async fn test_s3_store() {
+        let Ok(bucket) = std::env::var("LIBREADS_TEST_S3_BUCKET") else {
+            return;
+        };
+        let prefix = format!("libreads-test-{}", uuid::Uuid::new_v4());
+        let store = s3::S3FileStore::new(bucket, prefix);
+
+        check_store(&store).await;
+
+        store
+            .put("ABCD/1984.epub", bytes_stream("book"))
+            .await
+            .unwrap();
+        let url = store
+            .presigned_url("ABCD/1984.epub", PRESIGNED_URL_TTL)
+            .await
+            .unwrap()
+            .unwrap();
+        let body = reqwest::get(url).await.unwrap().bytes().await.unwrap();
+        assert_eq!(&b"book"[..], &body[..]);
+        store.delete("ABCD/1984.epub").await.unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+//! Module frontend serves the Svelte front-end.
+//!
+//! The built front-end is served from `Config::frontend_dir` (by default
+//! `./frontend/build`) when that directory exists, so it can be rebuilt
+//! without restarting the server. Otherwise, when the `embed-frontend`
+//! feature is enabled, it is served from assets embedded in the binary at
+//! build time.
+//!
+//! Either way, paths without an extension are client-side routes, e.g.
+//! `/history`, and get the index page.
+//!
+//! Without either, e.g. in a fresh clone only used for the API, `/` shows a
+//! status page instead: the version, the API endpoints and the settings.
+
+use crate::{config::Config, openapi, web::escape_html};
+use actix_files::{Files, NamedFile};
+use actix_web::{
+    dev::{fn_service, ServiceRequest, ServiceResponse},
+    http::header::CONTENT_TYPE,
+    web::{self, Data, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use rust_embed::RustEmbed;
+use std::path::Path;
+
+pub const FRONTEND_BUILD_DIR: &str = "./frontend/build";
+const INDEX_FILE: &str = "index.html";
+
+#[cfg(feature = "embed-frontend")]
+#[derive(RustEmbed)]
+#[folder = "frontend/build"]
+pub struct FrontendAssets;
+
+#[derive(Debug, PartialEq)]
+pub enum FrontendMode {
+    // Served from the front-end directory.
+    Directory,
+    // Served from the assets embedded in the binary.
+    Embedded,
+    // No front-end: `/` shows the status page.
+    StatusPage,
+}
+
+pub fn frontend_mode(dir: &Path) -> FrontendMode {
+    if dir.is_dir() {
+        FrontendMode::Directory
+    } else if cfg!(feature = "embed-frontend") {
+        FrontendMode::Embedded
+    } else {
+        FrontendMode::StatusPage
+    }
+}
+
+#[test]
+fn test_frontend_mode() {
+    assert_eq!(
+        FrontendMode::Directory,
+        frontend_mode(Path::new("tests/testdata/frontend"))
+    );
+    // A file isn't a front-end directory.
+    let missing = if cfg!(feature = "embed-frontend") {
+        FrontendMode::Embedded
+    } else {
+        FrontendMode::StatusPage
+    };
+    for dir in [
+        "tests/testdata/no_such_frontend",
+        "tests/testdata/frontend/index.html",
+    ] {
+        assert_eq!(missing, frontend_mode(Path::new(dir)), "{}", dir);
+    }
+}
+
+// Registers the front-end on an actix app. It answers every path, so it
+// must be registered after the API. Only GET and HEAD requests are served.
+pub fn configure(cfg: &mut ServiceConfig, dir: &Path) {
+    match frontend_mode(dir) {
+        FrontendMode::Directory => serve_dir(cfg, dir),
+        FrontendMode::Embedded => {
+            #[cfg(feature = "embed-frontend")]
+            cfg.default_service(web::to(serve_embedded::<FrontendAssets>));
+        }
+        FrontendMode::StatusPage => {
+            cfg.service(
+                web::resource("/")
+                    .route(web::get().to(status_page))
+                    .route(web::head().to(status_page)),
+            );
+        }
+    }
+}
+
+fn serve_dir(cfg: &mut ServiceConfig, dir: &Path) {
+    let index = dir.join(INDEX_FILE);
+    let serve_index = fn_service(move |req: ServiceRequest| {
+        let index = index.clone();
+        async move {
+            let (req, _) = req.into_parts();
+            let response = if is_client_side_route(req.path()) {
+                NamedFile::open_async(index).await?.into_response(&req)
+            } else {
+                HttpResponse::NotFound().finish()
+            };
+            Ok(ServiceResponse::new(req, response))
+        }
+    });
+    cfg.service(
+        Files::new("/", dir)
+            .index_file(INDEX_FILE)
+            .default_handler(serve_index),
+    );
+}
+
+// Warns at startup when the front-end directory is missing, e.g. when
+// running LibReads from another directory.
+pub fn warn_if_missing(dir: &Path) {
+    match frontend_mode(dir) {
+        FrontendMode::Directory => {}
+        FrontendMode::Embedded => tracing::warn!(
+            "The front-end directory {:?} doesn't exist, serving the embedded front-end",
+            dir
+        ),
+        FrontendMode::StatusPage => tracing::warn!(
+            "The front-end directory {:?} doesn't exist, serving a status page instead (see LIBREADS_FRONTEND_DIR)",
+            dir
+        ),
+    }
+}
+
+// The API endpoints listed on the status page, as (method, path), from the
+// OpenAPI document so the list follows the routes. Their required query
+// parameters are shown, e.g. `/api/shelf?url={url}`. The probes are listed at
+// the root of the server, where they are served too, see `server::start`.
+fn api_endpoints() -> Vec<(&'static str, String)> {
+    use utoipa::openapi::{path::ParameterIn, Required};
+
+    let document = openapi::document("/api");
+    let mut endpoints = vec![];
+    for (path, item) in &document.paths.paths {
+        let operations = [
+            ("GET", &item.get),
+            ("HEAD", &item.head),
+            ("POST", &item.post),
+            ("PUT", &item.put),
+            ("PATCH", &item.patch),
+            ("DELETE", &item.delete),
+        ];
+        for (method, operation) in operations {
+            let Some(operation) = operation else {
+                continue;
+            };
+            let is_probe = operation.tags.iter().flatten().any(|tag| tag == "probes");
+            let query: Vec<String> = operation
+                .parameters
+                .iter()
+                .flatten()
+                .filter(|param| {
+                    param.parameter_in == ParameterIn::Query && param.required == Required::True
+                })
+                .map(|param| format!("{0}={{{0}}}", param.name))
+                .collect();
+            let mut path = match is_probe {
+                true => path.clone(),
+                false => format!("/api{}", path),
+            };
+            if !query.is_empty() {
+                path = format!("{}?{}", path, query.join("&"));
+            }
+            endpoints.push((method, path));
+        }
+    }
+    endpoints
+}
+
+async fn status_page(config: Option<Data<Config>>) -> HttpResponse {
+    let endpoints: String = api_endpoints()
+        .iter()
+        .map(|(method, path)| {
+            let path = match *method == "GET" && !path.contains('{') {
+                true => format!("<a href=\"{0}\">{0}</a>", escape_html(path)),
+                false => escape_html(path),
+            };
+            format!("<li><code>{} {}</code></li>", method, path)
+        })
+        .collect();
+    let settings: String = config
+        .map(|config| config.summary())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| {
+            format!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape_html(name),
+                escape_html(&value)
+            )
+        })
+        .collect();
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>LibReads</title></head>
+<body>
+<h1>LibReads {version}</h1>
+<p>The front-end isn't built: only the API is served.</p>
+<h2>API</h2>
+<ul>{endpoints}</ul>
+<h2>Settings</h2>
+<table>{settings}</table>
+</body>
+</html>
+",
+            version = env!("CARGO_PKG_VERSION"),
+        ))
+}
+
+fn is_client_side_route(path: &str) -> bool {
+    Path::new(path).extension().is_none()
+}
+
+// Serves an embedded asset, or the index page for client-side routes.
+pub async fn serve_embedded<E: RustEmbed>(req: HttpRequest) -> HttpResponse {
+    let path = req.path().trim_start_matches('/');
+    let path = if path.is_empty() { INDEX_FILE } else { path };
+
+    if let Some(file) = E::get(path) {
+        return HttpResponse::Ok()
+            .insert_header((CONTENT_TYPE, file.metadata.mimetype()))
+            .body(file.data.into_owned());
+    }
+
+    match E::get(INDEX_FILE) {
+        Some(index) if is_client_side_route(path) => HttpResponse::Ok()
+            .insert_header((CONTENT_TYPE, index.metadata.mimetype()))
+            .body(index.data.into_owned()),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body::to_bytes, test, web, App};
+
+    #[derive(RustEmbed)]
+    #[folder = "tests/testdata/frontend"]
+    struct TestAssets;
+
+    const TEST_FRONTEND_DIR: &str = "tests/testdata/frontend";
+
+    async fn get(path: &str) -> (u16, Option<String>, String) {
+        let app =
+            test::init_service(App::new().default_service(web::to(serve_embedded::<TestAssets>)))
+                .await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri(path).to_request()).await;
+        read_response(resp).await
+    }
+
+    async fn get_from_dir(
+        request: test::TestRequest,
+        dir: &'static str,
+    ) -> (u16, Option<String>, String) {
+        let app =
+            test::init_service(App::new().configure(|cfg| configure(cfg, Path::new(dir)))).await;
+        let resp = test::call_service(&app, request.to_request()).await;
+        read_response(resp).await
+    }
+
+    async fn read_response(resp: ServiceResponse) -> (u16, Option<String>, String) {
+        let status = resp.status().as_u16();
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8_lossy(&body).to_string(),
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_serve_index() {
+        let (status, content_type, body) = get("/").await;
+
+        assert_eq!(200, status);
+        assert_eq!(Some("text/html".to_string()), content_type);
+        assert!(body.contains("<div>LibReads</div>"));
+    }
+
+    #[actix_web::test]
+    async fn test_serve_asset() {
+        let (status, content_type, body) = get("/_app/start.js").await;
+
+        assert_eq!(200, status);
+        assert_eq!(Some("application/javascript".to_string()), content_type);
+        assert_eq!("console.log(\"LibReads\");\n", body);
+    }
+
+    #[actix_web::test]
+    async fn test_client_side_route_fallback() {
+        let (status, content_type, body) = get("/books/some-route").await;
+
+        assert_eq!(200, status);
+        assert_eq!(Some("text/html".to_string()), content_type);
+        assert!(body.contains("<div>LibReads</div>"));
+    }
+
+    #[actix_web::test]
+    async fn test_missing_asset() {
+        let (status, _, _) = get("/_app/missing.js").await;
+
+        assert_eq!(404, status);
+    }
+
+    #[actix_web::test]
+    async fn test_serve_from_dir() {
+        for (path, content_type, content) in [
+            ("/", "text/html", "<div>LibReads</div>"),
+            ("/_app/start.js", "application/javascript", "console.log"),
+            // Client-side routes get the index page.
+            ("/history", "text/html", "<div>LibReads</div>"),
+            ("/books/some-route", "text/html", "<div>LibReads</div>"),
+        ] {
+            let (status, got_content_type, body) =
+                get_from_dir(test::TestRequest::get().uri(path), TEST_FRONTEND_DIR).await;
+
+            assert_eq!(200, status, "{}", path);
+            assert!(
+                got_content_type.is_some_and(|got| got.starts_with(content_type)),
+                "{}",
+                path
+            );
+            assert!(body.contains(content), "{}: {}", path, body);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_serve_from_dir_not_found() {
+        let (status, _, _) = get_from_dir(
+            test::TestRequest::get().uri("/_app/missing.js"),
+            TEST_FRONTEND_DIR,
+        )
+        .await;
+        assert_eq!(404, status);
+
+        let (status, _, _) =
+            get_from_dir(test::TestRequest::post().uri("/history"), TEST_FRONTEND_DIR).await;
+        assert_eq!(405, status);
+    }
+
+    #[actix_web::test]
+    #[cfg(not(feature = "embed-frontend"))]
+    async fn test_status_page() {
+        let config = Config {
+            api_token: Some("s3cr3t".to_string()),
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(config))
+                .configure(|cfg| configure(cfg, Path::new("tests/testdata/no_such_frontend"))),
+        )
+        .await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        let (status, content_type, body) = read_response(resp).await;
+
+        assert_eq!(200, status);
+        assert_eq!(Some("text/html; charset=utf-8".to_string()), content_type);
+        assert!(body.contains(&format!("LibReads {}", env!("CARGO_PKG_VERSION"))));
+        assert!(
+            body.contains(r#"<a href="/healthz">/healthz</a>"#),
+            "{}",
+            body
+        );
+        // Every route of the OpenAPI document is listed.
+        for endpoint in [
+            "GET /api/download/{goodreads_url}",
+            "HEAD /api/download/{goodreads_url}",
+            "POST /api/download",
+            "GET /api/download/md5/{md5}",
+            "GET /api/shelf?url={url}",
+            "DELETE /api/library/{md5}",
+            "POST /api/queue",
+            r#"GET <a href="/api/queue">/api/queue</a>"#,
+            r#"GET <a href="/api/stats">/api/stats</a>"#,
+            r#"GET <a href="/api/openapi.json">/api/openapi.json</a>"#,
+            r#"GET <a href="/metrics">/metrics</a>"#,
+            "POST /api/admin/cleanup",
+        ] {
+            assert!(body.contains(endpoint), "{}: {}", endpoint, body);
+        }
+        assert!(
+            body.contains("<tr><th>API token</th><td>enabled</td></tr>"),
+            "{}",
+            body
+        );
+        assert!(!body.contains("s3cr3t"));
+    }
+
+    #[actix_web::test]
+    #[cfg(not(feature = "embed-frontend"))]
+    async fn test_missing_dir() {
+        let (status, _, _) = get_from_dir(
+            test::TestRequest::get().uri("/history"),
+            "tests/testdata/no_such_frontend",
+        )
+        .await;
+
+        assert_eq!(404, status);
+    }
+}
@@ -1,115 +1,1095 @@
 //! Module goodreads can find ISBN numbers (10 and 13) in a Goodreads HTML page
 //! for a book.
+//!
+//! Besides book pages (`/book/show/...`), it accepts work pages
+//! (`/work/...`) and review pages (`/review/show/...`), which are resolved to
+//! the book page they link to.
+//!
+//! It can also list the books of a shelf (`/review/list/...?shelf=to-read`).
+//!
+//! Goodreads localises its pages, e.g. under `/de/`: books are found with
+//! `itemprop` and `data-testid` attributes and the JSON data of the page,
+//! never with the text of its labels.
+//!
+//! Book pages are parsed with a `ParseReport` of the fields found, see
+//! `parse_check`.
 
+use crate::{
+    clock::SharedClock,
+    http_client::{self, ConnectionLimits, HttpFailure, RateLimit, SendError},
+    parse_check::ParseReport,
+};
 use async_trait::async_trait;
 use regex::Regex;
-use scraper::{Html, Selector};
-use serde::Deserialize;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use utoipa::ToSchema;
 
-#[derive(Debug, PartialEq, Default)]
+// Where Goodreads is, though pages are fetched from the host of their URL.
+pub(crate) const BASE_URL: &str = "https://www.goodreads.com";
+
+// Selectors and regexes are compiled once, and all of them at startup, see
+// `compile_parsers`, so a typo panics instead of finding nothing.
+fn selector(css: &str) -> Selector {
+    Selector::parse(css).unwrap_or_else(|err| panic!("invalid selector {:?}: {:?}", css, err))
+}
+
+fn regex(pattern: &str) -> Regex {
+    Regex::new(pattern).unwrap_or_else(|err| panic!("invalid regex {:?}: {}", pattern, err))
+}
+
+static BOOK_DATA: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"script[type="application/ld+json"]"#));
+static NEXT_DATA: LazyLock<Selector> = LazyLock::new(|| selector(r#"script[id="__NEXT_DATA__"]"#));
+static ISBN: LazyLock<Selector> = LazyLock::new(|| selector(r#"span[itemprop="isbn"]"#));
+static TITLE: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"h1[data-testid="bookTitle"], h1[id="bookTitle"]"#));
+static SERIES: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"[data-testid="bookSeries"], #bookSeries"#));
+static AUTHOR: LazyLock<Selector> = LazyLock::new(|| {
+    selector(
+        r#"div[class="ContributorLinksList"] span[data-testid="name"], a[class="authorName"] span[itemprop="name"]"#,
+    )
+});
+static COVER: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"div[class="BookCover__image"] img, img[id="coverImage"]"#));
+static OPEN_GRAPH_IMAGE: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"meta[property="og:image"]"#));
+static PAGES: LazyLock<Selector> = LazyLock::new(|| {
+    selector(r#"p[data-testid="pagesFormat"], #details span[itemprop="numberOfPages"]"#)
+});
+static LEGACY_FORMAT: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"#details span[itemprop="bookFormat"]"#));
+static PAGES_FORMAT: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"p[data-testid="pagesFormat"]"#));
+static LANGUAGE: LazyLock<Selector> = LazyLock::new(|| selector(r#"[itemprop="inLanguage"]"#));
+static EDITION_DETAILS: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#".EditionDetails div[class="DescListItem"] dd"#));
+static LEGACY_DETAILS_ROW: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"#details div[class="row"]"#));
+// In order of preference, see `find_book_link`.
+static BOOK_LINKS: LazyLock<[Selector; 3]> = LazyLock::new(|| {
+    [
+        selector(r#"link[rel="canonical"][href*="/book/show/"]"#),
+        selector(r#"a[class="bookTitle"][href*="/book/show/"]"#),
+        selector(r#"a[href*="/book/show/"]"#),
+    ]
+});
+static SHELF_ROW: LazyLock<Selector> = LazyLock::new(|| selector(r#"tr[class~="bookalike"]"#));
+static SHELF_TITLE: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"td[class~="title"] a[href*="/book/show/"]"#));
+static SHELF_AUTHOR: LazyLock<Selector> =
+    LazyLock::new(|| selector(r#"td[class~="author"] a[href*="/author/show/"]"#));
+static NEXT_PAGE: LazyLock<Selector> = LazyLock::new(|| selector(r#"a[class~="next_page"]"#));
+
+static WHITESPACE: LazyLock<Regex> = LazyLock::new(|| regex(r"\s+"));
+static ISBN_10: LazyLock<Regex> = LazyLock::new(|| regex(r"\b\d{9}[\dXx]\b"));
+static SERIES_INDEX: LazyLock<Regex> =
+    LazyLock::new(|| regex(r"^(.*?),?\s*#\s*(\d+(?:\.\d+)?(?:\s*[-–]\s*\d+(?:\.\d+)?)?)$"));
+static SERIES_SUFFIX: LazyLock<Regex> = LazyLock::new(|| regex(r"\s*\(([^()]*#\s*\d[^()]*)\)\s*$"));
+static PAGE_COUNT: LazyLock<Regex> = LazyLock::new(|| regex(r"(\d[\d,.]*)\s*\p{L}"));
+static EDITION_QUALIFIER: LazyLock<Regex> = LazyLock::new(|| {
+    regex(
+        r"(?i)\s*\([^()]*\b([ée]dition|ausgabe|edici[oó]n|edizione|editie|edi[cç][aã]o)\b[^()]*\)\s*$",
+    )
+});
+static BRACKETS: LazyLock<Regex> = LazyLock::new(|| regex(r"\([^()]*\)|\[[^\[\]]*\]"));
+
+// Compiles the selectors and regexes the pages are parsed with, which panics
+// on invalid ones.
+pub(crate) fn compile_parsers() {
+    for selector in [
+        &BOOK_DATA,
+        &NEXT_DATA,
+        &ISBN,
+        &TITLE,
+        &SERIES,
+        &AUTHOR,
+        &COVER,
+        &OPEN_GRAPH_IMAGE,
+        &PAGES,
+        &LEGACY_FORMAT,
+        &PAGES_FORMAT,
+        &LANGUAGE,
+        &EDITION_DETAILS,
+        &LEGACY_DETAILS_ROW,
+        &SHELF_ROW,
+        &SHELF_TITLE,
+        &SHELF_AUTHOR,
+        &NEXT_PAGE,
+    ] {
+        LazyLock::force(selector);
+    }
+    LazyLock::force(&BOOK_LINKS);
+    for regex in [
+        &WHITESPACE,
+        &ISBN_10,
+        &SERIES_INDEX,
+        &SERIES_SUFFIX,
+        &PAGE_COUNT,
+        &EDITION_QUALIFIER,
+        &BRACKETS,
+    ] {
+        LazyLock::force(regex);
+    }
+}
+
+#[test]
+fn test_compile_parsers() {
+    compile_parsers();
+}
+
+#[test]
+#[should_panic(expected = "invalid selector")]
+fn test_invalid_selector() {
+    selector("div[class=");
+}
+
+#[derive(Debug, PartialEq, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct BookIdentification {
     pub isbn10: Option<String>,
     pub isbn13: Option<String>,
     pub title: Option<String>,
     pub author: Option<String>,
+    pub cover_url: Option<String>,
+    // Number of pages of this edition, to tell e.g. abridged editions apart.
+    #[serde(default)]
+    pub pages: Option<u32>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    // The series the book belongs to, and its position in it, e.g. "1",
+    // "0.5" or "1-3". Left out of the title LibGen is searched by.
+    #[serde(default)]
+    pub series: Option<String>,
+    #[serde(default)]
+    pub series_index: Option<String>,
+    // The format of this edition, e.g. "Paperback", "Kindle Edition" or
+    // "Audiobook".
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+// What tells a book apart, in a canonical form: ISBNs without their dashes
+// nor spaces, and the title and author without extra whitespace. Two
+// identifications of the same book, e.g. with ISBN "978-0-553-41802-6" and
+// "9780553418026", have the same `CanonicalBookId`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct CanonicalBookId {
+    pub isbn10: Option<String>,
+    pub isbn13: Option<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+impl BookIdentification {
+    // A book known by its ISBN only: ISBN-10s are told apart by their
+    // length, once canonical.
+    pub fn from_isbn(isbn: &str) -> Self {
+        let isbn = canonical_isbn(isbn);
+        match isbn.as_deref().map(str::len) {
+            Some(10) => Self {
+                isbn10: isbn,
+                ..Default::default()
+            },
+            _ => Self {
+                isbn13: isbn,
+                ..Default::default()
+            },
+        }
+    }
+
+    // A book known by its title and author only, searched by title on LibGen.
+    pub fn from_title_and_author(title: &str, author: &str) -> Self {
+        Self {
+            title: Some(title.to_string()),
+            author: Some(author.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn canonical(&self) -> CanonicalBookId {
+        let text = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| value.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|value| !value.is_empty())
+        };
+        CanonicalBookId {
+            isbn10: self.isbn10.as_deref().and_then(canonical_isbn),
+            isbn13: self.isbn13.as_deref().and_then(canonical_isbn),
+            title: text(&self.title),
+            author: text(&self.author),
+        }
+    }
+
+    // Whether this is an audiobook or box set edition: LibGen has no book
+    // under its ISBN, though it may have the book itself.
+    pub fn is_audio_or_box_set(&self) -> bool {
+        self.format.as_deref().is_some_and(|format| {
+            let format = format.to_lowercase();
+            ["audio", "mp3", "hörbuch", "box set", "boxed set"]
+                .iter()
+                .any(|keyword| format.contains(keyword))
+        })
+    }
+
+    // Whether both identify the same book, see `CanonicalBookId`.
+    pub fn same_book(&self, other: &BookIdentification) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+#[test]
+fn test_book_identification_constructors() {
+    assert_eq!(
+        BookIdentification {
+            isbn10: Some("055341802X".to_string()),
+            ..Default::default()
+        },
+        BookIdentification::from_isbn("0-553-41802-x")
+    );
+    assert_eq!(
+        BookIdentification {
+            isbn13: Some("9780553418026".to_string()),
+            ..Default::default()
+        },
+        BookIdentification::from_isbn("978-0-553-41802-6")
+    );
+    assert_eq!(
+        BookIdentification {
+            title: Some("Dune".to_string()),
+            author: Some("Frank Herbert".to_string()),
+            ..Default::default()
+        },
+        BookIdentification::from_title_and_author("Dune", "Frank Herbert")
+    );
+}
+
+// Strips the dashes and spaces of an ISBN, and upper-cases the "X" check
+// digit of ISBN-10s. Empty ISBNs are none.
+pub fn canonical_isbn(isbn: &str) -> Option<String> {
+    let isbn: String = isbn
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    (!isbn.is_empty()).then_some(isbn)
+}
+
+#[test]
+fn test_is_audio_or_box_set() {
+    let edition = |format: Option<&str>| BookIdentification {
+        format: format.map(str::to_string),
+        ..Default::default()
+    };
+    for format in [
+        "Audiobook",
+        "Audio CD",
+        "Audible Audio",
+        "MP3 CD",
+        "Hörbuch",
+        "Box Set",
+        "Boxed Set",
+    ] {
+        assert!(edition(Some(format)).is_audio_or_box_set(), "{}", format);
+    }
+    for format in [
+        Some("Paperback"),
+        Some("Kindle Edition"),
+        Some("ebook"),
+        None,
+    ] {
+        assert!(!edition(format).is_audio_or_box_set(), "{:?}", format);
+    }
+}
+
+#[test]
+fn test_canonical_book_id() {
+    let hyphenated = BookIdentification {
+        isbn10: Some("0-553-41802-x".to_string()),
+        isbn13: Some("978-0-553-41802-6".to_string()),
+        title: Some("  The Martian ".to_string()),
+        author: Some("Andy  Weir".to_string()),
+        cover_url: Some("https://example.com/cover.jpg".to_string()),
+        ..Default::default()
+    };
+    let plain = BookIdentification {
+        isbn10: Some("055341802X".to_string()),
+        isbn13: Some("9780553418026".to_string()),
+        title: Some("The Martian".to_string()),
+        author: Some("Andy Weir".to_string()),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        CanonicalBookId {
+            isbn10: Some("055341802X".to_string()),
+            isbn13: Some("9780553418026".to_string()),
+            title: Some("The Martian".to_string()),
+            author: Some("Andy Weir".to_string()),
+        },
+        hyphenated.canonical()
+    );
+    assert_ne!(hyphenated, plain);
+    assert!(hyphenated.same_book(&plain));
+    let ids = std::collections::HashSet::from([hyphenated.canonical(), plain.canonical()]);
+    assert_eq!(1, ids.len());
+
+    assert!(!plain.same_book(&BookIdentification {
+        isbn13: Some("9780553418033".to_string()),
+        ..plain.clone()
+    }));
+    assert_eq!(
+        CanonicalBookId::default(),
+        BookIdentification {
+            isbn10: Some(" - ".to_string()),
+            title: Some(" ".to_string()),
+            ..Default::default()
+        }
+        .canonical()
+    );
 }
 
 #[async_trait]
 #[cfg_attr(test, mockall::automock)]
 pub trait BookIdentificationGetter {
-    async fn get_identification(
-        &self,
-        page_url: &str,
-    ) -> Result<BookIdentification, reqwest::Error>;
+    async fn get_identification(&self, page_url: &str) -> Result<BookIdentification, Error>;
+}
+
+// A book listed on a Goodreads shelf.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ShelfEntry {
+    pub book_url: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+#[async_trait]
+#[cfg_attr(test, mockall::automock)]
+pub trait ShelfGetter {
+    async fn get_shelf(&self, shelf_url: &str) -> Result<Vec<ShelfEntry>, Error>;
 }
 
 #[derive(Default)]
-pub struct Goodreads {}
+pub struct Goodreads {
+    pub client: reqwest::Client,
+    pub limits: ConnectionLimits,
+    // Where pages are fetched from instead of the host of their URL, e.g. a
+    // test server. Their path and query are kept.
+    pub base_url: Option<String>,
+    // Times the waits before retrying a rate-limited request.
+    pub clock: SharedClock,
+}
 
+// The schema.org data of the page.
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct BookData {
     isbn: Option<String>,
+    number_of_pages: Option<u32>,
+    book_format: Option<String>,
+    in_language: Option<String>,
+}
+
+// The edition details of the page's `__NEXT_DATA__`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BookDetails {
+    isbn: Option<String>,
+    isbn13: Option<String>,
+    num_pages: Option<u32>,
+    publisher: Option<String>,
+    format: Option<String>,
+    language: Option<Language>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Language {
+    name: Option<String>,
 }
 
 impl Goodreads {
-    fn find_isbn_10(&self, fragment: &Html) -> Option<String> {
-        if let Some(isbn) = self.find_isbn_10_v1(fragment) {
+    fn fetched_url(&self, url: &reqwest::Url) -> reqwest::Url {
+        let Some(base_url) = &self.base_url else {
+            return url.clone();
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        reqwest::Url::parse(&format!("{}{}", base_url.trim_end_matches('/'), path))
+            .unwrap_or_else(|_| url.clone())
+    }
+
+    // Pages are asked for in English, though they are parsed the same in any
+    // language.
+    fn get(&self, url: &reqwest::Url) -> reqwest::RequestBuilder {
+        self.client
+            .get(self.fetched_url(url))
+            .header(reqwest::header::ACCEPT_LANGUAGE, "en")
+    }
+}
+
+// A book page, whose JSON data is parsed once for all the fields read from
+// it.
+struct BookPage<'a> {
+    document: &'a Html,
+    // The schema.org data of the page.
+    book_data: Vec<BookData>,
+    // The book of the page in its `__NEXT_DATA__`, with the objects it refers
+    // to, see `find_next_data_book`.
+    next_data_book: Option<(serde_json::Value, serde_json::Value)>,
+}
+
+impl<'a> BookPage<'a> {
+    fn new(document: &'a Html, page_url: &str) -> Self {
+        let book_data = document
+            .select(&BOOK_DATA)
+            .filter_map(|script_tag| serde_json::from_str(&script_tag.inner_html()).ok())
+            .collect();
+        Self {
+            document,
+            book_data,
+            next_data_book: find_next_data_book(document, page_url),
+        }
+    }
+
+    fn find_isbn_10(&self) -> Option<String> {
+        if let Some(isbn) = self.find_isbn_10_v1() {
             return Some(isbn);
         }
 
-        let selector = Selector::parse(r#"script[type="application/ld+json"]"#).ok()?;
-        for script_tag in fragment.select(&selector) {
-            let book: Result<BookData, _> = serde_json::from_str(&script_tag.inner_html());
-            if let Ok(book) = book {
-                if book.isbn.is_some() {
-                    return book.isbn;
+        // The structured data holds the ISBN-13 when there is no ISBN-10.
+        self.book_data
+            .iter()
+            .filter_map(|book| book.isbn.clone())
+            .find(|isbn| is_isbn_10(isbn))
+    }
+
+    // Legacy way to get the ISBN, doesn't seem to work in 2024.
+    // The ISBN-10 is in the text of the `infoBoxRowItem` before the
+    // `greyText` span holding the ISBN-13, whatever its label:
+    // <div class="infoBoxRowItem">
+    //     0521405998
+    //     <span class="greyText">(ISBN13: <span itemprop="isbn">9780521405997</span>)</span>
+    // </div>
+    fn find_isbn_10_v1(&self) -> Option<String> {
+        let span = self.document.select(&ISBN).next()?;
+        let row_item = span
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .find(|element| {
+                element
+                    .value()
+                    .classes()
+                    .any(|class| class == "infoBoxRowItem")
+            })?;
+
+        let mut isbn = None;
+        for child in row_item.children() {
+            if child.id() == span.id()
+                || span.ancestors().any(|ancestor| ancestor.id() == child.id())
+            {
+                break;
+            }
+            if let Some(text) = child.value().as_text() {
+                if let Some(found) = ISBN_10.find(text) {
+                    isbn = Some(found.as_str().to_string());
                 }
             }
         }
 
-        None
+        isbn
     }
 
-    // Legacy way to get the ISBN, doesn't seem to work in 2024
-    fn find_isbn_10_v1(&self, fragment: &Html) -> Option<String> {
-        let selector = Selector::parse(r#"span[itemprop="isbn"]"#).ok()?;
-        let span = fragment.select(&selector).next()?;
-        let div = span.parent()?.parent()?;
+    fn find_isbn_13(&self) -> Option<String> {
+        if let Some(span) = self.document.select(&ISBN).next() {
+            return Some(span.text().collect());
+        }
 
-        let content = div.first_child()?.value().as_text()?;
-        Some(content.trim().to_string())
+        self.book_data
+            .iter()
+            .filter_map(|book| book.isbn.clone())
+            .find(|isbn| is_isbn_13(isbn))
     }
 
-    fn find_isbn_13(&self, fragment: &Html) -> Option<String> {
-        let selector = Selector::parse(r#"span[itemprop="isbn"]"#).ok()?;
-        let span = fragment.select(&selector).next()?;
-        Some(span.text().collect())
+    fn find_title(&self) -> Option<String> {
+        let span = self.document.select(&TITLE).next()?;
+        Some(span.text().collect::<String>().trim().to_string())
     }
 
-    fn find_title(&self, fragment: &Html) -> Option<String> {
-        let selector =
-            Selector::parse(r#"h1[data-testid="bookTitle"], h1[id="bookTitle"]"#).ok()?;
-        let span = fragment.select(&selector).next()?;
-        Some(span.text().collect::<String>().trim().to_string())
+    // New layout: `<h3 data-testid="bookSeries"><a>The Broken Earth #1</a></h3>`.
+    // Legacy layout: `<h2 id="bookSeries"><a>(The Broken Earth #1)</a></h2>`.
+    fn find_series(&self) -> Option<Series> {
+        self.document
+            .select(&SERIES)
+            .find_map(|element| parse_series(&element.text().collect::<String>()))
     }
 
-    fn find_author(&self, fragment: &Html) -> Option<String> {
-        let selector =
-            Selector::parse(r#"div[class="ContributorLinksList"] span[data-testid="name"], a[class="authorName"] span[itemprop="name"]"#)
-                .ok()?;
-        let span = fragment.select(&selector).next()?;
+    fn find_author(&self) -> Option<String> {
+        let span = self.document.select(&AUTHOR).next()?;
 
         let raw_author: String = span.text().collect();
-        let re = Regex::new(r"\s+").unwrap();
-        let author = re.replace_all(raw_author.as_str(), " ");
+        let author = WHITESPACE.replace_all(raw_author.as_str(), " ");
 
         Some(author.to_string())
     }
+
+    fn find_cover_url(&self) -> Option<String> {
+        if let Some(img) = self.document.select(&COVER).next() {
+            if let Some(src) = img.value().attr("src") {
+                return Some(src.trim().to_string());
+            }
+        }
+
+        // Fall back to the OpenGraph image, which is present in most layouts.
+        let meta = self.document.select(&OPEN_GRAPH_IMAGE).next()?;
+        Some(meta.value().attr("content")?.trim().to_string())
+    }
+
+    // The structured data has the number, in any language.
+    // New layout: `<p data-testid="pagesFormat">328 pages, Paperback</p>`.
+    // Legacy layout: `<span itemprop="numberOfPages">703 pages</span>`.
+    fn find_pages(&self) -> Option<u32> {
+        if let Some(pages) = self
+            .book_data
+            .iter()
+            .find_map(|book| book.number_of_pages.filter(|pages| *pages > 0))
+        {
+            return Some(pages);
+        }
+
+        self.document
+            .select(&PAGES)
+            .find_map(|element| parse_pages(&element.text().collect::<String>()))
+    }
+
+    // The structured data has it, in the page's language. Otherwise, the
+    // legacy layout has `<span itemprop="bookFormat">Paperback</span>`, and
+    // the new one `<p data-testid="pagesFormat">328 pages, Paperback</p>`,
+    // or only the format when the edition has no pages.
+    fn find_format(&self) -> Option<String> {
+        let non_empty = |format: &str| {
+            let format = format.trim();
+            (!format.is_empty()).then(|| format.to_string())
+        };
+        if let Some(format) = self
+            .book_data
+            .iter()
+            .find_map(|book| book.book_format.as_deref().and_then(non_empty))
+        {
+            return Some(format);
+        }
+
+        if let Some(format) = self
+            .document
+            .select(&LEGACY_FORMAT)
+            .find_map(|element| non_empty(&element.text().collect::<String>()))
+        {
+            return Some(format);
+        }
+
+        self.document.select(&PAGES_FORMAT).find_map(|element| {
+            let text: String = element.text().collect();
+            match text.rsplit_once(',') {
+                Some((_, format)) => non_empty(format),
+                None if parse_pages(&text).is_none() => non_empty(&text),
+                None => None,
+            }
+        })
+    }
+
+    // The structured data has the language, in the page's language, and so
+    // does the legacy layout: `<div itemprop="inLanguage">English</div>`.
+    fn find_language(&self) -> Option<String> {
+        let non_empty = |language: &str| {
+            let language = language.trim();
+            (!language.is_empty()).then(|| language.to_string())
+        };
+        if let Some(language) = self
+            .book_data
+            .iter()
+            .find_map(|book| book.in_language.as_deref().and_then(non_empty))
+        {
+            return Some(language);
+        }
+
+        self.document
+            .select(&LANGUAGE)
+            .find_map(|element| non_empty(&element.text().collect::<String>()))
+    }
+
+    // New layout: the edition detail naming its publisher, e.g.
+    // "July 1, 1950 by New American Library".
+    // Legacy layout: the `#details` row naming it, without the first
+    // publication date in its `nobr`.
+    // Their labels aren't read: pages in other languages have the publisher
+    // in their `__NEXT_DATA__`, see `find_book_details`.
+    fn find_publisher(&self) -> Option<String> {
+        if let Some(publisher) = self
+            .document
+            .select(&EDITION_DETAILS)
+            .find_map(|value| parse_publisher(&value.text().collect::<String>()))
+        {
+            return Some(publisher);
+        }
+
+        self.document.select(&LEGACY_DETAILS_ROW).find_map(|row| {
+            let text: String = row
+                .children()
+                .filter_map(|child| child.value().as_text())
+                .map(|text| text.to_string())
+                .collect();
+            parse_publisher(&text)
+        })
+    }
+
+    // Returns the edition details of the book of the page, see
+    // `find_next_data_book`.
+    fn find_book_details(&self) -> Option<BookDetails> {
+        let (book, _) = self.next_data_book.as_ref()?;
+        serde_json::from_value(book["details"].clone()).ok()
+    }
+
+    // Returns the first series of the book of the page, which refers to it:
+    // `"bookSeries": [{"userPosition": "1", "series": {"__ref": "Series:..."}}]`.
+    fn find_next_data_series(&self) -> Option<Series> {
+        let (book, objects) = self.next_data_book.as_ref()?;
+        let book_series = &book["bookSeries"][0];
+        let reference = book_series["series"]["__ref"].as_str()?;
+        let name = objects[reference]["title"].as_str()?.trim();
+        let index = book_series["userPosition"]
+            .as_str()
+            .map(str::trim)
+            .filter(|index| !index.is_empty());
+
+        (!name.is_empty()).then(|| Series {
+            name: name.to_string(),
+            index: index.map(str::to_string),
+        })
+    }
+}
+
+// Returns the book of the page from its `__NEXT_DATA__`, which also holds
+// other books, e.g. the recommended ones, with the objects they refer to by
+// `__ref`. The book is the one with the ID of the page, or of `page_url`.
+fn find_next_data_book(
+    fragment: &Html,
+    page_url: &str,
+) -> Option<(serde_json::Value, serde_json::Value)> {
+    let script_tag = fragment.select(&NEXT_DATA).next()?;
+    let data: serde_json::Value = serde_json::from_str(&script_tag.inner_html()).ok()?;
+
+    let id: String = data["query"]["book_id"]
+        .as_str()
+        .unwrap_or_default()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    let id: u64 = match id.is_empty() {
+        true => book_id(page_url)?.parse().ok()?,
+        false => id.parse().ok()?,
+    };
+    let objects = &data["props"]["pageProps"]["apolloState"];
+    let book = objects
+        .as_object()?
+        .values()
+        .find(|book| book["__typename"] == "Book" && book["legacyId"].as_u64() == Some(id))?;
+    Some((book.clone(), objects.clone()))
+}
+
+#[derive(Debug, PartialEq)]
+struct Series {
+    name: String,
+    index: Option<String>,
+}
+
+// Reads a series as Goodreads writes it, with or without parentheses and a
+// comma, e.g. "(The Broken Earth, #1)", "The Broken Earth #1",
+// "(Discworld, #5.5)" or "(The Lord of the Rings, #1-3)".
+fn parse_series(text: &str) -> Option<Series> {
+    let text = WHITESPACE.replace_all(text.trim(), " ");
+    let text = text
+        .strip_prefix('(')
+        .and_then(|text| text.strip_suffix(')'))
+        .unwrap_or(&text)
+        .trim();
+
+    let (name, index) = match SERIES_INDEX.captures(text) {
+        Some(captures) => (
+            captures.get(1).map_or("", |name| name.as_str().trim()),
+            Some(captures[2].replace(' ', "")),
+        ),
+        None => (text, None),
+    };
+    (!name.is_empty()).then(|| Series {
+        name: name.to_string(),
+        index,
+    })
+}
+
+// Returns the title without its series suffix, e.g. "The Fifth Season" for
+// "The Fifth Season (The Broken Earth, #1)", and the series. Only suffixes
+// with a position are series: "(German Edition)" isn't one.
+fn split_series(title: &str) -> (&str, Option<Series>) {
+    match SERIES_SUFFIX.captures(title) {
+        Some(captures) if captures.get(0).unwrap().start() > 0 => (
+            &title[..captures.get(0).unwrap().start()],
+            parse_series(&captures[1]),
+        ),
+        _ => (title, None),
+    }
+}
+
+// Leniently reads a page count, in any language: the first number followed
+// by a word, e.g. "320 pages, Paperback", "1,024 pages" or "1.024 Seiten".
+fn parse_pages(text: &str) -> Option<u32> {
+    let count = PAGE_COUNT
+        .captures(text)?
+        .get(1)?
+        .as_str()
+        .replace([',', '.'], "");
+    count.parse().ok().filter(|pages| *pages > 0)
+}
+
+// The title to search a book by: its edition and series are left out, e.g.
+// "Der Process" for "Der Process (German Edition)", or "The Fifth Season" for
+// "The Fifth Season (The Broken Earth, #1)", as they are missing from other
+// sources.
+pub fn search_title(title: &str) -> String {
+    let mut title = title.trim().to_string();
+    loop {
+        let (without_series, series) = split_series(&title);
+        if series.is_some() {
+            title = without_series.to_string();
+            continue;
+        }
+        match EDITION_QUALIFIER.find(&title) {
+            Some(found) if found.start() > 0 => title.truncate(found.start()),
+            _ => return title,
+        }
+    }
+}
+
+// Longer titles are cut, between words, to search LibGen by.
+const MAX_SHORT_TITLE_CHARS: usize = 60;
+
+// LibGen only finds titles containing the whole search, so a title with its
+// subtitle may find nothing, e.g. "Thinking, Fast and Slow: The
+// Groundbreaking Investigation..." instead of "Thinking, Fast and Slow".
+// Returns a shorter search: the title without bracketed qualifiers, before
+// its subtitle when at least 3 words are left, and cut to
+// `MAX_SHORT_TITLE_CHARS`. Nothing when it isn't shorter.
+pub fn short_title(title: &str) -> Option<String> {
+    let without_brackets = BRACKETS.replace_all(title, " ");
+    let collapse = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut short = collapse(&without_brackets);
+    if let Some((before, _)) = short.split_once([':', '–', '—']) {
+        let before = collapse(before);
+        if before.split(' ').count() >= 3 {
+            short = before;
+        }
+    }
+    if short.chars().count() > MAX_SHORT_TITLE_CHARS {
+        let cut: String = short.chars().take(MAX_SHORT_TITLE_CHARS + 1).collect();
+        short = match cut.rsplit_once(' ') {
+            Some((words, _)) => words.to_string(),
+            None => cut.chars().take(MAX_SHORT_TITLE_CHARS).collect(),
+        };
+    }
+    let short = short.trim_end_matches([',', ';', '-', ' ']).to_string();
+
+    (!short.is_empty() && short != title.trim()).then_some(short)
+}
+
+// The publisher is what comes after "by", e.g. in
+// "Published May 1st 2004 by Castle Books".
+fn parse_publisher(text: &str) -> Option<String> {
+    let text = WHITESPACE.replace_all(text.trim(), " ");
+    let (_, publisher) = text.split_once(" by ")?;
+    let publisher = publisher.trim();
+    (!publisher.is_empty()).then(|| publisher.to_string())
+}
+
+#[derive(Debug, PartialEq)]
+enum PageKind {
+    Book,
+    Work,
+    Review,
+    Shelf,
+}
+
+const SUPPORTED_URLS: &str =
+    "expected a Goodreads book (/book/show/...), work (/work/...) or review (/review/show/...) URL";
+
+fn page_kind(page_url: &reqwest::Url) -> Option<PageKind> {
+    let mut segments: Vec<&str> = page_url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    // Localised pages are prefixed with the language, e.g. /en/book/show/...
+    if segments.first().is_some_and(|segment| segment.len() == 2) {
+        segments.remove(0);
+    }
+
+    match segments.as_slice() {
+        ["book", "show", _, ..] => Some(PageKind::Book),
+        ["work", _, _, ..] => Some(PageKind::Work),
+        ["review", "show", _, ..] => Some(PageKind::Review),
+        ["review", "list", _, ..] => Some(PageKind::Shelf),
+        _ => None,
+    }
+}
+
+// Query parameters Goodreads and link shorteners add to book URLs, which
+// don't change the page.
+const TRACKING_PARAMETERS: &[&str] = &["ac", "from_search", "from_srp", "qid", "rank", "ref"];
+
+// Returns the numeric ID of a Goodreads book URL, e.g. `40961427` for
+// `/en/book/show/40961427-1984` or `/book/show/40961427.Nineteen_Eighty_Four`.
+pub fn book_id(page_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(page_url.trim()).ok()?;
+    let host = url.host_str()?;
+    if host != "goodreads.com" && !host.ends_with(".goodreads.com") {
+        return None;
+    }
+    if page_kind(&url) != Some(PageKind::Book) {
+        return None;
+    }
+
+    let slug = url
+        .path_segments()?
+        .skip_while(|segment| *segment != "show")
+        .nth(1)?;
+    let id: String = slug.chars().take_while(char::is_ascii_digit).collect();
+    (!id.is_empty()).then_some(id)
+}
+
+// The page of the Goodreads book with this numeric ID.
+pub fn book_url(id: &str) -> String {
+    format!("https://www.goodreads.com/book/show/{}", id)
+}
+
+// Returns the key identifying the page of `page_url`, so different
+// spellings of the same URL share their cache entries and history records.
+// Book URLs become `https://www.goodreads.com/book/show/{id}`; other URLs
+// lose their fragment and tracking parameters. The page is still fetched
+// from the URL the user gave.
+pub fn canonical_book_url(page_url: &str) -> String {
+    if let Some(id) = book_id(page_url) {
+        return book_url(&id);
+    }
+
+    let Ok(mut url) = reqwest::Url::parse(page_url.trim()) else {
+        return page_url.trim().to_string();
+    };
+    url.set_fragment(None);
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| {
+            !TRACKING_PARAMETERS.contains(&name.as_ref()) && !name.starts_with("utm_")
+        })
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    url.to_string()
+}
+
+impl Goodreads {
+    // Returns the URL of the book page for any supported Goodreads URL.
+    // Work and review pages are fetched to find the book they point to.
+    async fn resolve_book_url(&self, page_url: &str) -> Result<String, Error> {
+        let url = reqwest::Url::parse(page_url)
+            .map_err(|_| Error::InvalidUrl(format!("{:?}: {}", page_url, SUPPORTED_URLS)))?;
+
+        match page_kind(&url) {
+            Some(PageKind::Book) => Ok(page_url.to_string()),
+            Some(PageKind::Work) | Some(PageKind::Review) => {
+                let _permit = self.limits.metadata().await;
+                let body = http_client::send(self.get(&url), &*self.clock, &self.limits)
+                    .await?
+                    .text()
+                    .await?;
+                let document = Html::parse_document(&body);
+                let book_link = find_book_link(&document)
+                    .ok_or_else(|| Error::InvalidUrl(format!("no book found on {:?}", page_url)))?;
+
+                let book_url = url
+                    .join(&book_link)
+                    .map_err(|_| Error::InvalidUrl(format!("invalid book link {:?}", book_link)))?;
+                Ok(book_url.to_string())
+            }
+            Some(PageKind::Shelf) | None => Err(Error::InvalidUrl(format!(
+                "{:?}: {}",
+                page_url, SUPPORTED_URLS
+            ))),
+        }
+    }
+}
+
+// Finds the link to the book page, in order of preference: the canonical
+// link, the review's book title, or any link to a book.
+fn find_book_link(document: &Html) -> Option<String> {
+    BOOK_LINKS.iter().find_map(|selector| {
+        let element = document.select(selector).next()?;
+        Some(element.value().attr("href")?.to_string())
+    })
+}
+
+impl Goodreads {
+    // Returns the URL of the book page for any supported Goodreads URL, and
+    // its HTML.
+    async fn fetch_book_page(&self, page_url: &str) -> Result<(String, String), Error> {
+        let book_url = self.resolve_book_url(page_url).await?;
+        let book_url = reqwest::Url::parse(&book_url)
+            .map_err(|_| Error::InvalidUrl(format!("invalid book URL {:?}", book_url)))?;
+        let _permit = self.limits.metadata().await;
+        let body = http_client::send(self.get(&book_url), &*self.clock, &self.limits)
+            .await?
+            .text()
+            .await?;
+        Ok((book_url.to_string(), body))
+    }
+
+    // Reports what is found on the book page, without looking the book up.
+    pub async fn parse_report(&self, page_url: &str) -> Result<ParseReport, Error> {
+        let (book_url, body) = self.fetch_book_page(page_url).await?;
+        Ok(parse_book_page(&Html::parse_document(&body), &book_url).1)
+    }
+}
+
+// Finds the book on its page, and reports which fields were found. The
+// language is only reported.
+pub fn parse_book_page(document: &Html, book_url: &str) -> (BookIdentification, ParseReport) {
+    let page = BookPage::new(document, book_url);
+    let details = page.find_book_details().unwrap_or_default();
+    let isbn13 = details
+        .isbn13
+        .filter(|isbn| is_isbn_13(isbn))
+        .or_else(|| page.find_isbn_13());
+    // Never report the ISBN-13 twice.
+    let isbn10 = details
+        .isbn
+        .filter(|isbn| is_isbn_10(isbn))
+        .or_else(|| page.find_isbn_10())
+        .filter(|isbn10| Some(isbn10) != isbn13.as_ref());
+    let title = page.find_title();
+    let series = page
+        .find_next_data_series()
+        .or_else(|| page.find_series())
+        .or_else(|| title.as_deref().and_then(|title| split_series(title).1));
+    let author = page.find_author();
+    let cover_url = page.find_cover_url();
+    let pages = details
+        .num_pages
+        .filter(|pages| *pages > 0)
+        .or_else(|| page.find_pages());
+    let publisher = details
+        .publisher
+        .map(|publisher| publisher.trim().to_string())
+        .filter(|publisher| !publisher.is_empty())
+        .or_else(|| page.find_publisher());
+    let format = details
+        .format
+        .map(|format| format.trim().to_string())
+        .filter(|format| !format.is_empty())
+        .or_else(|| page.find_format());
+    let language = details
+        .language
+        .and_then(|language| language.name)
+        .map(|language| language.trim().to_string())
+        .filter(|language| !language.is_empty())
+        .or_else(|| page.find_language());
+
+    let book = BookIdentification {
+        isbn10,
+        isbn13,
+        title,
+        author,
+        cover_url,
+        pages,
+        publisher,
+        series_index: series.as_ref().and_then(|series| series.index.clone()),
+        series: series.map(|series| series.name),
+        format,
+    };
+    let mut report = ParseReport::new("goodreads");
+    report.field("isbn10", &book.isbn10);
+    report.field("isbn13", &book.isbn13);
+    report.field("title", &book.title);
+    report.field("author", &book.author);
+    report.field("cover_url", &book.cover_url);
+    report.field("pages", &book.pages);
+    report.field("publisher", &book.publisher);
+    report.field("series", &book.series);
+    report.field("series_index", &book.series_index);
+    report.field("format", &book.format);
+    report.field("language", &language);
+    (book, report)
 }
 
 #[async_trait]
 impl BookIdentificationGetter for Goodreads {
-    async fn get_identification(
-        &self,
-        page_url: &str,
-    ) -> Result<BookIdentification, reqwest::Error> {
-        let body = reqwest::get(page_url).await?.text().await?;
-
-        let document = Html::parse_document(&body);
-        let isbn10 = self.find_isbn_10(&document);
-        let isbn13 = self.find_isbn_13(&document);
-        let title = self.find_title(&document);
-        let author = self.find_author(&document);
-
-        Ok(BookIdentification {
-            isbn10,
-            isbn13,
-            title,
-            author,
-        })
+    async fn get_identification(&self, page_url: &str) -> Result<BookIdentification, Error> {
+        let (book_url, body) = self.fetch_book_page(page_url).await?;
+        Ok(parse_book_page(&Html::parse_document(&body), &book_url).0)
+    }
+}
+
+fn is_isbn_13(isbn: &str) -> bool {
+    isbn.len() == 13 && isbn.bytes().all(|digit| digit.is_ascii_digit())
+}
+
+// An ISBN-10 is 9 digits followed by a check digit, which can be an X.
+fn is_isbn_10(isbn: &str) -> bool {
+    let isbn = isbn.as_bytes();
+    isbn.len() == 10
+        && isbn[..9].iter().all(u8::is_ascii_digit)
+        && (isbn[9].is_ascii_digit() || isbn[9] == b'X' || isbn[9] == b'x')
+}
+
+#[test]
+fn test_is_isbn_10() {
+    for isbn in ["0521405998", "080442957X", "080442957x"] {
+        assert!(is_isbn_10(isbn), "{}", isbn);
+    }
+    for isbn in [
+        "",
+        "ISBN",
+        "9780521405997",
+        "052140599",
+        "X521405998",
+        "05214O5998",
+    ] {
+        assert!(!is_isbn_10(isbn), "{}", isbn);
     }
 }
 
+#[test]
+fn test_book_identification_round_trip() {
+    let book_identification = BookIdentification {
+        isbn10: Some("0521405998".to_string()),
+        isbn13: Some("9780521405997".to_string()),
+        title: Some("Governing the Commons".to_string()),
+        author: Some("Elinor Ostrom".to_string()),
+        cover_url: None,
+        pages: Some(280),
+        publisher: Some("Cambridge University Press".to_string()),
+        series: None,
+        series_index: None,
+        format: None,
+    };
+
+    let json = serde_json::to_string(&book_identification).unwrap();
+    assert_eq!(book_identification, serde_json::from_str(&json).unwrap());
+}
+
 #[cfg(test)]
 mod test_find_isbn_10 {
     use super::*;
@@ -124,11 +1104,11 @@ mod test_find_isbn_10 {
                 <span class="greyText">(ISBN13: <span itemprop='isbn'>9780521405997</span>)</span>
             </div>
         </div>"#;
-        let fragment = Html::parse_fragment(&fragment);
+        let fragment = Html::parse_fragment(fragment);
 
         assert_eq!(
             Some("0521405998".to_string()),
-            Goodreads::default().find_isbn_10(&fragment)
+            BookPage::new(&fragment, "").find_isbn_10()
         )
     }
 
@@ -142,9 +1122,299 @@ mod test_find_isbn_10 {
                 <span class="greyText">(ISBN13: <span itemprop='something_random'>9780521405997</span>)</span>
             </div>
         </div>"#;
-        let fragment = Html::parse_fragment(&fragment);
+        let fragment = Html::parse_fragment(fragment);
 
-        assert_eq!(None, Goodreads::default().find_isbn_10(&fragment))
+        assert_eq!(None, BookPage::new(&fragment, "").find_isbn_10())
+    }
+
+    #[test]
+    fn test_label_first() {
+        let page = include_str!("../tests/testdata/goodreads_isbn_label_first_page.html");
+        let document = Html::parse_document(page);
+
+        assert_eq!(
+            Some("0521405998".to_string()),
+            BookPage::new(&document, "").find_isbn_10()
+        )
+    }
+
+    #[test]
+    fn test_only_isbn_13() {
+        let page = include_str!("../tests/testdata/goodreads_isbn13_only_page.html");
+        let document = Html::parse_document(page);
+
+        assert_eq!(None, BookPage::new(&document, "").find_isbn_10())
+    }
+
+    #[test]
+    fn test_not_an_isbn() {
+        let fragment = r#"
+        <div class="clearFloats">
+            <div class="infoBoxRowTitle">ISBN</div>
+            <div class="infoBoxRowItem">
+                ISBN
+                <span class="greyText">(ISBN13: <span itemprop='isbn'>9780521405997</span>)</span>
+            </div>
+        </div>"#;
+        let fragment = Html::parse_fragment(fragment);
+
+        assert_eq!(None, BookPage::new(&fragment, "").find_isbn_10())
+    }
+}
+
+#[cfg(test)]
+mod test_get_identification {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    #[tokio::test]
+    async fn test_only_isbn_13() {
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/book/show/1");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_isbn13_only_page.html"
+            ));
+        });
+
+        let got = Goodreads::default()
+            .get_identification(&mock_server.url("/book/show/1"))
+            .await
+            .unwrap();
+
+        assert_eq!(None, got.isbn10);
+        assert_eq!(Some("9780521405997".to_string()), got.isbn13);
+    }
+
+    #[tokio::test]
+    async fn test_localised_page() {
+        let mock_server = MockServer::start();
+        let mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/de/book/show/6416946-der-process")
+                .header("accept-language", "en");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_de_book_page.html"
+            ));
+        });
+
+        let got = Goodreads::default()
+            .get_identification(&mock_server.url("/de/book/show/6416946-der-process"))
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(
+            BookIdentification {
+                isbn10: Some("3866473257".to_string()),
+                isbn13: Some("9783866473256".to_string()),
+                title: Some("Der Process (German Edition)".to_string()),
+                author: Some("Franz Kafka".to_string()),
+                cover_url: Some("https://images-na.ssl-images-amazon.com/images/S/compressed.photo.goodreads.com/books/1348430458i/6416946.jpg".to_string()),
+                pages: Some(256),
+                publisher: Some("Anaconda Verlag".to_string()),
+                series: None,
+                series_index: None,
+                format: Some("Taschenbuch".to_string()),
+            },
+            got
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_series {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    // In both layouts, the series is apart from the title.
+    #[tokio::test]
+    async fn test_series_book() {
+        for (fixture, cover_url) in [
+            (
+                include_str!("../tests/testdata/goodreads_series_book_page.html"),
+                "https://images-na.ssl-images-amazon.com/images/S/compressed.photo.goodreads.com/books/1386803701i/19161852.jpg",
+            ),
+            (
+                include_str!("../tests/testdata/goodreads_series_legacy_book_page.html"),
+                "https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1386803701l/19161852.jpg",
+            ),
+        ] {
+            let mock_server = MockServer::start();
+            mock_server.mock(|when, then| {
+                when.method(GET)
+                    .path("/book/show/19161852-the-fifth-season");
+                then.status(200).body(fixture);
+            });
+
+            let got = Goodreads::default()
+                .get_identification(&mock_server.url("/book/show/19161852-the-fifth-season"))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                BookIdentification {
+                    isbn10: Some("0316229296".to_string()),
+                    isbn13: Some("9780316229296".to_string()),
+                    title: Some("The Fifth Season".to_string()),
+                    author: Some("N.K. Jemisin".to_string()),
+                    cover_url: Some(cover_url.to_string()),
+                    pages: Some(512),
+                    publisher: Some("Orbit".to_string()),
+                    series: Some("The Broken Earth".to_string()),
+                    series_index: Some("1".to_string()),
+                    format: Some("Paperback".to_string()),
+                },
+                got
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audiobook_book() {
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/book/show/21825181-the-martian");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_audiobook_book_page.html"
+            ));
+        });
+
+        let got = Goodreads::default()
+            .get_identification(&mock_server.url("/book/show/21825181-the-martian"))
+            .await
+            .unwrap();
+
+        assert_eq!(Some("9781480596962".to_string()), got.isbn13);
+        assert_eq!(Some("Andy Weir".to_string()), got.author);
+        assert_eq!(Some("Audiobook".to_string()), got.format);
+        assert_eq!(None, got.pages);
+        assert!(got.is_audio_or_box_set());
+    }
+
+    #[test]
+    fn test_find_format() {
+        for (fixture, want) in [
+            (
+                include_str!("../tests/testdata/goodreads_series_legacy_book_page.html"),
+                Some("Paperback"),
+            ),
+            (
+                r#"<p data-testid="pagesFormat">328 pages, Kindle Edition</p>"#,
+                Some("Kindle Edition"),
+            ),
+            (
+                r#"<p data-testid="pagesFormat">Audio CD</p>"#,
+                Some("Audio CD"),
+            ),
+            (r#"<p data-testid="pagesFormat">328 pages</p>"#, None),
+            (
+                include_str!("../tests/testdata/goodreads_isbn13_only_page.html"),
+                None,
+            ),
+        ] {
+            let fragment = Html::parse_document(fixture);
+            assert_eq!(
+                want.map(str::to_string),
+                BookPage::new(&fragment, "").find_format(),
+                "{}",
+                fixture
+            );
+        }
+    }
+
+    // The page's data has the series of other books too.
+    #[test]
+    fn test_find_next_data_series() {
+        let fragment = Html::parse_document(include_str!(
+            "../tests/testdata/goodreads_series_book_page.html"
+        ));
+
+        assert_eq!(
+            Some(Series {
+                name: "The Broken Earth".to_string(),
+                index: Some("1".to_string()),
+            }),
+            BookPage::new(&fragment, "").find_next_data_series()
+        );
+        let fragment = Html::parse_document(include_str!(
+            "../tests/testdata/goodreads_de_book_page.html"
+        ));
+        assert_eq!(None, BookPage::new(&fragment, "").find_next_data_series());
+        assert_eq!(None, BookPage::new(&fragment, "").find_series());
+    }
+
+    #[test]
+    fn test_parse_series() {
+        let series = |name: &str, index: Option<&str>| {
+            Some(Series {
+                name: name.to_string(),
+                index: index.map(str::to_string),
+            })
+        };
+        for (text, want) in [
+            (
+                "(The Broken Earth, #1)",
+                series("The Broken Earth", Some("1")),
+            ),
+            (
+                "(The Broken Earth #1)",
+                series("The Broken Earth", Some("1")),
+            ),
+            ("The Broken Earth #1", series("The Broken Earth", Some("1"))),
+            (
+                "\n    (The Broken Earth\n  #1)\n ",
+                series("The Broken Earth", Some("1")),
+            ),
+            ("(Discworld, #5.5)", series("Discworld", Some("5.5"))),
+            (
+                "(The Lord of the Rings, #1 - 3)",
+                series("The Lord of the Rings", Some("1-3")),
+            ),
+            ("(Dune, # 2)", series("Dune", Some("2"))),
+            ("(The Broken Earth)", series("The Broken Earth", None)),
+            ("(Catch-22 #1)", series("Catch-22", Some("1"))),
+            ("", None),
+            ("()", None),
+            ("#1", None),
+        ] {
+            assert_eq!(want, parse_series(text), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_split_series() {
+        for (title, want_title, want_series) in [
+            (
+                "The Fifth Season (The Broken Earth, #1)",
+                "The Fifth Season",
+                Some(("The Broken Earth", Some("1"))),
+            ),
+            (
+                "Mort (Discworld, #4; Death, #1)",
+                "Mort",
+                Some(("Discworld, #4; Death", Some("1"))),
+            ),
+            (
+                "Der Process (German Edition)",
+                "Der Process (German Edition)",
+                None,
+            ),
+            ("(Dune, #1)", "(Dune, #1)", None),
+            ("1984", "1984", None),
+        ] {
+            let (title_got, series_got) = split_series(title);
+            assert_eq!(want_title, title_got, "{:?}", title);
+            assert_eq!(
+                want_series.map(|(name, index)| Series {
+                    name: name.to_string(),
+                    index: index.map(str::to_string),
+                }),
+                series_got,
+                "{:?}",
+                title
+            );
+        }
     }
 }
 
@@ -162,11 +1432,11 @@ mod test_find_isbn_13 {
                 <span class="greyText">(ISBN13: <span itemprop='isbn'>9780521405997</span>)</span>
             </div>
         </div>"#;
-        let fragment = Html::parse_fragment(&fragment);
+        let fragment = Html::parse_fragment(fragment);
 
         assert_eq!(
             Some("9780521405997".to_string()),
-            Goodreads::default().find_isbn_13(&fragment)
+            BookPage::new(&fragment, "").find_isbn_13()
         )
     }
 
@@ -180,9 +1450,9 @@ mod test_find_isbn_13 {
                 <span class="greyText">(ISBN13: <span itemprop='something_random'>9780521405997</span>)</span>
             </div>
         </div>"#;
-        let fragment = Html::parse_fragment(&fragment);
+        let fragment = Html::parse_fragment(fragment);
 
-        assert_eq!(None, Goodreads::default().find_isbn_13(&fragment))
+        assert_eq!(None, BookPage::new(&fragment, "").find_isbn_13())
     }
 }
 
@@ -198,7 +1468,7 @@ mod test_find_title {
 
         assert_eq!(
             Some("1984".to_string()),
-            Goodreads::default().find_title(&fragment)
+            BookPage::new(&fragment, "").find_title()
         )
     }
 
@@ -210,7 +1480,7 @@ mod test_find_title {
 
         assert_eq!(
             Some("The Origin of Species".to_string()),
-            Goodreads::default().find_title(&fragment)
+            BookPage::new(&fragment, "").find_title()
         )
     }
 
@@ -223,9 +1493,9 @@ mod test_find_title {
                 </h1>
             </div>
         </div>"#;
-        let fragment = Html::parse_fragment(&fragment);
+        let fragment = Html::parse_fragment(fragment);
 
-        assert_eq!(None, Goodreads::default().find_title(&fragment))
+        assert_eq!(None, BookPage::new(&fragment, "").find_title())
     }
 }
 
@@ -241,7 +1511,7 @@ mod test_find_author {
 
         assert_eq!(
             Some("George Orwell".to_string()),
-            Goodreads::default().find_author(&fragment)
+            BookPage::new(&fragment, "").find_author()
         )
     }
 
@@ -253,7 +1523,7 @@ mod test_find_author {
 
         assert_eq!(
             Some("Charles Darwin".to_string()),
-            Goodreads::default().find_author(&fragment)
+            BookPage::new(&fragment, "").find_author()
         )
     }
 
@@ -269,8 +1539,745 @@ mod test_find_author {
             </div>
         </h3>
     </div>"#;
-        let fragment = Html::parse_fragment(&fragment);
+        let fragment = Html::parse_fragment(fragment);
+
+        assert_eq!(None, BookPage::new(&fragment, "").find_author())
+    }
+}
+
+#[cfg(test)]
+mod test_find_cover_url {
+    use super::*;
+
+    #[test]
+    fn test_ok() {
+        let fragment = Html::parse_fragment(include_str!(
+            "../tests/testdata/goodreads_1984_book_page.html"
+        ));
+
+        assert_eq!(
+            Some("https://images-na.ssl-images-amazon.com/images/S/compressed.photo.goodreads.com/books/1348990566i/5470.jpg".to_string()),
+            BookPage::new(&fragment, "").find_cover_url()
+        )
+    }
+
+    #[test]
+    fn test_ok_alternative_layout() {
+        let fragment = Html::parse_fragment(include_str!(
+            "../tests/testdata/goodreads_origin_of_species_curl_page.html"
+        ));
+
+        assert_eq!(
+            Some("https://i.gr-assets.com/images/S/compressed.photo.goodreads.com/books/1298417570l/22463.jpg".to_string()),
+            BookPage::new(&fragment, "").find_cover_url()
+        )
+    }
+
+    #[test]
+    fn test_og_image_fallback() {
+        let fragment = r#"
+        <head>
+            <meta content='https://example.com/cover.jpg' property='og:image'>
+        </head>"#;
+        let fragment = Html::parse_fragment(fragment);
+
+        assert_eq!(
+            Some("https://example.com/cover.jpg".to_string()),
+            BookPage::new(&fragment, "").find_cover_url()
+        )
+    }
+
+    #[test]
+    fn test_missing() {
+        let fragment = r#"
+        <div class="BookCover">
+            <div class="BookCover__image"></div>
+        </div>"#;
+        let fragment = Html::parse_fragment(fragment);
+
+        assert_eq!(None, BookPage::new(&fragment, "").find_cover_url())
+    }
+}
+
+#[test]
+fn test_parse_pages() {
+    for (text, want) in [
+        ("320 pages, Paperback", Some(320)),
+        ("703 pages", Some(703)),
+        ("Kindle Edition, 1,024 pages", Some(1024)),
+        ("1 page", Some(1)),
+        ("256 Seiten, Taschenbuch", Some(256)),
+        ("1.024 Seiten", Some(1024)),
+        ("412 pages, Broché", Some(412)),
+        ("Paperback", None),
+        ("0 pages", None),
+        ("99999999999 pages", None),
+        ("", None),
+    ] {
+        assert_eq!(want, parse_pages(text), "{:?}", text);
+    }
+}
+
+#[test]
+fn test_search_title() {
+    for (title, want) in [
+        ("Der Process (German Edition)", "Der Process"),
+        ("Le Petit Prince (Édition Collector)", "Le Petit Prince"),
+        (
+            "Cien años de soledad (Edición conmemorativa)",
+            "Cien años de soledad",
+        ),
+        ("Il nome della rosa (Nuova edizione)", "Il nome della rosa"),
+        ("Faust (Reclams Ausgabe) (Kindle Edition)", "Faust"),
+        ("Dune (Dune, #1)", "Dune"),
+        (
+            "The Fifth Season (The Broken Earth, #1)",
+            "The Fifth Season",
+        ),
+        ("Der Process (Kafka, #2) (German Edition)", "Der Process"),
+        ("Dune (Dune)", "Dune (Dune)"),
+        ("1984", "1984"),
+        ("(Special Edition)", "(Special Edition)"),
+    ] {
+        assert_eq!(want, search_title(title), "{:?}", title);
+    }
+}
+
+#[test]
+fn test_short_title() {
+    for (title, want) in [
+        (
+            "Thinking, Fast and Slow: The Groundbreaking Investigation",
+            Some("Thinking, Fast and Slow"),
+        ),
+        (
+            "Governing the Commons – The Evolution of Institutions",
+            Some("Governing the Commons"),
+        ),
+        (
+            "The Origin of Species [Illustrated]",
+            Some("The Origin of Species"),
+        ),
+        (
+            "The Selfish Gene (Popular Science): 40th Anniversary",
+            Some("The Selfish Gene"),
+        ),
+        (
+            "  A   Brief History   of Time ",
+            Some("A Brief History of Time"),
+        ),
+        (
+            "The Extraordinarily Long Title of a Book That Never Seems to End at All",
+            Some("The Extraordinarily Long Title of a Book That Never Seems to"),
+        ),
+        // Too few words would be left without the subtitle.
+        ("Sapiens: A Brief History of Humankind", None),
+        ("Dune – Part One", None),
+        ("Thinking, Fast and Slow", None),
+        ("(Untitled)", None),
+        ("", None),
+    ] {
+        assert_eq!(want.map(str::to_string), short_title(title), "{:?}", title);
+    }
+}
+
+#[test]
+fn test_parse_publisher() {
+    for (text, want) in [
+        (
+            "July 1, 1950 by New American\n    Library",
+            Some("New American Library"),
+        ),
+        (
+            "Published\n May 1st 2004\n by Castle Books\n",
+            Some("Castle Books"),
+        ),
+        ("Published 2004", None),
+        ("Published 2004 by ", None),
+    ] {
+        assert_eq!(
+            want.map(str::to_string),
+            parse_publisher(text),
+            "{:?}",
+            text
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_find_pages_and_publisher {
+    use super::*;
+
+    #[test]
+    fn test_ok() {
+        let fragment = Html::parse_fragment(include_str!(
+            "../tests/testdata/goodreads_1984_book_page.html"
+        ));
+
+        assert_eq!(Some(328), BookPage::new(&fragment, "").find_pages());
+        assert_eq!(
+            Some("New American Library".to_string()),
+            BookPage::new(&fragment, "").find_publisher()
+        );
+    }
+
+    #[test]
+    fn test_ok_alternative_layout() {
+        let fragment = Html::parse_fragment(include_str!(
+            "../tests/testdata/goodreads_origin_of_species_curl_page.html"
+        ));
 
-        assert_eq!(None, Goodreads::default().find_author(&fragment))
+        assert_eq!(Some(703), BookPage::new(&fragment, "").find_pages());
+        assert_eq!(
+            Some("Castle Books".to_string()),
+            BookPage::new(&fragment, "").find_publisher()
+        );
+    }
+
+    // The page count is read without the structured data too. The
+    // publisher only is in the page's data, in German.
+    #[test]
+    fn test_ok_localised() {
+        let fragment = Html::parse_fragment(
+            &include_str!("../tests/testdata/goodreads_de_book_page.html")
+                .replace(r#""numberOfPages":256,"#, ""),
+        );
+
+        assert_eq!(Some(256), BookPage::new(&fragment, "").find_pages());
+        assert_eq!(None, BookPage::new(&fragment, "").find_publisher());
+        let details = BookPage::new(&fragment, "").find_book_details().unwrap();
+        assert_eq!(Some("Anaconda Verlag".to_string()), details.publisher);
+        assert_eq!(Some(256), details.num_pages);
+        assert_eq!(
+            Some("9783866473256".to_string()),
+            BookPage::new(&fragment, "").find_isbn_13()
+        );
+    }
+
+    #[test]
+    fn test_missing() {
+        let fragment = r#"
+        <div class="FeaturedDetails">
+            <p data-testid="pagesFormat">Kindle Edition</p>
+        </div>
+        <div class="DescListItem">
+            <dt>Published</dt>
+            <dd>January 1, 2001</dd>
+        </div>"#;
+        let fragment = Html::parse_fragment(fragment);
+
+        assert_eq!(None, BookPage::new(&fragment, "").find_pages());
+        assert_eq!(None, BookPage::new(&fragment, "").find_publisher());
+
+        let fragment = Html::parse_fragment(include_str!(
+            "../tests/testdata/goodreads_isbn13_only_page.html"
+        ));
+        assert_eq!(None, BookPage::new(&fragment, "").find_pages());
+        assert_eq!(None, BookPage::new(&fragment, "").find_publisher());
+    }
+}
+
+#[test]
+fn test_page_kind() {
+    for (url, want) in [
+        (
+            "https://www.goodreads.com/book/show/40961427-1984",
+            Some(PageKind::Book),
+        ),
+        (
+            "https://www.goodreads.com/en/book/show/40961427-1984?ref=share",
+            Some(PageKind::Book),
+        ),
+        (
+            "https://m.goodreads.com/book/show/40961427",
+            Some(PageKind::Book),
+        ),
+        (
+            "https://www.goodreads.com/work/quotes/153313-nineteen-eighty-four",
+            Some(PageKind::Work),
+        ),
+        (
+            "https://www.goodreads.com/work/editions/153313",
+            Some(PageKind::Work),
+        ),
+        (
+            "https://www.goodreads.com/review/show/1234567890",
+            Some(PageKind::Review),
+        ),
+        (
+            "https://www.goodreads.com/review/list/42-jane?shelf=to-read",
+            Some(PageKind::Shelf),
+        ),
+        ("https://www.goodreads.com/author/show/3706", None),
+        ("https://www.goodreads.com/book/show", None),
+        ("https://www.goodreads.com/", None),
+    ] {
+        let url = reqwest::Url::parse(url).unwrap();
+        assert_eq!(want, page_kind(&url), "{}", url);
+    }
+}
+
+#[test]
+fn test_canonical_book_url() {
+    for (url, want) in [
+        (
+            "https://www.goodreads.com/book/show/40961427-1984",
+            "https://www.goodreads.com/book/show/40961427",
+        ),
+        (
+            "https://www.goodreads.com/book/show/40961427-1984?ac=1&from_search=true&qid=AbC12&rank=1#CommunityReviews",
+            "https://www.goodreads.com/book/show/40961427",
+        ),
+        (
+            "https://www.goodreads.com/en/book/show/40961427-1984?ref=share",
+            "https://www.goodreads.com/book/show/40961427",
+        ),
+        (
+            "https://www.goodreads.com/fr/book/show/40961427-1984/",
+            "https://www.goodreads.com/book/show/40961427",
+        ),
+        (
+            "http://goodreads.com/book/show/1048424.Governing_the_Commons",
+            "https://www.goodreads.com/book/show/1048424",
+        ),
+        (
+            "https://m.goodreads.com/book/show/1048424",
+            "https://www.goodreads.com/book/show/1048424",
+        ),
+        (
+            " https://www.goodreads.com/book/show/1048424.Governing_the_Commons?utm_source=newsletter ",
+            "https://www.goodreads.com/book/show/1048424",
+        ),
+        // Work and review pages are only cleaned up: the book they point to
+        // isn't known until they are fetched.
+        (
+            "https://www.goodreads.com/work/editions/153313?utm_medium=email#top",
+            "https://www.goodreads.com/work/editions/153313",
+        ),
+        (
+            "https://www.goodreads.com/review/list/42-jane?shelf=to-read&ref=nav_mybooks",
+            "https://www.goodreads.com/review/list/42-jane?shelf=to-read",
+        ),
+        // Other hosts, such as test servers, are left alone.
+        (
+            "http://127.0.0.1:8080/book/show/40961427-1984#reviews",
+            "http://127.0.0.1:8080/book/show/40961427-1984",
+        ),
+        ("not a url", "not a url"),
+    ] {
+        assert_eq!(want, canonical_book_url(url), "{}", url);
+    }
+
+    assert_eq!(
+        Some("40961427".to_string()),
+        book_id("https://www.goodreads.com/book/show/40961427.Nineteen_Eighty_Four")
+    );
+    assert_eq!(None, book_id("https://www.goodreads.com/book/show"));
+    assert_eq!(
+        None,
+        book_id("https://www.goodreads.com/book/show/nineteen-eighty-four")
+    );
+    assert_eq!(None, book_id("https://example.com/book/show/40961427"));
+}
+
+#[test]
+fn test_find_book_link() {
+    for (page, want) in [
+        (
+            include_str!("../tests/testdata/goodreads_1984_book_page.html"),
+            "https://www.goodreads.com/book/show/40961427-1984",
+        ),
+        (
+            include_str!("../tests/testdata/goodreads_work_quotes_page.html"),
+            "/book/show/40961427-1984",
+        ),
+        (
+            include_str!("../tests/testdata/goodreads_review_page.html"),
+            "/book/show/40961427-1984",
+        ),
+    ] {
+        let document = Html::parse_document(page);
+        assert_eq!(Some(want.to_string()), find_book_link(&document));
+    }
+
+    let document = Html::parse_document("<html><body>Nothing here</body></html>");
+    assert_eq!(None, find_book_link(&document));
+}
+
+#[cfg(test)]
+mod test_resolve_book_url {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    #[tokio::test]
+    async fn test_book_url() {
+        let url = "https://www.goodreads.com/book/show/40961427-1984";
+        assert_eq!(
+            Ok(url.to_string()),
+            Goodreads::default().resolve_book_url(url).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_work_url() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/work/quotes/153313-nineteen-eighty-four");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_work_quotes_page.html"
+            ));
+        });
+
+        let got = Goodreads::default()
+            .resolve_book_url(&mock_server.url("/work/quotes/153313-nineteen-eighty-four"))
+            .await;
+
+        endpoint_mock.assert();
+        assert_eq!(Ok(mock_server.url("/book/show/40961427-1984")), got);
+    }
+
+    #[tokio::test]
+    async fn test_review_url() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/review/show/1234567890");
+            then.status(200)
+                .body(include_str!("../tests/testdata/goodreads_review_page.html"));
+        });
+
+        let got = Goodreads::default()
+            .resolve_book_url(&mock_server.url("/review/show/1234567890"))
+            .await;
+
+        endpoint_mock.assert();
+        assert_eq!(Ok(mock_server.url("/book/show/40961427-1984")), got);
+    }
+
+    #[tokio::test]
+    async fn test_review_without_book() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/review/show/1");
+            then.status(200)
+                .body("<html><body>Review not found</body></html>");
+        });
+
+        let got = Goodreads::default()
+            .resolve_book_url(&mock_server.url("/review/show/1"))
+            .await;
+
+        endpoint_mock.assert();
+        assert!(matches!(got, Err(Error::InvalidUrl(_))));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_url() {
+        for url in [
+            "https://www.goodreads.com/author/show/3706.George_Orwell",
+            "https://www.goodreads.com/",
+            "not a url",
+        ] {
+            let got = Goodreads::default().resolve_book_url(url).await;
+            match got {
+                Err(Error::InvalidUrl(message)) => assert!(
+                    message.contains("/book/show/") && message.contains("/review/show/"),
+                    "{}",
+                    message
+                ),
+                got => panic!("unexpected result {:?}", got),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_base_url() {
+        let mock_server = MockServer::start();
+        let work_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/work/quotes/153313")
+                .query_param("ref", "share");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_work_quotes_page.html"
+            ));
+        });
+        let book_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book/show/40961427-1984");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_1984_book_page.html"
+            ));
+        });
+
+        let goodreads = Goodreads {
+            base_url: Some(mock_server.base_url()),
+            ..Default::default()
+        };
+        let got = goodreads
+            .get_identification("https://www.goodreads.com/work/quotes/153313?ref=share")
+            .await
+            .expect("Should fetch the pages from the base URL");
+
+        work_mock.assert();
+        book_mock.assert();
+        assert_eq!(Some("1984".to_string()), got.title);
+    }
+
+    #[tokio::test]
+    async fn test_get_identification_of_work_url() {
+        let mock_server = MockServer::start();
+        let work_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/work/quotes/153313");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_work_quotes_page.html"
+            ));
+        });
+        let book_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book/show/40961427-1984");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_1984_book_page.html"
+            ));
+        });
+
+        let got = Goodreads::default()
+            .get_identification(&mock_server.url("/work/quotes/153313"))
+            .await
+            .expect("Should resolve the book page");
+
+        work_mock.assert();
+        book_mock.assert();
+        assert_eq!(Some("1984".to_string()), got.title);
+    }
+}
+
+// Shelves are paginated. This bounds how many pages are fetched.
+const MAX_SHELF_PAGES: u32 = 100;
+
+#[async_trait]
+impl ShelfGetter for Goodreads {
+    async fn get_shelf(&self, shelf_url: &str) -> Result<Vec<ShelfEntry>, Error> {
+        let invalid_url = || {
+            Error::InvalidUrl(format!(
+                "{:?}: expected a Goodreads shelf URL (/review/list/...)",
+                shelf_url
+            ))
+        };
+        let url = reqwest::Url::parse(shelf_url).map_err(|_| invalid_url())?;
+        if page_kind(&url) != Some(PageKind::Shelf) {
+            return Err(invalid_url());
+        }
+
+        let mut entries = vec![];
+        for page in 1..=MAX_SHELF_PAGES {
+            let _permit = self.limits.metadata().await;
+            let body = http_client::send(
+                self.get(&shelf_page_url(&url, page)),
+                &*self.clock,
+                &self.limits,
+            )
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+            let document = Html::parse_document(&body);
+
+            let page_entries = find_shelf_entries(&document, &url);
+            if page_entries.is_empty() {
+                break;
+            }
+            entries.extend(page_entries);
+            if !has_next_page(&document) {
+                break;
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+// Returns the shelf URL for the given page, keeping the other parameters
+// (shelf, sort order...).
+fn shelf_page_url(shelf_url: &reqwest::Url, page: u32) -> reqwest::Url {
+    let mut url = shelf_url.clone();
+    let params: Vec<(String, String)> = shelf_url
+        .query_pairs()
+        .filter(|(name, _)| name != "page")
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(params)
+        .append_pair("page", &page.to_string());
+    url
+}
+
+#[test]
+fn test_shelf_page_url() {
+    let url =
+        reqwest::Url::parse("https://www.goodreads.com/review/list/42-jane?shelf=to-read&page=3")
+            .unwrap();
+    assert_eq!(
+        "https://www.goodreads.com/review/list/42-jane?shelf=to-read&page=2",
+        shelf_page_url(&url, 2).as_str()
+    );
+}
+
+fn find_shelf_entries(document: &Html, shelf_url: &reqwest::Url) -> Vec<ShelfEntry> {
+    document
+        .select(&SHELF_ROW)
+        .filter_map(|row| {
+            let link = row.select(&SHELF_TITLE).next()?;
+            let book_url = shelf_url.join(link.value().attr("href")?).ok()?;
+            let title = link
+                .value()
+                .attr("title")
+                .map(str::to_string)
+                .unwrap_or_else(|| link.text().collect())
+                .trim()
+                .to_string();
+            let author = row
+                .select(&SHELF_AUTHOR)
+                .next()
+                .map(|author| shelf_author_name(&author.text().collect::<String>()));
+
+            Some(ShelfEntry {
+                book_url: book_url.to_string(),
+                title: Some(title).filter(|title| !title.is_empty()),
+                author,
+            })
+        })
+        .collect()
+}
+
+// Shelves list authors as "Last, First", book pages as "First Last".
+fn shelf_author_name(name: &str) -> String {
+    match name.trim().split_once(", ") {
+        Some((last, first)) => format!("{} {}", first.trim(), last.trim()),
+        None => name.trim().to_string(),
+    }
+}
+
+#[test]
+fn test_shelf_author_name() {
+    assert_eq!("Elinor Ostrom", shelf_author_name(" Ostrom, Elinor "));
+    assert_eq!("Plato", shelf_author_name("Plato"));
+}
+
+fn has_next_page(document: &Html) -> bool {
+    document.select(&NEXT_PAGE).next().is_some()
+}
+
+#[cfg(test)]
+mod test_get_shelf {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    #[tokio::test]
+    async fn test_multiple_pages() {
+        let mock_server = MockServer::start();
+        let page_1_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/review/list/42-jane")
+                .query_param("shelf", "to-read")
+                .query_param("page", "1");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_shelf_page_1.html"
+            ));
+        });
+        let page_2_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/review/list/42-jane")
+                .query_param("shelf", "to-read")
+                .query_param("page", "2");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_shelf_page_2.html"
+            ));
+        });
+
+        let got = Goodreads::default()
+            .get_shelf(&mock_server.url("/review/list/42-jane?shelf=to-read"))
+            .await
+            .expect("Should list the shelf");
+
+        page_1_mock.assert();
+        page_2_mock.assert();
+        assert_eq!(
+            vec![
+                ShelfEntry {
+                    book_url: mock_server.url("/book/show/1048424.Governing_the_Commons"),
+                    title: Some(
+                        "Governing the Commons: The Evolution of Institutions for Collective Action"
+                            .to_string()
+                    ),
+                    author: Some("Elinor Ostrom".to_string()),
+                },
+                ShelfEntry {
+                    book_url: mock_server.url("/book/show/40961427-1984"),
+                    title: Some("1984".to_string()),
+                    author: Some("George Orwell".to_string()),
+                },
+                ShelfEntry {
+                    book_url: mock_server.url("/book/show/22463.The_Origin_of_Species"),
+                    title: Some("On the Origin of Species".to_string()),
+                    author: Some("Charles Darwin".to_string()),
+                },
+            ],
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_empty_shelf() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/review/list/42-jane");
+            then.status(200)
+                .body("<html><body>This shelf is empty.</body></html>");
+        });
+
+        let got = Goodreads::default()
+            .get_shelf(&mock_server.url("/review/list/42-jane?shelf=read"))
+            .await;
+
+        endpoint_mock.assert_hits(1);
+        assert_eq!(Ok(vec![]), got);
+    }
+
+    #[tokio::test]
+    async fn test_not_a_shelf() {
+        for url in [
+            "https://www.goodreads.com/book/show/40961427-1984",
+            "not a url",
+        ] {
+            let got = Goodreads::default().get_shelf(url).await;
+            assert!(matches!(got, Err(Error::InvalidUrl(_))), "{}", url);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Error {
+    HttpError(HttpFailure),
+    InvalidUrl(String),
+    // Goodreads asked us to slow down.
+    RateLimited(RateLimit),
+    // Goodreads kept failing, so it isn't called for a while.
+    UpstreamUnavailable(String),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::HttpError(HttpFailure::from(err))
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::Http(err) => Self::from(err),
+            SendError::RateLimited(limit) => Self::RateLimited(limit),
+            SendError::CircuitOpen(open) => Self::UpstreamUnavailable(open.to_string()),
+            SendError::Replay(message) => Self::UpstreamUnavailable(message),
+        }
     }
 }
@@ -0,0 +1,518 @@
+//! Module history remembers the books the server delivered, or failed to,
+//! and aggregates them for `GET /api/stats`.
+//!
+//! Downloads are recorded with the URL they were asked for with, and the
+//! book it refers to, see `book_ref`, so that `GET /api/history/book/{book}`
+//! lists the attempts at a book however its URL was spelled.
+//!
+//! There is no database: the most recent `MAX_RECORDS` downloads are kept in
+//! memory, and lost when the server stops. Statistics are folded over them
+//! in a single pass, newest first, stopping at the start of the time range.
+
+use crate::{book_ref::BookRef, clock::SharedClock, extension::Extension};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use utoipa::ToSchema;
+
+// Older downloads are forgotten.
+pub const MAX_RECORDS: usize = 10_000;
+// How many titles the statistics list.
+const TOP_TITLES: usize = 10;
+
+// What a download was asked for.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BookRequest {
+    // As it was sent, for display. Unknown for downloads by MD5.
+    pub url: Option<String>,
+    // Unknown for pages that don't name a book, e.g. Goodreads works.
+    pub book: Option<BookRef>,
+}
+
+impl BookRequest {
+    pub fn from_url(url: &str) -> Self {
+        Self {
+            url: Some(url.to_string()),
+            book: BookRef::from_url(url),
+        }
+    }
+}
+
+// A download, served or emailed, or an error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadRecord {
+    pub at: SystemTime,
+    pub request: BookRequest,
+    // Unknown when the book wasn't found.
+    pub title: Option<String>,
+    pub extension: Extension,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Delivered {
+        bytes: u64,
+        // Whether it was served from the library instead of downloaded.
+        from_library: bool,
+        conversion_duration: Option<Duration>,
+    },
+    // The kind of `web::Error`, e.g. "not found".
+    Failed(String),
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct Stats {
+    pub downloads: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    // How many downloads failed, by kind of error.
+    pub failures: BTreeMap<String, u64>,
+    // Most requested first.
+    pub formats: Vec<Count>,
+    pub bytes_served: u64,
+    // The share of delivered books that came from the library, if any was.
+    pub cache_hit_rate: Option<f64>,
+    pub average_conversion_ms: Option<u64>,
+    // The 10 most downloaded titles, most downloaded first.
+    pub top_titles: Vec<Count>,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct Count {
+    pub name: String,
+    pub downloads: u64,
+}
+
+// A download of a book, as listed by `GET /api/history/book/{book}`.
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct Attempt {
+    // In seconds since the Unix epoch.
+    pub at: u64,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub format: String,
+    pub delivered: bool,
+    // The kind of error, if it failed.
+    pub error: Option<String>,
+    pub bytes: Option<u64>,
+    pub from_library: Option<bool>,
+}
+
+impl From<&DownloadRecord> for Attempt {
+    fn from(record: &DownloadRecord) -> Self {
+        let mut attempt = Self {
+            at: record
+                .at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            url: record.request.url.clone(),
+            title: record.title.clone(),
+            format: record.extension.to_string(),
+            delivered: false,
+            error: None,
+            bytes: None,
+            from_library: None,
+        };
+        match &record.outcome {
+            Outcome::Delivered {
+                bytes,
+                from_library,
+                ..
+            } => {
+                attempt.delivered = true;
+                attempt.bytes = Some(*bytes);
+                attempt.from_library = Some(*from_library);
+            }
+            Outcome::Failed(kind) => attempt.error = Some(kind.clone()),
+        }
+        attempt
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    records: Mutex<VecDeque<DownloadRecord>>,
+    clock: SharedClock,
+}
+
+impl History {
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    pub fn record(
+        &self,
+        request: &BookRequest,
+        title: Option<String>,
+        extension: Extension,
+        outcome: Outcome,
+    ) {
+        let record = DownloadRecord {
+            at: self.clock.now(),
+            request: request.clone(),
+            title,
+            extension,
+            outcome,
+        };
+        let mut records = self.records.lock().unwrap();
+        if records.len() == MAX_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    // The downloads of `book` still remembered, newest first.
+    pub fn attempts(&self, book: &BookRef) -> Vec<Attempt> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| record.request.book.as_ref() == Some(book))
+            .map(Attempt::from)
+            .collect()
+    }
+
+    // Aggregates the downloads of the last `since`, or all of them.
+    pub fn stats(&self, since: Option<Duration>) -> Stats {
+        let start = since.and_then(|since| self.clock.now().checked_sub(since));
+        let records = self.records.lock().unwrap();
+        let mut stats = Stats::default();
+        let mut formats: HashMap<String, u64> = HashMap::new();
+        let mut titles: HashMap<&str, u64> = HashMap::new();
+        let (mut from_library, mut conversions, mut conversion_time) = (0, 0, Duration::ZERO);
+
+        for record in records
+            .iter()
+            .rev()
+            .take_while(|record| start.is_none_or(|start| record.at >= start))
+        {
+            stats.downloads += 1;
+            *formats.entry(record.extension.to_string()).or_default() += 1;
+            match &record.outcome {
+                Outcome::Delivered {
+                    bytes,
+                    from_library: hit,
+                    conversion_duration,
+                } => {
+                    stats.succeeded += 1;
+                    stats.bytes_served += bytes;
+                    from_library += u64::from(*hit);
+                    if let Some(duration) = conversion_duration {
+                        conversions += 1;
+                        conversion_time += *duration;
+                    }
+                    if let Some(title) = &record.title {
+                        *titles.entry(title).or_default() += 1;
+                    }
+                }
+                Outcome::Failed(kind) => {
+                    stats.failed += 1;
+                    *stats.failures.entry(kind.clone()).or_default() += 1;
+                }
+            }
+        }
+
+        stats.formats = most_first(formats.into_iter().collect());
+        stats.top_titles = most_first(
+            titles
+                .into_iter()
+                .map(|(title, downloads)| (title.to_string(), downloads))
+                .collect(),
+        );
+        stats.top_titles.truncate(TOP_TITLES);
+        stats.cache_hit_rate =
+            (stats.succeeded > 0).then(|| from_library as f64 / stats.succeeded as f64);
+        stats.average_conversion_ms =
+            (conversions > 0).then(|| (conversion_time / conversions).as_millis() as u64);
+        stats
+    }
+}
+
+// Sorts by count, then by name for a stable order.
+fn most_first(counts: Vec<(String, u64)>) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts
+        .into_iter()
+        .map(|(name, downloads)| Count { name, downloads })
+        .collect();
+    counts.sort_by(|a, b| b.downloads.cmp(&a.downloads).then(a.name.cmp(&b.name)));
+    counts
+}
+
+// Parses a time range such as "90s", "30m", "12h", "7d" or "2w".
+pub fn parse_since(since: &str) -> Result<Duration, String> {
+    let since = since.trim();
+    let invalid = || {
+        format!(
+            "invalid time range {:?}, expected a number and a unit among s, m, h, d and w, e.g. \"7d\"",
+            since
+        )
+    };
+    let split = since
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (count, unit) = since.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+#[test]
+fn test_parse_since() {
+    for (since, want) in [
+        ("90s", 90),
+        ("30m", 30 * 60),
+        ("12h", 12 * 60 * 60),
+        (" 7d ", 7 * 24 * 60 * 60),
+        ("2w", 14 * 24 * 60 * 60),
+        ("0d", 0),
+    ] {
+        assert_eq!(
+            Ok(Duration::from_secs(want)),
+            parse_since(since),
+            "{}",
+            since
+        );
+    }
+    for since in [
+        "",
+        "7",
+        "d",
+        "-7d",
+        "7 d",
+        "7D",
+        "7y",
+        "1.5h",
+        "99999999999999999999w",
+    ] {
+        assert!(parse_since(since).is_err(), "{:?}", since);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::Arc;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn delivered(bytes: u64, from_library: bool, conversion_ms: Option<u64>) -> Outcome {
+        Outcome::Delivered {
+            bytes,
+            from_library,
+            conversion_duration: conversion_ms.map(Duration::from_millis),
+        }
+    }
+
+    // Ten days of downloads, one day apart, the last one now.
+    fn seeded(clock: &Arc<MockClock>) -> History {
+        let history = History::with_clock(SharedClock(clock.clone()));
+        let fixtures = [
+            (
+                "Dune",
+                Extension::Epub,
+                delivered(1_000, false, Some(4_000)),
+            ),
+            (
+                "Dune",
+                Extension::Mobi,
+                delivered(2_000, false, Some(6_000)),
+            ),
+            ("1984", Extension::Epub, delivered(500, true, None)),
+            (
+                "",
+                Extension::Epub,
+                Outcome::Failed("not found".to_string()),
+            ),
+            ("Dune", Extension::Epub, delivered(1_000, true, None)),
+            ("Emma", Extension::Pdf, delivered(3_000, false, None)),
+            (
+                "Emma",
+                Extension::Epub,
+                Outcome::Failed("upstream".to_string()),
+            ),
+            ("1984", Extension::Epub, delivered(500, false, Some(1_000))),
+            (
+                "",
+                Extension::Mobi,
+                Outcome::Failed("not found".to_string()),
+            ),
+            ("1984", Extension::Epub, delivered(500, true, None)),
+        ];
+        for (i, (title, extension, outcome)) in fixtures.into_iter().enumerate() {
+            if i > 0 {
+                clock.advance(DAY);
+            }
+            let title = (!title.is_empty()).then(|| title.to_string());
+            history.record(&BookRequest::default(), title, extension, outcome);
+        }
+        history
+    }
+
+    fn count(name: &str, downloads: u64) -> Count {
+        Count {
+            name: name.to_string(),
+            downloads,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH + 1_000 * DAY));
+        let history = seeded(&clock);
+
+        assert_eq!(
+            Stats {
+                downloads: 10,
+                succeeded: 7,
+                failed: 3,
+                failures: BTreeMap::from([
+                    ("not found".to_string(), 2),
+                    ("upstream".to_string(), 1)
+                ]),
+                formats: vec![count("epub", 7), count("mobi", 2), count("pdf", 1)],
+                bytes_served: 8_500,
+                cache_hit_rate: Some(3.0 / 7.0),
+                average_conversion_ms: Some(3_666),
+                top_titles: vec![count("1984", 3), count("Dune", 3), count("Emma", 1)],
+            },
+            history.stats(None)
+        );
+
+        // Since 3 days ago.
+        assert_eq!(
+            Stats {
+                downloads: 4,
+                succeeded: 2,
+                failed: 2,
+                failures: BTreeMap::from([
+                    ("not found".to_string(), 1),
+                    ("upstream".to_string(), 1)
+                ]),
+                formats: vec![count("epub", 3), count("mobi", 1)],
+                bytes_served: 1_000,
+                cache_hit_rate: Some(0.5),
+                average_conversion_ms: Some(1_000),
+                top_titles: vec![count("1984", 2)],
+            },
+            history.stats(Some(3 * DAY))
+        );
+
+        clock.advance(DAY);
+        assert_eq!(Stats::default(), history.stats(Some(DAY / 2)));
+    }
+
+    #[test]
+    fn test_stats_top_titles() {
+        let history = History::default();
+        for i in 0..12 {
+            for _ in 0..=i {
+                history.record(
+                    &BookRequest::default(),
+                    Some(format!("Book {:02}", i)),
+                    Extension::Epub,
+                    delivered(1, false, None),
+                );
+            }
+        }
+
+        let top_titles = history.stats(None).top_titles;
+        assert_eq!(TOP_TITLES, top_titles.len());
+        assert_eq!(count("Book 11", 12), top_titles[0]);
+        assert_eq!(count("Book 02", 3), top_titles[9]);
+    }
+
+    #[test]
+    fn test_record_forgets_oldest() {
+        let history = History::default();
+        for i in 0..=MAX_RECORDS {
+            history.record(
+                &BookRequest::default(),
+                Some(i.to_string()),
+                Extension::Epub,
+                delivered(1, false, None),
+            );
+        }
+
+        let records = history.records.lock().unwrap();
+        assert_eq!(MAX_RECORDS, records.len());
+        assert_eq!(Some("1".to_string()), records[0].title);
+    }
+
+    #[test]
+    fn test_attempts() {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH + DAY));
+        let history = History::with_clock(SharedClock(clock.clone()));
+        let short_url = "https://www.goodreads.com/book/show/1048424";
+        let long_url = "https://www.goodreads.com/en/book/show/1048424.Governing_the_Commons";
+        history.record(
+            &BookRequest::from_url(short_url),
+            None,
+            Extension::Epub,
+            Outcome::Failed("upstream".to_string()),
+        );
+        clock.advance(DAY);
+        history.record(
+            &BookRequest::from_url("https://www.goodreads.com/book/show/40961427-1984"),
+            Some("1984".to_string()),
+            Extension::Epub,
+            delivered(500, false, None),
+        );
+        history.record(
+            &BookRequest::from_url(long_url),
+            Some("Governing the Commons".to_string()),
+            Extension::Mobi,
+            delivered(1_000, true, None),
+        );
+
+        let book = "1048424".parse().unwrap();
+        assert_eq!(
+            vec![
+                Attempt {
+                    at: 2 * DAY.as_secs(),
+                    url: Some(long_url.to_string()),
+                    title: Some("Governing the Commons".to_string()),
+                    format: "mobi".to_string(),
+                    delivered: true,
+                    error: None,
+                    bytes: Some(1_000),
+                    from_library: Some(true),
+                },
+                Attempt {
+                    at: DAY.as_secs(),
+                    url: Some(short_url.to_string()),
+                    title: None,
+                    format: "epub".to_string(),
+                    delivered: false,
+                    error: Some("upstream".to_string()),
+                    bytes: None,
+                    from_library: None,
+                },
+            ],
+            history.attempts(&book)
+        );
+        assert_eq!(
+            Vec::<Attempt>::new(),
+            history.attempts(&"1".parse().unwrap())
+        );
+    }
+}
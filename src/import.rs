@@ -0,0 +1,184 @@
+//! Module import reads book lists exported from other services, so whole
+//! libraries can be downloaded at once.
+//!
+//! For now, it reads the CSV export of a Goodreads library (My Books >
+//! Import and export > Export library).
+
+use crate::goodreads::BookIdentification;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportedBooks {
+    pub books: Vec<BookIdentification>,
+    // Rows without any ISBN, which can't be looked up on LibGen.
+    pub skipped: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoodreadsRow {
+    #[serde(rename = "Title")]
+    title: String,
+    #[serde(rename = "Author")]
+    author: String,
+    #[serde(rename = "ISBN")]
+    isbn10: String,
+    #[serde(rename = "ISBN13")]
+    isbn13: String,
+    #[serde(rename = "Publisher", default)]
+    publisher: String,
+    #[serde(rename = "Number of Pages", default)]
+    pages: String,
+    #[serde(rename = "Binding", default)]
+    binding: String,
+}
+
+pub fn parse_goodreads_csv(reader: impl std::io::Read) -> Result<ImportedBooks, Error> {
+    let mut imported = ImportedBooks::default();
+
+    for row in csv::Reader::from_reader(reader).deserialize() {
+        let row: GoodreadsRow = row?;
+        let isbn10 = goodreads_isbn(&row.isbn10);
+        let isbn13 = goodreads_isbn(&row.isbn13);
+        if isbn10.is_none() && isbn13.is_none() {
+            imported.skipped += 1;
+            continue;
+        }
+
+        imported.books.push(BookIdentification {
+            isbn10,
+            isbn13,
+            title: non_empty(&row.title),
+            author: non_empty(&row.author),
+            cover_url: None,
+            pages: row.pages.trim().parse().ok().filter(|pages| *pages > 0),
+            publisher: non_empty(&row.publisher),
+            series: None,
+            series_index: None,
+            format: non_empty(&row.binding),
+        });
+    }
+
+    Ok(imported)
+}
+
+// Goodreads exports ISBNs as formulas, so spreadsheets keep them as text:
+// `="0521405998"`, or `=""` when there is none.
+fn goodreads_isbn(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value.strip_prefix('=').unwrap_or(value);
+    non_empty(value.trim_matches('"'))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+#[test]
+fn test_goodreads_isbn() {
+    for (value, want) in [
+        ("=\"0521405998\"", Some("0521405998")),
+        ("9780521405997", Some("9780521405997")),
+        (" =\"9780521405997\" ", Some("9780521405997")),
+        ("=\"\"", None),
+        ("", None),
+    ] {
+        assert_eq!(want.map(str::to_string), goodreads_isbn(value), "{}", value);
+    }
+}
+
+#[test]
+fn test_parse_goodreads_csv() {
+    let got = parse_goodreads_csv(
+        include_bytes!("../tests/testdata/goodreads_library_export.csv").as_slice(),
+    )
+    .expect("Should parse the export");
+
+    let book = |isbn10: Option<&str>,
+                isbn13: Option<&str>,
+                title: &str,
+                author: &str,
+                pages: u32,
+                publisher: &str,
+                binding: &str| BookIdentification {
+        isbn10: isbn10.map(str::to_string),
+        isbn13: isbn13.map(str::to_string),
+        title: Some(title.to_string()),
+        author: Some(author.to_string()),
+        cover_url: None,
+        pages: Some(pages),
+        publisher: Some(publisher.to_string()),
+        series: None,
+        series_index: None,
+        format: Some(binding.to_string()),
+    };
+    assert_eq!(
+        ImportedBooks {
+            books: vec![
+                book(
+                    Some("0521405998"),
+                    Some("9780521405997"),
+                    "Governing the Commons: The Evolution of Institutions for Collective Action (Political Economy of Institutions and Decisions)",
+                    "Elinor Ostrom",
+                    298,
+                    "Cambridge University Press",
+                    "Paperback",
+                ),
+                book(
+                    None,
+                    Some("9780451529060"),
+                    "On the Origin of Species",
+                    "Charles Darwin",
+                    512,
+                    "Signet",
+                    "Mass Market Paperback",
+                ),
+                book(
+                    Some("2070360075"),
+                    None,
+                    "Le Vieil Homme et la Mer",
+                    "Ernest Hemingway",
+                    151,
+                    "Gallimard",
+                    "Poche",
+                ),
+                book(
+                    Some("5170906301"),
+                    Some("9785170906307"),
+                    "Преступление и наказание",
+                    "Фёдор Достоевский",
+                    608,
+                    "АСТ",
+                    "Paperback",
+                ),
+            ],
+            // 1984 has no ISBN.
+            skipped: 1,
+        },
+        got
+    );
+}
+
+#[test]
+fn test_parse_goodreads_csv_invalid() {
+    assert!(parse_goodreads_csv("Title,Author\n1984,George Orwell\n".as_bytes()).is_err());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Csv(String),
+}
+
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        Self::Csv(err.to_string())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Csv(message) => write!(f, "invalid Goodreads CSV export: {}", message),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Module keyed_lock serializes asynchronous work by key, e.g. the writes of
+//! the files of a book, keyed by its MD5, when the queue, a batch and a
+//! request download the same book at once.
+//!
+//! Only the keys being locked or waited for are kept.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OwnedMutexGuard;
+
+type Locks = Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>;
+
+// Clones share their locks.
+#[derive(Clone, Default)]
+pub struct KeyedLocks {
+    locks: Locks,
+}
+
+impl KeyedLocks {
+    // Waits until nobody else holds `key`, and holds it until the guard is
+    // dropped.
+    pub async fn lock(&self, key: &str) -> KeyGuard {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        // Made before waiting, for a waiter dropped while it waits, e.g. when
+        // the client hung up, to forget the key too.
+        let mut guard = KeyGuard {
+            guard: None,
+            key: key.to_string(),
+            locks: self.locks.clone(),
+        };
+        guard.guard = Some(lock.lock_owned().await);
+        guard
+    }
+
+    // How many keys are locked or waited for.
+    fn len(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+}
+
+impl std::fmt::Debug for KeyedLocks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyedLocks")
+            .field("locked", &self.len())
+            .finish()
+    }
+}
+
+pub struct KeyGuard {
+    guard: Option<OwnedMutexGuard<()>>,
+    key: String,
+    locks: Locks,
+}
+
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+        self.guard.take();
+        // Waiters hold a clone of the lock: it is only forgotten when there
+        // are none left.
+        if locks
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_same_key_waits() {
+        let locks = KeyedLocks::default();
+        let guard = locks.lock("AB13").await;
+
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move {
+                let _guard = locks.lock("AB13").await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        // Other keys aren't held up.
+        drop(locks.lock("CD45").await);
+
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("the lock is released")
+            .unwrap();
+        assert_eq!(0, locks.len());
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_forgotten() {
+        let locks = KeyedLocks::default();
+        let first = locks.lock("AB13").await;
+        let second = locks.lock("CD45").await;
+        assert_eq!(2, locks.len());
+
+        drop(first);
+        assert_eq!(1, locks.len());
+        drop(second);
+        assert_eq!(0, locks.len());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiters_are_forgotten() {
+        let locks = KeyedLocks::default();
+
+        // Cancelled after the lock was released.
+        let guard = locks.lock("AB13").await;
+        let mut waiter = Box::pin(locks.lock("AB13"));
+        assert!(futures_util::poll!(&mut waiter).is_pending());
+        drop(guard);
+        drop(waiter);
+        assert_eq!(0, locks.len());
+
+        // Cancelled while the lock is held.
+        let guard = locks.lock("AB13").await;
+        let mut waiter = Box::pin(locks.lock("AB13"));
+        assert!(futures_util::poll!(&mut waiter).is_pending());
+        drop(waiter);
+        assert_eq!(1, locks.len());
+        drop(guard);
+        assert_eq!(0, locks.len());
+
+        // The key can still be locked.
+        drop(locks.lock("AB13").await);
+        assert_eq!(0, locks.len());
+    }
+}
@@ -0,0 +1,310 @@
+//! Module kindle emails books to a Kindle, with Amazon's Send to Kindle
+//! service: books sent as attachments to the `@kindle.com` address of a
+//! device show up in its library.
+//!
+//! The sender must be in the approved senders of the Amazon account, and
+//! Amazon rejects attachments over 50 MB.
+
+use crate::config::KindleConfig;
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const MAX_ATTACHMENT_SIZE: u64 = 50 * 1024 * 1024;
+
+// SMTP servers listening on this port expect TLS right away, others are
+// asked to upgrade with STARTTLS.
+const SMTPS_PORT: u16 = 465;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub attachment_name: String,
+    pub attachment_content_type: String,
+    pub attachment: Vec<u8>,
+}
+
+#[async_trait]
+#[cfg_attr(test, mockall::automock)]
+pub trait Mailer {
+    async fn send(&self, email: &Email) -> Result<(), Error>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &KindleConfig) -> Result<Self, Error> {
+        let builder = if config.smtp_port == SMTPS_PORT {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)
+        }
+        .map_err(|err| Error::Smtp(err.to_string()))?
+        .port(config.smtp_port);
+
+        let builder = match (&config.smtp_username, &config.smtp_password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            _ => builder,
+        };
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), Error> {
+        self.transport
+            .send(message(email)?)
+            .await
+            .map_err(|err| Error::Smtp(err.to_string()))?;
+        Ok(())
+    }
+}
+
+// Builds the email: a short text, and the book as an attachment.
+pub fn message(email: &Email) -> Result<Message, Error> {
+    let mailbox = |address: &str| {
+        address
+            .parse::<Mailbox>()
+            .map_err(|_| Error::InvalidAddress(address.to_string()))
+    };
+    let content_type = ContentType::parse(&email.attachment_content_type)
+        .unwrap_or(ContentType::parse("application/octet-stream").expect("valid content type"));
+
+    Message::builder()
+        .from(mailbox(&email.from)?)
+        .to(mailbox(&email.to)?)
+        .subject(&email.subject)
+        .multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(format!(
+                    "{}, sent by LibReads.",
+                    email.attachment_name
+                )))
+                .singlepart(
+                    Attachment::new(email.attachment_name.clone())
+                        .body(email.attachment.clone(), content_type),
+                ),
+        )
+        .map_err(|err| Error::Smtp(err.to_string()))
+}
+
+// Acknowledges a book sent to a Kindle.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub to: String,
+    pub filename: String,
+    pub bytes: u64,
+}
+
+pub struct Kindle {
+    pub mailer: Box<dyn Mailer + Send + Sync>,
+    pub from: String,
+    pub to: String,
+    pub max_attachment_size: u64,
+}
+
+impl Kindle {
+    pub fn new(config: &KindleConfig) -> Result<Self, Error> {
+        Ok(Self {
+            mailer: Box::new(SmtpMailer::new(config)?),
+            from: config.from.clone(),
+            to: config.kindle_email.clone(),
+            max_attachment_size: MAX_ATTACHMENT_SIZE,
+        })
+    }
+
+    // Emails the book at `path`, named after its file. Books over the
+    // attachment size limit are rejected before being read.
+    pub async fn send(&self, path: &Path, content_type: &str) -> Result<Delivery, Error> {
+        let bytes = tokio::fs::metadata(path).await?.len();
+        if bytes > self.max_attachment_size {
+            return Err(Error::TooLarge {
+                limit: self.max_attachment_size,
+                size: bytes,
+            });
+        }
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let email = Email {
+            from: self.from.clone(),
+            to: self.to.clone(),
+            subject: filename.clone(),
+            attachment_name: filename.clone(),
+            attachment_content_type: content_type.to_string(),
+            attachment: tokio::fs::read(path).await?,
+        };
+        self.mailer.send(&email).await?;
+
+        Ok(Delivery {
+            to: self.to.clone(),
+            filename,
+            bytes,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    // In bytes.
+    TooLarge { limit: u64, size: u64 },
+    InvalidAddress(String),
+    Smtp(String),
+    Io(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TooLarge { limit, size } => write!(
+                f,
+                "the book is {} bytes, over the {} bytes Send to Kindle attachment limit",
+                size, limit
+            ),
+            Error::InvalidAddress(address) => write!(f, "invalid email address {:?}", address),
+            Error::Smtp(message) => write!(f, "could not send the email: {}", message),
+            Error::Io(message) => write!(f, "could not read the book: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOK: &str = "tests/testdata/dummy_ebook.epub";
+
+    fn kindle(mailer: MockMailer, max_attachment_size: u64) -> Kindle {
+        Kindle {
+            mailer: Box::new(mailer),
+            from: "libreads@example.com".to_string(),
+            to: "me_123@kindle.com".to_string(),
+            max_attachment_size,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let book = std::fs::read(BOOK).unwrap();
+        let want = Email {
+            from: "libreads@example.com".to_string(),
+            to: "me_123@kindle.com".to_string(),
+            subject: "dummy_ebook.epub".to_string(),
+            attachment_name: "dummy_ebook.epub".to_string(),
+            attachment_content_type: "application/epub+zip".to_string(),
+            attachment: book.clone(),
+        };
+        let mut mailer = MockMailer::new();
+        mailer
+            .expect_send()
+            .withf(move |email| *email == want)
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(()) }));
+
+        let got = kindle(mailer, MAX_ATTACHMENT_SIZE)
+            .send(Path::new(BOOK), "application/epub+zip")
+            .await;
+
+        assert_eq!(
+            Ok(Delivery {
+                to: "me_123@kindle.com".to_string(),
+                filename: "dummy_ebook.epub".to_string(),
+                bytes: book.len() as u64,
+            }),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_too_large() {
+        let mut mailer = MockMailer::new();
+        mailer.expect_send().never();
+        let size = std::fs::metadata(BOOK).unwrap().len();
+
+        let got = kindle(mailer, size - 1)
+            .send(Path::new(BOOK), "application/epub+zip")
+            .await;
+
+        assert_eq!(
+            Err(Error::TooLarge {
+                limit: size - 1,
+                size
+            }),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_smtp_failure() {
+        let mut mailer = MockMailer::new();
+        mailer
+            .expect_send()
+            .returning(|_| Box::pin(async { Err(Error::Smtp("554 relay denied".to_string())) }));
+
+        let got = kindle(mailer, MAX_ATTACHMENT_SIZE)
+            .send(Path::new(BOOK), "application/epub+zip")
+            .await;
+
+        assert_eq!(Err(Error::Smtp("554 relay denied".to_string())), got);
+    }
+
+    #[test]
+    fn test_message() {
+        let email = Email {
+            from: "libreads@example.com".to_string(),
+            to: "me_123@kindle.com".to_string(),
+            subject: "1984.azw3".to_string(),
+            attachment_name: "1984.azw3".to_string(),
+            attachment_content_type: "application/vnd.amazon.ebook".to_string(),
+            attachment: vec![0xff, 0x00, 0x01],
+        };
+
+        let formatted = String::from_utf8(message(&email).unwrap().formatted()).unwrap();
+
+        for want in [
+            "From: libreads@example.com",
+            "To: me_123@kindle.com",
+            "Subject: 1984.azw3",
+            "Content-Type: multipart/mixed",
+            "Content-Disposition: attachment; filename=\"1984.azw3\"",
+            "Content-Type: application/vnd.amazon.ebook",
+            // The binary book, in base64.
+            "Content-Transfer-Encoding: base64",
+            "/wAB",
+        ] {
+            assert!(formatted.contains(want), "{} not in {}", want, formatted);
+        }
+
+        assert_eq!(
+            Err(Error::InvalidAddress("kindle.com".to_string())),
+            message(&Email {
+                to: "kindle.com".to_string(),
+                ..email
+            })
+            .map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Module language_check tells whether a downloaded book is in the language
+//! asked for with `lang`, see `selection`. LibGen's language field is often
+//! wrong, e.g. for scans uploaded in another language than the original.
+//!
+//! A sample of the text is read from the book, and its language detected
+//! with whatlang. Only formats whose text can be read cheaply are sampled:
+//! EPUB and HTMLZ, from their largest content document, and TXT. Other
+//! formats, short samples, and languages whatlang doesn't know are let
+//! through.
+//!
+//! Enabled with `LIBREADS_LANGUAGE_CHECK`, see `libreads::LibReads::download_as`
+//! for what happens to the books in another language.
+
+use crate::extension::Extension;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::Read, path::Path, sync::OnceLock};
+use whatlang::Lang;
+
+// How sure whatlang must be before a book is considered in another language.
+pub const MIN_CONFIDENCE: f64 = 0.9;
+// How much of the book is read. A few paragraphs are plenty for whatlang.
+const MAX_SAMPLE_BYTES: u64 = 64 * 1024;
+const MAX_SAMPLE_CHARS: usize = 4000;
+
+// The book seems to be in another language than the one asked for.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LanguageMismatch {
+    // Lowercased, as LibGen spells them, e.g. "english".
+    pub requested: String,
+    pub detected: String,
+    // Between 0 and 1.
+    pub confidence: f64,
+}
+
+impl std::fmt::Display for LanguageMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the book seems to be in {} ({:.0}% confident), not in {}",
+            self.detected,
+            self.confidence * 100.0,
+            self.requested
+        )
+    }
+}
+
+// Reads a sample of the book at `path`, and tells whether it contradicts
+// `requested`. Blocking: the book is read from disk.
+pub fn check(path: &Path, extension: &Extension, requested: &str) -> Option<LanguageMismatch> {
+    let sample = match sample(path, extension) {
+        Ok(sample) => sample?,
+        Err(err) => {
+            tracing::warn!(
+                "Could not read {} to check its language: {}",
+                path.display(),
+                err
+            );
+            return None;
+        }
+    };
+    mismatch(&sample, requested)
+}
+
+// Whether `text` is, with high confidence, in another language than
+// `requested`.
+pub fn mismatch(text: &str, requested: &str) -> Option<LanguageMismatch> {
+    // Languages whatlang doesn't know can't be told apart from the others.
+    if !Lang::all()
+        .iter()
+        .any(|lang| language_name(*lang) == requested)
+    {
+        return None;
+    }
+    let info = whatlang::detect(text)?;
+    let detected = language_name(info.lang());
+    match info.is_reliable() && info.confidence() >= MIN_CONFIDENCE && detected != requested {
+        true => Some(LanguageMismatch {
+            requested: requested.to_string(),
+            detected,
+            confidence: info.confidence(),
+        }),
+        false => None,
+    }
+}
+
+// The name of the language, as LibGen spells them, lowercased.
+fn language_name(lang: Lang) -> String {
+    match lang {
+        Lang::Cmn => "chinese".to_string(),
+        lang => lang.eng_name().to_lowercase(),
+    }
+}
+
+// A sample of the text of the book, None for formats that aren't sampled.
+fn sample(path: &Path, extension: &Extension) -> std::io::Result<Option<String>> {
+    let text = match extension {
+        Extension::Epub | Extension::Htmlz => strip_markup(&largest_document(path)?),
+        Extension::Txt => read_prefix(File::open(path)?)?,
+        _ => return Ok(None),
+    };
+    let text: String = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_SAMPLE_CHARS)
+        .collect();
+    Ok(Some(text))
+}
+
+// The beginning of the largest HTML document of a ZIP, which is the body of
+// the book more often than not, rather than its cover or table of contents.
+fn largest_document(path: &Path) -> std::io::Result<String> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut largest: Option<(usize, u64)> = None;
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let name = entry.name().to_lowercase();
+        let is_document = [".xhtml", ".html", ".htm"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix));
+        if is_document && largest.is_none_or(|(_, size)| entry.size() > size) {
+            largest = Some((i, entry.size()));
+        }
+    }
+    match largest {
+        Some((i, _)) => read_prefix(archive.by_index(i)?),
+        None => Ok(String::new()),
+    }
+}
+
+fn read_prefix(reader: impl Read) -> std::io::Result<String> {
+    let mut bytes = vec![];
+    reader.take(MAX_SAMPLE_BYTES).read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Drops the tags, and the head, whose title and styles aren't the text.
+fn strip_markup(html: &str) -> String {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    let markup = MARKUP.get_or_init(|| {
+        Regex::new(
+            r"(?is)<head\b.*?</head>|<(script|style)\b.*?</(script|style)>|<[^>]*>|&[a-z#0-9]+;",
+        )
+        .expect("valid regex")
+    });
+    markup.replace_all(html, " ").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGLISH_EPUB: &str = "tests/testdata/english_ebook.epub";
+    const RUSSIAN_EPUB: &str = "tests/testdata/russian_ebook.epub";
+
+    #[test]
+    fn test_check() {
+        let epub = Extension::Epub;
+        assert_eq!(None, check(Path::new(ENGLISH_EPUB), &epub, "english"));
+        assert_eq!(None, check(Path::new(RUSSIAN_EPUB), &epub, "russian"));
+
+        let got = check(Path::new(RUSSIAN_EPUB), &epub, "english").unwrap();
+        assert_eq!("english", got.requested);
+        assert_eq!("russian", got.detected);
+        assert!(got.confidence >= MIN_CONFIDENCE);
+        let got = check(Path::new(ENGLISH_EPUB), &epub, "french").unwrap();
+        assert_eq!("english", got.detected);
+    }
+
+    #[test]
+    fn test_check_lets_through() {
+        // Formats that aren't sampled, and unreadable books.
+        assert_eq!(
+            None,
+            check(
+                Path::new("tests/testdata/dummy_ebook.mobi"),
+                &Extension::Mobi,
+                "french"
+            )
+        );
+        assert_eq!(
+            None,
+            check(
+                Path::new("tests/testdata/missing.epub"),
+                &Extension::Epub,
+                "french"
+            )
+        );
+        assert_eq!(
+            None,
+            check(
+                Path::new("tests/testdata/dummy_ebook.mobi"),
+                &Extension::Epub,
+                "french"
+            )
+        );
+        // Languages whatlang doesn't know.
+        assert_eq!(
+            None,
+            check(Path::new(ENGLISH_EPUB), &Extension::Epub, "klingon")
+        );
+    }
+
+    #[test]
+    fn test_mismatch() {
+        assert_eq!(
+            Some("chinese".to_string()),
+            mismatch("这是一本用中文写的书，讲述了一个很长的故事。", "english").map(|m| m.detected)
+        );
+        assert_eq!(
+            None,
+            mismatch("这是一本用中文写的书，讲述了一个很长的故事。", "chinese")
+        );
+        // Too short to be sure.
+        assert_eq!(None, mismatch("Chapter 1", "french"));
+        assert_eq!(None, mismatch("", "french"));
+    }
+
+    #[test]
+    fn test_sample() {
+        let got = sample(Path::new(ENGLISH_EPUB), &Extension::Epub)
+            .unwrap()
+            .unwrap();
+        assert!(
+            got.starts_with("It is a truth universally acknowledged"),
+            "{}",
+            got
+        );
+        assert!(!got.contains('<'), "{}", got);
+        assert!(!got.contains("text-indent"), "{}", got);
+        assert_eq!(
+            None,
+            sample(Path::new(ENGLISH_EPUB), &Extension::Pdf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_language_mismatch_display() {
+        let mismatch = LanguageMismatch {
+            requested: "english".to_string(),
+            detected: "russian".to_string(),
+            confidence: 0.984,
+        };
+        assert_eq!(
+            "the book seems to be in russian (98% confident), not in english",
+            mismatch.to_string()
+        );
+    }
+}
@@ -1,8 +1,42 @@
+pub mod book_query;
+pub mod book_ref;
+pub mod capabilities;
+pub mod circuit_breaker;
+pub mod clock;
+pub mod config;
 pub mod convert;
+pub mod device;
 pub mod extension;
+pub mod frontend;
+pub mod history;
+pub mod import;
+pub mod language_check;
 pub mod libreads;
+pub mod maintenance;
+pub mod name_template;
+pub mod openapi;
+pub mod output_path;
+pub mod parse_check;
+pub mod prober;
+pub mod queue;
+pub mod ranking;
+pub mod request_log;
+pub mod selection;
+pub mod server;
+pub mod tls;
 pub mod web;
 
+mod archive;
+mod calibre_library;
+mod file_store;
 mod goodreads;
+mod http_client;
+mod keyed_lock;
+pub mod kindle;
 mod libgen;
+mod libgen_li;
+mod libgen_rocks;
+mod library;
 mod library_dot_lol;
+mod links_cache;
+mod temp_file;
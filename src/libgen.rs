@@ -3,16 +3,32 @@
 //! the LibGen API for that.
 //!
 //! Example request:
-//! http://libgen.rs/json.php?isbn=9788853001351&fields=Title,Author,Year,Extension,MD5
+//! http://libgen.rs/json.php?isbn=9788853001351&fields=Title,Author,Year,Extension,MD5,Filesize,Language,Pages,Publisher
 //!
 //! Example response:
-//! [{"title":"Pride and Prejudice","author":"Jane Austen","year":"2000","extension":"pdf","md5":"ab13556b96d473c8dfad7165c4704526"}]
+//! [{"title":"Pride and Prejudice","author":"Jane Austen","year":"2000","extension":"pdf","md5":"ab13556b96d473c8dfad7165c4704526","filesize":"2341234","language":"English","pages":"328","publisher":"Penguin"}]
+//!
+//! The JSON API can't search by title, so title searches scrape the search
+//! page next to it instead:
+//! http://libgen.rs/search.php?req=Pride+and+Prejudice&column=title
 
-use crate::{extension::Extension, goodreads::BookIdentification};
+use crate::{
+    clock::SharedClock,
+    extension::Extension,
+    goodreads::BookIdentification,
+    http_client::{self, ConnectionLimits, HttpFailure, RateLimit, SendError},
+    ranking::{CandidateRanker, RankingContext},
+    selection::Filter,
+};
 use async_trait::async_trait;
-use serde::Deserialize;
+use futures_util::future::join_all;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{cmp::Reverse, collections::HashSet};
+use utoipa::ToSchema;
 
-const BASE_URL: &str = "http://libgen.rs/json.php";
+pub(crate) const BASE_URL: &str = "http://libgen.rs/json.php";
+const SEARCH_PAGE: &str = "search.php";
 
 #[async_trait]
 #[cfg_attr(test, mockall::automock)]
@@ -21,20 +37,273 @@ pub trait MetadataStore {
         &self,
         book_identification: &BookIdentification,
     ) -> Result<Vec<LibgenMetadata>, Error>;
+
+    // Finds books whose title contains `title`. Stores that can't search by
+    // title find nothing.
+    async fn search_by_title(&self, _title: &str) -> Result<Vec<LibgenMetadata>, Error> {
+        Ok(vec![])
+    }
+
+    // Finds the edition with this MD5. Stores that can't look books up by
+    // MD5 find nothing.
+    async fn get_metadata_by_md5(&self, _md5: &str) -> Result<Option<LibgenMetadata>, Error> {
+        Ok(None)
+    }
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
 pub struct LibgenMetadata {
     pub title: String,
     pub author: String,
     pub year: String,
-    #[serde(flatten)]
+    // LibGen sometimes omits the extension, or sets it to null.
+    #[serde(
+        default = "unknown_extension",
+        deserialize_with = "deserialize_extension"
+    )]
     pub extension: Extension,
     pub md5: String,
+    // In bytes, when known.
+    #[serde(default, deserialize_with = "deserialize_filesize")]
+    pub filesize: Option<u64>,
+    // As LibGen spells it, e.g. "English" or "English, French".
+    #[serde(default, deserialize_with = "deserialize_text")]
+    pub language: Option<String>,
+    // Number of pages, when LibGen knows it.
+    #[serde(default, deserialize_with = "deserialize_pages")]
+    pub pages: Option<u32>,
+    // Not every mirror returns it.
+    #[serde(default, deserialize_with = "deserialize_text")]
+    pub publisher: Option<String>,
+}
+
+fn unknown_extension() -> Extension {
+    Extension::Other(String::new())
+}
+
+fn deserialize_extension<'de, D>(deserializer: D) -> Result<Extension, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Extension>::deserialize(deserializer)?.unwrap_or_else(unknown_extension))
+}
+
+// The LibGen JSON API returns sizes as strings, e.g. "2341234".
+fn deserialize_filesize<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Filesize {
+        Number(u64),
+        Text(String),
+    }
+
+    Ok(match Option::<Filesize>::deserialize(deserializer)? {
+        Some(Filesize::Number(size)) => Some(size),
+        Some(Filesize::Text(size)) => size.trim().parse().ok(),
+        None => None,
+    }
+    .filter(|size| *size > 0))
+}
+
+// Trims the text, and leaves out empty ones.
+fn deserialize_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty()))
+}
+
+// LibGen spells page counts in many ways, e.g. "328", "xii, 328" or
+// "328[4]": the largest number is the number of pages.
+fn deserialize_pages<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Pages {
+        Number(u32),
+        Text(String),
+    }
+
+    Ok(match Option::<Pages>::deserialize(deserializer)? {
+        Some(Pages::Number(pages)) => Some(pages).filter(|pages| *pages > 0),
+        Some(Pages::Text(pages)) => parse_pages(&pages),
+        None => None,
+    })
+}
+
+fn parse_pages(pages: &str) -> Option<u32> {
+    pages
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|number| number.parse().ok())
+        .max()
+        .filter(|pages| *pages > 0)
+}
+
+#[test]
+fn test_deserialise_libgen_metadata_pages() {
+    for (pages, want) in [
+        (r#""328""#, Some(328)),
+        (r#""xii, 328""#, Some(328)),
+        (r#""328[4]""#, Some(328)),
+        ("328", Some(328)),
+        (r#""""#, None),
+        (r#""0""#, None),
+        ("null", None),
+    ] {
+        let json = format!(
+            r#"{{ "title": "t", "author": "a", "year": "2000", "md5": "M", "pages": {} }}"#,
+            pages
+        );
+        let got: LibgenMetadata = serde_json::from_str(&json).expect("Should deserialise");
+        assert_eq!(want, got.pages, "{}", pages);
+    }
+}
+
+#[test]
+fn test_deserialise_libgen_metadata_publisher() {
+    for (publisher, want) in [
+        (
+            r#", "publisher": "Penguin Classics""#,
+            Some("Penguin Classics"),
+        ),
+        (r#", "publisher": " Penguin ""#, Some("Penguin")),
+        (r#", "publisher": """#, None),
+        (r#", "publisher": null"#, None),
+        // Mirrors that don't return it.
+        ("", None),
+    ] {
+        let json = format!(
+            r#"{{ "title": "t", "author": "a", "year": "2000", "md5": "M"{} }}"#,
+            publisher
+        );
+        let got: LibgenMetadata = serde_json::from_str(&json).expect("Should deserialise");
+        assert_eq!(want.map(str::to_string), got.publisher, "{}", publisher);
+    }
+}
+
+#[test]
+fn test_deserialise_libgen_metadata_extension() {
+    for (extension, want) in [
+        (r#""pdf""#, Extension::Pdf),
+        (r#""PdF""#, Extension::Pdf),
+        (r#""djvu""#, Extension::Djvu),
+        (
+            r#""RANDOMEXTENSION""#,
+            Extension::Other("randomextension".to_string()),
+        ),
+        (r#""""#, Extension::Other(String::new())),
+        ("null", Extension::Other(String::new())),
+    ] {
+        let json = format!(
+            r#"{{ "title": "t", "author": "a", "year": "2000", "extension": {}, "md5": "M" }}"#,
+            extension
+        );
+        let got: LibgenMetadata = serde_json::from_str(&json).expect("Should deserialise");
+        assert_eq!(want, got.extension, "{}", extension);
+    }
+
+    let got: LibgenMetadata =
+        serde_json::from_str(r#"{ "title": "t", "author": "a", "year": "2000", "md5": "M" }"#)
+            .expect("Should deserialise without an extension");
+    assert_eq!(Extension::Other(String::new()), got.extension);
+    assert_eq!(None, got.filesize);
+    assert_eq!(None, got.language);
+}
+
+#[test]
+fn test_deserialise_libgen_metadata_filesize_and_language() {
+    for (filesize, language, want_filesize, want_language) in [
+        (
+            r#""2341234""#,
+            r#""English""#,
+            Some(2341234),
+            Some("English"),
+        ),
+        ("2341234", r#"" French ""#, Some(2341234), Some("French")),
+        (r#""""#, r#""""#, None, None),
+        (r#""0""#, "null", None, None),
+        ("null", "null", None, None),
+    ] {
+        let json = format!(
+            r#"{{ "title": "t", "author": "a", "year": "2000", "md5": "M", "filesize": {}, "language": {} }}"#,
+            filesize, language
+        );
+        let got: LibgenMetadata = serde_json::from_str(&json).expect("Should deserialise");
+        assert_eq!(want_filesize, got.filesize, "{}", filesize);
+        assert_eq!(
+            want_language.map(str::to_string),
+            got.language,
+            "{}",
+            language
+        );
+    }
+}
+
+// Some LibGen rows have their MD5 uppercased, or padded with whitespace.
+// Returns it lowercased, or None when it isn't 32 hexadecimal characters.
+pub fn normalise_md5(md5: &str) -> Option<String> {
+    let md5 = md5.trim();
+    let is_md5 = md5.len() == 32 && md5.chars().all(|c| c.is_ascii_hexdigit());
+    is_md5.then(|| md5.to_lowercase())
+}
+
+#[test]
+fn test_normalise_md5() {
+    for (md5, want) in [
+        (
+            "ab13556b96d473c8dfad7165c4704526",
+            Some("ab13556b96d473c8dfad7165c4704526"),
+        ),
+        (
+            "AB13556B96D473C8dfad7165c4704526",
+            Some("ab13556b96d473c8dfad7165c4704526"),
+        ),
+        (
+            " AB13556B96D473C8DFAD7165C4704526\n",
+            Some("ab13556b96d473c8dfad7165c4704526"),
+        ),
+        ("", None),
+        ("ABCD", None),
+        ("AB13556B96D473C8DFAD7165C47045260", None),
+        ("AB13556B96D473C8DFAD7165C470452G", None),
+        ("AB13556B96D473C8 DFAD7165C470452", None),
+        ("../../../../../../../../etc/passwd", None),
+    ] {
+        assert_eq!(want.map(str::to_string), normalise_md5(md5), "{:?}", md5);
+    }
+}
+
+#[test]
+fn test_libgen_metadata_round_trip() {
+    let metadata = LibgenMetadata {
+        title: "Governing the Commons".to_string(),
+        author: "Elinor Ostrom".to_string(),
+        year: "1990".to_string(),
+        extension: Extension::Epub,
+        md5: "ABCD".to_string(),
+        filesize: None,
+        language: None,
+        pages: Some(280),
+        publisher: Some("Cambridge University Press".to_string()),
+    };
+
+    let json = serde_json::to_string(&metadata).unwrap();
+    assert_eq!(metadata, serde_json::from_str(&json).unwrap());
 }
 
 pub struct Libgen {
-    base_url: String,
+    pub base_url: String,
+    pub client: reqwest::Client,
+    pub limits: ConnectionLimits,
+    // Times the waits before retrying a rate-limited request.
+    pub clock: SharedClock,
 }
 
 #[async_trait]
@@ -43,32 +312,564 @@ impl MetadataStore for Libgen {
         &self,
         book_identification: &BookIdentification,
     ) -> Result<Vec<LibgenMetadata>, Error> {
-        let query = if let Some(isbn10) = &book_identification.isbn10 {
-            format!("isbn={isbn}", isbn = &isbn10)
-        } else if let Some(isbn13) = &book_identification.isbn13 {
-            format!("isbn={isbn}", isbn = &isbn13)
-        } else if let (Some(title), Some(author)) =
-            (&book_identification.title, &book_identification.author)
-        {
-            return Err(Error::NoIsbn {
-                title: title.to_owned(),
-                author: author.to_owned(),
-            });
-        } else {
+        let isbns: Vec<&String> = [&book_identification.isbn10, &book_identification.isbn13]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if isbns.is_empty() {
+            if let (Some(title), Some(author)) =
+                (&book_identification.title, &book_identification.author)
+            {
+                return Err(Error::NoIsbn {
+                    title: title.to_owned(),
+                    author: author.to_owned(),
+                });
+            }
             return Err(Error::MissingIndentificationInfo);
+        }
+
+        // LibGen's index is inconsistent: some books can only be found by
+        // one of their ISBNs, so all of them are looked up, concurrently.
+        let results = join_all(
+            isbns
+                .into_iter()
+                .map(|isbn| self.get_metadata_by_isbn(isbn)),
+        )
+        .await;
+
+        merge_results(results)
+    }
+
+    async fn search_by_title(&self, title: &str) -> Result<Vec<LibgenMetadata>, Error> {
+        self.search(title, "title").await
+    }
+
+    // The JSON API only looks books up by ID, but the search page can by MD5.
+    async fn get_metadata_by_md5(&self, md5: &str) -> Result<Option<LibgenMetadata>, Error> {
+        let Some(md5) = normalise_md5(md5) else {
+            return Ok(None);
         };
+        Ok(self
+            .search(&md5, "md5")
+            .await?
+            .into_iter()
+            .find(|book| book.md5 == md5))
+    }
+}
+
+impl Libgen {
+    // Searches the search page, in one of its columns, e.g. "title".
+    async fn search(&self, req: &str, column: &str) -> Result<Vec<LibgenMetadata>, Error> {
+        let request = self.client.get(self.search_url()?).query(&[
+            ("req", req),
+            ("column", column),
+            ("res", "100"),
+            ("view", "simple"),
+            ("phrase", "1"),
+            ("open", "0"),
+        ]);
+        let _permit = self.limits.metadata().await;
+        let body = http_client::send(request, &*self.clock, &self.limits)
+            .await?
+            .text()
+            .await?;
+
+        parse_search_page(&body)
+    }
+}
+
+// Merges the results of concurrent queries. A failed query doesn't hide the
+// books found by the others, but when nothing was found, the most
+// informative error is returned rather than an empty result.
+fn merge_results(
+    results: Vec<Result<Vec<LibgenMetadata>, Error>>,
+) -> Result<Vec<LibgenMetadata>, Error> {
+    let mut books_metadata = vec![];
+    let mut errors = vec![];
+    for result in results {
+        match result {
+            Ok(found) => books_metadata.extend(found),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    if books_metadata.is_empty() {
+        // A rate limit says when to come back, and an excerpt of a
+        // maintenance page says more than a network error.
+        let most_informative = errors.into_iter().min_by_key(|err| {
+            Reverse(match err {
+                Error::RateLimited(_) => 3,
+                Error::UpstreamUnavailable(_) => 2,
+                Error::HttpError(_) => 1,
+                _ => 0,
+            })
+        });
+        if let Some(err) = most_informative {
+            return Err(err);
+        }
+    }
+
+    Ok(dedupe(&books_metadata))
+}
+
+#[test]
+fn test_merge_results() {
+    let book = |md5: &str| LibgenMetadata {
+        title: "Emma".to_string(),
+        author: "Jane Austen".to_string(),
+        year: md5.to_string(),
+        extension: Extension::Pdf,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+
+    assert_eq!(Ok(vec![]), merge_results(vec![]));
+    assert_eq!(Ok(vec![]), merge_results(vec![Ok(vec![]), Ok(vec![])]));
+    // Duplicates across queries are collapsed.
+    assert_eq!(
+        Ok(vec![book("a"), book("b")]),
+        merge_results(vec![Ok(vec![book("a")]), Ok(vec![book("b"), book("a")])])
+    );
+    // Failures are ignored when another query found something.
+    assert_eq!(
+        Ok(vec![book("a")]),
+        merge_results(vec![
+            Err(Error::HttpError(HttpFailure::other("timeout"))),
+            Ok(vec![book("a")]),
+        ])
+    );
+    // But not when nothing was found.
+    assert_eq!(
+        Err(Error::HttpError(HttpFailure::other("timeout"))),
+        merge_results(vec![
+            Ok(vec![]),
+            Err(Error::HttpError(HttpFailure::other("timeout")))
+        ])
+    );
+    assert_eq!(
+        Err(Error::UpstreamUnavailable("maintenance".to_string())),
+        merge_results(vec![
+            Err(Error::HttpError(HttpFailure::other("timeout"))),
+            Err(Error::UpstreamUnavailable("maintenance".to_string())),
+            Err(Error::HttpError(HttpFailure::other("connection reset"))),
+        ])
+    );
+    assert_eq!(
+        Err(Error::HttpError(HttpFailure::other("timeout"))),
+        merge_results(vec![
+            Err(Error::HttpError(HttpFailure::other("timeout"))),
+            Err(Error::HttpError(HttpFailure::other("connection reset"))),
+        ])
+    );
+    let limit = RateLimit {
+        status: 429,
+        retry_after: None,
+    };
+    assert_eq!(
+        Err(Error::RateLimited(limit.clone())),
+        merge_results(vec![
+            Err(Error::UpstreamUnavailable("maintenance".to_string())),
+            Err(Error::RateLimited(limit)),
+        ])
+    );
+}
 
+impl Libgen {
+    async fn get_metadata_by_isbn(&self, isbn: &str) -> Result<Vec<LibgenMetadata>, Error> {
         let url = format!(
-            "{base_url}?{query}&fields=Title,Author,Year,Extension,MD5",
+            "{base_url}?isbn={isbn}&fields=Title,Author,Year,Extension,MD5,Filesize,Language,Pages,Publisher",
             base_url = self.base_url,
-            query = query,
+            isbn = isbn,
         );
 
-        let resp = reqwest::get(url).await?.json().await?;
-        Ok(resp)
+        let _permit = self.limits.metadata().await;
+        let body = http_client::send(self.client.get(url), &*self.clock, &self.limits)
+            .await?
+            .text()
+            .await?;
+        let books_metadata = parse_metadata_response(&body)?;
+
+        // Books with an invalid MD5 can't be downloaded from the mirrors.
+        Ok(books_metadata
+            .into_iter()
+            .filter_map(|book| match normalise_md5(&book.md5) {
+                Some(md5) => Some(LibgenMetadata { md5, ..book }),
+                None => {
+                    tracing::warn!("Skipping \"{}\": invalid MD5 {:?}", book.title, book.md5);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    // The search page lives next to the JSON API.
+    fn search_url(&self) -> Result<reqwest::Url, Error> {
+        reqwest::Url::parse(&self.base_url)
+            .and_then(|url| url.join(SEARCH_PAGE))
+            .map_err(|err| {
+                Error::HttpError(HttpFailure::other(format!("{:?}: {}", self.base_url, err)))
+            })
+    }
+}
+
+// Reads the results table of a search page. Its columns are: ID, authors,
+// title (linking to the MD5), publisher, year, pages, language, size,
+// extension, then the mirrors.
+fn parse_search_page(body: &str) -> Result<Vec<LibgenMetadata>, Error> {
+    let document = Html::parse_document(body);
+    let table = document
+        .select(&Selector::parse("table.c").unwrap())
+        .next()
+        // Maintenance pages have no results table, even an empty one.
+        .ok_or_else(|| Error::UpstreamUnavailable(excerpt(body)))?;
+    let cells = Selector::parse("td").unwrap();
+    let book_link = Selector::parse(r#"a[href*="md5="]"#).unwrap();
+    let text = |cell: &ElementRef| cell.text().collect::<String>().trim().to_string();
+
+    let books_metadata = table
+        .select(&Selector::parse("tr").unwrap())
+        .filter_map(|row| {
+            let cells: Vec<_> = row.select(&cells).collect();
+            if cells.len() < 9 {
+                return None;
+            }
+            // The link also contains the ISBNs, after the title.
+            let link = cells[2].select(&book_link).next()?;
+            let title = link.text().next().unwrap_or_default().trim().to_string();
+            let (_, md5) = link.value().attr("href")?.split_once("md5=")?;
+            let Some(md5) = normalise_md5(md5) else {
+                tracing::warn!("Skipping \"{}\": invalid MD5 {:?}", title, md5);
+                return None;
+            };
+            let Ok(extension) = text(&cells[8]).parse::<Extension>();
+
+            Some(LibgenMetadata {
+                title,
+                author: text(&cells[1]),
+                year: text(&cells[4]),
+                extension,
+                md5,
+                filesize: parse_search_page_size(&text(&cells[7])),
+                language: Some(text(&cells[6])).filter(|language| !language.is_empty()),
+                pages: parse_pages(&text(&cells[5])),
+                publisher: Some(text(&cells[3])).filter(|publisher| !publisher.is_empty()),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(dedupe(&books_metadata))
+}
+
+// Search pages round sizes, e.g. "2 Mb" or "812 Kb".
+fn parse_search_page_size(size: &str) -> Option<u64> {
+    let (number, unit) = size.trim().split_once(' ')?;
+    let number: u64 = number.parse().ok()?;
+    let multiplier = match unit.trim().to_lowercase().as_str() {
+        "bytes" | "b" => 1,
+        "kb" => 1024,
+        "mb" => 1024 * 1024,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    Some(number * multiplier)
+}
+
+#[test]
+fn test_parse_search_page_size() {
+    for (size, want) in [
+        ("2 Mb", Some(2 * 1024 * 1024)),
+        ("812 Kb", Some(812 * 1024)),
+        ("1 Gb", Some(1024 * 1024 * 1024)),
+        ("500 bytes", Some(500)),
+        ("", None),
+        ("2Mb", None),
+        ("big", None),
+    ] {
+        assert_eq!(want, parse_search_page_size(size), "{:?}", size);
     }
 }
 
+#[test]
+fn test_parse_search_page() {
+    let got = parse_search_page(include_str!("../tests/testdata/libgen.rs_search_page.html"))
+        .expect("Should parse the results");
+
+    assert_eq!(
+        vec![
+            LibgenMetadata {
+                title: "Governing the Commons: The Evolution of Institutions for Collective Action"
+                    .to_string(),
+                author: "Ostrom, Elinor".to_string(),
+                year: "1990".to_string(),
+                extension: Extension::Pdf,
+                md5: "5f3a1dc2e6d7e9a30b6e0f6f4d4c5ab1".to_string(),
+                filesize: Some(2 * 1024 * 1024),
+                language: Some("English".to_string()),
+                pages: Some(298),
+                publisher: Some("Cambridge University Press".to_string()),
+            },
+            LibgenMetadata {
+                title: "Governing the Commons".to_string(),
+                author: "Elinor Ostrom".to_string(),
+                year: "2015".to_string(),
+                extension: Extension::Epub,
+                md5: "8c0e5b3d1f0a4c4b9a2e7d6f5e4d3c2b".to_string(),
+                filesize: Some(1024 * 1024),
+                language: Some("English".to_string()),
+                pages: Some(280),
+                publisher: Some("Cambridge University Press".to_string()),
+            },
+            LibgenMetadata {
+                title: "Elinor Ostrom's Rules for Radicals: Cooperative Alternatives beyond Markets and States".to_string(),
+                author: "Derek Wall".to_string(),
+                year: "2017".to_string(),
+                extension: Extension::Epub,
+                md5: "0a1b2c3d4e5f60718293a4b5c6d7e8f9".to_string(),
+                filesize: Some(1024 * 1024),
+                language: Some("English".to_string()),
+                pages: Some(192),
+                publisher: Some("Pluto Press".to_string()),
+            },
+        ],
+        got
+    );
+
+    assert_eq!(
+        Ok(vec![]),
+        parse_search_page(r#"<table class="c"><tr><td><b>ID</b></td></tr></table>"#)
+    );
+    assert!(matches!(
+        parse_search_page("<h1>Database maintenance</h1>"),
+        Err(Error::UpstreamUnavailable(_))
+    ));
+}
+
+// LibGen's title search matches any book containing the searched words, so
+// only the results that look like the wanted book are kept: one title
+// contains the other, e.g. with a subtitle, and the author's last name
+// matches. Case and punctuation are ignored.
+pub fn matches_title_and_author(book: &LibgenMetadata, title: &str, author: &str) -> bool {
+    let (book_title, title) = (normalise_text(&book.title), normalise_text(title));
+    let contains = |a: &str, b: &str| format!(" {} ", a).contains(&format!(" {} ", b));
+    if title.is_empty() || !(contains(&book_title, &title) || contains(&title, &book_title)) {
+        return false;
+    }
+
+    let author = normalise_text(author);
+    let Some(last_name) = author
+        .split(' ')
+        .next_back()
+        .filter(|name| !name.is_empty())
+    else {
+        return false;
+    };
+    normalise_text(&book.author)
+        .split(' ')
+        .any(|name| name == last_name)
+}
+
+// Lowercases `text`, and replaces punctuation with single spaces.
+fn normalise_text(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn test_matches_title_and_author() {
+    let book = |title: &str, author: &str| LibgenMetadata {
+        title: title.to_string(),
+        author: author.to_string(),
+        year: "1990".to_string(),
+        extension: Extension::Pdf,
+        md5: "ab13556b96d473c8dfad7165c4704526".to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+
+    for (title, author) in [
+        ("Governing the Commons", "Elinor Ostrom"),
+        ("governing the commons", "Ostrom, Elinor"),
+        (
+            "Governing the Commons: The Evolution of Institutions for Collective Action",
+            "Elinor Ostrom",
+        ),
+        ("Governing  the Commons!", "E. Ostrom; J. Walker"),
+    ] {
+        assert!(
+            matches_title_and_author(
+                &book(title, author),
+                "Governing the Commons",
+                "Elinor Ostrom"
+            ),
+            "{} by {}",
+            title,
+            author
+        );
+    }
+    // The Goodreads title can be the longer one.
+    assert!(matches_title_and_author(
+        &book("Emma", "Jane Austen"),
+        "Emma (Penguin Classics)",
+        "Jane Austen"
+    ));
+
+    for (title, author) in [
+        ("Governing the Commons", "Derek Wall"),
+        ("Elinor Ostrom's Rules for Radicals", "Derek Wall"),
+        ("Governing the Common Good", "Elinor Ostrom"),
+        ("Understanding Institutional Diversity", "Elinor Ostrom"),
+        ("Governing the Commons", ""),
+    ] {
+        assert!(
+            !matches_title_and_author(
+                &book(title, author),
+                "Governing the Commons",
+                "Elinor Ostrom"
+            ),
+            "{} by {}",
+            title,
+            author
+        );
+    }
+    assert!(!matches_title_and_author(
+        &book("Governing the Commons", "Elinor Ostrom"),
+        "Governing the Commons",
+        ""
+    ));
+}
+
+fn looks_like_json(body: &str) -> bool {
+    let body = body.trim_start();
+    body.starts_with(['[', '{', '"']) || body.starts_with("null")
+}
+
+// LibGen usually answers with an array, but sometimes with a bare object, or
+// with `null` or even `"null"` when nothing is found.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MetadataResponse {
+    Many(Vec<LibgenMetadata>),
+    One(Box<LibgenMetadata>),
+    Null(Option<String>),
+}
+
+fn parse_metadata_response(body: &str) -> Result<Vec<LibgenMetadata>, Error> {
+    if body.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    // When overloaded, LibGen answers with an HTML maintenance page and
+    // a 200 status, so we can't rely on the status code alone.
+    if !looks_like_json(body) {
+        return Err(Error::UpstreamUnavailable(excerpt(body)));
+    }
+
+    match serde_json::from_str(body) {
+        Ok(MetadataResponse::Many(books_metadata)) => Ok(books_metadata),
+        Ok(MetadataResponse::One(book_metadata)) => Ok(vec![*book_metadata]),
+        Ok(MetadataResponse::Null(None)) => Ok(vec![]),
+        Ok(MetadataResponse::Null(Some(null))) if null == "null" => Ok(vec![]),
+        Ok(MetadataResponse::Null(Some(_))) | Err(_) => {
+            Err(Error::UpstreamUnavailable(excerpt(body)))
+        }
+    }
+}
+
+#[test]
+fn test_parse_metadata_response() {
+    let emma = || LibgenMetadata {
+        title: "Emma".to_string(),
+        author: "Jane Austen".to_string(),
+        year: "2000".to_string(),
+        extension: Extension::Pdf,
+        md5: "AB13556B96D473C8DFAD7165C4704526".to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+    let object = r#"{"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"AB13556B96D473C8DFAD7165C4704526"}"#;
+    let with_details = r#"{"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"AB13556B96D473C8DFAD7165C4704526","pages":"474","publisher":"Penguin Classics"}"#;
+
+    for (body, want) in [
+        (format!("[{}]", object), Ok(vec![emma()])),
+        (
+            format!("[{}]", with_details),
+            Ok(vec![LibgenMetadata {
+                pages: Some(474),
+                publisher: Some("Penguin Classics".to_string()),
+                ..emma()
+            }]),
+        ),
+        (format!("[{0},{0}]", object), Ok(vec![emma(), emma()])),
+        (format!(" {}\n", object), Ok(vec![emma()])),
+        ("[]".to_string(), Ok(vec![])),
+        ("null".to_string(), Ok(vec![])),
+        (r#""null""#.to_string(), Ok(vec![])),
+        ("".to_string(), Ok(vec![])),
+        (" \n".to_string(), Ok(vec![])),
+        (
+            r#""error""#.to_string(),
+            Err(Error::UpstreamUnavailable(r#""error""#.to_string())),
+        ),
+        (
+            r#"{"error":"oops"}"#.to_string(),
+            Err(Error::UpstreamUnavailable(
+                r#"{"error":"oops"}"#.to_string(),
+            )),
+        ),
+    ] {
+        assert_eq!(want, parse_metadata_response(&body), "{}", body);
+    }
+}
+
+const EXCERPT_MAX_CHARS: usize = 200;
+
+// Returns the beginning of the text content of a response, without HTML
+// tags, to give an idea of what went wrong.
+fn excerpt(body: &str) -> String {
+    let text: String = if looks_like_json(body) {
+        body.to_string()
+    } else {
+        Html::parse_document(body)
+            .root_element()
+            .text()
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match text.char_indices().nth(EXCERPT_MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+#[test]
+fn test_excerpt() {
+    for (body, want) in [
+        (
+            "<html><head><title>Maintenance</title></head><body><h1>Database   maintenance</h1>\n<p>Come back later</p></body></html>",
+            "Maintenance Database maintenance Come back later",
+        ),
+        (r#"[{"title": "Emma""#, r#"[{"title": "Emma""#),
+        ("", ""),
+    ] {
+        assert_eq!(want, excerpt(body));
+    }
+
+    let long_body = "a".repeat(500);
+    assert_eq!(format!("{}...", "a".repeat(200)), excerpt(&long_body));
+}
+
 #[tokio::test]
 #[ignore = "This test calls the LibGen API, don't run it with every file change"]
 async fn third_party_test_get_metadata_from_libgen_api() {
@@ -77,6 +878,12 @@ async fn third_party_test_get_metadata_from_libgen_api() {
         isbn13: Some("9788853001351".to_string()),
         title: None,
         author: None,
+        cover_url: None,
+        pages: None,
+        publisher: None,
+        series: None,
+        series_index: None,
+        format: None,
     };
 
     let got = Libgen::default()
@@ -100,6 +907,12 @@ async fn test_get_metadata_no_isbn() {
         isbn13: None,
         title: Some("Hello".to_string()),
         author: Some("World".to_string()),
+        cover_url: None,
+        pages: None,
+        publisher: None,
+        series: None,
+        series_index: None,
+        format: None,
     };
     let got = Libgen::default().get_metadata(&book_identification).await;
 
@@ -119,24 +932,581 @@ async fn test_get_metadata_http_error() {
         isbn13: Some("123".to_string()),
         title: None,
         author: None,
+        cover_url: None,
+        pages: None,
+        publisher: None,
+        series: None,
+        series_index: None,
+        format: None,
     };
     let libgen = Libgen {
         base_url: "bad url".to_string(),
+        ..Default::default()
     };
     let got = libgen.get_metadata(&book_identification).await;
 
-    assert_eq!(Err(Error::HttpError("builder error".to_string())), got);
+    assert_eq!(
+        Err(Error::HttpError(HttpFailure::other("builder error"))),
+        got
+    );
 }
 
-pub fn find_most_relevant(books_metadata: &[LibgenMetadata]) -> Option<LibgenMetadata> {
-    if books_metadata.is_empty() {
-        return None;
+#[cfg(test)]
+mod test_isbn_fallback {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+    use std::time::{Duration, Instant};
+
+    const EMMA: &str = r#"[{"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"AB13556B96D473C8DFAD7165C4704526"}]"#;
+
+    fn book_identification() -> BookIdentification {
+        BookIdentification {
+            isbn10: Some("0123456789".to_string()),
+            isbn13: Some("9780123456786".to_string()),
+            title: None,
+            author: None,
+            cover_url: None,
+            pages: None,
+            publisher: None,
+            series: None,
+            series_index: None,
+            format: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_isbn13() {
+        let mock_server = MockServer::start();
+        let isbn10_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/")
+                .query_param("isbn", "0123456789")
+                .query_param(
+                    "fields",
+                    "Title,Author,Year,Extension,MD5,Filesize,Language,Pages,Publisher",
+                );
+            then.status(200).body("[]");
+        });
+        let isbn13_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/")
+                .query_param("isbn", "9780123456786")
+                .query_param(
+                    "fields",
+                    "Title,Author,Year,Extension,MD5,Filesize,Language,Pages,Publisher",
+                );
+            then.status(200).body(EMMA);
+        });
+
+        let libgen = Libgen {
+            base_url: mock_server.url("/"),
+            ..Default::default()
+        };
+        let got = libgen
+            .get_metadata(&book_identification())
+            .await
+            .expect("The call should succeed");
+
+        isbn10_mock.assert();
+        isbn13_mock.assert();
+        assert_eq!(1, got.len());
+        assert_eq!("ab13556b96d473c8dfad7165c4704526", got[0].md5);
+    }
+
+    #[tokio::test]
+    async fn test_both_isbns_are_queried_concurrently() {
+        const DELAY: Duration = Duration::from_millis(500);
+        let mock_server = MockServer::start();
+        let isbn10_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/").query_param("isbn", "0123456789");
+            then.status(200).delay(DELAY).body(EMMA);
+        });
+        let isbn13_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/")
+                .query_param("isbn", "9780123456786");
+            then.status(200).delay(DELAY).body(format!(
+                "[{},{}]",
+                EMMA.trim_matches(['[', ']']),
+                r#"{"title":"Emma","author":"Jane Austen","year":"2003","extension":"epub","md5":"EF12556B96D473C8DFAD7165C4704526"}"#
+            ));
+        });
+
+        let libgen = Libgen {
+            base_url: mock_server.url("/"),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let got = libgen
+            .get_metadata(&book_identification())
+            .await
+            .expect("The call should succeed");
+        let elapsed = start.elapsed();
+
+        isbn10_mock.assert();
+        isbn13_mock.assert();
+        // Roughly the slowest query, rather than the sum of both.
+        assert!(elapsed >= DELAY, "{:?}", elapsed);
+        assert!(elapsed < DELAY * 2, "{:?}", elapsed);
+        // Emma was found by both queries.
+        assert_eq!(
+            vec![
+                "ab13556b96d473c8dfad7165c4704526",
+                "ef12556b96d473c8dfad7165c4704526"
+            ],
+            got.iter().map(|book| book.md5.as_str()).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_one_isbn_query_fails() {
+        let mock_server = MockServer::start();
+        let isbn10_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/").query_param("isbn", "0123456789");
+            then.status(200)
+                .body("<html><body><h1>Database maintenance</h1></body></html>");
+        });
+        let isbn13_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/")
+                .query_param("isbn", "9780123456786");
+            then.status(200).body(EMMA);
+        });
+
+        let libgen = Libgen {
+            base_url: mock_server.url("/"),
+            ..Default::default()
+        };
+        let got = libgen
+            .get_metadata(&book_identification())
+            .await
+            .expect("The books found with the ISBN-13 should be returned");
+
+        isbn10_mock.assert();
+        isbn13_mock.assert();
+        assert_eq!(1, got.len());
+    }
+
+    #[tokio::test]
+    async fn test_nothing_found_with_either_isbn() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/");
+            then.status(200).body("[]");
+        });
+
+        let libgen = Libgen {
+            base_url: mock_server.url("/"),
+            ..Default::default()
+        };
+        let got = libgen.get_metadata(&book_identification()).await;
+
+        endpoint_mock.assert_hits(2);
+        assert_eq!(Ok(vec![]), got);
+    }
+}
+
+#[cfg(test)]
+mod test_unavailable {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    async fn get_metadata_from(mock_server: &MockServer) -> Result<Vec<LibgenMetadata>, Error> {
+        let book_identification = BookIdentification {
+            isbn10: Some("123".to_string()),
+            isbn13: None,
+            title: None,
+            author: None,
+            cover_url: None,
+            pages: None,
+            publisher: None,
+            series: None,
+            series_index: None,
+            format: None,
+        };
+        let libgen = Libgen {
+            base_url: mock_server.url("/"),
+            ..Default::default()
+        };
+        libgen.get_metadata(&book_identification).await
+    }
+
+    #[tokio::test]
+    async fn test_html_maintenance_page() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/");
+            then.status(200)
+                .header("content-type", "text/html; charset=UTF-8")
+                .body("<html><body><h1>Database maintenance</h1><p>Please come back later.</p></body></html>");
+        });
+
+        let got = get_metadata_from(&mock_server).await;
+
+        endpoint_mock.assert();
+        assert_eq!(
+            Err(Error::UpstreamUnavailable(
+                "Database maintenance Please come back later.".to_string()
+            )),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_response_shapes() {
+        let emma = r#"{"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"AB13556B96D473C8DFAD7165C4704526"}"#;
+
+        for (body, want_books) in [
+            (format!("[{}]", emma), 1),
+            (emma.to_string(), 1),
+            ("null".to_string(), 0),
+            (r#""null""#.to_string(), 0),
+            ("".to_string(), 0),
+        ] {
+            let mock_server = MockServer::start();
+            let endpoint_mock = mock_server.mock(|when, then| {
+                when.method(GET).path("/");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(&body);
+            });
+
+            let got = get_metadata_from(&mock_server).await;
+
+            endpoint_mock.assert();
+            assert_eq!(
+                Ok(want_books),
+                got.map(|books_metadata| books_metadata.len()),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"[{"title":"Emma","#);
+        });
+
+        let got = get_metadata_from(&mock_server).await;
+
+        endpoint_mock.assert();
+        assert_eq!(
+            Err(Error::UpstreamUnavailable(
+                r#"[{"title":"Emma","#.to_string()
+            )),
+            got
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_get_metadata_normalises_md5s() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/").query_param("isbn", "123");
+        then.status(200).body(
+            r#"[
+                {"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"AB13556B96D473C8DFAD7165C4704526"},
+                {"title":"Emma","author":"Jane Austen","year":"2001","extension":"pdf","md5":"ab13556b96d473c8dfad7165c4704526"},
+                {"title":"Emma","author":"Jane Austen","year":"2002","extension":"epub","md5":" Ef12556b96d473c8dfad7165c4704526 "},
+                {"title":"Emma","author":"Jane Austen","year":"2003","extension":"mobi","md5":"not an md5"},
+                {"title":"Emma","author":"Jane Austen","year":"2004","extension":"mobi","md5":""}
+            ]"#,
+        );
+    });
+
+    let libgen = Libgen {
+        base_url: mock_server.url("/"),
+        ..Default::default()
+    };
+    let got = libgen
+        .get_metadata(&BookIdentification {
+            isbn10: Some("123".to_string()),
+            ..Default::default()
+        })
+        .await
+        .expect("The call should succeed");
+
+    endpoint_mock.assert();
+    // The first two rows are the same file.
+    assert_eq!(
+        vec![
+            "ab13556b96d473c8dfad7165c4704526",
+            "ef12556b96d473c8dfad7165c4704526"
+        ],
+        got.iter().map(|book| book.md5.as_str()).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_get_metadata_dedupes_results() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/").query_param("isbn", "123");
+        then.status(200).body(
+            r#"[
+                {"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"ab13556b96d473c8dfad7165c4704526"},
+                {"title":"Emma","author":"Jane Austen","year":"2000","extension":"pdf","md5":"ab13556b96d473c8dfad7165c4704526"},
+                {"title":"Emma","author":"Jane Austen","year":"2000","extension":"epub","md5":"ef12556b96d473c8dfad7165c4704526"}
+            ]"#,
+        );
+    });
+
+    let book_identification = BookIdentification {
+        isbn10: Some("123".to_string()),
+        isbn13: None,
+        title: None,
+        author: None,
+        cover_url: None,
+        pages: None,
+        publisher: None,
+        series: None,
+        series_index: None,
+        format: None,
+    };
+    let libgen = Libgen {
+        base_url: mock_server.url("/"),
+        ..Default::default()
+    };
+    let got = libgen
+        .get_metadata(&book_identification)
+        .await
+        .expect("The call should succeed");
+
+    endpoint_mock.assert();
+    assert_eq!(
+        vec![Extension::Pdf, Extension::Epub],
+        got.into_iter()
+            .map(|book| book.extension)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_search_by_title() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET)
+            .path("/search.php")
+            .query_param("req", "Governing the Commons")
+            .query_param("column", "title");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body(include_str!("../tests/testdata/libgen.rs_search_page.html"));
+    });
+
+    let libgen = Libgen {
+        base_url: mock_server.url("/json.php"),
+        ..Default::default()
+    };
+    let got = libgen
+        .search_by_title("Governing the Commons")
+        .await
+        .expect("The call should succeed");
+
+    endpoint_mock.assert();
+    assert_eq!(
+        vec![
+            "5f3a1dc2e6d7e9a30b6e0f6f4d4c5ab1",
+            "8c0e5b3d1f0a4c4b9a2e7d6f5e4d3c2b",
+            "0a1b2c3d4e5f60718293a4b5c6d7e8f9"
+        ],
+        got.iter().map(|book| book.md5.as_str()).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_get_metadata_by_md5() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET)
+            .path("/search.php")
+            .query_param("req", "8c0e5b3d1f0a4c4b9a2e7d6f5e4d3c2b")
+            .query_param("column", "md5");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body(include_str!("../tests/testdata/libgen.rs_search_page.html"));
+    });
+
+    let libgen = Libgen {
+        base_url: mock_server.url("/json.php"),
+        ..Default::default()
+    };
+    let got = libgen
+        .get_metadata_by_md5("8C0E5B3D1F0A4C4B9A2E7D6F5E4D3C2B")
+        .await
+        .expect("The call should succeed")
+        .expect("The book should be found");
+
+    endpoint_mock.assert();
+    assert_eq!("Governing the Commons", got.title);
+    assert_eq!(Extension::Epub, got.extension);
+
+    // Other MD5s in the results aren't this book.
+    mock_server.mock(|when, then| {
+        when.method(GET)
+            .path("/search.php")
+            .query_param("req", "ffffffffffffffffffffffffffffffff");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body(include_str!("../tests/testdata/libgen.rs_search_page.html"));
+    });
+    assert_eq!(
+        None,
+        libgen
+            .get_metadata_by_md5("ffffffffffffffffffffffffffffffff")
+            .await
+            .unwrap()
+    );
+    assert_eq!(None, libgen.get_metadata_by_md5("nope").await.unwrap());
+}
+
+// How many of the books LibGen returned were left out by `rank`, and why.
+#[derive(Debug, PartialEq, Default, Clone, Serialize, Deserialize)]
+pub struct FilterReport {
+    pub results: usize,
+    pub duplicates: usize,
+    // Books without an extension can't be converted.
+    pub unknown_extension: usize,
+    // DJVU books, when DJVU can't be converted.
+    pub djvu_excluded: usize,
+    // Books left out by the selection filters of the request, before
+    // ranking. See `selection`.
+    #[serde(default)]
+    pub filtered_out: usize,
+    // The filter that removed the last books, when none passed them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emptied_by: Option<Filter>,
+}
+
+// Why a book was left out by `rank`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Elimination {
+    Duplicate,
+    UnknownExtension,
+    DjvuExcluded,
+}
+
+// Returns the books without duplicates, most relevant first according to
+// `ranker`, along with what was left out.
+pub fn rank(
+    books_metadata: &[LibgenMetadata],
+    ctx: &RankingContext,
+    can_convert_djvu: bool,
+    ranker: &dyn CandidateRanker,
+) -> (Vec<LibgenMetadata>, FilterReport) {
+    let (books, eliminated) = rank_with_eliminations(books_metadata, ctx, can_convert_djvu, ranker);
+    let count = |reason: Elimination| {
+        eliminated
+            .iter()
+            .filter(|(_, elimination)| *elimination == reason)
+            .count()
+    };
+    let report = FilterReport {
+        results: books_metadata.len(),
+        duplicates: count(Elimination::Duplicate),
+        unknown_extension: count(Elimination::UnknownExtension),
+        djvu_excluded: count(Elimination::DjvuExcluded),
+        filtered_out: 0,
+        emptied_by: None,
+    };
+
+    (books, report)
+}
+
+// Same as `rank`, returning each book that was left out.
+// DJVU editions are left out when they can't be converted and other formats
+// are available, unless DJVU is the wanted format.
+pub fn rank_with_eliminations(
+    books_metadata: &[LibgenMetadata],
+    ctx: &RankingContext,
+    can_convert_djvu: bool,
+    ranker: &dyn CandidateRanker,
+) -> (Vec<LibgenMetadata>, Vec<(LibgenMetadata, Elimination)>) {
+    let (books_metadata, duplicates) = partition_duplicates(books_metadata);
+    let mut eliminated: Vec<_> = duplicates
+        .into_iter()
+        .map(|book| (book, Elimination::Duplicate))
+        .collect();
+
+    let (mut books_metadata, unknown): (Vec<_>, Vec<_>) = books_metadata
+        .into_iter()
+        .partition(|book| book.extension != Extension::Other(String::new()));
+    eliminated.extend(
+        unknown
+            .into_iter()
+            .map(|book| (book, Elimination::UnknownExtension)),
+    );
+
+    let wants_djvu = ctx.wanted_extension == Some(Extension::Djvu);
+    let has_other_formats = books_metadata
+        .iter()
+        .any(|book| book.extension != Extension::Djvu);
+    if !wants_djvu && !can_convert_djvu && has_other_formats {
+        let djvu;
+        (books_metadata, djvu) = books_metadata
+            .into_iter()
+            .partition(|book| book.extension != Extension::Djvu);
+        eliminated.extend(
+            djvu.into_iter()
+                .map(|book| (book, Elimination::DjvuExcluded)),
+        );
+    }
+
+    // The sort is stable, so ties keep LibGen's order.
+    books_metadata.sort_by_cached_key(|book| Reverse(ranker.score(book, ctx)));
+    (books_metadata, eliminated)
+}
+
+// LibGen years are free text: "2015", "c1998", "2003-2005", "" or garbage.
+// Returns the first four-digit number, if any.
+pub(crate) fn parse_year(year: &str) -> Option<u16> {
+    year.split(|c: char| !c.is_ascii_digit())
+        .find(|digits| digits.len() == 4)
+        .and_then(|digits| digits.parse().ok())
+}
+
+#[test]
+fn test_parse_year() {
+    for (year, want) in [
+        ("2015", Some(2015)),
+        (" 1998 ", Some(1998)),
+        ("c1998", Some(1998)),
+        ("2003-2005", Some(2003)),
+        ("[2015]", Some(2015)),
+        ("", None),
+        ("unknown", None),
+        ("15", None),
+        ("20150", None),
+    ] {
+        assert_eq!(want, parse_year(year), "{:?}", year);
     }
+}
 
-    let mut books_metadata = books_metadata.to_owned();
-    books_metadata.sort_by(|a, b| a.extension.cmp(&b.extension));
+#[cfg(test)]
+use crate::ranking::DefaultRanker;
 
-    Some(books_metadata[0].clone())
+#[cfg(test)]
+fn ctx(wanted_extension: Option<&Extension>) -> RankingContext {
+    RankingContext {
+        wanted_extension: wanted_extension.cloned(),
+        ..RankingContext::default()
+    }
 }
 
 #[test]
@@ -148,6 +1518,10 @@ fn test_find_most_relevant() {
             year: "2000".to_string(),
             extension: Extension::Pdf,
             md5: "ABCD".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
         },
         LibgenMetadata {
             title: "Pride and Prejudice".to_string(),
@@ -155,6 +1529,10 @@ fn test_find_most_relevant() {
             year: "2000".to_string(),
             extension: Extension::Azw3,
             md5: "EF12".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
         },
         // This is the most relevant, because it has the Mobi extension.
         LibgenMetadata {
@@ -163,6 +1541,10 @@ fn test_find_most_relevant() {
             year: "2000".to_string(),
             extension: Extension::Mobi,
             md5: "3456".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
         },
         LibgenMetadata {
             title: "Pride and Prejudice".to_string(),
@@ -170,37 +1552,574 @@ fn test_find_most_relevant() {
             year: "2000".to_string(),
             extension: Extension::Epub,
             md5: "7890".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
         },
     ];
 
     assert_eq!(
-        Some(books_metadata[2].clone()),
-        find_most_relevant(&books_metadata)
+        Some(&books_metadata[2]),
+        rank(&books_metadata, &ctx(None), true, &DefaultRanker::default())
+            .0
+            .first()
     )
 }
 
 #[test]
 fn test_find_most_relevant_no_books() {
-    assert_eq!(None, find_most_relevant(&vec![]));
+    assert_eq!(
+        None,
+        rank(&[], &ctx(None), true, &DefaultRanker::default())
+            .0
+            .first()
+    );
+}
+
+#[test]
+fn test_rank() {
+    let book = |extension: Extension, md5: &str| LibgenMetadata {
+        title: "Pride and Prejudice".to_string(),
+        author: "Jane Austen".to_string(),
+        year: "2000".to_string(),
+        extension,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+    let books_metadata = vec![
+        book(Extension::Pdf, "ABCD"),
+        book(Extension::Azw3, "EF12"),
+        book(Extension::Mobi, "3456"),
+        book(Extension::Mobi, "3456"),
+        book(Extension::Epub, "7890"),
+    ];
+
+    assert_eq!(
+        vec!["3456", "7890", "EF12", "ABCD"],
+        rank(&books_metadata, &ctx(None), true, &DefaultRanker::default())
+            .0
+            .iter()
+            .map(|book| book.md5.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        (vec![], FilterReport::default()),
+        rank(&[], &ctx(None), true, &DefaultRanker::default())
+    );
+}
+
+#[test]
+fn test_rank_djvu() {
+    let book = |extension: Extension, md5: &str| LibgenMetadata {
+        title: "Pride and Prejudice".to_string(),
+        author: "Jane Austen".to_string(),
+        year: "2000".to_string(),
+        extension,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+    let books_metadata = vec![
+        book(Extension::Djvu, "DJVU"),
+        book(Extension::Doc, "DOC"),
+        book(Extension::Pdf, "PDF"),
+    ];
+    let md5s = |(books, _): (Vec<LibgenMetadata>, FilterReport)| {
+        books.into_iter().map(|book| book.md5).collect::<Vec<_>>()
+    };
+
+    for (wanted_extension, can_convert_djvu, want) in [
+        // DJVU goes after PDF...
+        (Some(&Extension::Mobi), true, vec!["PDF", "DJVU", "DOC"]),
+        (None, true, vec!["PDF", "DJVU", "DOC"]),
+        // ...and is skipped when it can't be converted...
+        (Some(&Extension::Mobi), false, vec!["PDF", "DOC"]),
+        // ...unless DJVU is wanted.
+        (Some(&Extension::Djvu), false, vec!["DJVU", "PDF", "DOC"]),
+    ] {
+        assert_eq!(
+            want,
+            md5s(rank(
+                &books_metadata,
+                &ctx(wanted_extension),
+                can_convert_djvu,
+                &DefaultRanker::default()
+            ))
+        );
+    }
+
+    // DJVU is kept when there is nothing else.
+    assert_eq!(
+        vec!["DJVU"],
+        md5s(rank(
+            &[book(Extension::Djvu, "DJVU")],
+            &ctx(Some(&Extension::Mobi)),
+            false,
+            &DefaultRanker::default()
+        ))
+    );
+}
+
+#[test]
+fn test_rank_prefers_newer_editions() {
+    let book = |extension: Extension, year: &str, md5: &str| LibgenMetadata {
+        title: "Pride and Prejudice".to_string(),
+        author: "Jane Austen".to_string(),
+        year: year.to_string(),
+        extension,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+    let books_metadata = vec![
+        book(Extension::Epub, "garbage", "GARBAGE"),
+        book(Extension::Epub, "1998", "1998"),
+        book(Extension::Epub, "", "EMPTY"),
+        book(Extension::Epub, "2015", "2015"),
+        book(Extension::Mobi, "1990", "MOBI"),
+        book(Extension::Epub, "unknown", "UNKNOWN"),
+        book(Extension::Epub, "c2015", "C2015"),
+    ];
+    let md5s = |books: &[LibgenMetadata]| {
+        rank(books, &ctx(None), true, &DefaultRanker::default())
+            .0
+            .into_iter()
+            .map(|book| book.md5)
+            .collect::<Vec<_>>()
+    };
+
+    // The format still comes first. Editions without a year come last, in
+    // LibGen's order.
+    let want = vec![
+        "MOBI", "2015", "C2015", "1998", "GARBAGE", "EMPTY", "UNKNOWN",
+    ];
+    assert_eq!(want, md5s(&books_metadata));
+
+    // Ties keep the order they were found in.
+    let mut reversed = books_metadata.clone();
+    reversed.reverse();
+    assert_eq!(
+        vec!["MOBI", "C2015", "2015", "1998", "UNKNOWN", "EMPTY", "GARBAGE"],
+        md5s(&reversed)
+    );
+}
+
+#[test]
+fn test_rank_report() {
+    let book = |extension: Extension, md5: &str| LibgenMetadata {
+        title: "Pride and Prejudice".to_string(),
+        author: "Jane Austen".to_string(),
+        year: "2000".to_string(),
+        extension,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+
+    let (books, report) = rank(
+        &[
+            book(Extension::Djvu, "DJVU"),
+            book(Extension::Pdf, "PDF"),
+            book(Extension::Pdf, "PDF"),
+            book(Extension::Other(String::new()), "UNKNOWN"),
+        ],
+        &ctx(Some(&Extension::Mobi)),
+        false,
+        &DefaultRanker::default(),
+    );
+    assert_eq!(vec![book(Extension::Pdf, "PDF")], books);
+    assert_eq!(
+        FilterReport {
+            results: 4,
+            duplicates: 1,
+            unknown_extension: 1,
+            djvu_excluded: 1,
+            filtered_out: 0,
+            emptied_by: None,
+        },
+        report
+    );
+
+    // Everything is filtered out.
+    let (books, report) = rank(
+        &[
+            book(Extension::Other(String::new()), "A"),
+            book(Extension::Other(String::new()), "A"),
+            LibgenMetadata {
+                year: "2010".to_string(),
+                ..book(Extension::Other(String::new()), "B")
+            },
+        ],
+        &ctx(None),
+        true,
+        &DefaultRanker::default(),
+    );
+    assert_eq!(Vec::<LibgenMetadata>::new(), books);
+    assert_eq!(
+        FilterReport {
+            results: 3,
+            duplicates: 1,
+            unknown_extension: 2,
+            djvu_excluded: 0,
+            filtered_out: 0,
+            emptied_by: None,
+        },
+        report
+    );
+}
+
+#[test]
+fn test_rank_with_custom_ranker() {
+    struct PdfFirst;
+
+    impl CandidateRanker for PdfFirst {
+        fn score(&self, candidate: &LibgenMetadata, _: &RankingContext) -> i64 {
+            i64::from(candidate.extension == Extension::Pdf)
+        }
+    }
+
+    let book = |extension: Extension, md5: &str| LibgenMetadata {
+        title: "Pride and Prejudice".to_string(),
+        author: "Jane Austen".to_string(),
+        year: "2000".to_string(),
+        extension,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
+    };
+    let books_metadata = vec![
+        book(Extension::Mobi, "MOBI"),
+        book(Extension::Pdf, "PDF"),
+        book(Extension::Epub, "EPUB"),
+    ];
+
+    let (books, _) = rank(&books_metadata, &ctx(None), true, &PdfFirst);
+    assert_eq!(
+        vec!["PDF", "MOBI", "EPUB"],
+        books
+            .iter()
+            .map(|book| book.md5.as_str())
+            .collect::<Vec<_>>()
+    );
+}
+
+// LibGen often returns the same book several times: mirrored uploads share
+// the same MD5, and re-uploads of the same file get a different MD5 but
+// identical metadata. Only the first occurrence of each is kept.
+pub fn dedupe(books_metadata: &[LibgenMetadata]) -> Vec<LibgenMetadata> {
+    partition_duplicates(books_metadata).0
+}
+
+// Returns the books to keep, and the duplicates.
+fn partition_duplicates(
+    books_metadata: &[LibgenMetadata],
+) -> (Vec<LibgenMetadata>, Vec<LibgenMetadata>) {
+    let mut seen_md5s = HashSet::new();
+    let mut seen_editions = HashSet::new();
+
+    let (kept, duplicates): (Vec<&LibgenMetadata>, Vec<&LibgenMetadata>) =
+        books_metadata.iter().partition(|book| {
+            seen_md5s.insert(book.md5.as_str())
+                && seen_editions.insert((
+                    book.title.as_str(),
+                    book.author.as_str(),
+                    book.year.as_str(),
+                    &book.extension,
+                ))
+        });
+
+    (
+        kept.into_iter().cloned().collect(),
+        duplicates.into_iter().cloned().collect(),
+    )
+}
+
+#[cfg(test)]
+mod test_dedupe {
+    use super::*;
+
+    fn book(title: &str, year: &str, extension: Extension, md5: &str) -> LibgenMetadata {
+        LibgenMetadata {
+            title: title.to_string(),
+            author: "Jane Austen".to_string(),
+            year: year.to_string(),
+            extension,
+            md5: md5.to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }
+    }
+
+    #[test]
+    fn test_same_md5() {
+        let books = vec![
+            book("Pride and Prejudice", "2000", Extension::Pdf, "ABCD"),
+            book(
+                "Pride and Prejudice (mirror)",
+                "2001",
+                Extension::Epub,
+                "ABCD",
+            ),
+            book("Pride and Prejudice", "2000", Extension::Epub, "EF12"),
+        ];
+
+        assert_eq!(vec![books[0].clone(), books[2].clone()], dedupe(&books));
+    }
+
+    #[test]
+    fn test_same_edition_different_md5() {
+        let books = vec![
+            book("Pride and Prejudice", "2000", Extension::Pdf, "ABCD"),
+            book("Pride and Prejudice", "2000", Extension::Pdf, "EF12"),
+            book("Pride and Prejudice", "2005", Extension::Pdf, "3456"),
+            book("Pride and Prejudice", "2000", Extension::Mobi, "7890"),
+        ];
+
+        assert_eq!(
+            vec![books[0].clone(), books[2].clone(), books[3].clone()],
+            dedupe(&books)
+        );
+    }
+
+    #[test]
+    fn test_no_duplicates() {
+        let books = vec![
+            book("Pride and Prejudice", "2000", Extension::Pdf, "ABCD"),
+            book("Pride and Prejudice", "2000", Extension::Mobi, "EF12"),
+        ];
+
+        assert_eq!(books, dedupe(&books));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(Vec::<LibgenMetadata>::new(), dedupe(&[]));
+    }
+}
+
+// Tries each store in order, and returns the first non-empty result, or the
+// result of the last store.
+pub struct FailoverMetadataStore {
+    pub stores: Vec<Box<dyn MetadataStore + Send + Sync + 'static>>,
+}
+
+#[async_trait]
+impl MetadataStore for FailoverMetadataStore {
+    async fn get_metadata(
+        &self,
+        book_identification: &BookIdentification,
+    ) -> Result<Vec<LibgenMetadata>, Error> {
+        let mut result = Ok(vec![]);
+        for store in &self.stores {
+            result = store.get_metadata(book_identification).await;
+            match &result {
+                Ok(books_metadata) if !books_metadata.is_empty() => return result,
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!("Could not get metadata, trying the next source: {:?}", err)
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn search_by_title(&self, title: &str) -> Result<Vec<LibgenMetadata>, Error> {
+        let mut result = Ok(vec![]);
+        for store in &self.stores {
+            result = store.search_by_title(title).await;
+            match &result {
+                Ok(books_metadata) if !books_metadata.is_empty() => return result,
+                Ok(_) => {}
+                Err(err) => tracing::warn!(
+                    "Could not search by title, trying the next source: {:?}",
+                    err
+                ),
+            }
+        }
+
+        result
+    }
+
+    async fn get_metadata_by_md5(&self, md5: &str) -> Result<Option<LibgenMetadata>, Error> {
+        let mut result = Ok(None);
+        for store in &self.stores {
+            result = store.get_metadata_by_md5(md5).await;
+            match &result {
+                Ok(Some(_)) => return result,
+                Ok(None) => {}
+                Err(err) => tracing::warn!(
+                    "Could not look up {}, trying the next source: {:?}",
+                    md5,
+                    err
+                ),
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test_failover {
+    use super::*;
+
+    fn store(result: Result<Vec<LibgenMetadata>, Error>) -> Box<MockMetadataStore> {
+        let mut store = MockMetadataStore::new();
+        store
+            .expect_get_metadata()
+            .return_once(move |_| Box::pin(async move { result }));
+        Box::new(store)
+    }
+
+    fn unused_store() -> Box<MockMetadataStore> {
+        let mut store = MockMetadataStore::new();
+        store.expect_get_metadata().never();
+        Box::new(store)
+    }
+
+    fn book() -> LibgenMetadata {
+        LibgenMetadata {
+            title: "1984".to_string(),
+            author: "George Orwell".to_string(),
+            year: "2017".to_string(),
+            extension: Extension::Epub,
+            md5: "/books/1984.epub".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_store_found_books() {
+        let store = FailoverMetadataStore {
+            stores: vec![store(Ok(vec![book()])), unused_store()],
+        };
+
+        let got = store.get_metadata(&BookIdentification::default()).await;
+        assert_eq!(Ok(vec![book()]), got);
+    }
+
+    #[tokio::test]
+    async fn test_first_store_found_nothing() {
+        let store = FailoverMetadataStore {
+            stores: vec![store(Ok(vec![])), store(Ok(vec![book()]))],
+        };
+
+        let got = store.get_metadata(&BookIdentification::default()).await;
+        assert_eq!(Ok(vec![book()]), got);
+    }
+
+    #[tokio::test]
+    async fn test_first_store_failed() {
+        let store = FailoverMetadataStore {
+            stores: vec![
+                store(Err(Error::LocalLibrary("no such table".to_string()))),
+                store(Ok(vec![])),
+            ],
+        };
+
+        let got = store.get_metadata(&BookIdentification::default()).await;
+        assert_eq!(Ok(vec![]), got);
+    }
+
+    #[tokio::test]
+    async fn test_last_store_failed() {
+        let store = FailoverMetadataStore {
+            stores: vec![
+                store(Ok(vec![])),
+                store(Err(Error::HttpError(HttpFailure::other("timeout")))),
+            ],
+        };
+
+        let got = store.get_metadata(&BookIdentification::default()).await;
+        assert_eq!(Err(Error::HttpError(HttpFailure::other("timeout"))), got);
+    }
+
+    #[tokio::test]
+    async fn test_no_stores() {
+        let store = FailoverMetadataStore { stores: vec![] };
+
+        let got = store.get_metadata(&BookIdentification::default()).await;
+        assert_eq!(Ok(vec![]), got);
+    }
+
+    #[tokio::test]
+    async fn test_search_by_title() {
+        let search_store = |result: Result<Vec<LibgenMetadata>, Error>| {
+            let mut store = MockMetadataStore::new();
+            store
+                .expect_search_by_title()
+                .withf(|title| title == "1984")
+                .return_once(move |_| Box::pin(async move { result }));
+            Box::new(store)
+        };
+        let store = FailoverMetadataStore {
+            stores: vec![
+                search_store(Err(Error::LocalLibrary("no such table".to_string()))),
+                search_store(Ok(vec![book()])),
+            ],
+        };
+
+        assert_eq!(Ok(vec![book()]), store.search_by_title("1984").await);
+    }
 }
 
 impl Default for Libgen {
     fn default() -> Self {
         Self {
             base_url: BASE_URL.to_string(),
+            client: reqwest::Client::new(),
+            limits: ConnectionLimits::default(),
+            clock: SharedClock::default(),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     MissingIndentificationInfo,
     NoIsbn { title: String, author: String },
-    HttpError(String),
+    HttpError(HttpFailure),
+    // LibGen answered with something else than the expected JSON, usually
+    // a maintenance page. Contains an excerpt of the response.
+    UpstreamUnavailable(String),
+    // The local Calibre library could not be read.
+    LocalLibrary(String),
+    // LibGen asked us to slow down.
+    RateLimited(RateLimit),
 }
 
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self {
-        Self::HttpError(err.to_string())
+        Self::HttpError(HttpFailure::from(err))
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::Http(err) => Self::from(err),
+            SendError::RateLimited(limit) => Self::RateLimited(limit),
+            SendError::CircuitOpen(open) => Self::UpstreamUnavailable(open.to_string()),
+            SendError::Replay(message) => Self::UpstreamUnavailable(message),
+        }
     }
 }
@@ -0,0 +1,67 @@
+//! Module libgen_li can find download links for a book on libgen.li, from
+//! its MD5 hash. It runs the same software as libgen.rocks.
+
+use crate::{
+    http_client::ConnectionLimits,
+    libgen_rocks::get_download_links_from_ads_page,
+    library_dot_lol::{DownloadLinks, DownloadLinksStore, Error},
+};
+use async_trait::async_trait;
+
+pub(crate) const BASE_URL: &str = "https://libgen.li";
+
+pub struct LibgenLi {
+    pub base_url: String,
+    pub client: reqwest::Client,
+    pub limits: ConnectionLimits,
+}
+
+#[async_trait]
+impl DownloadLinksStore for LibgenLi {
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, Error> {
+        get_download_links_from_ads_page(&self.client, &self.limits, &self.base_url, id).await
+    }
+}
+
+impl Default for LibgenLi {
+    fn default() -> Self {
+        Self {
+            base_url: BASE_URL.to_string(),
+            client: reqwest::Client::new(),
+            limits: ConnectionLimits::default(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_get_download_links() {
+    use httpmock::{Method::GET, MockServer};
+
+    let mock_server = MockServer::start();
+    let libgen_li = LibgenLi {
+        base_url: mock_server.base_url(),
+        ..Default::default()
+    };
+
+    let endpoint_mock = mock_server.mock(|when, then| {
+        when.method(GET)
+            .path("/ads.php")
+            .query_param("md5", "AB13556B96D473C8DFAD7165C4704526");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body(include_str!("../tests/testdata/libgen.rocks_ads_page.html"));
+    });
+    let got = libgen_li
+        .get_download_links("AB13556B96D473C8DFAD7165C4704526")
+        .await
+        .expect("Should find the download link");
+
+    endpoint_mock.assert();
+    assert_eq!(
+        format!(
+            "{}/get.php?md5=ab13556b96d473c8dfad7165c4704526&key=4PXTS0C0HS3QVBW6",
+            mock_server.base_url()
+        ),
+        got.http
+    );
+}
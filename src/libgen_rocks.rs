@@ -0,0 +1,206 @@
+//! Module libgen_rocks can find download links for a book on libgen.rocks,
+//! from its MD5 hash.
+//!
+//! The "ads" page (`ads.php?md5=...`) contains a `get.php` link with a
+//! short-lived key, which is the only download link we can get from it.
+
+use crate::{
+    http_client::{self, ConnectionLimits},
+    library_dot_lol::{DownloadLinks, DownloadLinksStore, Error},
+};
+use async_trait::async_trait;
+use scraper::{Html, Selector};
+
+pub(crate) const BASE_URL: &str = "https://libgen.rocks";
+
+pub struct LibgenRocks {
+    pub base_url: String,
+    pub client: reqwest::Client,
+    pub limits: ConnectionLimits,
+}
+
+#[async_trait]
+impl DownloadLinksStore for LibgenRocks {
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, Error> {
+        get_download_links_from_ads_page(&self.client, &self.limits, &self.base_url, id).await
+    }
+}
+
+impl Default for LibgenRocks {
+    fn default() -> Self {
+        Self {
+            base_url: BASE_URL.to_string(),
+            client: reqwest::Client::new(),
+            limits: ConnectionLimits::default(),
+        }
+    }
+}
+
+// libgen.rocks and libgen.li run the same software, so their ads pages
+// can be scraped the same way.
+pub(crate) async fn get_download_links_from_ads_page(
+    client: &reqwest::Client,
+    limits: &ConnectionLimits,
+    base_url: &str,
+    id: &str,
+) -> Result<DownloadLinks, Error> {
+    let page_url = format!("{base_url}/ads.php?md5={id}", base_url = base_url, id = id);
+    let _permit = limits.metadata().await;
+    let body = http_client::send_once(client.get(page_url), limits)
+        .await?
+        .text()
+        .await?;
+    let document = Html::parse_document(&body);
+
+    let key =
+        extract_key(&document).ok_or_else(|| Error::MissingDownloadLinks(base_url.to_string()))?;
+
+    Ok(DownloadLinks {
+        http: format!(
+            "{base_url}/get.php?md5={id}&key={key}",
+            base_url = base_url,
+            id = id.to_lowercase(),
+            key = key
+        ),
+        ..DownloadLinks::default()
+    })
+}
+
+fn extract_key(fragment: &Html) -> Option<String> {
+    let selector = Selector::parse(r#"a[href*="get.php?md5="]"#).ok()?;
+    let href = fragment.select(&selector).next()?.value().attr("href")?;
+
+    href.split(['?', '&'])
+        .find_map(|param| param.strip_prefix("key="))
+        .filter(|key| !key.is_empty())
+        .map(|key| key.to_string())
+}
+
+#[test]
+fn test_extract_key() {
+    let fragment =
+        Html::parse_document(include_str!("../tests/testdata/libgen.rocks_ads_page.html"));
+
+    assert_eq!(Some("4PXTS0C0HS3QVBW6".to_string()), extract_key(&fragment));
+}
+
+#[test]
+fn test_extract_key_missing() {
+    let fragment = Html::parse_fragment(
+        r#"<td><a href="get.php?md5=ab13556b96d473c8dfad7165c4704526"><h2>GET</h2></a></td>"#,
+    );
+
+    assert_eq!(None, extract_key(&fragment));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    #[tokio::test]
+    async fn test_get_download_links() {
+        let mock_server = MockServer::start();
+        let libgen_rocks = LibgenRocks {
+            base_url: mock_server.base_url(),
+            ..Default::default()
+        };
+
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/ads.php")
+                .query_param("md5", "AB13556B96D473C8DFAD7165C4704526");
+            then.status(200)
+                .header("content-type", "text/html")
+                .body(include_str!("../tests/testdata/libgen.rocks_ads_page.html"));
+        });
+        let got = libgen_rocks
+            .get_download_links("AB13556B96D473C8DFAD7165C4704526")
+            .await;
+
+        endpoint_mock.assert();
+        assert_eq!(
+            Ok(DownloadLinks {
+                http: format!(
+                    "{}/get.php?md5=ab13556b96d473c8dfad7165c4704526&key=4PXTS0C0HS3QVBW6",
+                    mock_server.base_url()
+                ),
+                ..DownloadLinks::default()
+            }),
+            got,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_no_key() {
+        let mock_server = MockServer::start();
+        let libgen_rocks = LibgenRocks {
+            base_url: mock_server.base_url(),
+            ..Default::default()
+        };
+
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/ads.php");
+            then.status(200).body("<html><body>Not found</body></html>");
+        });
+        let got = libgen_rocks.get_download_links("ABCD").await;
+
+        endpoint_mock.assert();
+        assert_eq!(
+            Err(Error::MissingDownloadLinks(mock_server.base_url())),
+            got
+        );
+    }
+
+    // library.lol sometimes answers with a page without its download links,
+    // e.g. when the file isn't on the mirror: libgen.rocks' single link is
+    // used instead.
+    #[tokio::test]
+    async fn test_failover_from_library_dot_lol() {
+        use crate::library_dot_lol::{FailoverDownloadLinksStore, LibraryDotLol};
+
+        let library_dot_lol = MockServer::start();
+        let library_dot_lol_mock = library_dot_lol.mock(|when, then| {
+            when.method(GET).path("/AB13556B96D473C8DFAD7165C4704526");
+            then.status(200)
+                .header("content-type", "text/html")
+                .body("<html><body><h1>File not found on this mirror</h1></body></html>");
+        });
+        let libgen_rocks = MockServer::start();
+        let libgen_rocks_mock = libgen_rocks.mock(|when, then| {
+            when.method(GET)
+                .path("/ads.php")
+                .query_param("md5", "AB13556B96D473C8DFAD7165C4704526");
+            then.status(200)
+                .header("content-type", "text/html")
+                .body(include_str!("../tests/testdata/libgen.rocks_ads_page.html"));
+        });
+        let store = FailoverDownloadLinksStore {
+            stores: vec![
+                Box::new(LibraryDotLol {
+                    base_url: library_dot_lol.base_url(),
+                    ..Default::default()
+                }),
+                Box::new(LibgenRocks {
+                    base_url: libgen_rocks.base_url(),
+                    ..Default::default()
+                }),
+            ],
+        };
+
+        let got = store
+            .get_download_links("AB13556B96D473C8DFAD7165C4704526")
+            .await
+            .expect("libgen.rocks should have a link");
+
+        library_dot_lol_mock.assert();
+        libgen_rocks_mock.assert();
+        let link = format!(
+            "{}/get.php?md5=ab13556b96d473c8dfad7165c4704526&key=4PXTS0C0HS3QVBW6",
+            libgen_rocks.base_url()
+        );
+        // The only link, downloaded over HTTP.
+        assert_eq!(vec![("HTTP".to_string(), link.clone())], got.named_links());
+        assert_eq!(link, got.best_link());
+    }
+}
@@ -0,0 +1,586 @@
+//! Module library keeps the books LibReads downloaded, so they are served
+//! from the library the next time they are requested instead of going back
+//! to LibGen.
+//!
+//! Each book is stored under its LibGen MD5, next to a `metadata.json`
+//! describing it, in a `file_store::FileStore`:
+//!
+//! ```text
+//! {md5}/{title}.{extension}
+//! {md5}/metadata.json
+//! ```
+//!
+//! Entries also record the book they were asked for as, see `book_ref`,
+//! although editions are only looked up by MD5 and ISBN.
+//!
+//! The index used for lookups is rebuilt from the `metadata.json` files the
+//! first time the library is used.
+//!
+//! A book is stored by one writer at a time: the queue, a batch and a request
+//! can all download it at once. Readers wait for a book being stored, or
+//! download it again when storing it failed.
+
+use crate::{
+    book_ref::BookRef,
+    clock::{unix_seconds, SharedClock},
+    convert::sanitise_title,
+    file_store::{self, FileStore, PRESIGNED_URL_TTL},
+    goodreads::{canonical_isbn, BookIdentification},
+    keyed_lock::KeyedLocks,
+    libgen::{self, LibgenMetadata, MetadataStore},
+    library_dot_lol::DownloadLinks,
+    output_path,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio::sync::OnceCell;
+use utoipa::ToSchema;
+
+const METADATA_FILE: &str = "metadata.json";
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LibraryEntry {
+    // The LibGen edition the book was downloaded from, with the extension of
+    // the stored file.
+    pub metadata: LibgenMetadata,
+    pub isbn10: Option<String>,
+    pub isbn13: Option<String>,
+    // Unknown for books downloaded by MD5, and those stored before it was
+    // recorded.
+    #[serde(default)]
+    pub book: Option<BookRef>,
+    // Name of the book file, in the book's directory.
+    pub file: String,
+    // Seconds since the Unix epoch.
+    pub stored_at: u64,
+}
+
+impl LibraryEntry {
+    // The key of the book file in the store.
+    fn key(&self) -> String {
+        format!("{}/{}", self.metadata.md5, self.file)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Library {
+    files: Arc<dyn FileStore>,
+    // Entries by MD5, read from the store on first use.
+    index: Arc<OnceCell<RwLock<HashMap<String, LibraryEntry>>>>,
+    // Held by MD5 while an entry is written or deleted. An entry holds a
+    // single book, whatever its format.
+    writes: KeyedLocks,
+    // Timestamps stored books.
+    clock: SharedClock,
+}
+
+impl Library {
+    // Opens the library kept in `files`.
+    pub fn new(files: Arc<dyn FileStore>) -> Self {
+        Self {
+            files,
+            index: Arc::new(OnceCell::new()),
+            writes: KeyedLocks::default(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    async fn index(&self) -> &RwLock<HashMap<String, LibraryEntry>> {
+        self.index
+            .get_or_init(|| async { RwLock::new(scan(&*self.files).await) })
+            .await
+    }
+
+    // Returns the books in the library, most recently stored first.
+    pub async fn list(&self) -> Vec<LibraryEntry> {
+        let mut entries: Vec<_> = self
+            .index()
+            .await
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| {
+            b.stored_at
+                .cmp(&a.stored_at)
+                .then_with(|| a.metadata.md5.cmp(&b.metadata.md5))
+        });
+        entries
+    }
+
+    // Waits for the book to be stored first, when it is being stored.
+    pub async fn get(&self, md5: &str) -> Option<LibraryEntry> {
+        let md5 = md5.to_uppercase();
+        drop(self.writes.lock(&md5).await);
+        self.index().await.read().unwrap().get(&md5).cloned()
+    }
+
+    // Finds a book by ISBN, in its canonical form.
+    pub async fn find(&self, book_identification: &BookIdentification) -> Option<LibraryEntry> {
+        let canonical = book_identification.canonical();
+        let isbns: Vec<String> = [canonical.isbn10, canonical.isbn13]
+            .into_iter()
+            .flatten()
+            .collect();
+        if isbns.is_empty() {
+            return None;
+        }
+
+        self.list().await.into_iter().find(|entry| {
+            [&entry.isbn10, &entry.isbn13]
+                .into_iter()
+                .flatten()
+                .filter_map(|isbn| canonical_isbn(isbn))
+                .any(|isbn| isbns.contains(&isbn))
+        })
+    }
+
+    // Returns a stored book, with a `file://` link to it, or a presigned URL
+    // when it isn't on disk.
+    pub async fn get_with_links(&self, md5: &str) -> Option<(LibgenMetadata, DownloadLinks)> {
+        let entry = self.get(md5).await?;
+        let http = match self.path(&entry) {
+            Some(path) => reqwest::Url::from_file_path(path.canonicalize().ok()?)
+                .ok()?
+                .to_string(),
+            None => self
+                .files
+                .presigned_url(&entry.key(), PRESIGNED_URL_TTL)
+                .await
+                .ok()??,
+        };
+        let download_links = DownloadLinks {
+            http,
+            ..Default::default()
+        };
+        Some((entry.metadata, download_links))
+    }
+
+    // Where the book file of `entry` is, when the library is on disk.
+    pub fn path(&self, entry: &LibraryEntry) -> Option<PathBuf> {
+        self.files.local_path(&entry.key())
+    }
+
+    // Copies `file` into the library. An existing entry for the same MD5 is
+    // replaced.
+    pub async fn store(
+        &self,
+        metadata: &LibgenMetadata,
+        book_identification: &BookIdentification,
+        book: Option<&BookRef>,
+        file: &Path,
+    ) -> Result<LibraryEntry, Error> {
+        let md5 = entry_dir_name(&metadata.md5).ok_or(Error::InvalidMd5)?;
+        let title = sanitise_title(&metadata.title);
+        let title = if title.is_empty() { &md5 } else { &title };
+        let entry = LibraryEntry {
+            metadata: LibgenMetadata {
+                md5: md5.clone(),
+                ..metadata.clone()
+            },
+            isbn10: book_identification.isbn10.clone(),
+            isbn13: book_identification.isbn13.clone(),
+            book: book.cloned(),
+            file: output_path::escape_reserved(format!("{}.{}", title, metadata.extension)),
+            stored_at: unix_seconds(&*self.clock),
+        };
+
+        let index = self.index().await;
+        let _write = self.writes.lock(&md5).await;
+        // Left out of the index until the new one is complete: readers
+        // download the book again if storing it fails.
+        index.write().unwrap().remove(&md5);
+        self.delete_entry(&md5).await?;
+        self.files
+            .put(&entry.key(), file_store::file_stream(file).await?)
+            .await?;
+        // Written last: a book without its metadata is ignored when the index
+        // is rebuilt.
+        let metadata_file = serde_json::to_vec_pretty(&entry)?;
+        self.files
+            .put(
+                &format!("{}/{}", md5, METADATA_FILE),
+                file_store::bytes_stream(metadata_file),
+            )
+            .await?;
+
+        index.write().unwrap().insert(md5, entry.clone());
+        Ok(entry)
+    }
+
+    // Deletes a book from the library.
+    pub async fn remove(&self, md5: &str) -> Result<(), Error> {
+        let md5 = entry_dir_name(md5).ok_or(Error::InvalidMd5)?;
+        let _write = self.writes.lock(&md5).await;
+        if self.index().await.write().unwrap().remove(&md5).is_none() {
+            return Err(Error::NotFound(md5));
+        }
+        self.delete_entry(&md5).await
+    }
+
+    // Deletes every file stored for `md5`, metadata first.
+    async fn delete_entry(&self, md5: &str) -> Result<(), Error> {
+        let metadata_key = format!("{}/{}", md5, METADATA_FILE);
+        self.files.delete(&metadata_key).await?;
+        for key in self.files.list(&format!("{}/", md5)).await? {
+            self.files.delete(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+// Only LibGen MD5s are stored: other IDs, such as the file paths of a
+// Calibre library, can't be used as keys.
+fn entry_dir_name(md5: &str) -> Option<String> {
+    let is_md5 = !md5.is_empty() && md5.chars().all(|c| c.is_ascii_alphanumeric());
+    is_md5.then(|| md5.to_uppercase())
+}
+
+// Reads every `metadata.json` in `files`. Entries whose book file is missing
+// are skipped.
+async fn scan(files: &dyn FileStore) -> HashMap<String, LibraryEntry> {
+    let keys = match files.list("").await {
+        Ok(keys) => keys,
+        Err(err) => {
+            tracing::warn!("Could not read the library: {}", err);
+            return HashMap::new();
+        }
+    };
+
+    let mut index = HashMap::new();
+    let metadata_keys = keys
+        .iter()
+        .filter(|key| key.split('/').nth(1) == Some(METADATA_FILE));
+    for key in metadata_keys {
+        let entry = match files.get(key).await {
+            Ok(body) => file_store::read_to_vec(body)
+                .await
+                .ok()
+                .and_then(|data| serde_json::from_slice::<LibraryEntry>(&data).ok()),
+            Err(_) => None,
+        };
+        let Some(entry) = entry else {
+            tracing::warn!("Skipping invalid library entry {}", key);
+            continue;
+        };
+        if files.exists(&entry.key()).await != Ok(true) {
+            tracing::warn!("Skipping library entry {} without its book", key);
+            continue;
+        }
+        index.insert(entry.metadata.md5.clone(), entry);
+    }
+
+    index
+}
+
+#[async_trait]
+impl MetadataStore for Library {
+    async fn get_metadata(
+        &self,
+        book_identification: &BookIdentification,
+    ) -> Result<Vec<LibgenMetadata>, libgen::Error> {
+        Ok(self
+            .find(book_identification)
+            .await
+            .map(|entry| entry.metadata)
+            .into_iter()
+            .collect())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    InvalidMd5,
+    NotFound(String),
+    Io(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+impl From<file_store::Error> for Error {
+    fn from(err: file_store::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, extension::Extension, file_store::LocalFileStore};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const MD5: &str = "AB13556B96D473C8DFAD7165C4704526";
+    const BOOK: &str = "tests/testdata/dummy_ebook.epub";
+
+    fn library_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libreads_library_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open(dir: &Path) -> Library {
+        Library::new(Arc::new(LocalFileStore::new(dir)))
+    }
+
+    fn metadata() -> LibgenMetadata {
+        LibgenMetadata {
+            title: "1984".to_string(),
+            author: "George Orwell".to_string(),
+            year: "2017".to_string(),
+            extension: Extension::Epub,
+            md5: MD5.to_lowercase(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }
+    }
+
+    fn identification() -> BookIdentification {
+        BookIdentification {
+            isbn10: Some("0451524934".to_string()),
+            isbn13: Some("978-0451524935".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store() {
+        let dir = library_dir("store");
+        let library = open(&dir);
+
+        let book = "1048424".parse().unwrap();
+        let entry = library
+            .store(&metadata(), &identification(), Some(&book), Path::new(BOOK))
+            .await
+            .unwrap();
+
+        assert_eq!(MD5, entry.metadata.md5);
+        assert_eq!(Some(book), entry.book);
+        assert_eq!("1984.epub", entry.file);
+        assert_eq!(Some(dir.join(MD5).join("1984.epub")), library.path(&entry));
+        assert_eq!(
+            std::fs::read(BOOK).unwrap(),
+            std::fs::read(library.path(&entry).unwrap()).unwrap()
+        );
+        assert!(dir.join(MD5).join(METADATA_FILE).is_file());
+        assert_eq!(vec![entry.clone()], library.list().await);
+
+        // The index is rebuilt from disk.
+        assert_eq!(vec![entry], open(&dir).list().await);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_most_recent_first() {
+        let dir = library_dir("list");
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+        let library = open(&dir).with_clock(SharedClock(clock.clone()));
+
+        let older = library
+            .store(&metadata(), &identification(), None, Path::new(BOOK))
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(60));
+        let other = LibgenMetadata {
+            md5: "0123456789abcdef0123456789abcdef".to_string(),
+            ..metadata()
+        };
+        let newer = library
+            .store(&other, &identification(), None, Path::new(BOOK))
+            .await
+            .unwrap();
+
+        assert_eq!(1_000, older.stored_at);
+        assert_eq!(1_060, newer.stored_at);
+        assert_eq!(vec![newer, older], library.list().await);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lookup_hit() {
+        let dir = library_dir("lookup_hit");
+        let library = open(&dir);
+        let entry = library
+            .store(&metadata(), &identification(), None, Path::new(BOOK))
+            .await
+            .unwrap();
+
+        assert_eq!(Some(entry.clone()), library.get(MD5).await);
+        assert_eq!(Some(entry.clone()), library.get(&MD5.to_lowercase()).await);
+        for book_identification in [
+            BookIdentification {
+                isbn13: Some("9780451524935".to_string()),
+                ..Default::default()
+            },
+            BookIdentification {
+                isbn10: Some("0451524934".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(
+                Some(entry.clone()),
+                library.find(&book_identification).await
+            );
+            assert_eq!(
+                Ok(vec![entry.metadata.clone()]),
+                library.get_metadata(&book_identification).await
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lookup_miss() {
+        let dir = library_dir("lookup_miss");
+        let library = open(&dir);
+        library
+            .store(&metadata(), &identification(), None, Path::new(BOOK))
+            .await
+            .unwrap();
+
+        assert_eq!(None, library.get("6C6C4E4E1B9D9B1E8C3E5D1A2F0B7C8D").await);
+        for book_identification in [
+            BookIdentification {
+                isbn13: Some("9780451526342".to_string()),
+                ..Default::default()
+            },
+            // Books are only looked up by ISBN.
+            BookIdentification {
+                title: Some("1984".to_string()),
+                author: Some("George Orwell".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(None, library.find(&book_identification).await);
+            assert_eq!(Ok(vec![]), library.get_metadata(&book_identification).await);
+        }
+
+        // Calibre books aren't stored.
+        assert_eq!(
+            Err(Error::InvalidMd5),
+            library
+                .store(
+                    &LibgenMetadata {
+                        md5: "/books/1984.epub".to_string(),
+                        ..metadata()
+                    },
+                    &identification(),
+                    None,
+                    Path::new(BOOK),
+                )
+                .await
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let dir = library_dir("remove");
+        let library = open(&dir);
+        library
+            .store(&metadata(), &identification(), None, Path::new(BOOK))
+            .await
+            .unwrap();
+
+        assert_eq!(Ok(()), library.remove(&MD5.to_lowercase()).await);
+        assert_eq!(None, library.get(MD5).await);
+        assert!(!dir.join(MD5).exists());
+        assert!(open(&dir).list().await.is_empty());
+
+        assert_eq!(
+            Err(Error::NotFound(MD5.to_string())),
+            library.remove(MD5).await
+        );
+        assert_eq!(Err(Error::InvalidMd5), library.remove("..").await);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_stores() {
+        let dir = library_dir("concurrent_stores");
+        let library = open(&dir);
+        let (epub, identification) = (metadata(), identification());
+        let mobi = LibgenMetadata {
+            extension: Extension::Mobi,
+            ..metadata()
+        };
+
+        let (epub, mobi) = tokio::join!(
+            library.store(&epub, &identification, None, Path::new(BOOK)),
+            library.store(
+                &mobi,
+                &identification,
+                None,
+                Path::new("tests/testdata/dummy_ebook.mobi")
+            ),
+        );
+        let (epub, mobi) = (epub.unwrap(), mobi.unwrap());
+
+        // One of them won, and its book is the only one left.
+        let got = library.get(MD5).await.unwrap();
+        assert!(got == epub || got == mobi, "{:?}", got);
+        let mut files: Vec<_> = std::fs::read_dir(dir.join(MD5))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        let mut want = vec![got.file.clone(), METADATA_FILE.to_string()];
+        want.sort();
+        assert_eq!(want, files);
+        assert_eq!(vec![got], open(&dir).list().await);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_store() {
+        let dir = library_dir("failed_store");
+        let library = open(&dir);
+        library
+            .store(&metadata(), &identification(), None, Path::new(BOOK))
+            .await
+            .unwrap();
+
+        let got = library
+            .store(
+                &metadata(),
+                &identification(),
+                None,
+                Path::new("tests/testdata/missing.epub"),
+            )
+            .await;
+
+        assert!(matches!(got, Err(Error::Io(_))), "{:?}", got);
+        // Downloaded again rather than served half-written.
+        assert_eq!(None, library.get(MD5).await);
+        assert!(library.list().await.is_empty());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
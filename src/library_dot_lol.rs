@@ -1,56 +1,366 @@
 //! Module download_registry can find download links for a book.
 //!
 //! In the current implementation, it takes a book MD5 hash from LibGen,
-//! and finds the download links in http://library.lol
+//! and finds the download links in http://library.lol. Other mirrors
+//! serving the same files (see `libgen_rocks` and `libgen_li`) implement
+//! the same `DownloadLinksStore` trait, and `FailoverDownloadLinksStore`
+//! tries several of them in order.
 
+use crate::{
+    http_client::{self, ConnectionLimits, HttpFailure, SendError},
+    parse_check::ParseReport,
+};
 use async_trait::async_trait;
 use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-const BASE_URL: &str = "http://library.lol/main";
+pub(crate) const BASE_URL: &str = "http://library.lol/main";
+// IPFS gateways books are downloaded from, in order of preference.
+pub const DEFAULT_IPFS_GATEWAYS: &[&str] =
+    &["https://dweb.link", "https://w3s.link", "https://ipfs.io"];
 
-#[derive(PartialEq, Debug)]
+// Links a mirror doesn't provide are left empty.
+#[derive(PartialEq, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DownloadLinks {
-    pub cloudflare: String,
-    pub ipfs_dot_io: String,
-    pub infura: String,
-    pub pinata: String,
+    // The IPFS content id of the book: every gateway serves it, see
+    // `ipfs_link`. Links cached before it was renamed call it `cid`.
+    #[serde(default, alias = "cid", skip_serializing_if = "Option::is_none")]
+    pub ipfs_cid: Option<String>,
+    // The gateways to download `ipfs_cid` from, in order of preference, e.g.
+    // "https://dweb.link".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gateways: Vec<String>,
+    // A direct link to the book, from the mirror itself or on disk.
+    #[serde(default)]
     pub http: String,
+    // The name the book was uploaded with, from the `filename=` parameter of
+    // the IPFS links, e.g. "Jane Austen - Pride and Prejudice (CIDEB, 2000).pdf".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+impl DownloadLinks {
+    // Returns the first available link, in the order of `named_links`.
+    pub fn best_link(&self) -> String {
+        self.named_links()
+            .into_iter()
+            .next()
+            .map(|(_, link)| link)
+            .unwrap_or_default()
+    }
+
+    // Returns the available links with the name of their mirror, in order of
+    // preference: the IPFS gateways, then HTTP.
+    pub fn named_links(&self) -> Vec<(String, String)> {
+        let mut links: Vec<(String, String)> = self
+            .gateways
+            .iter()
+            .filter_map(|gateway| Some((gateway_name(gateway), self.ipfs_link(gateway)?)))
+            .collect();
+        if !self.http.is_empty() {
+            links.push(("HTTP".to_string(), self.http.clone()));
+        }
+        links
+    }
+
+    // Returns the link to the book on an IPFS gateway, e.g.
+    // "https://dweb.link/ipfs/<cid>?filename=Emma.epub".
+    pub fn ipfs_link(&self, gateway: &str) -> Option<String> {
+        let cid = self.ipfs_cid.as_deref()?;
+        let mut url =
+            reqwest::Url::parse(&format!("{}/ipfs/{}", gateway.trim_end_matches('/'), cid)).ok()?;
+        if let Some(filename) = &self.filename {
+            url.query_pairs_mut().append_pair("filename", filename);
+            // Spaces as gateways and library.lol write them.
+            let query = url.query().map(|query| query.replace('+', "%20"));
+            url.set_query(query.as_deref());
+        }
+        Some(url.to_string())
+    }
+
+    // The links library.lol used to list, kept for callers relying on them.
+    // Cloudflare's gateway has shut down.
+    pub fn cloudflare(&self) -> String {
+        self.ipfs_link("https://cloudflare-ipfs.com")
+            .unwrap_or_default()
+    }
+
+    pub fn ipfs_dot_io(&self) -> String {
+        self.ipfs_link("https://ipfs.io").unwrap_or_default()
+    }
+
+    pub fn infura(&self) -> String {
+        self.ipfs_link("https://ipfs.infura.io").unwrap_or_default()
+    }
+
+    pub fn pinata(&self) -> String {
+        self.ipfs_link("https://gateway.pinata.cloud")
+            .unwrap_or_default()
+    }
+}
+
+// Names a gateway after its host, e.g. "dweb.link".
+fn gateway_name(gateway: &str) -> String {
+    reqwest::Url::parse(gateway)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| gateway.to_string())
+}
+
+fn default_gateways() -> Vec<String> {
+    DEFAULT_IPFS_GATEWAYS
+        .iter()
+        .map(|gateway| gateway.to_string())
+        .collect()
+}
+
+#[test]
+fn test_best_link() {
+    let links = DownloadLinks {
+        ipfs_cid: Some("bafk".to_string()),
+        gateways: default_gateways(),
+        http: "http".to_string(),
+        filename: None,
+    };
+    assert_eq!("https://dweb.link/ipfs/bafk", links.best_link());
+    assert_eq!(
+        vec![
+            (
+                "dweb.link".to_string(),
+                "https://dweb.link/ipfs/bafk".to_string()
+            ),
+            (
+                "w3s.link".to_string(),
+                "https://w3s.link/ipfs/bafk".to_string()
+            ),
+            (
+                "ipfs.io".to_string(),
+                "https://ipfs.io/ipfs/bafk".to_string()
+            ),
+            ("HTTP".to_string(), "http".to_string()),
+        ],
+        links.named_links()
+    );
+
+    // Gateways aren't used without a CID.
+    let links = DownloadLinks {
+        gateways: default_gateways(),
+        http: "http".to_string(),
+        ..DownloadLinks::default()
+    };
+    assert_eq!("http", links.best_link());
+    assert_eq!(
+        vec![("HTTP".to_string(), "http".to_string())],
+        links.named_links()
+    );
+
+    assert_eq!("", DownloadLinks::default().best_link());
+}
+
+#[test]
+fn test_ipfs_links_for_custom_gateways() {
+    let links = DownloadLinks {
+        ipfs_cid: Some("bafk".to_string()),
+        gateways: vec![
+            "http://127.0.0.1:8080".to_string(),
+            "https://ipfs.example.com/".to_string(),
+        ],
+        http: String::new(),
+        filename: Some("Orgueil et préjugés (CIDEB, 2000).pdf".to_string()),
+    };
+    let filename = "?filename=Orgueil%20et%20pr%C3%A9jug%C3%A9s%20%28CIDEB%2C%202000%29.pdf";
+
+    assert_eq!(
+        vec![
+            (
+                "127.0.0.1".to_string(),
+                format!("http://127.0.0.1:8080/ipfs/bafk{}", filename)
+            ),
+            (
+                "ipfs.example.com".to_string(),
+                format!("https://ipfs.example.com/ipfs/bafk{}", filename)
+            ),
+        ],
+        links.named_links()
+    );
+    assert_eq!(
+        format!("https://ipfs.infura.io/ipfs/bafk{}", filename),
+        links.infura()
+    );
+    assert_eq!("", DownloadLinks::default().cloudflare());
+}
+
+#[test]
+fn test_download_links_round_trip() {
+    let links = DownloadLinks {
+        ipfs_cid: Some("bafk".to_string()),
+        gateways: default_gateways(),
+        http: "http".to_string(),
+        ..DownloadLinks::default()
+    };
+
+    let json = serde_json::to_string(&links).unwrap();
+    assert!(json.contains(r#""ipfs_cid":"bafk""#), "{}", json);
+    assert_eq!(links, serde_json::from_str(&json).unwrap());
+
+    // Links cached before the CID was renamed.
+    let cached: DownloadLinks = serde_json::from_str(r#"{"cid":"bafk","http":"http"}"#).unwrap();
+    assert_eq!(Some("bafk".to_string()), cached.ipfs_cid);
 }
 
 #[async_trait]
 #[cfg_attr(test, mockall::automock)]
 pub trait DownloadLinksStore {
-    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, reqwest::Error>;
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, Error>;
 }
 
 pub struct LibraryDotLol {
     pub base_url: String,
+    pub client: reqwest::Client,
+    pub limits: ConnectionLimits,
+    // See `DownloadLinks::gateways`.
+    pub gateways: Vec<String>,
+}
+
+impl LibraryDotLol {
+    async fn fetch_page(&self, id: &str) -> Result<String, Error> {
+        // library.lol paths are case-sensitive: lowercase MD5s get a 404.
+        let page_url = format!(
+            "{base_url}/{id}",
+            base_url = self.base_url,
+            id = id.trim().to_uppercase()
+        );
+        let _permit = self.limits.metadata().await;
+        Ok(
+            http_client::send_once(self.client.get(page_url), &self.limits)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?,
+        )
+    }
+
+    // Reports the links found on the page of the book with this MD5, without
+    // downloading it.
+    pub async fn parse_report(&self, id: &str) -> Result<ParseReport, Error> {
+        let body = self.fetch_page(id).await?;
+        Ok(extract_links(&Html::parse_document(&body), &self.gateways).1)
+    }
 }
 
 #[async_trait]
 impl DownloadLinksStore for LibraryDotLol {
-    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, reqwest::Error> {
-        let page_url = format!("{base_url}/{id}", base_url = self.base_url, id = id);
-        let body = reqwest::get(page_url).await?.text().await?;
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, Error> {
+        let body = self.fetch_page(id).await?;
         let document = Html::parse_document(&body);
 
-        Ok(extract_links(&document))
+        extract_links(&document, &self.gateways)
+            .0
+            .ok_or_else(|| Error::MissingDownloadLinks("library.lol".to_string()))
     }
 }
 
-fn extract_links(fragment: &Html) -> DownloadLinks {
+// library.lol links to the book on its own server, and on a few IPFS
+// gateways, which all serve the same CID. The report counts the links, and
+// names their mirrors.
+pub(crate) fn extract_links(
+    fragment: &Html,
+    gateways: &[String],
+) -> (Option<DownloadLinks>, ParseReport) {
     let links: Vec<String> = fragment
         .select(&Selector::parse(r#"div[id="download"] a"#).unwrap())
-        .map(|element| element.value().attr("href").unwrap().to_string())
+        .filter_map(|element| element.value().attr("href"))
+        .map(str::to_string)
+        .collect();
+
+    let cid = links.iter().find_map(|link| ipfs_cid(link));
+    let download_links = DownloadLinks {
+        http: links
+            .iter()
+            .find(|link| ipfs_cid(link).is_none())
+            .cloned()
+            .unwrap_or_default(),
+        gateways: match cid {
+            Some(_) => gateways.to_vec(),
+            None => vec![],
+        },
+        ipfs_cid: cid,
+        filename: links.iter().find_map(|link| filename_param(link)),
+    };
+
+    let names: Vec<String> = download_links
+        .named_links()
+        .into_iter()
+        .map(|(name, _)| name)
         .collect();
+    let mut report = ParseReport::new("library.lol");
+    report.field("links", &(!links.is_empty()).then_some(links.len()));
+    report.field("names", &(!names.is_empty()).then_some(names));
+    report.field("ipfs_cid", &download_links.ipfs_cid);
+    report.field("filename", &download_links.filename);
+    let found = !download_links.best_link().is_empty();
+    (found.then_some(download_links), report)
+}
 
-    // TODO: return a HashMap of ["name" => "link"] instead of hardcoding sources?
-    DownloadLinks {
-        http: links.get(0).unwrap().to_owned(),
-        cloudflare: links.get(1).unwrap().to_owned(),
-        ipfs_dot_io: links.get(2).unwrap().to_owned(),
-        infura: links.get(3).unwrap().to_owned(),
-        pinata: links.get(4).unwrap().to_owned(),
+// Returns the CID of an IPFS gateway link, e.g. "bafk..." in
+// "https://ipfs.io/ipfs/bafk...?filename=Emma.epub".
+fn ipfs_cid(link: &str) -> Option<String> {
+    let url = reqwest::Url::parse(link).ok()?;
+    let mut segments = url.path_segments()?;
+    segments.find(|segment| *segment == "ipfs")?;
+    segments
+        .next()
+        .filter(|cid| !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_string)
+}
+
+#[test]
+fn test_ipfs_cid() {
+    for (link, want) in [
+        (
+            "https://cloudflare-ipfs.com/ipfs/bafykbzacedg7mmtzkrbk?filename=Emma.pdf",
+            Some("bafykbzacedg7mmtzkrbk"),
+        ),
+        (
+            "https://gateway.pinata.cloud/ipfs/QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG",
+            Some("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG"),
+        ),
+        ("http://12.34.45.67/main/316000/example.pdf", None),
+        ("https://ipfs.io/ipfs/", None),
+        ("https://ipfs.io/ipfs/../etc", None),
+        ("not a link", None),
+    ] {
+        assert_eq!(want.map(str::to_string), ipfs_cid(link), "{}", link);
+    }
+}
+
+// Returns the percent-decoded `filename=` query parameter of `link`, if any.
+fn filename_param(link: &str) -> Option<String> {
+    reqwest::Url::parse(link)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "filename")
+        .map(|(_, filename)| filename.trim().to_string())
+        .filter(|filename| !filename.is_empty())
+}
+
+#[test]
+fn test_filename_param() {
+    for (link, want) in [
+        (
+            "https://ipfs.io/ipfs/example?filename=example_filename.pdf",
+            Some("example_filename.pdf"),
+        ),
+        (
+            "https://ipfs.io/ipfs/example?filename=Orgueil%20et%20pr%C3%A9jug%C3%A9s.epub",
+            Some("Orgueil et préjugés.epub"),
+        ),
+        ("https://ipfs.io/ipfs/example?filename=", None),
+        ("https://ipfs.io/ipfs/example.pdf", None),
+        ("not a link", None),
+    ] {
+        assert_eq!(want.map(str::to_string), filename_param(link), "{}", link);
     }
 }
 
@@ -69,35 +379,76 @@ fn test_extract_links() {
 </div>
 "#;
 
-    let fragment = Html::parse_fragment(&download_html);
-    let got = extract_links(&fragment);
+    let fragment = Html::parse_fragment(download_html);
+    let got = extract_links(&fragment, &default_gateways())
+        .0
+        .expect("Should find links");
 
     assert_eq!(
-        "https://cloudflare-ipfs.com/ipfs/example?filename=example_filename.pdf",
-        got.cloudflare,
-    );
-    assert_eq!(
-        "https://ipfs.io/ipfs/example?filename=example_filename.pdf",
-        got.ipfs_dot_io
+        DownloadLinks {
+            ipfs_cid: Some("example".to_string()),
+            gateways: default_gateways(),
+            http: "http://some_ip_address/main/316000/some_path/example_filename.pdf".to_string(),
+            filename: Some("example_filename.pdf".to_string()),
+        },
+        got
     );
     assert_eq!(
-        "https://ipfs.infura.io/ipfs/example?filename=example_filename.pdf",
-        got.infura
+        "https://dweb.link/ipfs/example?filename=example_filename.pdf",
+        got.best_link()
     );
     assert_eq!(
         "https://gateway.pinata.cloud/ipfs/example?filename=example_filename.pdf",
-        got.pinata
+        got.pinata()
+    );
+
+    // Without IPFS links, only the direct link is left.
+    let fragment = Html::parse_fragment(
+        r#"<div id="download"><a href="http://some_ip_address/main/example.pdf">GET</a></div>"#,
     );
     assert_eq!(
-        "http://some_ip_address/main/316000/some_path/example_filename.pdf",
-        got.http
+        Some(DownloadLinks {
+            http: "http://some_ip_address/main/example.pdf".to_string(),
+            ..DownloadLinks::default()
+        }),
+        extract_links(&fragment, &default_gateways()).0
     );
 }
 
+// Tries each store in order and returns the first links found. If every
+// store fails, the last error is returned.
+pub struct FailoverDownloadLinksStore {
+    pub stores: Vec<Box<dyn DownloadLinksStore + Send + Sync + 'static>>,
+}
+
+#[async_trait]
+impl DownloadLinksStore for FailoverDownloadLinksStore {
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, Error> {
+        let mut last_error = Error::NoDownloadLinksStore;
+        for store in &self.stores {
+            match store.get_download_links(id).await {
+                Ok(links) => return Ok(links),
+                Err(err) => {
+                    tracing::warn!(
+                        "Could not get download links, trying the next source: {:?}",
+                        err
+                    );
+                    last_error = err;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
 impl Default for LibraryDotLol {
     fn default() -> Self {
         Self {
             base_url: BASE_URL.to_string(),
+            client: reqwest::Client::new(),
+            limits: ConnectionLimits::default(),
+            gateways: default_gateways(),
         }
     }
 }
@@ -113,6 +464,7 @@ mod tests {
         let mock_server = MockServer::start();
         let lib_dot_lol = LibraryDotLol {
             base_url: mock_server.base_url(),
+            ..Default::default()
         };
 
         let endpoint_mock = mock_server.mock(|when, then| {
@@ -125,17 +477,351 @@ mod tests {
             .get_download_links("AB13556B96D473C8DFAD7165C4704526")
             .await;
 
+        endpoint_mock.assert();
+        // "example.pdf" isn't a CID: only the direct link is kept.
+        let got = got.unwrap();
+        assert_eq!(
+            DownloadLinks {
+                http: "http://12.34.45.67/main/316000/example.pdf".to_string(),
+                ..Default::default()
+            },
+            got,
+        );
+        assert_eq!(
+            "http://12.34.45.67/main/316000/example.pdf",
+            got.best_link()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_original_filename() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let lib_dot_lol = LibraryDotLol {
+            base_url: mock_server.base_url(),
+            ..Default::default()
+        };
+
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/AB13556B96D473C8DFAD7165C4704526");
+            then.status(200)
+                .header("content-type", "text/html")
+                .body(include_str!(
+                    "../tests/testdata/library.lol_original_filename_page.html"
+                ));
+        });
+        let got = lib_dot_lol
+            .get_download_links("AB13556B96D473C8DFAD7165C4704526")
+            .await;
+
         endpoint_mock.assert();
         assert!(got.is_ok());
+        let filename = "?filename=Jane%20Austen%20-%20Orgueil%20et%20pr%C3%A9jug%C3%A9s%20%28CIDEB%2C%202000%29.pdf";
+        let got = got.unwrap();
         assert_eq!(
             DownloadLinks {
-                cloudflare: "https://cloudflare-ipfs.com/ipfs/example.pdf".to_string(),
-                ipfs_dot_io: "https://ipfs.io/ipfs/example.pdf".to_string(),
-                infura: "https://ipfs.infura.io/ipfs/example.pdf".to_string(),
-                pinata: "https://gateway.pinata.cloud/ipfs/example.pdf".to_string(),
+                ipfs_cid: Some("example".to_string()),
+                gateways: default_gateways(),
                 http: "http://12.34.45.67/main/316000/example.pdf".to_string(),
+                filename: Some("Jane Austen - Orgueil et préjugés (CIDEB, 2000).pdf".to_string()),
             },
-            got.unwrap(),
+            got,
+        );
+        // The same links as on the page, from the CID.
+        assert_eq!(
+            format!("https://cloudflare-ipfs.com/ipfs/example{}", filename),
+            got.cloudflare()
+        );
+        assert_eq!(
+            format!("https://ipfs.io/ipfs/example{}", filename),
+            got.ipfs_dot_io()
+        );
+        assert_eq!(
+            format!("https://dweb.link/ipfs/example{}", filename),
+            got.best_link()
+        );
+    }
+
+    // A self-hosted node listed first is tried before the public gateways.
+    #[tokio::test]
+    async fn test_get_download_links_local_gateway_first() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/AB13556B96D473C8DFAD7165C4704526");
+            then.status(200).body(include_str!(
+                "../tests/testdata/library.lol_original_filename_page.html"
+            ));
+        });
+        let lib_dot_lol = LibraryDotLol {
+            base_url: mock_server.base_url(),
+            gateways: vec![
+                "http://127.0.0.1:8080".to_string(),
+                "https://dweb.link".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let got = lib_dot_lol
+            .get_download_links("AB13556B96D473C8DFAD7165C4704526")
+            .await
+            .unwrap();
+
+        let filename = "?filename=Jane%20Austen%20-%20Orgueil%20et%20pr%C3%A9jug%C3%A9s%20%28CIDEB%2C%202000%29.pdf";
+        assert_eq!(
+            format!("http://127.0.0.1:8080/ipfs/example{}", filename),
+            got.best_link()
+        );
+        assert_eq!(
+            vec!["127.0.0.1", "dweb.link", "HTTP"],
+            got.named_links()
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_uppercases_md5() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/AB13556B96D473C8DFAD7165C4704526");
+            then.status(200)
+                .body(include_str!("../tests/testdata/library.lol_book_page.html"));
+        });
+        let lib_dot_lol = LibraryDotLol {
+            base_url: mock_server.base_url(),
+            ..Default::default()
+        };
+
+        for id in [
+            "ab13556b96d473c8dfad7165c4704526",
+            " Ab13556B96d473c8dfad7165c4704526 ",
+        ] {
+            assert!(lib_dot_lol.get_download_links(id).await.is_ok(), "{:?}", id);
+        }
+        endpoint_mock.assert_hits(2);
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_not_found() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let not_found_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/AB13556B96D473C8DFAD7165C4704526");
+            then.status(404)
+                .body("<html><body>File not found</body></html>");
+        });
+        let empty_page_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/EF12556B96D473C8DFAD7165C4704526");
+            then.status(200)
+                .body("<html><body>Nothing here</body></html>");
+        });
+        let lib_dot_lol = LibraryDotLol {
+            base_url: mock_server.base_url(),
+            ..Default::default()
+        };
+
+        let got = lib_dot_lol
+            .get_download_links("AB13556B96D473C8DFAD7165C4704526")
+            .await;
+        not_found_mock.assert();
+        assert!(matches!(got, Err(Error::HttpError(_))));
+
+        let got = lib_dot_lol
+            .get_download_links("EF12556B96D473C8DFAD7165C4704526")
+            .await;
+        empty_page_mock.assert();
+        assert_eq!(
+            Err(Error::MissingDownloadLinks("library.lol".to_string())),
+            got
         );
     }
 }
+
+// library.lol being down shouldn't slow the other download sources down.
+#[tokio::test]
+async fn test_circuit_breaker() {
+    use crate::{
+        circuit_breaker::CircuitBreakers,
+        clock::{MockClock, SharedClock},
+    };
+    use httpmock::{Method::GET, MockServer};
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+    let breakers = CircuitBreakers::new(2, Duration::from_secs(30), SharedClock(clock.clone()));
+    let library_dot_lol = MockServer::start();
+    let mirror = MockServer::start();
+    let mut down_mock = library_dot_lol.mock(|when, then| {
+        when.method(GET).path("/ABCD");
+        then.status(502);
+    });
+    let mirror_mock = mirror.mock(|when, then| {
+        when.method(GET).path("/ABCD");
+        then.status(200)
+            .body(include_str!("../tests/testdata/library.lol_book_page.html"));
+    });
+    let limits = ConnectionLimits::default().with_breakers(breakers.clone());
+    let store = FailoverDownloadLinksStore {
+        stores: vec![
+            Box::new(LibraryDotLol {
+                base_url: library_dot_lol.base_url(),
+                limits: limits.clone(),
+                ..Default::default()
+            }),
+            Box::new(LibraryDotLol {
+                base_url: mirror.base_url(),
+                limits: limits.clone(),
+                ..Default::default()
+            }),
+        ],
+    };
+
+    for _ in 0..4 {
+        assert!(store.get_download_links("ABCD").await.is_ok());
+    }
+    // After two failures, library.lol isn't called anymore.
+    down_mock.assert_hits(2);
+    mirror_mock.assert_hits(4);
+    let library_dot_lol_host = library_dot_lol.address().to_string();
+    assert_eq!(
+        Err(Error::UpstreamUnavailable(format!(
+            "{} is unavailable after 2 consecutive failures",
+            library_dot_lol_host
+        ))),
+        store.stores[0].get_download_links("ABCD").await
+    );
+
+    // It's back up, and called again after the cooldown.
+    down_mock.delete();
+    let up_mock = library_dot_lol.mock(|when, then| {
+        when.method(GET).path("/ABCD");
+        then.status(200)
+            .body(include_str!("../tests/testdata/library.lol_book_page.html"));
+    });
+    clock.advance(Duration::from_secs(30));
+    for _ in 0..2 {
+        assert!(store.get_download_links("ABCD").await.is_ok());
+    }
+    up_mock.assert_hits(2);
+    mirror_mock.assert_hits(4);
+    assert_eq!(
+        crate::circuit_breaker::State::Closed,
+        breakers.statuses()[&library_dot_lol_host].state
+    );
+}
+
+#[cfg(test)]
+mod failover_tests {
+    use super::*;
+    use mockall::predicate::eq;
+
+    fn failing_store(err: Error) -> MockDownloadLinksStore {
+        let mut store = MockDownloadLinksStore::new();
+        store
+            .expect_get_download_links()
+            .with(eq("ABCD"))
+            .once()
+            .return_once(move |_| Box::pin(async move { Err(err) }));
+        store
+    }
+
+    fn succeeding_store(http: &'static str) -> MockDownloadLinksStore {
+        let mut store = MockDownloadLinksStore::new();
+        store
+            .expect_get_download_links()
+            .with(eq("ABCD"))
+            .once()
+            .returning(move |_| {
+                Box::pin(async move {
+                    Ok(DownloadLinks {
+                        http: http.to_string(),
+                        ..DownloadLinks::default()
+                    })
+                })
+            });
+        store
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_store() {
+        let store = FailoverDownloadLinksStore {
+            stores: vec![
+                Box::new(failing_store(Error::HttpError(HttpFailure::other("down")))),
+                Box::new(succeeding_store("second")),
+                Box::new(MockDownloadLinksStore::new()), // Never called
+            ],
+        };
+
+        assert_eq!(
+            Ok(DownloadLinks {
+                http: "second".to_string(),
+                ..DownloadLinks::default()
+            }),
+            store.get_download_links("ABCD").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_returns_last_error() {
+        let store = FailoverDownloadLinksStore {
+            stores: vec![
+                Box::new(failing_store(Error::HttpError(HttpFailure::other("down")))),
+                Box::new(failing_store(Error::MissingDownloadLinks(
+                    "libgen.rocks".to_string(),
+                ))),
+            ],
+        };
+
+        assert_eq!(
+            Err(Error::MissingDownloadLinks("libgen.rocks".to_string())),
+            store.get_download_links("ABCD").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_stores() {
+        let store = FailoverDownloadLinksStore { stores: vec![] };
+
+        assert_eq!(
+            Err(Error::NoDownloadLinksStore),
+            store.get_download_links("ABCD").await
+        );
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Error {
+    HttpError(HttpFailure),
+    MissingDownloadLinks(String),
+    NoDownloadLinksStore,
+    // The mirror kept failing, so it isn't called for a while.
+    UpstreamUnavailable(String),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::HttpError(HttpFailure::from(err))
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::Http(err) => Self::from(err),
+            SendError::RateLimited(limit) => Self::HttpError(HttpFailure::from(limit)),
+            SendError::CircuitOpen(open) => Self::UpstreamUnavailable(open.to_string()),
+            SendError::Replay(message) => Self::UpstreamUnavailable(message),
+        }
+    }
+}
@@ -5,21 +5,329 @@
 //! In other words, it acts as glue between the other modules in this repo.
 
 use crate::{
-    goodreads::{BookIdentificationGetter, Goodreads},
-    libgen::{self, Libgen, LibgenMetadata, MetadataStore},
-    library_dot_lol::{DownloadLinks, DownloadLinksStore, LibraryDotLol},
+    book_query::BookQuery,
+    book_ref::BookRef,
+    calibre_library::CalibreLibrary,
+    circuit_breaker::{self, CircuitBreakers},
+    clock::{Clock, SharedClock, SystemClock},
+    config::{Config, DownloadSource},
+    convert::{self, ConversionOptions, ConversionResult, InputBookInfo},
+    extension::Extension,
+    file_store::{self, FileStore},
+    goodreads::{
+        self, BookIdentification, BookIdentificationGetter, Goodreads, ShelfEntry, ShelfGetter,
+    },
+    http_client::{self, ConnectionLimits, HttpFailure, RateLimit},
+    kindle::{self, Delivery, Kindle},
+    libgen::{self, FailoverMetadataStore, FilterReport, Libgen, LibgenMetadata, MetadataStore},
+    libgen_li::LibgenLi,
+    libgen_rocks::LibgenRocks,
+    library::{self, Library, LibraryEntry},
+    library_dot_lol::{
+        self, DownloadLinks, DownloadLinksStore, FailoverDownloadLinksStore, LibraryDotLol,
+    },
+    links_cache::{self, CachedDownloadLinksStore},
+    ranking::{CandidateRanker, DefaultRanker, RankingContext},
+    selection::{Filter, SelectionFilters},
+};
+use async_trait::async_trait;
+use futures_util::stream::{FuturesOrdered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 pub struct LibReads {
     pub(crate) isbn_getter: Box<dyn BookIdentificationGetter + Send + Sync + 'static>,
+    pub(crate) shelf_getter: Box<dyn ShelfGetter + Send + Sync + 'static>,
     pub(crate) metadata_store: Box<dyn MetadataStore + Send + Sync + 'static>,
     pub(crate) download_links_store: Box<dyn DownloadLinksStore + Send + Sync + 'static>,
+    // How many editions can be tried before giving up.
+    pub(crate) max_attempts: usize,
+    // For how many editions download links are looked up at once, see
+    // `next_candidate`.
+    pub(crate) links_prefetch: usize,
+    // How many editions are kept once ranked, best first. Some ISBNs, e.g.
+    // of omnibuses, have hundreds of them on LibGen.
+    pub(crate) max_editions: usize,
+    // Whether ddjvu is installed, see `convert::DDJVU_EXECUTABLE`.
+    pub(crate) can_convert_djvu: bool,
+    // Orders the editions found on LibGen, see `with_ranker`.
+    pub(crate) ranker: Box<dyn CandidateRanker + Send + Sync + 'static>,
+    // Where downloaded books are kept, when enabled.
+    pub(crate) library: Option<Library>,
+    // Where books are emailed with `send_to_kindle`, when enabled.
+    pub(crate) kindle: Option<Kindle>,
+    // Shared by every outbound request, including the book downloads.
+    pub(crate) connection_limits: ConnectionLimits,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct BookInfo {
     pub metadata: LibgenMetadata,
     pub download_links: DownloadLinks,
+    pub cover_url: Option<String>,
+    pub isbn10: Option<String>,
+    pub isbn13: Option<String>,
+    // Formats of all the editions found on LibGen, including the selected one.
+    pub available_formats: Vec<Extension>,
+    // The other editions found on LibGen, most relevant first. They are
+    // used when the selected one can't be converted.
+    pub alternatives: Vec<LibgenMetadata>,
+    // Whether nothing was found by ISBN, and the editions were found by
+    // title and author instead.
+    #[serde(default)]
+    pub found_by_title: bool,
+    // What Goodreads says about the book. Its title and author may be
+    // spelled differently than LibGen's, in `metadata`.
+    #[serde(default)]
+    pub identification: BookIdentification,
+    // The book asked for, see `book_ref`. Unknown for editions picked by
+    // MD5.
+    #[serde(default)]
+    pub book: Option<BookRef>,
+}
+
+// Everything the pipeline decided for a book, up to the download links. It
+// explains why an edition was picked over the others.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SelectionReport {
+    pub identification: BookIdentification,
+    // See `BookInfo::found_by_title`.
+    pub found_by_title: bool,
+    // Every edition found on LibGen: the ranked ones first, in order, then
+    // the ones that were filtered out.
+    pub candidates: Vec<CandidateReport>,
+    // How many candidates there are, when `candidates` is a page of them,
+    // see `paginate`.
+    #[serde(default)]
+    pub total: usize,
+    // How many ranked editions were dropped, past `max_editions`.
+    #[serde(default)]
+    pub truncated: usize,
+    pub chosen: Option<LibgenMetadata>,
+    pub download_links: Option<DownloadLinks>,
+}
+
+impl SelectionReport {
+    // Keeps the `limit` candidates from `offset`, in order. `total` still
+    // counts all of them.
+    pub fn paginate(self, offset: usize, limit: usize) -> Self {
+        Self {
+            candidates: self
+                .candidates
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .collect(),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CandidateReport {
+    pub metadata: LibgenMetadata,
+    // Position in the ranking, starting at 1. None when filtered out.
+    pub rank: Option<usize>,
+    // Why the edition can't be selected. Ranked editions after the chosen
+    // one are still alternatives.
+    pub eliminated: Option<Elimination>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Elimination {
+    Duplicate,
+    UnknownExtension,
+    DjvuExcluded,
+    // Ranked past `max_attempts`.
+    MaxAttempts,
+    NoDownloadLinks,
+    // Left out by the selection filters of the request.
+    ExcludedFormat,
+    OverMaxSize,
+    OtherLanguage,
+    BeforeMinYear,
+}
+
+impl From<Filter> for Elimination {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Exclude => Self::ExcludedFormat,
+            Filter::MaxSizeMb => Self::OverMaxSize,
+            Filter::Lang => Self::OtherLanguage,
+            Filter::MinYear => Self::BeforeMinYear,
+        }
+    }
+}
+
+impl From<libgen::Elimination> for Elimination {
+    fn from(elimination: libgen::Elimination) -> Self {
+        match elimination {
+            libgen::Elimination::Duplicate => Self::Duplicate,
+            libgen::Elimination::UnknownExtension => Self::UnknownExtension,
+            libgen::Elimination::DjvuExcluded => Self::DjvuExcluded,
+        }
+    }
+}
+
+// How long each stage of the pipeline took, in the order they ran.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn record(&mut self, stage: &'static str, duration: Duration) {
+        self.stages.push((stage, duration));
+    }
+
+    pub fn stages(&self) -> &[(&'static str, Duration)] {
+        &self.stages
+    }
+}
+
+#[test]
+fn test_timings() {
+    let mut timings = Timings::default();
+    timings.record("goodreads", Duration::from_millis(120));
+    timings.record("libgen", Duration::from_millis(45));
+
+    assert_eq!(
+        &[
+            ("goodreads", Duration::from_millis(120)),
+            ("libgen", Duration::from_millis(45)),
+        ],
+        timings.stages()
+    );
+}
+
+// The operations the web server needs from LibReads. Applications embedding
+// LibReads can depend on this trait instead, and mock it with
+// `MockBookService` by enabling the `test-util` feature.
+#[async_trait]
+#[cfg_attr(any(test, feature = "test-util"), mockall::automock)]
+pub trait BookService: Send + Sync {
+    // Finds the book and its download links, among the editions passing
+    // `filters`, ranked for `wanted_extension` when known.
+    async fn book_info(
+        &self,
+        goodreads_book_url: &str,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error>;
+
+    // Same as `book_info`, for a book asked for by ISBN, title and author,
+    // or page, see `BookQuery`.
+    async fn book_info_by_query(
+        &self,
+        query: &BookQuery,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error>;
+
+    // Downloads a book found by `book_info`, converted to `wanted_extension`.
+    async fn book_file(
+        &self,
+        book_info: BookInfo,
+        wanted_extension: Extension,
+        options: &ConversionOptions,
+    ) -> Result<ConversionResult, Error>;
+
+    // Reports the editions considered for the book, and why one was chosen.
+    async fn candidates(
+        &self,
+        goodreads_book_url: &str,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<SelectionReport, Error>;
+
+    // Finds a LibGen edition by its MD5, to download with `book_file`.
+    async fn book_info_by_md5(
+        &self,
+        md5: &str,
+        title: Option<&str>,
+        author: Option<&str>,
+    ) -> Result<BookInfo, Error>;
+
+    async fn shelf(&self, shelf_url: &str) -> Result<Vec<ShelfEntry>, Error>;
+
+    async fn library_books(&self) -> Result<Vec<LibraryEntry>, Error>;
+
+    async fn remove_from_library(&self, md5: &str) -> Result<(), Error>;
+
+    // Emails a book downloaded with `book_file` to the configured Kindle.
+    async fn send_to_kindle(&self, result: &ConversionResult) -> Result<Delivery, Error>;
+}
+
+#[async_trait]
+impl BookService for LibReads {
+    async fn book_info(
+        &self,
+        goodreads_book_url: &str,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error> {
+        self.get_book_info_with_timings(goodreads_book_url, wanted_extension, filters)
+            .await
+    }
+
+    async fn book_info_by_query(
+        &self,
+        query: &BookQuery,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error> {
+        self.get_book_info_by_query_with_timings(query, wanted_extension, filters)
+            .await
+    }
+
+    async fn book_file(
+        &self,
+        book_info: BookInfo,
+        wanted_extension: Extension,
+        options: &ConversionOptions,
+    ) -> Result<ConversionResult, Error> {
+        self.download_as(book_info, wanted_extension, options).await
+    }
+
+    async fn candidates(
+        &self,
+        goodreads_book_url: &str,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<SelectionReport, Error> {
+        self.dry_run(goodreads_book_url, wanted_extension, filters)
+            .await
+    }
+
+    async fn book_info_by_md5(
+        &self,
+        md5: &str,
+        title: Option<&str>,
+        author: Option<&str>,
+    ) -> Result<BookInfo, Error> {
+        self.get_book_info_by_md5(md5, title, author).await
+    }
+
+    async fn shelf(&self, shelf_url: &str) -> Result<Vec<ShelfEntry>, Error> {
+        self.list_books_from_shelf_url(shelf_url).await
+    }
+
+    async fn library_books(&self) -> Result<Vec<LibraryEntry>, Error> {
+        LibReads::library_books(self).await
+    }
+
+    async fn remove_from_library(&self, md5: &str) -> Result<(), Error> {
+        LibReads::remove_from_library(self, md5).await
+    }
+
+    async fn send_to_kindle(&self, result: &ConversionResult) -> Result<Delivery, Error> {
+        LibReads::send_to_kindle(self, result).await
+    }
 }
 
 impl LibReads {
@@ -27,124 +335,1330 @@ impl LibReads {
         &self,
         goodreads_book_url: &str,
     ) -> Result<BookInfo, Error> {
+        let (book_info, _) = self
+            .get_book_info_with_timings(goodreads_book_url, None, &SelectionFilters::default())
+            .await?;
+        Ok(book_info)
+    }
+
+    // Lists the books of a Goodreads shelf, e.g. "to-read". Each of them can
+    // then be downloaded with its `book_url`.
+    pub async fn list_books_from_shelf_url(
+        &self,
+        shelf_url: &str,
+    ) -> Result<Vec<ShelfEntry>, Error> {
+        Ok(self.shelf_getter.get_shelf(shelf_url).await?)
+    }
+
+    // Same as `get_book_info_from_goodreads_url`, also returning how long
+    // each upstream took. Editions that don't pass `filters` are left out,
+    // and the others are ranked for `wanted_extension`, when known.
+    pub async fn get_book_info_with_timings(
+        &self,
+        goodreads_book_url: &str,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error> {
+        let mut timings = Timings::default();
+
+        let start = Instant::now();
         let book_identification = self
             .isbn_getter
             .get_identification(goodreads_book_url)
             .await?;
+        timings.record("goodreads", start.elapsed());
+
+        self.select_edition(
+            book_identification,
+            BookRef::from_url(goodreads_book_url),
+            &goodreads::canonical_book_url(goodreads_book_url),
+            timings,
+            wanted_extension,
+            filters,
+        )
+        .await
+    }
+
+    // Finds the book `query` is for, from its page like
+    // `get_book_info_from_goodreads_url`, or straight on LibGen by its ISBN,
+    // or title and author.
+    pub async fn get_book_info(&self, query: &BookQuery) -> Result<BookInfo, Error> {
+        let (book_info, _) = self
+            .get_book_info_by_query_with_timings(query, None, &SelectionFilters::default())
+            .await?;
+        Ok(book_info)
+    }
+
+    // Same as `get_book_info_with_timings`, for any `BookQuery`.
+    pub async fn get_book_info_by_query_with_timings(
+        &self,
+        query: &BookQuery,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error> {
+        let book_identification = match query {
+            BookQuery::Url(page_url) => {
+                return self
+                    .get_book_info_with_timings(page_url, wanted_extension, filters)
+                    .await
+            }
+            BookQuery::Goodreads(id) => {
+                return self
+                    .get_book_info_with_timings(&goodreads::book_url(id), wanted_extension, filters)
+                    .await
+            }
+            BookQuery::Isbn(isbn) => BookIdentification::from_isbn(isbn),
+            BookQuery::TitleAndAuthor { title, author } => {
+                BookIdentification::from_title_and_author(title, author)
+            }
+        };
+        self.select_edition(
+            book_identification,
+            None,
+            &query.to_string(),
+            Timings::default(),
+            wanted_extension,
+            filters,
+        )
+        .await
+    }
+
+    // Picks the best edition of the book on LibGen, with its download links.
+    // `asked_for` is what the book was asked for with, to log.
+    async fn select_edition(
+        &self,
+        book_identification: BookIdentification,
+        book: Option<BookRef>,
+        asked_for: &str,
+        mut timings: Timings,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<(BookInfo, Timings), Error> {
+        let start = Instant::now();
+        let (books_metadata, found_by_title) = self.find_editions(&book_identification).await?;
+        timings.record("libgen", start.elapsed());
+        let results = books_metadata.len();
+        let available_formats = books_metadata
+            .iter()
+            .map(|book| book.extension.clone())
+            .collect();
+        let (kept, rejected) = filters.apply(books_metadata);
+        let (mut candidates, mut report) = libgen::rank(
+            &kept,
+            &RankingContext {
+                pages: book_identification.pages,
+                ..RankingContext::new(wanted_extension, filters)
+            },
+            self.can_convert_djvu,
+            self.ranker.as_ref(),
+        );
+        report.results = results;
+        report.filtered_out = rejected.len();
+        if candidates.is_empty() {
+            if kept.is_empty() {
+                report.emptied_by = SelectionFilters::emptied_by(&rejected);
+            }
+            return Err(Error::NotFound {
+                searched: Box::new(book_identification),
+                filters: report,
+            });
+        }
+        candidates.truncate(self.max_attempts.max(1).min(self.max_editions.max(1)));
+
+        let start = Instant::now();
+        let mut candidates = VecDeque::from(candidates);
+        let mut failed_attempts = vec![];
+        let (book_metadata, download_links) = self
+            .next_candidate(&mut candidates, &mut failed_attempts)
+            .await
+            .ok_or_else(|| Error::from_attempts(failed_attempts))?;
+        timings.record("links", start.elapsed());
+        tracing::info!(
+            "Selected {} ({}, {}) out of {} editions for {}",
+            book_metadata.md5,
+            book_metadata.extension,
+            if book_metadata.year.is_empty() {
+                "unknown year"
+            } else {
+                &book_metadata.year
+            },
+            results,
+            asked_for
+        );
+
+        let book_info = BookInfo {
+            metadata: book_metadata,
+            download_links,
+            cover_url: book_identification.cover_url.clone(),
+            isbn10: book_identification.isbn10.clone(),
+            isbn13: book_identification.isbn13.clone(),
+            available_formats,
+            alternatives: candidates.into(),
+            found_by_title,
+            identification: book_identification,
+            book,
+        };
+        Ok((book_info, timings))
+    }
+
+    // LibGen often indexes the ISBN of another edition than the Goodreads
+    // one. When nothing is found by ISBN, editions are searched by title,
+    // keeping those by the same author. Returns whether that was the case.
+    async fn find_editions(
+        &self,
+        book_identification: &BookIdentification,
+    ) -> Result<(Vec<LibgenMetadata>, bool), Error> {
+        let title_and_author = book_identification
+            .title
+            .as_ref()
+            .zip(book_identification.author.as_ref());
+        // LibGen has nothing under the ISBN of an audiobook or a box set, so
+        // those are searched by title right away.
+        if let Some((title, author)) =
+            title_and_author.filter(|_| book_identification.is_audio_or_box_set())
+        {
+            tracing::info!(
+                "Goodreads lists the {} edition of \"{}\" by {}, searching by title",
+                book_identification.format.as_deref().unwrap_or_default(),
+                title,
+                author
+            );
+            return self.find_editions_by_title(title, author).await;
+        }
+        // Nor can books asked for by title, see `BookQuery`, or whose page
+        // has no ISBN, be searched by it.
+        if let Some((title, author)) = title_and_author.filter(|_| {
+            book_identification.isbn10.is_none() && book_identification.isbn13.is_none()
+        }) {
+            return self.find_editions_by_title(title, author).await;
+        }
 
         let books_metadata = self
             .metadata_store
-            .get_metadata(&book_identification)
+            .get_metadata(book_identification)
             .await?;
-        let book_metadata = match libgen::find_most_relevant(&books_metadata) {
-            None => return Err("Nothing found on LibGen for this book")?,
-            Some(book_metadata) => book_metadata,
+        let Some((title, author)) = title_and_author else {
+            return Ok((books_metadata, false));
         };
+        if !books_metadata.is_empty() {
+            return Ok((books_metadata, false));
+        }
+        tracing::info!("Nothing found by ISBN for \"{}\" by {}", title, author);
+        self.find_editions_by_title(title, author).await
+    }
 
-        println!(
-            "Formats found: {:?} -> {:?} selected",
-            books_metadata
-                .iter()
-                .map(|book| &book.extension)
-                .collect::<Vec<_>>(),
-            &book_metadata.extension
+    async fn find_editions_by_title(
+        &self,
+        title: &str,
+        author: &str,
+    ) -> Result<(Vec<LibgenMetadata>, bool), Error> {
+        // The shorter title, without its subtitle, is only searched when the
+        // whole one finds nothing.
+        let search_title = goodreads::search_title(title);
+        let mut books_metadata = self.search_by_title(&search_title, author).await?;
+        if books_metadata.is_empty() {
+            if let Some(short_title) = goodreads::short_title(&search_title) {
+                tracing::info!(
+                    "Nothing found by title for \"{}\", searching \"{}\"",
+                    search_title,
+                    short_title
+                );
+                books_metadata = self.search_by_title(&short_title, author).await?;
+            }
+        }
+        tracing::info!(
+            "Found {} editions of \"{}\" by {} by title",
+            books_metadata.len(),
+            title,
+            author
         );
+        let found_by_title = !books_metadata.is_empty();
+        Ok((books_metadata, found_by_title))
+    }
 
-        let download_links = self
-            .download_links_store
-            .get_download_links(book_metadata.md5.as_str())
+    // Searches LibGen by title, keeping the editions by `author`.
+    async fn search_by_title(
+        &self,
+        title: &str,
+        author: &str,
+    ) -> Result<Vec<LibgenMetadata>, Error> {
+        Ok(self
+            .metadata_store
+            .search_by_title(title)
+            .await?
+            .into_iter()
+            .filter(|book| libgen::matches_title_and_author(book, title, author))
+            .collect())
+    }
+
+    // Runs the pipeline up to the download links, without downloading
+    // anything, and reports how the edition was selected.
+    pub async fn dry_run(
+        &self,
+        goodreads_book_url: &str,
+        wanted_extension: Option<&Extension>,
+        filters: &SelectionFilters,
+    ) -> Result<SelectionReport, Error> {
+        let identification = self
+            .isbn_getter
+            .get_identification(goodreads_book_url)
             .await?;
+        let (books_metadata, found_by_title) = self.find_editions(&identification).await?;
+        let (books_metadata, rejected) = filters.apply(books_metadata);
+        let (mut ranked, eliminated) = libgen::rank_with_eliminations(
+            &books_metadata,
+            &RankingContext {
+                pages: identification.pages,
+                ..RankingContext::new(wanted_extension, filters)
+            },
+            self.can_convert_djvu,
+            self.ranker.as_ref(),
+        );
+        // The best editions are kept, in order.
+        let truncated = ranked.len().saturating_sub(self.max_editions.max(1));
+        ranked.truncate(self.max_editions.max(1));
+
+        let max_attempts = self.max_attempts.max(1);
+        let mut failed_attempts = vec![];
+        let selected = self
+            .next_candidate(
+                &mut ranked.iter().take(max_attempts).cloned().collect(),
+                &mut failed_attempts,
+            )
+            .await;
+
+        let ranked = ranked.into_iter().enumerate().map(|(i, metadata)| {
+            let eliminated = if i >= max_attempts {
+                Some(Elimination::MaxAttempts)
+            } else if failed_attempts.iter().any(|(md5, _)| *md5 == metadata.md5) {
+                Some(Elimination::NoDownloadLinks)
+            } else {
+                None
+            };
+            CandidateReport {
+                metadata,
+                rank: Some(i + 1),
+                eliminated,
+            }
+        });
+        let filtered = eliminated
+            .into_iter()
+            .map(|(metadata, elimination)| (metadata, elimination.into()))
+            .chain(
+                rejected
+                    .into_iter()
+                    .map(|(metadata, filter)| (metadata, filter.into())),
+            )
+            .map(|(metadata, elimination)| CandidateReport {
+                metadata,
+                rank: None,
+                eliminated: Some(elimination),
+            });
+        let candidates: Vec<_> = ranked.chain(filtered).collect();
+
+        let (chosen, download_links) = selected.unzip();
+        Ok(SelectionReport {
+            identification,
+            found_by_title,
+            total: candidates.len(),
+            candidates,
+            truncated,
+            chosen,
+            download_links,
+        })
+    }
+
+    // Downloads the book and converts it. When that fails, the alternative
+    // editions are tried in order, until one of them works. So are they when
+    // the book seems to be in another language than `options.language`, but
+    // the first such book is served if none of them is in the right one.
+    pub async fn download_as(
+        &self,
+        book_info: BookInfo,
+        wanted_extension: Extension,
+        options: &ConversionOptions,
+    ) -> Result<ConversionResult, Error> {
+        // Downloads count towards the same limits as every other request.
+        let options = &ConversionOptions {
+            connection_limits: self.connection_limits.clone(),
+            ..options.clone()
+        };
+        let cover_url = book_info.cover_url.clone();
+        let identification = BookIdentification {
+            isbn10: book_info.isbn10.clone(),
+            isbn13: book_info.isbn13.clone(),
+            ..Default::default()
+        };
+        let book_ref = book_info.book.clone();
+        let candidates = book_info.alternatives.clone();
+        let mut alternatives = VecDeque::from(candidates.clone());
+        let mut failed_attempts = vec![];
+        let mut metadata = book_info.metadata.clone();
+        let mut md5 = book_info.metadata.md5.clone();
+        let mut book = InputBookInfo::from(book_info);
+        // The first book in another language, with its metadata and whether
+        // it was already on disk.
+        let mut mismatched: Option<(LibgenMetadata, bool, ConversionResult)> = None;
+
+        loop {
+            let is_local = book.is_local();
+            match convert::download_as_with_options(book, wanted_extension.clone(), options).await {
+                Ok(result) => {
+                    if let Some(mismatch) = &result.language_mismatch {
+                        tracing::warn!("Trying another edition than {}: {}", md5, mismatch);
+                        match mismatched {
+                            None => mismatched = Some((metadata.clone(), is_local, result)),
+                            Some(_) => {
+                                let _ = tokio::fs::remove_file(&result.path).await;
+                            }
+                        }
+                    } else {
+                        if let Some((_, _, mismatched)) = mismatched {
+                            let _ = tokio::fs::remove_file(&mismatched.path).await;
+                        }
+                        // Books that were already on disk aren't stored again.
+                        if !is_local {
+                            self.store_in_library(
+                                &metadata,
+                                &identification,
+                                book_ref.as_ref(),
+                                &result,
+                            )
+                            .await;
+                        }
+                        return Ok(result);
+                    }
+                }
+                Err(err) => {
+                    let retry = is_retryable(&err);
+                    tracing::warn!("Could not get the book {}: {:?}", md5, err);
+                    let err = suggest_docx(&metadata, &err, &candidates, &failed_attempts)
+                        .unwrap_or(Error::Conversion(err));
+                    failed_attempts.push((md5, err));
+                    if !retry {
+                        break;
+                    }
+                }
+            }
+
+            let Some((next, download_links)) = self
+                .next_candidate(&mut alternatives, &mut failed_attempts)
+                .await
+            else {
+                break;
+            };
+            metadata = next;
+            md5 = metadata.md5.clone();
+            book = InputBookInfo::from(BookInfo {
+                metadata: metadata.clone(),
+                download_links,
+                cover_url: cover_url.clone(),
+                isbn10: None,
+                isbn13: None,
+                available_formats: vec![],
+                alternatives: vec![],
+                found_by_title: false,
+                identification: BookIdentification::default(),
+                book: None,
+            });
+        }
+
+        let Some((metadata, is_local, result)) = mismatched else {
+            return Err(Error::from_attempts(failed_attempts));
+        };
+        if !is_local {
+            self.store_in_library(&metadata, &identification, book_ref.as_ref(), &result)
+                .await;
+        }
+        Ok(result)
+    }
+
+    // Finds a LibGen edition by its MD5, without searching Goodreads nor
+    // LibGen, e.g. one picked from the candidates of a dry run. The title
+    // and author only name the file: LibGen is asked for them when missing,
+    // and for the format when the download links don't tell it.
+    pub async fn get_book_info_by_md5(
+        &self,
+        md5: &str,
+        title: Option<&str>,
+        author: Option<&str>,
+    ) -> Result<BookInfo, Error> {
+        let md5 = libgen::normalise_md5(md5).ok_or_else(|| {
+            Error::InvalidInput(format!(
+                "invalid MD5 {:?}: expected 32 hexadecimal characters",
+                md5
+            ))
+        })?;
+
+        let in_library = match &self.library {
+            Some(library) => library.get_with_links(&md5).await,
+            None => None,
+        };
+        let (metadata, download_links) = match in_library {
+            Some(found) => found,
+            None => {
+                let download_links = self
+                    .download_links_store
+                    .get_download_links(&md5)
+                    .await
+                    .map_err(|err| match err {
+                        library_dot_lol::Error::MissingDownloadLinks(source) => {
+                            Error::NoDownloadLinks(format!(
+                                "No download links found for {} on {}",
+                                md5, source
+                            ))
+                        }
+                        err => Error::from(err),
+                    })?;
+                let metadata = self
+                    .metadata_by_md5(&md5, title, author, &download_links)
+                    .await?;
+                (metadata, download_links)
+            }
+        };
 
         Ok(BookInfo {
-            metadata: book_metadata,
+            available_formats: vec![metadata.extension.clone()],
+            metadata,
             download_links,
+            cover_url: None,
+            isbn10: None,
+            isbn13: None,
+            alternatives: vec![],
+            found_by_title: false,
+            identification: BookIdentification::default(),
+            book: None,
         })
     }
-}
 
-impl Default for LibReads {
-    fn default() -> Self {
-        Self {
-            isbn_getter: Box::new(Goodreads::default()),
-            metadata_store: Box::new(Libgen::default()),
-            download_links_store: Box::new(LibraryDotLol::default()),
+    // Downloads the LibGen edition with this MD5, converted to
+    // `wanted_extension`, see `get_book_info_by_md5`.
+    pub async fn get_book_file_by_md5(
+        &self,
+        md5: &str,
+        title: Option<&str>,
+        author: Option<&str>,
+        wanted_extension: Extension,
+        options: &ConversionOptions,
+    ) -> Result<ConversionResult, Error> {
+        let book_info = self.get_book_info_by_md5(md5, title, author).await?;
+        self.download_as(book_info, wanted_extension, options).await
+    }
+
+    // Completes what the caller said about the edition with what LibGen
+    // knows. A LibGen failure only matters when the format is unknown.
+    async fn metadata_by_md5(
+        &self,
+        md5: &str,
+        title: Option<&str>,
+        author: Option<&str>,
+        download_links: &DownloadLinks,
+    ) -> Result<LibgenMetadata, Error> {
+        let extension = extension_from_links(download_links);
+        let found = match (title, &extension) {
+            (Some(_), Some(_)) => None,
+            _ => match self.metadata_store.get_metadata_by_md5(md5).await {
+                Ok(found) => found,
+                Err(err) if extension.is_some() => {
+                    tracing::warn!("Could not look up {} on LibGen: {:?}", md5, err);
+                    None
+                }
+                Err(err) => return Err(err.into()),
+            },
+        };
+        let filename_title = download_links.filename.as_ref().and_then(|filename| {
+            std::path::Path::new(filename)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+        });
+
+        let Some(extension) = found
+            .as_ref()
+            .map(|found| found.extension.clone())
+            .filter(|extension| *extension != Extension::Other(String::new()))
+            .or(extension)
+        else {
+            return Err(Error::ApplicationError(format!(
+                "The format of {} is unknown",
+                md5
+            )));
+        };
+        let found = found.unwrap_or_else(|| LibgenMetadata {
+            title: filename_title.unwrap_or_else(|| md5.to_string()),
+            author: String::new(),
+            year: String::new(),
+            extension: extension.clone(),
+            md5: md5.to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        });
+
+        Ok(LibgenMetadata {
+            title: title.map(str::to_string).unwrap_or(found.title),
+            author: author.map(str::to_string).unwrap_or(found.author),
+            extension,
+            md5: md5.to_string(),
+            ..found
+        })
+    }
+
+    // Keeps a copy of a downloaded book in the library, when enabled. The
+    // download doesn't fail when it can't be stored.
+    async fn store_in_library(
+        &self,
+        metadata: &LibgenMetadata,
+        identification: &BookIdentification,
+        book: Option<&BookRef>,
+        result: &ConversionResult,
+    ) {
+        let Some(library) = &self.library else {
+            return;
+        };
+        let metadata = LibgenMetadata {
+            extension: result.extension.clone(),
+            ..metadata.clone()
+        };
+        if let Err(err) = library
+            .store(&metadata, identification, book, &result.path)
+            .await
+        {
+            tracing::warn!("Could not store {} in the library: {:?}", metadata.md5, err);
         }
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub enum Error {
-    HttpError(String),
-    ApplicationError(String),
-}
+    // Lists the books in the library, most recently stored first.
+    pub async fn library_books(&self) -> Result<Vec<LibraryEntry>, Error> {
+        Ok(self.library()?.list().await)
+    }
 
-impl From<reqwest::Error> for Error {
-    fn from(err: reqwest::Error) -> Self {
-        Error::HttpError(err.to_string())
+    // Deletes a book from the library, by MD5.
+    pub async fn remove_from_library(&self, md5: &str) -> Result<(), Error> {
+        Ok(self.library()?.remove(md5).await?)
+    }
+
+    // Emails a downloaded book to the Kindle address of the configuration.
+    pub async fn send_to_kindle(&self, result: &ConversionResult) -> Result<Delivery, Error> {
+        let kindle = self.kindle.as_ref().ok_or_else(|| {
+            Error::InvalidInput(
+                "Kindle delivery isn't configured, see LIBREADS_KINDLE_EMAIL".to_string(),
+            )
+        })?;
+        let delivery = kindle
+            .send(&result.path, &result.extension.content_type())
+            .await
+            .map_err(Error::Kindle)?;
+        tracing::info!("Sent {} to {}", delivery.filename, delivery.to);
+        Ok(delivery)
+    }
+
+    fn library(&self) -> Result<&Library, Error> {
+        self.library
+            .as_ref()
+            .ok_or_else(|| Error::NotInLibrary("No library directory configured".to_string()))
+    }
+
+    // Takes the first of the candidates with download links, in order. The
+    // links of the next `links_prefetch` candidates are looked up at once,
+    // so they are ready when the first one has none. The lookups still
+    // running when a candidate is taken are dropped, and the candidates
+    // after it are left for the next call.
+    async fn next_candidate(
+        &self,
+        candidates: &mut VecDeque<LibgenMetadata>,
+        failed_attempts: &mut Vec<(String, Error)>,
+    ) -> Option<(LibgenMetadata, DownloadLinks)> {
+        let mut lookups = FuturesOrdered::new();
+        loop {
+            while lookups.len() < self.links_prefetch.max(1) {
+                let Some(candidate) = candidates.get(lookups.len()) else {
+                    break;
+                };
+                lookups.push_back(self.links(candidate.clone()));
+            }
+            let found = lookups.next().await?;
+            let candidate = candidates.pop_front()?;
+            match found {
+                Ok(found) => return Some(found),
+                Err(err) => {
+                    tracing::warn!("Could not get links for {}: {:?}", candidate.md5, err);
+                    failed_attempts.push((candidate.md5, Error::from(err)));
+                }
+            }
+        }
+    }
+
+    // Books already in the library are served from there, in the format
+    // they were stored in.
+    async fn links(
+        &self,
+        candidate: LibgenMetadata,
+    ) -> Result<(LibgenMetadata, DownloadLinks), library_dot_lol::Error> {
+        if let Some(library) = &self.library {
+            if let Some(found) = library.get_with_links(&candidate.md5).await {
+                return Ok(found);
+            }
+        }
+
+        let download_links = self
+            .download_links_store
+            .get_download_links(candidate.md5.as_str())
+            .await?;
+        Ok((candidate, download_links))
     }
 }
 
-impl From<libgen::Error> for Error {
-    fn from(err: libgen::Error) -> Self {
-        match err {
-            libgen::Error::MissingIndentificationInfo => Self::ApplicationError(
-                "Not enough info about the book found in this page".to_string(),
-            ),
-            libgen::Error::NoIsbn { title, author } => Self::ApplicationError(format!(
-                "No ISBN found for \"{title}\" by {author}",
-                title = title,
-                author = author
-            )),
-            libgen::Error::HttpError(err) => Self::HttpError(err),
+// The format of a book, from the name it was uploaded with, or its link.
+fn extension_from_links(download_links: &DownloadLinks) -> Option<Extension> {
+    let link_path = reqwest::Url::parse(&download_links.http)
+        .map(|url| url.path().to_string())
+        .unwrap_or_default();
+    let names = [download_links.filename.as_deref(), Some(link_path.as_str())];
+    for name in names.into_iter().flatten() {
+        let Some(extension) = std::path::Path::new(name).extension() else {
+            continue;
+        };
+        let Ok(extension) = extension.to_string_lossy().parse::<Extension>();
+        if !matches!(extension, Extension::Other(_)) {
+            return Some(extension);
         }
     }
+
+    None
 }
 
 #[test]
-fn test_libgen_error_to_error() {
-    for (err, want) in vec![
-        (
-            libgen::Error::MissingIndentificationInfo,
-            Error::ApplicationError(
-                "Not enough info about the book found in this page".to_string(),
-            ),
-        ),
+fn test_extension_from_links() {
+    for (filename, http, want) in [
         (
-            libgen::Error::NoIsbn {
-                title: "1984".to_string(),
-                author: "George Orwell".to_string(),
-            },
-            Error::ApplicationError(r#"No ISBN found for "1984" by George Orwell"#.to_string()),
+            Some("Jane Austen - Emma (2000).epub"),
+            "http://example.com/main/ab13",
+            Some(Extension::Epub),
         ),
         (
-            libgen::Error::HttpError("Oh no!!".to_string()),
-            Error::HttpError("Oh no!!".to_string()),
+            None,
+            "http://example.com/get/Emma.pdf?key=1",
+            Some(Extension::Pdf),
         ),
+        (None, "http://example.com/main/ab13", None),
+        (Some("Emma.unknown"), "", None),
     ] {
-        assert_eq!(want, Error::from(err));
+        let download_links = DownloadLinks {
+            filename: filename.map(str::to_string),
+            http: http.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            want,
+            extension_from_links(&download_links),
+            "{:?}",
+            filename
+        );
     }
 }
 
-impl From<&str> for Error {
-    fn from(err: &str) -> Self {
-        Error::ApplicationError(err.to_string())
+// Calibre can't read legacy DOC files: when it gave up on a DOC edition, a
+// DOCX edition of the same book that wasn't tried yet is suggested instead.
+fn suggest_docx(
+    metadata: &LibgenMetadata,
+    err: &convert::Error,
+    candidates: &[LibgenMetadata],
+    failed_attempts: &[(String, Error)],
+) -> Option<Error> {
+    if metadata.extension != Extension::Doc || !matches!(err, convert::Error::Unsupported(_)) {
+        return None;
     }
+    candidates
+        .iter()
+        .filter(|candidate| candidate.extension == Extension::Docx)
+        .find(|candidate| failed_attempts.iter().all(|(md5, _)| *md5 != candidate.md5))
+        .map(|docx| Error::UnsupportedDoc {
+            docx_md5: docx.md5.clone(),
+        })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        extension::Extension,
-        goodreads::{BookIdentification, MockBookIdentificationGetter},
-        libgen::{LibgenMetadata, MockMetadataStore},
-        library_dot_lol::MockDownloadLinksStore,
+#[test]
+fn test_suggest_docx() {
+    let edition = |extension: Extension, md5: &str| LibgenMetadata {
+        title: "Emma".to_string(),
+        author: "Jane Austen".to_string(),
+        year: "2000".to_string(),
+        extension,
+        md5: md5.to_string(),
+        filesize: None,
+        language: None,
+        pages: None,
+        publisher: None,
     };
-    use mockall::predicate::eq;
-    use std::vec;
+    let unsupported = convert::Error::Unsupported("this server's Calibre cannot read DOC".into());
+    let doc = edition(Extension::Doc, "DOC");
+    let candidates = vec![
+        edition(Extension::Pdf, "PDF"),
+        edition(Extension::Docx, "DOCX1"),
+        edition(Extension::Docx, "DOCX2"),
+    ];
 
-    #[tokio::test]
-    #[ignore = "This test calls live web pages and APIs, no need to run it with every file save."]
+    assert_eq!(
+        Some(Error::UnsupportedDoc {
+            docx_md5: "DOCX1".to_string()
+        }),
+        suggest_docx(&doc, &unsupported, &candidates, &[])
+    );
+    // Editions that already failed aren't suggested.
+    let failed = [(
+        "DOCX1".to_string(),
+        Error::Conversion(convert::Error::DrmProtected),
+    )];
+    assert_eq!(
+        Some(Error::UnsupportedDoc {
+            docx_md5: "DOCX2".to_string()
+        }),
+        suggest_docx(&doc, &unsupported, &candidates, &failed)
+    );
+    assert_eq!(
+        None,
+        suggest_docx(&doc, &unsupported, &candidates[..1], &[])
+    );
+    // Only unsupported DOC editions get a suggestion.
+    assert_eq!(
+        None,
+        suggest_docx(&doc, &convert::Error::DrmProtected, &candidates, &[])
+    );
+    assert_eq!(
+        None,
+        suggest_docx(
+            &edition(Extension::Pdf, "PDF"),
+            &unsupported,
+            &candidates,
+            &[]
+        )
+    );
+}
+
+// Opens the store at `location`, e.g. the library directory. The feature
+// using it is disabled when it can't be opened.
+fn open_file_store(location: &std::path::Path) -> Option<Arc<dyn FileStore>> {
+    match file_store::open(location) {
+        Ok(files) => Some(files),
+        Err(err) => {
+            tracing::warn!("Could not open {:?}: {}", location, err);
+            None
+        }
+    }
+}
+
+// Failures that are specific to an edition are worth retrying with another
+// one. Local I/O errors and timeouts would most likely happen again.
+fn is_retryable(err: &convert::Error) -> bool {
+    match err {
+        convert::Error::Http(_)
+        | convert::Error::UpstreamUnavailable(_)
+        | convert::Error::Conversion(_)
+        | convert::Error::DrmProtected
+        | convert::Error::TooLarge { .. }
+        | convert::Error::InvalidDownload(_)
+        | convert::Error::UnrarMissing
+        | convert::Error::Unsupported(_) => true,
+        convert::Error::Io(_) | convert::Error::ConversionTimeout(_) => false,
+    }
+}
+
+#[test]
+fn test_is_retryable() {
+    assert!(is_retryable(&convert::Error::Http(HttpFailure::other(
+        "404"
+    ))));
+    assert!(is_retryable(&convert::Error::UpstreamUnavailable(
+        "dweb.link is unavailable after 5 consecutive failures".to_string()
+    )));
+    assert!(is_retryable(&convert::Error::Conversion(
+        "traceback".to_string()
+    )));
+    assert!(is_retryable(&convert::Error::DrmProtected));
+    assert!(is_retryable(&convert::Error::UnrarMissing));
+    assert!(is_retryable(&convert::Error::Unsupported(
+        "this server's Calibre cannot read DOC".to_string()
+    )));
+    assert!(is_retryable(&convert::Error::TooLarge {
+        limit: 1024,
+        seen: 2048
+    }));
+    assert!(!is_retryable(&convert::Error::Io("disk full".to_string())));
+    assert!(!is_retryable(&convert::Error::ConversionTimeout(
+        Duration::from_secs(600)
+    )));
+}
+
+impl LibReads {
+    pub fn new(config: &Config) -> Self {
+        Self::new_with_clock(config, Arc::new(SystemClock))
+    }
+
+    // Same as `new`, with the clock telling the time to the download links
+    // cache, the library and the retries, e.g. a `clock::MockClock` in tests.
+    pub fn new_with_clock(config: &Config, clock: Arc<dyn Clock>) -> Self {
+        goodreads::compile_parsers();
+        let clock = SharedClock(clock);
+        let client = http_client::client(config.proxy.as_deref())
+            .expect("The proxy should have been validated with the config");
+        let mut limits = ConnectionLimits::new(
+            config.max_download_connections,
+            config.max_metadata_connections,
+        )
+        .with_breakers(CircuitBreakers::new(
+            circuit_breaker::DEFAULT_FAILURE_THRESHOLD,
+            circuit_breaker::DEFAULT_COOLDOWN,
+            clock.clone(),
+        ));
+        if let Some(recording) = &config.http_recording {
+            limits = limits.with_recording(recording.client());
+        }
+        let base_url = |base_url: &Option<String>| {
+            base_url.as_deref().map(|base_url| {
+                http_client::parse_base_url(base_url)
+                    .expect("The base URLs should have been validated with the config")
+            })
+        };
+        let goodreads_url = base_url(&config.goodreads_url);
+        let mut libgen = Libgen {
+            client: client.clone(),
+            limits: limits.clone(),
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        if let Some(base_url) = base_url(&config.libgen_url) {
+            libgen.base_url = base_url;
+        }
+        let library_lol_url = base_url(&config.library_lol_url);
+        let mut download_links_stores = config
+            .download_sources
+            .iter()
+            .map(
+                |source| -> Box<dyn DownloadLinksStore + Send + Sync + 'static> {
+                    match source {
+                        DownloadSource::LibraryDotLol => {
+                            let mut library_dot_lol = LibraryDotLol {
+                                client: client.clone(),
+                                limits: limits.clone(),
+                                gateways: config.ipfs_gateways.clone(),
+                                ..Default::default()
+                            };
+                            if let Some(base_url) = &library_lol_url {
+                                library_dot_lol.base_url = base_url.clone();
+                            }
+                            Box::new(library_dot_lol)
+                        }
+                        DownloadSource::LibgenRocks => Box::new(LibgenRocks {
+                            client: client.clone(),
+                            limits: limits.clone(),
+                            ..Default::default()
+                        }),
+                        DownloadSource::LibgenLi => Box::new(LibgenLi {
+                            client: client.clone(),
+                            limits: limits.clone(),
+                            ..Default::default()
+                        }),
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+
+        // Only the links found on the mirrors are cached.
+        let links_cache = config
+            .cache_dir
+            .as_ref()
+            .and_then(|dir| open_file_store(&dir.join(links_cache::CACHE_SUBDIR)));
+        if let Some(files) = links_cache {
+            download_links_stores = vec![Box::new(CachedDownloadLinksStore {
+                store: Box::new(FailoverDownloadLinksStore {
+                    stores: download_links_stores,
+                }),
+                files,
+                ttl: config.links_cache_ttl,
+                clock: clock.clone(),
+            })];
+        }
+
+        let mut metadata_stores: Vec<Box<dyn MetadataStore + Send + Sync + 'static>> =
+            vec![Box::new(libgen)];
+
+        // Books already in the local library are served from there first.
+        if let Some(path) = &config.calibre_library {
+            let library = CalibreLibrary::new(path);
+            metadata_stores.insert(0, Box::new(library.clone()));
+            download_links_stores.insert(0, Box::new(library));
+        }
+
+        // Books downloaded before come first. Their links are resolved by
+        // `next_candidate`.
+        let library = config
+            .library_dir
+            .as_ref()
+            .and_then(|dir| open_file_store(dir))
+            .map(|files| Library::new(files).with_clock(clock.clone()));
+        if let Some(library) = &library {
+            metadata_stores.insert(0, Box::new(library.clone()));
+        }
+
+        let kindle =
+            config
+                .kindle
+                .as_ref()
+                .and_then(|kindle_config| match Kindle::new(kindle_config) {
+                    Ok(kindle) => Some(kindle),
+                    Err(err) => {
+                        tracing::warn!("Kindle delivery is disabled: {}", err);
+                        None
+                    }
+                });
+
+        Self {
+            isbn_getter: Box::new(Goodreads {
+                client: client.clone(),
+                limits: limits.clone(),
+                base_url: goodreads_url.clone(),
+                clock: clock.clone(),
+            }),
+            shelf_getter: Box::new(Goodreads {
+                client,
+                limits: limits.clone(),
+                base_url: goodreads_url,
+                clock,
+            }),
+            metadata_store: Box::new(FailoverMetadataStore {
+                stores: metadata_stores,
+            }),
+            download_links_store: Box::new(FailoverDownloadLinksStore {
+                stores: download_links_stores,
+            }),
+            max_attempts: config.max_attempts,
+            links_prefetch: config.links_prefetch,
+            max_editions: config.max_editions,
+            can_convert_djvu: convert::is_on_path(convert::DDJVU_EXECUTABLE),
+            ranker: Box::new(DefaultRanker::default()),
+            library,
+            kindle,
+            connection_limits: limits,
+        }
+    }
+
+    // The circuits of the upstreams LibReads calls, shared with everything
+    // calling them.
+    pub fn circuit_breakers(&self) -> &CircuitBreakers {
+        self.connection_limits.breakers()
+    }
+
+    // Replaces how editions are ordered, `ranking::DefaultRanker` by default.
+    pub fn with_ranker(mut self, ranker: impl CandidateRanker + Send + Sync + 'static) -> Self {
+        self.ranker = Box::new(ranker);
+        self
+    }
+}
+
+impl Default for LibReads {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    HttpError(HttpFailure),
+    ApplicationError(String),
+    UpstreamUnavailable(String),
+    InvalidInput(String),
+    Conversion(convert::Error),
+    Kindle(kindle::Error),
+    // Every edition that was tried failed, with the MD5 of each of them.
+    AttemptsFailed(Vec<(String, Error)>),
+    // The book isn't in the library, or there is no library.
+    NotInLibrary(String),
+    // Calibre can't read the DOC edition, but the DOCX edition with this MD5
+    // can be tried instead.
+    UnsupportedDoc {
+        docx_md5: String,
+    },
+    // No mirror has the edition asked for by MD5.
+    NoDownloadLinks(String),
+    // LibGen had no usable edition of the book.
+    NotFound {
+        searched: Box<BookIdentification>,
+        filters: FilterReport,
+    },
+    // An upstream, e.g. "Goodreads", asked us to slow down.
+    RateLimited {
+        upstream: String,
+        limit: RateLimit,
+    },
+}
+
+impl Error {
+    // A single failed attempt is reported as is.
+    fn from_attempts(mut attempts: Vec<(String, Error)>) -> Self {
+        match attempts.len() {
+            0 => Self::ApplicationError("No edition could be tried".to_string()),
+            1 => attempts.remove(0).1,
+            _ => Self::AttemptsFailed(attempts),
+        }
+    }
+}
+
+#[test]
+fn test_error_from_attempts() {
+    assert_eq!(
+        Error::ApplicationError("No edition could be tried".to_string()),
+        Error::from_attempts(vec![])
+    );
+    assert_eq!(
+        Error::HttpError(HttpFailure::other("404")),
+        Error::from_attempts(vec![(
+            "A".to_string(),
+            Error::HttpError(HttpFailure::other("404"))
+        )])
+    );
+
+    let attempts = vec![
+        ("A".to_string(), Error::HttpError(HttpFailure::other("404"))),
+        (
+            "B".to_string(),
+            Error::Conversion(convert::Error::DrmProtected),
+        ),
+    ];
+    assert_eq!(
+        Error::AttemptsFailed(attempts.clone()),
+        Error::from_attempts(attempts)
+    );
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::HttpError(HttpFailure::from(err))
+    }
+}
+
+impl From<goodreads::Error> for Error {
+    fn from(err: goodreads::Error) -> Self {
+        match err {
+            goodreads::Error::HttpError(err) => Self::HttpError(err),
+            goodreads::Error::RateLimited(limit) => Self::RateLimited {
+                upstream: "Goodreads".to_string(),
+                limit,
+            },
+            goodreads::Error::InvalidUrl(message) => Self::InvalidInput(message),
+            goodreads::Error::UpstreamUnavailable(message) => Self::UpstreamUnavailable(message),
+        }
+    }
+}
+
+#[test]
+fn test_goodreads_error_to_error() {
+    assert_eq!(
+        Error::HttpError(HttpFailure::other("Oh no!!")),
+        Error::from(goodreads::Error::HttpError(HttpFailure::other("Oh no!!")))
+    );
+    assert_eq!(
+        Error::InvalidInput("unsupported URL".to_string()),
+        Error::from(goodreads::Error::InvalidUrl("unsupported URL".to_string()))
+    );
+    assert_eq!(
+        Error::UpstreamUnavailable("www.goodreads.com is unavailable".to_string()),
+        Error::from(goodreads::Error::UpstreamUnavailable(
+            "www.goodreads.com is unavailable".to_string()
+        ))
+    );
+    let limit = RateLimit {
+        status: 429,
+        retry_after: Some(Duration::from_secs(120)),
+    };
+    assert_eq!(
+        Error::RateLimited {
+            upstream: "Goodreads".to_string(),
+            limit: limit.clone(),
+        },
+        Error::from(goodreads::Error::RateLimited(limit))
+    );
+}
+
+impl From<libgen::Error> for Error {
+    fn from(err: libgen::Error) -> Self {
+        match err {
+            libgen::Error::MissingIndentificationInfo => Self::ApplicationError(
+                "Not enough info about the book found in this page".to_string(),
+            ),
+            libgen::Error::NoIsbn { title, author } => Self::ApplicationError(format!(
+                "No ISBN found for \"{title}\" by {author}",
+                title = title,
+                author = author
+            )),
+            libgen::Error::HttpError(err) => Self::HttpError(err),
+            libgen::Error::UpstreamUnavailable(excerpt) => {
+                Self::UpstreamUnavailable(format!("LibGen is temporarily unavailable: {}", excerpt))
+            }
+            libgen::Error::LocalLibrary(err) => {
+                Self::ApplicationError(format!("Could not read the Calibre library: {}", err))
+            }
+            libgen::Error::RateLimited(limit) => Self::RateLimited {
+                upstream: "LibGen".to_string(),
+                limit,
+            },
+        }
+    }
+}
+
+#[test]
+fn test_libgen_error_to_error() {
+    for (err, want) in [
+        (
+            libgen::Error::MissingIndentificationInfo,
+            Error::ApplicationError(
+                "Not enough info about the book found in this page".to_string(),
+            ),
+        ),
+        (
+            libgen::Error::NoIsbn {
+                title: "1984".to_string(),
+                author: "George Orwell".to_string(),
+            },
+            Error::ApplicationError(r#"No ISBN found for "1984" by George Orwell"#.to_string()),
+        ),
+        (
+            libgen::Error::HttpError(HttpFailure::other("Oh no!!")),
+            Error::HttpError(HttpFailure::other("Oh no!!")),
+        ),
+        (
+            libgen::Error::UpstreamUnavailable("Database maintenance".to_string()),
+            Error::UpstreamUnavailable(
+                "LibGen is temporarily unavailable: Database maintenance".to_string(),
+            ),
+        ),
+        (
+            libgen::Error::LocalLibrary("no such table: books".to_string()),
+            Error::ApplicationError(
+                "Could not read the Calibre library: no such table: books".to_string(),
+            ),
+        ),
+        (
+            libgen::Error::RateLimited(RateLimit {
+                status: 503,
+                retry_after: None,
+            }),
+            Error::RateLimited {
+                upstream: "LibGen".to_string(),
+                limit: RateLimit {
+                    status: 503,
+                    retry_after: None,
+                },
+            },
+        ),
+    ] {
+        assert_eq!(want, Error::from(err));
+    }
+}
+
+impl From<library_dot_lol::Error> for Error {
+    fn from(err: library_dot_lol::Error) -> Self {
+        match err {
+            library_dot_lol::Error::HttpError(err) => Self::HttpError(err),
+            library_dot_lol::Error::MissingDownloadLinks(source) => {
+                Self::ApplicationError(format!("No download links found on {}", source))
+            }
+            library_dot_lol::Error::NoDownloadLinksStore => {
+                Self::ApplicationError("No download source configured".to_string())
+            }
+            library_dot_lol::Error::UpstreamUnavailable(message) => {
+                Self::UpstreamUnavailable(message)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_library_dot_lol_error_to_error() {
+    for (err, want) in [
+        (
+            library_dot_lol::Error::HttpError(HttpFailure::other("Oh no!!")),
+            Error::HttpError(HttpFailure::other("Oh no!!")),
+        ),
+        (
+            library_dot_lol::Error::MissingDownloadLinks("libgen.li".to_string()),
+            Error::ApplicationError("No download links found on libgen.li".to_string()),
+        ),
+        (
+            library_dot_lol::Error::NoDownloadLinksStore,
+            Error::ApplicationError("No download source configured".to_string()),
+        ),
+        (
+            library_dot_lol::Error::UpstreamUnavailable("library.lol is unavailable".to_string()),
+            Error::UpstreamUnavailable("library.lol is unavailable".to_string()),
+        ),
+    ] {
+        assert_eq!(want, Error::from(err));
+    }
+}
+
+impl From<library::Error> for Error {
+    fn from(err: library::Error) -> Self {
+        match err {
+            library::Error::InvalidMd5 => Self::InvalidInput("Invalid MD5".to_string()),
+            library::Error::NotFound(md5) => {
+                Self::NotInLibrary(format!("{} is not in the library", md5))
+            }
+            library::Error::Io(err) => {
+                Self::ApplicationError(format!("Could not update the library: {}", err))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_library_error_to_error() {
+    for (err, want) in [
+        (
+            library::Error::InvalidMd5,
+            Error::InvalidInput("Invalid MD5".to_string()),
+        ),
+        (
+            library::Error::NotFound("MYBOOKMD5".to_string()),
+            Error::NotInLibrary("MYBOOKMD5 is not in the library".to_string()),
+        ),
+        (
+            library::Error::Io("disk full".to_string()),
+            Error::ApplicationError("Could not update the library: disk full".to_string()),
+        ),
+    ] {
+        assert_eq!(want, Error::from(err));
+    }
+}
+
+impl From<&str> for Error {
+    fn from(err: &str) -> Self {
+        Error::ApplicationError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DEFAULT_LINKS_PREFETCH, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_EDITIONS};
+    use crate::{
+        goodreads::{BookIdentification, MockBookIdentificationGetter, MockShelfGetter},
+        libgen::{LibgenMetadata, MockMetadataStore},
+        library_dot_lol::MockDownloadLinksStore,
+    };
+    use mockall::predicate::{eq, function};
+    use std::vec;
+
+    // Matches the identifications of the same book, however their ISBNs are
+    // written.
+    fn same_book(
+        want: BookIdentification,
+    ) -> impl mockall::Predicate<BookIdentification> + Send + Sync + 'static {
+        function(move |got: &BookIdentification| got.same_book(&want))
+    }
+
+    #[tokio::test]
+    #[ignore = "This test calls live web pages and APIs, no need to run it with every file save."]
     async fn third_party_test_get_download_links_from_goodreads_url() {
         let test_url = "https://www.goodreads.com/book/show/1048424.Governing_the_Commons";
         let libreads = LibReads::default();
@@ -154,8 +1668,8 @@ mod tests {
             .expect("Should get download links");
 
         assert_eq!(
-            "https://gateway.ipfs.io/ipfs/bafykbzacedqn6erurfdw45jy4xbwldyh3ihqykr2kp3sx7knm6lslzcj66m76?filename=%28Political%20Economy%20of%20Institutions%20and%20Decisions%29%20Elinor%20Ostrom%20-%20Governing%20the%20Commons_%20The%20Evolution%20of%20Institutions%20for%20Collective%20Action%20%28Political%20Economy%20of%20Institutions%20and%20Decisions%29-Cambridge.djvu",
-            got.download_links.ipfs_dot_io
+            "https://ipfs.io/ipfs/bafykbzacedqn6erurfdw45jy4xbwldyh3ihqykr2kp3sx7knm6lslzcj66m76?filename=%28Political%20Economy%20of%20Institutions%20and%20Decisions%29%20Elinor%20Ostrom%20-%20Governing%20the%20Commons_%20The%20Evolution%20of%20Institutions%20for%20Collective%20Action%20%28Political%20Economy%20of%20Institutions%20and%20Decisions%29-Cambridge.djvu",
+            got.download_links.ipfs_dot_io()
         );
         assert_eq!(
             "https://download.library.lol/main/501000/b41ce081c95a5c4864bec8488a7a6387/%28Political%20Economy%20of%20Institutions%20and%20Decisions%29%20Elinor%20Ostrom%20-%20Governing%20the%20Commons_%20The%20Evolution%20of%20Institutions%20for%20Collective%20Action%20%28Political%20Economy%20of%20Institutions%20and%20Decisions%29-Cambridge.djvu",
@@ -175,110 +1689,1787 @@ mod tests {
 
         let libreads = LibReads {
             isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
             metadata_store: Box::new(Libgen::default()),
             download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert!(got.is_err());
+        println!("{:?}", got);
+
+        assert_eq!(
+            Err(Error::ApplicationError(
+                "Not enough info about the book found in this page".to_string()
+            )),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_propagates_reqwest_errors() {
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .with(eq("http://hello.world"))
+            .once()
+            .returning(move |_| {
+                // Using a badly formatted URL is the best way I found of returning a reqwest::Error.
+                // TODO: change `get_isbn` to wrap the error in a custom type instead.
+                Box::pin(async { Err(reqwest::get("Bad_Url").await.unwrap_err().into()) })
+            });
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert_eq!(
+            Err(Error::HttpError(HttpFailure::other("builder error"))),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_nothing_found_on_libgen() {
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .with(eq("http://hello.world"))
+            .once()
+            .returning(move |_| {
+                Box::pin(async {
+                    Ok(BookIdentification {
+                        isbn10: None,
+                        isbn13: Some("fake_isbn_13".to_string()),
+                        title: None,
+                        author: None,
+                        cover_url: None,
+                        pages: None,
+                        publisher: None,
+                        series: None,
+                        series_index: None,
+                        format: None,
+                    })
+                })
+            });
+
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(same_book(BookIdentification {
+                isbn10: None,
+                isbn13: Some("fake_isbn_13".to_string()),
+                title: None,
+                author: None,
+                cover_url: None,
+                pages: None,
+                publisher: None,
+                series: None,
+                series_index: None,
+                format: None,
+            }))
+            .once()
+            .returning(move |_| Box::pin(async { Ok(vec![]) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert_eq!(
+            Err(Error::NotFound {
+                searched: Box::new(BookIdentification {
+                    isbn13: Some("fake_isbn_13".to_string()),
+                    ..Default::default()
+                }),
+                filters: FilterReport::default(),
+            }),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_book_info_filters_remove_everything() {
+        let edition = |extension: Extension, year: &str, md5: &str| LibgenMetadata {
+            title: "Governing the Commons".to_string(),
+            author: "Elinor Ostrom".to_string(),
+            year: year.to_string(),
+            extension,
+            md5: md5.to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        };
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .once()
+            .returning(move |_| {
+                Box::pin(async move {
+                    Ok(vec![
+                        edition(Extension::Pdf, "2015", "PDF"),
+                        edition(Extension::Epub, "1990", "OLDEPUB"),
+                    ])
+                })
+            });
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(governing_the_commons())),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            // No edition is left to get links for.
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let filters = SelectionFilters::parse(Some("pdf"), None, None, Some("2010")).unwrap();
+        let got = libreads
+            .get_book_info_with_timings("http://hello.world", None, &filters)
+            .await;
+
+        assert_eq!(
+            Err(Error::NotFound {
+                searched: Box::new(governing_the_commons()),
+                filters: FilterReport {
+                    results: 2,
+                    filtered_out: 2,
+                    emptied_by: Some(Filter::MinYear),
+                    ..Default::default()
+                },
+            }),
+            got.map(|(book_info, _)| book_info)
+        );
+    }
+
+    fn governing_the_commons() -> BookIdentification {
+        BookIdentification {
+            isbn13: Some("9780521405997".to_string()),
+            title: Some("Governing the Commons".to_string()),
+            author: Some("Elinor Ostrom".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn identification_getter(identification: BookIdentification) -> MockBookIdentificationGetter {
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .once()
+            .returning(move |_| {
+                let identification = identification.clone();
+                Box::pin(async move { Ok(identification) })
+            });
+        isbn_getter_mock
+    }
+
+    #[tokio::test]
+    async fn test_title_search_when_nothing_found_by_isbn() {
+        let edition = |title: &str, author: &str, md5: &str| LibgenMetadata {
+            title: title.to_string(),
+            author: author.to_string(),
+            year: "1990".to_string(),
+            extension: Extension::Epub,
+            md5: md5.to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        };
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(same_book(governing_the_commons()))
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        let found = vec![
+            edition(
+                "Governing the Commons: The Evolution of Institutions for Collective Action",
+                "Ostrom, Elinor",
+                "OSTROMMD5",
+            ),
+            edition(
+                "Elinor Ostrom's Rules for Radicals",
+                "Derek Wall",
+                "WALLMD5",
+            ),
+        ];
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .once()
+            .returning(move |_| {
+                let found = found.clone();
+                Box::pin(async move { Ok(found) })
+            });
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("OSTROMMD5"))
+            .once()
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(DownloadLinks {
+                        http: "http://example.com/book.epub".to_string(),
+                        ..Default::default()
+                    })
+                })
+            });
+
+        let libreads = LibReads {
+            // Goodreads sometimes writes ISBNs with dashes.
+            isbn_getter: Box::new(identification_getter(BookIdentification {
+                isbn13: Some("978-0-521-40599-7".to_string()),
+                ..governing_the_commons()
+            })),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await
+            .expect("The book should be found by title");
+
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+        assert!(got.found_by_title);
+        // Books by other authors are left out.
+        assert_eq!(vec![Extension::Epub], got.available_formats);
+        assert!(got.alternatives.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_book_info_by_query() {
+        let ostrom = || {
+            vec![LibgenMetadata {
+                title: "Governing the Commons".to_string(),
+                author: "Elinor Ostrom".to_string(),
+                year: "1990".to_string(),
+                extension: Extension::Epub,
+                md5: "OSTROMMD5".to_string(),
+                filesize: None,
+                language: None,
+                pages: None,
+                publisher: None,
+            }]
+        };
+        let libreads = |isbn_getter: MockBookIdentificationGetter,
+                        metadata_store: MockMetadataStore| {
+            let mut download_links_store_mock = MockDownloadLinksStore::new();
+            download_links_store_mock
+                .expect_get_download_links()
+                .with(eq("OSTROMMD5"))
+                .returning(|_| Box::pin(async { Ok(DownloadLinks::default()) }));
+            LibReads {
+                isbn_getter: Box::new(isbn_getter),
+                shelf_getter: Box::new(MockShelfGetter::new()),
+                metadata_store: Box::new(metadata_store),
+                download_links_store: Box::new(download_links_store_mock),
+                max_attempts: DEFAULT_MAX_ATTEMPTS,
+                links_prefetch: DEFAULT_LINKS_PREFETCH,
+                max_editions: DEFAULT_MAX_EDITIONS,
+                can_convert_djvu: true,
+                ranker: Box::new(DefaultRanker::default()),
+                library: None,
+                kindle: None,
+                connection_limits: ConnectionLimits::default(),
+            }
+        };
+
+        // ISBNs are looked up on LibGen without reading any page.
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock.expect_get_identification().never();
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(eq(BookIdentification {
+                isbn13: Some("9780521405997".to_string()),
+                ..Default::default()
+            }))
+            .once()
+            .returning(move |_| Box::pin(async move { Ok(ostrom()) }));
+        let got = libreads(isbn_getter_mock, metadata_store_mock)
+            .get_book_info(&"isbn:978-0-521-40599-7".parse().unwrap())
+            .await
+            .expect("The book should be found by ISBN");
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+        assert!(!got.found_by_title);
+        assert_eq!(None, got.book);
+
+        // Titles are searched for straight away.
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock.expect_get_identification().never();
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock.expect_get_metadata().never();
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .once()
+            .returning(move |_| Box::pin(async move { Ok(ostrom()) }));
+        let got = libreads(isbn_getter_mock, metadata_store_mock)
+            .get_book_info(&BookQuery::TitleAndAuthor {
+                title: "Governing the Commons".to_string(),
+                author: "Elinor Ostrom".to_string(),
+            })
+            .await
+            .expect("The book should be found by title");
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+        assert!(got.found_by_title);
+        assert_eq!(
+            BookIdentification::from_title_and_author("Governing the Commons", "Elinor Ostrom"),
+            got.identification
+        );
+
+        // Goodreads IDs are read from their page.
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .with(eq("https://www.goodreads.com/book/show/1048424"))
+            .once()
+            .returning(|_| Box::pin(async { Ok(governing_the_commons()) }));
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(same_book(governing_the_commons()))
+            .once()
+            .returning(move |_| Box::pin(async move { Ok(ostrom()) }));
+        let got = libreads(isbn_getter_mock, metadata_store_mock)
+            .get_book_info(&BookQuery::Goodreads("1048424".to_string()))
+            .await
+            .expect("The book should be found from its page");
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+        assert_eq!(Some("1048424".parse().unwrap()), got.book);
+    }
+
+    #[tokio::test]
+    async fn test_title_search_without_subtitle() {
+        let searched = BookIdentification {
+            title: Some(
+                "Governing the Commons: The Evolution of Institutions for Collective Action"
+                    .to_string(),
+            ),
+            ..governing_the_commons()
+        };
+        let found = vec![LibgenMetadata {
+            title: "Governing the Commons".to_string(),
+            author: "Elinor Ostrom".to_string(),
+            year: "1990".to_string(),
+            extension: Extension::Epub,
+            md5: "OSTROMMD5".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }];
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        let mut searches = mockall::Sequence::new();
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq(
+                "Governing the Commons: The Evolution of Institutions for Collective Action",
+            ))
+            .once()
+            .in_sequence(&mut searches)
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .once()
+            .in_sequence(&mut searches)
+            .returning(move |_| {
+                let found = found.clone();
+                Box::pin(async move { Ok(found) })
+            });
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("OSTROMMD5"))
+            .once()
+            .returning(|_| Box::pin(async { Ok(DownloadLinks::default()) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(searched)),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await
+            .expect("The book should be found by its short title");
+
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+        assert!(got.found_by_title);
+    }
+
+    // The full title finding the book, the short one isn't searched.
+    #[tokio::test]
+    async fn test_title_search_with_subtitle() {
+        let title = "Governing the Commons: The Evolution of Institutions for Collective Action";
+        let found = vec![LibgenMetadata {
+            title: title.to_string(),
+            author: "Elinor Ostrom".to_string(),
+            year: "1990".to_string(),
+            extension: Extension::Epub,
+            md5: "OSTROMMD5".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }];
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq(title))
+            .once()
+            .returning(move |_| {
+                let found = found.clone();
+                Box::pin(async move { Ok(found) })
+            });
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .never();
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .once()
+            .returning(|_| Box::pin(async { Ok(DownloadLinks::default()) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(BookIdentification {
+                title: Some(title.to_string()),
+                ..governing_the_commons()
+            })),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await
+            .expect("The book should be found by its full title");
+
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+    }
+
+    #[tokio::test]
+    async fn test_audiobook_edition_searched_by_title() {
+        let found = vec![LibgenMetadata {
+            title: "Governing the Commons".to_string(),
+            author: "Elinor Ostrom".to_string(),
+            year: "1990".to_string(),
+            extension: Extension::Epub,
+            md5: "OSTROMMD5".to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }];
+        let mut metadata_store_mock = MockMetadataStore::new();
+        // The audiobook's ISBN isn't looked up.
+        metadata_store_mock.expect_get_metadata().never();
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .once()
+            .returning(move |_| {
+                let found = found.clone();
+                Box::pin(async move { Ok(found) })
+            });
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("OSTROMMD5"))
+            .once()
+            .returning(|_| Box::pin(async { Ok(DownloadLinks::default()) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(BookIdentification {
+                format: Some("Audible Audio".to_string()),
+                ..governing_the_commons()
+            })),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await
+            .expect("The book should be found by title");
+
+        assert_eq!("OSTROMMD5", got.metadata.md5);
+        assert!(got.found_by_title);
+    }
+
+    // Without a title and author to search by, the box set's ISBN is all
+    // there is.
+    #[tokio::test]
+    async fn test_box_set_without_author_searched_by_isbn() {
+        let box_set = BookIdentification {
+            isbn13: Some("9780345538376".to_string()),
+            title: Some("The Hobbit and The Lord of the Rings".to_string()),
+            format: Some("Box Set".to_string()),
+            ..Default::default()
+        };
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(same_book(box_set.clone()))
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        metadata_store_mock.expect_search_by_title().never();
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(box_set)),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert!(matches!(got, Err(Error::NotFound { .. })), "{:?}", got);
+    }
+
+    #[tokio::test]
+    async fn test_nothing_found_by_isbn_nor_title() {
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(governing_the_commons())),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert_eq!(
+            Err(Error::NotFound {
+                searched: Box::new(governing_the_commons()),
+                filters: FilterReport::default(),
+            }),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_title_search_without_edition() {
+        let searched = BookIdentification {
+            title: Some("Governing the Commons (English Edition)".to_string()),
+            ..governing_the_commons()
+        };
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+        metadata_store_mock
+            .expect_search_by_title()
+            .with(eq("Governing the Commons"))
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![]) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(searched.clone())),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        // The title shown is still the Goodreads one.
+        assert_eq!(
+            Err(Error::NotFound {
+                searched: Box::new(searched),
+                filters: FilterReport::default(),
+            }),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_book_info_by_md5() {
+        const MD5: &str = "ab13556b96d473c8dfad7165c4704526";
+        let emma = LibgenMetadata {
+            title: "Emma".to_string(),
+            author: "Jane Austen".to_string(),
+            year: "2000".to_string(),
+            extension: Extension::Epub,
+            md5: MD5.to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        };
+        let found = emma.clone();
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata_by_md5()
+            .with(eq(MD5))
+            .once()
+            .return_once(|_| Box::pin(async { Ok(Some(found)) }));
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq(MD5))
+            .once()
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(DownloadLinks {
+                        http: "http://example.com/main/ab13".to_string(),
+                        ..Default::default()
+                    })
+                })
+            });
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        // Without a title, LibGen names the book.
+        let got = libreads
+            .get_book_info_by_md5(" AB13556B96D473C8DFAD7165C4704526", None, Some("J. Austen"))
+            .await
+            .expect("The book should be found");
+
+        assert_eq!(
+            LibgenMetadata {
+                author: "J. Austen".to_string(),
+                ..emma
+            },
+            got.metadata
+        );
+        assert_eq!(vec![Extension::Epub], got.available_formats);
+        assert!(got.alternatives.is_empty());
+
+        assert!(matches!(
+            libreads.get_book_info_by_md5("MYBOOKMD5", None, None).await,
+            Err(Error::InvalidInput(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_books_from_shelf_url() {
+        let shelf_url = "https://www.goodreads.com/review/list/42-jane?shelf=to-read";
+        let entry = ShelfEntry {
+            book_url: "https://www.goodreads.com/book/show/40961427-1984".to_string(),
+            title: Some("1984".to_string()),
+            author: Some("George Orwell".to_string()),
+        };
+
+        let mut shelf_getter_mock = MockShelfGetter::new();
+        let returned_entry = entry.clone();
+        shelf_getter_mock
+            .expect_get_shelf()
+            .with(eq(shelf_url))
+            .once()
+            .returning(move |_| {
+                let entry = returned_entry.clone();
+                Box::pin(async move { Ok(vec![entry]) })
+            });
+        shelf_getter_mock
+            .expect_get_shelf()
+            .with(eq("https://www.goodreads.com/book/show/1"))
+            .once()
+            .returning(|url| {
+                let err = goodreads::Error::InvalidUrl(url.to_string());
+                Box::pin(async move { Err(err) })
+            });
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(shelf_getter_mock),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+
+        assert_eq!(
+            Ok(vec![entry]),
+            libreads.list_books_from_shelf_url(shelf_url).await
+        );
+        assert_eq!(
+            Err(Error::InvalidInput(
+                "https://www.goodreads.com/book/show/1".to_string()
+            )),
+            libreads
+                .list_books_from_shelf_url("https://www.goodreads.com/book/show/1")
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_found_some_links() {
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .with(eq("http://hello.world"))
+            .once()
+            .returning(move |_| {
+                Box::pin(async {
+                    Ok(BookIdentification {
+                        isbn10: Some("fake_isbn_10".to_string()),
+                        isbn13: None,
+                        title: None,
+                        author: None,
+                        cover_url: Some("fake_cover_url".to_string()),
+                        pages: None,
+                        publisher: None,
+                        series: None,
+                        series_index: None,
+                        format: None,
+                    })
+                })
+            });
+
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(same_book(BookIdentification {
+                isbn10: Some("fake_isbn_10".to_string()),
+                isbn13: None,
+                title: None,
+                author: None,
+                cover_url: Some("fake_cover_url".to_string()),
+                pages: None,
+                publisher: None,
+                series: None,
+                series_index: None,
+                format: None,
+            }))
+            .once()
+            .returning(move |_| {
+                Box::pin(async {
+                    Ok(vec![
+                        LibgenMetadata {
+                            title: "hello".to_string(),
+                            author: "hello".to_string(),
+                            year: "hello".to_string(),
+                            extension: Extension::Pdf,
+                            md5: "MYPDFMD5".to_string(),
+                            filesize: None,
+                            language: None,
+                            pages: None,
+                            publisher: None,
+                        },
+                        LibgenMetadata {
+                            title: "hello".to_string(),
+                            author: "hello".to_string(),
+                            year: "hello".to_string(),
+                            extension: Extension::Mobi,
+                            md5: "MYBOOKMD5".to_string(),
+                            filesize: None,
+                            language: None,
+                            pages: None,
+                            publisher: None,
+                        },
+                    ])
+                })
+            });
+
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("MYBOOKMD5"))
+            .once()
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(DownloadLinks {
+                        ipfs_cid: Some("fake_cid".to_string()),
+                        gateways: vec!["https://fake.gateway".to_string()],
+                        http: "fake_http_link".to_string(),
+                        filename: None,
+                    })
+                })
+            });
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert_eq!(
+            Ok(BookInfo {
+                metadata: LibgenMetadata {
+                    title: "hello".to_string(),
+                    author: "hello".to_string(),
+                    year: "hello".to_string(),
+                    extension: Extension::Mobi,
+                    md5: "MYBOOKMD5".to_string(),
+                    filesize: None,
+                    language: None,
+                    pages: None,
+                    publisher: None,
+                },
+                download_links: DownloadLinks {
+                    ipfs_cid: Some("fake_cid".to_string()),
+                    gateways: vec!["https://fake.gateway".to_string()],
+                    http: "fake_http_link".to_string(),
+                    filename: None,
+                },
+                cover_url: Some("fake_cover_url".to_string()),
+                isbn10: Some("fake_isbn_10".to_string()),
+                isbn13: None,
+                available_formats: vec![Extension::Pdf, Extension::Mobi],
+                alternatives: vec![LibgenMetadata {
+                    title: "hello".to_string(),
+                    author: "hello".to_string(),
+                    year: "hello".to_string(),
+                    extension: Extension::Pdf,
+                    md5: "MYPDFMD5".to_string(),
+                    filesize: None,
+                    language: None,
+                    pages: None,
+                    publisher: None,
+                }],
+                found_by_title: false,
+                identification: BookIdentification {
+                    isbn10: Some("fake_isbn_10".to_string()),
+                    isbn13: None,
+                    title: None,
+                    author: None,
+                    cover_url: Some("fake_cover_url".to_string()),
+                    pages: None,
+                    publisher: None,
+                    series: None,
+                    series_index: None,
+                    format: None,
+                },
+                book: None,
+            }),
+            got
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_download_links_failed_to_get_library_dot_lol_links() {
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .with(eq("http://hello.world"))
+            .once()
+            .returning(move |_| {
+                Box::pin(async {
+                    Ok(BookIdentification {
+                        isbn10: Some("fake_isbn_10".to_string()),
+                        isbn13: None,
+                        title: None,
+                        author: None,
+                        cover_url: None,
+                        pages: None,
+                        publisher: None,
+                        series: None,
+                        series_index: None,
+                        format: None,
+                    })
+                })
+            });
+
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata()
+            .with(same_book(BookIdentification {
+                isbn10: Some("fake_isbn_10".to_string()),
+                isbn13: None,
+                title: None,
+                author: None,
+                cover_url: None,
+                pages: None,
+                publisher: None,
+                series: None,
+                series_index: None,
+                format: None,
+            }))
+            .once()
+            .returning(move |_| {
+                Box::pin(async {
+                    Ok(vec![LibgenMetadata {
+                        title: "hello".to_string(),
+                        author: "hello".to_string(),
+                        year: "hello".to_string(),
+                        extension: Extension::Mobi,
+                        md5: "MYBOOKMD5".to_string(),
+                        filesize: None,
+                        language: None,
+                        pages: None,
+                        publisher: None,
+                    }])
+                })
+            });
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(LibraryDotLol {
+                base_url: "bad url".to_string(),
+                ..Default::default()
+            }),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let got = libreads
+            .get_book_info_from_goodreads_url("http://hello.world")
+            .await;
+
+        assert_eq!(
+            Err(Error::HttpError(HttpFailure::other("builder error"))),
+            got
+        )
+    }
+
+    fn edition(extension: Extension, md5: &str) -> LibgenMetadata {
+        LibgenMetadata {
+            title: format!("drm test {}", md5),
+            author: "hello".to_string(),
+            year: "hello".to_string(),
+            extension,
+            md5: md5.to_string(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }
+    }
+
+    fn book_info(
+        metadata: LibgenMetadata,
+        download_link: String,
+        alternatives: Vec<LibgenMetadata>,
+    ) -> BookInfo {
+        BookInfo {
+            metadata,
+            download_links: DownloadLinks {
+                http: download_link,
+                ..Default::default()
+            },
+            cover_url: None,
+            isbn10: None,
+            isbn13: None,
+            available_formats: vec![],
+            alternatives,
+            found_by_title: false,
+            identification: BookIdentification::default(),
+            book: None,
+        }
+    }
+
+    #[test]
+    fn test_book_info_round_trip() {
+        let mut book_info = book_info(
+            edition(Extension::Epub, "EPUBMD5"),
+            "https://example.com/book.epub".to_string(),
+            vec![edition(Extension::Pdf, "PDFMD5")],
+        );
+        book_info.cover_url = Some("https://example.com/cover.jpg".to_string());
+        book_info.available_formats = vec![Extension::Epub, Extension::Pdf];
+
+        let json = serde_json::to_string(&book_info).unwrap();
+        assert_eq!(book_info, serde_json::from_str(&json).unwrap());
+    }
+
+    #[cfg(feature = "convert")]
+    #[tokio::test]
+    async fn test_download_as_skips_drm_protected_edition() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let pdf_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/encrypted.pdf");
+            then.status(200).body(include_bytes!(
+                "../tests/testdata/dummy_encrypted_ebook.pdf"
+            ));
+        });
+        let mobi_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.mobi");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
+        });
+
+        let mobi_url = mock_server.url("/book.mobi");
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("MOBIMD5"))
+            .once()
+            .returning(move |_| {
+                let http = mobi_url.clone();
+                Box::pin(async move {
+                    Ok(DownloadLinks {
+                        http,
+                        ..Default::default()
+                    })
+                })
+            });
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+
+        let got = libreads
+            .download_as(
+                book_info(
+                    edition(Extension::Pdf, "PDFMD5"),
+                    mock_server.url("/encrypted.pdf"),
+                    vec![edition(Extension::Mobi, "MOBIMD5")],
+                ),
+                Extension::Mobi,
+                &ConversionOptions::default(),
+            )
+            .await
+            .expect("the second edition should be used");
+
+        pdf_mock.assert();
+        mobi_mock.assert();
+        assert_eq!(Extension::Mobi, got.extension);
+        assert!(!got.converted);
+        std::fs::remove_file(got.path).unwrap();
+        assert!(!std::path::Path::new("drm test PDFMD5.pdf").exists());
+    }
+
+    #[cfg(feature = "convert")]
+    #[tokio::test]
+    async fn test_download_as_all_editions_drm_protected() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let pdf_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/encrypted.pdf");
+            then.status(200).body(include_bytes!(
+                "../tests/testdata/dummy_encrypted_ebook.pdf"
+            ));
+        });
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+
+        let got = libreads
+            .download_as(
+                book_info(
+                    edition(Extension::Pdf, "ONLYMD5"),
+                    mock_server.url("/encrypted.pdf"),
+                    vec![],
+                ),
+                Extension::Mobi,
+                &ConversionOptions::default(),
+            )
+            .await;
+
+        pdf_mock.assert();
+        assert_eq!(Err(Error::Conversion(convert::Error::DrmProtected)), got);
+    }
+
+    #[tokio::test]
+    async fn test_download_as_skips_edition_in_another_language() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let russian_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/russian.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/russian_ebook.epub"));
+        });
+        let english_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/english.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/english_ebook.epub"));
+        });
+
+        let english_url = mock_server.url("/english.epub");
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("ENGLISHMD5"))
+            .once()
+            .returning(move |_| {
+                let http = english_url.clone();
+                Box::pin(async move {
+                    Ok(DownloadLinks {
+                        http,
+                        ..Default::default()
+                    })
+                })
+            });
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+
+        let got = libreads
+            .download_as(
+                book_info(
+                    edition(Extension::Epub, "RUSSIANMD5"),
+                    mock_server.url("/russian.epub"),
+                    vec![edition(Extension::Epub, "ENGLISHMD5")],
+                ),
+                Extension::Epub,
+                &ConversionOptions {
+                    language: Some("english".to_string()),
+                    ..ConversionOptions::default()
+                },
+            )
+            .await
+            .expect("the second edition should be used");
+
+        russian_mock.assert();
+        english_mock.assert();
+        assert_eq!("ENGLISHMD5", got.md5);
+        assert_eq!(None, got.language_mismatch);
+        std::fs::remove_file(got.path).unwrap();
+        assert!(!std::path::Path::new("drm test RUSSIANMD5.epub").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_as_serves_edition_in_another_language_without_alternative() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let russian_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/only_russian.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/russian_ebook.epub"));
+        });
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+
+        let got = libreads
+            .download_as(
+                book_info(
+                    edition(Extension::Epub, "ONLYRUSSIANMD5"),
+                    mock_server.url("/only_russian.epub"),
+                    vec![],
+                ),
+                Extension::Epub,
+                &ConversionOptions {
+                    language: Some("english".to_string()),
+                    ..ConversionOptions::default()
+                },
+            )
+            .await
+            .expect("the only edition should be served anyway");
+
+        russian_mock.assert();
+        assert_eq!("ONLYRUSSIANMD5", got.md5);
+        let mismatch = got.language_mismatch.expect("a language mismatch");
+        assert_eq!("russian", mismatch.detected);
+        assert_eq!("english", mismatch.requested);
+        std::fs::remove_file(got.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_as_uses_next_edition_after_404() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let missing_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/missing.mobi");
+            then.status(404);
+        });
+        let mobi_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.mobi");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
+        });
+
+        let mobi_url = mock_server.url("/book.mobi");
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("SECONDMD5"))
+            .once()
+            .returning(move |_| {
+                let http = mobi_url.clone();
+                Box::pin(async move {
+                    Ok(DownloadLinks {
+                        http,
+                        ..Default::default()
+                    })
+                })
+            });
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
         };
-        let got = libreads
-            .get_book_info_from_goodreads_url("http://hello.world")
-            .await;
 
-        assert!(got.is_err());
-        println!("{:?}", got);
+        let got = libreads
+            .download_as(
+                book_info(
+                    edition(Extension::Mobi, "FIRSTMD5"),
+                    mock_server.url("/missing.mobi"),
+                    vec![
+                        edition(Extension::Mobi, "SECONDMD5"),
+                        edition(Extension::Mobi, "THIRDMD5"),
+                    ],
+                ),
+                Extension::Mobi,
+                &ConversionOptions::default(),
+            )
+            .await
+            .expect("the second edition should be used");
 
-        assert_eq!(
-            Err(Error::ApplicationError(
-                "Not enough info about the book found in this page".to_string()
-            )),
-            got
-        );
+        missing_mock.assert();
+        mobi_mock.assert();
+        assert_eq!(Some("drm test SECONDMD5.mobi"), got.path.to_str());
+        assert_eq!("SECONDMD5", got.md5);
+        assert_eq!("HTTP", got.source);
+        assert_eq!(Extension::Mobi, got.original_extension);
+        std::fs::remove_file(got.path).unwrap();
     }
 
     #[tokio::test]
-    async fn test_get_download_links_propagates_reqwest_errors() {
-        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
-        isbn_getter_mock
-            .expect_get_identification()
-            .with(eq("http://hello.world"))
+    async fn test_download_as_summarises_failed_attempts() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let missing_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/missing.mobi");
+            then.status(404);
+        });
+
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("SECONDMD5"))
             .once()
-            .returning(move |_| {
-                // Using a badly formatted URL is the best way I found of returning a reqwest::Error.
-                // TODO: change `get_isbn` to wrap the error in a custom type instead.
-                Box::pin(async { Err(reqwest::get("Bad_Url").await.unwrap_err()) })
+            .returning(|id| {
+                let id = id.to_string();
+                Box::pin(async move { Err(library_dot_lol::Error::MissingDownloadLinks(id)) })
             });
-
         let libreads = LibReads {
-            isbn_getter: Box::new(isbn_getter_mock),
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
             metadata_store: Box::new(MockMetadataStore::new()),
-            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
         };
+
         let got = libreads
-            .get_book_info_from_goodreads_url("http://hello.world")
+            .download_as(
+                book_info(
+                    edition(Extension::Mobi, "FIRSTMD5"),
+                    mock_server.url("/missing.mobi"),
+                    vec![edition(Extension::Mobi, "SECONDMD5")],
+                ),
+                Extension::Mobi,
+                &ConversionOptions::default(),
+            )
             .await;
 
-        assert_eq!(Err(Error::HttpError("builder error".to_string())), got);
+        missing_mock.assert();
+        match got {
+            Err(Error::AttemptsFailed(attempts)) => {
+                let md5s: Vec<&str> = attempts.iter().map(|(md5, _)| md5.as_str()).collect();
+                assert_eq!(vec!["FIRSTMD5", "SECONDMD5"], md5s);
+                assert!(matches!(
+                    attempts[0].1,
+                    Error::Conversion(convert::Error::Http(_))
+                ));
+            }
+            got => panic!("unexpected result {:?}", got),
+        }
     }
 
     #[tokio::test]
-    async fn test_get_download_links_nothing_found_on_libgen() {
+    async fn test_get_book_info_skips_editions_without_links() {
         let mut isbn_getter_mock = MockBookIdentificationGetter::new();
         isbn_getter_mock
             .expect_get_identification()
-            .with(eq("http://hello.world"))
+            .returning(|_| Box::pin(async { Ok(BookIdentification::default()) }));
+
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock.expect_get_metadata().returning(|_| {
+            Box::pin(async {
+                Ok(vec![
+                    edition(Extension::Mobi, "FIRSTMD5"),
+                    edition(Extension::Epub, "SECONDMD5"),
+                    edition(Extension::Azw3, "THIRDMD5"),
+                    edition(Extension::Pdf, "FOURTHMD5"),
+                ])
+            })
+        });
+
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("FIRSTMD5"))
             .once()
-            .returning(move |_| {
+            .returning(|_| {
                 Box::pin(async {
-                    Ok(BookIdentification {
-                        isbn10: None,
-                        isbn13: Some("fake_isbn_13".to_string()),
-                        title: None,
-                        author: None,
-                    })
+                    Err(library_dot_lol::Error::HttpError(HttpFailure::other("404")))
                 })
             });
-
-        let mut metadata_store_mock = MockMetadataStore::new();
-        metadata_store_mock
-            .expect_get_metadata()
-            .with(eq(BookIdentification {
-                isbn10: None,
-                isbn13: Some("fake_isbn_13".to_string()),
-                title: None,
-                author: None,
-            }))
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("SECONDMD5"))
             .once()
-            .returning(move |_| Box::pin(async { Ok(vec![]) }));
+            .returning(|_| Box::pin(async { Ok(DownloadLinks::default()) }));
 
         let libreads = LibReads {
             isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
             metadata_store: Box::new(metadata_store_mock),
-            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: 3,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
         };
         let got = libreads
             .get_book_info_from_goodreads_url("http://hello.world")
-            .await;
+            .await
+            .expect("the second edition has links");
+
+        assert_eq!("SECONDMD5", got.metadata.md5);
+        // The fourth edition is past the attempt limit.
+        assert_eq!(vec![edition(Extension::Azw3, "THIRDMD5")], got.alternatives);
+    }
+
+    // Finds FIRSTMD5, SECONDMD5 and THIRDMD5 on LibGen, in that order. Each
+    // of their links is found, or not, after the given delay.
+    fn slow_links(
+        links_prefetch: usize,
+        lookups: [(&'static str, u64, bool); 3],
+    ) -> (LibReads, Arc<std::sync::Mutex<Vec<&'static str>>>) {
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock.expect_get_metadata().returning(|_| {
+            Box::pin(async {
+                Ok(vec![
+                    edition(Extension::Mobi, "FIRSTMD5"),
+                    edition(Extension::Mobi, "SECONDMD5"),
+                    edition(Extension::Mobi, "THIRDMD5"),
+                ])
+            })
+        });
+        let started = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        for (md5, delay_ms, found) in lookups {
+            let started = started.clone();
+            download_links_store_mock
+                .expect_get_download_links()
+                .with(eq(md5))
+                .returning(move |_| {
+                    started.lock().unwrap().push(md5);
+                    Box::pin(async move {
+                        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                        match found {
+                            true => Ok(DownloadLinks::default()),
+                            false => {
+                                Err(library_dot_lol::Error::HttpError(HttpFailure::other("404")))
+                            }
+                        }
+                    })
+                });
+        }
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(identification_getter(BookIdentification::default())),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: 3,
+            links_prefetch,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        (libreads, started)
+    }
+
+    async fn links_duration(libreads: &LibReads) -> (BookInfo, Duration) {
+        let (book_info, timings) = libreads
+            .get_book_info_with_timings("http://hello.world", None, &SelectionFilters::default())
+            .await
+            .expect("an edition has links");
+        let (_, duration) = *timings
+            .stages()
+            .iter()
+            .find(|(stage, _)| *stage == "links")
+            .unwrap();
+        (book_info, duration)
+    }
+
+    #[tokio::test]
+    async fn test_get_book_info_prefetches_links() {
+        let lookups = [
+            ("FIRSTMD5", 300, false),
+            ("SECONDMD5", 400, true),
+            ("THIRDMD5", 0, true),
+        ];
+
+        // One after the other, the first edition's lookup delays the second.
+        let (libreads, started) = slow_links(1, lookups);
+        let (book_info, sequential) = links_duration(&libreads).await;
+        assert_eq!("SECONDMD5", book_info.metadata.md5);
+        assert_eq!(vec!["FIRSTMD5", "SECONDMD5"], *started.lock().unwrap());
+        assert!(sequential >= Duration::from_millis(700), "{:?}", sequential);
 
+        // Both overlap, and the third is looked up as soon as the first failed.
+        let (libreads, started) = slow_links(2, lookups);
+        let (book_info, prefetched) = links_duration(&libreads).await;
+        assert_eq!("SECONDMD5", book_info.metadata.md5);
         assert_eq!(
-            Err(Error::ApplicationError(
-                "Nothing found on LibGen for this book".to_string()
-            )),
-            got
+            vec![edition(Extension::Mobi, "THIRDMD5")],
+            book_info.alternatives
+        );
+        assert_eq!(
+            vec!["FIRSTMD5", "SECONDMD5", "THIRDMD5"],
+            *started.lock().unwrap()
         );
+        assert!(prefetched >= Duration::from_millis(400), "{:?}", prefetched);
+        assert!(prefetched < Duration::from_millis(650), "{:?}", prefetched);
     }
 
     #[tokio::test]
-    async fn test_get_download_links_found_some_links() {
+    async fn test_get_book_info_drops_slower_prefetches() {
+        let (libreads, started) = slow_links(
+            3,
+            [
+                ("FIRSTMD5", 100, true),
+                ("SECONDMD5", 5_000, true),
+                ("THIRDMD5", 0, true),
+            ],
+        );
+
+        let (book_info, duration) = links_duration(&libreads).await;
+
+        // The best edition is used as soon as its links are found, even
+        // though the third's came first.
+        assert_eq!("FIRSTMD5", book_info.metadata.md5);
+        assert!(duration < Duration::from_secs(1), "{:?}", duration);
+        assert_eq!(3, started.lock().unwrap().len());
+        // The editions looked up ahead are still alternatives.
+        assert_eq!(
+            vec![
+                edition(Extension::Mobi, "SECONDMD5"),
+                edition(Extension::Mobi, "THIRDMD5")
+            ],
+            book_info.alternatives
+        );
+    }
+
+    // Walks the whole pipeline, with every upstream replaced by a local
+    // server.
+    #[tokio::test]
+    async fn test_new_with_base_urls() {
+        use httpmock::{Method::GET, MockServer};
+
+        let goodreads = MockServer::start();
+        let goodreads_mock = goodreads.mock(|when, then| {
+            when.method(GET).path("/book/show/1048424");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_isbn13_only_page.html"
+            ));
+        });
+        let libgen = MockServer::start();
+        let libgen_mock = libgen.mock(|when, then| {
+            when.method(GET)
+                .path("/json.php")
+                .query_param("isbn", "9780521405997");
+            then.status(200).body(
+                r#"[{"title":"Base URLs","author":"Elinor Ostrom","year":"1990","extension":"epub","md5":"AB13556B96D473C8DFAD7165C4704526"}]"#,
+            );
+        });
+        let mirror = MockServer::start();
+        let book_url = mirror.url("/book.epub");
+        let page_mock = mirror.mock(|when, then| {
+            when.method(GET)
+                .path("/main/AB13556B96D473C8DFAD7165C4704526");
+            then.status(200).body(format!(
+                r#"<div id="download"><h2><a href="{}">GET</a></h2></div>"#,
+                book_url
+            ));
+        });
+        let book_mock = mirror.mock(|when, then| {
+            when.method(GET).path("/book.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+        });
+
+        let libreads = LibReads::new(&Config {
+            download_sources: vec![DownloadSource::LibraryDotLol],
+            goodreads_url: Some(goodreads.base_url()),
+            libgen_url: Some(libgen.url("/json.php")),
+            library_lol_url: Some(mirror.url("/main")),
+            ..Config::default()
+        });
+        let book_info = libreads
+            .get_book_info_from_goodreads_url("https://www.goodreads.com/book/show/1048424")
+            .await
+            .expect("the book should be found on the local servers");
+        assert_eq!(book_url, book_info.download_links.best_link());
+
+        let got = libreads
+            .download_as(book_info, Extension::Epub, &ConversionOptions::default())
+            .await
+            .expect("the book should be downloaded from the local mirror");
+
+        goodreads_mock.assert();
+        libgen_mock.assert();
+        page_mock.assert();
+        book_mock.assert();
+        assert!(!got.converted);
+        assert_eq!(
+            include_bytes!("../tests/testdata/dummy_ebook.epub").as_slice(),
+            std::fs::read(&got.path).unwrap()
+        );
+        std::fs::remove_file(got.path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "base URLs should have been validated")]
+    fn test_new_with_invalid_base_url() {
+        LibReads::new(&Config {
+            libgen_url: Some("libgen.rs/json.php".to_string()),
+            ..Config::default()
+        });
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let book_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.epub");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.epub"));
+        });
+
         let mut isbn_getter_mock = MockBookIdentificationGetter::new();
         isbn_getter_mock
             .expect_get_identification()
             .with(eq("http://hello.world"))
             .once()
-            .returning(move |_| {
+            .returning(|_| {
                 Box::pin(async {
                     Ok(BookIdentification {
-                        isbn10: Some("fake_isbn_10".to_string()),
-                        isbn13: None,
-                        title: None,
-                        author: None,
+                        isbn13: Some("9780451524935".to_string()),
+                        ..Default::default()
                     })
                 })
             });
@@ -286,123 +3477,376 @@ mod tests {
         let mut metadata_store_mock = MockMetadataStore::new();
         metadata_store_mock
             .expect_get_metadata()
-            .with(eq(BookIdentification {
-                isbn10: Some("fake_isbn_10".to_string()),
-                isbn13: None,
-                title: None,
-                author: None,
-            }))
             .once()
-            .returning(move |_| {
+            .returning(|_| {
                 Box::pin(async {
-                    Ok(vec![LibgenMetadata {
-                        title: "hello".to_string(),
-                        author: "hello".to_string(),
-                        year: "hello".to_string(),
-                        extension: Extension::Mobi,
-                        md5: "MYBOOKMD5".to_string(),
-                    }])
+                    Ok(vec![
+                        edition(Extension::Mobi, "FIRSTMD5"),
+                        edition(Extension::Other(String::new()), "NOEXTMD5"),
+                        edition(Extension::Epub, "SECONDMD5"),
+                        edition(Extension::Azw3, "THIRDMD5"),
+                        edition(Extension::Pdf, "FOURTHMD5"),
+                    ])
                 })
             });
 
+        let epub_url = mock_server.url("/book.epub");
         let mut download_links_store_mock = MockDownloadLinksStore::new();
         download_links_store_mock
             .expect_get_download_links()
-            .with(eq("MYBOOKMD5"))
+            .with(eq("FIRSTMD5"))
             .once()
             .returning(|_| {
                 Box::pin(async {
+                    Err(library_dot_lol::Error::HttpError(HttpFailure::other("404")))
+                })
+            });
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq("SECONDMD5"))
+            .once()
+            .returning(move |_| {
+                let http = epub_url.clone();
+                Box::pin(async move {
                     Ok(DownloadLinks {
-                        cloudflare: "fake_cloudflare_link".to_string(),
-                        ipfs_dot_io: "fake_ipfs_dot_io_link".to_string(),
-                        infura: "fake_infura_link".to_string(),
-                        pinata: "fake_pinata_link".to_string(),
-                        http: "fake_http_link".to_string(),
+                        http,
+                        ..Default::default()
                     })
                 })
             });
 
         let libreads = LibReads {
             isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
             metadata_store: Box::new(metadata_store_mock),
             download_links_store: Box::new(download_links_store_mock),
+            max_attempts: 3,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
         };
         let got = libreads
-            .get_book_info_from_goodreads_url("http://hello.world")
-            .await;
+            .dry_run("http://hello.world", None, &SelectionFilters::default())
+            .await
+            .expect("the second edition has links");
 
+        let candidate = |extension, md5, rank, eliminated| CandidateReport {
+            metadata: edition(extension, md5),
+            rank,
+            eliminated,
+        };
         assert_eq!(
-            Ok(BookInfo {
-                metadata: LibgenMetadata {
-                    title: "hello".to_string(),
-                    author: "hello".to_string(),
-                    year: "hello".to_string(),
-                    extension: Extension::Mobi,
-                    md5: "MYBOOKMD5".to_string(),
+            SelectionReport {
+                identification: BookIdentification {
+                    isbn13: Some("9780451524935".to_string()),
+                    ..Default::default()
                 },
-                download_links: DownloadLinks {
-                    cloudflare: "fake_cloudflare_link".to_string(),
-                    ipfs_dot_io: "fake_ipfs_dot_io_link".to_string(),
-                    infura: "fake_infura_link".to_string(),
-                    pinata: "fake_pinata_link".to_string(),
-                    http: "fake_http_link".to_string(),
-                }
-            }),
+                found_by_title: false,
+                candidates: vec![
+                    candidate(
+                        Extension::Mobi,
+                        "FIRSTMD5",
+                        Some(1),
+                        Some(Elimination::NoDownloadLinks)
+                    ),
+                    candidate(Extension::Epub, "SECONDMD5", Some(2), None),
+                    candidate(Extension::Azw3, "THIRDMD5", Some(3), None),
+                    candidate(
+                        Extension::Pdf,
+                        "FOURTHMD5",
+                        Some(4),
+                        Some(Elimination::MaxAttempts)
+                    ),
+                    candidate(
+                        Extension::Other(String::new()),
+                        "NOEXTMD5",
+                        None,
+                        Some(Elimination::UnknownExtension)
+                    ),
+                ],
+                total: 5,
+                truncated: 0,
+                chosen: Some(edition(Extension::Epub, "SECONDMD5")),
+                download_links: Some(DownloadLinks {
+                    http: mock_server.url("/book.epub"),
+                    ..Default::default()
+                }),
+            },
             got
         );
+        // Nothing was downloaded.
+        book_mock.assert_hits(0);
     }
 
     #[tokio::test]
-    async fn test_get_download_links_failed_to_get_library_dot_lol_links() {
-        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
-        isbn_getter_mock
-            .expect_get_identification()
-            .with(eq("http://hello.world"))
-            .once()
+    async fn test_keeps_the_best_editions() {
+        // An omnibus ISBN: hundreds of editions, some of them duplicates.
+        let editions: Vec<_> = (0..300)
+            .map(|i| LibgenMetadata {
+                year: format!("{}", 1990 + i % 30),
+                filesize: Some(1000 + i as u64 * 7 % 13),
+                ..edition(
+                    [
+                        Extension::Epub,
+                        Extension::Mobi,
+                        Extension::Pdf,
+                        Extension::Azw3,
+                    ][i % 4]
+                        .clone(),
+                    &format!("{:032X}", i - i % 10 / 9),
+                )
+            })
+            .collect();
+        let (want, _) = libgen::rank(
+            &editions,
+            &RankingContext::new(None, &SelectionFilters::default()),
+            true,
+            &DefaultRanker::default(),
+        );
+
+        let mut metadata_store_mock = MockMetadataStore::new();
+        let found = editions.clone();
+        metadata_store_mock
+            .expect_get_metadata()
+            .times(2)
             .returning(move |_| {
-                Box::pin(async {
-                    Ok(BookIdentification {
-                        isbn10: Some("fake_isbn_10".to_string()),
-                        isbn13: None,
-                        title: None,
-                        author: None,
+                let found = found.clone();
+                Box::pin(async move { Ok(found) })
+            });
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .returning(|md5| {
+                let http = format!("https://example.com/{}", md5);
+                Box::pin(async move {
+                    Ok(DownloadLinks {
+                        http,
+                        ..Default::default()
                     })
                 })
             });
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock
+            .expect_get_identification()
+            .times(2)
+            .returning(|_| Box::pin(async { Ok(BookIdentification::default()) }));
+        let libreads = LibReads {
+            isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: 20,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: 10,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+        let md5s = |editions: &[LibgenMetadata]| -> Vec<String> {
+            editions.iter().map(|edition| edition.md5.clone()).collect()
+        };
+
+        let report = libreads
+            .dry_run("http://hello.world", None, &SelectionFilters::default())
+            .await
+            .unwrap();
+        let ranked: Vec<_> = report
+            .candidates
+            .iter()
+            .filter(|candidate| candidate.rank.is_some())
+            .map(|candidate| candidate.metadata.clone())
+            .collect();
+        assert_eq!(md5s(&want[..10]), md5s(&ranked));
+        assert_eq!(want.len() - 10, report.truncated);
+        assert_eq!(report.candidates.len(), report.total);
+        assert_eq!(
+            editions.len() - want.len(),
+            report.total - 10,
+            "the duplicates are still reported"
+        );
+
+        // A page keeps the order, and the total.
+        let page = report.clone().paginate(2, 3);
+        assert_eq!(
+            vec![Some(3), Some(4), Some(5)],
+            page.candidates
+                .iter()
+                .map(|candidate| candidate.rank)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(report.total, page.total);
+        assert!(report.clone().paginate(1000, 3).candidates.is_empty());
+
+        // The alternatives of the selected edition are the next best, up to
+        // `max_editions` even with more attempts.
+        let (book_info, _) = libreads
+            .get_book_info_with_timings("http://hello.world", None, &SelectionFilters::default())
+            .await
+            .unwrap();
+        assert_eq!(want[0].md5, book_info.metadata.md5);
+        assert_eq!(md5s(&want[1..10]), md5s(&book_info.alternatives));
+    }
+
+    #[tokio::test]
+    async fn test_download_as_serves_library_books() {
+        use httpmock::{Method::GET, MockServer};
+
+        let mock_server = MockServer::start();
+        let mobi_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book.mobi");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
+        });
 
+        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
+        isbn_getter_mock.expect_get_identification().returning(|_| {
+            Box::pin(async {
+                Ok(BookIdentification {
+                    isbn13: Some("9780451524935".to_string()),
+                    ..Default::default()
+                })
+            })
+        });
+
+        // Only the first request goes to LibGen.
         let mut metadata_store_mock = MockMetadataStore::new();
         metadata_store_mock
             .expect_get_metadata()
-            .with(eq(BookIdentification {
-                isbn10: Some("fake_isbn_10".to_string()),
-                isbn13: None,
-                title: None,
-                author: None,
-            }))
+            .once()
+            .returning(|_| Box::pin(async { Ok(vec![edition(Extension::Mobi, "LIBRARYMD5")]) }));
+        let mobi_url = mock_server.url("/book.mobi");
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
             .once()
             .returning(move |_| {
-                Box::pin(async {
-                    Ok(vec![LibgenMetadata {
-                        title: "hello".to_string(),
-                        author: "hello".to_string(),
-                        year: "hello".to_string(),
-                        extension: Extension::Mobi,
-                        md5: "MYBOOKMD5".to_string(),
-                    }])
+                let http = mobi_url.clone();
+                Box::pin(async move {
+                    Ok(DownloadLinks {
+                        http,
+                        ..Default::default()
+                    })
                 })
             });
 
+        let dir = std::env::temp_dir().join("libreads_library_download_as");
+        let _ = std::fs::remove_dir_all(&dir);
+        let library = Library::new(Arc::new(crate::file_store::LocalFileStore::new(&dir)));
         let libreads = LibReads {
             isbn_getter: Box::new(isbn_getter_mock),
-            metadata_store: Box::new(metadata_store_mock),
-            download_links_store: Box::new(LibraryDotLol {
-                base_url: "bad url".to_string(),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(FailoverMetadataStore {
+                stores: vec![Box::new(library.clone()), Box::new(metadata_store_mock)],
             }),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: Some(library.clone()),
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
         };
-        let got = libreads
-            .get_book_info_from_goodreads_url("http://hello.world")
-            .await;
 
-        assert_eq!(Err(Error::HttpError("builder error".to_string())), got)
+        for _ in 0..2 {
+            let book_info = libreads
+                .get_book_info_from_goodreads_url("http://hello.world")
+                .await
+                .expect("the book should be found");
+            let got = libreads
+                .download_as(book_info, Extension::Mobi, &ConversionOptions::default())
+                .await
+                .expect("the book should be downloaded");
+            assert_eq!(
+                include_bytes!("../tests/testdata/dummy_ebook.mobi").as_slice(),
+                std::fs::read(&got.path).unwrap()
+            );
+            std::fs::remove_file(got.path).unwrap();
+        }
+
+        mobi_mock.assert_hits(1);
+        let entry = library
+            .get("LIBRARYMD5")
+            .await
+            .expect("the book should be stored");
+        assert_eq!(Some("9780451524935".to_string()), entry.isbn13);
+        assert_eq!(Ok(vec![entry]), libreads.library_books().await);
+        assert_eq!(Ok(()), libreads.remove_from_library("LIBRARYMD5").await);
+        assert!(matches!(
+            libreads.remove_from_library("LIBRARYMD5").await,
+            Err(Error::NotInLibrary(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_to_kindle() {
+        let path = std::env::temp_dir().join("libreads_send_to_kindle.azw3");
+        std::fs::copy("tests/testdata/dummy_ebook.mobi", &path).unwrap();
+        let result = ConversionResult {
+            path: path.clone(),
+            extension: Extension::Azw3,
+            bytes: std::fs::metadata(&path).unwrap().len(),
+            converted: true,
+            md5: "MYBOOKMD5".to_string(),
+            source: "dweb.link".to_string(),
+            original_extension: Extension::Epub,
+            download_duration: Duration::from_millis(10),
+            conversion_duration: Some(Duration::from_millis(10)),
+            language_mismatch: None,
+        };
+        let mut mailer = kindle::MockMailer::new();
+        mailer
+            .expect_send()
+            .withf(|email| {
+                email.attachment_name == "libreads_send_to_kindle.azw3"
+                    && email.attachment_content_type == "application/vnd.amazon.ebook"
+            })
+            .once()
+            .returning(|_| Box::pin(async { Ok(()) }));
+        let mut libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(MockMetadataStore::new()),
+            download_links_store: Box::new(MockDownloadLinksStore::new()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: ConnectionLimits::default(),
+        };
+
+        assert!(matches!(
+            libreads.send_to_kindle(&result).await,
+            Err(Error::InvalidInput(_))
+        ));
+
+        libreads.kindle = Some(Kindle {
+            mailer: Box::new(mailer),
+            from: "libreads@example.com".to_string(),
+            to: "me_123@kindle.com".to_string(),
+            max_attachment_size: kindle::MAX_ATTACHMENT_SIZE,
+        });
+        assert_eq!(
+            Ok(Delivery {
+                to: "me_123@kindle.com".to_string(),
+                filename: "libreads_send_to_kindle.azw3".to_string(),
+                bytes: result.bytes,
+            }),
+            libreads.send_to_kindle(&result).await
+        );
+
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,212 @@
+//! Module links_cache keeps the download links found for a book on disk, so
+//! they don't have to be scraped again from the mirrors on every request.
+//!
+//! Each book is cached in its own JSON file, named after its MD5, in a
+//! `file_store::FileStore`. Entries
+//! expire after a while, because gateway URLs do change from time to time.
+
+use crate::{
+    clock::{unix_seconds, SharedClock},
+    file_store::{self, FileStore},
+    library_dot_lol::{DownloadLinks, DownloadLinksStore, Error},
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+
+// Where the cache is kept, in `Config::cache_dir`.
+pub const CACHE_SUBDIR: &str = "download_links";
+
+// Caches the links found by `store` in `files`.
+pub struct CachedDownloadLinksStore {
+    pub store: Box<dyn DownloadLinksStore + Send + Sync + 'static>,
+    pub files: Arc<dyn FileStore>,
+    pub ttl: Duration,
+    // Tells the age of entries.
+    pub clock: SharedClock,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    // Seconds since the Unix epoch.
+    fetched_at: u64,
+    links: DownloadLinks,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, ttl: Duration, clock: &SharedClock) -> bool {
+        let age = unix_seconds(&**clock).saturating_sub(self.fetched_at);
+        age >= ttl.as_secs()
+    }
+}
+
+// Whether a cache file is expired, or can't be read, so it can be deleted.
+pub(crate) fn is_expired(data: &[u8], ttl: Duration, clock: &SharedClock) -> bool {
+    serde_json::from_slice::<CacheEntry>(data).map_or(true, |entry| entry.is_expired(ttl, clock))
+}
+
+impl CachedDownloadLinksStore {
+    // Only LibGen MD5s are cached: other IDs, such as the file paths of a
+    // local library, can't be used as file names.
+    fn entry_key(&self, id: &str) -> Option<String> {
+        let is_md5 = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric());
+        is_md5.then(|| format!("{}.json", id.to_uppercase()))
+    }
+
+    async fn read(&self, key: &str) -> Option<DownloadLinks> {
+        let body = self.files.get(key).await.ok()?;
+        let data = file_store::read_to_vec(body).await.ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&data).ok()?;
+        (!entry.is_expired(self.ttl, &self.clock)).then_some(entry.links)
+    }
+
+    async fn write(&self, key: &str, links: &DownloadLinks) -> Result<(), file_store::Error> {
+        let entry = CacheEntry {
+            fetched_at: unix_seconds(&*self.clock),
+            links: links.clone(),
+        };
+        let data = serde_json::to_vec(&entry).map_err(std::io::Error::from)?;
+        self.files.put(key, file_store::bytes_stream(data)).await
+    }
+}
+
+#[async_trait]
+impl DownloadLinksStore for CachedDownloadLinksStore {
+    async fn get_download_links(&self, id: &str) -> Result<DownloadLinks, Error> {
+        let Some(key) = self.entry_key(id) else {
+            return self.store.get_download_links(id).await;
+        };
+        if let Some(links) = self.read(&key).await {
+            return Ok(links);
+        }
+
+        let links = self.store.get_download_links(id).await?;
+        if !links.best_link().is_empty() {
+            if let Err(err) = self.write(&key, &links).await {
+                tracing::warn!("Could not cache the download links of {}: {:?}", id, err);
+            }
+        }
+        Ok(links)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, file_store::LocalFileStore, library_dot_lol::LibraryDotLol};
+    use httpmock::{Method::GET, MockServer};
+    use std::{
+        path::{Path, PathBuf},
+        time::UNIX_EPOCH,
+    };
+
+    const MD5: &str = "AB13556B96D473C8DFAD7165C4704526";
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libreads_links_cache_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn cached_store(base_url: String, dir: &Path, ttl: Duration) -> CachedDownloadLinksStore {
+        CachedDownloadLinksStore {
+            store: Box::new(LibraryDotLol {
+                base_url,
+                ..Default::default()
+            }),
+            files: Arc::new(LocalFileStore::new(dir)),
+            ttl,
+            clock: SharedClock::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_second_lookup_is_cached() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path(format!("/{}", MD5));
+            then.status(200)
+                .body(include_str!("../tests/testdata/library.lol_book_page.html"));
+        });
+        let dir = cache_dir("second_lookup");
+        let store = cached_store(mock_server.base_url(), &dir, DEFAULT_TTL);
+
+        let first = store.get_download_links(MD5).await.unwrap();
+        let second = store.get_download_links(MD5).await.unwrap();
+
+        endpoint_mock.assert_hits(1);
+        assert_eq!(first, second);
+        assert_eq!("http://12.34.45.67/main/316000/example.pdf", second.http);
+
+        // The cache outlives the store.
+        let store = cached_store(mock_server.base_url(), &dir, DEFAULT_TTL);
+        assert_eq!(Ok(first), store.get_download_links(MD5).await);
+        endpoint_mock.assert_hits(1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_entry_is_refreshed() {
+        let mock_server = MockServer::start();
+        let endpoint_mock = mock_server.mock(|when, then| {
+            when.method(GET).path(format!("/{}", MD5));
+            then.status(200)
+                .body(include_str!("../tests/testdata/library.lol_book_page.html"));
+        });
+        let dir = cache_dir("expired_entry");
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000)));
+        let store = CachedDownloadLinksStore {
+            clock: SharedClock(clock.clone()),
+            ..cached_store(mock_server.base_url(), &dir, DEFAULT_TTL)
+        };
+        store.get_download_links(MD5).await.unwrap();
+
+        // Still fresh right before the TTL.
+        clock.advance(DEFAULT_TTL - Duration::from_secs(1));
+        store.get_download_links(MD5).await.unwrap();
+        endpoint_mock.assert_hits(1);
+
+        clock.advance(Duration::from_secs(1));
+        let got = store.get_download_links(MD5).await.unwrap();
+        endpoint_mock.assert_hits(2);
+        assert_eq!("http://12.34.45.67/main/316000/example.pdf", got.http);
+
+        // The refreshed entry is used from now on.
+        clock.advance(DEFAULT_TTL - Duration::from_secs(1));
+        store.get_download_links(MD5).await.unwrap();
+        endpoint_mock.assert_hits(2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_files_are_not_cached() {
+        let mut mock_store = crate::library_dot_lol::MockDownloadLinksStore::new();
+        mock_store
+            .expect_get_download_links()
+            .times(2)
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(DownloadLinks {
+                        http: "file:///books/1984.epub".to_string(),
+                        ..Default::default()
+                    })
+                })
+            });
+        let dir = cache_dir("local_files");
+        let store = CachedDownloadLinksStore {
+            store: Box::new(mock_store),
+            files: Arc::new(LocalFileStore::new(&dir)),
+            ttl: DEFAULT_TTL,
+            clock: SharedClock::default(),
+        };
+
+        for _ in 0..2 {
+            store.get_download_links("/books/1984.epub").await.unwrap();
+        }
+        assert!(!dir.exists());
+    }
+}
@@ -1,21 +1,317 @@
-use actix_files::Files;
-use actix_web::{
-    web::{get, Data},
-    App, HttpServer,
+use actix_web::web::Data;
+use indicatif::{ProgressBar, ProgressStyle};
+use libreads::{
+    book_query::BookQuery,
+    capabilities::ConverterCapabilities,
+    config::{self, Config, TlsConfig},
+    convert::{ConversionOptions, DownloadProgress, ProgressCallback},
+    extension::Extension,
+    frontend, import,
+    libreads::{BookService, LibReads},
+    maintenance::Maintenance,
+    prober::UpstreamProber,
+    queue::{Queue, Worker},
+    selection::SelectionFilters,
+    server,
+    web::LibReadsState,
 };
-use libreads::{libreads::LibReads, web::download};
+use std::sync::Arc;
+
+const LISTEN_FLAG: &str = "--listen";
+const TLS_SELF_SIGNED_FLAG: &str = "--tls-self-signed";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let libreads = Data::new(LibReads::default());
-
-    HttpServer::new(move || {
-        App::new()
-            .service(Files::new("/", "./frontend/build").index_file("index.html"))
-            .route("/download/{goodreads_url}", get().to(download))
-            .app_data(libreads.clone())
-    })
-    .bind(("127.0.0.1", 8001))?
-    .run()
-    .await
+    // `libreads import-goodreads-csv <file>` prints the books of a Goodreads
+    // library export as JSON, and `libreads --dry-run <goodreads_url>` how an
+    // edition of the book would be selected, instead of starting the server.
+    // `libreads --send-to-kindle <book>` emails the book to the configured
+    // Kindle.
+    // `libreads --download <book>` downloads the book as it is on LibGen,
+    // with a progress bar.
+    // `libreads --info <book>` prints what was found for the book as JSON.
+    // Books are a Goodreads URL, or a query such as `isbn:9780553418026`,
+    // see `book_query::BookQuery`.
+    // `libreads --listen <addresses>` overrides `LIBREADS_BIND`.
+    // `libreads --tls-self-signed` serves HTTPS with a certificate generated
+    // at startup, unless `LIBREADS_TLS_CERT` is set, see `tls`.
+    // `--exclude`, `--max-size-mb`, `--lang` and `--min-year` filter the
+    // editions of `--dry-run`, `--send-to-kindle` and `--download`, see
+    // `selection::SelectionFilters`.
+    // Requests, and the steps taken to answer them, are logged to stdout,
+    // see `request_log`.
+    tracing_subscriber::fmt::init();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let listen = take_flag(&mut args, LISTEN_FLAG);
+    let tls_self_signed = take_switch(&mut args, TLS_SELF_SIGNED_FLAG);
+    let filters = SelectionFilters::parse(
+        take_flag(&mut args, "--exclude").as_deref(),
+        take_flag(&mut args, "--max-size-mb").as_deref(),
+        take_flag(&mut args, "--lang").as_deref(),
+        take_flag(&mut args, "--min-year").as_deref(),
+    )
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string()))?;
+    let command = match args.as_slice() {
+        [command, arg] => Some((command.as_str(), arg.as_str())),
+        _ => None,
+    };
+    if let Some(("import-goodreads-csv", path)) = command {
+        return print_goodreads_csv(path);
+    }
+
+    let invalid_input =
+        |err: config::Error| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string());
+    let mut config = Config::from_env().map_err(invalid_input)?;
+    if let Some(listen) = listen {
+        config.listen =
+            config::parse_listen_addresses(LISTEN_FLAG, &listen).map_err(invalid_input)?;
+    }
+    if tls_self_signed {
+        if config.tls.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} can't be used with a certificate, unset LIBREADS_TLS_CERT and LIBREADS_TLS_KEY",
+                    TLS_SELF_SIGNED_FLAG
+                ),
+            ));
+        }
+        config.tls = Some(TlsConfig::SelfSigned);
+    }
+    if let Some(("--dry-run", goodreads_url)) = command {
+        return print_dry_run(&LibReads::new(&config), goodreads_url, &filters).await;
+    }
+    if let Some(("--send-to-kindle", book)) = command {
+        return send_to_kindle(&config, &parse_book_query(book)?, &filters).await;
+    }
+    if let Some(("--download", book)) = command {
+        return download(&config, &parse_book_query(book)?, &filters).await;
+    }
+    if let Some(("--info", book)) = command {
+        return print_info(&LibReads::new(&config), &parse_book_query(book)?, &filters).await;
+    }
+    let libreads = LibReads::new(&config);
+    let breakers = libreads.circuit_breakers().clone();
+    // Reports whether the upstreams are reachable, when enabled.
+    let prober = config.probe_interval.map(|interval| {
+        let prober = UpstreamProber::new(&config, &libreads);
+        prober.clone().spawn(interval);
+        prober
+    });
+    let libreads: Data<dyn BookService> = Data::from(Arc::new(libreads) as Arc<_>);
+    frontend::warn_if_missing(&config.frontend_dir);
+    // Also warms ebook-convert up, so the first conversion isn't slower.
+    let capabilities = match config.conversion_enabled {
+        true => {
+            let options = ConversionOptions::default();
+            ConverterCapabilities::probe(&options.executable, &options.djvu_executable).await
+        }
+        false => ConverterCapabilities::disabled(),
+    };
+    tracing::info!("Converter: {}", capabilities);
+
+    // Cleans up what a previous run left behind, then periodically.
+    let maintenance = Maintenance::new(&config);
+    maintenance.clone().spawn(config.cleanup_interval);
+
+    // Downloads the books queued before a restart too, see `queue`.
+    let queue_options = ConversionOptions {
+        timeout: config.conversion_timeout,
+        max_download_size: Some(config.max_download_size),
+        proxy: config.proxy.clone(),
+        name_template: config.name_template.clone(),
+        capabilities: Some(capabilities.clone()),
+        work_dir: config.work_dir.clone(),
+        ..ConversionOptions::default()
+    };
+    let queue = match &config.queue_file {
+        Some(path) => Some((Arc::new(Queue::open(path)?), config.queue_concurrency)),
+        None => None,
+    };
+
+    let mut state = LibReadsState::new(config, libreads.clone())
+        .with_capabilities(capabilities)
+        .with_breakers(breakers)
+        .with_maintenance(maintenance);
+    if let Some(prober) = prober {
+        state = state.with_prober(prober);
+    }
+    if let Some((queue, concurrency)) = queue {
+        Worker::new(
+            queue.clone(),
+            libreads.into_inner(),
+            state.history(),
+            queue_options,
+        )
+        .spawn(concurrency);
+        state = state.with_queue(queue);
+    }
+    let (server, urls) = server::start(state)?;
+    for url in urls {
+        tracing::info!("Listening on {}", url);
+    }
+    server.await
+}
+
+// Removes `flag` and its value from `args`, and returns the value. The flag
+// can also be given as `flag=value`.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let position = args
+        .iter()
+        .position(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)))?;
+    let arg = args.remove(position);
+    match arg.split_once('=') {
+        Some((_, value)) => Some(value.to_string()),
+        None if position < args.len() => Some(args.remove(position)),
+        None => Some(String::new()),
+    }
+}
+
+// Removes `flag`, which takes no value, from `args`, and tells whether it was
+// there.
+fn take_switch(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+fn print_goodreads_csv(path: &str) -> std::io::Result<()> {
+    let file = std::fs::File::open(path)?;
+    let imported = import::parse_goodreads_csv(file)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    println!("{}", serde_json::to_string_pretty(&imported)?);
+    Ok(())
+}
+
+fn parse_book_query(book: &str) -> std::io::Result<BookQuery> {
+    book.parse()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+}
+
+async fn print_info(
+    libreads: &LibReads,
+    query: &BookQuery,
+    filters: &SelectionFilters,
+) -> std::io::Result<()> {
+    let (book_info, _) = libreads
+        .get_book_info_by_query_with_timings(query, None, filters)
+        .await
+        .map_err(|err| std::io::Error::other(format!("{:?}", err)))?;
+    println!("{}", serde_json::to_string_pretty(&book_info)?);
+    Ok(())
+}
+
+async fn print_dry_run(
+    libreads: &LibReads,
+    goodreads_url: &str,
+    filters: &SelectionFilters,
+) -> std::io::Result<()> {
+    let report = libreads
+        .dry_run(goodreads_url, None, filters)
+        .await
+        .map_err(|err| std::io::Error::other(format!("{:?}", err)))?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+// Send to Kindle converts EPUB books itself, and no longer accepts MOBI
+// attachments.
+async fn send_to_kindle(
+    config: &Config,
+    query: &BookQuery,
+    filters: &SelectionFilters,
+) -> std::io::Result<()> {
+    let libreads = LibReads::new(config);
+    let failed = |err| std::io::Error::other(format!("{:?}", err));
+    let options = ConversionOptions {
+        timeout: config.conversion_timeout,
+        max_download_size: Some(config.max_download_size),
+        proxy: config.proxy.clone(),
+        name_template: config.name_template.clone(),
+        capabilities: (!config.conversion_enabled).then(ConverterCapabilities::disabled),
+        language: filters.language.clone().filter(|_| config.language_check),
+        ..ConversionOptions::default()
+    };
+
+    let (book_info, _) = libreads
+        .get_book_info_by_query_with_timings(query, Some(&Extension::Epub), filters)
+        .await
+        .map_err(failed)?;
+    let result = libreads
+        .download_as(book_info, Extension::Epub, &options)
+        .await
+        .map_err(failed)?;
+    let delivery = libreads.send_to_kindle(&result).await;
+    let _ = std::fs::remove_file(&result.path);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&delivery.map_err(failed)?)?
+    );
+    Ok(())
+}
+
+// Downloads the book to the current directory. A download cut short is
+// resumed from its `.part` file when run again. Ctrl-C deletes it instead.
+async fn download(
+    config: &Config,
+    query: &BookQuery,
+    filters: &SelectionFilters,
+) -> std::io::Result<()> {
+    let libreads = LibReads::new(config);
+    let failed = |err| std::io::Error::other(format!("{:?}", err));
+    let progress_bar = ProgressBar::no_length().with_style(
+        ProgressStyle::with_template(
+            "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+        )
+        .map_err(std::io::Error::other)?
+        .progress_chars("=> "),
+    );
+    let progress = {
+        let progress_bar = progress_bar.clone();
+        ProgressCallback::new(move |progress: &DownloadProgress| {
+            if let Some(total) = progress.total {
+                progress_bar.set_length(total);
+            }
+            progress_bar.set_position(progress.downloaded);
+            progress_bar.set_message(progress.source.clone());
+        })
+    };
+    let options = ConversionOptions {
+        max_download_size: Some(config.max_download_size),
+        proxy: config.proxy.clone(),
+        name_template: config.name_template.clone(),
+        resume: true,
+        progress: Some(progress),
+        language: filters.language.clone().filter(|_| config.language_check),
+        ..ConversionOptions::default()
+    };
+
+    let (book_info, _) = libreads
+        .get_book_info_by_query_with_timings(query, None, filters)
+        .await
+        .map_err(failed)?;
+    let extension = book_info.metadata.extension.clone();
+    // Dropping the download deletes the partial file.
+    let result = tokio::select! {
+        result = libreads.download_as(book_info, extension, &options) => result,
+        _ = tokio::signal::ctrl_c() => {
+            progress_bar.abandon_with_message("Cancelled");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "download cancelled",
+            ));
+        }
+    };
+    progress_bar.finish_and_clear();
+    let result = result.map_err(failed)?;
+    println!(
+        "Ebook downloaded as {} ({} bytes, from {})",
+        result.path.display(),
+        result.bytes,
+        result.source
+    );
+    if let Some(mismatch) = &result.language_mismatch {
+        println!("Warning: {}", mismatch);
+    }
+    Ok(())
 }
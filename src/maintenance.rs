@@ -0,0 +1,383 @@
+//! Module maintenance deletes what LibReads leaves behind: books that were
+//! downloaded or converted but never served, e.g. when the server stopped
+//! midway, and the download links cache entries that expired.
+//!
+//! It runs when the server starts, then every `Config::cleanup_interval`,
+//! and on demand through `POST /api/admin/cleanup`.
+//!
+//! Books are downloaded and converted in `Config::work_dir`, a directory of
+//! their own: the directory LibReads is run from is never swept. Only the
+//! files LibReads writes there are deleted: books, in a format it knows,
+//! their covers and partial downloads, once older than
+//! `Config::temp_file_max_age`.
+
+use crate::{
+    clock::SharedClock,
+    config::Config,
+    extension::Extension,
+    file_store::{self, FileStore},
+    links_cache,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use utoipa::ToSchema;
+
+pub const DEFAULT_TEMP_FILE_MAX_AGE: Duration = Duration::from_secs(6 * 60 * 60);
+pub const DEFAULT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// `libreads` in the system's temporary directory, e.g. `/tmp/libreads`.
+pub fn default_work_dir() -> PathBuf {
+    std::env::temp_dir().join("libreads")
+}
+
+// What to clean up. Without a scope, the temporary files and the expired
+// cache entries are deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // Only the temporary files.
+    Temp,
+    // Only the expired cache entries.
+    Cache,
+    // The temporary files, and every cache entry, expired or not.
+    All,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct Removed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Removed {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct CleanupReport {
+    pub temp_files: Removed,
+    pub cache_entries: Removed,
+}
+
+#[derive(Clone, Debug)]
+pub struct Maintenance {
+    // Where books are downloaded and converted.
+    work_dir: PathBuf,
+    temp_file_max_age: Duration,
+    links_cache: Option<Arc<dyn FileStore>>,
+    links_cache_ttl: Duration,
+    clock: SharedClock,
+}
+
+impl Maintenance {
+    pub fn new(config: &Config) -> Self {
+        let links_cache = config
+            .cache_dir
+            .as_ref()
+            .and_then(|dir| file_store::open(&dir.join(links_cache::CACHE_SUBDIR)).ok());
+        Self {
+            work_dir: config.work_dir.clone(),
+            temp_file_max_age: config.temp_file_max_age,
+            links_cache,
+            links_cache_ttl: config.links_cache_ttl,
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub async fn run(&self, scope: Option<Scope>) -> io::Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+        if scope != Some(Scope::Cache) {
+            report.temp_files = self.remove_temp_files().await?;
+        }
+        if scope != Some(Scope::Temp) {
+            let all = scope == Some(Scope::All);
+            report.cache_entries = self.remove_cache_entries(all).await?;
+        }
+        Ok(report)
+    }
+
+    // Cleans up now, then every `interval`, until the runtime stops.
+    pub fn spawn(self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                match self.run(None).await {
+                    Ok(report) if report != CleanupReport::default() => {
+                        tracing::info!("Cleaned up: {:?}", report)
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("Could not clean up: {}", err),
+                }
+            }
+        })
+    }
+
+    async fn remove_temp_files(&self) -> io::Result<Removed> {
+        let mut removed = Removed::default();
+        let mut entries = match tokio::fs::read_dir(&self.work_dir).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(removed),
+            entries => entries?,
+        };
+        let now = self.clock.now();
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            let age = metadata
+                .modified()
+                .map(|modified| now.duration_since(modified).unwrap_or_default())
+                .unwrap_or_default();
+            if !metadata.is_file() || !is_temp_file(&entry.path()) || age < self.temp_file_max_age {
+                continue;
+            }
+            if tokio::fs::remove_file(entry.path()).await.is_ok() {
+                removed.add(metadata.len());
+            }
+        }
+        Ok(removed)
+    }
+
+    // Deletes the expired entries of the download links cache, or all of
+    // them.
+    async fn remove_cache_entries(&self, all: bool) -> io::Result<Removed> {
+        let mut removed = Removed::default();
+        let Some(files) = &self.links_cache else {
+            return Ok(removed);
+        };
+        for key in files.list("").await.map_err(io::Error::other)? {
+            let Ok(body) = files.get(&key).await else {
+                continue;
+            };
+            let data = file_store::read_to_vec(body).await?;
+            if !all && !links_cache::is_expired(&data, self.links_cache_ttl, &self.clock) {
+                continue;
+            }
+            if files.delete(&key).await.is_ok() {
+                removed.add(data.len() as u64);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+// Books, in a format LibReads knows, e.g. `1984.epub`, their covers, e.g.
+// `1984.cover.jpg`, and partial downloads, e.g. `1984.epub.part`.
+fn is_temp_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let is_book = |name: &str| {
+        Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| !matches!(ext.parse(), Ok(Extension::Other(_))))
+    };
+    match name.strip_suffix(".part") {
+        Some(name) => is_book(name),
+        None => is_book(name) || name.contains(".cover."),
+    }
+}
+
+#[test]
+fn test_is_temp_file() {
+    for name in [
+        "1984.epub",
+        "George Orwell - 1984 (1949).MOBI",
+        "1984.epub.part",
+        "1984.cover.jpg",
+        "Broken Djvu (djvu).pdf",
+    ] {
+        assert!(is_temp_file(Path::new(name)), "{}", name);
+    }
+    for name in [
+        "Cargo.toml",
+        "README.md",
+        "1984",
+        ".part",
+        "notes.txt.bak",
+        "backup.tar.part",
+    ] {
+        assert!(!is_temp_file(Path::new(name)), "{}", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{unix_seconds, MockClock},
+        file_store::LocalFileStore,
+        library_dot_lol::DownloadLinks,
+    };
+    use std::time::SystemTime;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libreads_maintenance_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Writes a file last modified `age` before `now`.
+    fn write_file(dir: &Path, name: &str, data: &str, now: SystemTime, age: Duration) {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(now - age)
+            .unwrap();
+    }
+
+    fn maintenance(config: &Config, dir: &Path, now: SystemTime) -> Maintenance {
+        Maintenance::new(&Config {
+            work_dir: dir.to_path_buf(),
+            ..config.clone()
+        })
+        .with_clock(SharedClock(Arc::new(MockClock::new(now))))
+    }
+
+    #[tokio::test]
+    async fn test_remove_temp_files() {
+        let dir = test_dir("temp_files");
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+        write_file(&dir, "old.epub", "12345", now, 7 * hour);
+        write_file(&dir, "old.epub.part", "123", now, 7 * hour);
+        write_file(&dir, "old.cover.jpg", "12", now, 8 * hour);
+        // Recent, or not written by LibReads.
+        write_file(&dir, "recent.mobi", "1234", now, hour);
+        write_file(&dir, "notes.md", "1234", now, 7 * hour);
+        std::fs::create_dir(dir.join("old.pdf")).unwrap();
+
+        let maintenance = maintenance(&Config::default(), &dir, now);
+        let report = maintenance.run(Some(Scope::Temp)).await.unwrap();
+
+        assert_eq!(
+            CleanupReport {
+                temp_files: Removed {
+                    files: 3,
+                    bytes: 10
+                },
+                cache_entries: Removed::default(),
+            },
+            report
+        );
+        let mut left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(vec!["notes.md", "old.pdf", "recent.mobi"], left);
+
+        // Nothing left to remove.
+        assert_eq!(
+            CleanupReport::default(),
+            maintenance.run(Some(Scope::Temp)).await.unwrap()
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Books the user keeps in the directory LibReads is run from look like
+    // the ones it leaves behind.
+    #[tokio::test]
+    async fn test_working_directory_is_not_swept() {
+        let now = SystemTime::now();
+        let old = Duration::from_secs(7 * 60 * 60);
+        write_file(Path::new("."), "report.pdf", "report", now, old);
+        let maintenance = Maintenance::new(&Config::default())
+            .with_clock(SharedClock(Arc::new(MockClock::new(now))));
+
+        let report = maintenance.run(Some(Scope::Temp)).await;
+
+        let kept = Path::new("report.pdf").exists();
+        std::fs::remove_file("report.pdf").unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(kept, "report.pdf should be left alone");
+        assert_eq!(
+            std::env::temp_dir().join("libreads"),
+            Config::default().work_dir
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_cache_entries() {
+        let dir = test_dir("cache_entries");
+        let temp_dir = dir.join("temp");
+        let now = SystemTime::now();
+        let clock = MockClock::new(now);
+        let config = Config {
+            cache_dir: Some(dir.clone()),
+            links_cache_ttl: Duration::from_secs(60),
+            ..Config::default()
+        };
+        let cache = LocalFileStore::new(dir.join(links_cache::CACHE_SUBDIR));
+        let entry = |age: u64| {
+            serde_json::to_vec(&serde_json::json!({
+                "fetched_at": unix_seconds(&clock) - age,
+                "links": DownloadLinks::default(),
+            }))
+            .unwrap()
+        };
+        let fresh = entry(30);
+        let expired = entry(90);
+        for (key, data) in [
+            ("FRESH.json", fresh.clone()),
+            ("EXPIRED.json", expired.clone()),
+            ("INVALID.json", b"{".to_vec()),
+        ] {
+            cache
+                .put(key, file_store::bytes_stream(data))
+                .await
+                .unwrap();
+        }
+        write_file(&dir, "old.epub", "12345", now, Duration::from_secs(86_400));
+        let maintenance = maintenance(&config, &temp_dir, now);
+
+        let report = maintenance.run(Some(Scope::Cache)).await.unwrap();
+        assert_eq!(
+            CleanupReport {
+                temp_files: Removed::default(),
+                cache_entries: Removed {
+                    files: 2,
+                    bytes: expired.len() as u64 + 1,
+                },
+            },
+            report
+        );
+        assert_eq!(Ok(vec!["FRESH.json".to_string()]), cache.list("").await);
+
+        // Fresh entries only go with the whole cache.
+        let report = maintenance.run(None).await.unwrap();
+        assert_eq!(CleanupReport::default(), report);
+        let report = maintenance.run(Some(Scope::All)).await.unwrap();
+        assert_eq!(
+            Removed {
+                files: 1,
+                bytes: fresh.len() as u64,
+            },
+            report.cache_entries
+        );
+        assert_eq!(Ok(vec![]), cache.list("").await);
+        // Only the temporary directory is cleaned up.
+        assert!(dir.join("old.epub").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! Module name_template names the books LibReads serves after a template,
+//! e.g. `{author} - {title} ({year})`, set with `LIBREADS_NAME_TEMPLATE` or
+//! `?name_template=`.
+//!
+//! Placeholders are `{title}`, `{author}`, `{year}`, `{extension}` (the format
+//! served), `{md5}`, and `{series}` and `{series_index}` (the position of the
+//! book in its series, from Goodreads), which are empty for books outside of
+//! a series. Each value is sanitised like titles are, and the extension is
+//! always appended to the rendered name.
+
+use crate::{convert::sanitise_title, extension::Extension};
+use std::str::FromStr;
+
+// Characters that could break a path or a header.
+const FORBIDDEN_CHARACTERS: &str = "/\\\":*?<>|";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct NameTemplate {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Placeholder {
+    Title,
+    Author,
+    Year,
+    Extension,
+    Md5,
+    Series,
+    SeriesIndex,
+}
+
+impl FromStr for Placeholder {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "title" => Ok(Self::Title),
+            "author" => Ok(Self::Author),
+            "year" => Ok(Self::Year),
+            "extension" => Ok(Self::Extension),
+            "md5" => Ok(Self::Md5),
+            "series" => Ok(Self::Series),
+            "series_index" => Ok(Self::SeriesIndex),
+            _ => Err(Error::UnknownPlaceholder(name.to_string())),
+        }
+    }
+}
+
+// The values placeholders are replaced with.
+pub struct NameFields<'a> {
+    pub title: &'a str,
+    pub author: &'a str,
+    pub year: &'a str,
+    pub extension: &'a Extension,
+    pub md5: &'a str,
+    pub series: &'a str,
+    pub series_index: &'a str,
+}
+
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut rest = template.trim();
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(at) if rest[at..].starts_with('}') => return Err(Error::Unbalanced),
+                Some(0) => {
+                    let end = rest.find('}').ok_or(Error::Unbalanced)?;
+                    let name = &rest[1..end];
+                    if name.contains('{') {
+                        return Err(Error::Unbalanced);
+                    }
+                    segments.push(Segment::Placeholder(name.trim().parse()?));
+                    rest = &rest[end + 1..];
+                }
+                at => {
+                    let (text, next) = rest.split_at(at.unwrap_or(rest.len()));
+                    if let Some(c) = text
+                        .chars()
+                        .find(|c| FORBIDDEN_CHARACTERS.contains(*c) || c.is_control())
+                    {
+                        return Err(Error::ForbiddenCharacter(c));
+                    }
+                    segments.push(Segment::Text(text.to_string()));
+                    rest = next;
+                }
+            }
+        }
+
+        if !segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder(_)))
+        {
+            return Err(Error::NoPlaceholder);
+        }
+        Ok(Self { segments })
+    }
+}
+
+impl NameTemplate {
+    // Returns the file name of a book, with its extension. Falls back to the
+    // title when the template renders to nothing, e.g. `{year}` for a book
+    // without a year.
+    pub fn render(&self, fields: &NameFields) -> String {
+        let name: String = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Placeholder(placeholder) => sanitise_title(&match placeholder {
+                    Placeholder::Title => fields.title.to_string(),
+                    Placeholder::Author => fields.author.to_string(),
+                    Placeholder::Year => fields.year.to_string(),
+                    Placeholder::Extension => fields.extension.to_string(),
+                    Placeholder::Md5 => fields.md5.to_string(),
+                    Placeholder::Series => fields.series.to_string(),
+                    Placeholder::SeriesIndex => fields.series_index.to_string(),
+                }),
+            })
+            .collect();
+        let name = name
+            .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+            .trim_end();
+
+        match name.is_empty() {
+            true => format!("{}.{}", sanitise_title(fields.title), fields.extension),
+            false => format!("{}.{}", name, fields.extension),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    UnknownPlaceholder(String),
+    Unbalanced,
+    ForbiddenCharacter(char),
+    NoPlaceholder,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownPlaceholder(name) => write!(
+                f,
+                "unknown placeholder {{{}}}, expected: {{title}}, {{author}}, {{year}}, {{extension}}, {{md5}}",
+                name
+            ),
+            Error::Unbalanced => write!(f, "unbalanced braces"),
+            Error::ForbiddenCharacter(c) => write!(f, "forbidden character {:?}", c),
+            Error::NoPlaceholder => write!(f, "no placeholder, every book would have the same name"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> NameFields<'static> {
+        NameFields {
+            title: "Governing the Commons: The Evolution of Institutions",
+            author: "Elinor Ostrom",
+            year: "1990",
+            extension: &Extension::Epub,
+            md5: "5f3a1dc2e6d7e9a30b6e0f6f4d4c5ab1",
+            series: "",
+            series_index: "",
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(NameTemplate {
+                segments: vec![
+                    Segment::Placeholder(Placeholder::Author),
+                    Segment::Text(" - ".to_string()),
+                    Segment::Placeholder(Placeholder::Title),
+                    Segment::Text(" (".to_string()),
+                    Segment::Placeholder(Placeholder::Year),
+                    Segment::Text(")".to_string()),
+                ]
+            }),
+            "{author} - {title} ({year})".parse()
+        );
+        assert_eq!(
+            Ok(NameTemplate {
+                segments: vec![Segment::Placeholder(Placeholder::Md5)]
+            }),
+            " { md5 } ".parse()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for (template, want) in [
+            (
+                "{title} [{isbn13}]",
+                Error::UnknownPlaceholder("isbn13".to_string()),
+            ),
+            ("{Title}", Error::UnknownPlaceholder("Title".to_string())),
+            ("{}", Error::UnknownPlaceholder(String::new())),
+            ("{title", Error::Unbalanced),
+            ("title}", Error::Unbalanced),
+            ("{{title}}", Error::Unbalanced),
+            ("{author}/{title}", Error::ForbiddenCharacter('/')),
+            ("book", Error::NoPlaceholder),
+            ("", Error::NoPlaceholder),
+        ] {
+            assert_eq!(
+                Err(want),
+                template.parse::<NameTemplate>(),
+                "{:?}",
+                template
+            );
+        }
+        assert_eq!(
+            Err(Error::ForbiddenCharacter('\t')),
+            "{title}\t{author}".parse::<NameTemplate>()
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        for (template, want) in [
+            (
+                "{author} - {title} ({year})",
+                "Elinor Ostrom - Governing the Commons  The Evolution of Institutions (1990).epub",
+            ),
+            (
+                "{title}",
+                "Governing the Commons  The Evolution of Institutions.epub",
+            ),
+            (
+                "{md5} [{extension}]",
+                "5f3a1dc2e6d7e9a30b6e0f6f4d4c5ab1 [epub].epub",
+            ),
+        ] {
+            let template: NameTemplate = template.parse().unwrap();
+            assert_eq!(want, template.render(&fields()));
+        }
+    }
+
+    #[test]
+    fn test_render_sanitises_values() {
+        let template: NameTemplate = "{author} - {title}".parse().unwrap();
+        let fields = NameFields {
+            title: "../../etc/passwd",
+            author: "\"Anonymous\"",
+            ..fields()
+        };
+
+        assert_eq!("Anonymous - etc passwd.epub", template.render(&fields));
+    }
+
+    #[test]
+    fn test_render_series() {
+        let template: NameTemplate = "{series} {series_index} - {title}".parse().unwrap();
+        let fields = NameFields {
+            title: "The Fifth Season",
+            series: "The Broken Earth",
+            series_index: "1",
+            ..fields()
+        };
+
+        assert_eq!(
+            "The Broken Earth 1 - The Fifth Season.epub",
+            template.render(&fields)
+        );
+    }
+
+    #[test]
+    fn test_render_empty() {
+        let template: NameTemplate = "{year}".parse().unwrap();
+        let fields = NameFields {
+            year: "",
+            ..fields()
+        };
+
+        assert_eq!(
+            "Governing the Commons  The Evolution of Institutions.epub",
+            template.render(&fields)
+        );
+    }
+}
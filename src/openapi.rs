@@ -0,0 +1,151 @@
+//! Module openapi describes the HTTP API as an OpenAPI document, served at
+//! `/api/openapi.json`, from the `utoipa::path` annotations of the handlers
+//! of `web` and the schemas of the types they answer with.
+
+use crate::{maintenance, web};
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        server::Server,
+    },
+    Modify, OpenApi,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "LibReads",
+        description = "Download books from Goodreads links, converted for e-readers."
+    ),
+    paths(
+        web::download,
+        web::download_head,
+        web::download_post,
+        web::download_by_md5,
+        web::info,
+        web::search,
+        web::shelf,
+        web::import_goodreads_csv,
+        web::library,
+        web::remove_from_library,
+        web::cleanup,
+        web::stats,
+        web::book_history,
+        web::enqueue,
+        web::queue_items,
+        web::debug_parse,
+        web::healthz,
+        web::metrics,
+        web::openapi_document,
+    ),
+    // Only referenced by query parameters, which aren't collected.
+    components(schemas(maintenance::Scope)),
+    modifiers(&ApiToken),
+    // The token is only required when `LIBREADS_API_TOKEN` is set.
+    security((), ("api_token" = [])),
+    tags(
+        (name = "books", description = "Find and download books"),
+        (name = "library", description = "The books kept on disk, see LIBREADS_LIBRARY_DIR"),
+        (name = "queue", description = "Background downloads, see LIBREADS_QUEUE_FILE"),
+        (name = "admin", description = "Maintenance, statistics and debugging"),
+        (name = "probes", description = "Health checks and metrics, without the API token"),
+        (name = "docs", description = "The description of the API, without the API token"),
+    ),
+)]
+struct ApiDoc;
+
+// `LIBREADS_API_TOKEN`, as a bearer token. It can also be given as a `token`
+// query parameter, which OpenAPI can't express as an alternative.
+struct ApiToken;
+
+impl Modify for ApiToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_default()
+            .add_security_scheme(
+                "api_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+// The document of the API mounted at `base_path`, e.g. "/api".
+pub fn document(base_path: &str) -> utoipa::openapi::OpenApi {
+    let mut document = ApiDoc::openapi();
+    let base_path = match base_path {
+        "" => "/",
+        base_path => base_path,
+    };
+    document.servers = Some(vec![Server::new(base_path)]);
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let document: serde_json::Value =
+            serde_json::from_str(&document("/api").to_json().unwrap()).unwrap();
+
+        assert_eq!(serde_json::json!([{ "url": "/api" }]), document["servers"]);
+        for (path, method) in [
+            ("/download/{goodreads_url}", "get"),
+            ("/download/{goodreads_url}", "head"),
+            ("/download", "post"),
+            ("/download/md5/{md5}", "get"),
+            ("/info/{goodreads_url}", "get"),
+            ("/search", "get"),
+            ("/library", "get"),
+            ("/library/{md5}", "delete"),
+            ("/queue", "get"),
+            ("/queue", "post"),
+            ("/healthz", "get"),
+            ("/openapi.json", "get"),
+        ] {
+            assert!(
+                document["paths"][path][method].is_object(),
+                "{} {}",
+                method,
+                path
+            );
+        }
+        let schemas = &document["components"]["schemas"];
+        for (schema, property) in [
+            ("BookInfo", "download_links"),
+            ("BookInfo", "identification"),
+            ("DownloadLinks", "ipfs_cid"),
+            ("ErrorBody", "error"),
+            ("ErrorBody", "details"),
+            ("QueueItem", "status"),
+            ("QueueItem", "progress"),
+            ("DownloadRequest", "filters"),
+        ] {
+            assert!(
+                schemas[schema]["properties"][property].is_object(),
+                "{}.{}",
+                schema,
+                property
+            );
+        }
+        assert_eq!(
+            serde_json::json!(["pending", "active", "done", "failed"]),
+            schemas["Status"]["enum"]
+        );
+        assert_eq!("string", schemas["Extension"]["type"]);
+
+        // Every schema referenced is described.
+        let text = document.to_string();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas[name].is_object(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_document_at_the_root() {
+        assert_eq!("/", document("").servers.unwrap()[0].url);
+    }
+}
@@ -0,0 +1,284 @@
+//! Module output_path builds the paths of the files LibReads writes, from
+//! names that come from upstreams: book titles, original filenames, name
+//! templates and store keys.
+//!
+//! Every file LibReads writes goes through `join`: the books, covers and
+//! ZIPs in the work directory, and the files of `file_store`, which keeps
+//! the library and the links cache. Names are checked component by
+//! component, and the resolved path must stay under its root, so neither
+//! `..`, an absolute path nor a symbolic link can make LibReads write
+//! anywhere else.
+//!
+//! Names that Windows reserves, e.g. `CON` or `lpt1.epub`, are rejected too,
+//! so a library copied to Windows stays readable. `escape_reserved` renames
+//! them beforehand, for names that are legitimately titles.
+
+use std::path::{Path, PathBuf};
+
+// Windows reserves these names, with any extension, in any case.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // A `/`-separated component of the name that can't be a file name.
+    InvalidComponent {
+        name: String,
+        component: String,
+        reason: &'static str,
+    },
+    // The path resolves outside of its root, e.g. through a symbolic link.
+    OutsideRoot {
+        path: PathBuf,
+        root: PathBuf,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidComponent {
+                name,
+                component,
+                reason,
+            } => write!(
+                f,
+                "invalid file name {:?}: {:?} {}",
+                name, component, reason
+            ),
+            Error::OutsideRoot { path, root } => {
+                write!(f, "{} is outside of {}", path.display(), root.display())
+            }
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())
+    }
+}
+
+// Where `name`, a `/`-separated relative path such as `{md5}/{title}.epub`,
+// is written under `root`. An empty root is the working directory, and the
+// path stays relative then. The root, and the parents of the path, don't
+// have to exist yet.
+pub fn join(root: &Path, name: &str) -> Result<PathBuf, Error> {
+    let mut path = root.to_path_buf();
+    for component in name.split('/') {
+        check_component(name, component)?;
+        path.push(component);
+    }
+
+    let absolute_root = match root.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => root.to_path_buf(),
+    };
+    let resolved_root = resolve(&absolute_root);
+    if !resolve(&absolute_root.join(name)).starts_with(&resolved_root) {
+        return Err(Error::OutsideRoot {
+            path,
+            root: resolved_root,
+        });
+    }
+    Ok(path)
+}
+
+fn check_component(name: &str, component: &str) -> Result<(), Error> {
+    let reason = if component.is_empty() {
+        Some("is empty")
+    } else if component == "." || component == ".." {
+        Some("is a relative directory")
+    } else if component.contains('\\') {
+        Some("contains a path separator")
+    } else if component.contains('\0') {
+        Some("contains a NUL character")
+    } else if component.contains(char::is_control) {
+        Some("contains a control character")
+    } else if is_reserved(component) {
+        Some("is reserved on Windows")
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => Err(Error::InvalidComponent {
+            name: name.to_string(),
+            component: component.to_string(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
+
+// Whether Windows reserves the file name: the stem, before the first dot,
+// is a device name, or the name ends with a dot or a space.
+pub fn is_reserved(file_name: &str) -> bool {
+    let stem = file_name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        || file_name.ends_with(['.', ' '])
+}
+
+// Renames a file name Windows reserves, e.g. the book "Con" to `_Con.epub`
+// instead of `Con.epub`, so it can be written. Trailing dots and spaces are
+// dropped.
+pub fn escape_reserved(file_name: String) -> String {
+    let file_name = file_name.trim_end_matches(['.', ' ']);
+    match is_reserved(file_name) {
+        true => format!("_{}", file_name),
+        false => file_name.to_string(),
+    }
+}
+
+// Canonicalizes the longest part of `path` that exists, which resolves
+// symbolic links, and appends the rest, which can't contain any.
+fn resolve(path: &Path) -> PathBuf {
+    let mut missing = vec![];
+    let mut existing = path;
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .into_iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libreads_output_path_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_join() {
+        let dir = root("join");
+        assert_eq!(Ok(dir.join("1984.epub")), join(&dir, "1984.epub"));
+        assert_eq!(
+            Ok(dir.join("ABCD").join("Orgueil et préjugés.epub")),
+            join(&dir, "ABCD/Orgueil et préjugés.epub")
+        );
+        // Neither the root nor the parents have to exist.
+        assert_eq!(
+            Ok(dir.join("missing").join("ABCD").join("book.epub")),
+            join(&dir.join("missing"), "ABCD/book.epub")
+        );
+        // An empty root is the working directory.
+        assert_eq!(
+            Ok(PathBuf::from("book.epub")),
+            join(Path::new(""), "book.epub")
+        );
+        assert_eq!(
+            Ok(PathBuf::from("Title (Publisher, 2000).pdf.part")),
+            join(Path::new(""), "Title (Publisher, 2000).pdf.part")
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_join_rejects_traversal() {
+        let dir = root("traversal");
+        for (name, component) in [
+            ("../../etc/cron.d/evil", ".."),
+            ("ABCD/../../secret", ".."),
+            ("./book.epub", "."),
+            ("/etc/passwd", ""),
+            ("ABCD//book.epub", ""),
+            ("", ""),
+            ("..\\..\\windows\\evil.epub", "..\\..\\windows\\evil.epub"),
+            ("C:\\evil.epub", "C:\\evil.epub"),
+            ("book\0.epub", "book\0.epub"),
+            ("book\n.epub", "book\n.epub"),
+        ] {
+            match join(&dir, name) {
+                Err(Error::InvalidComponent { component: got, .. }) => {
+                    assert_eq!(component, got, "{:?}", name)
+                }
+                got => panic!("{:?} should be rejected, got {:?}", name, got),
+            }
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_join_rejects_reserved_names() {
+        let dir = root("reserved");
+        for name in [
+            "CON",
+            "con.epub",
+            "Lpt1.tar.gz",
+            "ABCD/aux",
+            "NUL .txt",
+            "book.",
+            "book ",
+        ] {
+            assert!(
+                matches!(
+                    join(&dir, name),
+                    Err(Error::InvalidComponent {
+                        reason: "is reserved on Windows",
+                        ..
+                    })
+                ),
+                "{:?}",
+                name
+            );
+        }
+        for name in ["Console.epub", "COM10.epub", "nullable", "Con Air.epub"] {
+            assert!(join(&dir, name).is_ok(), "{:?}", name);
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_join_rejects_symbolic_links_outside_root() {
+        let dir = root("symlink");
+        let outside = root("symlink_outside");
+        std::os::unix::fs::symlink(&outside, dir.join("escape")).unwrap();
+        std::fs::create_dir(dir.join("inside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("inside"), dir.join("alias")).unwrap();
+
+        assert!(matches!(
+            join(&dir, "escape/book.epub"),
+            Err(Error::OutsideRoot { .. })
+        ));
+        assert_eq!(
+            Ok(dir.join("alias").join("book.epub")),
+            join(&dir, "alias/book.epub")
+        );
+        let _ = std::fs::remove_dir_all(dir);
+        let _ = std::fs::remove_dir_all(outside);
+    }
+
+    #[test]
+    fn test_escape_reserved() {
+        for (name, want) in [
+            ("Con.epub", "_Con.epub"),
+            ("aux", "_aux"),
+            ("Title. ", "Title"),
+            ("Con.", "_Con"),
+            ("Title.epub", "Title.epub"),
+            ("Con Air.epub", "Con Air.epub"),
+        ] {
+            assert_eq!(want, escape_reserved(name.to_string()), "{}", name);
+            assert!(join(Path::new(""), &escape_reserved(name.to_string())).is_ok());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Module parse_check tells what the page parsers find, to notice when
+//! Goodreads or library.lol change their pages before books stop being
+//! found.
+//!
+//! `GET /api/debug/parse?url=...` fetches a Goodreads book page, or a
+//! library.lol page, and reports the fields parsed from it, without looking
+//! the book up nor downloading it.
+//!
+//! The bundled pages of `tests/testdata` are parsed by the golden tests,
+//! whose reports are kept in `tests/testdata/golden`. After a deliberate
+//! parser change, run them with `LIBREADS_UPDATE_GOLDEN=1` to rewrite them.
+
+use crate::{
+    config::Config,
+    goodreads::{self, Goodreads},
+    http_client::{self, ConnectionLimits},
+    library_dot_lol::LibraryDotLol,
+    libreads::Error,
+};
+use scraper::Html;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+// What a parser found on a page.
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct ParseReport {
+    // "goodreads" or "library.lol".
+    pub parser: String,
+    // Every field the parser looks for, and what it found, null if nothing.
+    pub fields: BTreeMap<String, serde_json::Value>,
+    // The fields that weren't found, in the order they are looked for.
+    pub missing: Vec<String>,
+}
+
+impl ParseReport {
+    pub fn new(parser: &str) -> Self {
+        Self {
+            parser: parser.to_string(),
+            ..Self::default()
+        }
+    }
+
+    // Records what was found for a field, usually an `Option`.
+    pub fn field(&mut self, name: &str, value: &impl Serialize) {
+        let value = serde_json::to_value(value).unwrap_or_default();
+        if value.is_null() {
+            self.missing.push(name.to_string());
+        }
+        self.fields.insert(name.to_string(), value);
+    }
+}
+
+#[test]
+fn test_parse_report() {
+    let mut report = ParseReport::new("goodreads");
+    report.field("title", &Some("1984"));
+    report.field("isbn10", &None::<String>);
+    report.field("pages", &Some(328));
+
+    assert_eq!(
+        serde_json::json!({
+            "parser": "goodreads",
+            "fields": {"isbn10": null, "pages": 328, "title": "1984"},
+            "missing": ["isbn10"],
+        }),
+        serde_json::to_value(report).unwrap()
+    );
+}
+
+// Parses a Goodreads book page that was already fetched, e.g. a saved one.
+pub fn parse_goodreads_page(document: &Html, book_url: &str) -> ParseReport {
+    goodreads::parse_book_page(document, book_url).1
+}
+
+// Fetches and parses pages like LibReads does, on its own connections.
+pub struct ParseChecker {
+    goodreads: Goodreads,
+    library_dot_lol: LibraryDotLol,
+}
+
+impl ParseChecker {
+    pub fn new(config: &Config) -> Self {
+        goodreads::compile_parsers();
+        let client = http_client::client(config.proxy.as_deref())
+            .expect("The proxy should have been validated with the config");
+        let mut limits = ConnectionLimits::new(
+            config.max_download_connections,
+            config.max_metadata_connections,
+        );
+        if let Some(recording) = &config.http_recording {
+            limits = limits.with_recording(recording.client());
+        }
+        let base_url = |base_url: &Option<String>| {
+            base_url.as_deref().map(|base_url| {
+                http_client::parse_base_url(base_url)
+                    .expect("The base URLs should have been validated with the config")
+            })
+        };
+        let mut library_dot_lol = LibraryDotLol {
+            client: client.clone(),
+            limits: limits.clone(),
+            gateways: config.ipfs_gateways.clone(),
+            ..Default::default()
+        };
+        if let Some(base_url) = base_url(&config.library_lol_url) {
+            library_dot_lol.base_url = base_url;
+        }
+        Self {
+            goodreads: Goodreads {
+                client,
+                limits,
+                base_url: base_url(&config.goodreads_url),
+                ..Default::default()
+            },
+            library_dot_lol,
+        }
+    }
+
+    // Picks the parser after the host of the page: Goodreads book, work and
+    // review pages, or library.lol book pages, e.g.
+    // "http://library.lol/main/<md5>".
+    pub async fn check(&self, page_url: &str) -> Result<ParseReport, Error> {
+        let unsupported = || {
+            Error::InvalidInput(format!(
+                "no parser for {:?}: expected a Goodreads or library.lol page",
+                page_url
+            ))
+        };
+        let url = reqwest::Url::parse(page_url).map_err(|_| unsupported())?;
+        let host = url.host_str().unwrap_or_default();
+
+        if host == "goodreads.com" || host.ends_with(".goodreads.com") {
+            return Ok(self.goodreads.parse_report(page_url).await?);
+        }
+        if host == "library.lol" || page_url.starts_with(&self.library_dot_lol.base_url) {
+            let md5 = url
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+                .ok_or_else(unsupported)?;
+            return Ok(self.library_dot_lol.parse_report(md5).await?);
+        }
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+
+    fn checker(server: &MockServer) -> ParseChecker {
+        ParseChecker::new(&Config {
+            goodreads_url: Some(server.base_url()),
+            library_lol_url: Some(server.url("/main")),
+            ..Config::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_check_goodreads() {
+        let server = MockServer::start();
+        let page = server.mock(|when, then| {
+            when.method(GET)
+                .path("/book/show/19161852-the-fifth-season");
+            then.status(200).body(include_str!(
+                "../tests/testdata/goodreads_series_book_page.html"
+            ));
+        });
+
+        let report = checker(&server)
+            .check("https://www.goodreads.com/book/show/19161852-the-fifth-season")
+            .await
+            .unwrap();
+
+        page.assert();
+        assert_eq!("goodreads", report.parser);
+        assert_eq!(
+            Some(&serde_json::json!("9780316229296")),
+            report.fields.get("isbn13")
+        );
+        assert_eq!(Vec::<String>::new(), report.missing);
+    }
+
+    #[tokio::test]
+    async fn test_check_library_dot_lol() {
+        let server = MockServer::start();
+        let page = server.mock(|when, then| {
+            when.method(GET).path("/main/ABCDEF");
+            then.status(200).body(include_str!(
+                "../tests/testdata/library.lol_original_filename_page.html"
+            ));
+        });
+
+        let report = checker(&server)
+            .check("http://library.lol/main/abcdef")
+            .await
+            .unwrap();
+
+        page.assert();
+        assert_eq!("library.lol", report.parser);
+        assert_eq!(Vec::<String>::new(), report.missing);
+    }
+
+    #[tokio::test]
+    async fn test_check_unsupported() {
+        let server = MockServer::start();
+        for page_url in ["https://example.com/book/1", "not a URL"] {
+            assert!(
+                matches!(
+                    checker(&server).check(page_url).await,
+                    Err(Error::InvalidInput(_))
+                ),
+                "{}",
+                page_url
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use super::*;
+    use crate::library_dot_lol;
+    use std::path::{Path, PathBuf};
+
+    const TESTDATA: &str = "tests/testdata";
+
+    // Shelves, reviews and works aren't book pages: only the link to the
+    // book is read from them.
+    const NOT_BOOK_PAGES: &[&str] = &["_shelf_", "_review_", "_work_"];
+
+    // The HTML fixtures whose name starts with `prefix`.
+    fn fixtures(prefix: &str) -> Vec<PathBuf> {
+        let mut fixtures: Vec<PathBuf> = std::fs::read_dir(TESTDATA)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with(prefix)
+                    && name.ends_with(".html")
+                    && !NOT_BOOK_PAGES.iter().any(|kind| name.contains(kind))
+            })
+            .collect();
+        fixtures.sort();
+        assert!(!fixtures.is_empty(), "no {} fixture", prefix);
+        fixtures
+    }
+
+    // Compares the report with the one kept for the fixture, or rewrites it.
+    fn assert_golden(fixture: &Path, report: &ParseReport) {
+        let golden = Path::new(TESTDATA)
+            .join("golden")
+            .join(fixture.with_extension("json").file_name().unwrap());
+        let got = serde_json::to_string_pretty(report).unwrap() + "\n";
+        if std::env::var_os("LIBREADS_UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+            std::fs::write(&golden, got).unwrap();
+            return;
+        }
+        let want = std::fs::read_to_string(&golden).unwrap_or_default();
+        assert_eq!(
+            want,
+            got,
+            "{} changed, rerun with LIBREADS_UPDATE_GOLDEN=1 if expected",
+            golden.display()
+        );
+    }
+
+    #[test]
+    fn test_goodreads_golden() {
+        for fixture in fixtures("goodreads_") {
+            let document = Html::parse_document(&std::fs::read_to_string(&fixture).unwrap());
+            let report = parse_goodreads_page(&document, "https://www.goodreads.com/book/show/0");
+            assert_golden(&fixture, &report);
+        }
+    }
+
+    #[test]
+    fn test_library_dot_lol_golden() {
+        for fixture in fixtures("library.lol_") {
+            let document = Html::parse_document(&std::fs::read_to_string(&fixture).unwrap());
+            let (_, report) =
+                library_dot_lol::extract_links(&document, &Config::default().ipfs_gateways);
+            assert_golden(&fixture, &report);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Module prober checks, in the background, whether the upstreams are
+//! reachable from this host: Goodreads, LibGen and the download sources.
+//!
+//! It is disabled unless `Config::probe_interval` is set. Every interval,
+//! each upstream is sent a `HEAD` request, on the connections and through
+//! the circuit breakers LibReads uses: upstreams whose circuit is open
+//! aren't called, and those rate-limiting us are left alone until they
+//! said to retry. Their availability is reported by `/healthz`, and as
+//! Prometheus gauges by `/metrics`.
+
+use crate::{
+    circuit_breaker,
+    clock::{unix_seconds, SharedClock},
+    config::{Config, DownloadSource},
+    goodreads,
+    http_client::{self, ConnectionLimits},
+    libgen, libgen_li, libgen_rocks, library_dot_lol,
+    libreads::LibReads,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+// Probes are cheap: upstreams slower than this are considered down.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long an upstream rate-limiting us without a `Retry-After` is left
+// alone.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct UpstreamStatus {
+    // Whether it answered the last probe, rate limits included.
+    pub up: bool,
+    // When it last answered, in seconds since the Unix epoch.
+    pub last_success: Option<u64>,
+    // It isn't probed before then, as it asked.
+    #[serde(skip)]
+    backoff_until: Option<SystemTime>,
+}
+
+#[derive(Clone)]
+pub struct UpstreamProber {
+    // Where each upstream is probed, e.g. "http://libgen.rs/json.php".
+    urls: Vec<String>,
+    client: reqwest::Client,
+    limits: ConnectionLimits,
+    clock: SharedClock,
+    // By host, see `circuit_breaker::host`.
+    statuses: Arc<Mutex<BTreeMap<String, UpstreamStatus>>>,
+}
+
+impl UpstreamProber {
+    // Probes the upstreams `libreads` is configured with, on its connections.
+    pub fn new(config: &Config, libreads: &LibReads) -> Self {
+        let mut urls = vec![
+            config
+                .goodreads_url
+                .clone()
+                .unwrap_or_else(|| goodreads::BASE_URL.to_string()),
+            config
+                .libgen_url
+                .clone()
+                .unwrap_or_else(|| libgen::BASE_URL.to_string()),
+        ];
+        urls.extend(config.download_sources.iter().map(|source| {
+            match source {
+                DownloadSource::LibraryDotLol => config
+                    .library_lol_url
+                    .clone()
+                    .unwrap_or_else(|| library_dot_lol::BASE_URL.to_string()),
+                DownloadSource::LibgenRocks => libgen_rocks::BASE_URL.to_string(),
+                DownloadSource::LibgenLi => libgen_li::BASE_URL.to_string(),
+            }
+        }));
+        Self {
+            urls,
+            client: http_client::client(config.proxy.as_deref())
+                .expect("The proxy should have been validated with the config"),
+            limits: libreads.connection_limits.clone(),
+            clock: SharedClock::default(),
+            statuses: Arc::default(),
+        }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    // Probes every upstream once, at the same time.
+    pub async fn probe(&self) {
+        futures_util::future::join_all(self.urls.iter().map(|url| self.probe_upstream(url))).await;
+    }
+
+    // Probes now, then every `interval`, until the runtime stops.
+    pub fn spawn(self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                self.probe().await;
+            }
+        })
+    }
+
+    async fn probe_upstream(&self, url: &str) {
+        let Ok(parsed) = reqwest::Url::parse(url) else {
+            return;
+        };
+        let host = circuit_breaker::host(&parsed);
+        let now = self.clock.now();
+        let backing_off = self
+            .statuses
+            .lock()
+            .unwrap()
+            .get(&host)
+            .and_then(|status| status.backoff_until)
+            .is_some_and(|until| now < until);
+        if backing_off {
+            return;
+        }
+
+        let response = {
+            let _permit = self.limits.metadata().await;
+            let request = self.client.head(parsed).timeout(PROBE_TIMEOUT);
+            http_client::send_once(request, &self.limits).await
+        };
+        // An open circuit means it failed too often to be called.
+        let (up, backoff) = match response {
+            Ok(response) => match http_client::rate_limit(&response) {
+                Some(limit) => (true, Some(limit.retry_after.unwrap_or(DEFAULT_BACKOFF))),
+                None => (!response.status().is_server_error(), None),
+            },
+            Err(_) => (false, None),
+        };
+
+        let mut statuses = self.statuses.lock().unwrap();
+        let status = statuses.entry(host).or_default();
+        status.up = up;
+        if up {
+            status.last_success = Some(unix_seconds(&*self.clock));
+        }
+        status.backoff_until = backoff.map(|backoff| now + backoff);
+    }
+
+    // The upstreams probed so far, by host.
+    pub fn statuses(&self) -> BTreeMap<String, UpstreamStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    // The statuses as Prometheus gauges, in its text format.
+    pub fn metrics(&self) -> String {
+        let statuses = self.statuses();
+        let mut metrics = String::new();
+        metrics.push_str(
+            "# HELP libreads_upstream_up Whether the upstream answered the last probe.\n\
+             # TYPE libreads_upstream_up gauge\n",
+        );
+        for (host, status) in &statuses {
+            let _ = writeln!(
+                metrics,
+                "libreads_upstream_up{{host=\"{}\"}} {}",
+                host,
+                u8::from(status.up)
+            );
+        }
+        metrics.push_str(
+            "# HELP libreads_upstream_last_success_timestamp_seconds When the upstream last answered a probe.\n\
+             # TYPE libreads_upstream_last_success_timestamp_seconds gauge\n",
+        );
+        for (host, status) in &statuses {
+            if let Some(last_success) = status.last_success {
+                let _ = writeln!(
+                    metrics,
+                    "libreads_upstream_last_success_timestamp_seconds{{host=\"{}\"}} {}",
+                    host, last_success
+                );
+            }
+        }
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use httpmock::{Method, MockServer};
+    use std::time::UNIX_EPOCH;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn host(server: &MockServer) -> String {
+        server.address().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let healthy = MockServer::start();
+        let healthy_probe = healthy.mock(|when, then| {
+            when.method(Method::HEAD).path("/");
+            then.status(200);
+        });
+        let failing = MockServer::start();
+        let failing_probe = failing.mock(|when, then| {
+            when.method(Method::HEAD).path("/json.php");
+            then.status(500);
+        });
+        let rate_limiting = MockServer::start();
+        let rate_limited_probe = rate_limiting.mock(|when, then| {
+            when.method(Method::HEAD).path("/main");
+            then.status(429).header("Retry-After", "60");
+        });
+        let config = Config {
+            goodreads_url: Some(healthy.base_url()),
+            libgen_url: Some(failing.url("/json.php")),
+            library_lol_url: Some(rate_limiting.url("/main")),
+            download_sources: vec![DownloadSource::LibraryDotLol],
+            ..Config::default()
+        };
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(NOW)));
+        let prober = UpstreamProber::new(&config, &LibReads::new(&config))
+            .with_clock(SharedClock(clock.clone()));
+
+        prober.probe().await;
+
+        let status = |up: bool, last_success: Option<u64>| (up, last_success);
+        let statuses: BTreeMap<String, (bool, Option<u64>)> = prober
+            .statuses()
+            .into_iter()
+            .map(|(host, got)| (host, status(got.up, got.last_success)))
+            .collect();
+        assert_eq!(
+            BTreeMap::from([
+                (host(&healthy), status(true, Some(NOW))),
+                (host(&failing), status(false, None)),
+                // Rate limits mean it is up.
+                (host(&rate_limiting), status(true, Some(NOW))),
+            ]),
+            statuses
+        );
+        let metrics = prober.metrics();
+        for line in [
+            format!("libreads_upstream_up{{host=\"{}\"}} 1", host(&healthy)),
+            format!("libreads_upstream_up{{host=\"{}\"}} 0", host(&failing)),
+            format!(
+                "libreads_upstream_up{{host=\"{}\"}} 1",
+                host(&rate_limiting)
+            ),
+            format!(
+                "libreads_upstream_last_success_timestamp_seconds{{host=\"{}\"}} {}",
+                host(&healthy),
+                NOW
+            ),
+        ] {
+            assert!(metrics.lines().any(|got| got == line), "{}", metrics);
+        }
+        assert!(!metrics.contains(&format!(
+            "libreads_upstream_last_success_timestamp_seconds{{host=\"{}\"}}",
+            host(&failing)
+        )));
+
+        // The rate-limiting upstream is left alone until it said to retry.
+        clock.advance(Duration::from_secs(30));
+        prober.probe().await;
+        rate_limited_probe.assert_hits(1);
+        clock.advance(Duration::from_secs(30));
+        prober.probe().await;
+        rate_limited_probe.assert_hits(2);
+        healthy_probe.assert_hits(3);
+        failing_probe.assert_hits(3);
+    }
+
+    #[tokio::test]
+    async fn test_probe_respects_open_circuits() {
+        let failing = MockServer::start();
+        let failing_probe = failing.mock(|when, then| {
+            when.method(Method::HEAD);
+            then.status(500);
+        });
+        let config = Config::default();
+        let libreads = LibReads::new(&config);
+        let prober = UpstreamProber {
+            urls: vec![failing.base_url()],
+            ..UpstreamProber::new(&config, &libreads)
+        };
+
+        for _ in 0..circuit_breaker::DEFAULT_FAILURE_THRESHOLD + 1 {
+            prober.probe().await;
+        }
+
+        // Once the circuit is open, it isn't called anymore.
+        failing_probe.assert_hits(circuit_breaker::DEFAULT_FAILURE_THRESHOLD as usize);
+        assert!(!prober.statuses()[&host(&failing)].up);
+    }
+}
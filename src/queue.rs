@@ -0,0 +1,814 @@
+//! Module queue downloads books in the background, so a long list of them
+//! doesn't depend on a single request staying open, nor on the server
+//! staying up.
+//!
+//! `POST /api/queue` enqueues books, and `GET /api/queue` lists them, with
+//! their progress. Workers, `Config::queue_concurrency` of them, download
+//! them one by one like `GET /api/download` would, into the library, and
+//! record them in the history.
+//!
+//! The queue is kept in a JSON journal, `Config::queue_file`, rewritten
+//! whenever a book is enqueued, started or finished. Books that were being
+//! downloaded when the server stopped are queued again when it starts. At
+//! most `MAX_PENDING` books wait at once, which keeps the journal small.
+
+use crate::{
+    clock::{unix_seconds, SharedClock},
+    convert::{ConversionOptions, DownloadProgress, ProgressCallback},
+    extension::Extension,
+    history::{BookRequest, History, Outcome},
+    libreads::BookService,
+    selection::SelectionFilters,
+    web,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::Notify,
+    task::{AbortHandle, JoinHandle},
+};
+use utoipa::ToSchema;
+
+pub const DEFAULT_CONCURRENCY: usize = 2;
+// Older finished books are forgotten.
+const MAX_FINISHED: usize = 1_000;
+// Books can't be enqueued while this many are pending or active.
+pub const MAX_PENDING: usize = 1_000;
+
+// The journal, serialised while locked, to be saved once unlocked.
+type Serialized = serde_json::Result<Vec<u8>>;
+
+#[derive(Debug)]
+pub enum Error {
+    // Enqueuing would leave more than `MAX_PENDING` books waiting.
+    Full { waiting: usize },
+    Journal(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full { waiting } => write!(
+                f,
+                "the queue is full: {} books are waiting, at most {}",
+                waiting, MAX_PENDING
+            ),
+            Self::Journal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Active,
+    Done,
+    Failed,
+}
+
+// A book to download, as enqueued.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueRequest {
+    // A Goodreads book URL.
+    pub url: String,
+    pub format: Extension,
+    // ebook-convert output profile, see `device`.
+    pub output_profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct QueueItem {
+    pub id: u64,
+    pub url: String,
+    pub format: Extension,
+    pub output_profile: Option<String>,
+    pub status: Status,
+    // How far the download is, while active. Dropped when the journal is
+    // loaded.
+    #[serde(default)]
+    pub progress: Option<Progress>,
+    // Once done: the title and the MD5 of the edition, in the library.
+    pub title: Option<String>,
+    pub md5: Option<String>,
+    // Once failed, e.g. "not found: ...".
+    pub error: Option<String>,
+    // In seconds since the Unix epoch.
+    pub enqueued_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Progress {
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+// What is written to the journal.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Journal {
+    next_id: u64,
+    items: Vec<QueueItem>,
+}
+
+pub struct Queue {
+    journal: Mutex<Journal>,
+    // Held while the journal is changed and written, so that it is written
+    // in order, and an enqueue can be undone.
+    saving: tokio::sync::Mutex<()>,
+    path: PathBuf,
+    // Wakes a worker up when a book is enqueued.
+    work: Notify,
+    clock: SharedClock,
+}
+
+impl Queue {
+    // Loads the journal at `path`, or starts an empty one if there is none
+    // yet. Books left active by a previous run are pending again.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut journal: Journal = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid queue journal {}: {}", path.display(), err),
+                )
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Journal::default(),
+            Err(err) => {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!(
+                        "could not read the queue journal {}: {}",
+                        path.display(),
+                        err
+                    ),
+                ))
+            }
+        };
+        for item in &mut journal.items {
+            if item.status == Status::Active {
+                item.status = Status::Pending;
+            }
+            item.progress = None;
+        }
+        Ok(Self {
+            journal: Mutex::new(journal),
+            saving: tokio::sync::Mutex::new(()),
+            path,
+            work: Notify::new(),
+            clock: SharedClock::default(),
+        })
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    // Enqueues the books, in order, and returns them. Nothing is enqueued
+    // if there are too many, or the journal can't be written.
+    pub async fn enqueue(&self, requests: Vec<QueueRequest>) -> Result<Vec<QueueItem>, Error> {
+        let _saving = self.saving.lock().await;
+        let (first, enqueued, serialized) = self.push(requests)?;
+        if let Err(err) = self.save(serialized).await {
+            self.journal.lock().unwrap().items.truncate(first);
+            return Err(Error::Journal(err));
+        }
+        for _ in &enqueued {
+            self.work.notify_one();
+        }
+        Ok(enqueued)
+    }
+
+    // Adds the books as pending, and returns where they start, what was
+    // added, and the journal to save.
+    fn push(
+        &self,
+        requests: Vec<QueueRequest>,
+    ) -> Result<(usize, Vec<QueueItem>, Serialized), Error> {
+        let mut journal = self.journal.lock().unwrap();
+        let waiting = journal
+            .items
+            .iter()
+            .filter(|item| matches!(item.status, Status::Pending | Status::Active))
+            .count();
+        if waiting + requests.len() > MAX_PENDING {
+            return Err(Error::Full { waiting });
+        }
+        let enqueued_at = unix_seconds(&*self.clock);
+        let first = journal.items.len();
+        for request in requests {
+            let id = journal.next_id;
+            journal.next_id += 1;
+            journal.items.push(QueueItem {
+                id,
+                url: request.url,
+                format: request.format,
+                output_profile: request.output_profile,
+                status: Status::Pending,
+                progress: None,
+                title: None,
+                md5: None,
+                error: None,
+                enqueued_at,
+                finished_at: None,
+            });
+        }
+        let enqueued = journal.items[first..].to_vec();
+        Ok((first, enqueued, serde_json::to_vec(&*journal)))
+    }
+
+    // Every book, in the order they were enqueued.
+    pub fn items(&self) -> Vec<QueueItem> {
+        self.journal.lock().unwrap().items.clone()
+    }
+
+    // Marks the oldest pending book active, and returns it.
+    async fn claim(&self) -> Option<QueueItem> {
+        let _saving = self.saving.lock().await;
+        let (item, serialized) = {
+            let mut journal = self.journal.lock().unwrap();
+            let item = journal
+                .items
+                .iter_mut()
+                .find(|item| item.status == Status::Pending)?;
+            item.status = Status::Active;
+            let item = item.clone();
+            (item, serde_json::to_vec(&*journal))
+        };
+        self.save_or_warn(serialized).await;
+        Some(item)
+    }
+
+    // Waits for a pending book, and claims it.
+    async fn next(&self) -> QueueItem {
+        loop {
+            if let Some(item) = self.claim().await {
+                return item;
+            }
+            self.work.notified().await;
+        }
+    }
+
+    fn progress(&self, id: u64, progress: &DownloadProgress) {
+        let mut journal = self.journal.lock().unwrap();
+        if let Some(item) = journal.items.iter_mut().find(|item| item.id == id) {
+            item.progress = Some(Progress {
+                downloaded: progress.downloaded,
+                total: progress.total,
+            });
+        }
+    }
+
+    // Records how an active book ended: its title and MD5, or the error.
+    async fn finish(&self, id: u64, result: Result<(Option<String>, String), String>) {
+        let _saving = self.saving.lock().await;
+        if let Some(serialized) = self.record_finish(id, result) {
+            self.save_or_warn(serialized).await;
+        }
+    }
+
+    // Updates the book, and returns the journal to save, unless the book
+    // wasn't active.
+    fn record_finish(
+        &self,
+        id: u64,
+        result: Result<(Option<String>, String), String>,
+    ) -> Option<Serialized> {
+        let mut journal = self.journal.lock().unwrap();
+        let finished_at = unix_seconds(&*self.clock);
+        let item = journal
+            .items
+            .iter_mut()
+            .find(|item| item.id == id && item.status == Status::Active)?;
+        item.progress = None;
+        item.finished_at = Some(finished_at);
+        match result {
+            Ok((title, md5)) => {
+                item.status = Status::Done;
+                item.title = title;
+                item.md5 = Some(md5);
+            }
+            Err(err) => {
+                item.status = Status::Failed;
+                item.error = Some(err);
+            }
+        }
+
+        let finished = |item: &QueueItem| matches!(item.status, Status::Done | Status::Failed);
+        let mut excess = journal
+            .items
+            .iter()
+            .filter(|item| finished(item))
+            .count()
+            .saturating_sub(MAX_FINISHED);
+        journal.items.retain(|item| {
+            let forget = excess > 0 && finished(item);
+            excess -= usize::from(forget);
+            !forget
+        });
+        Some(serde_json::to_vec(&*journal))
+    }
+
+    // Replaces the journal, so it is never left half-written. It is
+    // serialised while locked, and written off the runtime's threads.
+    async fn save(&self, serialized: Serialized) -> io::Result<()> {
+        let partial = self.path.with_extension("json.part");
+        tokio::fs::write(&partial, serialized?).await?;
+        tokio::fs::rename(&partial, &self.path).await
+    }
+
+    // Workers carry on when the journal can't be written: the queue is only
+    // lost if the server stops before it can be again.
+    async fn save_or_warn(&self, serialized: Serialized) {
+        if let Err(err) = self.save(serialized).await {
+            tracing::warn!(
+                "Could not write the queue journal {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+// Downloads the queued books, one at a time per spawned task.
+#[derive(Clone)]
+pub struct Worker {
+    queue: Arc<Queue>,
+    libreads: Arc<dyn BookService>,
+    history: Arc<History>,
+    // Those of `GET /api/download`, without the query parameters.
+    options: ConversionOptions,
+}
+
+impl Worker {
+    pub fn new(
+        queue: Arc<Queue>,
+        libreads: Arc<dyn BookService>,
+        history: Arc<History>,
+        options: ConversionOptions,
+    ) -> Self {
+        Self {
+            queue,
+            libreads,
+            history,
+            options,
+        }
+    }
+
+    // Runs `concurrency` workers until the runtime stops.
+    pub fn spawn(self, concurrency: usize) -> Vec<JoinHandle<()>> {
+        (0..concurrency.max(1))
+            .map(|_| tokio::spawn(self.clone().run()))
+            .collect()
+    }
+
+    // Each book is processed in its own task: one that panics fails, rather
+    // than staying active with a worker less.
+    async fn run(self) {
+        loop {
+            let item = self.queue.next().await;
+            let (id, url, format) = (item.id, item.url.clone(), item.format.clone());
+            let task = tokio::spawn({
+                let worker = self.clone();
+                async move { worker.process(item).await }
+            });
+            let _abort = AbortOnDrop(task.abort_handle());
+            if let Err(err) = task.await {
+                tracing::error!(url, "Queued download panicked: {}", err);
+                let err = web::Error::from(crate::libreads::Error::ApplicationError(
+                    "the download panicked, see the server logs".to_string(),
+                ));
+                self.history.record(
+                    &BookRequest::from_url(&url),
+                    None,
+                    format,
+                    Outcome::Failed(err.name().to_string()),
+                );
+                self.queue.finish(id, Err(err.to_string())).await;
+            }
+        }
+    }
+
+    async fn process(&self, item: QueueItem) {
+        let progress = {
+            let (queue, id) = (self.queue.clone(), item.id);
+            ProgressCallback::new(move |progress: &DownloadProgress| queue.progress(id, progress))
+        };
+        let options = ConversionOptions {
+            output_profile: item.output_profile.clone(),
+            progress: Some(progress),
+            ..self.options.clone()
+        };
+
+        let mut title = None;
+        let result = match self
+            .libreads
+            .book_info(&item.url, Some(&item.format), &SelectionFilters::default())
+            .await
+        {
+            Ok((book_info, _)) => {
+                title = web::title(&book_info);
+                self.libreads
+                    .book_file(book_info, item.format.clone(), &options)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        let request = BookRequest::from_url(&item.url);
+        match result {
+            Ok(result) => {
+                self.history.record(
+                    &request,
+                    title.clone(),
+                    item.format.clone(),
+                    Outcome::Delivered {
+                        bytes: result.bytes,
+                        from_library: result.source == crate::convert::LOCAL_SOURCE,
+                        conversion_duration: result.conversion_duration,
+                    },
+                );
+                // The library keeps its own copy.
+                let _ = tokio::fs::remove_file(&result.path).await;
+                self.queue.finish(item.id, Ok((title, result.md5))).await;
+            }
+            Err(err) => {
+                let err = web::Error::from(err);
+                self.history.record(
+                    &request,
+                    title,
+                    item.format.clone(),
+                    Outcome::Failed(err.name().to_string()),
+                );
+                self.queue.finish(item.id, Err(err.to_string())).await;
+            }
+        }
+    }
+}
+
+// Aborts the task of a book when its worker is, e.g. when the runtime stops.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        convert::ConversionResult,
+        goodreads::BookIdentification,
+        libgen::LibgenMetadata,
+        library_dot_lol::DownloadLinks,
+        libreads::{self, BookInfo, MockBookService, Timings},
+    };
+    use std::{collections::HashMap, time::Duration};
+
+    fn journal(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("libreads_queue_{}.json", name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn request(id: &str) -> QueueRequest {
+        QueueRequest {
+            url: format!("https://www.goodreads.com/book/show/{}", id),
+            format: Extension::Epub,
+            output_profile: None,
+        }
+    }
+
+    fn book_info(url: &str) -> BookInfo {
+        let id = url.rsplit('/').next().unwrap();
+        BookInfo {
+            metadata: LibgenMetadata {
+                title: id.to_string(),
+                author: "Elinor Ostrom".to_string(),
+                year: "1990".to_string(),
+                extension: Extension::Epub,
+                md5: format!("md5-{}", id),
+                filesize: None,
+                language: None,
+                pages: None,
+                publisher: None,
+            },
+            download_links: DownloadLinks {
+                ipfs_cid: None,
+                gateways: vec![],
+                http: "http://library.lol/main/md5".to_string(),
+                filename: None,
+            },
+            cover_url: None,
+            isbn10: None,
+            isbn13: None,
+            available_formats: vec![Extension::Epub],
+            alternatives: vec![],
+            found_by_title: false,
+            identification: BookIdentification::default(),
+            book: None,
+        }
+    }
+
+    // Finds every book. Downloads block until `release` is notified, once
+    // per download, and are counted by MD5 once they return.
+    fn libreads(
+        release: Arc<tokio::sync::Semaphore>,
+        completed: Arc<Mutex<HashMap<String, usize>>>,
+    ) -> Arc<dyn BookService> {
+        let mut libreads = MockBookService::new();
+        libreads.expect_book_info().returning(|url, _, _| {
+            let book_info = book_info(url);
+            Box::pin(async { Ok((book_info, Timings::default())) })
+        });
+        libreads
+            .expect_book_file()
+            .returning(move |book_info, extension, _| {
+                let (release, completed) = (release.clone(), completed.clone());
+                Box::pin(async move {
+                    release.acquire().await.unwrap().forget();
+                    let md5 = book_info.metadata.md5.clone();
+                    *completed.lock().unwrap().entry(md5.clone()).or_default() += 1;
+                    Ok(ConversionResult {
+                        path: PathBuf::from("/nonexistent/libreads_queue_book.epub"),
+                        extension,
+                        bytes: 42,
+                        converted: false,
+                        md5,
+                        source: "dweb.link".to_string(),
+                        original_extension: Extension::Epub,
+                        download_duration: Duration::ZERO,
+                        conversion_duration: None,
+                        language_mismatch: None,
+                    })
+                })
+            });
+        Arc::new(libreads)
+    }
+
+    async fn wait_for(queue: &Queue, done: impl Fn(&[QueueItem]) -> bool) {
+        for _ in 0..200 {
+            if done(&queue.items()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out: {:?}", queue.items());
+    }
+
+    fn statuses(queue: &Queue) -> Vec<Status> {
+        queue.items().iter().map(|item| item.status).collect()
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_and_reopen() {
+        let path = journal("reopen");
+        let queue = Queue::open(&path).unwrap();
+        let enqueued = queue
+            .enqueue(vec![request("1"), request("2")])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![0, 1],
+            enqueued.iter().map(|item| item.id).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(0), queue.claim().await.map(|item| item.id));
+        assert_eq!(vec![Status::Active, Status::Pending], statuses(&queue));
+
+        // The active book is pending again after a restart, and ids go on.
+        let queue = Queue::open(&path).unwrap();
+        assert_eq!(vec![Status::Pending, Status::Pending], statuses(&queue));
+        assert_eq!(2, queue.enqueue(vec![request("3")]).await.unwrap()[0].id);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_open_invalid_journal() {
+        let path = journal("invalid");
+        std::fs::write(&path, "not JSON").unwrap();
+        let Err(err) = Queue::open(&path) else {
+            panic!("the journal should be invalid");
+        };
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(
+            err.to_string().contains(&path.display().to_string()),
+            "{}",
+            err
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_finish_forgets_oldest_finished() {
+        let path = journal("forget");
+        let queue = Queue::open(&path).unwrap();
+        for id in 0..MAX_FINISHED + 1 {
+            queue.enqueue(vec![request(&id.to_string())]).await.unwrap();
+            let item = queue.claim().await.unwrap();
+            queue
+                .finish(item.id, Err("not found: nope".to_string()))
+                .await;
+        }
+        queue.enqueue(vec![request("last")]).await.unwrap();
+        let items = queue.items();
+        assert_eq!(MAX_FINISHED + 1, items.len());
+        assert_eq!(1, items[0].id);
+        assert_eq!(Status::Pending, items.last().unwrap().status);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_limits() {
+        let path = journal("full");
+        let queue = Queue::open(&path).unwrap();
+        let requests = |count: usize| (0..count).map(|id| request(&id.to_string())).collect();
+        queue.enqueue(requests(MAX_PENDING - 1)).await.unwrap();
+        queue.claim().await.unwrap();
+
+        // Active books are waiting too.
+        let Err(Error::Full { waiting }) = queue.enqueue(requests(2)).await else {
+            panic!("the queue should be full");
+        };
+        assert_eq!(MAX_PENDING - 1, waiting);
+        assert_eq!(MAX_PENDING - 1, queue.items().len());
+        queue.enqueue(requests(1)).await.unwrap();
+
+        // Nothing is enqueued when the journal can't be written.
+        let queue = Queue::open(journal("nonexistent").join("queue.json")).unwrap();
+        let Err(Error::Journal(_)) = queue.enqueue(requests(1)).await else {
+            panic!("the journal should not be writable");
+        };
+        assert!(queue.items().is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_worker_failure() {
+        let path = journal("failure");
+        let queue = Arc::new(Queue::open(&path).unwrap());
+        let mut libreads = MockBookService::new();
+        libreads.expect_book_info().returning(|_, _, _| {
+            Box::pin(async { Err(libreads::Error::NotInLibrary("no edition".to_string())) })
+        });
+        let history = Arc::new(History::default());
+        let workers = Worker::new(
+            queue.clone(),
+            Arc::new(libreads),
+            history.clone(),
+            ConversionOptions::default(),
+        )
+        .spawn(1);
+
+        queue.enqueue(vec![request("1048424")]).await.unwrap();
+        wait_for(&queue, |items| items[0].status == Status::Failed).await;
+
+        let item = &queue.items()[0];
+        assert_eq!(Some("not found: no edition"), item.error.as_deref());
+        assert_eq!(1, history.stats(None).failed);
+        for worker in workers {
+            worker.abort();
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_worker_panic() {
+        let path = journal("panic");
+        let queue = Arc::new(Queue::open(&path).unwrap());
+        let release = Arc::new(tokio::sync::Semaphore::new(1));
+        let completed = Arc::new(Mutex::new(HashMap::new()));
+        let downloads = libreads(release, completed.clone());
+        let mut libreads = MockBookService::new();
+        libreads.expect_book_info().returning(|url, _, _| {
+            let book_info = book_info(url);
+            Box::pin(async { Ok((book_info, Timings::default())) })
+        });
+        libreads
+            .expect_book_file()
+            .returning(move |book_info, extension, options| {
+                let downloads = downloads.clone();
+                let options = options.clone();
+                Box::pin(async move {
+                    if book_info.metadata.md5 == "md5-1" {
+                        panic!("Delete input file");
+                    }
+                    downloads.book_file(book_info, extension, &options).await
+                })
+            });
+        let history = Arc::new(History::default());
+        // A single worker, which must survive the panic.
+        let workers = Worker::new(
+            queue.clone(),
+            Arc::new(libreads),
+            history.clone(),
+            ConversionOptions::default(),
+        )
+        .spawn(1);
+
+        queue
+            .enqueue(vec![request("1"), request("2")])
+            .await
+            .unwrap();
+        wait_for(&queue, |items| {
+            items
+                .iter()
+                .all(|item| item.status != Status::Pending && item.status != Status::Active)
+        })
+        .await;
+
+        let items = queue.items();
+        assert_eq!(vec![Status::Failed, Status::Done], statuses(&queue));
+        assert_eq!(
+            Some("application: the download panicked, see the server logs"),
+            items[0].error.as_deref()
+        );
+        assert_eq!(Some("md5-2"), items[1].md5.as_deref());
+        let stats = history.stats(None);
+        assert_eq!((1, 1), (stats.succeeded, stats.failed));
+        for worker in workers {
+            worker.abort();
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_worker_crash_and_restart() {
+        let path = journal("crash");
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        let completed = Arc::new(Mutex::new(HashMap::new()));
+        let history = Arc::new(History::default());
+
+        let queue = Arc::new(Queue::open(&path).unwrap());
+        queue
+            .enqueue(vec![request("1"), request("2"), request("3")])
+            .await
+            .unwrap();
+        let worker = Worker::new(
+            queue.clone(),
+            libreads(release.clone(), completed.clone()),
+            history.clone(),
+            ConversionOptions::default(),
+        );
+        let workers = worker.spawn(1);
+        // The first book is done, and the worker crashes during the second.
+        release.add_permits(1);
+        wait_for(&queue, |items| {
+            items[0].status == Status::Done && items[1].status == Status::Active
+        })
+        .await;
+        for worker in workers {
+            worker.abort();
+        }
+
+        // Restarting requeues the second book, and finishes the others.
+        let queue = Arc::new(Queue::open(&path).unwrap());
+        assert_eq!(
+            vec![Status::Done, Status::Pending, Status::Pending],
+            statuses(&queue)
+        );
+        let workers = Worker::new(
+            queue.clone(),
+            libreads(release.clone(), completed.clone()),
+            history.clone(),
+            ConversionOptions::default(),
+        )
+        .spawn(2);
+        release.add_permits(2);
+        wait_for(&queue, |items| {
+            items.iter().all(|item| item.status == Status::Done)
+        })
+        .await;
+        for worker in workers {
+            worker.abort();
+        }
+
+        let items = Queue::open(&path).unwrap().items();
+        assert_eq!(
+            vec![Some("md5-1"), Some("md5-2"), Some("md5-3")],
+            items
+                .iter()
+                .map(|item| item.md5.as_deref())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some("2"), items[1].title.as_deref());
+        // Each book completed exactly once.
+        assert_eq!(
+            HashMap::from([
+                ("md5-1".to_string(), 1),
+                ("md5-2".to_string(), 1),
+                ("md5-3".to_string(), 1),
+            ]),
+            *completed.lock().unwrap()
+        );
+        let stats = history.stats(None);
+        assert_eq!((3, 0), (stats.succeeded, stats.failed));
+        let _ = std::fs::remove_file(&path);
+    }
+}
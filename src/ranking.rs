@@ -0,0 +1,383 @@
+//! Module ranking scores the editions found on LibGen, to try the most
+//! relevant first.
+//!
+//! A `CandidateRanker` gives each edition a score, and editions are tried
+//! highest score first. `DefaultRanker` prefers the best format, then
+//! editions in the requested language, then the ones about as long as the
+//! Goodreads edition, then the ones under the size limit, then newer ones. Applications embedding LibReads can plug their own ranker with
+//! `LibReads::with_ranker`, e.g. a `CompositeRanker` adding their signals to
+//! the default ones.
+
+pub use crate::libgen::LibgenMetadata;
+use crate::{extension::Extension, libgen::parse_year, selection::SelectionFilters};
+
+// What the request asks for, that rankers can take into account.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct RankingContext {
+    pub wanted_extension: Option<Extension>,
+    // Lowercased language name, as LibGen spells it, e.g. "english".
+    pub language: Option<String>,
+    // In bytes.
+    pub max_size: Option<u64>,
+    // Number of pages of the Goodreads edition.
+    pub pages: Option<u32>,
+}
+
+impl RankingContext {
+    pub fn new(wanted_extension: Option<&Extension>, filters: &SelectionFilters) -> Self {
+        Self {
+            wanted_extension: wanted_extension.cloned(),
+            language: filters.language.clone(),
+            max_size: filters.max_size,
+            pages: None,
+        }
+    }
+}
+
+pub trait CandidateRanker {
+    // Higher is better. Candidates with the same score keep LibGen's order.
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64;
+}
+
+// Sums the scores of other rankers, each multiplied by its weight.
+#[derive(Default)]
+pub struct CompositeRanker {
+    rankers: Vec<(i64, Box<dyn CandidateRanker + Send + Sync>)>,
+}
+
+impl CompositeRanker {
+    pub fn with(
+        mut self,
+        weight: i64,
+        ranker: impl CandidateRanker + Send + Sync + 'static,
+    ) -> Self {
+        self.rankers.push((weight, Box::new(ranker)));
+        self
+    }
+}
+
+impl CandidateRanker for CompositeRanker {
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64 {
+        self.rankers
+            .iter()
+            .map(|(weight, ranker)| weight.saturating_mul(ranker.score(candidate, ctx)))
+            .fold(0, i64::saturating_add)
+    }
+}
+
+// Weights of the default signals. Each one outweighs every signal after it:
+// years are below 10 000, and the other signals score -1, 0 or 1.
+const FORMAT_WEIGHT: i64 = 10_000_000;
+const LANGUAGE_WEIGHT: i64 = 1_000_000;
+const PAGES_WEIGHT: i64 = 100_000;
+const SIZE_WEIGHT: i64 = 10_000;
+const YEAR_WEIGHT: i64 = 1;
+
+pub struct DefaultRanker(CompositeRanker);
+
+impl Default for DefaultRanker {
+    fn default() -> Self {
+        Self(
+            CompositeRanker::default()
+                .with(FORMAT_WEIGHT, FormatRanker)
+                .with(LANGUAGE_WEIGHT, LanguageRanker)
+                .with(PAGES_WEIGHT, PagesRanker)
+                .with(SIZE_WEIGHT, SizeRanker)
+                .with(YEAR_WEIGHT, YearRanker),
+        )
+    }
+}
+
+impl CandidateRanker for DefaultRanker {
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64 {
+        self.0.score(candidate, ctx)
+    }
+}
+
+// Formats in the order of `Extension`'s `Ord`. ebook-convert can't read DJVU,
+// which has to be converted to PDF first: DJVU comes right after PDF, unless
+// it is the wanted format.
+pub struct FormatRanker;
+
+impl CandidateRanker for FormatRanker {
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64 {
+        let position = match candidate.extension {
+            Extension::Djvu if ctx.wanted_extension != Some(Extension::Djvu) => {
+                Extension::Pdf.preference() * 2 + 1
+            }
+            ref extension => extension.preference() * 2,
+        };
+        -i64::from(position)
+    }
+}
+
+// Editions known to be in the requested language come before the ones
+// LibGen doesn't know the language of.
+pub struct LanguageRanker;
+
+impl CandidateRanker for LanguageRanker {
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64 {
+        match (&ctx.language, &candidate.language) {
+            (Some(language), Some(candidate_language)) => i64::from(
+                crate::selection::is_in_language(candidate_language, language),
+            ),
+            _ => 0,
+        }
+    }
+}
+
+// Editions with at least twice as many pages as the Goodreads edition, or
+// half as many, e.g. an abridged one, are probably a different text: they
+// come after the ones of similar or unknown length.
+pub struct PagesRanker;
+
+impl CandidateRanker for PagesRanker {
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64 {
+        match (ctx.pages, candidate.pages) {
+            (Some(wanted), Some(pages)) => {
+                let (shorter, longer) = (wanted.min(pages), wanted.max(pages));
+                -i64::from(u64::from(longer) >= 2 * u64::from(shorter))
+            }
+            _ => 0,
+        }
+    }
+}
+
+// Editions known to be under the size limit come before the ones of unknown
+// size.
+pub struct SizeRanker;
+
+impl CandidateRanker for SizeRanker {
+    fn score(&self, candidate: &LibgenMetadata, ctx: &RankingContext) -> i64 {
+        match (ctx.max_size, candidate.filesize) {
+            (Some(max_size), Some(filesize)) => i64::from(filesize <= max_size),
+            _ => 0,
+        }
+    }
+}
+
+// Newer editions first. Editions without a year come last.
+pub struct YearRanker;
+
+impl CandidateRanker for YearRanker {
+    fn score(&self, candidate: &LibgenMetadata, _: &RankingContext) -> i64 {
+        parse_year(&candidate.year).map(i64::from).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(extension: Extension, year: &str) -> LibgenMetadata {
+        LibgenMetadata {
+            title: "Pride and Prejudice".to_string(),
+            author: "Jane Austen".to_string(),
+            year: year.to_string(),
+            extension,
+            md5: String::new(),
+            filesize: None,
+            language: None,
+            pages: None,
+            publisher: None,
+        }
+    }
+
+    #[test]
+    fn test_format_ranker() {
+        let ctx = RankingContext::default();
+        let score = |extension: Extension| FormatRanker.score(&book(extension, ""), &ctx);
+
+        assert!(score(Extension::Mobi) > score(Extension::Epub));
+        assert!(score(Extension::Pdf) > score(Extension::Djvu));
+        assert!(score(Extension::Djvu) > score(Extension::Doc));
+        assert!(score(Extension::Docx) > score(Extension::Doc));
+
+        let ctx = RankingContext {
+            wanted_extension: Some(Extension::Djvu),
+            ..RankingContext::default()
+        };
+        assert!(
+            FormatRanker.score(&book(Extension::Djvu, ""), &ctx)
+                > FormatRanker.score(&book(Extension::Pdf, ""), &ctx)
+        );
+    }
+
+    #[test]
+    fn test_language_and_size_rankers() {
+        let ctx = RankingContext {
+            language: Some("english".to_string()),
+            max_size: Some(1024),
+            ..RankingContext::default()
+        };
+        let english = LibgenMetadata {
+            language: Some("English, French".to_string()),
+            filesize: Some(512),
+            ..book(Extension::Epub, "2000")
+        };
+        let unknown = book(Extension::Epub, "2000");
+
+        assert_eq!(1, LanguageRanker.score(&english, &ctx));
+        assert_eq!(0, LanguageRanker.score(&unknown, &ctx));
+        assert_eq!(1, SizeRanker.score(&english, &ctx));
+        assert_eq!(0, SizeRanker.score(&unknown, &ctx));
+
+        // Without a preference, nothing changes.
+        let ctx = RankingContext::default();
+        assert_eq!(0, LanguageRanker.score(&english, &ctx));
+        assert_eq!(0, SizeRanker.score(&english, &ctx));
+    }
+
+    #[test]
+    fn test_pages_ranker() {
+        let ctx = RankingContext {
+            pages: Some(900),
+            ..RankingContext::default()
+        };
+        let score = |pages: Option<u32>| {
+            let candidate = LibgenMetadata {
+                pages,
+                ..book(Extension::Epub, "2000")
+            };
+            PagesRanker.score(&candidate, &ctx)
+        };
+
+        assert_eq!(0, score(Some(900)));
+        assert_eq!(0, score(Some(640)));
+        assert_eq!(0, score(Some(1200)));
+        assert_eq!(0, score(None));
+        assert_eq!(-1, score(Some(300)));
+        assert_eq!(-1, score(Some(1800)));
+
+        // Without the Goodreads page count, nothing changes.
+        let candidate = LibgenMetadata {
+            pages: Some(300),
+            ..book(Extension::Epub, "2000")
+        };
+        assert_eq!(0, PagesRanker.score(&candidate, &RankingContext::default()));
+    }
+
+    #[test]
+    fn test_default_ranker_signal_order() {
+        let ranker = DefaultRanker::default();
+        let ctx = RankingContext {
+            language: Some("english".to_string()),
+            ..RankingContext::default()
+        };
+        let score = |book: &LibgenMetadata| ranker.score(book, &ctx);
+        let old_english = LibgenMetadata {
+            language: Some("English".to_string()),
+            ..book(Extension::Epub, "1813")
+        };
+
+        // The format outweighs the language, which outweighs the year.
+        assert!(score(&book(Extension::Mobi, "1813")) > score(&old_english));
+        assert!(score(&old_english) > score(&book(Extension::Epub, "2020")));
+
+        // The number of pages outweighs the year.
+        let ctx = RankingContext {
+            pages: Some(900),
+            ..RankingContext::default()
+        };
+        let abridged = LibgenMetadata {
+            pages: Some(300),
+            ..book(Extension::Epub, "2020")
+        };
+        let unabridged = LibgenMetadata {
+            pages: Some(912),
+            ..book(Extension::Epub, "1813")
+        };
+        assert!(ranker.score(&unabridged, &ctx) > ranker.score(&abridged, &ctx));
+        assert!(score(&book(Extension::Epub, "2020")) > score(&book(Extension::Epub, "")));
+    }
+
+    struct Fixed(i64);
+
+    impl CandidateRanker for Fixed {
+        fn score(&self, _: &LibgenMetadata, _: &RankingContext) -> i64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_composite_ranker() {
+        let ctx = RankingContext::default();
+        let candidate = book(Extension::Epub, "2000");
+
+        assert_eq!(0, CompositeRanker::default().score(&candidate, &ctx));
+        assert_eq!(
+            23,
+            CompositeRanker::default()
+                .with(10, Fixed(2))
+                .with(3, Fixed(1))
+                .score(&candidate, &ctx)
+        );
+        assert_eq!(
+            i64::MAX,
+            CompositeRanker::default()
+                .with(i64::MAX, Fixed(2))
+                .with(1, Fixed(1))
+                .score(&candidate, &ctx)
+        );
+    }
+
+    // Scores only depend on the candidate and the context: the same inputs
+    // always give the same score, whatever was scored before.
+    #[test]
+    fn test_scoring_is_deterministic() {
+        let extensions = [
+            Extension::Mobi,
+            Extension::Epub,
+            Extension::Djvu,
+            Extension::Pdf,
+            Extension::Cbz,
+            Extension::Other(String::new()),
+        ];
+        let years = ["", "1813", "c1998", "2003-2005", "garbage"];
+        let languages = [None, Some("English"), Some("french")];
+        let filesizes = [None, Some(0), Some(1024), Some(u64::MAX)];
+        let pages = [None, Some(1), Some(320), Some(u32::MAX)];
+        let mut candidates = vec![];
+        for extension in &extensions {
+            for year in years {
+                for language in languages {
+                    for filesize in filesizes {
+                        for pages in pages {
+                            candidates.push(LibgenMetadata {
+                                language: language.map(str::to_string),
+                                filesize,
+                                pages,
+                                ..book(extension.clone(), year)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        let contexts = [
+            RankingContext::default(),
+            RankingContext {
+                wanted_extension: Some(Extension::Djvu),
+                language: Some("english".to_string()),
+                max_size: Some(1024),
+                pages: Some(320),
+            },
+        ];
+
+        let ranker = DefaultRanker::default();
+        for ctx in &contexts {
+            let scores: Vec<i64> = candidates.iter().map(|c| ranker.score(c, ctx)).collect();
+            let reversed: Vec<i64> = candidates
+                .iter()
+                .rev()
+                .map(|c| DefaultRanker::default().score(c, ctx))
+                .collect();
+            assert_eq!(
+                scores,
+                reversed.into_iter().rev().collect::<Vec<_>>(),
+                "{:?}",
+                ctx
+            );
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Module request_log logs every request the server answers: its method,
+//! path, status, duration, the bytes sent, and the error when there was one.
+//!
+//! Each request gets an id, sent back in the `X-Request-Id` header for users
+//! to quote. Everything logged while answering it, including the download
+//! and conversion streamed afterwards, is in a `request` span with that id.
+
+use crate::web;
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    web::Bytes,
+};
+use regex::Regex;
+use std::{
+    pin::Pin,
+    sync::LazyLock,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::Instrument;
+
+pub const X_REQUEST_ID: &str = "X-Request-Id";
+// Paths are cut after this many characters.
+const MAX_LOGGED_PATH_LENGTH: usize = 120;
+// The download and info paths of Goodreads URLs, see `normalise_path`.
+static GOODREADS_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(/api/(?:download|info)/)(?:[^/]*?book(?:/|%2F)show(?:/|%2F))?(\d+)[^/]*$")
+        .unwrap()
+});
+
+// Identifies the request, and logs it once its body is sent, or the client
+// hung up. The query string isn't logged: it can hold the API token.
+pub async fn log_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<LoggedBody>, actix_web::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let mut record = RequestRecord {
+        id: id.clone(),
+        method: req.method().to_string(),
+        path: normalise_path(req.path()),
+        start: Instant::now(),
+        status: 0,
+        error: None,
+    };
+    let span = tracing::info_span!(
+        "request",
+        request_id = %record.id,
+        method = %record.method,
+        path = %record.path,
+    );
+
+    let mut res = next.call(req).instrument(span.clone()).await?;
+
+    record.status = res.status().as_u16();
+    record.error = res
+        .response()
+        .error()
+        .and_then(|err| err.as_error::<web::Error>())
+        .map(|err| err.name().to_string());
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static("x-request-id"), value);
+    }
+
+    Ok(res.map_body(|_, body| LoggedBody {
+        body: body.boxed(),
+        bytes: 0,
+        record: Some(record),
+        span,
+    }))
+}
+
+struct RequestRecord {
+    id: String,
+    method: String,
+    path: String,
+    start: Instant,
+    status: u16,
+    // The kind of `web::Error` returned, e.g. "not found".
+    error: Option<String>,
+}
+
+// The response body, counting the bytes sent. The request is logged when it
+// is dropped, i.e. once it was sent or given up on.
+pub struct LoggedBody {
+    body: BoxBody,
+    bytes: u64,
+    record: Option<RequestRecord>,
+    span: tracing::Span,
+}
+
+impl MessageBody for LoggedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = &mut *self;
+        let _entered = this.span.enter();
+        let poll = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            this.bytes += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        let Some(record) = self.record.take() else {
+            return;
+        };
+        tracing::info!(
+            request_id = %record.id,
+            method = %record.method,
+            path = %record.path,
+            status = record.status,
+            duration_ms = record.start.elapsed().as_millis() as u64,
+            bytes = self.bytes,
+            error = record.error.as_deref(),
+            "{} {} {}",
+            record.method,
+            record.path,
+            record.status,
+        );
+    }
+}
+
+// Goodreads URLs are long, and only their book id matters: it is logged
+// instead of them, e.g. "/api/download/1048424". Other paths are cut.
+fn normalise_path(path: &str) -> String {
+    let path = GOODREADS_URL.replace(path, "$1$2");
+
+    match path.char_indices().nth(MAX_LOGGED_PATH_LENGTH) {
+        Some((end, _)) => format!("{}…", &path[..end]),
+        None => path.into_owned(),
+    }
+}
+
+#[test]
+fn test_normalise_path() {
+    for (path, want) in [
+        ("/healthz", "/healthz"),
+        ("/api/download/1048424", "/api/download/1048424"),
+        (
+            "/api/download/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons",
+            "/api/download/1048424",
+        ),
+        (
+            "/api/info/https:%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F40961427-1984",
+            "/api/info/40961427",
+        ),
+        (
+            "/api/download/https%3A%2F%2Fwww.goodreads.com%2Fwork%2Feditions%2F153313",
+            "/api/download/https%3A%2F%2Fwww.goodreads.com%2Fwork%2Feditions%2F153313",
+        ),
+        ("/api/library/AB13556B96D473C8DFAD7165C4704526", "/api/library/AB13556B96D473C8DFAD7165C4704526"),
+    ] {
+        assert_eq!(want, normalise_path(path), "{:?}", path);
+    }
+
+    let long = format!("/api/download/{}", "a".repeat(MAX_LOGGED_PATH_LENGTH));
+    let got = normalise_path(&long);
+    assert_eq!(MAX_LOGGED_PATH_LENGTH + 1, got.chars().count());
+    assert!(got.ends_with("a…"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware::from_fn, test, App, HttpResponse};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tracing_subscriber::{layer::SubscriberExt, registry::LookupSpan, Layer};
+
+    type Fields = HashMap<String, String>;
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    // Records the fields of every event, with the fields of the spans it is
+    // in.
+    #[derive(Clone, Default)]
+    struct CapturingLayer {
+        events: Arc<Mutex<Vec<Fields>>>,
+    }
+
+    impl<S> Layer<S> for CapturingLayer
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            ctx.span(id).unwrap().extensions_mut().insert(fields);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields::new();
+            if let Some(scope) = ctx.event_scope(event) {
+                for span in scope.from_root() {
+                    if let Some(span_fields) = span.extensions().get::<Fields>() {
+                        fields.extend(span_fields.clone());
+                    }
+                }
+            }
+            event.record(&mut FieldVisitor(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+    }
+
+    async fn found() -> HttpResponse {
+        tracing::info!(step = "lookup", "looking the book up");
+        HttpResponse::Ok().body("found")
+    }
+
+    // Logs from the download pipeline, as a missing book can't be sampled.
+    async fn checking_language() -> HttpResponse {
+        let book = std::path::Path::new("missing/Alice.epub");
+        crate::language_check::check(book, &crate::extension::Extension::Epub, "en");
+        HttpResponse::Ok().finish()
+    }
+
+    async fn failing() -> Result<HttpResponse, web::Error> {
+        Err(std::io::Error::other("disk full").into())
+    }
+
+    async fn call(uri: &str) -> (String, Bytes, Vec<Fields>) {
+        let layer = CapturingLayer::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer.clone()));
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(log_request))
+                .route("/api/info/{goodreads_url}", actix_web::web::get().to(found))
+                .route("/api/language", actix_web::web::get().to(checking_language))
+                .route(
+                    "/api/download/{goodreads_url}",
+                    actix_web::web::get().to(failing),
+                ),
+        )
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        let id = resp
+            .headers()
+            .get(X_REQUEST_ID)
+            .expect("the response should have a request id")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = test::read_body(resp).await;
+
+        let events = layer.events.lock().unwrap().clone();
+        (id, body, events)
+    }
+
+    fn request_event(events: &[Fields]) -> &Fields {
+        events
+            .iter()
+            .find(|event| event.contains_key("status"))
+            .expect("the request should be logged")
+    }
+
+    #[actix_web::test]
+    async fn test_log_request() {
+        let (id, body, events) = call(
+            "/api/info/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons?token=s3cr3t",
+        )
+        .await;
+
+        assert!(uuid::Uuid::parse_str(&id).is_ok(), "{:?}", id);
+        let logged = request_event(&events);
+        assert_eq!(Some(&id), logged.get("request_id"));
+        assert_eq!("GET", logged["method"]);
+        assert_eq!("/api/info/1048424", logged["path"]);
+        assert_eq!("200", logged["status"]);
+        assert_eq!(body.len().to_string(), logged["bytes"]);
+        assert!(logged.contains_key("duration_ms"));
+        assert!(!logged.contains_key("error"));
+        assert!(!logged["message"].contains("s3cr3t"));
+
+        // Events logged while answering are in the request's span.
+        let lookup = events
+            .iter()
+            .find(|event| event.get("step").map(String::as_str) == Some("lookup"))
+            .expect("the handler should log");
+        assert_eq!(Some(&id), lookup.get("request_id"));
+    }
+
+    #[actix_web::test]
+    async fn test_log_request_error() {
+        let (id, _, events) = call("/api/download/1048424").await;
+
+        let logged = request_event(&events);
+        assert_eq!(Some(&id), logged.get("request_id"));
+        assert_eq!("500", logged["status"]);
+        assert_eq!("i/o", logged["error"]);
+    }
+
+    #[actix_web::test]
+    async fn test_log_request_pipeline() {
+        let (id, _, events) = call("/api/language").await;
+
+        let warning = events
+            .iter()
+            .find(|event| event["message"].contains("Alice.epub"))
+            .expect("the language check should log");
+        assert_eq!(Some(&id), warning.get("request_id"));
+        assert_eq!("/api/language", warning["path"]);
+    }
+
+    #[actix_web::test]
+    async fn test_request_ids_are_unique() {
+        let (first, _, _) = call("/api/download/1048424").await;
+        let (second, _, _) = call("/api/download/1048424").await;
+
+        assert_ne!(first, second);
+    }
+}
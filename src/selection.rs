@@ -0,0 +1,439 @@
+//! Module selection narrows down the editions found on LibGen before they are
+//! ranked, with filters given per request, e.g.
+//! `?exclude=pdf,djvu&max_size_mb=50&lang=en&min_year=2010`.
+//!
+//! Filters are optional and combine: an edition must pass all of them. When
+//! LibGen doesn't know an edition's size, language or year, the matching
+//! filter lets it through.
+
+use crate::{
+    extension::Extension,
+    libgen::{parse_year, LibgenMetadata},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SelectionFilters {
+    pub exclude: Vec<Extension>,
+    // In bytes.
+    pub max_size: Option<u64>,
+    // Lowercased language name, as LibGen spells it, e.g. "english".
+    pub language: Option<String>,
+    pub min_year: Option<u16>,
+}
+
+// A filter of `SelectionFilters`, named after its query parameter.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Exclude,
+    MaxSizeMb,
+    Lang,
+    MinYear,
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Filter::Exclude => "exclude",
+            Filter::MaxSizeMb => "max_size_mb",
+            Filter::Lang => "lang",
+            Filter::MinYear => "min_year",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// LibGen spells languages out. Other names are matched as they are.
+const LANGUAGE_CODES: &[(&str, &str)] = &[
+    ("de", "german"),
+    ("en", "english"),
+    ("es", "spanish"),
+    ("fr", "french"),
+    ("it", "italian"),
+    ("ja", "japanese"),
+    ("nl", "dutch"),
+    ("pl", "polish"),
+    ("pt", "portuguese"),
+    ("ru", "russian"),
+    ("uk", "ukrainian"),
+    ("zh", "chinese"),
+];
+
+// Whether a LibGen language, e.g. "English, French", includes `language`,
+// lowercased.
+pub(crate) fn is_in_language(book_language: &str, language: &str) -> bool {
+    book_language
+        .to_lowercase()
+        .split([',', ';', '/'])
+        .any(|part| part.trim() == language)
+}
+
+impl SelectionFilters {
+    // Parses the raw values of the filters, e.g. from query parameters. Any of
+    // them can be omitted.
+    pub fn parse(
+        exclude: Option<&str>,
+        max_size_mb: Option<&str>,
+        lang: Option<&str>,
+        min_year: Option<&str>,
+    ) -> Result<Self, Error> {
+        let invalid = |filter: Filter, value: &str, reason: &str| Error::InvalidValue {
+            filter,
+            value: value.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut filters = Self::default();
+
+        if let Some(value) = exclude {
+            for extension in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let Ok(parsed) = extension.parse::<Extension>();
+                if let Extension::Other(_) = parsed {
+                    return Err(invalid(Filter::Exclude, extension, "unknown format"));
+                }
+                filters.exclude.push(parsed);
+            }
+        }
+        if let Some(value) = max_size_mb {
+            let megabytes: u64 = value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|megabytes| *megabytes > 0)
+                .ok_or_else(|| invalid(Filter::MaxSizeMb, value, "expected a number of MB"))?;
+            filters.max_size = Some(megabytes * 1024 * 1024);
+        }
+        if let Some(value) = lang {
+            let language = value.trim().to_lowercase();
+            if language.is_empty() || !language.chars().all(char::is_alphabetic) {
+                return Err(invalid(Filter::Lang, value, "expected a language, e.g. en"));
+            }
+            filters.language = Some(
+                LANGUAGE_CODES
+                    .iter()
+                    .find(|(code, _)| *code == language)
+                    .map(|(_, name)| name.to_string())
+                    .unwrap_or(language),
+            );
+        }
+        if let Some(value) = min_year {
+            filters.min_year = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(Filter::MinYear, value, "expected a year"))?,
+            );
+        }
+
+        Ok(filters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Returns the first filter `book` doesn't pass, if any.
+    pub fn rejects(&self, book: &LibgenMetadata) -> Option<Filter> {
+        if self.exclude.contains(&book.extension) {
+            return Some(Filter::Exclude);
+        }
+        if let (Some(max_size), Some(filesize)) = (self.max_size, book.filesize) {
+            if filesize > max_size {
+                return Some(Filter::MaxSizeMb);
+            }
+        }
+        if let (Some(language), Some(book_language)) = (&self.language, &book.language) {
+            if !is_in_language(book_language, language) {
+                return Some(Filter::Lang);
+            }
+        }
+        if let (Some(min_year), Some(year)) = (self.min_year, parse_year(&book.year)) {
+            if year < min_year {
+                return Some(Filter::MinYear);
+            }
+        }
+        None
+    }
+
+    // Splits the books into the ones passing every filter and the ones left
+    // out, with the filter that rejected them.
+    pub fn apply(
+        &self,
+        books_metadata: Vec<LibgenMetadata>,
+    ) -> (Vec<LibgenMetadata>, Vec<(LibgenMetadata, Filter)>) {
+        let mut kept = vec![];
+        let mut rejected = vec![];
+        for book in books_metadata {
+            match self.rejects(&book) {
+                Some(filter) => rejected.push((book, filter)),
+                None => kept.push(book),
+            }
+        }
+        (kept, rejected)
+    }
+
+    // The filter that removed the last editions, when none passed the
+    // filters: the one applied last among those that rejected something.
+    pub fn emptied_by(rejected: &[(LibgenMetadata, Filter)]) -> Option<Filter> {
+        [
+            Filter::MinYear,
+            Filter::Lang,
+            Filter::MaxSizeMb,
+            Filter::Exclude,
+        ]
+        .into_iter()
+        .find(|filter| {
+            rejected
+                .iter()
+                .any(|(_, rejected_by)| rejected_by == filter)
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    InvalidValue {
+        filter: Filter,
+        value: String,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidValue {
+                filter,
+                value,
+                reason,
+            } => write!(f, "invalid {} {:?}: {}", filter, value, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(
+        extension: Extension,
+        filesize: Option<u64>,
+        language: &str,
+        year: &str,
+    ) -> LibgenMetadata {
+        LibgenMetadata {
+            title: "Governing the Commons".to_string(),
+            author: "Elinor Ostrom".to_string(),
+            year: year.to_string(),
+            extension,
+            md5: "5f3a1dc2e6d7e9a30b6e0f6f4d4c5ab1".to_string(),
+            filesize,
+            language: Some(language.to_string()).filter(|language| !language.is_empty()),
+            pages: None,
+            publisher: None,
+        }
+    }
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(SelectionFilters::default()),
+            SelectionFilters::parse(None, None, None, None)
+        );
+        assert!(SelectionFilters::default().is_empty());
+
+        assert_eq!(
+            Ok(SelectionFilters {
+                exclude: vec![Extension::Pdf, Extension::Djvu],
+                max_size: Some(50 * MB),
+                language: Some("english".to_string()),
+                min_year: Some(2010),
+            }),
+            SelectionFilters::parse(Some("pdf, DJVU,"), Some("50"), Some("EN"), Some(" 2010 "))
+        );
+        assert_eq!(
+            Ok(Some("esperanto".to_string())),
+            SelectionFilters::parse(None, None, Some("Esperanto"), None).map(|f| f.language)
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for (filters, want_filter) in [
+            (
+                SelectionFilters::parse(Some("pdf,rar"), None, None, None),
+                Filter::Exclude,
+            ),
+            (
+                SelectionFilters::parse(None, Some("50MB"), None, None),
+                Filter::MaxSizeMb,
+            ),
+            (
+                SelectionFilters::parse(None, Some("-1"), None, None),
+                Filter::MaxSizeMb,
+            ),
+            (
+                SelectionFilters::parse(None, Some("0"), None, None),
+                Filter::MaxSizeMb,
+            ),
+            (
+                SelectionFilters::parse(None, None, Some(""), None),
+                Filter::Lang,
+            ),
+            (
+                SelectionFilters::parse(None, None, Some("en-US"), None),
+                Filter::Lang,
+            ),
+            (
+                SelectionFilters::parse(None, None, None, Some("2010s")),
+                Filter::MinYear,
+            ),
+        ] {
+            assert!(
+                matches!(filters, Err(Error::InvalidValue { filter, .. }) if filter == want_filter),
+                "{:?}",
+                filters
+            );
+        }
+
+        assert_eq!(
+            "invalid max_size_mb \"big\": expected a number of MB",
+            SelectionFilters::parse(None, Some("big"), None, None)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_exclude() {
+        let filters = SelectionFilters {
+            exclude: vec![Extension::Pdf, Extension::Djvu],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some(Filter::Exclude),
+            filters.rejects(&book(Extension::Pdf, None, "", ""))
+        );
+        assert_eq!(
+            Some(Filter::Exclude),
+            filters.rejects(&book(Extension::Djvu, None, "", ""))
+        );
+        assert_eq!(None, filters.rejects(&book(Extension::Epub, None, "", "")));
+    }
+
+    #[test]
+    fn test_max_size() {
+        let filters = SelectionFilters {
+            max_size: Some(50 * MB),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some(Filter::MaxSizeMb),
+            filters.rejects(&book(Extension::Pdf, Some(51 * MB), "", ""))
+        );
+        assert_eq!(
+            None,
+            filters.rejects(&book(Extension::Pdf, Some(50 * MB), "", ""))
+        );
+        // Unknown sizes are let through.
+        assert_eq!(None, filters.rejects(&book(Extension::Pdf, None, "", "")));
+    }
+
+    #[test]
+    fn test_language() {
+        let filters = SelectionFilters::parse(None, None, Some("en"), None).unwrap();
+
+        for language in ["English", "english", "English, French", "French;English"] {
+            assert_eq!(
+                None,
+                filters.rejects(&book(Extension::Epub, None, language, "")),
+                "{}",
+                language
+            );
+        }
+        for language in ["French", "Englishish"] {
+            assert_eq!(
+                Some(Filter::Lang),
+                filters.rejects(&book(Extension::Epub, None, language, "")),
+                "{}",
+                language
+            );
+        }
+        assert_eq!(None, filters.rejects(&book(Extension::Epub, None, "", "")));
+    }
+
+    #[test]
+    fn test_min_year() {
+        let filters = SelectionFilters {
+            min_year: Some(2010),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some(Filter::MinYear),
+            filters.rejects(&book(Extension::Epub, None, "", "1990"))
+        );
+        assert_eq!(
+            None,
+            filters.rejects(&book(Extension::Epub, None, "", "c2010"))
+        );
+        assert_eq!(None, filters.rejects(&book(Extension::Epub, None, "", "")));
+    }
+
+    #[test]
+    fn test_apply() {
+        let filters =
+            SelectionFilters::parse(Some("pdf"), Some("5"), Some("en"), Some("2000")).unwrap();
+        let books = vec![
+            book(Extension::Pdf, Some(MB), "English", "2015"),
+            book(Extension::Epub, Some(10 * MB), "English", "2015"),
+            book(Extension::Epub, Some(MB), "French", "2015"),
+            book(Extension::Epub, Some(MB), "English", "1990"),
+            book(Extension::Epub, Some(MB), "English", "2015"),
+            book(Extension::Mobi, None, "", ""),
+        ];
+
+        let (kept, rejected) = filters.apply(books.clone());
+
+        assert_eq!(vec![books[4].clone(), books[5].clone()], kept);
+        assert_eq!(
+            vec![
+                Filter::Exclude,
+                Filter::MaxSizeMb,
+                Filter::Lang,
+                Filter::MinYear
+            ],
+            rejected
+                .iter()
+                .map(|(_, filter)| *filter)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(Filter::MinYear),
+            SelectionFilters::emptied_by(&rejected)
+        );
+
+        // No filters keep everything.
+        assert_eq!(
+            (books.clone(), vec![]),
+            SelectionFilters::default().apply(books)
+        );
+    }
+
+    #[test]
+    fn test_emptied_by() {
+        let filters = SelectionFilters::parse(Some("pdf"), None, Some("en"), None).unwrap();
+        let (kept, rejected) = filters.apply(vec![
+            book(Extension::Pdf, None, "English", ""),
+            book(Extension::Epub, None, "French", ""),
+        ]);
+
+        assert!(kept.is_empty());
+        assert_eq!(Some(Filter::Lang), SelectionFilters::emptied_by(&rejected));
+        assert_eq!(None, SelectionFilters::emptied_by(&[]));
+    }
+}
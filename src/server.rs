@@ -0,0 +1,263 @@
+//! Module server runs the HTTP server: the API, and the front-end, on the
+//! addresses of `Config::listen`, over HTTPS when `Config::tls` is set, and
+//! on those of `Config::http_listen` over plain HTTP.
+
+use crate::{
+    config::ListenAddress,
+    frontend, request_log, tls,
+    web::{self, LibReadsState},
+};
+use actix_web::{
+    dev::Server,
+    middleware::{from_fn, Compress},
+    web::scope,
+    App, HttpServer,
+};
+
+// Binds the server to every address of the configuration, and returns it
+// with the URLs it listens on. Ports are resolved, so binding to port 0
+// gives the port the OS picked. It fails, naming the address, when one of
+// them can't be bound. The API is mounted under `/api` with
+// `web::configure`, like any app embedding LibReads would, so it is never
+// shadowed by the front-end, whose client-side routes can be anything else.
+// `/healthz` and `/metrics` are at the root too, for probes. Unix domain
+// sockets are always plain HTTP. It fails before binding anything when the
+// certificate can't be loaded.
+pub fn start(state: LibReadsState) -> std::io::Result<(Server, Vec<String>)> {
+    let listen = state.config().listen.clone();
+    let http_listen = state.config().http_listen.clone();
+    let tls_config = match &state.config().tls {
+        Some(config) => Some(tls::server_config(config, &listen)?),
+        None => None,
+    };
+
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .wrap(Compress::default())
+            .wrap(web::cors(state.config()))
+            .wrap(from_fn(request_log::log_request))
+            .configure(|cfg| web::configure_probes(cfg, state.clone()))
+            .service(scope("/api").configure(|cfg| web::configure(cfg, state.clone())))
+            .configure(|cfg| frontend::configure(cfg, &state.config().frontend_dir))
+    });
+
+    let mut urls = vec![];
+    let addresses = listen
+        .iter()
+        .map(|address| (address, tls_config.as_ref()))
+        .chain(http_listen.iter().map(|address| (address, None)));
+    for (address, tls_config) in addresses {
+        let bound = match address {
+            ListenAddress::Tcp(socket_address) => {
+                // Bound here rather than by actix, to know the actual port.
+                std::net::TcpListener::bind(socket_address).and_then(|listener| {
+                    let local_address = listener.local_addr()?;
+                    match tls_config {
+                        Some(tls_config) => {
+                            urls.push(format!("https://{}", local_address));
+                            server.listen_rustls_0_23(listener, tls_config.clone())
+                        }
+                        None => {
+                            urls.push(format!("http://{}", local_address));
+                            server.listen(listener)
+                        }
+                    }
+                })
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                urls.push(address.to_string());
+                server.bind_uds(path)
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix domain sockets aren't supported on this platform",
+            )),
+        };
+        server = bound.map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!("could not listen on {}: {}", address, err),
+            )
+        })?;
+    }
+
+    Ok((server.run(), urls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        capabilities::ConverterCapabilities,
+        config::{Config, TlsConfig},
+        libreads::{BookService, MockBookService},
+    };
+    use actix_web::web::Data;
+    use std::{path::PathBuf, sync::Arc};
+
+    fn libreads() -> Data<dyn BookService> {
+        Data::from(Arc::new(MockBookService::new()) as Arc<dyn BookService>)
+    }
+
+    #[actix_web::test]
+    async fn test_start_on_any_port() {
+        let config = Config {
+            listen: vec![ListenAddress::Tcp("127.0.0.1:0".parse().unwrap())],
+            ..Config::default()
+        };
+
+        let (server, urls) = start(
+            LibReadsState::new(config, libreads())
+                .with_capabilities(ConverterCapabilities::new(None, false)),
+        )
+        .expect("the server should start");
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        assert_eq!(1, urls.len());
+        assert!(urls[0].starts_with("http://127.0.0.1:"), "{:?}", urls);
+        assert!(!urls[0].ends_with(":0"), "{:?}", urls);
+        let body = reqwest::get(format!("{}/healthz", urls[0]))
+            .await
+            .expect("the server should answer")
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!("ok", body["status"]);
+        assert_eq!(
+            serde_json::Value::Null,
+            body["converter"]["calibre_version"]
+        );
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_start_with_tls() {
+        let config = Config {
+            listen: vec![ListenAddress::Tcp("127.0.0.1:0".parse().unwrap())],
+            tls: Some(TlsConfig::Files {
+                cert: PathBuf::from("tests/testdata/tls_cert.pem"),
+                key: PathBuf::from("tests/testdata/tls_key.pem"),
+            }),
+            http_listen: vec![ListenAddress::Tcp("127.0.0.1:0".parse().unwrap())],
+            ..Config::default()
+        };
+
+        let (server, urls) = start(
+            LibReadsState::new(config, libreads())
+                .with_capabilities(ConverterCapabilities::new(None, false)),
+        )
+        .expect("the server should start");
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        assert_eq!(2, urls.len());
+        assert!(urls[0].starts_with("https://127.0.0.1:"), "{:?}", urls);
+        assert!(urls[1].starts_with("http://127.0.0.1:"), "{:?}", urls);
+        // The fixture certificate isn't trusted.
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        for url in &urls {
+            let response = client
+                .get(format!("{}/healthz", url))
+                .send()
+                .await
+                .expect("the server should answer");
+            assert_eq!(200, response.status().as_u16(), "{}", url);
+        }
+        // HTTPS addresses don't answer plain HTTP.
+        assert!(
+            reqwest::get(format!("{}/healthz", urls[0].replacen("https", "http", 1)))
+                .await
+                .is_err()
+        );
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_start_with_unreadable_certificate() {
+        let config = Config {
+            listen: vec![ListenAddress::Tcp("127.0.0.1:0".parse().unwrap())],
+            tls: Some(TlsConfig::Files {
+                cert: PathBuf::from("tests/testdata/missing.pem"),
+                key: PathBuf::from("tests/testdata/tls_key.pem"),
+            }),
+            ..Config::default()
+        };
+
+        let Err(err) = start(
+            LibReadsState::new(config, libreads())
+                .with_capabilities(ConverterCapabilities::new(None, false)),
+        ) else {
+            panic!("the certificate should be missing");
+        };
+
+        assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+        assert!(
+            err.to_string()
+                .starts_with("could not read tests/testdata/missing.pem: "),
+            "{}",
+            err
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_start_on_address_in_use() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = Config {
+            listen: vec![ListenAddress::Tcp(address)],
+            ..Config::default()
+        };
+
+        let Err(err) = start(
+            LibReadsState::new(config, libreads())
+                .with_capabilities(ConverterCapabilities::new(None, false)),
+        ) else {
+            panic!("the address should be in use");
+        };
+
+        assert_eq!(std::io::ErrorKind::AddrInUse, err.kind());
+        assert!(
+            err.to_string()
+                .starts_with(&format!("could not listen on {}: ", address)),
+            "{}",
+            err
+        );
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn test_start_on_unix_socket() {
+        let path = std::env::temp_dir().join("libreads_server_test.sock");
+        let _ = std::fs::remove_file(&path);
+        let config = Config {
+            listen: vec![
+                ListenAddress::Tcp("127.0.0.1:0".parse().unwrap()),
+                ListenAddress::Unix(path.clone()),
+            ],
+            ..Config::default()
+        };
+
+        let (server, urls) = start(
+            LibReadsState::new(config, libreads())
+                .with_capabilities(ConverterCapabilities::new(None, false)),
+        )
+        .expect("the server should start");
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        assert_eq!(2, urls.len());
+        assert!(urls[0].starts_with("http://127.0.0.1:"), "{:?}", urls);
+        assert_eq!(format!("unix:{}", path.display()), urls[1]);
+        assert!(path.exists());
+        handle.stop(false).await;
+        let _ = std::fs::remove_file(&path);
+    }
+}
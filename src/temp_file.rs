@@ -0,0 +1,110 @@
+//! Module temp_file deletes the books LibReads downloads and converts once
+//! they have been served, whichever way serving them ends.
+
+use std::path::{Path, PathBuf};
+
+// Owns a temporary file, and deletes it when closed or dropped. Responses
+// hold it until their body is fully sent, so a slow client is never cut
+// short, and a client hanging up or an error doesn't leave the file behind.
+#[derive(Debug)]
+pub struct TempFileGuard {
+    path: Option<PathBuf>,
+}
+
+impl TempFileGuard {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or_else(|| Path::new(""))
+    }
+
+    // Deletes the file now. A file that was already deleted is not an error.
+    pub async fn close(mut self) -> std::io::Result<()> {
+        match self.path.take() {
+            Some(path) => match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TempFileGuard {
+    // Deleting can't be awaited here: it is spawned on the runtime, or done
+    // right away outside of one.
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    let _ = tokio::fs::remove_file(&path).await;
+                });
+            }
+            Err(_) => {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, "book").unwrap();
+        path
+    }
+
+    // Deletion is spawned when dropped: waits for it to happen.
+    async fn wait_until_deleted(path: &Path) -> bool {
+        for _ in 0..100 {
+            if !path.exists() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let path = temp_file("libreads_temp_file_close.epub");
+        let guard = TempFileGuard::new(&path);
+        assert_eq!(path, guard.path());
+
+        guard.close().await.expect("Should delete the file");
+        assert!(!path.exists());
+
+        // Already deleted.
+        TempFileGuard::new(&path)
+            .close()
+            .await
+            .expect("Should ignore missing files");
+    }
+
+    #[tokio::test]
+    async fn test_drop() {
+        let path = temp_file("libreads_temp_file_drop.epub");
+        drop(TempFileGuard::new(&path));
+
+        assert!(wait_until_deleted(&path).await);
+    }
+
+    #[test]
+    fn test_drop_outside_of_a_runtime() {
+        let path = temp_file("libreads_temp_file_drop_sync.epub");
+        drop(TempFileGuard::new(&path));
+
+        assert!(!path.exists());
+    }
+}
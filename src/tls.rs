@@ -0,0 +1,196 @@
+//! Module tls loads the certificate the server serves HTTPS with, see
+//! `config::TlsConfig`.
+//!
+//! Certificates are PEM files: the certificate chain, leaf first, and its
+//! private key, in PKCS#8, PKCS#1 or SEC1. For LAN use, where there is no
+//! domain to get a certificate for, `--tls-self-signed` generates one at
+//! each start instead. Browsers warn about it until it is trusted.
+
+use crate::config::{ListenAddress, TlsConfig};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    ServerConfig,
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    // The file can't be opened or read.
+    Read { path: PathBuf, err: std::io::Error },
+    // The file was read, but isn't what it should be.
+    Invalid { path: PathBuf, reason: String },
+    SelfSigned(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Read { path, err } => write!(f, "could not read {}: {}", path.display(), err),
+            Error::Invalid { path, reason } => write!(f, "invalid {}: {}", path.display(), reason),
+            Error::SelfSigned(reason) => {
+                write!(
+                    f,
+                    "could not generate a self-signed certificate: {}",
+                    reason
+                )
+            }
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match &err {
+            Error::Read { err, .. } => err.kind(),
+            _ => std::io::ErrorKind::InvalidInput,
+        };
+        std::io::Error::new(kind, err.to_string())
+    }
+}
+
+// The rustls configuration of the server. Self-signed certificates are
+// valid for localhost and the IP addresses the server listens on.
+pub fn server_config(tls: &TlsConfig, listen: &[ListenAddress]) -> Result<ServerConfig, Error> {
+    match tls {
+        TlsConfig::Files { cert, key } => load(cert, key),
+        TlsConfig::SelfSigned => {
+            let mut names = vec!["localhost".to_string()];
+            for address in listen {
+                if let ListenAddress::Tcp(address) = address {
+                    let ip = address.ip();
+                    if !ip.is_unspecified() && !names.contains(&ip.to_string()) {
+                        names.push(ip.to_string());
+                    }
+                }
+            }
+            self_signed(names)
+        }
+    }
+}
+
+// Loads a certificate chain and its private key from PEM files.
+pub fn load(cert: &Path, key: &Path) -> Result<ServerConfig, Error> {
+    let read = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| Error::Read {
+                path: path.to_path_buf(),
+                err,
+            })
+    };
+    let invalid = |path: &Path, reason: String| Error::Invalid {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let certs = rustls_pemfile::certs(&mut read(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(cert, err.to_string()))?;
+    if certs.is_empty() {
+        return Err(invalid(cert, "no PEM certificate found".to_string()));
+    }
+    let private_key = rustls_pemfile::private_key(&mut read(key)?)
+        .map_err(|err| invalid(key, err.to_string()))?
+        .ok_or_else(|| invalid(key, "no PEM private key found".to_string()))?;
+
+    // A key that doesn't match the certificate is reported as invalid.
+    build(certs, private_key).map_err(|err| invalid(key, err.to_string()))
+}
+
+// Generates a certificate for `names`, host names or IP addresses.
+pub fn self_signed(names: Vec<String>) -> Result<ServerConfig, Error> {
+    let generated = rcgen::generate_simple_self_signed(names)
+        .map_err(|err| Error::SelfSigned(err.to_string()))?;
+    let private_key =
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()));
+    build(vec![generated.cert.der().clone()], private_key)
+        .map_err(|err| Error::SelfSigned(err.to_string()))
+}
+
+// The ring provider is picked explicitly: other dependencies may enable
+// another one, and rustls wouldn't know which to use by default.
+fn build(
+    certs: Vec<CertificateDer<'static>>,
+    private_key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig, rustls::Error> {
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "tests/testdata/tls_cert.pem";
+    const KEY: &str = "tests/testdata/tls_key.pem";
+    // A valid key, for another certificate.
+    const OTHER_KEY: &str = "tests/testdata/tls_other_key.pem";
+
+    #[test]
+    fn test_load() {
+        assert!(load(Path::new(CERT), Path::new(KEY)).is_ok());
+        assert!(server_config(
+            &TlsConfig::Files {
+                cert: PathBuf::from(CERT),
+                key: PathBuf::from(KEY),
+            },
+            &[]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_load_unreadable() {
+        let missing = Path::new("tests/testdata/missing.pem");
+        for (cert, key) in [(missing, Path::new(KEY)), (Path::new(CERT), missing)] {
+            match load(cert, key) {
+                Err(err @ Error::Read { .. }) => assert!(
+                    err.to_string()
+                        .starts_with("could not read tests/testdata/missing.pem: "),
+                    "{}",
+                    err
+                ),
+                other => panic!("unexpected {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        for (cert, key, invalid) in [
+            // Keys aren't certificates, and the other way around.
+            (KEY, KEY, KEY),
+            (CERT, CERT, CERT),
+            (CERT, OTHER_KEY, OTHER_KEY),
+            (
+                "tests/testdata/dummy_ebook.epub",
+                KEY,
+                "tests/testdata/dummy_ebook.epub",
+            ),
+        ] {
+            match load(Path::new(cert), Path::new(key)) {
+                Err(Error::Invalid { path, .. }) => {
+                    assert_eq!(Path::new(invalid), path, "{} {}", cert, key)
+                }
+                other => panic!("unexpected {:?} for {} {}", other.map(|_| ()), cert, key),
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_signed() {
+        let listen = [
+            ListenAddress::Tcp("0.0.0.0:8001".parse().unwrap()),
+            ListenAddress::Tcp("192.168.1.2:8443".parse().unwrap()),
+            ListenAddress::Unix(PathBuf::from("/run/libreads.sock")),
+        ];
+        assert!(server_config(&TlsConfig::SelfSigned, &listen).is_ok());
+    }
+}
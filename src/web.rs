@@ -1,261 +1,6326 @@
 //! Module http contains the web server exposing LibReads over an HTTP API.
 
 use crate::{
-    convert::{self, download_as},
+    archive::{self, ArchiveFormat},
+    book_query::BookQuery,
+    book_ref::BookRef,
+    capabilities::{ConverterCapabilities, UnsupportedConversion},
+    circuit_breaker::CircuitBreakers,
+    config::Config,
+    convert::{self, output_filename, ConversionOptions, ConversionResult, InputBookInfo},
+    device::{self, OutputSettings},
     extension::Extension,
-    libreads::{self, LibReads},
+    goodreads::{BookIdentification, ShelfEntry},
+    history::{self, Attempt, BookRequest, History, Outcome, Stats},
+    http_client::{HttpErrorKind, HttpFailure},
+    import::{self, ImportedBooks},
+    kindle,
+    libgen::{FilterReport, LibgenMetadata},
+    library::LibraryEntry,
+    library_dot_lol::DownloadLinks,
+    libreads::{self, BookInfo, BookService, Timings},
+    maintenance::{self, CleanupReport, Maintenance},
+    name_template::NameTemplate,
+    openapi, output_path,
+    parse_check::{ParseChecker, ParseReport},
+    prober::UpstreamProber,
+    queue::{self, Queue, QueueItem, QueueRequest},
+    selection::SelectionFilters,
+    temp_file::TempFileGuard,
 };
 
+use actix_cors::Cors;
 use actix_web::{
+    body::{EitherBody, MessageBody, SizedStream},
+    dev::{ServiceRequest, ServiceResponse},
     error,
-    http::header::{ContentDisposition, DispositionParam, DispositionType, CONTENT_TYPE},
-    web, HttpResponse, Result,
+    http::header::{
+        ContentDisposition, ContentEncoding, DispositionParam, DispositionType, HeaderName, ACCEPT,
+        AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE, RETRY_AFTER, VARY,
+    },
+    middleware::{from_fn, Next},
+    web, HttpRequest, HttpResponse, HttpResponseBuilder, Result,
 };
+use futures_util::{future::LocalBoxFuture, stream, Stream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+use utoipa::{IntoParams, ToSchema};
+
+const X_AVAILABLE_FORMATS: &str = "X-Available-Formats";
+const X_LIBREADS_MD5: &str = "X-LibReads-MD5";
+const X_LIBREADS_SOURCE: &str = "X-LibReads-Source";
+const X_LIBREADS_ORIGINAL_EXTENSION: &str = "X-LibReads-Original-Extension";
+// Set when the book seems to be in another language than `lang`, and no
+// edition in that language was found, see `language_check`.
+const X_LIBREADS_WARNING: &str = "X-LibReads-Warning";
+const SERVER_TIMING: &str = "Server-Timing";
+// `?format=original` serves the book as it was uploaded, unconverted.
+const ORIGINAL_FORMAT: &str = "original";
+// How many candidates a dry run returns by default, and at most, see
+// `candidates_page`.
+const DEFAULT_CANDIDATES_LIMIT: usize = 50;
+const MAX_CANDIDATES_LIMIT: usize = 500;
+// How long clients are asked to wait when a rate-limited upstream didn't say.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+// How many books `POST /api/queue` takes at once.
+const MAX_ENQUEUED_URLS: usize = 100;
+
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    // Wanted output extension, e.g. "epub". Takes precedence over `device`.
+    // Several comma-separated formats, e.g. "epub,pdf,original", are served
+    // as a ZIP, see `archive`.
+    pub format: Option<String>,
+    // Target e-reader, see `device::Device`.
+    pub device: Option<String>,
+    // Reports how the edition was selected, as JSON, instead of downloading
+    // it.
+    #[serde(default)]
+    pub dry_run: bool,
+    // The page of candidates a dry run returns: how many to skip, and how
+    // many to return, see `candidates_page`.
+    pub offset: Option<String>,
+    pub limit: Option<String>,
+    // Where to deliver the book instead of returning it: only "kindle", see
+    // `kindle`.
+    pub deliver: Option<String>,
+    // Selection filters, see `selection::SelectionFilters`: comma-separated
+    // extensions to leave out, e.g. "pdf,djvu", a maximum size in MB, a
+    // language, e.g. "en", and the oldest publication year.
+    pub exclude: Option<String>,
+    pub max_size_mb: Option<String>,
+    pub lang: Option<String>,
+    pub min_year: Option<String>,
+    // How to name the book, e.g. "{author} - {title}", see `name_template`.
+    // Takes precedence over `LIBREADS_NAME_TEMPLATE`.
+    pub name_template: Option<String>,
+    // Only for downloads by MD5: how to name the book, instead of asking
+    // LibGen.
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+// Returns the name template of the query, or the configured one.
+fn name_template(query: &DownloadQuery, config: &Config) -> Result<Option<NameTemplate>, Error> {
+    match &query.name_template {
+        Some(template) => template.parse().map(Some).map_err(|err| Error {
+            name: "invalid input".to_string(),
+            message: format!("invalid name template {:?}: {}", template, err),
+            details: None,
+            retry_after: None,
+        }),
+        None => Ok(config.name_template.clone()),
+    }
+}
+
+// Parses the selection filters of the query.
+fn selection_filters(query: &DownloadQuery) -> Result<SelectionFilters, Error> {
+    SelectionFilters::parse(
+        query.exclude.as_deref(),
+        query.max_size_mb.as_deref(),
+        query.lang.as_deref(),
+        query.min_year.as_deref(),
+    )
+    .map_err(|err| Error {
+        name: "invalid input".to_string(),
+        message: err.to_string(),
+        details: None,
+        retry_after: None,
+    })
+}
+
+// Acknowledges a book emailed to a Kindle with `?deliver=kindle`.
+#[derive(Debug, Serialize)]
+struct DeliveryResponse {
+    delivered_to: String,
+    filename: String,
+    bytes: u64,
+    md5: String,
+    extension: Extension,
+    // See `X_LIBREADS_WARNING`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+// Returns whether the book must be sent to a Kindle.
+fn deliver_to_kindle(query: &DownloadQuery) -> Result<bool, Error> {
+    match query.deliver.as_deref().map(str::trim) {
+        None => Ok(false),
+        Some(deliver) if deliver.eq_ignore_ascii_case("kindle") => Ok(true),
+        Some(deliver) => Err(Error {
+            name: "invalid input".to_string(),
+            message: format!("unknown delivery {:?}, expected: kindle", deliver),
+            details: None,
+            retry_after: None,
+        }),
+    }
+}
+
+// The response starts as soon as the book is found: headers are sent right
+// away, and the body is streamed once the download and conversion are done.
+// A failure after the headers were sent aborts the response, so clients see
+// an incomplete transfer rather than a truncated book.
+#[utoipa::path(
+    get,
+    path = "/download/{goodreads_url}",
+    tag = "books",
+    summary = "Download a book, converted to the wanted format",
+    params(
+        ("goodreads_url" = String, Path, description = "A URL-encoded Goodreads book URL, or a numeric Goodreads book ID"),
+        DownloadQuery,
+    ),
+    responses(
+        (status = 200, description = "The book, a ZIP of several formats, or JSON for dry runs and deliveries", content_type = "application/octet-stream"),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 404, description = "No edition found", body = ErrorBody),
+        (status = 422, description = "Unsupported conversion, or DRM-protected book", body = ErrorBody),
+        (status = 502, description = "An upstream failed", body = ErrorBody),
+    ),
+)]
+pub async fn download(
+    libreads: web::Data<dyn BookService>,
+    config: web::Data<Config>,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    history: Option<web::Data<History>>,
+    goodreads_url: web::Path<String>,
+    query: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, Error> {
+    let request = DownloadRequest {
+        url: goodreads_url.into_inner(),
+        query: query.into_inner(),
+    };
+    download_request(libreads, &config, capabilities, history, request).await
+}
+
+// Like `download`, with the Goodreads URL in a JSON body rather than an
+// encoded path segment, which some reverse proxies decode. See
+// `DownloadRequest`.
+#[utoipa::path(
+    post,
+    path = "/download",
+    tag = "books",
+    summary = "Download a book, with its options in a JSON body",
+    request_body = DownloadRequestBody,
+    responses(
+        (status = 200, description = "As for GET /download/{goodreads_url}", content_type = "application/octet-stream"),
+        (status = 400, description = "Invalid body, with the field at fault in its details", body = ErrorBody),
+        (status = 404, description = "No edition found", body = ErrorBody),
+        (status = 422, description = "Unsupported conversion, or DRM-protected book", body = ErrorBody),
+        (status = 502, description = "An upstream failed", body = ErrorBody),
+    ),
+)]
+pub async fn download_post(
+    libreads: web::Data<dyn BookService>,
+    config: web::Data<Config>,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    history: Option<web::Data<History>>,
+    body: web::Bytes,
+) -> Result<HttpResponse, Error> {
+    let request = DownloadRequest::from_json(&body)?;
+    download_request(libreads, &config, capabilities, history, request).await
+}
+
+async fn download_request(
+    libreads: web::Data<dyn BookService>,
+    config: &Config,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    history: Option<web::Data<History>>,
+    request: DownloadRequest,
+) -> Result<HttpResponse, Error> {
+    let options = conversion_options(&request.query, config, capabilities)?;
+    let original = original_format(&request.query, config);
+    resolve_download(
+        &libreads,
+        history,
+        &request.url,
+        &request.query,
+        original,
+        options,
+    )
+    .await?
+    .serve(libreads)
+    .await
+}
+
+// A download of a Goodreads book, with the options of `DownloadQuery`. The
+// JSON body of `POST /api/download` has the same fields, the selection
+// filters apart, e.g. `{"url": "https://www.goodreads.com/book/show/5470",
+// "format": "epub", "filters": {"lang": "en", "max_size_mb": 50}}`.
+#[derive(Debug, Default)]
+pub struct DownloadRequest {
+    pub url: String,
+    pub query: DownloadQuery,
+}
+
+// The JSON body `DownloadRequest::from_json` reads, only described in the
+// OpenAPI document. Numbers can be given as strings.
+#[derive(ToSchema)]
+#[schema(as = DownloadRequest)]
+#[allow(dead_code)]
+struct DownloadRequestBody {
+    url: String,
+    format: Option<String>,
+    device: Option<String>,
+    dry_run: Option<bool>,
+    offset: Option<u64>,
+    limit: Option<u64>,
+    deliver: Option<String>,
+    name_template: Option<String>,
+    filters: Option<DownloadFiltersBody>,
+}
+
+#[derive(ToSchema)]
+#[schema(as = DownloadFilters)]
+#[allow(dead_code)]
+struct DownloadFiltersBody {
+    exclude: Option<String>,
+    max_size_mb: Option<u64>,
+    lang: Option<String>,
+    min_year: Option<u32>,
+}
+
+type JsonObject = serde_json::Map<String, serde_json::Value>;
+
+impl DownloadRequest {
+    // Reads a JSON body. Errors name the field at fault in their `details`,
+    // e.g. `{"field": "filters.min_year"}`.
+    pub fn from_json(body: &[u8]) -> Result<Self, Error> {
+        let body: serde_json::Value = serde_json::from_slice(body)
+            .map_err(|err| invalid_field(None, format!("invalid JSON body: {}", err)))?;
+        let serde_json::Value::Object(mut fields) = body else {
+            return Err(invalid_field(None, "expected a JSON object".to_string()));
+        };
+        let mut filters: JsonObject = take_field(&mut fields, "filters")?.unwrap_or_default();
+        let url = take_field(&mut fields, "url")?
+            .ok_or_else(|| invalid_field(Some("url"), "url: missing field".to_string()))?;
+        let query = DownloadQuery {
+            format: take_field(&mut fields, "format")?,
+            device: take_field(&mut fields, "device")?,
+            dry_run: take_field(&mut fields, "dry_run")?.unwrap_or_default(),
+            offset: take_number(&mut fields, "offset")?,
+            limit: take_number(&mut fields, "limit")?,
+            deliver: take_field(&mut fields, "deliver")?,
+            name_template: take_field(&mut fields, "name_template")?,
+            exclude: take_field(&mut filters, "filters.exclude")?,
+            max_size_mb: take_number(&mut filters, "filters.max_size_mb")?,
+            lang: take_field(&mut filters, "filters.lang")?,
+            min_year: take_number(&mut filters, "filters.min_year")?,
+            ..DownloadQuery::default()
+        };
+
+        let unknown = fields.keys().next().cloned().or_else(|| {
+            filters
+                .keys()
+                .next()
+                .map(|name| format!("filters.{}", name))
+        });
+        if let Some(field) = unknown {
+            let message = format!("{}: unknown field", field);
+            return Err(invalid_field(Some(&field), message));
+        }
+        Ok(Self { url, query })
+    }
+}
+
+fn invalid_field(field: Option<&str>, message: String) -> Error {
+    Error {
+        name: "invalid input".to_string(),
+        message,
+        details: field.map(|field| serde_json::json!({ "field": field })),
+        retry_after: None,
+    }
+}
+
+// Removes the field at `path`, e.g. "filters.lang", from its object. Null
+// fields are missing.
+fn take_field<T: serde::de::DeserializeOwned>(
+    fields: &mut JsonObject,
+    path: &str,
+) -> Result<Option<T>, Error> {
+    let name = path.rsplit('.').next().unwrap_or(path);
+    match fields.remove(name) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(value) => serde_json::from_value(value)
+            .map(Some)
+            .map_err(|err| invalid_field(Some(path), format!("{}: {}", path, err))),
+    }
+}
+
+// Like `take_field`, for filters given as a number or a string.
+fn take_number(fields: &mut JsonObject, path: &str) -> Result<Option<String>, Error> {
+    match take_field(fields, path)? {
+        None => Ok(None),
+        Some(serde_json::Value::String(value)) => Ok(Some(value)),
+        Some(serde_json::Value::Number(value)) => Ok(Some(value.to_string())),
+        Some(value) => Err(invalid_field(
+            Some(path),
+            format!("{}: expected a number, got {}", path, value),
+        )),
+    }
+}
+
+// Parses the `offset` and `limit` of a dry run. The limit is between 1 and
+// `MAX_CANDIDATES_LIMIT`.
+fn candidates_page(query: &DownloadQuery) -> Result<(usize, usize), Error> {
+    let parse = |field: &str, value: &Option<String>, default: usize, min: usize| {
+        let Some(value) = value else {
+            return Ok(default);
+        };
+        value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|parsed| *parsed >= min)
+            .ok_or_else(|| {
+                invalid_field(
+                    Some(field),
+                    format!("invalid {} {:?}: expected a number", field, value),
+                )
+            })
+    };
+    let offset = parse("offset", &query.offset, 0, 0)?;
+    let limit = parse("limit", &query.limit, DEFAULT_CANDIDATES_LIMIT, 1)?;
+    if limit > MAX_CANDIDATES_LIMIT {
+        return Err(invalid_field(
+            Some("limit"),
+            format!("invalid limit {}: at most {}", limit, MAX_CANDIDATES_LIMIT),
+        ));
+    }
+    Ok((offset, limit))
+}
+
+// Reports how the edition is selected, a page of the candidates at a time.
+async fn dry_run(
+    libreads: &web::Data<dyn BookService>,
+    goodreads_url: &str,
+    wanted_extension: Option<&Extension>,
+    query: &DownloadQuery,
+    filters: &SelectionFilters,
+) -> Result<HttpResponse, Error> {
+    let (offset, limit) = candidates_page(query)?;
+    let report = libreads
+        .candidates(goodreads_url, wanted_extension, filters)
+        .await?;
+    Ok(HttpResponse::Ok().json(report.paginate(offset, limit)))
+}
+
+fn conversion_options(
+    query: &DownloadQuery,
+    config: &Config,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+) -> Result<ConversionOptions, Error> {
+    Ok(ConversionOptions {
+        timeout: config.conversion_timeout,
+        max_download_size: Some(config.max_download_size),
+        proxy: config.proxy.clone(),
+        name_template: name_template(query, config)?,
+        capabilities: match config.conversion_enabled {
+            true => capabilities.map(|capabilities| capabilities.get_ref().clone()),
+            false => Some(ConverterCapabilities::disabled()),
+        },
+        language: match config.language_check {
+            true => selection_filters(query)?.language,
+            false => None,
+        },
+        work_dir: config.work_dir.clone(),
+        ..ConversionOptions::default()
+    })
+}
+
+// Whether the book is served in the format it was uploaded in, whichever
+// edition is best: asked for with `format=original`, or by default when
+// conversion is disabled.
+fn original_format(query: &DownloadQuery, config: &Config) -> bool {
+    match &query.format {
+        Some(format) => format.trim().eq_ignore_ascii_case(ORIGINAL_FORMAT),
+        None => query.device.is_none() && !config.conversion_enabled,
+    }
+}
+
+// Returns the output settings, whether the book must be sent to a Kindle,
+// and the formats of the ZIP to serve instead, if several were asked for.
+fn delivery(
+    query: &DownloadQuery,
+) -> Result<(OutputSettings, bool, Option<Vec<ArchiveFormat>>), Error> {
+    let mut settings = output_settings_from_query(query)?;
+    let deliver_to_kindle = deliver_to_kindle(query)?;
+    let archive_formats = archive_formats(query)?;
+    if archive_formats.is_some() && deliver_to_kindle {
+        return Err(Error {
+            name: "invalid input".to_string(),
+            message: "only one format can be delivered to a Kindle".to_string(),
+            details: None,
+            retry_after: None,
+        });
+    }
+    // Send to Kindle no longer accepts MOBI, but converts EPUB itself.
+    if deliver_to_kindle && query.format.is_none() && query.device.is_none() {
+        settings.extension = Extension::Epub;
+    }
+    Ok((settings, deliver_to_kindle, archive_formats))
+}
+
+// Finds what a download serves, and checks it can be served, before
+// anything is downloaded: `download` serves it, and `download_head` only
+// sends its headers, so that both answer alike.
+async fn resolve_download(
+    libreads: &web::Data<dyn BookService>,
+    history: Option<web::Data<History>>,
+    requested_url: &str,
+    query: &DownloadQuery,
+    original: bool,
+    options: ConversionOptions,
+) -> Result<Download, Error> {
+    let goodreads_url = validate_goodreads_url(requested_url)?;
+    // Kept as it was sent, and by book, which bare IDs are too.
+    let request = BookRequest {
+        url: Some(requested_url.trim().to_string()),
+        book: BookRef::from_url(&goodreads_url),
+    };
+    let recorder = Recorder::new(history, request);
+    let (mut settings, deliver_to_kindle, archive_formats) = delivery(query)?;
+    let filters = selection_filters(query)?;
+    if let Some(formats) = archive_formats {
+        return resolve_archive(libreads, &goodreads_url, query, formats, options).await;
+    }
+    let wanted_extension = (!original).then_some(&settings.extension);
+    if query.dry_run {
+        return Ok(Download::Response(
+            dry_run(libreads, &goodreads_url, wanted_extension, query, &filters).await?,
+        ));
+    }
+    let (book_info, timings) = recorder.failed(
+        &settings.extension,
+        libreads
+            .book_info(&goodreads_url, wanted_extension, &filters)
+            .await,
+    )?;
+    if original {
+        settings.extension = book_info.metadata.extension.clone();
+    }
+
+    BookDownload::new(
+        recorder,
+        book_info,
+        timings,
+        settings,
+        deliver_to_kindle,
+        options,
+    )
+    .await
+    .map(Download::Book)
+}
+
+// Downloads a LibGen edition picked by its MD5, e.g. from the candidates of
+// a dry run, without searching Goodreads nor LibGen again. The `title` and
+// `author` query parameters name the file; LibGen is asked when they're
+// missing.
+#[utoipa::path(
+    get,
+    path = "/download/md5/{md5}",
+    tag = "books",
+    summary = "Download a LibGen edition by MD5",
+    params(
+        ("md5" = String, Path, description = "The MD5 of the LibGen edition"),
+        DownloadQuery,
+    ),
+    responses(
+        (status = 200, description = "The book, a ZIP of several formats, or JSON for deliveries", content_type = "application/octet-stream"),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 404, description = "No such edition", body = ErrorBody),
+        (status = 422, description = "Unsupported conversion, or DRM-protected book", body = ErrorBody),
+        (status = 502, description = "An upstream failed", body = ErrorBody),
+    ),
+)]
+pub async fn download_by_md5(
+    libreads: web::Data<dyn BookService>,
+    config: web::Data<Config>,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    history: Option<web::Data<History>>,
+    md5: web::Path<String>,
+    query: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, Error> {
+    let options = conversion_options(&query, &config, capabilities)?;
+    let (mut settings, deliver_to_kindle, archive_formats) = delivery(&query)?;
+    if query.dry_run {
+        return Err(Error {
+            name: "invalid input".to_string(),
+            message: "dry_run needs a Goodreads URL: an MD5 is a single edition".to_string(),
+            details: None,
+            retry_after: None,
+        });
+    }
+    let book_info = libreads
+        .book_info_by_md5(&md5, query.title.as_deref(), query.author.as_deref())
+        .await;
+    // ZIPs aren't recorded in the history, see `download_archive`.
+    let recorder = Recorder::new(history, BookRequest::default());
+    let book_info = match &archive_formats {
+        Some(_) => book_info?,
+        None => recorder.failed(&settings.extension, book_info)?,
+    };
+    if original_format(&query, &config) {
+        settings.extension = book_info.metadata.extension.clone();
+    }
+
+    let download = match archive_formats {
+        Some(formats) => Download::Archive(ArchiveDownload {
+            book_info,
+            timings: Timings::default(),
+            formats,
+            options: ConversionOptions {
+                output_profile: settings.output_profile.map(str::to_string),
+                ..options
+            },
+        }),
+        None => Download::Book(
+            BookDownload::new(
+                recorder,
+                book_info,
+                Timings::default(),
+                settings,
+                deliver_to_kindle,
+                options,
+            )
+            .await?,
+        ),
+    };
+    download.serve(libreads).await
+}
+
+// What a download serves, see `resolve_download`.
+enum Download {
+    // The answer of a dry run.
+    Response(HttpResponse),
+    Archive(ArchiveDownload),
+    Book(BookDownload),
+}
+
+impl Download {
+    async fn serve(self, libreads: web::Data<dyn BookService>) -> Result<HttpResponse, Error> {
+        match self {
+            Download::Response(response) => Ok(response),
+            Download::Archive(archive) => Ok(archive.headers().streaming(stream_archive(
+                libreads,
+                archive.book_info,
+                archive.formats,
+                archive.options,
+            ))),
+            Download::Book(book) => book.serve(libreads).await,
+        }
+    }
+
+    // The headers `serve` would send, without downloading nor sending
+    // anything.
+    fn head(self) -> HttpResponse {
+        match self {
+            Download::Response(response) => response,
+            Download::Archive(archive) => archive.headers().body(actix_web::body::None::new()),
+            Download::Book(book) if book.deliver_to_kindle => HttpResponse::Ok()
+                .content_type("application/json")
+                .body(actix_web::body::None::new()),
+            Download::Book(book) => match book.length {
+                Some(length) => book.headers().body(SizedStream::new(
+                    length,
+                    stream::empty::<Result<web::Bytes, actix_web::Error>>(),
+                )),
+                None => book.headers().body(actix_web::body::None::new()),
+            },
+        }
+    }
+}
+
+// Several formats of the book, served as a ZIP.
+struct ArchiveDownload {
+    book_info: BookInfo,
+    timings: Timings,
+    formats: Vec<ArchiveFormat>,
+    options: ConversionOptions,
+}
+
+impl ArchiveDownload {
+    fn headers(&self) -> HttpResponseBuilder {
+        let mut response = archive_headers(&self.book_info, self.options.name_template.as_ref());
+        if !self.timings.stages().is_empty() {
+            response.append_header(server_timing(&self.timings));
+        }
+        response
+    }
+}
+
+// The book found, served or emailed to the Kindle.
+struct BookDownload {
+    recorder: Recorder,
+    book_info: BookInfo,
+    timings: Timings,
+    extension: Extension,
+    deliver_to_kindle: bool,
+    options: ConversionOptions,
+    // The size of the file served, when it's known before downloading it.
+    length: Option<u64>,
+}
+
+impl BookDownload {
+    async fn new(
+        recorder: Recorder,
+        book_info: BookInfo,
+        timings: Timings,
+        settings: OutputSettings,
+        deliver_to_kindle: bool,
+        options: ConversionOptions,
+    ) -> Result<Self, Error> {
+        let options = ConversionOptions {
+            output_profile: settings.output_profile.map(str::to_string),
+            ..options
+        };
+        check_conversion(&book_info, &settings.extension, &options)?;
+        // Another edition can be served when the language is checked.
+        let length = match deliver_to_kindle || options.language.is_some() {
+            true => None,
+            false => library_file_size(&book_info, &settings.extension).await,
+        };
+        Ok(Self {
+            recorder,
+            book_info,
+            timings,
+            extension: settings.extension,
+            deliver_to_kindle,
+            options,
+            length,
+        })
+    }
+
+    fn headers(&self) -> HttpResponseBuilder {
+        let mut response = download_headers(
+            &self.book_info,
+            &self.extension,
+            self.options.name_template.as_ref(),
+        );
+        if !self.timings.stages().is_empty() {
+            response.append_header(server_timing(&self.timings));
+        }
+        response
+    }
+
+    async fn serve(self, libreads: web::Data<dyn BookService>) -> Result<HttpResponse, Error> {
+        let mut response = self.headers();
+        let BookDownload {
+            recorder,
+            book_info,
+            timings: _,
+            extension,
+            deliver_to_kindle,
+            options,
+            length,
+        } = self;
+
+        // The book is emailed once converted, and only the acknowledgment is
+        // returned.
+        if deliver_to_kindle {
+            let title = title(&book_info);
+            let result = libreads
+                .book_file(book_info, extension.clone(), &options)
+                .await
+                .map_err(Error::from);
+            let result = recorder.download(title, &extension, result)?;
+            let guard = TempFileGuard::new(&result.path);
+            let delivery = libreads.send_to_kindle(&result).await;
+            let _ = guard.close().await;
+            let delivery = delivery?;
+            return Ok(HttpResponse::Ok().json(DeliveryResponse {
+                delivered_to: delivery.to,
+                filename: delivery.filename,
+                bytes: delivery.bytes,
+                md5: result.md5,
+                extension: result.extension,
+                warning: result.language_mismatch.as_ref().map(ToString::to_string),
+            }));
+        }
+
+        // Checking the language takes the whole book, so it's downloaded
+        // before the response starts, and the headers describe the edition
+        // served.
+        if options.language.is_some() {
+            let title = title(&book_info);
+            let result = libreads
+                .book_file(book_info.clone(), extension.clone(), &options)
+                .await
+                .map_err(Error::from);
+            let result = recorder.download(title, &extension, result)?;
+            let guard = TempFileGuard::new(&result.path);
+            response
+                .insert_header((X_LIBREADS_MD5, result.md5.as_str()))
+                .insert_header((X_LIBREADS_SOURCE, result.source.as_str()))
+                .insert_header((
+                    X_LIBREADS_ORIGINAL_EXTENSION,
+                    result.original_extension.to_string(),
+                ));
+            if let Some(mismatch) = &result.language_mismatch {
+                response.insert_header((X_LIBREADS_WARNING, mismatch.to_string()));
+            }
+            return Ok(response.body(SizedStream::new(
+                result.bytes,
+                stream_file(Box::pin(async move { Ok(guard) })),
+            )));
+        }
+
+        let stream = stream_book(libreads, recorder, book_info, extension, options, length);
+        Ok(match length {
+            Some(length) => response.body(SizedStream::new(length, stream)),
+            None => response.streaming(stream),
+        })
+    }
+}
+
+// The size of the book when it's served as it's stored in the library: a
+// converted book's is only known once converted.
+async fn library_file_size(book_info: &BookInfo, extension: &Extension) -> Option<u64> {
+    if book_info.metadata.extension != *extension {
+        return None;
+    }
+    let path = InputBookInfo::from(book_info.clone()).local_path()?;
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    Some(metadata.len())
+}
+
+// A book none of whose editions can be served in the wanted format is
+// rejected before anything is downloaded, with the formats it can be served
+// in, instead of aborting the response.
+fn check_conversion(
+    book_info: &BookInfo,
+    extension: &Extension,
+    options: &ConversionOptions,
+) -> Result<(), Error> {
+    // Capabilities that weren't probed, e.g. in tests, are assumed to be a
+    // full toolchain's.
+    static ASSUMED: LazyLock<ConverterCapabilities> = LazyLock::new(ConverterCapabilities::assumed);
+    let capabilities = options.capabilities.as_ref().unwrap_or(&ASSUMED);
+    let sources: Vec<Extension> = std::iter::once(&book_info.metadata)
+        .chain(&book_info.alternatives)
+        .map(|edition| edition.extension.clone())
+        .collect();
+    Ok(capabilities.check_editions(&sources, extension)?)
+}
+
+// Finds the book to serve several formats of as a ZIP, named after the
+// book. ZIPs aren't recorded in the history, which counts downloads by
+// format.
+async fn resolve_archive(
+    libreads: &web::Data<dyn BookService>,
+    goodreads_url: &str,
+    query: &DownloadQuery,
+    formats: Vec<ArchiveFormat>,
+    options: ConversionOptions,
+) -> Result<Download, Error> {
+    let filters = selection_filters(query)?;
+    if query.dry_run {
+        return Ok(Download::Response(
+            dry_run(libreads, goodreads_url, None, query, &filters).await?,
+        ));
+    }
+    let (book_info, timings) = libreads.book_info(goodreads_url, None, &filters).await?;
+    let options = ConversionOptions {
+        output_profile: output_settings_from_query(query)?
+            .output_profile
+            .map(str::to_string),
+        ..options
+    };
+
+    Ok(Download::Archive(ArchiveDownload {
+        book_info,
+        timings,
+        formats,
+        options,
+    }))
+}
+
+// Returns the formats of the ZIP to serve, if several were asked for.
+fn archive_formats(query: &DownloadQuery) -> Result<Option<Vec<ArchiveFormat>>, Error> {
+    match &query.format {
+        Some(format) if format.contains(',') => {
+            archive::parse_formats(format)
+                .map(Some)
+                .map_err(|message| Error {
+                    name: "invalid input".to_string(),
+                    message,
+                    details: None,
+                    retry_after: None,
+                })
+        }
+        _ => Ok(None),
+    }
+}
+
+enum StreamState {
+    // Produces the file to stream, e.g. by downloading and converting a book.
+    Preparing(LocalBoxFuture<'static, Result<TempFileGuard, Error>>),
+    // The guard deletes the file when the response ends, even when the
+    // client hangs up before the end.
+    Streaming(tokio::fs::File, TempFileGuard),
+    Done,
+}
+
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// Downloads and converts the book, then streams the resulting file and
+// deletes it, see `TempFileGuard`. A file of another size than the
+// Content-Length announced, if any, isn't served.
+fn stream_book(
+    libreads: web::Data<dyn BookService>,
+    recorder: Recorder,
+    book_info: BookInfo,
+    extension: Extension,
+    options: ConversionOptions,
+    length: Option<u64>,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    stream_file(Box::pin(async move {
+        let (md5, source, title) = (
+            book_info.metadata.md5.clone(),
+            InputBookInfo::from(book_info.clone()).source().to_string(),
+            title(&book_info),
+        );
+        let result = libreads
+            .book_file(book_info, extension.clone(), &options)
+            .await
+            .map_err(Error::from);
+        let result = recorder.download(title, &extension, result)?;
+        if result.md5 != md5 || result.source != source {
+            tracing::warn!(
+                "Served {} from {} instead of {} from {}, as announced in the headers",
+                result.md5,
+                result.source,
+                md5,
+                source
+            );
+        }
+        let guard = TempFileGuard::new(result.path);
+        if length.is_some_and(|length| length != result.bytes) {
+            let _ = guard.close().await;
+            return Err(libreads::Error::ApplicationError(
+                "the book changed since its headers were sent".to_string(),
+            )
+            .into());
+        }
+        Ok(guard)
+    }))
+}
+
+// The title of the book in the history: Goodreads', or else LibGen's.
+pub(crate) fn title(book_info: &BookInfo) -> Option<String> {
+    book_info
+        .identification
+        .title
+        .clone()
+        .or_else(|| Some(book_info.metadata.title.clone()))
+        .filter(|title| !title.is_empty())
+}
+
+// Records the outcomes of a request in the history, if kept.
+struct Recorder {
+    history: Option<web::Data<History>>,
+    request: BookRequest,
+}
+
+impl Recorder {
+    fn new(history: Option<web::Data<History>>, request: BookRequest) -> Self {
+        Self { history, request }
+    }
+
+    // Records a failure to find the book.
+    fn failed<T>(
+        &self,
+        extension: &Extension,
+        result: Result<T, libreads::Error>,
+    ) -> Result<T, Error> {
+        result.map_err(|err| {
+            let err = Error::from(err);
+            if let Some(history) = &self.history {
+                history.record(
+                    &self.request,
+                    None,
+                    extension.clone(),
+                    Outcome::Failed(err.name.clone()),
+                );
+            }
+            err
+        })
+    }
+
+    // Records the book downloaded and converted, or the failure.
+    fn download(
+        &self,
+        title: Option<String>,
+        extension: &Extension,
+        result: Result<ConversionResult, Error>,
+    ) -> Result<ConversionResult, Error> {
+        let Some(history) = &self.history else {
+            return result;
+        };
+        let outcome = match &result {
+            Ok(result) => Outcome::Delivered {
+                bytes: result.bytes,
+                from_library: result.source == convert::LOCAL_SOURCE,
+                conversion_duration: result.conversion_duration,
+            },
+            Err(err) => Outcome::Failed(err.name.clone()),
+        };
+        history.record(&self.request, title, extension.clone(), outcome);
+        result
+    }
+}
+
+// Downloads the book, converts it to each format, then streams a ZIP of
+// them and deletes it all.
+fn stream_archive(
+    libreads: web::Data<dyn BookService>,
+    book_info: BookInfo,
+    formats: Vec<ArchiveFormat>,
+    options: ConversionOptions,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    stream_file(Box::pin(async move {
+        let input = InputBookInfo::from(book_info.clone());
+        let name_template = options.name_template.as_ref();
+        let artifacts =
+            archive::convert_all(libreads.get_ref(), book_info, &formats, &options).await?;
+
+        let entries = artifacts
+            .files
+            .iter()
+            .map(|(extension, file)| {
+                (
+                    output_filename(&input, extension, name_template),
+                    file.path().to_path_buf(),
+                )
+            })
+            .collect();
+        let errors = (!artifacts.errors.is_empty()).then(|| {
+            artifacts
+                .errors
+                .into_iter()
+                .map(|(extension, err)| format!("{}: {}\n", extension, Error::from(err).message))
+                .collect()
+        });
+        let zip = TempFileGuard::new(
+            output_path::join(
+                &options.work_dir,
+                &output_filename(&input, &Extension::Other("zip".to_string()), name_template),
+            )
+            .map_err(std::io::Error::from)?,
+        );
+        let written = archive::write_zip(zip.path().to_path_buf(), entries, errors).await;
+        for (_, file) in artifacts.files {
+            let _ = file.close().await;
+        }
+        written?;
+        Ok(zip)
+    }))
+}
+
+// Streams the prepared file, then deletes it.
+fn stream_file(
+    prepare: LocalBoxFuture<'static, Result<TempFileGuard, Error>>,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    stream::unfold(StreamState::Preparing(prepare), |state| async move {
+        let (mut file, guard) = match state {
+            StreamState::Preparing(prepare) => {
+                let guard = match prepare.await {
+                    Ok(guard) => guard,
+                    Err(err) => return Some((Err(err.into()), StreamState::Done)),
+                };
+                match tokio::fs::File::open(guard.path()).await {
+                    Ok(file) => (file, guard),
+                    Err(err) => {
+                        let _ = guard.close().await;
+                        return Some((Err(Error::from(err).into()), StreamState::Done));
+                    }
+                }
+            }
+            StreamState::Streaming(file, guard) => (file, guard),
+            StreamState::Done => return None,
+        };
+
+        let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+        match tokio::io::AsyncReadExt::read(&mut file, &mut buffer).await {
+            Ok(0) => {
+                drop(file);
+                let _ = guard.close().await;
+                None
+            }
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((
+                    Ok(web::Bytes::from(buffer)),
+                    StreamState::Streaming(file, guard),
+                ))
+            }
+            Err(err) => {
+                drop(file);
+                let _ = guard.close().await;
+                Some((Err(Error::from(err).into()), StreamState::Done))
+            }
+        }
+    })
+}
+
+// Answers HEAD requests with the same headers and status as `download`,
+// without downloading, converting nor delivering the book. Content-Length is
+// only sent for books served as they are stored in the library: the size of
+// a downloaded or converted file isn't known beforehand.
+#[utoipa::path(
+    head,
+    path = "/download/{goodreads_url}",
+    tag = "books",
+    summary = "The headers of a download, without the book",
+    params(
+        ("goodreads_url" = String, Path, description = "A URL-encoded Goodreads book URL, or a numeric Goodreads book ID"),
+        DownloadQuery,
+    ),
+    responses(
+        (status = 200, description = "The headers of the download"),
+        (status = 400, description = "Invalid input"),
+        (status = 404, description = "No edition found"),
+        (status = 422, description = "Unsupported conversion"),
+        (status = 502, description = "An upstream failed"),
+    ),
+)]
+pub async fn download_head(
+    libreads: web::Data<dyn BookService>,
+    config: web::Data<Config>,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    goodreads_url: web::Path<String>,
+    query: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, Error> {
+    let options = conversion_options(&query, &config, capabilities)?;
+    let original = original_format(&query, &config);
+    // Nothing is downloaded, so nothing is recorded in the history.
+    let download =
+        resolve_download(&libreads, None, &goodreads_url, &query, original, options).await?;
+    Ok(download.head())
+}
+
+// Lets browsers call the API from the origins allowed in the configuration,
+// e.g. a front-end hosted separately. The headers LibReads sets are exposed
+// to those callers.
+pub fn cors(config: &Config) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(["GET", "HEAD", "POST", "DELETE"])
+        .allow_any_header()
+        .expose_headers([
+            CONTENT_DISPOSITION,
+            HeaderName::from_static("x-available-formats"),
+            HeaderName::from_static("x-libreads-md5"),
+            HeaderName::from_static("x-libreads-source"),
+            HeaderName::from_static("x-libreads-original-extension"),
+            HeaderName::from_static("x-libreads-warning"),
+            HeaderName::from_static("server-timing"),
+            HeaderName::from_static("x-request-id"),
+        ])
+        .max_age(3600);
+
+    for origin in &config.cors_allowed_origins {
+        cors = match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        };
+    }
+
+    cors
+}
+
+// Goodreads exports of large libraries are a few megabytes.
+const MAX_IMPORT_SIZE: usize = 16 * 1024 * 1024;
+
+// What the API serves and reports on, shared by the workers of the server.
+// Everything but the book service and the configuration is optional, e.g.
+// `/healthz` leaves out the circuit breakers it isn't given.
+#[derive(Clone)]
+pub struct LibReadsState {
+    libreads: web::Data<dyn BookService>,
+    config: web::Data<Config>,
+    maintenance: web::Data<Maintenance>,
+    history: web::Data<History>,
+    parse_checker: web::Data<ParseChecker>,
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    breakers: Option<web::Data<CircuitBreakers>>,
+    prober: Option<web::Data<UpstreamProber>>,
+    queue: Option<web::Data<Queue>>,
+}
+
+impl LibReadsState {
+    pub fn new(config: Config, libreads: web::Data<dyn BookService>) -> Self {
+        Self {
+            libreads,
+            maintenance: web::Data::new(Maintenance::new(&config)),
+            history: web::Data::new(History::default()),
+            parse_checker: web::Data::new(ParseChecker::new(&config)),
+            config: web::Data::new(config),
+            capabilities: None,
+            breakers: None,
+            prober: None,
+            queue: None,
+        }
+    }
+
+    // Reported by `/healthz`, and gate conversions.
+    pub fn with_capabilities(self, capabilities: ConverterCapabilities) -> Self {
+        Self {
+            capabilities: Some(web::Data::new(capabilities)),
+            ..self
+        }
+    }
+
+    // Reported by `/healthz` and `/metrics`, usually
+    // `LibReads::circuit_breakers`.
+    pub fn with_breakers(self, breakers: CircuitBreakers) -> Self {
+        Self {
+            breakers: Some(web::Data::new(breakers)),
+            ..self
+        }
+    }
+
+    // Runs `POST /api/admin/cleanup`.
+    pub fn with_maintenance(self, maintenance: Maintenance) -> Self {
+        Self {
+            maintenance: web::Data::new(maintenance),
+            ..self
+        }
+    }
+
+    // Reported by `/healthz` and `/metrics`, once spawned.
+    pub fn with_prober(self, prober: UpstreamProber) -> Self {
+        Self {
+            prober: Some(web::Data::new(prober)),
+            ..self
+        }
+    }
+
+    // Serves `/api/queue`. Its workers are spawned separately, see
+    // `queue::Worker`.
+    pub fn with_queue(self, queue: Arc<Queue>) -> Self {
+        Self {
+            queue: Some(web::Data::from(queue)),
+            ..self
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Where downloads are recorded, e.g. by the queue workers.
+    pub fn history(&self) -> Arc<History> {
+        self.history.clone().into_inner()
+    }
+
+    fn register(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(self.libreads.clone())
+            .app_data(self.config.clone())
+            .app_data(self.maintenance.clone())
+            .app_data(self.history.clone())
+            .app_data(self.parse_checker.clone());
+        if let Some(capabilities) = &self.capabilities {
+            cfg.app_data(capabilities.clone());
+        }
+        if let Some(breakers) = &self.breakers {
+            cfg.app_data(breakers.clone());
+        }
+        if let Some(prober) = &self.prober {
+            cfg.app_data(prober.clone());
+        }
+        if let Some(queue) = &self.queue {
+            cfg.app_data(queue.clone());
+        }
+    }
+}
+
+// Mounts LibReads in an actix app, with the data it needs: `/healthz`,
+// `/metrics` and the API, at the root of the scope it is configured in.
+// The server mounts it under `/api`, see `server::start`; another app can
+// mount it anywhere, e.g. under `/books` for `/books/info/{goodreads_url}`,
+// see `examples/mount_under_prefix.rs`. Binding and TLS are left to the app.
+pub fn configure(cfg: &mut web::ServiceConfig, state: LibReadsState) {
+    configure_probes(cfg, state);
+    api(cfg);
+}
+
+// Registers `/healthz` and `/metrics` alone, e.g. at the root of the server
+// for probes, while the API lives under `/api`.
+pub fn configure_probes(cfg: &mut web::ServiceConfig, state: LibReadsState) {
+    state.register(cfg);
+    probes(cfg);
+}
+
+fn probes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz))
+        .route("/metrics", web::get().to(metrics));
+}
+
+// The routes of the API, which find their data in the app or the scope.
+// Its OpenAPI document, and Swagger UI, don't require the API token.
+fn api(cfg: &mut web::ServiceConfig) {
+    cfg.route("/openapi.json", web::get().to(openapi_document));
+    #[cfg(feature = "swagger-ui")]
+    cfg.service(
+        utoipa_swagger_ui::SwaggerUi::new("/docs/{_:.*}")
+            .config(utoipa_swagger_ui::Config::new(["../openapi.json"])),
+    );
+    cfg.service(
+        web::scope("")
+            .wrap(from_fn(require_token))
+            .route("/download/md5/{md5}", web::get().to(download_by_md5))
+            .route("/download", web::post().to(download_post))
+            .service(
+                web::resource("/download/{goodreads_url}")
+                    .route(web::get().to(download))
+                    .route(web::head().to(download_head)),
+            )
+            .route("/info/{goodreads_url}", web::get().to(info))
+            .route("/search", web::get().to(search))
+            .route("/shelf", web::get().to(shelf))
+            .route("/library", web::get().to(library))
+            .route("/library/{md5}", web::delete().to(remove_from_library))
+            .route("/admin/cleanup", web::post().to(cleanup))
+            .route("/stats", web::get().to(stats))
+            .route("/history/book/{book}", web::get().to(book_history))
+            .service(
+                web::resource("/queue")
+                    .route(web::get().to(queue_items))
+                    .route(web::post().to(enqueue)),
+            )
+            .route("/debug/parse", web::get().to(debug_parse))
+            .service(
+                web::resource("/import/goodreads-csv")
+                    .app_data(web::PayloadConfig::new(MAX_IMPORT_SIZE))
+                    .route(web::post().to(import_goodreads_csv)),
+            )
+            // Unknown API routes aren't front-end routes.
+            .default_service(web::to(api_not_found)),
+    );
+}
+
+// The OpenAPI document of the API, see `openapi`, with the path the API is
+// mounted at as its server, e.g. `/api`.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "docs",
+    summary = "This OpenAPI document",
+    security(()),
+    responses((status = 200, description = "The OpenAPI document", body = serde_json::Value)),
+)]
+pub async fn openapi_document(req: HttpRequest) -> HttpResponse {
+    let base_path = req.path().strip_suffix("/openapi.json").unwrap_or_default();
+    HttpResponse::Ok().json(openapi::document(base_path))
+}
+
+async fn api_not_found(req: HttpRequest) -> Result<HttpResponse, Error> {
+    Err(Error {
+        name: "not found".to_string(),
+        message: format!("no API route for {} {}", req.method(), req.path()),
+        details: None,
+        retry_after: None,
+    })
+}
+
+// Answers as long as the server is up. It never requires the API token.
+// What the conversion toolchain can do is reported once probed, the
+// circuits of the upstreams once called, and whether they are reachable
+// once the `prober` ran.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "probes",
+    summary = "Whether the server is up, with the state of its dependencies",
+    security(()),
+    responses((status = 200, description = "The server is up", body = serde_json::Value)),
+)]
+pub async fn healthz(
+    capabilities: Option<web::Data<ConverterCapabilities>>,
+    breakers: Option<web::Data<CircuitBreakers>>,
+    prober: Option<web::Data<UpstreamProber>>,
+) -> HttpResponse {
+    let mut body = serde_json::json!({ "status": "ok" });
+    if let Some(capabilities) = capabilities {
+        body["converter"] = serde_json::json!(capabilities.get_ref());
+    }
+    if let Some(breakers) = breakers {
+        body["upstreams"] = serde_json::json!(breakers.statuses());
+    }
+    if let Some(prober) = prober {
+        body["probes"] = serde_json::json!(prober.statuses());
+    }
+    HttpResponse::Ok().json(body)
+}
+
+// The availability of the upstreams as Prometheus gauges, once the `prober`
+// is enabled, and the requests to them that failed, by kind of error. Like
+// `/healthz`, it never requires the API token.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "probes",
+    summary = "Prometheus metrics of the upstreams",
+    security(()),
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain")),
+)]
+pub async fn metrics(
+    breakers: Option<web::Data<CircuitBreakers>>,
+    prober: Option<web::Data<UpstreamProber>>,
+) -> HttpResponse {
+    let mut metrics = prober.map(|prober| prober.metrics()).unwrap_or_default();
+    if let Some(breakers) = breakers {
+        metrics.push_str(&breakers.metrics());
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics)
+}
+
+// When `Config::api_token` is set, requests must provide it, either as an
+// `Authorization: Bearer <token>` header, or as a `token` query parameter for
+// e-readers following a plain link.
+pub async fn require_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let expected = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.api_token.clone());
+
+    if let Some(expected) = expected {
+        let authorized = request_token(req.request())
+            .is_some_and(|token| tokens_match(token.as_bytes(), expected.as_bytes()));
+        if !authorized {
+            let err = Error {
+                name: "unauthorized".to_string(),
+                message: "missing or invalid API token".to_string(),
+                details: None,
+                retry_after: None,
+            };
+            return Ok(req.error_response(err).map_into_right_body());
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+fn request_token(req: &HttpRequest) -> Option<String> {
+    if let Some(header) = req.headers().get(AUTHORIZATION) {
+        return header
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")
+            .map(|token| token.trim().to_string());
+    }
+
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok()?;
+    query.get("token").cloned()
+}
+
+// Compares the tokens in constant time, so response times don't tell how
+// much of a guessed token is right.
+fn tokens_match(got: &[u8], expected: &[u8]) -> bool {
+    got.len() == expected.len()
+        && got
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[test]
+fn test_tokens_match() {
+    assert!(tokens_match(b"s3cr3t", b"s3cr3t"));
+    assert!(!tokens_match(b"s3cr3T", b"s3cr3t"));
+    assert!(!tokens_match(b"s3cr3", b"s3cr3t"));
+    assert!(!tokens_match(b"", b"s3cr3t"));
+}
+
+// Without any query parameter, books are converted to Mobi.
+fn output_settings_from_query(query: &DownloadQuery) -> Result<OutputSettings, Error> {
+    let mut settings = match &query.device {
+        Some(device) => device::output_settings(
+            device
+                .parse()
+                .map_err(|message| invalid_field(Some("device"), message))?,
+        ),
+        None => OutputSettings {
+            extension: Extension::Mobi,
+            output_profile: None,
+        },
+    };
+
+    // Several formats are checked by `archive_formats`, and the original one
+    // is only known once the edition is.
+    if let Some(format) = query.format.as_ref().filter(|format| {
+        !format.contains(',') && !format.trim().eq_ignore_ascii_case(ORIGINAL_FORMAT)
+    }) {
+        let Ok(extension) = format.parse::<Extension>();
+        if !extension.is_conversion_target() {
+            return Err(invalid_field(
+                Some("format"),
+                format!("unsupported format {:?}", format),
+            ));
+        }
+        settings.extension = extension;
+    }
+
+    Ok(settings)
+}
+
+#[test]
+fn test_output_settings_from_query() {
+    for (format, device, want) in [
+        (
+            None,
+            None,
+            OutputSettings {
+                extension: Extension::Mobi,
+                output_profile: None,
+            },
+        ),
+        (
+            None,
+            Some("kindle"),
+            OutputSettings {
+                extension: Extension::Azw3,
+                output_profile: Some("kindle_pw3"),
+            },
+        ),
+        (
+            Some("epub"),
+            None,
+            OutputSettings {
+                extension: Extension::Epub,
+                output_profile: None,
+            },
+        ),
+        (
+            Some("mobi"),
+            Some("kindle"),
+            OutputSettings {
+                extension: Extension::Mobi,
+                output_profile: Some("kindle_pw3"),
+            },
+        ),
+        (
+            Some("txt"),
+            None,
+            OutputSettings {
+                extension: Extension::Txt,
+                output_profile: None,
+            },
+        ),
+        (
+            Some("HTMLZ"),
+            None,
+            OutputSettings {
+                extension: Extension::Htmlz,
+                output_profile: None,
+            },
+        ),
+    ] {
+        let query = DownloadQuery {
+            format: format.map(str::to_string),
+            device: device.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(want, output_settings_from_query(&query).unwrap());
+    }
+
+    for (format, device) in [
+        (None, Some("nook")),
+        (Some("cbz"), None),
+        (Some("exe"), None),
+        (Some("doc"), None),
+        (Some(""), None),
+    ] {
+        let query = DownloadQuery {
+            format: format.map(str::to_string),
+            device: device.map(str::to_string),
+            ..Default::default()
+        };
+        let got = output_settings_from_query(&query).unwrap_err();
+        assert_eq!("invalid input", got.name);
+    }
+}
+
+// Rejects anything that isn't a Goodreads URL or a numeric Goodreads book
+// ID, so we don't send garbage to the upstreams. Returns the URL to use.
+fn validate_goodreads_url(input: &str) -> Result<String, Error> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(format!("https://www.goodreads.com/book/show/{}", input));
+    }
+
+    let invalid = |reason: &str| Error {
+        name: "invalid input".to_string(),
+        message: format!(
+            "{}: expected a Goodreads book URL or a numeric Goodreads book ID",
+            reason
+        ),
+        details: None,
+        retry_after: None,
+    };
+
+    parse_goodreads_url(input).map_err(invalid)
+}
+
+// Parses a Goodreads URL, or returns why it isn't one.
+fn parse_goodreads_url(input: &str) -> Result<String, &'static str> {
+    let url = reqwest::Url::parse(input).map_err(|_| "not a valid URL")?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("unsupported URL scheme");
+    }
+    match url.host_str() {
+        Some(host) if host == "goodreads.com" || host.ends_with(".goodreads.com") => {
+            Ok(url.to_string())
+        }
+        _ => Err("unsupported host"),
+    }
+}
+
+#[test]
+fn test_validate_goodreads_url() {
+    for (input, want) in [
+        ("1048424", "https://www.goodreads.com/book/show/1048424"),
+        (
+            "https://www.goodreads.com/book/show/1048424.Governing_the_Commons",
+            "https://www.goodreads.com/book/show/1048424.Governing_the_Commons",
+        ),
+        (
+            "http://goodreads.com/book/show/1048424",
+            "http://goodreads.com/book/show/1048424",
+        ),
+    ] {
+        assert_eq!(want, validate_goodreads_url(input).unwrap());
+    }
+
+    for input in [
+        "",
+        "notaurl",
+        "12ab",
+        "ftp://www.goodreads.com/book/show/1",
+        "https://example.com/book/show/1",
+        "https://goodreads.com.example.com/book/show/1",
+        "https://notgoodreads.com/book/show/1",
+    ] {
+        let got = validate_goodreads_url(input).unwrap_err();
+        assert_eq!("invalid input", got.name);
+    }
+}
+
+// The X-LibReads-* headers describe the edition selected when the response
+// starts. If it can't be converted and another edition is used instead, the
+// book is still served, but the headers were already sent.
+fn download_headers(
+    book_info: &BookInfo,
+    extension: &Extension,
+    name_template: Option<&NameTemplate>,
+) -> HttpResponseBuilder {
+    let input = InputBookInfo::from(book_info.clone());
+    let content_type = (CONTENT_TYPE, extension.content_type());
+    let content_disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(output_filename(
+            &input,
+            extension,
+            name_template,
+        ))],
+    };
+    let available_formats = (
+        X_AVAILABLE_FORMATS,
+        book_info
+            .available_formats
+            .iter()
+            .map(Extension::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+
+    let mut response = HttpResponse::Ok();
+    response
+        .append_header(content_disposition)
+        .append_header(content_type)
+        .append_header(available_formats)
+        .append_header((X_LIBREADS_MD5, book_info.metadata.md5.as_str()))
+        .append_header((X_LIBREADS_SOURCE, input.source()))
+        .append_header((
+            X_LIBREADS_ORIGINAL_EXTENSION,
+            book_info.metadata.extension.to_string(),
+        ))
+        // Books are mostly compressed already (EPUB, AZW3...): the `Compress`
+        // middleware leaves responses with a Content-Encoding alone.
+        .insert_header(ContentEncoding::Identity);
+    response
+}
+
+// The same headers as `download_headers`, for a ZIP of several formats.
+fn archive_headers(
+    book_info: &BookInfo,
+    name_template: Option<&NameTemplate>,
+) -> HttpResponseBuilder {
+    let mut response = download_headers(
+        book_info,
+        &Extension::Other("zip".to_string()),
+        name_template,
+    );
+    response.insert_header((CONTENT_TYPE, "application/zip"));
+    response
+}
+
+// Returns what LibReads found for a book, without downloading it. It is
+// JSON by default, or a page that can be read in a browser or a terminal,
+// depending on the `Accept` header.
+#[utoipa::path(
+    get,
+    path = "/info/{goodreads_url}",
+    tag = "books",
+    summary = "What was found for a book, without downloading it",
+    params(("goodreads_url" = String, Path, description = "A URL-encoded Goodreads book URL, or a numeric Goodreads book ID")),
+    responses(
+        (status = 200, description = "JSON by default, or a page for Accept: text/html or text/plain", content(
+            (BookInfoResponse = "application/json"),
+            (String = "text/html"),
+            (String = "text/plain"),
+        )),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 404, description = "No edition found", body = ErrorBody),
+        (status = 502, description = "An upstream failed", body = ErrorBody),
+    ),
+)]
+pub async fn info(
+    req: HttpRequest,
+    libreads: web::Data<dyn BookService>,
+    goodreads_url: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let goodreads_url = validate_goodreads_url(&goodreads_url)?;
+    let (book_info, timings) = libreads
+        .book_info(&goodreads_url, None, &SelectionFilters::default())
+        .await?;
+    Ok(info_response(&req, book_info, &timings))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    // See `BookQuery`, e.g. "isbn:9780553418026".
+    q: String,
+}
+
+// Same as `info`, for a book asked for by ISBN, title and author, Goodreads
+// ID or page URL.
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "books",
+    summary = "What was found for a book asked for by ISBN, title and author, Goodreads ID or URL",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "As for GET /info/{goodreads_url}", content(
+            (BookInfoResponse = "application/json"),
+            (String = "text/html"),
+            (String = "text/plain"),
+        )),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "No edition found", body = ErrorBody),
+        (status = 502, description = "An upstream failed", body = ErrorBody),
+    ),
+)]
+pub async fn search(
+    req: HttpRequest,
+    libreads: web::Data<dyn BookService>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, Error> {
+    let book_query: BookQuery = query.q.parse().map_err(|message| Error {
+        name: "invalid input".to_string(),
+        message,
+        details: None,
+        retry_after: None,
+    })?;
+    let (book_info, timings) = libreads
+        .book_info_by_query(&book_query, None, &SelectionFilters::default())
+        .await?;
+    Ok(info_response(&req, book_info, &timings))
+}
+
+// Formats a book found by `info` or `search` for the `Accept` header.
+fn info_response(req: &HttpRequest, book_info: BookInfo, timings: &Timings) -> HttpResponse {
+    let book_info = BookInfoResponse::from(book_info);
+
+    let accept = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok());
+    let mut response = HttpResponse::Ok();
+    response
+        .append_header(server_timing(timings))
+        .append_header((VARY, "Accept"));
+    match negotiate_info_format(accept) {
+        InfoFormat::Json => response.json(book_info),
+        InfoFormat::Html => response
+            .content_type("text/html; charset=utf-8")
+            .body(info_html(&book_info)),
+        InfoFormat::Text => response
+            .content_type("text/plain; charset=utf-8")
+            .body(info_text(&book_info)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum InfoFormat {
+    Json,
+    Html,
+    Text,
+}
+
+// Picks the format with the highest quality in the `Accept` header, e.g.
+// `text/html;q=0.9, */*;q=0.1`. When qualities are equal, the first one
+// listed wins. Anything else falls back to JSON.
+fn negotiate_info_format(accept: Option<&str>) -> InfoFormat {
+    let mut best: Option<(InfoFormat, f32)> = None;
+
+    for media_range in accept.unwrap_or_default().split(',') {
+        let mut params = media_range.split(';').map(str::trim);
+        let format = match params.next().unwrap_or_default().to_lowercase().as_str() {
+            "application/json" | "*/*" => InfoFormat::Json,
+            "text/html" | "text/*" => InfoFormat::Html,
+            "text/plain" => InfoFormat::Text,
+            _ => continue,
+        };
+        let quality = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, value)| value.trim().parse::<f32>().ok());
+        let Some(quality) = quality.filter(|q| (0.0..=1.0).contains(q) && *q > 0.0) else {
+            continue;
+        };
+
+        if best.as_ref().is_none_or(|(_, best)| quality > *best) {
+            best = Some((format, quality));
+        }
+    }
+
+    best.map_or(InfoFormat::Json, |(format, _)| format)
+}
+
+#[test]
+fn test_negotiate_info_format() {
+    for (accept, want) in [
+        (None, InfoFormat::Json),
+        (Some(""), InfoFormat::Json),
+        (Some("application/json"), InfoFormat::Json),
+        (Some("text/html"), InfoFormat::Html),
+        (Some("TEXT/HTML"), InfoFormat::Html),
+        (Some("text/*"), InfoFormat::Html),
+        (Some("text/plain"), InfoFormat::Text),
+        (Some("text/plain; charset=utf-8"), InfoFormat::Text),
+        (Some("image/png"), InfoFormat::Json),
+        (Some("image/png, text/plain"), InfoFormat::Text),
+        // Browsers
+        (
+            Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            InfoFormat::Html,
+        ),
+        // curl
+        (Some("*/*"), InfoFormat::Json),
+        // Quality factors
+        (Some("text/html;q=0.9, */*;q=0.1"), InfoFormat::Html),
+        (Some("text/html;q=0.5, text/plain"), InfoFormat::Text),
+        (
+            Some("text/plain;q=0.2, application/json;q=0.8"),
+            InfoFormat::Json,
+        ),
+        (
+            Some("text/html ; q=0.3 , text/plain ; q=0.7"),
+            InfoFormat::Text,
+        ),
+        (Some("text/html, text/plain"), InfoFormat::Html),
+        (Some("text/html;q=0, text/plain;q=0.1"), InfoFormat::Text),
+        (Some("text/html;q=0"), InfoFormat::Json),
+        (Some("text/html;q=nope, text/plain;q=0.1"), InfoFormat::Text),
+        (Some("text/html;q=2, text/plain;q=0.1"), InfoFormat::Text),
+    ] {
+        assert_eq!(want, negotiate_info_format(accept), "{:?}", accept);
+    }
+}
+
+fn info_html(book_info: &BookInfoResponse) -> String {
+    let formats: Vec<String> = book_info
+        .available_formats
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let links: String = book_info
+        .download_links
+        .named_links()
+        .into_iter()
+        .map(|(name, link)| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape_html(&link),
+                escape_html(&name)
+            )
+        })
+        .collect();
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>{title}</title></head>
+<body>
+<h1>{title}</h1>
+<p>{author} ({year})</p>
+<p>Formats: {formats}</p>
+<ul>{links}</ul>
+</body>
+</html>
+",
+        title = escape_html(&book_info.title),
+        author = escape_html(&book_info.author),
+        year = escape_html(&book_info.year),
+        formats = escape_html(&formats.join(", ")),
+        links = links,
+    )
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[test]
+fn test_escape_html() {
+    assert_eq!(
+        "Tom &amp; Jerry &lt;3 &quot;quoted&quot; &#39;single&#39;",
+        escape_html(r#"Tom & Jerry <3 "quoted" 'single'"#)
+    );
+}
+
+fn info_text(book_info: &BookInfoResponse) -> String {
+    let formats: Vec<String> = book_info
+        .available_formats
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let mut text = format!(
+        "{}\nby {} ({})\nformats: {}\n",
+        book_info.title,
+        book_info.author,
+        book_info.year,
+        formats.join(", ")
+    );
+    for (name, link) in book_info.download_links.named_links() {
+        text.push_str(&format!("{}: {}\n", name, link));
+    }
+    text
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShelfQuery {
+    url: String,
+}
+
+// Lists the books of a Goodreads shelf, as JSON. Each `book_url` can be
+// passed to `/download` or `/info`.
+#[utoipa::path(
+    get,
+    path = "/shelf",
+    tag = "books",
+    summary = "The books of a public Goodreads shelf",
+    params(ShelfQuery),
+    responses(
+        (status = 200, description = "The books of the shelf", body = Vec<ShelfEntry>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 502, description = "An upstream failed", body = ErrorBody),
+    ),
+)]
+pub async fn shelf(
+    libreads: web::Data<dyn BookService>,
+    query: web::Query<ShelfQuery>,
+) -> Result<HttpResponse, Error> {
+    let shelf_url = parse_goodreads_url(query.url.trim()).map_err(|reason| Error {
+        name: "invalid input".to_string(),
+        message: format!("{}: expected a Goodreads shelf URL", reason),
+        details: None,
+        retry_after: None,
+    })?;
+    let books = libreads.shelf(&shelf_url).await?;
+
+    Ok(HttpResponse::Ok().json(books))
+}
+
+// Parses a Goodreads library export, sent as the raw request body, and
+// returns the books it lists as JSON.
+#[utoipa::path(
+    post,
+    path = "/import/goodreads-csv",
+    tag = "books",
+    summary = "The books of a Goodreads library export",
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (status = 200, description = "The books, and how many rows had no ISBN", body = ImportedBooks),
+        (status = 400, description = "Invalid CSV", body = ErrorBody),
+    ),
+)]
+pub async fn import_goodreads_csv(body: web::Bytes) -> Result<HttpResponse, Error> {
+    let imported = import::parse_goodreads_csv(body.as_ref())?;
+    Ok(HttpResponse::Ok().json(imported))
+}
+
+// Lists the books kept in the library, see `LIBREADS_LIBRARY_DIR`, most
+// recently stored first.
+#[utoipa::path(
+    get,
+    path = "/library",
+    tag = "library",
+    summary = "The books kept in the library, most recently stored first",
+    responses(
+        (status = 200, description = "The books of the library", body = Vec<LibraryEntry>),
+        (status = 404, description = "No library is configured", body = ErrorBody),
+    ),
+)]
+pub async fn library(libreads: web::Data<dyn BookService>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(libreads.library_books().await?))
+}
+
+// Deletes a book from the library, by MD5.
+#[utoipa::path(
+    delete,
+    path = "/library/{md5}",
+    tag = "library",
+    summary = "Delete a book from the library",
+    params(("md5" = String, Path, description = "The MD5 of the LibGen edition")),
+    responses(
+        (status = 204, description = "The book was deleted"),
+        (status = 404, description = "No such book, or no library is configured", body = ErrorBody),
+    ),
+)]
+pub async fn remove_from_library(
+    libreads: web::Data<dyn BookService>,
+    md5: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    libreads.remove_from_library(&md5).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CleanupQuery {
+    scope: Option<maintenance::Scope>,
+}
+
+// Deletes the temporary files and cache entries left behind, see
+// `maintenance`, and reports how many files and bytes were removed.
+#[utoipa::path(
+    post,
+    path = "/admin/cleanup",
+    tag = "admin",
+    summary = "Delete leftover temporary files and cache entries",
+    params(CleanupQuery),
+    responses(
+        (status = 200, description = "What was removed", body = CleanupReport),
+        (status = 400, description = "Invalid scope", body = ErrorBody),
+    ),
+)]
+pub async fn cleanup(
+    maintenance: web::Data<Maintenance>,
+    query: web::Query<CleanupQuery>,
+) -> Result<HttpResponse, Error> {
+    let report = maintenance.run(query.scope).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    // Only the downloads of this last period, e.g. "7d", see
+    // `history::parse_since`.
+    since: Option<String>,
+}
+
+// Aggregates the downloads since the server started, or of the last
+// `since`, see `history`.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "admin",
+    summary = "Aggregates of the downloads",
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "The aggregates", body = Stats),
+        (status = 400, description = "Invalid period", body = ErrorBody),
+    ),
+)]
+pub async fn stats(
+    history: web::Data<History>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, Error> {
+    let since = query
+        .since
+        .as_deref()
+        .map(history::parse_since)
+        .transpose()
+        .map_err(|message| Error {
+            name: "invalid input".to_string(),
+            message,
+            details: None,
+            retry_after: None,
+        })?;
+    Ok(HttpResponse::Ok().json(history.stats(since)))
+}
+
+// Lists the downloads of a book still in the history, newest first, however
+// its URL was spelled. The book is a Goodreads book ID, e.g. "1048424", or
+// another provider's prefixed ID, see `book_ref`.
+#[utoipa::path(
+    get,
+    path = "/history/book/{book}",
+    tag = "admin",
+    summary = "The downloads of a book, newest first",
+    params(("book" = String, Path, description = "A Goodreads book ID, e.g. 1048424, or another provider's prefixed ID, e.g. ol:OL12345M")),
+    responses(
+        (status = 200, description = "The downloads of the book", body = Vec<Attempt>),
+        (status = 400, description = "Invalid book", body = ErrorBody),
+    ),
+)]
+pub async fn book_history(
+    history: web::Data<History>,
+    book: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let book: BookRef = book.parse().map_err(|message| Error {
+        name: "invalid input".to_string(),
+        message,
+        details: None,
+        retry_after: None,
+    })?;
+    Ok(HttpResponse::Ok().json(history.attempts(&book)))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EnqueueRequest {
+    // Goodreads book URLs, or numeric IDs.
+    urls: Vec<String>,
+    // As for `GET /api/download`, but a single format, not "original".
+    format: Option<String>,
+    device: Option<String>,
+}
+
+fn queue_disabled() -> Error {
+    Error {
+        name: "unsupported".to_string(),
+        message: "the download queue is disabled, see LIBREADS_QUEUE_FILE".to_string(),
+        details: None,
+        retry_after: None,
+    }
+}
+
+// Enqueues books to download in the background, see `queue`, and returns
+// them with their IDs. Nothing is enqueued when one of them is invalid.
+#[utoipa::path(
+    post,
+    path = "/queue",
+    tag = "queue",
+    summary = "Download books in the background, into the library",
+    request_body = EnqueueRequest,
+    responses(
+        (status = 202, description = "The books enqueued", body = Vec<QueueItem>),
+        (status = 400, description = "Invalid input, with the field at fault in its details", body = ErrorBody),
+        (status = 501, description = "The queue is disabled", body = ErrorBody),
+        (status = 503, description = "Too many books are waiting", body = ErrorBody),
+    ),
+)]
+pub async fn enqueue(
+    queue: Option<web::Data<Queue>>,
+    body: web::Json<EnqueueRequest>,
+) -> Result<HttpResponse, Error> {
+    let queue = queue.ok_or_else(queue_disabled)?;
+    let body = body.into_inner();
+    if body.urls.is_empty() {
+        return Err(invalid_field(
+            Some("urls"),
+            "urls: expected at least one book".to_string(),
+        ));
+    }
+    if body.urls.len() > MAX_ENQUEUED_URLS {
+        return Err(invalid_field(
+            Some("urls"),
+            format!(
+                "urls: {} books, at most {} at once",
+                body.urls.len(),
+                MAX_ENQUEUED_URLS
+            ),
+        ));
+    }
+    if let Some(format) = body.format.as_deref().filter(|format| {
+        format.contains(',') || format.trim().eq_ignore_ascii_case(ORIGINAL_FORMAT)
+    }) {
+        return Err(invalid_field(
+            Some("format"),
+            format!("unsupported format {:?}: expected a single format", format),
+        ));
+    }
+    let settings = output_settings_from_query(&DownloadQuery {
+        format: body.format,
+        device: body.device,
+        ..DownloadQuery::default()
+    })?;
+    let requests = body
+        .urls
+        .iter()
+        .map(|url| {
+            Ok(QueueRequest {
+                url: validate_goodreads_url(url)?,
+                format: settings.extension.clone(),
+                output_profile: settings.output_profile.map(str::to_string),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(HttpResponse::Accepted().json(queue.enqueue(requests).await?))
+}
+
+// Lists the queued books, pending, active or finished, oldest first.
+#[utoipa::path(
+    get,
+    path = "/queue",
+    tag = "queue",
+    summary = "The queued books, oldest first",
+    responses(
+        (status = 200, description = "The queued books, pending, active or finished", body = Vec<QueueItem>),
+        (status = 501, description = "The queue is disabled", body = ErrorBody),
+    ),
+)]
+pub async fn queue_items(queue: Option<web::Data<Queue>>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(queue.ok_or_else(queue_disabled)?.items()))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DebugParseQuery {
+    url: String,
+}
+
+// Reports what the parsers find on a Goodreads or library.lol page, to tell
+// when they no longer understand it, see `parse_check`.
+#[utoipa::path(
+    get,
+    path = "/debug/parse",
+    tag = "admin",
+    summary = "What the parsers find on a Goodreads or library.lol page",
+    params(DebugParseQuery),
+    responses(
+        (status = 200, description = "What the parser found", body = ParseReport),
+        (status = 400, description = "Invalid URL", body = ErrorBody),
+        (status = 502, description = "The page couldn't be fetched", body = ErrorBody),
+    ),
+)]
+pub async fn debug_parse(
+    checker: web::Data<ParseChecker>,
+    query: web::Query<DebugParseQuery>,
+) -> Result<HttpResponse, Error> {
+    let report = checker.check(&query.url).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+// Formats the timings as a Server-Timing header, with durations in
+// milliseconds: `goodreads;dur=123.4, libgen;dur=45.0`.
+fn server_timing(timings: &Timings) -> (&'static str, String) {
+    let value = timings
+        .stages()
+        .iter()
+        .map(|(stage, duration)| format!("{};dur={:.1}", stage, duration.as_secs_f64() * 1000.0))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    (SERVER_TIMING, value)
+}
+
+#[test]
+fn test_server_timing() {
+    use std::time::Duration;
+
+    let mut timings = Timings::default();
+    timings.record("goodreads", Duration::from_micros(123_456));
+    timings.record("libgen", Duration::from_millis(45));
+    timings.record("conversion", Duration::ZERO);
+
+    assert_eq!(
+        (
+            "Server-Timing",
+            "goodreads;dur=123.5, libgen;dur=45.0, conversion;dur=0.0".to_string()
+        ),
+        server_timing(&timings)
+    );
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = BookInfo)]
+struct BookInfoResponse {
+    title: String,
+    author: String,
+    year: String,
+    extension: Extension,
+    md5: String,
+    download_links: DownloadLinks,
+    cover_url: Option<String>,
+    available_formats: Vec<Extension>,
+    // The next editions that would be tried, best first.
+    alternatives: Vec<LibgenMetadata>,
+    // Whether the editions were found by title, nothing matching the ISBN.
+    found_by_title: bool,
+    // What Goodreads says about the book.
+    identification: BookIdentification,
+}
+
+impl From<BookInfo> for BookInfoResponse {
+    fn from(book_info: BookInfo) -> Self {
+        Self {
+            title: book_info.metadata.title,
+            author: book_info.metadata.author,
+            year: book_info.metadata.year,
+            extension: book_info.metadata.extension,
+            md5: book_info.metadata.md5,
+            download_links: book_info.download_links,
+            cover_url: book_info.cover_url,
+            available_formats: book_info.available_formats,
+            alternatives: book_info.alternatives,
+            found_by_title: book_info.found_by_title,
+            identification: book_info.identification,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    name: String,
+    message: String,
+    // Machine-readable context, added to the JSON body when set.
+    details: Option<serde_json::Value>,
+    // Sent as Retry-After, when clients must back off.
+    retry_after: Option<Duration>,
+}
+
+// The JSON body of errors.
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorBody {
+    // The kind of error, e.g. "not found".
+    error: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
+}
+
+impl Error {
+    // The kind of error, e.g. "not found".
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl error::ResponseError for Error {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self.name.as_str() {
+            "upstream" => actix_web::http::StatusCode::BAD_GATEWAY,
+            "invalid input" => actix_web::http::StatusCode::BAD_REQUEST,
+            "timeout" => actix_web::http::StatusCode::GATEWAY_TIMEOUT,
+            "unavailable" => actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
+            "unsupported" => actix_web::http::StatusCode::NOT_IMPLEMENTED,
+            "drm protected" => actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            "attachment too large" => actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            "unsupported conversion" => actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            "too large" => actix_web::http::StatusCode::BAD_GATEWAY,
+            "unauthorized" => actix_web::http::StatusCode::UNAUTHORIZED,
+            "not found" => actix_web::http::StatusCode::NOT_FOUND,
+            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = ErrorBody {
+            error: self.name.clone(),
+            message: self.message.clone(),
+            details: self.details.clone(),
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header((RETRY_AFTER, retry_after.as_secs().to_string()));
+        }
+        response.json(body)
+    }
+}
+
+#[test]
+fn test_error_status_code() {
+    use actix_web::http::StatusCode;
+
+    for (name, want) in [
+        ("upstream", StatusCode::BAD_GATEWAY),
+        ("invalid input", StatusCode::BAD_REQUEST),
+        ("timeout", StatusCode::GATEWAY_TIMEOUT),
+        ("unavailable", StatusCode::SERVICE_UNAVAILABLE),
+        ("unsupported", StatusCode::NOT_IMPLEMENTED),
+        ("drm protected", StatusCode::UNPROCESSABLE_ENTITY),
+        ("attachment too large", StatusCode::UNPROCESSABLE_ENTITY),
+        ("unsupported conversion", StatusCode::UNPROCESSABLE_ENTITY),
+        ("too large", StatusCode::BAD_GATEWAY),
+        ("unauthorized", StatusCode::UNAUTHORIZED),
+        ("not found", StatusCode::NOT_FOUND),
+        ("http", StatusCode::INTERNAL_SERVER_ERROR),
+        ("i/o", StatusCode::INTERNAL_SERVER_ERROR),
+        ("application", StatusCode::INTERNAL_SERVER_ERROR),
+        ("anything at all", StatusCode::INTERNAL_SERVER_ERROR),
+    ] {
+        let error = Error {
+            name: name.to_string(),
+            message: "doesn't matter".to_string(),
+            details: None,
+            retry_after: None,
+        };
+
+        assert_eq!(want, actix_web::ResponseError::status_code(&error));
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+// Error messages sent to clients are cut after this many characters.
+const MAX_ERROR_MESSAGE_LENGTH: usize = 300;
+// Local paths, reduced to their file name by `sanitise_message`.
+static LOCAL_PATH: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r#"(?:/|[A-Za-z]:\\)(?:[^/\\\s"'()\[\]]+[/\\])+([^/\\\s"'()\[\]]*)"#).unwrap()
+});
+// URLs in error messages, redacted by `sanitise_message` when they point to
+// the self-hoster's network.
+static URL: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r#"[A-Za-z][A-Za-z0-9+.-]*://[^\s"'()<>]+"#).unwrap());
+
+// Failed requests say what went wrong with which upstream, so self-hosters
+// know whether to look at their resolver, their proxy or the upstream. The
+// kind is in the details, e.g. `{"kind": "dns_failure", "host": "libgen.rs"}`.
+impl From<HttpFailure> for Error {
+    fn from(failure: HttpFailure) -> Self {
+        let host = failure.host.as_deref().unwrap_or("unknown");
+        let (name, reason) = match failure.kind {
+            HttpErrorKind::DnsFailure => (
+                "upstream",
+                format!(
+                    "cannot reach upstream host {}: its name doesn't resolve",
+                    host
+                ),
+            ),
+            HttpErrorKind::ConnectTimeout | HttpErrorKind::ConnectionRefused => {
+                ("upstream", format!("cannot reach upstream host {}", host))
+            }
+            HttpErrorKind::TlsFailure => (
+                "upstream",
+                format!(
+                    "TLS failure with upstream host {}, check the proxy and the CA certificates",
+                    host
+                ),
+            ),
+            HttpErrorKind::ReadTimeout => (
+                "timeout",
+                format!("upstream host {} took too long to answer", host),
+            ),
+            HttpErrorKind::ConnectionReset => (
+                "upstream",
+                format!("upstream host {} closed the connection", host),
+            ),
+            HttpErrorKind::Status(_) | HttpErrorKind::Other => {
+                return Error {
+                    name: "upstream".to_string(),
+                    message: sanitise_message(&failure.message),
+                    details: None,
+                    retry_after: None,
+                }
+            }
+        };
+        Error {
+            name: name.to_string(),
+            message: sanitise_message(&format!("{}: {}", reason, failure.message)),
+            details: Some(serde_json::json!({
+                "kind": failure.kind.label(),
+                "host": failure.host,
+            })),
+            retry_after: None,
+        }
+    }
+}
+
+#[test]
+fn test_http_failure() {
+    let failure = |kind: HttpErrorKind| HttpFailure {
+        kind,
+        host: Some("libgen.rs".to_string()),
+        message: "error sending request".to_string(),
+    };
+
+    let got = Error::from(failure(HttpErrorKind::DnsFailure));
+    assert_eq!(502, actix_web::ResponseError::status_code(&got).as_u16());
+    assert_eq!(
+        "cannot reach upstream host libgen.rs: its name doesn't resolve: error sending request",
+        got.message
+    );
+    assert_eq!(
+        Some(serde_json::json!({"kind": "dns_failure", "host": "libgen.rs"})),
+        got.details
+    );
+
+    for (kind, status, message) in [
+        (
+            HttpErrorKind::ConnectionRefused,
+            502,
+            "cannot reach upstream host libgen.rs",
+        ),
+        (
+            HttpErrorKind::ConnectTimeout,
+            502,
+            "cannot reach upstream host libgen.rs",
+        ),
+        (
+            HttpErrorKind::TlsFailure,
+            502,
+            "TLS failure with upstream host libgen.rs",
+        ),
+        (
+            HttpErrorKind::ReadTimeout,
+            504,
+            "upstream host libgen.rs took too long",
+        ),
+        (
+            HttpErrorKind::ConnectionReset,
+            502,
+            "upstream host libgen.rs closed",
+        ),
+    ] {
+        let got = Error::from(failure(kind.clone()));
+        assert_eq!(
+            status,
+            actix_web::ResponseError::status_code(&got).as_u16(),
+            "{:?}",
+            kind
+        );
+        assert!(got.message.starts_with(message), "{}", got.message);
+    }
+
+    // Other failures keep their message.
+    let got = Error::from(failure(HttpErrorKind::Status(500)));
+    assert_eq!(502, actix_web::ResponseError::status_code(&got).as_u16());
+    assert_eq!("error sending request", got.message);
+    assert_eq!(None, got.details);
+}
+
+// Upstream, I/O and serde messages can be long, and name local paths. Clients
+// get the basename of absolute paths, and the start of long messages.
+fn sanitise_message(message: &str) -> String {
+    let message = URL.replace_all(message, |captures: &regex::Captures| {
+        let url = &captures[0];
+        match reqwest::Url::parse(url) {
+            Ok(parsed) if !is_internal_host(&parsed) => url.to_string(),
+            _ => "<internal URL>".to_string(),
+        }
+    });
+    let message = LOCAL_PATH.replace_all(&message, |captures: &regex::Captures| {
+        let start = captures.get(0).unwrap().start();
+        // Only absolute paths: the path of a URL follows its host.
+        match message[..start].chars().last() {
+            None => captures[1].to_string(),
+            Some(c) if c.is_whitespace() || "\"'(=[".contains(c) => captures[1].to_string(),
+            Some(_) => captures[0].to_string(),
+        }
+    });
+
+    match message.char_indices().nth(MAX_ERROR_MESSAGE_LENGTH) {
+        Some((end, _)) => format!("{}…", message[..end].trim_end()),
+        None => message.into_owned(),
+    }
+}
+
+// Whether `url` points to a loopback, private or link-local address, e.g. the
+// address of a mirror or converter on the self-hoster's network.
+fn is_internal_host(url: &reqwest::Url) -> bool {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let internal_ipv4 = |ip: Ipv4Addr| {
+        ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+    };
+    let Some(host) = url.host_str() else {
+        return true;
+    };
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(ip)) => internal_ipv4(ip),
+        Ok(IpAddr::V6(ip)) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip.to_ipv4_mapped().is_some_and(internal_ipv4)
+        }
+        // Single-label names, e.g. "calibre", only resolve on a local network.
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost"
+                || !domain.contains('.')
+                || [".localhost", ".local", ".internal", ".lan"]
+                    .iter()
+                    .any(|suffix| domain.ends_with(suffix))
+        }
+    }
+}
+
+#[test]
+fn test_sanitise_message() {
+    for (message, want) in [
+        ("not a valid URL", "not a valid URL"),
+        (
+            "No such file or directory: /var/lib/libreads/books/Alice.epub",
+            "No such file or directory: Alice.epub",
+        ),
+        (
+            "could not open \"/tmp/Alice in Wonderland.epub\"",
+            "could not open \"Alice in Wonderland.epub\"",
+        ),
+        (
+            r"access denied (C:\Users\me\Alice.epub)",
+            "access denied (Alice.epub)",
+        ),
+        ("/home/me/.cache/", ""),
+        (
+            "error sending request for url (http://10.0.0.3:8080/main/ABCD)",
+            "error sending request for url (<internal URL>)",
+        ),
+        (
+            "error sending request for url (http://localhost:8080/ipfs/bafk)",
+            "error sending request for url (<internal URL>)",
+        ),
+        (
+            "error sending request for url (http://[::1]/main/ABCD): timed out",
+            "error sending request for url (<internal URL>): timed out",
+        ),
+        (
+            "error sending request for url (http://calibre:8080/convert)",
+            "error sending request for url (<internal URL>)",
+        ),
+        (
+            "error sending request for url (http://library.lol/main/ABCD)",
+            "error sending request for url (http://library.lol/main/ABCD)",
+        ),
+        ("expected 1/2 of it", "expected 1/2 of it"),
+    ] {
+        assert_eq!(want, sanitise_message(message), "{:?}", message);
+    }
+
+    let long = "é".repeat(MAX_ERROR_MESSAGE_LENGTH + 1);
+    let got = sanitise_message(&long);
+    assert_eq!(MAX_ERROR_MESSAGE_LENGTH + 1, got.chars().count());
+    assert!(got.ends_with("é…"));
+    assert_eq!(
+        "é".repeat(MAX_ERROR_MESSAGE_LENGTH),
+        sanitise_message(&"é".repeat(MAX_ERROR_MESSAGE_LENGTH))
+    );
+}
+
+// ebook-convert dumps its whole output when it fails. It is logged with an id
+// for clients to report, rather than sent to them.
+fn conversion_failure(output: &str) -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    static FAILURES: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(FAILURES.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+    let id = format!("{:016x}", hasher.finish());
+
+    tracing::error!(error_id = %id, "Conversion error {}: {}", id, output);
+    format!("the conversion failed, see error {} in the server logs", id)
+}
+
+#[test]
+fn test_conversion_failure() {
+    let output = "Traceback (most recent call last):\n  File \"/usr/lib/calibre/ebook-convert\"";
+    let got = conversion_failure(output);
+    assert!(
+        got.starts_with("the conversion failed, see error "),
+        "{}",
+        got
+    );
+    assert!(!got.contains("Traceback"));
+    assert_ne!(got, conversion_failure(output));
+}
+
+impl From<libreads::Error> for Error {
+    fn from(err: libreads::Error) -> Self {
+        match err {
+            libreads::Error::HttpError(failure) => Error::from(failure),
+            libreads::Error::ApplicationError(message) => Error {
+                name: "application".to_string(),
+                message: sanitise_message(&message),
+                details: None,
+                retry_after: None,
+            },
+            libreads::Error::UpstreamUnavailable(message) => Error {
+                name: "unavailable".to_string(),
+                message: sanitise_message(&message),
+                details: None,
+                retry_after: None,
+            },
+            libreads::Error::InvalidInput(message) => Error {
+                name: "invalid input".to_string(),
+                message: sanitise_message(&message),
+                details: None,
+                retry_after: None,
+            },
+            libreads::Error::Conversion(err) => Error::from(err),
+            libreads::Error::Kindle(err) => Error::from(err),
+            // Reported as the last failure, listing all of them.
+            libreads::Error::AttemptsFailed(attempts) => {
+                let count = attempts.len();
+                let errors: Vec<(String, Error)> = attempts
+                    .into_iter()
+                    .map(|(md5, err)| (md5, Error::from(err)))
+                    .collect();
+                let summary = errors
+                    .iter()
+                    .map(|(md5, err)| format!("{}: {}", md5, err))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+
+                Error {
+                    name: errors
+                        .last()
+                        .map(|(_, err)| err.name.clone())
+                        .unwrap_or_default(),
+                    message: sanitise_message(&format!(
+                        "all {} editions failed ({})",
+                        count, summary
+                    )),
+                    details: None,
+                    retry_after: None,
+                }
+            }
+            libreads::Error::UnsupportedDoc { docx_md5 } => Error {
+                name: "unsupported".to_string(),
+                message: format!(
+                    "this server's Calibre cannot read DOC, try the DOCX edition {} instead",
+                    docx_md5
+                ),
+                details: Some(serde_json::json!({ "docx_md5": docx_md5 })),
+                retry_after: None,
+            },
+            libreads::Error::NotInLibrary(message) | libreads::Error::NoDownloadLinks(message) => {
+                Error {
+                    name: "not found".to_string(),
+                    message: sanitise_message(&message),
+                    details: None,
+                    retry_after: None,
+                }
+            }
+            libreads::Error::RateLimited { upstream, limit } => Error {
+                name: "unavailable".to_string(),
+                message: format!("{} {}", upstream, limit),
+                details: None,
+                retry_after: Some(limit.retry_after.unwrap_or(DEFAULT_RETRY_AFTER)),
+            },
+            libreads::Error::NotFound { searched, filters } => Error {
+                name: "not found".to_string(),
+                message: not_found_message(&searched, &filters),
+                details: Some(serde_json::json!({
+                    "searched": {
+                        "isbn10": searched.isbn10,
+                        "isbn13": searched.isbn13,
+                        "title": searched.title,
+                        "author": searched.author,
+                    },
+                    "filters": filters,
+                })),
+                retry_after: None,
+            },
+        }
+    }
+}
+
+// E.g. "nothing found on LibGen for this book (ISBN-13 9780141439518): 3
+// results, 1 duplicate, 2 without a known extension".
+fn not_found_message(searched: &BookIdentification, filters: &FilterReport) -> String {
+    let identifiers = [
+        ("ISBN-10", &searched.isbn10),
+        ("ISBN-13", &searched.isbn13),
+        ("title", &searched.title),
+        ("author", &searched.author),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.as_ref().map(|value| format!("{} {}", name, value)))
+    .collect::<Vec<_>>()
+    .join(", ");
+
+    let mut reasons = vec![format!("{} results", filters.results)];
+    for (count, reason) in [
+        (filters.duplicates, "duplicate"),
+        (filters.unknown_extension, "without a known extension"),
+        (filters.djvu_excluded, "DJVU that can't be converted"),
+    ] {
+        if count > 0 {
+            reasons.push(format!("{} {}", count, reason));
+        }
+    }
+    if filters.filtered_out > 0 {
+        reasons.push(match filters.emptied_by {
+            Some(filter) => format!(
+                "{} left out by the filters, the last ones by {}",
+                filters.filtered_out, filter
+            ),
+            None => format!("{} left out by the filters", filters.filtered_out),
+        });
+    }
+
+    format!(
+        "nothing found on LibGen for this book ({}): {}",
+        identifiers,
+        reasons.join(", ")
+    )
+}
+
+#[test]
+fn test_error_from_libreads_error() {
+    for (err, want) in [
+        (
+            libreads::Error::HttpError(HttpFailure::other("something bad")),
+            "upstream: something bad",
+        ),
+        (
+            libreads::Error::ApplicationError("oh no".to_string()),
+            "application: oh no",
+        ),
+        (
+            libreads::Error::UpstreamUnavailable("LibGen is down".to_string()),
+            "unavailable: LibGen is down",
+        ),
+        (
+            libreads::Error::RateLimited {
+                upstream: "Goodreads".to_string(),
+                limit: crate::http_client::RateLimit {
+                    status: 429,
+                    retry_after: Some(Duration::from_secs(30)),
+                },
+            },
+            "unavailable: Goodreads rate limited (429), retry in 30 seconds",
+        ),
+        (
+            libreads::Error::InvalidInput("unsupported URL".to_string()),
+            "invalid input: unsupported URL",
+        ),
+        (
+            libreads::Error::Conversion(convert::Error::DrmProtected),
+            "drm protected: the book is DRM-protected or encrypted, so it cannot be converted",
+        ),
+        (
+            libreads::Error::AttemptsFailed(vec![
+                ("A".to_string(), libreads::Error::HttpError(HttpFailure::other("404"))),
+                (
+                    "B".to_string(),
+                    libreads::Error::Conversion(convert::Error::DrmProtected),
+                ),
+            ]),
+            "drm protected: all 2 editions failed (A: upstream: 404; B: drm protected: the book is DRM-protected or encrypted, so it cannot be converted)",
+        ),
+        (
+            libreads::Error::NotFound {
+                searched: Box::new(BookIdentification {
+                    isbn13: Some("9780141439518".to_string()),
+                    title: Some("Pride and Prejudice".to_string()),
+                    ..Default::default()
+                }),
+                filters: FilterReport {
+                    results: 3,
+                    duplicates: 1,
+                    unknown_extension: 2,
+                    djvu_excluded: 0,
+                    filtered_out: 0,
+                    emptied_by: None,
+                },
+            },
+            "not found: nothing found on LibGen for this book (ISBN-13 9780141439518, title Pride and Prejudice): 3 results, 1 duplicate, 2 without a known extension",
+        ),
+        (
+            libreads::Error::NotFound {
+                searched: Box::new(BookIdentification {
+                    isbn13: Some("9780141439518".to_string()),
+                    ..Default::default()
+                }),
+                filters: FilterReport {
+                    results: 3,
+                    duplicates: 0,
+                    unknown_extension: 0,
+                    djvu_excluded: 0,
+                    filtered_out: 3,
+                    emptied_by: Some(crate::selection::Filter::Lang),
+                },
+            },
+            "not found: nothing found on LibGen for this book (ISBN-13 9780141439518): 3 results, 3 left out by the filters, the last ones by lang",
+        ),
+    ] {
+        let got_err = Error::from(err);
+        assert_eq!(want, format!("{}", got_err))
+    }
+
+    // The DOCX edition is given to clients, to download it by MD5.
+    let got = Error::from(libreads::Error::UnsupportedDoc {
+        docx_md5: "DOCXMD5".to_string(),
+    });
+    assert_eq!(
+        "unsupported: this server's Calibre cannot read DOC, try the DOCX edition DOCXMD5 instead",
+        got.to_string()
+    );
+    assert_eq!(
+        Some(serde_json::json!({"docx_md5": "DOCXMD5"})),
+        got.details
+    );
+
+    // Long upstream messages are cut.
+    let got = Error::from(libreads::Error::HttpError(HttpFailure::other(format!(
+        "unexpected body: {}",
+        "<html>".repeat(1000)
+    ))));
+    assert_eq!(MAX_ERROR_MESSAGE_LENGTH + 1, got.message.chars().count());
+    assert!(got.message.ends_with('…'));
+}
+
+impl From<convert::Error> for Error {
+    fn from(err: convert::Error) -> Self {
+        match err {
+            convert::Error::Io(message) => Error {
+                name: "i/o".to_string(),
+                message: sanitise_message(&message),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::Http(failure) => Error::from(failure),
+            convert::Error::Conversion(output) => Error {
+                name: "conversion".to_string(),
+                message: conversion_failure(&output),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::ConversionTimeout(timeout) => Error {
+                name: "timeout".to_string(),
+                message: format!("the conversion took longer than {:?}", timeout),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::DrmProtected => Error {
+                name: "drm protected".to_string(),
+                message: "the book is DRM-protected or encrypted, so it cannot be converted"
+                    .to_string(),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::TooLarge { limit, seen } => Error {
+                name: "too large".to_string(),
+                message: format!(
+                    "the book is larger than the {} bytes limit (got {} bytes)",
+                    limit, seen
+                ),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::UpstreamUnavailable(message) => Error {
+                name: "unavailable".to_string(),
+                message: sanitise_message(&message),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::InvalidDownload(message) => Error {
+                name: "upstream".to_string(),
+                message: format!("the mirror did not send the book: {}", message),
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::Unsupported(message) => Error {
+                name: "unsupported".to_string(),
+                message,
+                details: None,
+                retry_after: None,
+            },
+            convert::Error::UnrarMissing => Error {
+                name: "missing dependency".to_string(),
+                message: "CBR comics need Calibre with unrar support on the server".to_string(),
+                details: None,
+                retry_after: None,
+            },
+        }
+    }
+}
+
+impl From<UnsupportedConversion> for Error {
+    fn from(err: UnsupportedConversion) -> Self {
+        Error {
+            name: "unsupported conversion".to_string(),
+            message: err.reason,
+            details: Some(serde_json::json!({
+                "source": err.source,
+                "requested": err.requested,
+                "available_formats": err.available_formats,
+            })),
+            retry_after: None,
+        }
+    }
+}
+
+impl From<kindle::Error> for Error {
+    fn from(err: kindle::Error) -> Self {
+        let name = match err {
+            kindle::Error::TooLarge { .. } => "attachment too large",
+            kindle::Error::Smtp(_) => "upstream",
+            kindle::Error::InvalidAddress(_) => "application",
+            kindle::Error::Io(_) => "i/o",
+        };
+        Error {
+            name: name.to_string(),
+            message: sanitise_message(&err.to_string()),
+            details: None,
+            retry_after: None,
+        }
+    }
+}
+
+#[test]
+fn test_error_from_kindle_error() {
+    for (err, want) in [
+        (
+            kindle::Error::TooLarge {
+                limit: 50,
+                size: 60,
+            },
+            "attachment too large: the book is 60 bytes, over the 50 bytes Send to Kindle attachment limit",
+        ),
+        (
+            kindle::Error::Smtp("535 authentication failed".to_string()),
+            "upstream: could not send the email: 535 authentication failed",
+        ),
+    ] {
+        assert_eq!(want, Error::from(err).to_string());
+    }
+}
+
+#[test]
+fn test_error_from_convert_error() {
+    for (err, want) in [
+        (convert::Error::Io("failure".to_string()), "i/o: failure"),
+        (
+            convert::Error::Io(
+                "No such file or directory (os error 2): /srv/libreads/Book.epub".to_string(),
+            ),
+            "i/o: No such file or directory (os error 2): Book.epub",
+        ),
+        (
+            convert::Error::Http(HttpFailure::other("failure!!1")),
+            "upstream: failure!!1",
+        ),
+        (
+            convert::Error::ConversionTimeout(std::time::Duration::from_secs(600)),
+            "timeout: the conversion took longer than 600s",
+        ),
+        (
+            convert::Error::DrmProtected,
+            "drm protected: the book is DRM-protected or encrypted, so it cannot be converted",
+        ),
+        (
+            convert::Error::TooLarge {
+                limit: 1024,
+                seen: 2048,
+            },
+            "too large: the book is larger than the 1024 bytes limit (got 2048 bytes)",
+        ),
+        (
+            convert::Error::UnrarMissing,
+            "missing dependency: CBR comics need Calibre with unrar support on the server",
+        ),
+        (
+            convert::Error::Unsupported("this server's Calibre cannot read DJVU".to_string()),
+            "unsupported: this server's Calibre cannot read DJVU",
+        ),
+    ] {
+        let got_err = Error::from(err);
+        assert_eq!(want, format!("{}", got_err))
+    }
+
+    // The output of ebook-convert is only logged.
+    let got = Error::from(convert::Error::Conversion(
+        "Traceback (most recent call last): unknown format provided".to_string(),
+    ));
+    assert_eq!("conversion", got.name);
+    assert!(!got.message.contains("Traceback"), "{}", got.message);
+}
+
+impl From<import::Error> for Error {
+    fn from(err: import::Error) -> Self {
+        Error {
+            name: "invalid input".to_string(),
+            message: sanitise_message(&err.to_string()),
+            details: None,
+            retry_after: None,
+        }
+    }
+}
+
+impl From<queue::Error> for Error {
+    fn from(err: queue::Error) -> Self {
+        match err {
+            queue::Error::Full { .. } => Error {
+                name: "unavailable".to_string(),
+                message: err.to_string(),
+                details: None,
+                retry_after: Some(DEFAULT_RETRY_AFTER),
+            },
+            queue::Error::Journal(err) => Error::from(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error {
+            name: "i/o".to_string(),
+            message: sanitise_message(&err.to_string()),
+            details: None,
+            retry_after: None,
+        }
+    }
+}
+
+#[test]
+fn test_error_from_queue_error() {
+    use actix_web::ResponseError;
+
+    let err = Error::from(queue::Error::Full { waiting: 1_000 });
+    assert_eq!(503, err.status_code().as_u16());
+    assert_eq!(Some(DEFAULT_RETRY_AFTER), err.retry_after);
+    assert_eq!(
+        "unavailable: the queue is full: 1000 books are waiting, at most 1000",
+        err.to_string()
+    );
+}
+
+#[test]
+fn test_error_from_stdio_error() {
+    let got_err: Error = std::io::Error::new(std::io::ErrorKind::AddrInUse, "big failure").into();
+    assert_eq!("i/o: big failure", format!("{}", got_err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DEFAULT_LINKS_PREFETCH, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_EDITIONS};
+    use crate::{
+        goodreads::{MockBookIdentificationGetter, MockShelfGetter, ShelfEntry},
+        libgen::{LibgenMetadata, MockMetadataStore},
+        library_dot_lol::{DownloadLinks, MockDownloadLinksStore},
+        libreads::{LibReads, MockBookService, SelectionReport},
+    };
+    use actix_web::body::BodySize;
+    use actix_web::http::header::{
+        HeaderName, HeaderValue, ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_ENCODING,
+        CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER,
+    };
+    use futures_util::StreamExt;
+    use httpmock::{Method::GET, MockServer};
+    use mockall::predicate::{always, eq};
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    };
+
+    const GOODREADS_URL: &str = "https://www.goodreads.com/book/show/1048424.Governing_the_Commons";
+
+    // Goes through the real LibReads, with only its upstreams mocked. The
+    // other tests mock LibReads as a whole.
+    #[actix_web::test]
+    async fn test_download() {
+        let mock_download_server = MockServer::start();
+        let endpoint_mock = mock_download_server.mock(|when, then| {
+            when.method(GET).path("/book.mobi");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
+        });
+        let url = mock_download_server.url("/book.mobi").to_owned();
+        let download_link: &'static str = Box::leak(url.into_boxed_str()); // Leaks memory!! TODO: find another way to do this.
+
+        let mock_goodreads_url = web::Path::from(GOODREADS_URL.to_string());
+        let mock_libreads: web::Data<dyn BookService> =
+            web::Data::from(Arc::new(get_mock_libreads(download_link)) as Arc<_>);
+
+        let resp = download(
+            mock_libreads,
+            web::Data::new(Config::default()),
+            None,
+            None,
+            mock_goodreads_url,
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        let cd = resp.headers().get(CONTENT_DISPOSITION).unwrap();
+        assert_eq!(r#"attachment; filename="hello.mobi""#, cd);
+
+        let ct = resp.headers().get(CONTENT_TYPE).unwrap();
+        assert_eq!("application/x-mobipocket-ebook", ct);
+
+        let formats = resp.headers().get(X_AVAILABLE_FORMATS).unwrap();
+        assert_eq!("mobi", formats);
+
+        assert_eq!("MYBOOKMD5", resp.headers().get(X_LIBREADS_MD5).unwrap());
+        assert_eq!("HTTP", resp.headers().get(X_LIBREADS_SOURCE).unwrap());
+        assert_eq!(
+            "mobi",
+            resp.headers().get(X_LIBREADS_ORIGINAL_EXTENSION).unwrap()
+        );
+
+        // Headers are sent before downloading the book, so the download and
+        // conversion timings aren't known yet.
+        assert_server_timing(
+            resp.headers().get(SERVER_TIMING),
+            &["goodreads", "libgen", "links"],
+        );
+
+        let body = actix_web::body::to_bytes(resp.into_body())
+            .await
+            .expect("the body should be streamed");
+        assert_eq!(
+            include_bytes!("../tests/testdata/dummy_ebook.mobi").as_slice(),
+            body
+        );
+
+        // Local file has been deleted
+        assert!(!Path::new("hello.mobi").exists());
+        endpoint_mock.assert();
+    }
+
+    const MD5: &str = "ab13556b96d473c8dfad7165c4704526";
+
+    // A LibReads that can only find the links and metadata of `MD5`.
+    fn md5_libreads(
+        links: Result<DownloadLinks, crate::library_dot_lol::Error>,
+        metadata: Option<LibgenMetadata>,
+    ) -> web::Data<dyn BookService> {
+        let mut download_links_store_mock = MockDownloadLinksStore::new();
+        download_links_store_mock
+            .expect_get_download_links()
+            .with(eq(MD5))
+            .return_once(move |_| Box::pin(async move { links }));
+        let mut metadata_store_mock = MockMetadataStore::new();
+        metadata_store_mock
+            .expect_get_metadata_by_md5()
+            .with(eq(MD5))
+            .return_once(move |_| Box::pin(async move { Ok(metadata) }));
+
+        let libreads = LibReads {
+            isbn_getter: Box::new(MockBookIdentificationGetter::new()),
+            shelf_getter: Box::new(MockShelfGetter::new()),
+            metadata_store: Box::new(metadata_store_mock),
+            download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(crate::ranking::DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: crate::http_client::ConnectionLimits::default(),
+        };
+        web::Data::from(Arc::new(libreads) as Arc<dyn BookService>)
+    }
+
+    async fn call_download_by_md5(
+        libreads: web::Data<dyn BookService>,
+        md5: &str,
+        query: DownloadQuery,
+    ) -> Result<HttpResponse, Error> {
+        download_by_md5(
+            libreads,
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(md5.to_string()),
+            web::Query(query),
+        )
+        .await
+    }
+
+    #[actix_web::test]
+    async fn test_download_by_md5() {
+        let mock_download_server = MockServer::start();
+        let endpoint_mock = mock_download_server.mock(|when, then| {
+            when.method(GET).path("/book.mobi");
+            then.status(200)
+                .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
+        });
+        let libreads = md5_libreads(
+            Ok(DownloadLinks {
+                http: mock_download_server.url("/book.mobi"),
+                ..DownloadLinks::default()
+            }),
+            None,
+        );
+
+        // Neither Goodreads nor LibGen are searched: the title comes from
+        // the query, and the format from the link.
+        let resp = call_download_by_md5(
+            libreads,
+            &MD5.to_uppercase(),
+            DownloadQuery {
+                title: Some("Emma".to_string()),
+                author: Some("Jane Austen".to_string()),
+                ..DownloadQuery::default()
+            },
+        )
+        .await
+        .expect("the call should succeed");
+
+        let cd = resp.headers().get(CONTENT_DISPOSITION).unwrap();
+        assert_eq!(r#"attachment; filename="Emma.mobi""#, cd);
+        assert_eq!(MD5, resp.headers().get(X_LIBREADS_MD5).unwrap());
+        assert_eq!("mobi", resp.headers().get(X_AVAILABLE_FORMATS).unwrap());
+        assert!(resp.headers().get(SERVER_TIMING).is_none());
+
+        let body = actix_web::body::to_bytes(resp.into_body())
+            .await
+            .expect("the body should be streamed");
+        assert_eq!(
+            include_bytes!("../tests/testdata/dummy_ebook.mobi").as_slice(),
+            body
+        );
+        endpoint_mock.assert();
+    }
+
+    #[actix_web::test]
+    async fn test_download_by_md5_invalid_input() {
+        for (md5, query) in [
+            ("nope", DownloadQuery::default()),
+            ("ab13556b96d473c8dfad7165c470452", DownloadQuery::default()),
+            ("zz13556b96d473c8dfad7165c4704526", DownloadQuery::default()),
+            (
+                MD5,
+                DownloadQuery {
+                    dry_run: true,
+                    ..DownloadQuery::default()
+                },
+            ),
+        ] {
+            let err =
+                call_download_by_md5(md5_libreads(Ok(DownloadLinks::default()), None), md5, query)
+                    .await
+                    .unwrap_err();
+
+            assert_eq!("invalid input", err.name, "{}", md5);
+            assert_eq!(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                actix_web::ResponseError::status_code(&err)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_by_md5_links_not_found() {
+        for (err, want) in [
+            (
+                crate::library_dot_lol::Error::MissingDownloadLinks("library.lol".to_string()),
+                actix_web::http::StatusCode::NOT_FOUND,
+            ),
+            (
+                crate::library_dot_lol::Error::HttpError(HttpFailure::other(
+                    "503 Service Unavailable",
+                )),
+                actix_web::http::StatusCode::BAD_GATEWAY,
+            ),
+        ] {
+            let got =
+                call_download_by_md5(md5_libreads(Err(err), None), MD5, DownloadQuery::default())
+                    .await
+                    .unwrap_err();
+
+            assert_eq!(want, actix_web::ResponseError::status_code(&got), "{}", got);
+        }
+    }
+
+    fn expect_book_file(
+        service: &mut MockBookService,
+        extension: Extension,
+        name: &str,
+    ) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let converted = path.clone();
+        service
+            .expect_book_file()
+            .withf(move |_, wanted_extension, _| *wanted_extension == extension)
+            .once()
+            .returning(move |_, _, _| {
+                let path = converted.clone();
+                Box::pin(async move {
+                    std::fs::copy("tests/testdata/dummy_ebook.mobi", &path).unwrap();
+                    Ok(conversion_result(path))
+                })
+            });
+        path
+    }
+
+    #[cfg(feature = "convert")]
+    #[actix_web::test]
+    async fn test_download_to_kindle() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        let path = expect_book_file(&mut service, Extension::Epub, "libreads_web_kindle.epub");
+        let sent = path.clone();
+        service
+            .expect_send_to_kindle()
+            .withf(move |result| result.path == sent && result.path.exists())
+            .once()
+            .returning(|result| {
+                let delivery = kindle::Delivery {
+                    to: "me_123@kindle.com".to_string(),
+                    filename: "hello.mobi".to_string(),
+                    bytes: result.bytes,
+                };
+                Box::pin(async move { Ok(delivery) })
+            });
+
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                deliver: Some("kindle".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(actix_web::http::StatusCode::OK, resp.status());
+        assert!(resp.headers().get(CONTENT_DISPOSITION).is_none());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "delivered_to": "me_123@kindle.com",
+                "filename": "hello.mobi",
+                "bytes": include_bytes!("../tests/testdata/dummy_ebook.mobi").len(),
+                "md5": "MYBOOKMD5",
+                "extension": "mobi",
+            }),
+            body
+        );
+        // The converted book isn't kept.
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "convert")]
+    #[actix_web::test]
+    async fn test_download_to_kindle_too_large() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        let path = expect_book_file(
+            &mut service,
+            Extension::Pdf,
+            "libreads_web_kindle_too_large.pdf",
+        );
+        service.expect_send_to_kindle().once().returning(|_| {
+            Box::pin(async {
+                Err(libreads::Error::Kindle(kindle::Error::TooLarge {
+                    limit: kindle::MAX_ATTACHMENT_SIZE,
+                    size: 60 * 1024 * 1024,
+                }))
+            })
+        });
+
+        let err = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                format: Some("pdf".to_string()),
+                deliver: Some("kindle".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!("attachment too large", err.name);
+        assert!(!path.exists());
+    }
+
+    #[actix_web::test]
+    async fn test_download_unknown_delivery() {
+        // Rejected before looking the book up.
+        let err = download(
+            service_data(MockBookService::new()),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                deliver: Some("kobo".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!("invalid input", err.name);
+    }
+
+    #[cfg(feature = "convert")]
+    #[actix_web::test]
+    async fn test_download_streams_after_slow_conversion() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "slow"));
+        let path = std::env::temp_dir().join("libreads_web_slow.mobi");
+        let converted = path.clone();
+        service
+            .expect_book_file()
+            .withf(|_, extension, options| {
+                *extension == Extension::Mobi && options.output_profile.is_none()
+            })
+            .once()
+            .returning(move |_, _, _| {
+                let path = converted.clone();
+                Box::pin(async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    std::fs::copy("tests/testdata/dummy_ebook.epub", &path).unwrap();
+                    Ok(conversion_result(path))
+                })
+            });
+
+        let start = std::time::Instant::now();
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        // The response starts before the conversion is done.
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let cd = resp.headers().get(CONTENT_DISPOSITION).unwrap();
+        assert_eq!(r#"attachment; filename="slow.mobi""#, cd);
+
+        let body = actix_web::body::to_bytes(resp.into_body())
+            .await
+            .expect("the body should be streamed");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(
+            include_bytes!("../tests/testdata/dummy_ebook.epub").as_slice(),
+            body
+        );
+
+        // The converted file is deleted once streamed.
+        assert!(!path.exists());
+    }
+
+    #[actix_web::test]
+    async fn test_download_conversion_error_aborts_body() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "broken"));
+        service.expect_book_file().once().returning(|_, _, _| {
+            Box::pin(async {
+                Err(libreads::Error::Conversion(convert::Error::Conversion(
+                    "traceback".to_string(),
+                )))
+            })
+        });
+
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the headers are sent before the conversion");
+        assert_eq!(actix_web::http::StatusCode::OK, resp.status());
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await;
+        assert!(body.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_download_language_warning() {
+        for (language_check, want_language, want_warning) in [
+            (
+                true,
+                Some("english".to_string()),
+                Some("the book seems to be in russian (97% confident), not in english"),
+            ),
+            (false, None, None),
+        ] {
+            let mut service = MockBookService::new();
+            expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+            let path =
+                std::env::temp_dir().join(format!("libreads_web_language_{}.mobi", language_check));
+            let converted = path.clone();
+            service
+                .expect_book_file()
+                .withf(move |_, _, options| options.language == want_language)
+                .once()
+                .returning(move |_, _, options| {
+                    let path = converted.clone();
+                    let checked = options.language.is_some();
+                    Box::pin(async move {
+                        std::fs::copy("tests/testdata/dummy_ebook.mobi", &path).unwrap();
+                        Ok(convert::ConversionResult {
+                            md5: "RUSSIANMD5".to_string(),
+                            language_mismatch: checked.then(|| {
+                                crate::language_check::LanguageMismatch {
+                                    requested: "english".to_string(),
+                                    detected: "russian".to_string(),
+                                    confidence: 0.97,
+                                }
+                            }),
+                            ..conversion_result(path)
+                        })
+                    })
+                });
+
+            let resp = download(
+                service_data(service),
+                web::Data::new(Config {
+                    language_check,
+                    ..Config::default()
+                }),
+                None,
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(DownloadQuery {
+                    format: Some("mobi".to_string()),
+                    lang: Some("en".to_string()),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .expect("the call should succeed");
+
+            assert_eq!(actix_web::http::StatusCode::OK, resp.status());
+            let header = |name: &str| {
+                resp.headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+            assert_eq!(
+                want_warning.map(str::to_string),
+                header("x-libreads-warning")
+            );
+            // Checked books are downloaded before the headers are sent, so
+            // they name the edition served.
+            let want_md5 = match language_check {
+                true => "RUSSIANMD5",
+                false => "MYBOOKMD5",
+            };
+            assert_eq!(Some(want_md5.to_string()), header("x-libreads-md5"));
+            let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            assert_eq!(
+                std::fs::read("tests/testdata/dummy_ebook.mobi").unwrap(),
+                body
+            );
+            assert!(!path.exists());
+        }
+    }
+
+    // Makes the book service convert to a file of a few chunks.
+    fn expect_large_book_file(service: &mut MockBookService, name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let converted = path.clone();
+        service.expect_book_file().once().returning(move |_, _, _| {
+            let path = converted.clone();
+            Box::pin(async move {
+                std::fs::write(&path, vec![b'a'; 3 * STREAM_CHUNK_SIZE + 10]).unwrap();
+                Ok(conversion_result(path))
+            })
+        });
+        path
+    }
+
+    #[actix_web::test]
+    async fn test_stream_book_completed() {
+        let mut service = MockBookService::new();
+        let path = expect_large_book_file(&mut service, "libreads_web_completed.mobi");
+
+        let chunks: Vec<_> = stream_book(
+            service_data(service),
+            Recorder::new(None, BookRequest::default()),
+            book_info(Extension::Epub, "completed"),
+            Extension::Mobi,
+            ConversionOptions::default(),
+            None,
+        )
+        .collect()
+        .await;
+
+        let sizes: Vec<usize> = chunks
+            .into_iter()
+            .map(|chunk| chunk.expect("Should stream the book").len())
+            .collect();
+        assert_eq!(
+            vec![STREAM_CHUNK_SIZE, STREAM_CHUNK_SIZE, STREAM_CHUNK_SIZE, 10],
+            sizes
+        );
+        // Deleted as soon as the last chunk is read.
+        assert!(!path.exists());
+    }
+
+    // The Content-Length was sent already: a file of another size isn't
+    // served.
+    #[actix_web::test]
+    async fn test_stream_book_changed_size() {
+        let mut service = MockBookService::new();
+        let path = expect_large_book_file(&mut service, "libreads_web_changed_size.mobi");
+
+        let chunks: Vec<_> = stream_book(
+            service_data(service),
+            Recorder::new(None, BookRequest::default()),
+            book_info(Extension::Epub, "changed"),
+            Extension::Mobi,
+            ConversionOptions::default(),
+            Some(10),
+        )
+        .collect()
+        .await;
+
+        assert_eq!(1, chunks.len());
+        assert!(chunks[0].is_err());
+        assert!(!path.exists());
+    }
+
+    // A client hanging up drops the body stream midway: the file is kept
+    // while it is read, and deleted once dropped.
+    #[actix_web::test]
+    async fn test_stream_book_dropped() {
+        let mut service = MockBookService::new();
+        let path = expect_large_book_file(&mut service, "libreads_web_dropped.mobi");
+
+        let mut stream = Box::pin(stream_book(
+            service_data(service),
+            Recorder::new(None, BookRequest::default()),
+            book_info(Extension::Epub, "dropped"),
+            Extension::Mobi,
+            ConversionOptions::default(),
+            None,
+        ));
+        let chunk = stream.next().await.unwrap().unwrap();
+        assert_eq!(STREAM_CHUNK_SIZE, chunk.len());
+        assert!(path.exists());
+
+        drop(stream);
+        for _ in 0..100 {
+            if !path.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!path.exists());
+    }
+
+    #[actix_web::test]
+    async fn test_download_archive() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Pdf, "archive"));
+        let downloaded = std::env::temp_dir().join("libreads_web_archive.pdf");
+        let written = downloaded.clone();
+        // Downloaded once, as a PDF.
+        service
+            .expect_book_file()
+            .withf(|book_info, extension, _| {
+                *extension == Extension::Pdf
+                    && !book_info.download_links.http.starts_with("file://")
+            })
+            .once()
+            .returning(move |_, _, _| {
+                let path = written.clone();
+                Box::pin(async move {
+                    std::fs::write(&path, "original pdf").unwrap();
+                    Ok(conversion_result(path))
+                })
+            });
+        // Then converted from the downloaded file.
+        service
+            .expect_book_file()
+            .withf(|book_info, extension, _| {
+                *extension == Extension::Epub
+                    && book_info.download_links.http.starts_with("file://")
+                    && book_info.alternatives.is_empty()
+            })
+            .once()
+            .returning(|book_info, _, _| {
+                let original = reqwest::Url::parse(&book_info.download_links.http)
+                    .unwrap()
+                    .to_file_path()
+                    .unwrap();
+                let path = std::env::temp_dir().join("libreads_web_archive.epub");
+                Box::pin(async move {
+                    let content = std::fs::read_to_string(original).unwrap();
+                    std::fs::write(&path, format!("epub of the {}", content)).unwrap();
+                    Ok(conversion_result(path))
+                })
+            });
+        service
+            .expect_book_file()
+            .withf(|_, extension, _| *extension == Extension::Mobi)
+            .once()
+            .returning(|_, _, _| {
+                Box::pin(async {
+                    Err(libreads::Error::Conversion(convert::Error::Conversion(
+                        "Traceback".to_string(),
+                    )))
+                })
+            });
+
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                format: Some("epub,pdf,original,mobi".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(
+            r#"attachment; filename="archive.zip""#,
+            resp.headers().get(CONTENT_DISPOSITION).unwrap()
+        );
+        assert_eq!("application/zip", resp.headers().get(CONTENT_TYPE).unwrap());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut zip.by_name(name).unwrap(), &mut content).unwrap();
+            content
+        };
+        assert_eq!("epub of the original pdf", read("archive.epub"));
+        assert_eq!("original pdf", read("archive.pdf"));
+        let errors = read(archive::ERRORS_FILENAME);
+        assert!(
+            errors.starts_with("mobi: the conversion failed"),
+            "{}",
+            errors
+        );
+        assert_eq!(
+            vec!["archive.epub", "archive.pdf", archive::ERRORS_FILENAME],
+            zip.file_names().collect::<Vec<_>>()
+        );
+
+        // Nothing is left behind.
+        assert!(!downloaded.exists());
+        assert!(!std::env::temp_dir()
+            .join("libreads_web_archive.original.pdf")
+            .exists());
+        assert!(!std::env::temp_dir()
+            .join("libreads_web_archive.epub")
+            .exists());
+        assert!(!Path::new("archive.zip").exists());
+    }
+
+    #[actix_web::test]
+    async fn test_download_archive_invalid_input() {
+        for (format, deliver) in [
+            ("epub,doc", None),
+            ("epub,,pdf", None),
+            ("epub,pdf", Some("kindle".to_string())),
+        ] {
+            let err = download(
+                service_data(MockBookService::new()),
+                web::Data::new(Config::default()),
+                None,
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(DownloadQuery {
+                    format: Some(format.to_string()),
+                    deliver,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!("invalid input", err.name, "{}", format);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_passes_device_settings() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "hello"));
+        let path = std::env::temp_dir().join("libreads_web_device.epub");
+        let converted = path.clone();
+        service
+            .expect_book_file()
+            .withf(|_, extension, options| {
+                *extension == Extension::Epub
+                    && options.output_profile.as_deref() == Some("kobo")
+                    && options.max_download_size == Some(Config::default().max_download_size)
+            })
+            .once()
+            .returning(move |_, _, _| {
+                let path = converted.clone();
+                Box::pin(async move {
+                    std::fs::copy("tests/testdata/dummy_ebook.epub", &path).unwrap();
+                    Ok(conversion_result(path))
+                })
+            });
+
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                device: Some("kobo".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the call should succeed");
+        actix_web::body::to_bytes(resp.into_body())
+            .await
+            .expect("the body should be streamed");
+
+        assert!(!path.exists());
+    }
+
+    #[actix_web::test]
+    async fn test_download_head() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        let path = std::env::temp_dir().join("libreads_web_head.mobi");
+        let converted = path.clone();
+        // Only the GET request downloads the book.
+        service.expect_book_file().once().returning(move |_, _, _| {
+            let path = converted.clone();
+            Box::pin(async move {
+                std::fs::copy("tests/testdata/dummy_ebook.mobi", &path).unwrap();
+                Ok(conversion_result(path))
+            })
+        });
+        let service = service_data(service);
+
+        let head_resp = download_head(
+            service.clone(),
+            web::Data::new(Config::default()),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the HEAD call should succeed");
+        assert!(head_resp.headers().get(CONTENT_LENGTH).is_none());
+
+        let get_resp = download(
+            service,
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the GET call should succeed");
+        let get_headers = get_resp.headers().clone();
+        actix_web::body::to_bytes(get_resp.into_body())
+            .await
+            .expect("the body should be streamed");
+
+        for header in [
+            CONTENT_DISPOSITION,
+            CONTENT_TYPE,
+            HeaderName::from_static("x-available-formats"),
+            HeaderName::from_static("x-libreads-md5"),
+            HeaderName::from_static("x-libreads-source"),
+            HeaderName::from_static("x-libreads-original-extension"),
+        ] {
+            assert_eq!(get_headers.get(&header), head_resp.headers().get(&header));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_head_library_book() {
+        let stored = std::env::temp_dir().join("libreads_web_head_library.mobi");
+        std::fs::copy("tests/testdata/dummy_ebook.mobi", &stored).unwrap();
+        let length = std::fs::metadata(&stored).unwrap().len();
+        let mut book = book_info(Extension::Mobi, "hello");
+        book.download_links = DownloadLinks {
+            ipfs_cid: None,
+            gateways: vec![],
+            http: reqwest::Url::from_file_path(&stored).unwrap().to_string(),
+            filename: None,
+        };
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book);
+        let copy = std::env::temp_dir().join("libreads_web_head_library_copy.mobi");
+        let (source, copied) = (stored.clone(), copy.clone());
+        service.expect_book_file().once().returning(move |_, _, _| {
+            let (source, path) = (source.clone(), copied.clone());
+            Box::pin(async move {
+                std::fs::copy(&source, &path).unwrap();
+                Ok(conversion_result(path))
+            })
+        });
+        let service = service_data(service);
+
+        // The size of a book served as it is stored is known beforehand.
+        let head_resp = download_head(
+            service.clone(),
+            web::Data::new(Config::default()),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the HEAD call should succeed");
+        assert_eq!(BodySize::Sized(length), head_resp.body().size());
+
+        let get_resp = download(
+            service,
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await
+        .expect("the GET call should succeed");
+        assert_eq!(BodySize::Sized(length), get_resp.body().size());
+        let body = actix_web::body::to_bytes(get_resp.into_body())
+            .await
+            .expect("the body should be streamed");
+        assert_eq!(length, body.len() as u64);
+        assert!(!copy.exists());
+        std::fs::remove_file(stored).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_download_head_unsupported_conversion() {
+        // Calibre is there, but not DjVuLibre.
+        let capabilities = ConverterCapabilities::new(
+            Some(crate::capabilities::CalibreVersion {
+                major: 7,
+                minor: 4,
+                patch: 0,
+            }),
+            false,
+        );
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Djvu, "hello"));
+        service.expect_book_file().never();
+        let service = service_data(service);
+        let query = || {
+            web::Query(DownloadQuery {
+                format: Some("epub".to_string()),
+                ..Default::default()
+            })
+        };
+
+        let head_err = download_head(
+            service.clone(),
+            web::Data::new(Config::default()),
+            Some(web::Data::new(capabilities.clone())),
+            web::Path::from(GOODREADS_URL.to_string()),
+            query(),
+        )
+        .await
+        .expect_err("the HEAD call should reject the conversion");
+        let get_err = download(
+            service,
+            web::Data::new(Config::default()),
+            Some(web::Data::new(capabilities)),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            query(),
+        )
+        .await
+        .expect_err("the GET call should reject the conversion");
+
+        assert_eq!(
+            actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            actix_web::ResponseError::status_code(&head_err)
+        );
+        assert_eq!(get_err.name, head_err.name);
+        assert_eq!(get_err.details, head_err.details);
+    }
+
+    #[actix_web::test]
+    async fn test_download_head_delivery() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "hello"));
+        // Nothing is downloaded nor sent.
+        service.expect_book_file().never();
+        service.expect_send_to_kindle().never();
+
+        let head_resp = download_head(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                deliver: Some("kindle".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the HEAD call should succeed");
+
+        assert_eq!(
+            "application/json",
+            head_resp.headers().get(CONTENT_TYPE).unwrap()
+        );
+        assert!(head_resp.headers().get(CONTENT_DISPOSITION).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_download_error() {
+        let mut service = MockBookService::new();
+        service
+            .expect_book_info()
+            .with(eq(GOODREADS_URL), always(), always())
+            .once()
+            .returning(|_, _, _| {
+                Box::pin(async { Err(libreads::Error::HttpError(HttpFailure::other("timeout"))) })
+            });
+        service.expect_book_file().never();
+
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery::default()),
+        )
+        .await;
+        assert!(resp.is_err())
+    }
+
+    #[actix_web::test]
+    async fn test_download_disallowed_format() {
+        for format in ["exe", "doc", "cbz"] {
+            // Any call to the service would fail the test.
+            let service = service_data(MockBookService::new());
+            let query = DownloadQuery {
+                format: Some(format.to_string()),
+                ..Default::default()
+            };
+
+            let err = download(
+                service.clone(),
+                web::Data::new(Config::default()),
+                None,
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(query.clone()),
+            )
+            .await
+            .expect_err("the format should be rejected");
+            assert_eq!(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                actix_web::ResponseError::status_code(&err)
+            );
+
+            let err = download_head(
+                service,
+                web::Data::new(Config::default()),
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(query),
+            )
+            .await
+            .expect_err("the format should be rejected");
+            assert_eq!(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                actix_web::ResponseError::status_code(&err)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_original_format() {
+        let disabled = Config {
+            conversion_enabled: false,
+            ..Config::default()
+        };
+        for (config, format) in [
+            (Config::default(), Some("original")),
+            // Books are served as they are by default without conversion.
+            (disabled.clone(), None),
+            (disabled, Some("ORIGINAL")),
+        ] {
+            let mut service = MockBookService::new();
+            let book = book_info(Extension::Epub, "original");
+            service
+                .expect_book_info()
+                .withf(|url, extension, _| url == GOODREADS_URL && extension.is_none())
+                .once()
+                .returning(move |_, _, _| {
+                    let book = book.clone();
+                    Box::pin(async move { Ok((book, Timings::default())) })
+                });
+            let path =
+                expect_book_file(&mut service, Extension::Epub, "libreads_web_original.epub");
+
+            let resp = download(
+                service_data(service),
+                web::Data::new(config),
+                None,
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(DownloadQuery {
+                    format: format.map(str::to_string),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .expect("the call should succeed");
+
+            let cd = resp.headers().get(CONTENT_DISPOSITION).unwrap();
+            assert_eq!(r#"attachment; filename="original.epub""#, cd);
+            actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+            assert!(!path.exists());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_conversion_disabled() {
+        let config = web::Data::new(Config {
+            conversion_enabled: false,
+            ..Config::default()
+        });
+        let query = DownloadQuery {
+            format: Some("mobi".to_string()),
+            ..Default::default()
+        };
+
+        // Nothing is converted, even with Calibre there.
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "hello"));
+        service.expect_book_file().never();
+        let err = download(
+            service_data(service),
+            config.clone(),
+            Some(web::Data::new(ConverterCapabilities::new(
+                Some(crate::capabilities::CalibreVersion {
+                    major: 7,
+                    minor: 4,
+                    patch: 0,
+                }),
+                true,
+            ))),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(query.clone()),
+        )
+        .await
+        .expect_err("the conversion should be rejected");
+        assert_eq!(
+            actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            actix_web::ResponseError::status_code(&err)
+        );
+        assert_eq!(
+            "conversion is disabled on this server: cannot convert EPUB to MOBI, ask for format=original",
+            err.message
+        );
+        assert_eq!(
+            Some(serde_json::json!({
+                "source": "epub",
+                "requested": "mobi",
+                "available_formats": ["epub"],
+            })),
+            err.details
+        );
+
+        // Editions already in the wanted format are served.
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        service
+            .expect_book_file()
+            .withf(|_, extension, options| {
+                *extension == Extension::Mobi
+                    && options.capabilities == Some(ConverterCapabilities::disabled())
+            })
+            .once()
+            .returning(|_, _, _| {
+                let path = std::env::temp_dir().join("libreads_web_disabled.mobi");
+                Box::pin(async move {
+                    std::fs::copy("tests/testdata/dummy_ebook.mobi", &path).unwrap();
+                    Ok(conversion_result(path))
+                })
+            });
+        let resp = download(
+            service_data(service),
+            config,
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(query),
+        )
+        .await
+        .expect("the call should succeed");
+        actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_download_unsupported_conversion() {
+        // Calibre is there, but not DjVuLibre.
+        let capabilities = ConverterCapabilities::new(
+            Some(crate::capabilities::CalibreVersion {
+                major: 7,
+                minor: 4,
+                patch: 0,
+            }),
+            false,
+        );
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Djvu, "hello"));
+        service.expect_book_file().never();
+
+        let err = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            Some(web::Data::new(capabilities)),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                format: Some("epub".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect_err("the conversion should be rejected");
+        assert_eq!("unsupported conversion", err.name);
+        assert_eq!(
+            actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            actix_web::ResponseError::status_code(&err)
+        );
+        assert_eq!(
+            Some(serde_json::json!({
+                "source": "djvu",
+                "requested": "epub",
+                "available_formats": ["djvu"],
+            })),
+            err.details
+        );
+    }
+
+    // Without probed capabilities, conversions no toolchain can do are still
+    // rejected upfront.
+    #[actix_web::test]
+    async fn test_download_unsupported_conversion_not_probed() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Doc, "hello"));
+        service.expect_book_file().never();
+        let service = service_data(service);
+        let query = || {
+            web::Query(DownloadQuery {
+                format: Some("epub".to_string()),
+                ..Default::default()
+            })
+        };
+
+        let head_err = download_head(
+            service.clone(),
+            web::Data::new(Config::default()),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            query(),
+        )
+        .await
+        .expect_err("the HEAD call should reject the conversion");
+        let get_err = download(
+            service,
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            query(),
+        )
+        .await
+        .expect_err("the GET call should reject the conversion");
+
+        for err in [head_err, get_err] {
+            assert_eq!("unsupported conversion", err.name);
+            assert_eq!(
+                actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+                actix_web::ResponseError::status_code(&err)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_txt_content_type() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "hello"));
+
+        let resp = download_head(
+            service_data(service),
+            web::Data::new(converting()),
+            calibre(),
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                format: Some("txt".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            resp.headers().get(CONTENT_TYPE).unwrap()
+        );
+        assert_eq!(
+            r#"attachment; filename="hello.txt""#,
+            resp.headers().get(CONTENT_DISPOSITION).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_download_invalid_input() {
+        for input in [
+            "notaurl",
+            "12ab",
+            "ftp://www.goodreads.com/book/show/1",
+            "https://example.com/book/show/1",
+            "",
+        ] {
+            let service = service_data(MockBookService::new());
+
+            let err = download(
+                service.clone(),
+                web::Data::new(Config::default()),
+                None,
+                None,
+                web::Path::from(input.to_string()),
+                web::Query(DownloadQuery::default()),
+            )
+            .await
+            .expect_err("invalid input should be rejected");
+            let resp = actix_web::ResponseError::error_response(&err);
+            assert_eq!(actix_web::http::StatusCode::BAD_REQUEST, resp.status());
+            assert_eq!(
+                "application/json",
+                resp.headers().get(CONTENT_TYPE).unwrap()
+            );
+
+            let err = download_head(
+                service,
+                web::Data::new(Config::default()),
+                None,
+                web::Path::from(input.to_string()),
+                web::Query(DownloadQuery::default()),
+            )
+            .await
+            .expect_err("invalid input should be rejected");
+            assert_eq!(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                actix_web::ResponseError::status_code(&err)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_unknown_device() {
+        let err = download(
+            service_data(MockBookService::new()),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                device: Some("nook".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect_err("unknown devices should be rejected");
+        assert_eq!(
+            actix_web::http::StatusCode::BAD_REQUEST,
+            actix_web::ResponseError::status_code(&err)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_download_head_with_device() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+
+        let head_resp = download_head(
+            service_data(service),
+            web::Data::new(converting()),
+            calibre(),
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                device: Some("generic".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the HEAD call should succeed");
+
+        let cd = head_resp.headers().get(CONTENT_DISPOSITION).unwrap();
+        assert_eq!(r#"attachment; filename="hello.epub""#, cd);
+
+        let ct = head_resp.headers().get(CONTENT_TYPE).unwrap();
+        assert_eq!("application/epub+zip", ct);
+    }
+
+    #[actix_web::test]
+    async fn test_download_head_with_original_filename() {
+        let mut book = book_info(Extension::Epub, "hello");
+        book.download_links.filename = Some("Jane Austen - Hello (CIDEB, 2000).epub".to_string());
+
+        for (format, want) in [
+            (
+                "epub",
+                r#"attachment; filename="Jane Austen - Hello (CIDEB, 2000).epub""#,
+            ),
+            (
+                "mobi",
+                r#"attachment; filename="Jane Austen - Hello (CIDEB, 2000).mobi""#,
+            ),
+        ] {
+            let mut service = MockBookService::new();
+            expect_book_info(&mut service, book.clone());
+
+            let head_resp = download_head(
+                service_data(service),
+                web::Data::new(converting()),
+                calibre(),
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(DownloadQuery {
+                    format: Some(format.to_string()),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .expect("the HEAD call should succeed");
+
+            let cd = head_resp.headers().get(CONTENT_DISPOSITION).unwrap();
+            assert_eq!(want, cd);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_head_with_name_template() {
+        let config = Config {
+            name_template: Some("{title}".parse().unwrap()),
+            ..Config::default()
+        };
+        for (name_template, want) in [
+            (None, r#"attachment; filename="hello.mobi""#),
+            (
+                Some("{author} - {title} ({year}) [{md5}]"),
+                r#"attachment; filename="Jane Austen - hello (2000) [MYBOOKMD5].mobi""#,
+            ),
+        ] {
+            let mut book = book_info(Extension::Mobi, "hello");
+            book.metadata.author = "Jane Austen".to_string();
+            book.metadata.year = "2000".to_string();
+            book.download_links.filename = Some("ignored.mobi".to_string());
+            let mut service = MockBookService::new();
+            expect_book_info(&mut service, book);
+
+            let head_resp = download_head(
+                service_data(service),
+                web::Data::new(config.clone()),
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(DownloadQuery {
+                    name_template: name_template.map(str::to_string),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .expect("the HEAD call should succeed");
+
+            let cd = head_resp.headers().get(CONTENT_DISPOSITION).unwrap();
+            assert_eq!(want, cd);
+        }
+
+        // Any call to the service would fail the test.
+        let err = download_head(
+            service_data(MockBookService::new()),
+            web::Data::new(config),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                name_template: Some("{title} [{isbn13}]".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect_err("the template should be rejected");
+        assert_eq!(
+            actix_web::http::StatusCode::BAD_REQUEST,
+            actix_web::ResponseError::status_code(&err)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_download_dry_run() {
+        let book = book_info(Extension::Mobi, "hello");
+        let report = SelectionReport {
+            identification: BookIdentification {
+                isbn10: Some("fake_isbn_10".to_string()),
+                ..Default::default()
+            },
+            found_by_title: false,
+            candidates: vec![],
+            total: 0,
+            truncated: 0,
+            chosen: Some(book.metadata),
+            download_links: Some(book.download_links),
+        };
+        let want = serde_json::to_value(&report).unwrap();
+        let mut service = MockBookService::new();
+        service
+            .expect_candidates()
+            .withf(|url, extension, filters| {
+                url == GOODREADS_URL && *extension == Some(&Extension::Mobi) && filters.is_empty()
+            })
+            .once()
+            .returning(move |_, _, _| {
+                let report = report.clone();
+                Box::pin(async move { Ok(report) })
+            });
+        // Nothing is downloaded nor converted.
+        service.expect_book_info().never();
+        service.expect_book_file().never();
+
+        let resp = download(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                format: Some("mobi".to_string()),
+                dry_run: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect("the call should succeed");
+
+        let ct = resp.headers().get(CONTENT_TYPE).unwrap();
+        assert_eq!("application/json", ct);
+        assert!(resp.headers().get(CONTENT_DISPOSITION).is_none());
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            want,
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_download_dry_run_page() {
+        let report = SelectionReport {
+            identification: BookIdentification::default(),
+            found_by_title: false,
+            candidates: (1..=5)
+                .map(|rank| libreads::CandidateReport {
+                    metadata: book_info(Extension::Epub, &rank.to_string()).metadata,
+                    rank: Some(rank),
+                    eliminated: None,
+                })
+                .collect(),
+            total: 5,
+            truncated: 0,
+            chosen: None,
+            download_links: None,
+        };
+        let mut service = MockBookService::new();
+        service
+            .expect_candidates()
+            .once()
+            .returning(move |_, _, _| {
+                let report = report.clone();
+                Box::pin(async move { Ok(report) })
+            });
+        let service = service_data(service);
+        let query = |offset: Option<&str>, limit: Option<&str>| {
+            web::Query(DownloadQuery {
+                dry_run: true,
+                offset: offset.map(str::to_string),
+                limit: limit.map(str::to_string),
+                ..Default::default()
+            })
+        };
+
+        let resp = download(
+            service.clone(),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            query(Some("1"), Some("2")),
+        )
+        .await
+        .expect("the call should succeed");
+        let body: serde_json::Value =
+            serde_json::from_slice(&actix_web::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(5, body["total"]);
+        assert_eq!(
+            vec![2, 3],
+            body["candidates"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|candidate| candidate["rank"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        // Rejected before looking the book up.
+        for (offset, limit, field) in [
+            (None, Some("0"), "limit"),
+            (None, Some("501"), "limit"),
+            (None, Some("ten"), "limit"),
+            (Some("-1"), None, "offset"),
+        ] {
+            let err = download(
+                service.clone(),
+                web::Data::new(Config::default()),
+                None,
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                query(offset, limit),
+            )
+            .await
+            .expect_err("the page should be rejected");
+            assert_eq!("invalid input", err.name);
+            assert_eq!(Some(serde_json::json!({ "field": field })), err.details);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_selection_filters() {
+        let mut service = MockBookService::new();
+        service
+            .expect_book_info()
+            .withf(|url, _, filters| {
+                url == GOODREADS_URL
+                    && *filters
+                        == SelectionFilters {
+                            exclude: vec![Extension::Pdf, Extension::Djvu],
+                            max_size: Some(50 * 1024 * 1024),
+                            language: Some("english".to_string()),
+                            min_year: Some(2010),
+                        }
+            })
+            .once()
+            .returning(|_, _, _| {
+                Box::pin(async { Err(libreads::Error::HttpError(HttpFailure::other("timeout"))) })
+            });
+
+        let err = download_head(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            web::Path::from(GOODREADS_URL.to_string()),
+            web::Query(DownloadQuery {
+                exclude: Some("pdf,djvu".to_string()),
+                max_size_mb: Some("50".to_string()),
+                lang: Some("en".to_string()),
+                min_year: Some("2010".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .expect_err("the mock fails after checking the filters");
+        assert_eq!("upstream", err.name);
+    }
+
+    #[actix_web::test]
+    async fn test_download_invalid_selection_filters() {
+        for query in [
+            DownloadQuery {
+                exclude: Some("pdf,rar".to_string()),
+                ..Default::default()
+            },
+            DownloadQuery {
+                max_size_mb: Some("big".to_string()),
+                ..Default::default()
+            },
+            DownloadQuery {
+                min_year: Some("recent".to_string()),
+                ..Default::default()
+            },
+        ] {
+            // Any call to the service would fail the test.
+            let err = download(
+                service_data(MockBookService::new()),
+                web::Data::new(Config::default()),
+                None,
+                None,
+                web::Path::from(GOODREADS_URL.to_string()),
+                web::Query(query),
+            )
+            .await
+            .expect_err("the filters should be rejected");
+            assert_eq!(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                actix_web::ResponseError::status_code(&err)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_post() {
+        let mut service = MockBookService::new();
+        service
+            .expect_candidates()
+            .withf(|url, extension, filters| {
+                url == GOODREADS_URL
+                    && *extension == Some(&Extension::Epub)
+                    && *filters
+                        == SelectionFilters {
+                            max_size: Some(50 * 1024 * 1024),
+                            language: Some("english".to_string()),
+                            min_year: Some(2010),
+                            ..Default::default()
+                        }
+            })
+            .once()
+            .returning(|_, _, _| {
+                Box::pin(async {
+                    Ok(SelectionReport {
+                        identification: BookIdentification::default(),
+                        found_by_title: false,
+                        candidates: vec![],
+                        total: 0,
+                        truncated: 0,
+                        chosen: None,
+                        download_links: None,
+                    })
+                })
+            });
+
+        let body = serde_json::json!({
+            "url": GOODREADS_URL,
+            "format": "epub",
+            "dry_run": true,
+            "filters": {"lang": "en", "max_size_mb": 50, "min_year": "2010"},
+        });
+        let resp = download_post(
+            service_data(service),
+            web::Data::new(Config::default()),
+            None,
+            None,
+            web::Bytes::from(body.to_string()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(
+            "application/json",
+            resp.headers().get(CONTENT_TYPE).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_download_post_invalid_body() {
+        for (body, field) in [
+            (
+                r#"{"url": "https://www.goodreads.com/book/show/1", "format": "exe"}"#,
+                Some("format"),
+            ),
+            (r#"{"format": "epub"}"#, Some("url")),
+            (r#"{"url": 1}"#, Some("url")),
+            (
+                r#"{"url": "https://www.goodreads.com/book/show/1", "filters": {"min_year": true}}"#,
+                Some("filters.min_year"),
+            ),
+            (
+                r#"{"url": "https://www.goodreads.com/book/show/1", "filters": {"year": 2010}}"#,
+                Some("filters.year"),
+            ),
+            (
+                r#"{"url": "https://www.goodreads.com/book/show/1", "formats": "epub"}"#,
+                Some("formats"),
+            ),
+            (r#"["https://www.goodreads.com/book/show/1"]"#, None),
+            ("not JSON", None),
+        ] {
+            // Any call to the service would fail the test.
+            let err = download_post(
+                service_data(MockBookService::new()),
+                web::Data::new(Config::default()),
+                None,
+                None,
+                web::Bytes::from(body),
+            )
+            .await
+            .expect_err("the body should be rejected");
+            assert_eq!(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                actix_web::ResponseError::status_code(&err),
+                "{}",
+                body
+            );
+            assert_eq!(
+                field.map(|field| serde_json::json!({ "field": field })),
+                err.details,
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_info() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+
+        let resp = info(
+            actix_web::test::TestRequest::default().to_http_request(),
+            service_data(service),
+            web::Path::from(GOODREADS_URL.to_string()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        let ct = resp.headers().get(CONTENT_TYPE).unwrap();
+        assert_eq!("application/json", ct);
+
+        assert_server_timing(
+            resp.headers().get(SERVER_TIMING),
+            &["goodreads", "libgen", "links"],
+        );
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "title": "hello",
+                "author": "hello",
+                "year": "hello",
+                "extension": "mobi",
+                "md5": "MYBOOKMD5",
+                "download_links": {
+                    "ipfs_cid": "fake_cid",
+                    "gateways": ["https://fake.gateway"],
+                    "http": "fake_http_link",
+                },
+                "cover_url": null,
+                "available_formats": ["mobi"],
+                "alternatives": [],
+                "found_by_title": false,
+                "identification": {
+                    "isbn10": "fake_isbn_10",
+                    "isbn13": null,
+                    "title": "hello (Goodreads)",
+                    "author": "Hello",
+                    "cover_url": null,
+                    "pages": null,
+                    "publisher": null,
+                    "series": "Hello World",
+                    "series_index": "2",
+                    "format": null,
+                },
+            }),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_search() {
+        use actix_web::{test, App};
+
+        let mut service = MockBookService::new();
+        service
+            .expect_book_info_by_query()
+            .with(
+                eq(BookQuery::TitleAndAuthor {
+                    title: "Either|Or".to_string(),
+                    author: "Søren Kierkegaard".to_string(),
+                }),
+                always(),
+                always(),
+            )
+            .once()
+            .returning(|_, _, _| {
+                Box::pin(async { Ok((book_info(Extension::Epub, "hello"), Timings::default())) })
+            });
+        service.expect_book_info().never();
+        let app = test::init_service(
+            App::new()
+                .app_data(service_data(service))
+                .route("/search", web::get().to(search)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/search?q=title%3AEither%5C%7COr%7Cauthor%3AS%C3%B8ren%20Kierkegaard")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(actix_web::http::StatusCode::OK, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("MYBOOKMD5", body["md5"]);
+
+        // Queries that can't be parsed aren't searched for, e.g. when the
+        // "|" of the title isn't escaped.
+        let req = test::TestRequest::get()
+            .uri("/search?q=title%3AEither%7COr")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(actix_web::http::StatusCode::BAD_REQUEST, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("invalid input", body["error"]);
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains(r#""Or" is not a key:value field"#));
+    }
+
+    #[actix_web::test]
+    async fn test_info_html() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "Tom & <Jerry>"));
+
+        let resp = info(
+            actix_web::test::TestRequest::default()
+                .insert_header((ACCEPT, "text/html;q=0.9, */*;q=0.1"))
+                .to_http_request(),
+            service_data(service),
+            web::Path::from(GOODREADS_URL.to_string()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(
+            "text/html; charset=utf-8",
+            resp.headers().get(CONTENT_TYPE).unwrap()
+        );
+        assert_eq!("Accept", resp.headers().get(VARY).unwrap());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(
+            body.contains("<h1>Tom &amp; &lt;Jerry&gt;</h1>"),
+            "{}",
+            body
+        );
+        assert!(body.contains("<p>hello (hello)</p>"), "{}", body);
+        assert!(body.contains("<p>Formats: mobi</p>"), "{}", body);
+        assert!(
+            body.contains(
+                r#"<li><a href="https://fake.gateway/ipfs/fake_cid">fake.gateway</a></li>"#
+            ),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains(r#"<li><a href="fake_http_link">HTTP</a></li>"#),
+            "{}",
+            body
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_info_text() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+
+        let resp = info(
+            actix_web::test::TestRequest::default()
+                .insert_header((ACCEPT, "text/plain"))
+                .to_http_request(),
+            service_data(service),
+            web::Path::from(GOODREADS_URL.to_string()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            resp.headers().get(CONTENT_TYPE).unwrap()
+        );
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            "hello
+by hello (hello)
+formats: mobi
+fake.gateway: https://fake.gateway/ipfs/fake_cid
+HTTP: fake_http_link
+",
+            body
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_info_unknown_accept_is_json() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+
+        let resp = info(
+            actix_web::test::TestRequest::default()
+                .insert_header((ACCEPT, "image/png"))
+                .to_http_request(),
+            service_data(service),
+            web::Path::from(GOODREADS_URL.to_string()),
+        )
+        .await
+        .expect("the call should succeed");
+
+        assert_eq!(
+            "application/json",
+            resp.headers().get(CONTENT_TYPE).unwrap()
+        );
+    }
+
+    async fn call_compressed(
+        service: MockBookService,
+        uri: &str,
+    ) -> actix_web::dev::ServiceResponse {
+        use actix_web::{middleware::Compress, test, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap(Compress::default())
+                .app_data(service_data(service))
+                .app_data(web::Data::new(Config::default()))
+                .route("/info/{goodreads_url}", web::get().to(info))
+                .route("/download/{goodreads_url}", web::get().to(download)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((ACCEPT_ENCODING, "gzip"))
+            .to_request();
+
+        test::call_service(&app, req).await.map_into_boxed_body()
+    }
+
+    #[actix_web::test]
+    async fn test_info_is_compressed() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+
+        let resp = call_compressed(
+            service,
+            "/info/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons",
+        )
+        .await;
+
+        assert!(resp.status().is_success());
+        assert_eq!("gzip", resp.headers().get(CONTENT_ENCODING).unwrap());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        // gzip magic number
+        assert_eq!([0x1f, 0x8b], body[..2]);
+    }
+
+    #[actix_web::test]
+    async fn test_download_is_not_compressed() {
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Epub, "hello"));
+        let path = std::env::temp_dir().join("libreads_web_not_compressed.epub");
+        let converted = path.clone();
+        service.expect_book_file().once().returning(move |_, _, _| {
+            let path = converted.clone();
+            Box::pin(async move {
+                std::fs::copy("tests/testdata/dummy_ebook.epub", &path).unwrap();
+                Ok(conversion_result(path))
+            })
+        });
+
+        let resp = call_compressed(service, "/download/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons?format=epub").await;
+
+        assert!(resp.status().is_success());
+        assert_eq!("identity", resp.headers().get(CONTENT_ENCODING).unwrap());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            include_bytes!("../tests/testdata/dummy_ebook.epub").as_slice(),
+            body
+        );
+        assert!(!path.exists());
+    }
+
+    #[actix_web::test]
+    async fn test_library_not_configured() {
+        let mut service = MockBookService::new();
+        service.expect_library_books().once().returning(|| {
+            Box::pin(async {
+                Err(libreads::Error::NotInLibrary(
+                    "No library directory configured".to_string(),
+                ))
+            })
+        });
+        service
+            .expect_remove_from_library()
+            .with(eq("MYBOOKMD5"))
+            .once()
+            .returning(|_| {
+                Box::pin(async {
+                    Err(libreads::Error::NotInLibrary(
+                        "No library directory configured".to_string(),
+                    ))
+                })
+            });
+        let service = service_data(service);
+
+        let err = library(service.clone()).await.unwrap_err();
+        assert_eq!(
+            actix_web::http::StatusCode::NOT_FOUND,
+            actix_web::ResponseError::status_code(&err)
+        );
+        let err = remove_from_library(service, web::Path::from("MYBOOKMD5".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            actix_web::http::StatusCode::NOT_FOUND,
+            actix_web::ResponseError::status_code(&err)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_cleanup() {
+        use actix_web::{test, App};
+
+        let dir = std::env::temp_dir().join("libreads_web_cleanup");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("orphan.epub"), "book").unwrap();
+        let maintenance = Maintenance::new(&Config {
+            work_dir: dir.clone(),
+            temp_file_max_age: Duration::from_secs(60),
+            ..Config::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(maintenance))
+                .service(web::scope("/api").route("/admin/cleanup", web::post().to(cleanup))),
+        )
+        .await;
+
+        // Too recent to be an orphan.
+        let req = test::TestRequest::post()
+            .uri("/api/admin/cleanup?scope=temp")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            serde_json::json!({
+                "temp_files": {"files": 0, "bytes": 0},
+                "cache_entries": {"files": 0, "bytes": 0},
+            }),
+            body
+        );
+        assert!(dir.join("orphan.epub").exists());
+
+        let req = test::TestRequest::post()
+            .uri("/api/admin/cleanup?scope=everything")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(actix_web::http::StatusCode::BAD_REQUEST, resp.status());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_book_history() {
+        use actix_web::{test, App};
+
+        let mut service = MockBookService::new();
+        service.expect_book_info().times(2).returning(|_, _, _| {
+            Box::pin(async { Err(libreads::Error::HttpError(HttpFailure::other("timeout"))) })
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(service_data(service))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(History::default()))
+                .configure(configure),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        // The same book, by its ID and by a longer URL.
+        for uri in [
+            "/api/download/1048424?format=epub",
+            "/api/download/https%3A%2F%2Fwww.goodreads.com%2Fen%2Fbook%2Fshow%2F1048424.Governing_the_Commons?format=mobi",
+        ] {
+            let resp = test::call_service(&app, get(uri)).await;
+            assert_eq!(502, resp.status().as_u16());
+        }
+
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, get("/api/history/book/1048424")).await;
+        let attempts = body.as_array().unwrap();
+        assert_eq!(2, attempts.len());
+        assert_eq!(
+            "https://www.goodreads.com/en/book/show/1048424.Governing_the_Commons",
+            attempts[0]["url"]
+        );
+        assert_eq!("mobi", attempts[0]["format"]);
+        assert_eq!("1048424", attempts[1]["url"]);
+        assert_eq!("upstream", attempts[1]["error"]);
+        assert_eq!(false, attempts[1]["delivered"]);
+
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, get("/api/history/book/ol:OL12345M")).await;
+        assert_eq!(serde_json::json!([]), body);
+
+        let resp = test::call_service(&app, get("/api/history/book/not-a-book")).await;
+        assert_eq!(400, resp.status().as_u16());
+    }
+
+    #[actix_web::test]
+    async fn test_queue() {
+        use actix_web::{test, App};
+
+        let path = std::env::temp_dir().join("libreads_web_queue.json");
+        let _ = std::fs::remove_file(&path);
+        let queue = Arc::new(Queue::open(&path).unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(service_data(MockBookService::new()))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::from(queue.clone()))
+                .configure(configure),
+        )
+        .await;
+        let post = |body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/api/queue")
+                .set_json(body)
+                .to_request()
+        };
+
+        let resp = test::call_service(
+            &app,
+            post(serde_json::json!({"urls": ["1048424", GOODREADS_URL], "device": "kobo"})),
+        )
+        .await;
+        assert_eq!(202, resp.status().as_u16());
+        let enqueued: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(0, enqueued[0]["id"]);
+        assert_eq!(
+            "https://www.goodreads.com/book/show/1048424",
+            enqueued[0]["url"]
+        );
+        assert_eq!("epub", enqueued[1]["format"]);
+        assert_eq!("pending", enqueued[1]["status"]);
+
+        // Nothing is enqueued when a book is invalid.
+        for body in [
+            serde_json::json!({"urls": ["1048424", "https://example.com/book/1"]}),
+            serde_json::json!({"urls": []}),
+            serde_json::json!({"urls": ["1048424"], "format": "epub,pdf"}),
+            serde_json::json!({"urls": ["1048424"], "format": "original"}),
+            serde_json::json!({"urls": vec!["1048424"; MAX_ENQUEUED_URLS + 1]}),
+        ] {
+            let resp = test::call_service(&app, post(body.clone())).await;
+            assert_eq!(400, resp.status().as_u16(), "{}", body);
+        }
+
+        let body: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get().uri("/api/queue").to_request(),
+        )
+        .await;
+        assert_eq!(enqueued, body);
+        assert_eq!(2, Queue::open(&path).unwrap().items().len());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[actix_web::test]
+    async fn test_queue_disabled() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(service_data(MockBookService::new()))
+                .app_data(web::Data::new(Config::default()))
+                .configure(configure),
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/api/queue").to_request(),
+        )
+        .await;
+        assert_eq!(501, resp.status().as_u16());
+    }
+
+    #[cfg(feature = "convert")]
+    #[actix_web::test]
+    async fn test_stats() {
+        use actix_web::{test, App};
+
+        let mut service = MockBookService::new();
+        service.expect_book_info().once().returning(|_, _, _| {
+            Box::pin(async { Ok((book_info(Extension::Epub, "hello"), Timings::default())) })
+        });
+        let path = expect_large_book_file(&mut service, "libreads_web_stats.mobi");
+        service
+            .expect_book_info_by_md5()
+            .once()
+            .returning(|_, _, _| {
+                Box::pin(async { Err(libreads::Error::HttpError(HttpFailure::other("timeout"))) })
+            });
+        let app = test::init_service(
+            App::new()
+                .app_data(service_data(service))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(History::default()))
+                .configure(configure),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let resp = test::call_service(
+            &app,
+            get(
+                "/api/download/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424?format=mobi",
+            ),
+        )
+        .await;
+        assert_eq!(200, resp.status().as_u16());
+        test::read_body(resp).await;
+        assert!(!path.exists());
+        let resp = test::call_service(
+            &app,
+            get("/api/download/md5/AB13556B96D473C8DFAD7165C4704526"),
+        )
+        .await;
+        assert_eq!(502, resp.status().as_u16());
+
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, get("/api/stats?since=7d")).await;
+        assert_eq!(
+            serde_json::json!({
+                "downloads": 2,
+                "succeeded": 1,
+                "failed": 1,
+                "failures": {"upstream": 1},
+                "formats": [{"name": "mobi", "downloads": 2}],
+                "bytes_served": 3 * STREAM_CHUNK_SIZE + 10,
+                "cache_hit_rate": 0.0,
+                "average_conversion_ms": 10,
+                "top_titles": [{"name": "hello (Goodreads)", "downloads": 1}],
+            }),
+            body
+        );
+
+        let resp = test::call_service(&app, get("/api/stats?since=forever")).await;
+        assert_eq!(400, resp.status().as_u16());
+    }
+
+    #[actix_web::test]
+    async fn test_debug_parse() {
+        use actix_web::{test, App};
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/main/ABCDEF");
+            then.status(200).body(include_str!(
+                "../tests/testdata/library.lol_original_filename_page.html"
+            ));
+        });
+        let config = Config {
+            api_token: Some("s3cr3t".to_string()),
+            library_lol_url: Some(server.url("/main")),
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ParseChecker::new(&config)))
+                .app_data(web::Data::new(config))
+                .configure(configure),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let resp = test::call_service(
+            &app,
+            get("/api/debug/parse?url=http%3A%2F%2Flibrary.lol%2Fmain%2FABCDEF"),
+        )
+        .await;
+        assert_eq!(401, resp.status().as_u16());
+
+        let body: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            get("/api/debug/parse?url=http%3A%2F%2Flibrary.lol%2Fmain%2FABCDEF&token=s3cr3t"),
+        )
+        .await;
+        assert_eq!("library.lol", body["parser"]);
+        assert_eq!(serde_json::json!([]), body["missing"]);
+
+        let resp = test::call_service(
+            &app,
+            get("/api/debug/parse?url=https%3A%2F%2Fexample.com&token=s3cr3t"),
+        )
+        .await;
+        assert_eq!(400, resp.status().as_u16());
+    }
+
+    #[actix_web::test]
+    async fn test_shelf() {
+        let shelf_url = "https://www.goodreads.com/review/list/42-jane?shelf=to-read";
+        let mut service = MockBookService::new();
+        service
+            .expect_shelf()
+            .with(eq(shelf_url))
+            .once()
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(vec![ShelfEntry {
+                        book_url: "https://www.goodreads.com/book/show/40961427-1984".to_string(),
+                        title: Some("1984".to_string()),
+                        author: None,
+                    }])
+                })
+            });
+        let service = service_data(service);
+
+        let resp = shelf(
+            service.clone(),
+            web::Query(ShelfQuery {
+                url: shelf_url.to_string(),
+            }),
+        )
+        .await
+        .expect("the call should succeed");
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::json!([{
+                "book_url": "https://www.goodreads.com/book/show/40961427-1984",
+                "title": "1984",
+                "author": null,
+            }]),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+
+        let err = shelf(
+            service,
+            web::Query(ShelfQuery {
+                url: "https://example.com/review/list/42".to_string(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!("invalid input", err.name);
+    }
+    #[actix_web::test]
+    async fn test_import_goodreads_csv() {
+        let resp = import_goodreads_csv(web::Bytes::from_static(include_bytes!(
+            "../tests/testdata/goodreads_library_export.csv"
+        )))
+        .await
+        .expect("the call should succeed");
 
-pub async fn download(
-    libreads: web::Data<LibReads>,
-    goodreads_url: web::Path<String>,
-) -> Result<HttpResponse, Error> {
-    let book_info = libreads
-        .get_book_info_from_goodreads_url(&goodreads_url)
-        .await?;
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let got = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(4, got["books"].as_array().unwrap().len());
+        assert_eq!(
+            serde_json::json!({
+                "isbn10": "0521405998",
+                "isbn13": "9780521405997",
+                "title": "Governing the Commons: The Evolution of Institutions for Collective Action (Political Economy of Institutions and Decisions)",
+                "author": "Elinor Ostrom",
+                "cover_url": null,
+                "pages": 298,
+                "publisher": "Cambridge University Press",
+                "series": null,
+                "series_index": null,
+                "format": "Paperback",
+            }),
+            got["books"][0]
+        );
+        assert_eq!(1, got["skipped"]);
 
-    let filename = download_as(book_info.into(), Extension::Mobi).await?;
-    let buffer = load_file_to_memory(&filename).await?;
+        let err = import_goodreads_csv(web::Bytes::from_static(b"not,a\nGoodreads,export\n"))
+            .await
+            .unwrap_err();
+        assert_eq!("invalid input", err.name);
+    }
 
-    let content_type = (CONTENT_TYPE, Extension::Mobi.content_type());
-    let content_disposition = ContentDisposition {
-        disposition: DispositionType::Attachment,
-        parameters: vec![DispositionParam::Filename(filename)],
-    };
+    #[actix_web::test]
+    async fn test_error_response_is_json() {
+        let err = Error {
+            name: "invalid input".to_string(),
+            message: "not a valid URL".to_string(),
+            details: None,
+            retry_after: None,
+        };
+        let resp = actix_web::ResponseError::error_response(&err);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
 
-    println!("Serving the converted file from memory!");
+        assert_eq!(
+            serde_json::json!({"error": "invalid input", "message": "not a valid URL"}),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+    }
 
-    Ok(HttpResponse::Ok()
-        .append_header(content_disposition)
-        .append_header(content_type)
-        .body(buffer))
-}
+    #[actix_web::test]
+    async fn test_info_not_found_reports_filters() {
+        let mut service = MockBookService::new();
+        service.expect_book_info().once().returning(|_, _, _| {
+            Box::pin(async {
+                Err(libreads::Error::NotFound {
+                    searched: Box::new(BookIdentification {
+                        isbn13: Some("9780141439518".to_string()),
+                        title: Some("Pride and Prejudice".to_string()),
+                        ..Default::default()
+                    }),
+                    filters: FilterReport {
+                        results: 2,
+                        duplicates: 1,
+                        unknown_extension: 1,
+                        djvu_excluded: 0,
+                        filtered_out: 0,
+                        emptied_by: None,
+                    },
+                })
+            })
+        });
 
-// Loads a file to memory and then delete it.
-#[cfg_attr(tarpaulin, ignore)] // It would complexify the code too much to be able to test each error path individually
-async fn load_file_to_memory(filename: &str) -> Result<Vec<u8>, std::io::Error> {
-    // (1) Load file to memory
-    let mut file = tokio::fs::File::open(&filename).await?;
-    let metadata = tokio::fs::metadata(&filename).await?; // Untested.
-    let mut buffer = vec![0; metadata.len() as usize];
-    tokio::io::AsyncReadExt::read(&mut file, &mut buffer).await?; // Untested.
+        let err = info(
+            actix_web::test::TestRequest::default().to_http_request(),
+            service_data(service),
+            web::Path::from(GOODREADS_URL.to_string()),
+        )
+        .await
+        .expect_err("nothing can be used");
+        let resp = actix_web::ResponseError::error_response(&err);
+        assert_eq!(actix_web::http::StatusCode::NOT_FOUND, resp.status());
 
-    // (2) Remove the file now that we have it in memory
-    tokio::fs::remove_file(&filename).await?; // Untested.
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "error": "not found",
+                "message": "nothing found on LibGen for this book (ISBN-13 9780141439518, title Pride and Prejudice): 2 results, 1 duplicate, 1 without a known extension",
+                "details": {
+                    "searched": {
+                        "isbn10": null,
+                        "isbn13": "9780141439518",
+                        "title": "Pride and Prejudice",
+                        "author": null,
+                    },
+                    "filters": {
+                        "results": 2,
+                        "duplicates": 1,
+                        "unknown_extension": 1,
+                        "djvu_excluded": 0,
+                        "filtered_out": 0,
+                    },
+                },
+            }),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+    }
 
-    Ok(buffer)
-}
+    #[actix_web::test]
+    async fn test_info_rate_limited() {
+        let mock_server = MockServer::start();
+        let goodreads_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/book/show/1234");
+            then.status(429).header("Retry-After", "120");
+        });
+        let service = LibReads::new(&Config {
+            goodreads_url: Some(mock_server.base_url()),
+            ..Default::default()
+        });
 
-#[tokio::test]
-async fn test_load_file_to_memory_inexisting_file() {
-    let got = load_file_to_memory("this file doesn't exist").await;
-    assert!(got.is_err());
-    let got = got.unwrap_err();
+        let err = info(
+            actix_web::test::TestRequest::default().to_http_request(),
+            web::Data::from(Arc::new(service) as Arc<dyn BookService>),
+            web::Path::from("1234".to_string()),
+        )
+        .await
+        .expect_err("Goodreads is rate limited");
+        goodreads_mock.assert();
 
-    assert_eq!(std::io::ErrorKind::NotFound, got.kind())
-}
+        let resp = actix_web::ResponseError::error_response(&err);
+        assert_eq!(
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
+            resp.status()
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("120")),
+            resp.headers().get(RETRY_AFTER)
+        );
+    }
 
-#[derive(Debug)]
-pub struct Error {
-    name: String,
-    message: String,
-}
+    #[actix_web::test]
+    async fn test_info_rate_limited_default_retry_after() {
+        let mut service = MockBookService::new();
+        service.expect_book_info().once().returning(|_, _, _| {
+            Box::pin(async {
+                Err(libreads::Error::RateLimited {
+                    upstream: "LibGen".to_string(),
+                    limit: crate::http_client::RateLimit {
+                        status: 503,
+                        retry_after: None,
+                    },
+                })
+            })
+        });
 
-impl error::ResponseError for Error {
-    fn status_code(&self) -> actix_web::http::StatusCode {
-        match self.name.as_str() {
-            "upstream" => actix_web::http::StatusCode::BAD_GATEWAY,
-            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        let err = info(
+            actix_web::test::TestRequest::default().to_http_request(),
+            service_data(service),
+            web::Path::from(GOODREADS_URL.to_string()),
+        )
+        .await
+        .expect_err("LibGen is rate limited");
+        let resp = actix_web::ResponseError::error_response(&err);
+        assert_eq!(
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
+            resp.status()
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("60")),
+            resp.headers().get(RETRY_AFTER)
+        );
     }
-}
 
-#[test]
-fn test_error_status_code() {
-    use actix_web::http::StatusCode;
+    async fn preflight(config: &Config, origin: &str) -> actix_web::dev::ServiceResponse {
+        preflight_method(config, origin, "GET").await
+    }
 
-    for (name, want) in vec![
-        ("upstream", StatusCode::BAD_GATEWAY),
-        ("http", StatusCode::INTERNAL_SERVER_ERROR),
-        ("i/o", StatusCode::INTERNAL_SERVER_ERROR),
-        ("application", StatusCode::INTERNAL_SERVER_ERROR),
-        ("anything at all", StatusCode::INTERNAL_SERVER_ERROR),
-    ] {
-        let error = Error {
-            name: name.to_string(),
-            message: "doesn't matter".to_string(),
+    async fn preflight_method(
+        config: &Config,
+        origin: &str,
+        method: &str,
+    ) -> actix_web::dev::ServiceResponse {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap(cors(config))
+                .route("/download/{goodreads_url}", web::get().to(download)),
+        )
+        .await;
+        let req = test::TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/download/1048424")
+            .insert_header(("Origin", origin))
+            .insert_header(("Access-Control-Request-Method", method))
+            .to_request();
+
+        test::call_service(&app, req).await.map_into_boxed_body()
+    }
+
+    #[actix_web::test]
+    async fn test_cors_allowed_origin() {
+        let config = Config {
+            cors_allowed_origins: vec!["https://me.github.io".to_string()],
+            ..Config::default()
         };
 
-        assert_eq!(want, actix_web::ResponseError::status_code(&error));
+        let resp = preflight(&config, "https://me.github.io").await;
+
+        assert!(resp.status().is_success());
+        assert_eq!(
+            "https://me.github.io",
+            resp.headers().get("access-control-allow-origin").unwrap()
+        );
+        let methods = resp.headers().get("access-control-allow-methods").unwrap();
+        let methods = methods.to_str().unwrap();
+        assert!(
+            methods.contains("GET") && methods.contains("POST"),
+            "{}",
+            methods
+        );
     }
-}
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}", self.name, self.message)
+    // Library entries can be deleted from a front-end hosted separately.
+    #[actix_web::test]
+    async fn test_cors_allows_delete() {
+        let config = Config {
+            cors_allowed_origins: vec!["https://me.github.io".to_string()],
+            ..Config::default()
+        };
+
+        let resp = preflight_method(&config, "https://me.github.io", "DELETE").await;
+
+        assert!(resp.status().is_success());
+        let methods = resp.headers().get("access-control-allow-methods").unwrap();
+        assert!(
+            methods.to_str().unwrap().contains("DELETE"),
+            "{:?}",
+            methods
+        );
     }
-}
 
-impl From<libreads::Error> for Error {
-    fn from(err: libreads::Error) -> Self {
-        match err {
-            libreads::Error::HttpError(message) => Error {
-                name: "upstream".to_string(),
-                message,
-            },
-            libreads::Error::ApplicationError(message) => Error {
-                name: "application".to_string(),
-                message,
-            },
+    #[actix_web::test]
+    async fn test_cors_disallowed_origin() {
+        let config = Config {
+            cors_allowed_origins: vec!["https://me.github.io".to_string()],
+            ..Config::default()
+        };
+
+        let resp = preflight(&config, "https://evil.example.com").await;
+
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_cors_no_allowed_origins() {
+        let resp = preflight(&Config::default(), "https://me.github.io").await;
+
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_cors_any_origin() {
+        let config = Config {
+            cors_allowed_origins: vec!["*".to_string()],
+            ..Config::default()
+        };
+
+        let resp = preflight(&config, "https://anywhere.example.com").await;
+
+        assert!(resp.status().is_success());
+        assert!(resp.headers().get("access-control-allow-origin").is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_cors_exposes_headers() {
+        use actix_web::{test, App};
+
+        let config = Config {
+            cors_allowed_origins: vec!["https://me.github.io".to_string()],
+            ..Config::default()
+        };
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        let app = test::init_service(
+            App::new()
+                .wrap(cors(&config))
+                .app_data(service_data(service))
+                .route("/info/{goodreads_url}", web::get().to(info)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/info/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons")
+            .insert_header(("Origin", "https://me.github.io"))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+
+        assert!(resp.status().is_success());
+        assert_eq!(
+            "https://me.github.io",
+            resp.headers().get("access-control-allow-origin").unwrap()
+        );
+        let exposed = resp
+            .headers()
+            .get("access-control-expose-headers")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_lowercase();
+        for header in [
+            "content-disposition",
+            "x-available-formats",
+            "x-libreads-md5",
+            "x-libreads-source",
+            "x-libreads-original-extension",
+            "x-libreads-warning",
+            "server-timing",
+        ] {
+            assert!(exposed.contains(header), "{}", exposed);
         }
     }
-}
 
-#[test]
-fn test_error_from_libreads_error() {
-    for (err, want) in vec![
-        (
-            libreads::Error::HttpError("something bad".to_string()),
-            "upstream: something bad",
-        ),
-        (
-            libreads::Error::ApplicationError("oh no".to_string()),
-            "application: oh no",
-        ),
-    ] {
-        let got_err = Error::from(err);
-        assert_eq!(want, format!("{}", got_err))
+    #[actix_web::test]
+    async fn test_api_routes_are_not_shadowed_by_the_frontend() {
+        use actix_web::{test, App};
+
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        let app = test::init_service(
+            App::new()
+                .app_data(service_data(service))
+                .app_data(web::Data::new(Config::default()))
+                .configure(configure)
+                .configure(|cfg| {
+                    crate::frontend::configure(cfg, Path::new("tests/testdata/frontend"))
+                }),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let resp = test::call_service(
+            &app,
+            get("/api/info/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons"),
+        )
+        .await;
+        assert_eq!(200, resp.status().as_u16());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("MYBOOKMD5", body["md5"]);
+
+        let resp = test::call_service(&app, get("/healthz")).await;
+        assert_eq!(200, resp.status().as_u16());
+
+        // Unknown API routes don't get the front-end.
+        let resp = test::call_service(&app, get("/api/history")).await;
+        assert_eq!(404, resp.status().as_u16());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("not found", body["error"]);
+
+        // But other routes do.
+        let resp = test::call_service(&app, get("/history")).await;
+        assert_eq!(200, resp.status().as_u16());
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("<div>LibReads</div>"));
     }
-}
 
-impl From<convert::Error> for Error {
-    fn from(err: convert::Error) -> Self {
-        match err {
-            convert::Error::Io(message) => Error {
-                name: "i/o".to_string(),
-                message, // TODO: hide me
-            },
-            convert::Error::Http(message) => Error {
-                name: "upstream".to_string(),
-                message,
-            },
-            convert::Error::Conversion(message) => Error {
-                name: "conversion".to_string(),
-                message,
-            },
+    async fn call_with_token(
+        api_token: Option<&str>,
+        uri: &str,
+        authorization: Option<&str>,
+    ) -> actix_web::dev::ServiceResponse {
+        use actix_web::{middleware::from_fn, test, App};
+
+        let config = Config {
+            api_token: api_token.map(str::to_string),
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .route("/healthz", web::get().to(healthz))
+                .service(
+                    web::resource("/info/{goodreads_url}")
+                        .wrap(from_fn(require_token))
+                        .route(web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        let mut req = test::TestRequest::get().uri(uri);
+        if let Some(authorization) = authorization {
+            req = req.insert_header((AUTHORIZATION, authorization));
         }
+        test::call_service(&app, req.to_request())
+            .await
+            .map_into_boxed_body()
     }
-}
 
-#[test]
-fn test_error_from_convert_error() {
-    for (err, want) in vec![
-        (convert::Error::Io("failure".to_string()), "i/o: failure"),
-        (
-            convert::Error::Http("failure!!1".to_string()),
-            "upstream: failure!!1",
-        ),
-        (
-            convert::Error::Conversion("unknown format provided".to_string()),
-            "conversion: unknown format provided",
-        ),
-    ] {
-        let got_err = Error::from(err);
-        assert_eq!(want, format!("{}", got_err))
+    #[actix_web::test]
+    async fn test_require_token_missing() {
+        let resp = call_with_token(Some("s3cr3t"), "/info/1048424", None).await;
+
+        assert_eq!(actix_web::http::StatusCode::UNAUTHORIZED, resp.status());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "error": "unauthorized",
+                "message": "missing or invalid API token",
+            }),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
     }
-}
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error {
-            name: "i/o".to_string(),
-            message: err.to_string(),
+    #[actix_web::test]
+    async fn test_require_token_wrong() {
+        for (uri, authorization) in [
+            ("/info/1048424", Some("Bearer nope")),
+            ("/info/1048424", Some("s3cr3t")),
+            ("/info/1048424?token=nope", None),
+            // The header takes precedence over the query parameter.
+            ("/info/1048424?token=s3cr3t", Some("Bearer nope")),
+        ] {
+            let resp = call_with_token(Some("s3cr3t"), uri, authorization).await;
+            assert_eq!(
+                actix_web::http::StatusCode::UNAUTHORIZED,
+                resp.status(),
+                "{} {:?}",
+                uri,
+                authorization
+            );
         }
     }
-}
 
-#[test]
-fn test_error_from_stdio_error() {
-    let got_err: Error = std::io::Error::new(std::io::ErrorKind::AddrInUse, "big failure").into();
-    assert_eq!("i/o: big failure", format!("{}", got_err))
-}
+    #[actix_web::test]
+    async fn test_require_token_correct() {
+        for (uri, authorization) in [
+            ("/info/1048424", Some("Bearer s3cr3t")),
+            ("/info/1048424?token=s3cr3t", None),
+            ("/info/1048424?format=epub&token=s3cr3t", None),
+        ] {
+            let resp = call_with_token(Some("s3cr3t"), uri, authorization).await;
+            assert!(resp.status().is_success(), "{} {:?}", uri, authorization);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
+    #[actix_web::test]
+    async fn test_require_token_disabled() {
+        let resp = call_with_token(None, "/info/1048424", None).await;
+        assert!(resp.status().is_success());
+    }
 
-    use super::*;
-    use crate::{
-        goodreads::{BookIdentification, MockBookIdentificationGetter},
-        libgen::{LibgenMetadata, MockMetadataStore},
-        library_dot_lol::{DownloadLinks, MockDownloadLinksStore},
-    };
-    use actix_web::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
-    use httpmock::{Method::GET, MockServer};
-    use mockall::predicate::eq;
+    #[actix_web::test]
+    async fn test_healthz_without_token() {
+        let resp = call_with_token(Some("s3cr3t"), "/healthz", None).await;
+
+        assert!(resp.status().is_success());
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(r#"{"status":"ok"}"#, body);
+    }
 
     #[actix_web::test]
-    async fn test_download() {
-        let mock_download_server = MockServer::start();
-        let endpoint_mock = mock_download_server.mock(|when, then| {
-            when.method(GET).path("/book.mobi");
-            then.status(200)
-                .body(include_bytes!("../tests/testdata/dummy_ebook.mobi"));
-        });
-        let url = mock_download_server.url("/book.mobi").to_owned();
-        let download_link: &'static str = Box::leak(url.into_boxed_str()); // Leaks memory!! TODO: find another way to do this.
+    async fn test_healthz_reports_converter() {
+        use crate::capabilities::CalibreVersion;
 
-        let mock_goodreads_url = web::Path::from("http://hello.world".to_string());
-        let mock_libreads = web::Data::new(get_mock_libreads(download_link));
+        let capabilities = ConverterCapabilities::new(
+            Some(CalibreVersion {
+                major: 7,
+                minor: 4,
+                patch: 0,
+            }),
+            false,
+        );
+        let resp = healthz(Some(web::Data::new(capabilities)), None, None).await;
+        let disabled = healthz(
+            Some(web::Data::new(ConverterCapabilities::disabled())),
+            None,
+            None,
+        )
+        .await;
 
-        let resp = download(mock_libreads, mock_goodreads_url)
+        let body = actix_web::body::to_bytes(disabled.into_body())
             .await
-            .expect("the call should succeed");
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(serde_json::json!(false), body["converter"]["enabled"]);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "status": "ok",
+                "converter": {
+                    "enabled": true,
+                    "calibre_version": "7.4.0",
+                    "djvu": false,
+                    "inputs": ["mobi", "epub", "azw3", "pdf", "docx", "txt", "htmlz", "cbz", "cbr"],
+                    "outputs": ["mobi", "epub", "azw3", "pdf", "txt", "htmlz"],
+                },
+            }),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+    }
 
-        let cd = resp.headers().get(CONTENT_DISPOSITION).unwrap();
-        assert_eq!(r#"attachment; filename="hello.mobi""#, cd);
+    #[actix_web::test]
+    async fn test_healthz_reports_upstreams() {
+        use crate::clock::SharedClock;
+        use std::time::Duration;
 
-        let ct = resp.headers().get(CONTENT_TYPE).unwrap();
-        assert_eq!("application/x-mobipocket-ebook", ct);
+        let breakers = CircuitBreakers::new(2, Duration::from_secs(30), SharedClock::default());
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+        breakers.record_failure(&url("http://library.lol/main/ABCD"));
+        breakers.record_failure(&url("http://library.lol/main/ABCD"));
+        breakers.record_failure(&url("https://libgen.rs/json.php"));
+        breakers.record_success(&url("https://dweb.link/ipfs/bafk"));
 
-        // Local file has been deleted
-        assert!(!Path::new("hello.mobi").exists());
-        endpoint_mock.assert();
+        let resp = healthz(None, Some(web::Data::new(breakers)), None).await;
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "status": "ok",
+                "upstreams": {
+                    "dweb.link": {"state": "closed", "consecutive_failures": 0},
+                    "libgen.rs": {"state": "closed", "consecutive_failures": 1},
+                    "library.lol": {"state": "open", "consecutive_failures": 2},
+                },
+            }),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
     }
 
     #[actix_web::test]
-    async fn test_download_error() {
-        let mock_goodreads_url = web::Path::from("http://hello.world".to_string());
+    async fn test_metrics_reports_upstream_errors() {
+        use crate::http_client::HttpErrorKind;
 
-        let mut isbn_getter_mock = MockBookIdentificationGetter::new();
-        isbn_getter_mock
-            .expect_get_identification()
-            .with(eq("http://hello.world"))
-            .once()
-            .returning(|_| Box::pin(async { Err(reqwest::get("Bad_Url").await.unwrap_err()) }));
+        let breakers = CircuitBreakers::default();
+        let url = reqwest::Url::parse("https://libgen.rs/json.php").unwrap();
+        breakers.record_error(&url, &HttpErrorKind::TlsFailure);
 
-        let mock_libreads = LibReads {
-            isbn_getter: Box::new(isbn_getter_mock),
-            metadata_store: Box::new(MockMetadataStore::new()),
-            download_links_store: Box::new(MockDownloadLinksStore::new()),
-        };
+        let resp = metrics(Some(web::Data::new(breakers)), None).await;
 
-        let resp = download(web::Data::new(mock_libreads), mock_goodreads_url).await;
-        assert!(resp.is_err())
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(
+            body.lines().any(|line| line
+                == "libreads_upstream_errors_total{host=\"libgen.rs\",kind=\"tls_failure\"} 1"),
+            "{}",
+            body
+        );
+    }
+
+    fn assert_server_timing(header: Option<&HeaderValue>, want_stages: &[&str]) {
+        let header = header.expect("Server-Timing header").to_str().unwrap();
+        let stages: Vec<&str> = header
+            .split(", ")
+            .map(|metric| {
+                let (stage, duration) = metric.split_once(";dur=").expect("dur param");
+                let duration: f64 = duration.parse().expect("duration in ms");
+                assert!((0.0..60_000.0).contains(&duration), "{}", header);
+                stage
+            })
+            .collect();
+
+        assert_eq!(want_stages, stages);
+    }
+
+    // The routes of `server::start`, with the data each test gives its app.
+    fn configure(cfg: &mut web::ServiceConfig) {
+        probes(cfg);
+        cfg.service(web::scope("/api").configure(api));
+    }
+
+    #[actix_web::test]
+    async fn test_mount_under_prefix() {
+        use actix_web::{test, App};
+
+        let mut service = MockBookService::new();
+        expect_book_info(&mut service, book_info(Extension::Mobi, "hello"));
+        let state = LibReadsState::new(
+            Config {
+                api_token: Some("secret".to_string()),
+                ..Config::default()
+            },
+            service_data(service),
+        )
+        .with_breakers(CircuitBreakers::default());
+        let app = test::init_service(
+            App::new()
+                .service(web::scope("/books").configure(|cfg| super::configure(cfg, state)))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri);
+
+        let resp = test::call_service(
+            &app,
+            get("/books/info/https%3A%2F%2Fwww.goodreads.com%2Fbook%2Fshow%2F1048424.Governing_the_Commons")
+                .insert_header((AUTHORIZATION, "Bearer secret"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(200, resp.status().as_u16());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("MYBOOKMD5", body["md5"]);
+
+        // The token is still required, but not by the probes.
+        let resp = test::call_service(&app, get("/books/info/1048424").to_request()).await;
+        assert_eq!(401, resp.status().as_u16());
+        let resp = test::call_service(&app, get("/books/healthz").to_request()).await;
+        assert_eq!(200, resp.status().as_u16());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(serde_json::json!({}), body["upstreams"]);
+
+        // The rest of the app is left alone.
+        let resp = test::call_service(
+            &app,
+            get("/books/unknown")
+                .insert_header((AUTHORIZATION, "Bearer secret"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(404, resp.status().as_u16());
+        let resp = test::call_service(&app, get("/").to_request()).await;
+        assert_eq!(200, resp.status().as_u16());
+    }
+
+    #[actix_web::test]
+    async fn test_openapi_document() {
+        use actix_web::{test, App};
+
+        let state = LibReadsState::new(
+            Config {
+                api_token: Some("secret".to_string()),
+                ..Config::default()
+            },
+            service_data(MockBookService::new()),
+        );
+        let app = test::init_service(
+            App::new().service(web::scope("/books").configure(|cfg| super::configure(cfg, state))),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        // Without the token, and with where the API is mounted as its server.
+        let resp = test::call_service(&app, get("/books/openapi.json")).await;
+        assert_eq!(200, resp.status().as_u16());
+        let document: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("/books", document["servers"][0]["url"]);
+
+        // Errors have the fields of their schema.
+        let resp = test::call_service(&app, get("/books/library")).await;
+        assert_eq!(401, resp.status().as_u16());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let schema = &document["components"]["schemas"]["ErrorBody"];
+        for field in body.as_object().unwrap().keys() {
+            assert!(schema["properties"][field].is_object(), "{}", field);
+        }
+        for field in schema["required"].as_array().unwrap() {
+            assert!(body[field.as_str().unwrap()].is_string(), "{}", field);
+        }
+    }
+
+    #[cfg(feature = "swagger-ui")]
+    #[actix_web::test]
+    async fn test_swagger_ui() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(App::new().configure(configure)).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/api/docs/").to_request(),
+        )
+        .await;
+        assert_eq!(200, resp.status().as_u16());
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("swagger-ui"));
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/api/docs/swagger-initializer.js")
+                .to_request(),
+        )
+        .await;
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("../openapi.json"));
     }
 
     // TODO: make the whole flow easier to mock, by wrapping it in a higher level thing.
+    fn service_data(service: MockBookService) -> web::Data<dyn BookService> {
+        web::Data::from(Arc::new(service) as Arc<dyn BookService>)
+    }
+
+    fn book_info(extension: Extension, title: &str) -> BookInfo {
+        BookInfo {
+            metadata: LibgenMetadata {
+                title: title.to_string(),
+                author: "hello".to_string(),
+                year: "hello".to_string(),
+                extension: extension.clone(),
+                md5: "MYBOOKMD5".to_string(),
+                filesize: None,
+                language: None,
+                pages: None,
+                publisher: None,
+            },
+            download_links: DownloadLinks {
+                ipfs_cid: Some("fake_cid".to_string()),
+                gateways: vec!["https://fake.gateway".to_string()],
+                http: "fake_http_link".to_string(),
+                filename: None,
+            },
+            cover_url: None,
+            isbn10: Some("fake_isbn_10".to_string()),
+            isbn13: None,
+            available_formats: vec![extension],
+            alternatives: vec![],
+            found_by_title: false,
+            identification: BookIdentification {
+                isbn10: Some("fake_isbn_10".to_string()),
+                title: Some(format!("{} (Goodreads)", title)),
+                author: Some("Hello".to_string()),
+                series: Some("Hello World".to_string()),
+                series_index: Some("2".to_string()),
+                ..Default::default()
+            },
+            book: None,
+        }
+    }
+
+    // A server converting books, with Calibre and DjVuLibre, whichever
+    // features this build has.
+    fn converting() -> Config {
+        Config {
+            conversion_enabled: true,
+            ..Config::default()
+        }
+    }
+
+    fn calibre() -> Option<web::Data<ConverterCapabilities>> {
+        Some(web::Data::new(ConverterCapabilities::new(
+            Some(crate::capabilities::CalibreVersion {
+                major: 7,
+                minor: 4,
+                patch: 0,
+            }),
+            true,
+        )))
+    }
+
+    // Every call to `book_info` for `GOODREADS_URL` finds `book_info`.
+    fn expect_book_info(service: &mut MockBookService, book_info: BookInfo) {
+        service
+            .expect_book_info()
+            .with(eq(GOODREADS_URL), always(), always())
+            .returning(move |_, _, _| {
+                let book_info = book_info.clone();
+                let mut timings = Timings::default();
+                for stage in ["goodreads", "libgen", "links"] {
+                    timings.record(stage, Duration::from_millis(10));
+                }
+                Box::pin(async move { Ok((book_info, timings)) })
+            });
+    }
+
+    fn conversion_result(path: PathBuf) -> convert::ConversionResult {
+        convert::ConversionResult {
+            bytes: std::fs::metadata(&path).unwrap().len(),
+            path,
+            extension: Extension::Mobi,
+            converted: true,
+            md5: "MYBOOKMD5".to_string(),
+            source: "dweb.link".to_string(),
+            original_extension: Extension::Epub,
+            download_duration: Duration::from_millis(10),
+            conversion_duration: Some(Duration::from_millis(10)),
+            language_mismatch: None,
+        }
+    }
+
     fn get_mock_libreads(book_download_url: &'static str) -> LibReads {
         let mut isbn_getter_mock = MockBookIdentificationGetter::new();
         isbn_getter_mock
             .expect_get_identification()
-            .with(eq("http://hello.world"))
+            .with(eq(GOODREADS_URL))
             .once()
             .returning(|_| {
                 Box::pin(async {
@@ -264,6 +6329,12 @@ mod tests {
                         isbn13: None,
                         title: None,
                         author: None,
+                        cover_url: None,
+                        pages: None,
+                        publisher: None,
+                        series: None,
+                        series_index: None,
+                        format: None,
                     })
                 })
             });
@@ -276,6 +6347,12 @@ mod tests {
                 isbn13: None,
                 title: None,
                 author: None,
+                cover_url: None,
+                pages: None,
+                publisher: None,
+                series: None,
+                series_index: None,
+                format: None,
             }))
             .once()
             .returning(|_| {
@@ -286,6 +6363,10 @@ mod tests {
                         year: "hello".to_string(),
                         extension: Extension::Mobi,
                         md5: "MYBOOKMD5".to_string(),
+                        filesize: None,
+                        language: None,
+                        pages: None,
+                        publisher: None,
                     }])
                 })
             });
@@ -295,22 +6376,28 @@ mod tests {
             .expect_get_download_links()
             .with(eq("MYBOOKMD5"))
             .once()
-            .returning(|_| {
-                Box::pin(async {
+            .returning(move |_| {
+                Box::pin(async move {
                     Ok(DownloadLinks {
-                        cloudflare: book_download_url.to_string(),
-                        ipfs_dot_io: "fake_ipfs_dot_io_link".to_string(),
-                        infura: "fake_infura_link".to_string(),
-                        pinata: "fake_pinata_link".to_string(),
-                        http: "fake_http_link".to_string(),
+                        http: book_download_url.to_string(),
+                        ..DownloadLinks::default()
                     })
                 })
             });
 
         LibReads {
             isbn_getter: Box::new(isbn_getter_mock),
+            shelf_getter: Box::new(MockShelfGetter::new()),
             metadata_store: Box::new(metadata_store_mock),
             download_links_store: Box::new(download_links_store_mock),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            links_prefetch: DEFAULT_LINKS_PREFETCH,
+            max_editions: DEFAULT_MAX_EDITIONS,
+            can_convert_djvu: true,
+            ranker: Box::new(crate::ranking::DefaultRanker::default()),
+            library: None,
+            kindle: None,
+            connection_limits: crate::http_client::ConnectionLimits::default(),
         }
     }
 }
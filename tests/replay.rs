@@ -0,0 +1,68 @@
+//! Runs LibReads end to end against the responses recorded in
+//! tests/testdata/cassettes, without touching the network: Goodreads, the
+//! LibGen JSON API, library.lol, and the book itself.
+//!
+//! New cassettes are recorded with `LIBREADS_RECORD`, see
+//! `http_client::RecordingClient`.
+
+use libreads::{
+    config::{Config, HttpRecording},
+    convert::ConversionOptions,
+    extension::Extension,
+    libreads::{Error, LibReads},
+};
+use std::path::PathBuf;
+
+const CASSETTES: &str = "tests/testdata/cassettes";
+const BOOK_URL: &str = "https://www.goodreads.com/book/show/1002.Governing_the_Commons";
+
+fn replaying() -> LibReads {
+    LibReads::new(&Config {
+        http_recording: Some(HttpRecording::Replay(PathBuf::from(CASSETTES))),
+        ..Config::default()
+    })
+}
+
+#[tokio::test]
+async fn test_download_from_recordings() {
+    let libreads = replaying();
+
+    let book_info = libreads
+        .get_book_info_from_goodreads_url(BOOK_URL)
+        .await
+        .unwrap();
+    assert_eq!(Some("9780521405997".to_string()), book_info.isbn13);
+    assert_eq!("Governing the Commons", book_info.metadata.title);
+    assert_eq!("21845606b3b7ef22fdd1d2753cc82eeb", book_info.metadata.md5);
+
+    let got = libreads
+        .download_as(book_info, Extension::Epub, &ConversionOptions::default())
+        .await
+        .unwrap();
+    let book = std::fs::read(&got.path).unwrap();
+    std::fs::remove_file(&got.path).unwrap();
+
+    assert_eq!(
+        std::fs::read("tests/testdata/dummy_ebook.epub").unwrap(),
+        book
+    );
+    assert_eq!("21845606b3b7ef22fdd1d2753cc82eeb", got.md5);
+    assert_eq!(Extension::Epub, got.extension);
+    assert!(!got.converted);
+}
+
+#[tokio::test]
+async fn test_missing_recording() {
+    let got = replaying()
+        .get_book_info_from_goodreads_url("https://www.goodreads.com/book/show/1")
+        .await;
+
+    let Err(Error::UpstreamUnavailable(message)) = got else {
+        panic!("expected a missing recording, got {:?}", got);
+    };
+    assert!(
+        message.contains("GET https://www.goodreads.com/book/show/1: not recorded"),
+        "{}",
+        message
+    );
+}
@@ -0,0 +1,4 @@
+#!/bin/sh
+# Stands in for ebook-convert in tests: only answers --version.
+echo "ebook-convert (calibre 6.11.0)"
+echo "Created by: Kovid Goyal <kovid@kovidgoyal.net>"
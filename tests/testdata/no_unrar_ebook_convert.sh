@@ -0,0 +1,4 @@
+#!/bin/sh
+# Stands in for an ebook-convert built without unrar, which can't read CBR.
+echo "ModuleNotFoundError: No module named 'calibre_extensions.unrar'" >&2
+exit 1
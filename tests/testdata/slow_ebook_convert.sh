@@ -0,0 +1,6 @@
+#!/bin/sh
+# Stands in for ebook-convert in tests: takes a while, then "converts" the
+# input by copying it to the output.
+sleep 1
+cp "$1" "$2"
+echo "Output saved to $2"